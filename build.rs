@@ -1,7 +1,7 @@
 use std::io::{Result, Write};
 use std::fs::{OpenOptions};
 use chrono::{DateTime, Utc};
-use csv::Reader;
+use csv::ReaderBuilder;
 
 fn update_version_number() -> Result<()> {
     let now: DateTime<Utc> = Utc::now();
@@ -18,27 +18,103 @@ fn update_version_number() -> Result<()> {
     Ok(())
 }
 
+/// src/syscall/syscall_num.rs is generated from src/syscall/syscall_num.csv, add new syscalls there.
+/// Rows are `name,id[,reported name]`.
+/// The csv is also copied next to the user headers, for the user programs.
 fn update_syscall_number() -> Result<()> {
     let fi = OpenOptions::new()
         .read(true)
-        .open("../syscall_num.csv")?;
+        .open("src/syscall/syscall_num.csv")?;
     let mut fo = OpenOptions::new()
         .write(true)
         .truncate(true)
         .create(true)
         .open("src/syscall/syscall_num.rs")?;
-    let mut rdr = Reader::from_reader(fi);
+    let mut rdr = ReaderBuilder::new().flexible(true).from_reader(fi);
+    let mut names = Vec::new();
     for result in rdr.records() {
         let record = result?;
         println!("{} => {}", record.get(0).unwrap(), record.get(1).unwrap());
         writeln!(fo, "pub const SYSCALL_{:<10}: usize = {:>3};", record.get(0).unwrap().to_ascii_uppercase(), record.get(1).unwrap())?;
+        // optional third column, name reported in /proc/syscall_stats when not the constant's
+        let name = match record.get(2) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => record.get(0).unwrap().to_ascii_lowercase(),
+        };
+        names.push((record.get(0).unwrap().to_ascii_uppercase(), name));
+    }
+    writeln!(fo)?;
+    writeln!(fo, "/// id and name of each, for reporting")?;
+    writeln!(fo, "pub const SYSCALL_NAMES: &[(usize, &str)] = &[")?;
+    for (id, name) in names {
+        writeln!(fo, "    (SYSCALL_{:<10}, \"{}\"),", id, name)?;
+    }
+    writeln!(fo, "];")?;
+    Ok(())
+}
+
+#[path = "src/device/ioctl_abi.rs"]
+#[allow(dead_code)]
+mod ioctl_abi;
+
+/// User headers for the device ioctl ABI, C and Rust, and the syscall number table.
+/// Goes to $PARCH_USER_INCLUDE, ../include by default, next to where the user programs live.
+fn emit_ioctl_headers() -> Result<()> {
+    println!("cargo:rerun-if-env-changed=PARCH_USER_INCLUDE");
+    let dir = std::env::var("PARCH_USER_INCLUDE").unwrap_or("../include".to_string());
+    std::fs::create_dir_all(&dir)?;
+    std::fs::copy("src/syscall/syscall_num.csv", format!("{}/syscall_num.csv", dir))?;
+    let c_type = |ty: &str| match ty {
+        "u8" => "uint8_t", "u16" => "uint16_t", "u32" => "uint32_t", "u64" => "uint64_t",
+        "i8" => "int8_t", "i16" => "int16_t", "i32" => "int32_t", "i64" => "int64_t",
+        _ => panic!("ioctl ABI field of type {} is not fixed width", ty),
+    };
+
+    let mut h = OpenOptions::new().write(true).truncate(true).create(true).open(format!("{}/parch_ioctl.h", dir))?;
+    writeln!(h, "/* Generated by ParchKernel build.rs from src/device/ioctl_abi.rs, don't edit. */")?;
+    writeln!(h, "#ifndef PARCH_IOCTL_H\n#define PARCH_IOCTL_H\n\n#include <stdint.h>\n")?;
+    for (name, value) in ioctl_abi::ABI_CONSTS {
+        writeln!(h, "#define {:<24} {:#x}UL", name, value)?;
+    }
+    for (name, fields, size) in ioctl_abi::ABI_STRUCTS {
+        writeln!(h, "\nstruct __attribute__((packed)) {} {{", name)?;
+        for (field, ty) in fields.iter() {
+            writeln!(h, "    {} {};", c_type(ty), field)?;
+        }
+        writeln!(h, "}};\n_Static_assert(sizeof(struct {}) == {}, \"ioctl ABI size mismatch\");", name, size)?;
+    }
+    writeln!(h, "\n#endif")?;
+
+    let mut rs = OpenOptions::new().write(true).truncate(true).create(true).open(format!("{}/parch_ioctl.rs", dir))?;
+    writeln!(rs, "//! Generated by ParchKernel build.rs from src/device/ioctl_abi.rs, don't edit.\n")?;
+    for (name, value) in ioctl_abi::ABI_CONSTS {
+        writeln!(rs, "pub const {:<24}: usize = {:#x};", name, value)?;
+    }
+    for (name, fields, size) in ioctl_abi::ABI_STRUCTS {
+        writeln!(rs, "\n#[repr(C, packed)]\n#[derive(Clone, Copy, Debug, Default)]\npub struct {} {{", name)?;
+        for (field, ty) in fields.iter() {
+            c_type(ty);
+            writeln!(rs, "    pub {}: {},", field, ty)?;
+        }
+        writeln!(rs, "}}\nconst _: () = assert!(core::mem::size_of::<{}>() == {});", name, size)?;
     }
     Ok(())
 }
 
+/// Kernel image goes at BASE_ADDRESS + KERNEL_LOAD_OFFSET. Unset is 0, the bare metal layout existing run setups
+/// expect; 0x200000 is where OpenSBI/RustSBI jump to.
+fn set_load_offset() {
+    println!("cargo:rerun-if-env-changed=KERNEL_LOAD_OFFSET");
+    if let Some(offset) = std::env::var("KERNEL_LOAD_OFFSET").ok().filter(|offset| !offset.is_empty()) {
+        println!("cargo:rustc-link-arg=--defsym=KERNEL_OFFSET={}", offset);
+    }
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=./src/");
-    println!("cargo:rerun-if-changed=../syscall_num.csv");
+    println!("cargo:rerun-if-changed=src/syscall/syscall_num.csv");
+    set_load_offset();
 	update_version_number().unwrap();
     update_syscall_number().unwrap();
+    emit_ioctl_headers().unwrap();
 }
\ No newline at end of file
@@ -3,9 +3,19 @@ use crate::mem::{PhysAddr, VirtAddr};
 pub const KERNEL_HEAP_SIZE  : usize = 0x100_0000;   // 16MiB
 pub const PROC_K_STACK_SIZE : usize = 0x10_0000;    // 1MiB
 pub const PROC_U_STACK_SIZE : usize = 0x10_0000;    // 1MiB
+pub const BOOT_STACK_SIZE   : usize = 4096 * 8;     // per hart, boot stacks in crt_setup.asm are sized from this
+pub const KSTACK_CANARY     : usize = 0xDEAD_C0DE_CAFE_BABE;
+pub const KSTACK_CANARY_WORDS : usize = 8;
+pub const KALLSYMS_SIZE     : usize = 0x8_0000;     // 512KiB, filled by tools/kallsyms.py on link
+pub const LOCK_SPIN_WARN    : usize = 1 << 26;      // spins before a SpinMutex reports its holder
 pub const PAGE_OFFSET		: usize = 12;
 pub const PAGE_SIZE			: usize = 1 << PAGE_OFFSET;
 pub const UART0_IRQ			: u32 = 10;
+pub const VT_MAX            : usize = 9;        // virtual consoles, one hotkey digit each
+pub const VT_SCROLLBACK     : usize = 0x4000;   // bytes kept per virtual console for redraw
+pub const TRACE_RING_SIZE   : usize = 4096;     // events kept unread in utils::trace
+pub const LOG_RING_SIZE     : usize = 0x4000;   // bytes of log kept for the crash dump
+pub const POISON_BYTE       : u8 = 0xAA;        // debug builds fill freed pages and heap blocks with it
 pub const CLINT_ADDR		: PhysAddr = PhysAddr(0x02000000);
 pub const PLIC_ADDR			: PhysAddr = PhysAddr(0x0C000000);
 pub const UART0_ADDR		: PhysAddr = PhysAddr(0x10000000);
@@ -21,22 +31,46 @@ pub const MMIO_RANGES       : &[(usize, usize)] = &[
 
 pub const TRAMPOLINE_ADDR   : VirtAddr = VirtAddr(usize::MAX - PAGE_SIZE + 1);
 pub const U_TRAMPOLINE_ADDR : VirtAddr = VirtAddr(TRAMPOLINE_ADDR.0 - PAGE_SIZE);
-pub const TRAP_CONTEXT_ADDR : VirtAddr = VirtAddr(U_TRAMPOLINE_ADDR.0 - PAGE_SIZE);
+pub const VDSO_DATA_ADDR    : VirtAddr = VirtAddr(U_TRAMPOLINE_ADDR.0 - PAGE_SIZE);
+pub const TRAP_CONTEXT_ADDR : VirtAddr = VirtAddr(VDSO_DATA_ADDR.0 - PAGE_SIZE);
 pub const PROC_K_STACK_ADDR : VirtAddr = VirtAddr(TRAP_CONTEXT_ADDR.0 - PAGE_SIZE - PROC_K_STACK_SIZE);
 pub const PROC_U_STACK_ADDR : VirtAddr = VirtAddr(PROC_K_STACK_ADDR.0 - PAGE_SIZE - PROC_U_STACK_SIZE);
+pub const PROC_ARGS_ADDR    : VirtAddr = VirtAddr(PROC_U_STACK_ADDR.0 - PAGE_SIZE - ARG_MAX);
+pub const ELF_INTERP_BASE   : VirtAddr = VirtAddr(0x20_0000_0000);
+pub const ELF_INTERP_RAND_PAGES : usize = 0x1_0000;   // 256MiB
+pub const ASLR_MMAP_RAND_PAGES  : usize = 0x1_0000;   // 256MiB
+pub const ASLR_STACK_RAND_PAGES : usize = 0x40;       // 256KiB
 
 
-pub const MAX_CPUS			: usize = 16;	
+pub const MAX_CPUS			: usize = 16;	// cap on hart id, crt_setup.asm parks harts above it
+pub const PAGE_MAGAZINE_SIZE : usize = 64;    // per hart cached free pages
+pub const PAGE_MAGAZINE_BATCH: usize = 32;    // pages moved per refill / drain
+pub const ZEROED_POOL_SIZE   : usize = 128;   // free pages kept zeroed ahead by the zeropage kernel thread
+pub const ZEROED_POOL_BATCH  : usize = 8;     // pages zeroed before looking for other work
+pub const ZEROED_POOL_INTERVAL_MS: usize = 100; // how often the zeropage kernel thread tops the pool up
 pub const CLOCK_FREQ		: usize = 0x00989680;   // from dtb
 pub const CYCLE_PER_TICK    : usize = 0x100;
 pub const TIMER_FRAC		: usize = 1;	// trigger every 100ms
+pub const WATCHDOG_THRESH   : usize = 10;   // seconds without S mode tick before a hart is reported
 
 pub const INIT_PROCESS_PATH      : &str = "/init_proc";
 
 pub const MAX_FD            : usize = 4096;
-pub const MAX_SYSCALL       : usize = 64;
+pub const MAX_SYSCALL       : usize = 128;   // size of kernel tables, not part of any user ABI. Was 64 until inotify_rm_watch took 64
+pub const MAX_IOV           : usize = 1024;
+pub const MAX_SPAWN_ACTIONS : usize = 64;
+pub const USER_STR_MAX      : usize = 1024;  // C strings from user (paths, argv, envp), NUL excluded
+pub const PATH_MAX          : usize = USER_STR_MAX; // bytes in a path, NUL excluded
+pub const NAME_MAX          : usize = 255;   // bytes in one path component
+pub const ARG_MAX           : usize = 0x2_0000; // 128KiB, argv and envp strings and their pointers on exec
 
 pub const MAX_LINK_RECURSE  : usize = 32;
+pub const MAX_SHEBANG_RECURSE : usize = 4;
+pub const SHEBANG_LINE_MAX  : usize = 256;
 
 pub const UUID_LENGTH       : usize = 16;  // 16 bytes
-pub const PIPE_BUFFER_MAX   : usize = 4096;
\ No newline at end of file
+pub const PIPE_BUF          : usize = 4096;    // pipe writes up to this are atomic
+pub const PIPE_DEFAULT_SIZE : usize = 0x1_0000; // 64KiB
+pub const PIPE_MAX_SIZE     : usize = 0x10_0000; // 1MiB, F_SETPIPE_SZ beyond this needs privilege
+pub const INOTIFY_QUEUE_MAX : usize = 1024;    // events queued per inotify fd before overflow
+pub const LOOP_COUNT        : usize = 4;    // /dev/loop0 to /dev/loop3
\ No newline at end of file
@@ -1,19 +1,44 @@
     .section .text.entry
     .globl _start
 _start:
+    # under SBI firmware we are in S mode, and reading mhartid traps to stvec
+    la t0, _start_sbi
+    csrw stvec, t0
+    # only MAX_CPUS boot stacks, park the rest
+    csrr t0, mhartid
+    li t1, {max_cpus}
+    bgeu t0, t1, park
     la sp, boot_stack
-    li t0, 4096 * 8
+    li t0, {boot_stack_size}
     csrr tp, mhartid
     addi t1, tp, 1
     mul t0, t0, t1
     add sp, sp, t0
     csrr a0, mhartid
+    # a1 = dtb from the reset stub, untouched so far
     call genesis_m
+park:
+    wfi
+    j park
+
+    # S mode entry, a0 = hart id, a1 = dtb / opaque. Also where secondary harts are started by HSM.
+    .align 2
+    .globl _start_sbi
+_start_sbi:
+    li t1, {max_cpus}
+    bgeu a0, t1, park
+    la sp, boot_stack
+    li t0, {boot_stack_size}
+    mv tp, a0
+    addi t1, a0, 1
+    mul t0, t0, t1
+    add sp, sp, t0
+    call genesis_sbi
 
     .section .bss.stack
     .globl boot_stack
 boot_stack:
-    .space 4096 * 8 * 16
+    .space {boot_stack_size} * {max_cpus}
     .globl boot_stack_top
 boot_stack_top:
 
@@ -3,9 +3,9 @@ use core::fmt::Debug;
 use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
 // use fdt_rs::{base::{DevTree, DevTreeNode}, prelude::FallibleIterator};
 use lazy_static::*;
-use crate::{mem::PhysAddr, utils::{ErrorNum, Mutex, RWLock, SpinRWLock, UUID}};
+use crate::{mem::PhysAddr, utils::{ErrorNum, Mutex, RWLock, SpinRWLock, UUID, bootargs}};
 use crate::utils::K_PRINT_HANDLER;
-use super::{DeviceTree, device_tree::DTBPropertyValue, drivers::{plic::PLIC, poweroff::PowerOff, reboot::Reboot, rtc::RTC, uart::UART}};
+use super::{DeviceTree, device_tree::DTBPropertyValue, drivers::uart::tty_ports, registry::{DriverEntry, driver_entries}};
 
 lazy_static!{
     pub static ref DEVICE_MANAGER: SpinRWLock<DeviceManager> = SpinRWLock::new(DeviceManager::init());
@@ -40,6 +40,8 @@ pub struct DeviceManager {
     list: BTreeMap<UUID, Arc<dyn Driver>>,
     /// there will be only ONE interrupt gateway(PLIC) in risc-v spec
     int_controller: Arc<dyn IntController>,
+    /// registry entries probed at boot
+    enabled: Vec<&'static DriverEntry>,
     dev_tree: DeviceTree
 }
 
@@ -49,32 +51,55 @@ impl DeviceManager {
             fn device_tree_blob();
         }
         let dev_tree = DeviceTree::parse(PhysAddr::from(device_tree_blob as usize)).unwrap();
-        let mut res = DeviceManager{
-            list: BTreeMap::new(),
-            int_controller : {
-                match PLIC::new(dev_tree.clone()).unwrap().as_slice() {
-                    [(_uuid, driver)] => driver.clone().as_int_controller().unwrap(),
-                    _ => panic!("No int controller found")
-                }
-            },
-            dev_tree: dev_tree.clone()
+        let (list, int_controller, enabled) = Self::register_by_dtb(dev_tree.clone()).unwrap();
+        let res = DeviceManager{
+            list,
+            int_controller: int_controller.expect("No int controller found"),
+            enabled,
+            dev_tree,
         };
-        res.register_by_dtb(dev_tree).unwrap();
         res.init_all().unwrap();
 
         // setup kernel printer
-        let uart_uuid = res.dev_tree.serach_compatible("ns16550a").unwrap()[0].acquire_r().driver;
+        // ttyS0 until bootargs are read, see uart::set_console
+        let uart_uuid = tty_ports(&res.dev_tree)[0].acquire_r().driver;
         K_PRINT_HANDLER.acquire().set_driver(res.get_device(uart_uuid).unwrap());
 
         res
     }
 
-    pub fn register_by_dtb(&mut self, device_tree: DeviceTree) -> Result<(), ErrorNum> {
-        self.list.append(&mut UART::new(device_tree.clone()).unwrap().into_iter().collect());
-        self.list.append(&mut RTC::new(device_tree.clone()).unwrap().into_iter().collect());
-        self.list.append(&mut PowerOff::new(device_tree.clone()).unwrap().into_iter().collect());
-        self.list.append(&mut Reboot::new(device_tree.clone()).unwrap().into_iter().collect());
-        Ok(())
+    /// Probe every enabled registry entry that has a compatible node.
+    /// Bootargs are not read yet at this point, so parse them from the tree here.
+    fn register_by_dtb(device_tree: DeviceTree) -> Result<(BTreeMap<UUID, Arc<dyn Driver>>, Option<Arc<dyn IntController>>, Vec<&'static DriverEntry>), ErrorNum> {
+        let args = bootargs::parse(&device_tree.bootargs().unwrap_or_default());
+        let mut list = BTreeMap::new();
+        let mut int_controller = None;
+        let mut enabled = Vec::new();
+        for entry in driver_entries() {
+            if !entry.matches(&device_tree) {
+                continue;
+            }
+            if !entry.enabled(&args) {
+                milestone!("Driver {} disabled by bootargs.", entry.name);
+                continue;
+            }
+            for (uuid, driver) in (entry.probe)(device_tree.clone())? {
+                match driver.clone().as_int_controller() {
+                    Ok(intc) => {
+                        if int_controller.replace(intc).is_some() {
+                            panic!("More than one int controller")
+                        }
+                    },
+                    Err(_) => { list.insert(uuid, driver); },
+                }
+            }
+            enabled.push(entry);
+        }
+        Ok((list, int_controller, enabled))
+    }
+
+    pub fn enabled_drivers(&self) -> Vec<&'static DriverEntry> {
+        self.enabled.clone()
     }
 
     // call this after boot and register, or warm reboot
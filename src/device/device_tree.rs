@@ -24,7 +24,7 @@ use alloc::borrow::ToOwned;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
 use alloc::{string::String, vec::Vec};
-use crate::utils::{ErrorNum, LogLevel, RWLock, SpinRWLock, UUID};
+use crate::utils::{ErrorNum, ErrorContext, KernelError, LogLevel, RWLock, SpinRWLock, UUID};
 use crate::mem::PhysAddr;
 use core::fmt::Debug;
 use core::mem::size_of;
@@ -158,6 +158,227 @@ impl FDTToken {
     }
 }
 
+/// Bytes of the nul terminated string at addr, without the nul.
+unsafe fn raw_cstr(addr: usize) -> &'static [u8] {
+    let mut len = 0;
+    while *((addr + len) as *const u8) != 0 {
+        len += 1;
+    }
+    core::slice::from_raw_parts(addr as *const u8, len)
+}
+
+/// Bitmask of enabled `/cpus/cpu@*` by their reg (hart id).
+/// Walks the raw structure block without allocating, locking or logging, as it's used to size per hart
+/// structures that locks and log depend on. Returns 0 on malformed blob.
+pub fn scan_hart_mask(addr: PhysAddr) -> usize {
+    scan_cpus(addr, b"").0
+}
+
+/// Bitmask of enabled harts whose ISA has extension `ext` (lowercase, single letter like `v` or multi-letter like `sstc`),
+/// from either `riscv,isa` or `riscv,isa-extensions`. Same constraints as scan_hart_mask.
+pub fn scan_isa_ext_mask(addr: PhysAddr, ext: &[u8]) -> usize {
+    scan_cpus(addr, ext).1
+}
+
+fn isa_has_ext(isa: &[u8], ext: &[u8]) -> bool {
+    // rv64imafdcv_zicsr_sstc, first token is the base with the single letter ones after rv64
+    let mut tokens = isa.split(|&c| c == b'_' || c == 0);
+    let base = tokens.next().unwrap_or(&[]);
+    if ext.len() == 1 {
+        return base.len() > 4 && base[4..].contains(&ext[0]);
+    }
+    tokens.any(|token| token == ext)
+}
+
+/// (present, has ext) hart masks.
+fn scan_cpus(addr: PhysAddr, ext: &[u8]) -> (usize, usize) {
+    let header: FDTHeader = unsafe { addr.read_volatile() };
+    if header.magic != 0xD00DFEED_u32.to_be() {
+        return (0, 0);
+    }
+    let be_u32 = |at: usize| u32::from_be(unsafe{(at as *const u32).read_unaligned()});
+    let string_addr = addr.0 + u32::from_be(header.string_offset) as usize;
+    let mut iter = addr.0 + u32::from_be(header.struct_offset) as usize;
+    let align4 = |x: usize| (x + 3) & !3;
+
+    let mut mask = 0usize;
+    let mut ext_mask = 0usize;
+    let mut depth = 0;
+    let mut in_cpus = false;
+    // reg, is cpu, disabled, has ext of the cpu@ node we are in
+    let mut cpu: Option<(Option<usize>, bool, bool, bool)> = None;
+    loop {
+        let token = be_u32(iter);
+        iter += 4;
+        match token {
+            0x1 => {
+                let name = unsafe{raw_cstr(iter)};
+                iter = align4(iter + name.len() + 1);
+                depth += 1;
+                if depth == 2 && name == b"cpus" {
+                    in_cpus = true;
+                } else if depth == 3 && in_cpus && name.starts_with(b"cpu@") {
+                    cpu = Some((None, false, false, false));
+                }
+            },
+            0x2 => {
+                if depth == 3 {
+                    if let Some((Some(reg), true, false, has_ext)) = cpu.take() {
+                        if reg < usize::BITS as usize {
+                            mask |= 1 << reg;
+                            if has_ext {
+                                ext_mask |= 1 << reg;
+                            }
+                        }
+                    }
+                }
+                if depth == 2 {
+                    in_cpus = false;
+                }
+                if depth == 0 {
+                    return (0, 0);
+                }
+                depth -= 1;
+            },
+            0x3 => {
+                let length = be_u32(iter) as usize;
+                let name = unsafe{raw_cstr(string_addr + be_u32(iter + 4) as usize)};
+                let value = unsafe{core::slice::from_raw_parts((iter + 8) as *const u8, length)};
+                iter = align4(iter + 8 + length);
+                if let (3, Some((reg, is_cpu, disabled, has_ext))) = (depth, cpu.as_mut()) {
+                    match name {
+                        b"reg" if length == 4 => *reg = Some(be_u32(value.as_ptr() as usize) as usize),
+                        b"reg" if length == 8 => *reg = Some(((be_u32(value.as_ptr() as usize) as usize) << 32) | be_u32(value.as_ptr() as usize + 4) as usize),
+                        b"device_type" => *is_cpu = value.starts_with(b"cpu\0"),
+                        b"status" => *disabled = value.starts_with(b"disabled"),
+                        b"riscv,isa" if !ext.is_empty() => *has_ext |= isa_has_ext(value, ext),
+                        b"riscv,isa-extensions" if !ext.is_empty() => *has_ext |= value.split(|&c| c == 0).any(|token| token == ext),
+                        _ => {}
+                    }
+                }
+            },
+            0x4 => {},
+            0x9 => return (mask, ext_mask),
+            _ => return (0, 0),
+        }
+    }
+}
+
+/// [start, end) from `reg` of the first enabled node with `compatible` (nul terminated, like `b"pmem-region\0"`),
+/// assuming 2 address and size cells, or 1 and 1. Same constraints as scan_hart_mask, None on malformed blob.
+pub fn scan_compatible_reg(addr: PhysAddr, compatible: &[u8]) -> Option<(usize, usize)> {
+    let header: FDTHeader = unsafe { addr.read_volatile() };
+    if header.magic != 0xD00DFEED_u32.to_be() {
+        return None;
+    }
+    let be_u32 = |at: usize| u32::from_be(unsafe{(at as *const u32).read_unaligned()});
+    let string_addr = addr.0 + u32::from_be(header.string_offset) as usize;
+    let mut iter = addr.0 + u32::from_be(header.struct_offset) as usize;
+    let align4 = |x: usize| (x + 3) & !3;
+
+    // properties come before subnodes, so a node is complete at the next begin or end token
+    // matches, disabled, reg of the node last begun
+    let mut node: (bool, bool, Option<(usize, usize)>) = (false, false, None);
+    loop {
+        let token = be_u32(iter);
+        iter += 4;
+        match token {
+            0x1 | 0x2 => {
+                if let (true, false, Some(reg)) = node {
+                    return Some(reg);
+                }
+                node = (false, false, None);
+                if token == 0x1 {
+                    let name = unsafe{raw_cstr(iter)};
+                    iter = align4(iter + name.len() + 1);
+                }
+            },
+            0x3 => {
+                let length = be_u32(iter) as usize;
+                let name = unsafe{raw_cstr(string_addr + be_u32(iter + 4) as usize)};
+                let value = unsafe{core::slice::from_raw_parts((iter + 8) as *const u8, length)};
+                let at = iter + 8;
+                iter = align4(iter + 8 + length);
+                let be_u64 = |at: usize| ((be_u32(at) as usize) << 32) | be_u32(at + 4) as usize;
+                match name {
+                    b"compatible" => node.0 = value.split_inclusive(|&c| c == 0).any(|s| s == compatible),
+                    b"status" => node.1 = value.starts_with(b"disabled"),
+                    b"reg" if length >= 16 => node.2 = Some((be_u64(at), be_u64(at) + be_u64(at + 8))),
+                    b"reg" if length == 8 => node.2 = Some((be_u32(at) as usize, be_u32(at) as usize + be_u32(at + 4) as usize)),
+                    _ => {}
+                }
+            },
+            0x4 => {},
+            _ => return None,
+        }
+    }
+}
+
+/// [start, end) from `/chosen`'s `linux,initrd-start` and `linux,initrd-end`, cells 32 or 64 bit.
+/// For the blob firmware passes, read before anything could reuse its memory. Same constraints as scan_hart_mask,
+/// None on malformed blob.
+pub fn scan_initrd(addr: PhysAddr) -> Option<(usize, usize)> {
+    let header: FDTHeader = unsafe { addr.read_volatile() };
+    if header.magic != 0xD00DFEED_u32.to_be() {
+        return None;
+    }
+    let be_u32 = |at: usize| u32::from_be(unsafe{(at as *const u32).read_unaligned()});
+    let string_addr = addr.0 + u32::from_be(header.string_offset) as usize;
+    let mut iter = addr.0 + u32::from_be(header.struct_offset) as usize;
+    let align4 = |x: usize| (x + 3) & !3;
+
+    let mut depth = 0;
+    let mut in_chosen = false;
+    let (mut start, mut end) = (None, None);
+    loop {
+        let token = be_u32(iter);
+        iter += 4;
+        match token {
+            0x1 => {
+                let name = unsafe{raw_cstr(iter)};
+                iter = align4(iter + name.len() + 1);
+                depth += 1;
+                in_chosen = depth == 2 && name == b"chosen";
+            },
+            0x2 => {
+                if in_chosen {
+                    break;
+                }
+                if depth == 0 {
+                    return None;
+                }
+                depth -= 1;
+            },
+            0x3 => {
+                let length = be_u32(iter) as usize;
+                let name = unsafe{raw_cstr(string_addr + be_u32(iter + 4) as usize)};
+                let at = iter + 8;
+                iter = align4(iter + 8 + length);
+                if !in_chosen {
+                    continue;
+                }
+                let value = match length {
+                    4 => be_u32(at) as usize,
+                    8 => ((be_u32(at) as usize) << 32) | be_u32(at + 4) as usize,
+                    _ => continue
+                };
+                match name {
+                    b"linux,initrd-start" => start = Some(value),
+                    b"linux,initrd-end" => end = Some(value),
+                    _ => {}
+                }
+            },
+            0x4 => {},
+            0x9 => break,
+            _ => return None,
+        }
+    }
+    match (start, end) {
+        (Some(start), Some(end)) if end > start => Some((start, end)),
+        _ => None
+    }
+}
+
 #[derive(Clone)]
 pub struct DeviceTree {
     reserved_mem: Vec<DTBMemReserve>,
@@ -165,12 +386,11 @@ pub struct DeviceTree {
 }
 
 impl DeviceTree {
-    pub fn parse(addr: PhysAddr) -> Result<Self, ErrorNum> {
+    pub fn parse(addr: PhysAddr) -> Result<Self, KernelError> {
         verbose!("Parsing on {:?}", addr);
         let header: FDTHeader = unsafe { addr.read_volatile() };
         if header.magic != 0xD00DFEED_u32.to_be() {
-            warning!("Bad dtb magic number");
-            return Err(ErrorNum::EBADDTB)
+            return Err(ErrorNum::EBADDTB.ctx("bad magic number"))
         }
 
         let rsvmap_addr = addr + u32::from_be(header.rsvmap_offset) as usize;
@@ -181,7 +401,7 @@ impl DeviceTree {
         verbose!("struct_addr: {:?}", struct_addr);
         verbose!("string_addr: {:?}", string_addr);
 
-        let reserved_mem = FDTReserveEntry::get_entries(rsvmap_addr)?.into_iter().map(|fdt_entry| DTBMemReserve {
+        let reserved_mem = FDTReserveEntry::get_entries(rsvmap_addr).context("bad memory reservation block")?.into_iter().map(|fdt_entry| DTBMemReserve {
             start: (u64::from_be(fdt_entry.address) as usize).into(),
             length: u64::from_be(fdt_entry.size) as usize,
         }).collect();
@@ -204,6 +424,86 @@ impl DeviceTree {
         })
     }
 
+    /// Tree with only a root node of 2 address cells and 2 size cells like QEMU virt, to fabricate devices on
+    /// with inject_node and probe drivers against in tests.
+    pub fn synthetic() -> Self {
+        let root = DTBNode::new(String::new(), None, false);
+        root.acquire_w().properties.extend([
+            ("#address-cells".to_string(), DTBPropertyValue::UInt32(2)),
+            ("#size-cells".to_string(), DTBPropertyValue::UInt32(2)),
+        ]);
+        Self {
+            reserved_mem: Vec::new(),
+            nodes: [root].to_vec(),
+        }
+    }
+
+    /// Node at absolute path like `/soc/uart@10000000`, `/` being the root.
+    pub fn node_by_path(&self, path: &str) -> Result<Arc<SpinRWLock<DTBNode>>, ErrorNum> {
+        if !path.starts_with('/') {
+            return Err(ErrorNum::EINVAL);
+        }
+        let mut node = self.nodes.iter().find(|node| node.acquire_r().unit_name.is_empty()).cloned().ok_or(ErrorNum::ENXIO)?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let child = node.acquire_r().children.iter().find(|child| child.acquire_r().unit_name == name).cloned().ok_or(ErrorNum::ENXIO)?;
+            node = child;
+        }
+        Ok(node)
+    }
+
+    /// Add node `name` under `parent_path`. Properties are raw bytes as in a DTB, and get typed the same way.
+    /// The node is seen by every clone of this tree, but no driver is probed for it.
+    pub fn inject_node(&self, parent_path: &str, name: &str, properties: Vec<(String, Vec<u8>)>) -> Result<Arc<SpinRWLock<DTBNode>>, ErrorNum> {
+        if name.is_empty() || name.contains('/') {
+            return Err(ErrorNum::EINVAL);
+        }
+        let parent = self.node_by_path(parent_path)?;
+        let properties = properties.into_iter()
+            .map(|(key, value)| Ok((key.clone(), DTBPropertyValue::from_bytes(key, value)?)))
+            .collect::<Result<Vec<_>, ErrorNum>>()?;
+        let mut parent_w = parent.acquire_w();
+        if parent_w.children.iter().any(|child| child.acquire_r().unit_name == name) {
+            return Err(ErrorNum::EEXIST);
+        }
+        let node = DTBNode::new(name.to_string(), Some(Arc::downgrade(&parent)), true);
+        node.acquire_w().properties = properties;
+        parent_w.children.push(node.clone());
+        Ok(node)
+    }
+
+    /// Remove a node added by inject_node, along with its children. Nodes from the blob can't be removed.
+    pub fn remove_node(&self, path: &str) -> Result<(), ErrorNum> {
+        let node = self.node_by_path(path)?;
+        let node_r = node.acquire_r();
+        if !node_r.injected {
+            return Err(ErrorNum::EPERM);
+        }
+        let parent = node_r.parent.as_ref().and_then(|parent| parent.upgrade()).ok_or(ErrorNum::ENXIO)?;
+        drop(node_r);
+        parent.acquire_w().children.retain(|child| !Arc::ptr_eq(child, &node));
+        Ok(())
+    }
+
+    /// Apply one overlay command:
+    /// - `add <path> [<prop>[=<type>:<v>,<v>...]]...` add a node, type is `u32` or `u64` (big endian cells) or `str`
+    /// - `del <path>` remove an added node
+    ///
+    /// like `add /soc/test@1000 compatible=str:parch,test reg=u32:0,0x1000,0,0x100 interrupts=u32:12`.
+    pub fn apply_overlay(&self, cmd: &str) -> Result<(), ErrorNum> {
+        let mut words = cmd.split_whitespace();
+        let (op, path) = (words.next().ok_or(ErrorNum::EINVAL)?, words.next().ok_or(ErrorNum::EINVAL)?);
+        match op {
+            "add" => {
+                let (parent, name) = path.rsplit_once('/').ok_or(ErrorNum::EINVAL)?;
+                let properties = words.map(parse_overlay_property).collect::<Result<Vec<_>, ErrorNum>>()?;
+                self.inject_node(if parent.is_empty() {"/"} else {parent}, name, properties)?;
+                Ok(())
+            },
+            "del" => self.remove_node(path),
+            _ => Err(ErrorNum::EINVAL),
+        }
+    }
+
     pub fn print(&self, log_level: LogLevel) {
         log!(log_level, "===== DeviceTree print begin =====");
         log!(log_level, " - Reserved memory regions: ");
@@ -260,6 +560,54 @@ impl DeviceTree {
         }
     }
 
+    /// ISA string of a hart, like `rv64imafdcsu`.
+    pub fn hart_isa(&self, hart_id: usize) -> Option<String> {
+        let cpus = self.search("device_type", DTBPropertyValue::CStr("cpu".to_string())).ok()?;
+        for cpu in cpus {
+            let cpu = cpu.acquire_r();
+            let reg = cpu.get_value("reg").ok()?.get_custom().ok()?;
+            if reg.len() == 4 && u32::from_be_bytes(reg.try_into().ok()?) as usize == hart_id {
+                return cpu.get_value("riscv,isa").ok()?.get_cstr().ok();
+            }
+        }
+        None
+    }
+
+    /// Kernel command line in /chosen, if any.
+    pub fn bootargs(&self) -> Option<String> {
+        let chosen = self.search_name("chosen").ok()?;
+        let bootargs = chosen.acquire_r().get_value("bootargs").ok()?;
+        bootargs.get_cstr().ok()
+    }
+
+    /// [start, end) of initrd loaded by firmware, if any. Cells can be 32 or 64 bit.
+    pub fn initrd(&self) -> Option<(PhysAddr, PhysAddr)> {
+        let chosen = self.search_name("chosen").ok()?;
+        let chosen = chosen.acquire_r();
+        let read_addr = |key: &str| -> Option<PhysAddr> {
+            let raw = chosen.get_value(key).ok()?.get_custom().ok()?;
+            match raw.len() {
+                4 => Some(PhysAddr::from(u32::from_be_bytes(raw.try_into().ok()?) as usize)),
+                8 => Some(PhysAddr::from(u64::from_be_bytes(raw.try_into().ok()?) as usize)),
+                _ => None
+            }
+        };
+        let start = read_addr("linux,initrd-start")?;
+        let end = read_addr("linux,initrd-end")?;
+        if end.0 <= start.0 {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    /// First `ramoops` region in /reserved-memory, for the crash dump.
+    pub fn crash_region(&self) -> Option<(PhysAddr, PhysAddr)> {
+        let node = self.serach_compatible("ramoops").ok()?.into_iter().next()?;
+        let reg = node.acquire_r().reg_value().ok()?;
+        let region = reg.first()?;
+        Some((PhysAddr::from(region.address), PhysAddr::from(region.address + region.size)))
+    }
+
     pub fn contains_field(&self, field: &str) -> Result<Vec<Arc<SpinRWLock<DTBNode>>>, ErrorNum> {
         let mut res = Vec::new();
         for child in self.nodes.iter() {
@@ -301,6 +649,35 @@ impl DeviceTree {
     }
 }
 
+/// `<prop>[=<type>:<values>]` of an overlay command to raw bytes.
+fn parse_overlay_property(spec: &str) -> Result<(String, Vec<u8>), ErrorNum> {
+    let (key, value) = match spec.split_once('=') {
+        Some((key, value)) => (key, value),
+        None => return Ok((spec.to_string(), Vec::new())),
+    };
+    let parse_int = |s: &str| -> Result<u64, ErrorNum> {
+        match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse::<u64>(),
+        }.map_err(|_| ErrorNum::EINVAL)
+    };
+    let mut bytes = Vec::new();
+    match value.split_once(':').ok_or(ErrorNum::EINVAL)? {
+        ("u32", cells) => for cell in cells.split(',') {
+            bytes.extend(u32::try_from(parse_int(cell)?).map_err(|_| ErrorNum::EINVAL)?.to_be_bytes());
+        },
+        ("u64", cells) => for cell in cells.split(',') {
+            bytes.extend(parse_int(cell)?.to_be_bytes());
+        },
+        ("str", strs) => for s in strs.split(',') {
+            bytes.extend(s.as_bytes());
+            bytes.push(0);
+        },
+        _ => return Err(ErrorNum::EINVAL),
+    }
+    Ok((key.to_string(), bytes))
+}
+
 #[derive(Copy, Clone)]
 pub struct DTBMemReserve {
     start: PhysAddr,
@@ -339,6 +716,18 @@ impl DTBPropertyValue {
 }
 
 impl DTBPropertyValue {
+    /// Back to the big endian bytes it was parsed from.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Empty             => Vec::new(),
+            Self::UInt32(val)       => val.to_be_bytes().to_vec(),
+            Self::UInt64(val)       => val.to_be_bytes().to_vec(),
+            Self::CStr(val)         => [val.as_bytes(), &[0]].concat(),
+            Self::CStrList(vals)    => vals.iter().flat_map(|val| val.bytes().chain([0])).collect(),
+            Self::Custom(val)       => val.clone(),
+        }
+    }
+
     pub fn from_bytes(name: String, value: Vec<u8>) -> Result<Self, ErrorNum> {
         let res = match name.as_str() {
             "riscv,isa"             => Self::CStr(Self::read_cstr(value)?),
@@ -364,6 +753,10 @@ impl DTBPropertyValue {
             "offset"                => Self::UInt32(Self::read_u32(value)?),
             "value"                 => Self::UInt32(Self::read_u32(value)?),
             "cpu"                   => Self::UInt32(Self::read_u32(value)?),
+            "bootargs"              => Self::CStr(Self::read_cstr(value)?),
+            "stdout-path"           => Self::CStr(Self::read_cstr(value)?),
+            "linux,initrd-start"    => Self::Custom(value),
+            "linux,initrd-end"      => Self::Custom(value),
             "clock-frequency"       => {
                 if value.len() == size_of::<u32>() {
                     Self::UInt32(Self::read_u32(value)?)
@@ -442,10 +835,23 @@ pub struct DTBNode {
     pub properties: Vec<(String, DTBPropertyValue)>,
     pub children: Vec<Arc<SpinRWLock<DTBNode>>>,
     pub parent: Option<Weak<SpinRWLock<DTBNode>>>,
-    pub driver: UUID
+    pub driver: UUID,
+    /// added at runtime by DeviceTree::inject_node
+    pub injected: bool,
 }
 
 impl DTBNode {
+    fn new(unit_name: String, parent: Option<Weak<SpinRWLock<DTBNode>>>, injected: bool) -> Arc<SpinRWLock<Self>> {
+        Arc::new(SpinRWLock::new(DTBNode {
+            unit_name,
+            properties: Vec::new(),
+            children: Vec::new(),
+            parent,
+            driver: UUID::new(),
+            injected,
+        }))
+    }
+
     pub fn print(&self, log_level: LogLevel, indent: usize) {
         let indent_str: String = (0..indent).map(|_| "\t").collect();
         log!(log_level, "{}Node <{}>", indent_str, self.unit_name);
@@ -487,7 +893,7 @@ impl DTBNode {
     }
 
     /// return node & it's end position's next address
-    pub fn read_node(start: PhysAddr, str_block: PhysAddr, parent: Option<Weak<SpinRWLock<DTBNode>>>) -> Result<Option<(Arc<SpinRWLock<DTBNode>>, PhysAddr)>, ErrorNum> {
+    pub fn read_node(start: PhysAddr, str_block: PhysAddr, parent: Option<Weak<SpinRWLock<DTBNode>>>) -> Result<Option<(Arc<SpinRWLock<DTBNode>>, PhysAddr)>, KernelError> {
         verbose!("Parsing node from {:?}", start);
         #[derive(Debug)]
         enum FSMState {
@@ -498,17 +904,11 @@ impl DTBNode {
 
         let mut state = FSMState::Begin;
         let mut iter = start;
-        let node = Arc::new(SpinRWLock::new(DTBNode {
-            unit_name: "".into(),
-            properties: Vec::new(),
-            children: Vec::new(),
-            parent,
-            driver: UUID::new()
-        }));
+        let node = Self::new("".into(), parent, false);
         let node_clone = node.clone();
         let mut node_guard = node_clone.acquire_w();
         loop {
-            let (token, nxt_addr) = FDTToken::read_token(iter)?;
+            let (token, nxt_addr) = FDTToken::read_token(iter).context("bad token")?;
             verbose!("reading on {:?}, current token {:?}, current state {:?}", iter, token, state);
             match state {
                 FSMState::Begin => {
@@ -523,7 +923,7 @@ impl DTBNode {
                         },
                         FDTToken::EndNode => {
                             warning!("token {:?} found when in {:?} state", token, state);
-                            return Err(ErrorNum::EBADDTB)
+                            return Err(ErrorNum::EBADDTB.ctx("node ends before it begins"))
                         },
                         _ => {
                             return Ok(None)
@@ -535,7 +935,7 @@ impl DTBNode {
                         FDTToken::Property(token) => {
                             iter = nxt_addr;
                             let name = (str_block + token.offset as usize).read_cstr();
-                            node_guard.properties.push((name.clone(), DTBPropertyValue::from_bytes(name, token.value)?));
+                            node_guard.properties.push((name.clone(), DTBPropertyValue::from_bytes(name, token.value).context("bad property value")?));
                         },
                         FDTToken::Nop => {
                             iter = nxt_addr;
@@ -546,7 +946,7 @@ impl DTBNode {
                         FDTToken::EndNode => return Ok(Some((node, nxt_addr))),
                         _ => {
                             warning!("token {:?} found when in {:?} state", token, state);
-                            return Err(ErrorNum::EBADDTB)
+                            return Err(ErrorNum::EBADDTB.ctx("unexpected token in properties"))
                         },
                     }
                 },
@@ -565,7 +965,7 @@ impl DTBNode {
                         FDTToken::EndNode => return Ok(Some((node, nxt_addr))),
                         _ => {
                             warning!("token {:?} found when in {:?} state", token, state);
-                            return Err(ErrorNum::EBADDTB)
+                            return Err(ErrorNum::EBADDTB.ctx("unexpected token in children"))
                         },
                     }
                 },
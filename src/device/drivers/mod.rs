@@ -3,4 +3,5 @@ pub mod uart;
 pub mod plic;
 pub mod poweroff;
 pub mod reboot;
+pub mod pstore;
 pub mod virtio_mmio;
\ No newline at end of file
@@ -4,39 +4,12 @@ use alloc::vec::Vec;
 use crate::device::DeviceTree;
 use crate::mem::PhysAddr;
 use crate::process::get_hart_id;
-use crate::utils::{ErrorNum, Mutex, RWLock, SpinMutex, cast_bytes};
+use crate::utils::{ErrorNum, Mutex, RWLock, SpinMutex};
 use crate::device::device_manager::{Driver, IntController};
+use crate::device::{ioctl_abi::*, ioctl_arg, ioctl_res};
 use core::fmt::Debug;
 use core::mem::size_of;
 
-enum_with_tryfrom_usize!{
-    #[repr(usize)]
-    pub enum IOCtlOp {
-        SetIRQPriority = 1,
-        SetHartIRQAvailability = 2,
-        SetHartIntThreshold = 3,
-        ReadHartIntThreshold = 4,
-    }
-}
-
-#[derive(Debug, Copy, Clone)]
-pub enum IOCtlParam {
-    /// irq, priority
-    SetIRQPriority(u32, u32),
-    /// hartid, irq, enable
-    SetHartIRQAvailability(usize, u32, bool),
-    /// hartid, threshold
-    SetHartIntThreshold(usize, u32),
-    ReadHartIntThreshold(usize)
-}
-
-pub enum IOCtlRes {
-    SetIRQPriority,
-    SetHartIRQAvailability,
-    SetHartIntThreshold,
-    ReadHartIntThreshold(u32)
-}
-
 pub struct PLIC {
     base_address: PhysAddr,
     dev_tree: DeviceTree,
@@ -157,29 +130,27 @@ impl Driver for PLIC {
     }
 
     fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
-        let op: IOCtlOp = op.try_into()?;
-        let param: IOCtlParam = cast_bytes(data)?;
-        let res = match (op, param) {
-            (IOCtlOp::SetIRQPriority, IOCtlParam::SetIRQPriority(irq, priority)) => {
-                self.operator.acquire().set_irq_priority(irq, priority);
-                IOCtlRes::SetIRQPriority
+        match op {
+            PLIC_SET_IRQ_PRIORITY => {
+                let arg: PLICIRQPriority = ioctl_arg(op, data)?;
+                self.operator.acquire().set_irq_priority(arg.irq, arg.priority);
             },
-            (IOCtlOp::SetHartIRQAvailability, IOCtlParam::SetHartIRQAvailability(hart, irq, availability)) => {
-                self.operator.acquire().hart_irq_availability(hart, irq, availability);
-                IOCtlRes::SetHartIRQAvailability
+            PLIC_SET_HART_IRQ => {
+                let arg: PLICHartIRQ = ioctl_arg(op, data)?;
+                self.operator.acquire().hart_irq_availability(arg.hart as usize, arg.irq, arg.enable != 0);
             },
-            (IOCtlOp::SetHartIntThreshold, IOCtlParam::SetHartIntThreshold(hart, threshold)) => {
-                self.operator.acquire().set_hart_priority_threshold(hart, threshold);
-                IOCtlRes::SetHartIntThreshold
+            PLIC_SET_HART_THRESHOLD => {
+                let arg: PLICHartThreshold = ioctl_arg(op, data)?;
+                self.operator.acquire().set_hart_priority_threshold(arg.hart as usize, arg.threshold);
             },
-            (IOCtlOp::ReadHartIntThreshold, IOCtlParam::ReadHartIntThreshold(hart)) => {
-                IOCtlRes::ReadHartIntThreshold(self.operator.acquire().read_hart_priority_threshold(hart))
+            PLIC_GET_HART_THRESHOLD => {
+                let mut arg: PLICHartThreshold = ioctl_arg(op, data)?;
+                arg.threshold = self.operator.acquire().read_hart_priority_threshold(arg.hart as usize);
+                return Ok(ioctl_res(op, &arg));
             },
-            _ => return Err(ErrorNum::EINVAL),
-        };
-
-        let slice = unsafe{core::slice::from_raw_parts(&res as *const IOCtlRes as *const u8, size_of::<IOCtlRes>())};
-        Ok(slice.to_vec())
+            _ => return Err(ErrorNum::ENOTTY),
+        }
+        Ok(Vec::new())
     }
 
     fn handle_int(&self) -> Result<(), crate::utils::ErrorNum> {
@@ -217,3 +188,5 @@ impl IntController for PLIC {
         Ok(self.operator.acquire().claim_hart_interrupt(get_hart_id()))
     }
 }
+
+register_driver!(plic, PLIC, ["riscv,plic0"]);
@@ -1,7 +1,7 @@
 use alloc::{sync::Arc, vec::Vec};
 
-use crate::{device::{device_manager::Driver, device_tree::DTBPropertyValue}, mem::PhysAddr, utils::{RWLock}};
-use core::{fmt::Debug, mem::size_of};
+use crate::{device::{device_manager::Driver, device_tree::DTBPropertyValue, ioctl_abi::SYSCON_TRIGGER, ioctl_no_arg}, mem::PhysAddr, utils::{RWLock}};
+use core::fmt::Debug;
 use crate::utils::ErrorNum;
 
 /// This is a generic poweroff dirver using syscon to map the poweroff register.
@@ -17,13 +17,6 @@ impl Debug for PowerOff {
     }
 }
 
-enum_with_tryfrom_usize!{
-    #[repr(usize)]
-    pub enum IOCtlOp {
-        Shutdown = 1,
-    }
-}
-
 impl PowerOff {
     pub fn shutdown(&self) {
         unsafe {
@@ -69,18 +62,15 @@ impl Driver for PowerOff {
     }
 
     fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
-        let op: IOCtlOp = op.try_into()?;
-        // sanity check
-        if size_of::<()>() != data.len() {
-            return Err(ErrorNum::EINVAL);
-        }
         match op {
-            IOCtlOp::Shutdown => {
+            SYSCON_TRIGGER => {
+                ioctl_no_arg(op, &data)?;
                 // TODO: write modified context information into nvm, then shutdown. Maybe asm code.
                 self.shutdown();
                 // The modified context will take us here, and it WILL return.
                 return Ok(Vec::new())
             },
+            _ => Err(ErrorNum::ENOTTY)
         }
     }
 
@@ -108,3 +98,5 @@ impl Driver for PowerOff {
         Err(ErrorNum::EPERM)
     }
 }
+
+register_driver!(poweroff, PowerOff, ["syscon-poweroff"]);
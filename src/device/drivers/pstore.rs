@@ -0,0 +1,110 @@
+//! Persistent log on a `pmem-region` of the DTB, like the one QEMU gives for a memory-backend-file. Milestone and
+//! fatal lines go here as well as to the console, so a boot that dies before UART is up, or with the console
+//! misconfigured, can be read back next boot in /proc/pstore or from the backing file on host.
+//!
+//! Region starts with a header, log text after it as a ring that carries on across boots. Found with a raw DTB
+//! scan on first write, so it works before heap and device manager. Has to be outside RAM: kernel maps it as MMIO
+//! then, and the page allocator never hands it out.
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::{config::{PHYS_END_ADDR, PHYS_START_ADDR}, device::pstore_region};
+
+const PSTORE_MAGIC: u64 = u64::from_le_bytes(*b"PARCHPST");
+
+#[repr(C)]
+struct PStoreHeader {
+    magic: u64,
+    /// bytes ever written, head of the ring is at written % ring size
+    written: u64,
+}
+
+const UNPROBED  : usize = 0;
+const ABSENT    : usize = 1;
+const READY     : usize = 2;
+
+static PSTORE_STATE: AtomicUsize = AtomicUsize::new(UNPROBED);
+static PSTORE_BASE: AtomicUsize = AtomicUsize::new(0);
+static PSTORE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+fn header() -> *mut PStoreHeader {
+    PSTORE_BASE.load(Ordering::Relaxed) as *mut PStoreHeader
+}
+
+fn ring_size() -> usize {
+    PSTORE_SIZE.load(Ordering::Relaxed) - core::mem::size_of::<PStoreHeader>()
+}
+
+/// Look for the region and format it if it's not ours yet. Writers only.
+fn probe() -> bool {
+    match PSTORE_STATE.load(Ordering::Acquire) {
+        READY => return true,
+        ABSENT => return false,
+        _ => {}
+    }
+    let usable = pstore_region().filter(|(start, end)| {
+        (end.0 <= PHYS_START_ADDR.0 || start.0 >= PHYS_END_ADDR.0) && end.0 > start.0 + core::mem::size_of::<PStoreHeader>()
+    });
+    let (start, end) = match usable {
+        Some(region) => region,
+        None => {
+            PSTORE_STATE.store(ABSENT, Ordering::Release);
+            return false;
+        }
+    };
+    PSTORE_BASE.store(start.0, Ordering::Relaxed);
+    PSTORE_SIZE.store(end.0 - start.0, Ordering::Relaxed);
+    unsafe {
+        let header = header();
+        if core::ptr::read_volatile(&(*header).magic) != PSTORE_MAGIC {
+            core::ptr::write_volatile(&mut (*header).written, 0);
+            core::ptr::write_volatile(&mut (*header).magic, PSTORE_MAGIC);
+        }
+    }
+    PSTORE_STATE.store(READY, Ordering::Release);
+    let _ = fmt::Write::write_str(&mut PStoreWriter, "======== boot ========\n");
+    true
+}
+
+/// Appends to the persistent log, dropped if there's none. Only under the print lock, like LogRingWriter.
+pub struct PStoreWriter;
+
+impl fmt::Write for PStoreWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !probe() {
+            return Ok(());
+        }
+        let header = header();
+        let ring = unsafe {header.add(1) as *mut u8};
+        let size = ring_size();
+        unsafe {
+            let mut written = core::ptr::read_volatile(&(*header).written) as usize;
+            for &b in s.as_bytes() {
+                ring.add(written % size).write_volatile(b);
+                written += 1;
+            }
+            core::ptr::write_volatile(&mut (*header).written, written as u64);
+        }
+        Ok(())
+    }
+}
+
+pub fn pstore_present() -> bool {
+    PSTORE_STATE.load(Ordering::Acquire) == READY
+}
+
+/// Whole persistent log, oldest first, this boot's and the ones before. None if there's no region, or nothing
+/// has been logged this boot to find it.
+pub fn pstore_text() -> Option<String> {
+    if !pstore_present() {
+        return None;
+    }
+    let header = header();
+    let ring = unsafe {header.add(1) as *const u8};
+    let size = ring_size();
+    let written = unsafe {core::ptr::read_volatile(&(*header).written)} as usize;
+    let len = written.min(size);
+    let text: Vec<u8> = (written - len..written).map(|i| unsafe {ring.add(i % size).read_volatile()}).collect();
+    Some(String::from_utf8_lossy(&text).into_owned())
+}
@@ -3,7 +3,7 @@
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use crate::device::DeviceTree;
+use crate::device::{DeviceTree, ioctl_abi::{RTC_READ_TIME, RTCTime}, ioctl_no_arg, ioctl_res};
 
 use crate::{device::device_manager::Driver, mem::PhysAddr};
 use crate::utils::{ErrorNum, RWLock, UUID};
@@ -20,14 +20,6 @@ pub struct RTC {
     addr: PhysAddr, 
 }
 
-enum_with_tryfrom_usize!{
-    #[repr(usize)]
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum IOCtlOp {
-        ReadTime = 1,
-    }
-}
-
 impl Debug for RTC {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "RTC @ {:?}", self.addr)
@@ -71,15 +63,12 @@ impl Driver for RTC {
     }
 
     fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
-        let op: IOCtlOp = op.try_into()?;
-        // sanity check
-        if size_of::<()>() != data.len() {
-            return Err(ErrorNum::EINVAL);
-        }
-        if op == IOCtlOp::ReadTime {
-            Ok(self.read_time().to_le_bytes().to_vec())
-        } else {
-            Err(ErrorNum::ENOSYS)
+        match op {
+            RTC_READ_TIME => {
+                ioctl_no_arg(op, &data)?;
+                Ok(ioctl_res(op, &RTCTime{time: self.read_time()}))
+            },
+            _ => Err(ErrorNum::ENOTTY)
         }
     }
 
@@ -110,4 +99,6 @@ impl Driver for RTC {
             Err(ErrorNum::EPERM)
         }
     }
-}
\ No newline at end of file
+}
+
+register_driver!(rtc, RTC, ["google,goldfish-rtc"]);
@@ -1,20 +1,65 @@
-//! UART driver for /dev/pts
-//! kernel print use utils/uart.rs
+//! UART driver for /dev/ttyS<N>
+//! Ports are numbered by base address, kernel print goes to the one picked by `console=ttyS<N>` bootarg, ttyS0 by
+//! default.
 
-use core::mem::size_of;
 use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
 
-use crate::{device::{device_manager::Driver}, mem::PhysAddr, process::get_processor, utils::{Mutex, MutexGuard, RWLock, SpinMutex, UUID, cast_bytes}};
-use core::{fmt::Debug};
+use crate::{device::{DTBNode, DeviceTree, DEVICE_MANAGER, device_manager::Driver, ioctl_abi::*, ioctl_arg, ioctl_no_arg, ioctl_res}, mem::PhysAddr, process::{get_processor, WaitQueue, Waker}, utils::{Mutex, MutexGuard, RWLock, SpinMutex, SpinRWLock, Condvar, UUID, K_PRINT_HANDLER}};
+use core::{fmt::Debug, sync::atomic::{AtomicUsize, Ordering}};
 use crate::utils::ErrorNum;
 use bitflags::*;
 
+const UART_FIFO_DEPTH: usize = 16;
+
+/// N of the ttyS<N> kernel print goes to.
+static CONSOLE_PORT: AtomicUsize = AtomicUsize::new(0);
+
+/// Device tree nodes of all UARTs, the index is the N of /dev/ttyS<N>.
+pub fn tty_ports(dev_tree: &DeviceTree) -> Vec<Arc<SpinRWLock<DTBNode>>> {
+    let mut ports = dev_tree.serach_compatible("ns16550a").unwrap_or_default();
+    ports.extend(dev_tree.serach_compatible("ns8250").unwrap_or_default());
+    ports.sort_by_key(|node| node.acquire_r().reg_value().ok().and_then(|reg| reg.first().map(|r| r.address)));
+    ports
+}
+
+/// Node of `name`, which is like ttyS0.
+pub fn tty_port(dev_tree: &DeviceTree, name: &str) -> Option<Arc<SpinRWLock<DTBNode>>> {
+    let idx: usize = name.strip_prefix("ttyS")?.parse().ok()?;
+    tty_ports(dev_tree).get(idx).cloned()
+}
+
+pub fn console_port() -> usize {
+    CONSOLE_PORT.load(Ordering::Relaxed)
+}
+
+/// Send kernel print to `name`, e.g. ttyS1.
+pub fn set_console(name: &str) -> Result<(), ErrorNum> {
+    let device_mgr = DEVICE_MANAGER.acquire_r();
+    let node = tty_port(&device_mgr.get_dev_tree(), name).ok_or(ErrorNum::ENODEV)?;
+    let driver = device_mgr.get_device(node.acquire_r().driver)?;
+    K_PRINT_HANDLER.acquire().set_driver(driver);
+    CONSOLE_PORT.store(name["ttyS".len()..].parse().unwrap(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Takes over input of a port, see UART::attach.
+pub trait TtyInput: Send + Sync {
+    /// called from interrupt handler, must not sleep
+    fn receive(&self, b: u8);
+}
+
 pub struct UART {
     base_address: PhysAddr,
     clock_freq: u32,
     operator: SpinMutex<UARTOperator>,
     buffer_r: SpinMutex<VecDeque<u8>>,
     buffer_w: SpinMutex<VecDeque<u8>>,
+    /// notified by handle_int when buffer_r get filled
+    read_cond: Condvar,
+    /// writers wait here for buffer_w to be drained
+    write_queue: WaitQueue,
+    /// received bytes go here instead of buffer_r if set
+    input: SpinMutex<Option<Arc<dyn TtyInput>>>,
 }
 
 struct UARTOperator{
@@ -22,16 +67,6 @@ struct UARTOperator{
     rcvr_length: RCVRLength,
 }
 
-enum_with_tryfrom_usize!{
-    #[repr(usize)]
-    pub enum IOCtlOp {
-        WriteByte = 1,
-        ReadByte = 2,
-        Config = 3,
-        Sync = 4,
-    }
-}
-
 #[derive(Copy, Clone, Debug)]
 pub enum ParityMode {
     EvenParity,
@@ -83,19 +118,48 @@ pub enum RCVRLength {
     Fourteen
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum IOCtlParam {
-    Write(u8),
-    Read,
-    Config(Config),
-    Sync,
-}
+impl TryFrom<UARTConfig> for Config {
+    type Error = ErrorNum;
 
-pub enum IOCtlRes {
-    Write,
-    Read(u8),
-    Config,
-    Sync,
+    fn try_from(c: UARTConfig) -> Result<Self, Self::Error> {
+        let data_bits = match c.data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            8 => DataBits::Eight,
+            _ => return Err(ErrorNum::EINVAL),
+        };
+        let sticky = if c.parity as usize & UART_PARITY_STICKY != 0 {StickyParity::Enable} else {StickyParity::Disable};
+        let parity = match c.parity as usize & !UART_PARITY_STICKY {
+            UART_PARITY_NONE if sticky == StickyParity::Disable => Parity::Disable,
+            UART_PARITY_EVEN => Parity::Enable(ParityMode::EvenParity, sticky),
+            UART_PARITY_ODD => Parity::Enable(ParityMode::OddParity, sticky),
+            _ => return Err(ErrorNum::EINVAL),
+        };
+        let stop_bits = match c.stop_bits as usize {
+            UART_STOP_ONE => StopBit::One,
+            UART_STOP_ONE_AND_HALF => StopBit::OneAndHalf,
+            UART_STOP_TWO => StopBit::Two,
+            _ => return Err(ErrorNum::EINVAL),
+        };
+        let rcvr_length = match c.rcvr_length {
+            1 => RCVRLength::One,
+            4 => RCVRLength::Four,
+            8 => RCVRLength::Eight,
+            14 => RCVRLength::Fourteen,
+            _ => return Err(ErrorNum::EINVAL),
+        };
+        if c.baud_rate == 0 {
+            return Err(ErrorNum::EINVAL);
+        }
+        Ok(Self {
+            baud_rate: c.baud_rate,
+            data_bits,
+            parity,
+            stop_bits,
+            rcvr_length,
+        })
+    }
 }
 
 #[repr(u8)]
@@ -270,6 +334,22 @@ impl UARTOperator {
             }
         }
     }
+
+    /// Non-blocking version of dump_w_buffer, fill the FIFO if it's empty.
+    /// Rest of the buffer will be sent on THREmpty interrupt. Return true if w_buffer is drained.
+    pub fn fill_fifo(&self, w_buffer: &mut VecDeque<u8>) -> bool {
+        let flags = LSRFlags::from_bits(self.read_reg(self.line_status_register())).unwrap();
+        if flags.contains(LSRFlags::FIFO_AVAILABLE) {
+            for _ in 0..UART_FIFO_DEPTH {
+                if let Some(b) = w_buffer.pop_front() {
+                    self.write_reg(self.transmitter_holding_buffer(), b);
+                } else {
+                    break;
+                }
+            }
+        }
+        w_buffer.is_empty()
+    }
 }
 
 impl Debug for UART {
@@ -279,40 +359,80 @@ impl Debug for UART {
 }
 
 impl UART {
+    /// Send all input to `sink` from now on, reading the port directly gets nothing after this.
+    pub fn attach(&self, sink: Arc<dyn TtyInput>) {
+        *self.input.acquire() = Some(sink);
+    }
+
+    fn forward_input(&self) {
+        let sink = self.input.acquire().clone();
+        if let Some(sink) = sink {
+            let received: Vec<u8> = self.buffer_r.acquire().drain(..).collect();
+            for b in received {
+                sink.receive(b);
+            }
+        }
+    }
+
+    /// A read wouldn't block. Never once input is attached elsewhere, reads of the port get nothing then.
+    pub fn read_ready(&self) -> bool {
+        if self.input.acquire().is_some() {
+            return false;
+        }
+        // operator first, buffer next
+        let operator = self.operator.acquire();
+        let mut buffer_r = self.buffer_r.acquire();
+        operator.deplete_r_buffer(&mut buffer_r);
+        !buffer_r.is_empty()
+    }
+
+    /// `waker` is woken next time input arrives.
+    pub fn register_waker(&self, waker: &Arc<Waker>) {
+        self.read_cond.register_waker(waker);
+    }
+
     fn write_byte(&self, b: u8) {
         self.buffer_w.acquire().push_back(b);
     }
 
     fn write_arr(&self, arr: Vec<u8>) {
+        // kernel print holds spin locks, must spin
+        let can_sleep = get_processor().can_sleep();
         // operator first, buffer next
-        let operator = self.operator.acquire();
-        let mut buffer_w = self.buffer_w.acquire();
-        buffer_w.extend(arr);
-        operator.dump_w_buffer(&mut buffer_w);
+        let mut operator = self.operator.acquire();
+        if !can_sleep {
+            let mut buffer_w = self.buffer_w.acquire();
+            buffer_w.extend(arr);
+            operator.dump_w_buffer(&mut buffer_w);
+            return;
+        }
+        self.buffer_w.acquire().extend(arr);
+        loop {
+            let mut buffer_w = self.buffer_w.acquire();
+            if operator.fill_fifo(&mut buffer_w) {
+                return;
+            }
+            drop(operator);
+            // woke by handle_int when buffer_w is drained
+            self.write_queue.sleep_on(buffer_w);
+            operator = self.operator.acquire();
+        }
     }
 
     fn read_byte(&self) -> u8 { 
-        let operator = self.operator.acquire();
-        // check buffer
-        let mut buffer_r = self.buffer_r.acquire();
-        if !buffer_r.is_empty() {
-            return buffer_r.pop_front().unwrap();
-        }
-        drop(buffer_r);
-        // check fifo, hold operator in case kernel need read
-        if let Ok(b) = operator.read() {
-            return b;
-        }
-        drop(operator);
         loop {
-            let core = get_processor();
-            if core.current().is_some() {
-                // sleep if is user program
-                core.suspend_switch();
-            }
-            if let Ok(b) = self.operator.acquire().read() {
+            // operator first, buffer next
+            let operator = self.operator.acquire();
+            let mut buffer_r = self.buffer_r.acquire();
+            // check fifo, in case interrupt has not been delivered yet
+            operator.deplete_r_buffer(&mut buffer_r);
+            drop(operator);
+            if let Some(b) = buffer_r.pop_front() {
                 return b;
             }
+            // sleep if is user program, kernel just spin
+            // must not hold buffer_r when retrying, or lock order with operator breaks
+            drop(self.read_cond.wait(buffer_r));
         }
     }
 }
@@ -321,12 +441,10 @@ impl Driver for UART {
     fn new(dev_tree: crate::device::DeviceTree) -> Result<alloc::vec::Vec<(UUID, alloc::sync::Arc<dyn Driver>)>, crate::utils::ErrorNum> where Self: Sized {
         let mut res = Vec::new();
         
-        let mut compatible = dev_tree.serach_compatible("ns16550a")?;
-        compatible.extend(dev_tree.serach_compatible("ns8250")?);
-        for c in compatible {
+        for (idx, c) in tty_ports(&dev_tree).into_iter().enumerate() {
             let node = c.acquire_r();
             let uuid = node.driver;
-            verbose!("Creating Driver instance for {} with uuid {}.", node.unit_name, uuid);
+            verbose!("Creating Driver instance for {} (ttyS{}) with uuid {}.", node.unit_name, idx, uuid);
             let base_address: PhysAddr = node.reg_value()?[0].address.into();
             let clock_freq = node.get_value("clock-frequency")?.get_u32()?;
             let driver = Self {
@@ -338,6 +456,9 @@ impl Driver for UART {
                 }),
                 buffer_r: SpinMutex::new("UART", VecDeque::new()),
                 buffer_w: SpinMutex::new("UART", VecDeque::new()),
+                read_cond: Condvar::new("UART read"),
+                write_queue: WaitQueue::new("UART write"),
+                input: SpinMutex::new("UART input", None),
             };
             res.push((uuid, Arc::new(driver).as_driver()));
         }
@@ -364,11 +485,23 @@ impl Driver for UART {
         let operator = self.operator.acquire();
         match operator.read_int_cause()? {
             IntStatus::ModemStatus => unimplemented!("Not enabled."),
-            IntStatus::THREmpty => operator.dump_w_buffer(&mut self.buffer_w.acquire()),
-            IntStatus::RecvAvail => operator.deplete_r_buffer(&mut self.buffer_r.acquire()),
+            IntStatus::THREmpty => {
+                if operator.fill_fifo(&mut self.buffer_w.acquire()) {
+                    self.write_queue.wake_all();
+                }
+            },
+            IntStatus::RecvAvail => {
+                operator.deplete_r_buffer(&mut self.buffer_r.acquire());
+                self.read_cond.notify_all();
+            },
             IntStatus::RecvLineStatus => unimplemented!("Not enabled."),
-            IntStatus::TimeOut => operator.deplete_r_buffer(&mut self.buffer_r.acquire()),
+            IntStatus::TimeOut => {
+                operator.deplete_r_buffer(&mut self.buffer_r.acquire());
+                self.read_cond.notify_all();
+            },
         }
+        drop(operator);
+        self.forward_input();
         Ok(())
     }
 
@@ -399,33 +532,31 @@ impl Driver for UART {
     }
 
     fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
-        let op = IOCtlOp::try_from(op)?;
-        let param: IOCtlParam = cast_bytes(data)?;
-        let res = match (op, param) {
-            (IOCtlOp::WriteByte, IOCtlParam::Write(b)) => {
-                self.write_byte(b);
-                IOCtlRes::Write
+        match op {
+            UART_WRITE_BYTE => {
+                let arg: UARTByte = ioctl_arg(op, data)?;
+                self.write_byte(arg.byte);
+                Ok(Vec::new())
             },
-            (IOCtlOp::ReadByte, IOCtlParam::Read) => {
-                IOCtlRes::Read(self.read_byte())
+            UART_READ_BYTE => {
+                ioctl_no_arg(op, &data)?;
+                Ok(ioctl_res(op, &UARTByte{byte: self.read_byte()}))
             },
-            (IOCtlOp::Config, IOCtlParam::Config(param)) => {
-                self.operator.acquire().config(self.clock_freq, param)?;
-                IOCtlRes::Config
+            UART_CONFIG => {
+                let arg: UARTConfig = ioctl_arg(op, data)?;
+                self.operator.acquire().config(self.clock_freq, arg.try_into()?)?;
+                Ok(Vec::new())
             },
-            (IOCtlOp::Sync, IOCtlParam::Sync) => {
+            UART_SYNC => {
+                ioctl_no_arg(op, &data)?;
                 let operator = self.operator.acquire();
                 operator.deplete_r_buffer(&mut self.buffer_r.acquire());
                 operator.dump_w_buffer(&mut self.buffer_w.acquire());
-
-                IOCtlRes::Sync
+                Ok(Vec::new())
             },
-            _ => {
-                return Err(ErrorNum::EINVAL);
-            }
-        };
-
-        let slice = unsafe{core::slice::from_raw_parts(&res as *const IOCtlRes as *const u8, size_of::<IOCtlRes>())};
-        Ok(slice.to_vec())
+            _ => Err(ErrorNum::ENOTTY)
+        }
     }
-}
\ No newline at end of file
+}
+
+register_driver!(uart, UART, ["ns16550a", "ns8250"]);
@@ -0,0 +1,225 @@
+//! ioctl ABI of the device drivers, the only part of them user space sees.
+//!
+//! Ops are encoded like Linux _IOC: direction, size of the argument, driver type and number in one word, so a
+//! mismatched argument is caught before the driver casts it. Arguments and results are packed C structs of fixed
+//! width fields only. Bump IOCTL_ABI_VERSION on any incompatible change, user space reads it with
+//! IOCTL_GET_ABI_VERSION on any device.
+//!
+//! This file must stay free of kernel dependencies: build.rs includes it and generates parch_ioctl.h and
+//! parch_ioctl.rs for user programs from ABI_CONSTS and ABI_STRUCTS.
+
+use core::mem::size_of;
+
+macro_rules! abi_consts {
+    ($($(#[$meta:meta])* $name:ident = $val:expr;)*) => {
+        $($(#[$meta])* pub const $name: usize = $val;)*
+        /// Everything above, for header generation.
+        pub const ABI_CONSTS: &[(&str, usize)] = &[$((stringify!($name), $name)),*];
+    };
+}
+
+macro_rules! abi_structs {
+    ($($(#[$meta:meta])* pub struct $name:ident { $($(#[$fmeta:meta])* pub $field:ident : $ty:ty,)* })*) => {
+        $(
+            $(#[$meta])*
+            #[repr(C, packed)]
+            #[derive(Clone, Copy, Debug, Default)]
+            pub struct $name { $($(#[$fmeta])* pub $field: $ty,)* }
+        )*
+        /// Name, fields and size of everything above, for header generation.
+        pub const ABI_STRUCTS: &[(&str, &[(&str, &str)], usize)] = &[
+            $((stringify!($name), &[$((stringify!($field), stringify!($ty))),*], size_of::<$name>())),*
+        ];
+    };
+}
+
+pub const IOC_NRBITS    : usize = 8;
+pub const IOC_TYPEBITS  : usize = 8;
+pub const IOC_SIZEBITS  : usize = 14;
+pub const IOC_NRSHIFT   : usize = 0;
+pub const IOC_TYPESHIFT : usize = IOC_NRSHIFT + IOC_NRBITS;
+pub const IOC_SIZESHIFT : usize = IOC_TYPESHIFT + IOC_TYPEBITS;
+pub const IOC_DIRSHIFT  : usize = IOC_SIZESHIFT + IOC_SIZEBITS;
+
+pub const fn ioc(dir: usize, ty: usize, nr: usize, size: usize) -> usize {
+    (dir << IOC_DIRSHIFT) | (ty << IOC_TYPESHIFT) | (nr << IOC_NRSHIFT) | (size << IOC_SIZESHIFT)
+}
+
+/// no argument
+pub const fn io(ty: usize, nr: usize) -> usize {
+    ioc(IOC_NONE, ty, nr, 0)
+}
+
+/// result of `size` bytes
+pub const fn ior(ty: usize, nr: usize, size: usize) -> usize {
+    ioc(IOC_READ, ty, nr, size)
+}
+
+/// argument of `size` bytes
+pub const fn iow(ty: usize, nr: usize, size: usize) -> usize {
+    ioc(IOC_WRITE, ty, nr, size)
+}
+
+/// argument and result of `size` bytes each
+pub const fn iowr(ty: usize, nr: usize, size: usize) -> usize {
+    ioc(IOC_READ | IOC_WRITE, ty, nr, size)
+}
+
+pub const fn ioc_dir(op: usize) -> usize {
+    op >> IOC_DIRSHIFT
+}
+
+pub const fn ioc_type(op: usize) -> usize {
+    (op >> IOC_TYPESHIFT) & ((1 << IOC_TYPEBITS) - 1)
+}
+
+pub const fn ioc_size(op: usize) -> usize {
+    (op >> IOC_SIZESHIFT) & ((1 << IOC_SIZEBITS) - 1)
+}
+
+abi_consts! {
+    IOCTL_ABI_VERSION       = 1;
+
+    IOC_NONE                = 0;
+    IOC_WRITE               = 1;
+    IOC_READ                = 2;
+
+    IOC_TYPE_GENERIC        = 0;
+    IOC_TYPE_UART           = b'U' as usize;
+    IOC_TYPE_PLIC           = b'P' as usize;
+    IOC_TYPE_RTC            = b'R' as usize;
+    IOC_TYPE_SYSCON         = b'S' as usize;
+    IOC_TYPE_VT             = b'V' as usize;
+    IOC_TYPE_TTY            = b'T' as usize;
+    IOC_TYPE_LOOP           = b'L' as usize;
+
+    /// answered by the kernel for any fd, result is IOCtlVersion
+    IOCTL_GET_ABI_VERSION   = ior(IOC_TYPE_GENERIC, 1, size_of::<IOCtlVersion>());
+
+    UART_WRITE_BYTE         = iow(IOC_TYPE_UART, 1, size_of::<UARTByte>());
+    UART_READ_BYTE          = ior(IOC_TYPE_UART, 2, size_of::<UARTByte>());
+    UART_CONFIG             = iow(IOC_TYPE_UART, 3, size_of::<UARTConfig>());
+    /// flush both buffers
+    UART_SYNC               = io (IOC_TYPE_UART, 4);
+
+    UART_PARITY_NONE        = 0;
+    UART_PARITY_EVEN        = 1;
+    UART_PARITY_ODD         = 2;
+    /// or'ed with even or odd
+    UART_PARITY_STICKY      = 4;
+    UART_STOP_ONE           = 0;
+    UART_STOP_ONE_AND_HALF  = 1;
+    UART_STOP_TWO           = 2;
+
+    PLIC_SET_IRQ_PRIORITY   = iow (IOC_TYPE_PLIC, 1, size_of::<PLICIRQPriority>());
+    PLIC_SET_HART_IRQ       = iow (IOC_TYPE_PLIC, 2, size_of::<PLICHartIRQ>());
+    PLIC_SET_HART_THRESHOLD = iow (IOC_TYPE_PLIC, 3, size_of::<PLICHartThreshold>());
+    /// threshold is ignored in the argument and filled in the result
+    PLIC_GET_HART_THRESHOLD = iowr(IOC_TYPE_PLIC, 4, size_of::<PLICHartThreshold>());
+
+    RTC_READ_TIME           = ior(IOC_TYPE_RTC, 1, size_of::<RTCTime>());
+
+    /// power off or reboot, depends on the device
+    SYSCON_TRIGGER          = io(IOC_TYPE_SYSCON, 1);
+
+    /// switch to virtual console, index from 0
+    VT_ACTIVATE             = iow(IOC_TYPE_VT, 1, size_of::<VTIndex>());
+    VT_GET_ACTIVE           = ior(IOC_TYPE_VT, 2, size_of::<VTIndex>());
+
+    /// N of /dev/pts/<N>, on the master from /dev/ptmx
+    TTY_GET_PTY_INDEX       = ior(IOC_TYPE_TTY, 1, size_of::<TtyIndex>());
+    TTY_GET_MODE            = ior(IOC_TYPE_TTY, 2, size_of::<TtyModeArg>());
+    TTY_SET_MODE            = iow(IOC_TYPE_TTY, 3, size_of::<TtyModeArg>());
+    /// make it the controlling terminal of the caller's session, session leader only
+    TTY_SET_CTTY            = io (IOC_TYPE_TTY, 4);
+    /// foreground process group, caller's session must control the terminal
+    TTY_GET_PGRP            = ior(IOC_TYPE_TTY, 5, size_of::<TtyPgrp>());
+    /// to a group of the caller's session
+    TTY_SET_PGRP            = iow(IOC_TYPE_TTY, 6, size_of::<TtyPgrp>());
+
+    /// TtyModeArg bits, line by line input with erase, kill and EOF editing
+    TTY_CANON               = 1 << 0;
+    TTY_ECHO                = 1 << 1;
+    /// CR typed reads as NL
+    TTY_ICRNL               = 1 << 2;
+    /// NL written goes out as CRNL
+    TTY_ONLCR               = 1 << 3;
+
+    /// back the loop device with the regular file open as LoopFd.fd in the caller, EBUSY if already bound
+    LOOP_SET_FD             = iow(IOC_TYPE_LOOP, 1, size_of::<LoopFd>());
+    LOOP_CLR_FD             = io (IOC_TYPE_LOOP, 2);
+    LOOP_GET_STATUS         = ior(IOC_TYPE_LOOP, 3, size_of::<LoopStatus>());
+}
+
+abi_structs! {
+    pub struct IOCtlVersion {
+        pub version: u32,
+    }
+
+    pub struct UARTByte {
+        pub byte: u8,
+    }
+
+    pub struct UARTConfig {
+        pub baud_rate: u32,
+        /// 5 to 8
+        pub data_bits: u8,
+        /// UART_PARITY_*
+        pub parity: u8,
+        /// UART_STOP_*
+        pub stop_bits: u8,
+        /// receive interrupt trigger level, 1, 4, 8 or 14 bytes
+        pub rcvr_length: u8,
+    }
+
+    pub struct PLICIRQPriority {
+        pub irq: u32,
+        pub priority: u32,
+    }
+
+    pub struct PLICHartIRQ {
+        pub hart: u64,
+        pub irq: u32,
+        /// 0 to disable
+        pub enable: u32,
+    }
+
+    pub struct PLICHartThreshold {
+        pub hart: u64,
+        pub threshold: u32,
+    }
+
+    pub struct RTCTime {
+        /// ns since epoch
+        pub time: u64,
+    }
+
+    pub struct VTIndex {
+        pub index: u32,
+    }
+
+    pub struct TtyIndex {
+        pub index: u32,
+    }
+
+    pub struct TtyModeArg {
+        /// TTY_* bits
+        pub flags: u32,
+    }
+
+    pub struct TtyPgrp {
+        pub pgrp: u32,
+    }
+
+    pub struct LoopFd {
+        pub fd: u32,
+    }
+
+    pub struct LoopStatus {
+        /// 0 if not bound, size is 0 then
+        pub bound: u32,
+        pub block_size: u32,
+        /// of the backing file, in bytes
+        pub size: u64,
+    }
+}
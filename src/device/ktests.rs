@@ -0,0 +1,91 @@
+//! Self tests for the line discipline, ioctl encoding, driver registry and device tree overlays, see utils::ktest.
+
+use alloc::vec::Vec;
+
+use crate::utils::{ErrorNum, RWLock, bootargs, ktest::KTestResult};
+
+use super::{DeviceTree, device_tree::DTBPropertyValue, ioctl_abi::*, ioctl_arg, registry::driver_entries, tty::{LineDiscipline, TtyMode}};
+
+fn type_in(ldisc: &mut LineDiscipline, s: &[u8]) -> Vec<u8> {
+    s.iter().flat_map(|b| ldisc.input(*b)).collect()
+}
+
+fn tty_canonical_editing() -> KTestResult {
+    let mut ldisc = LineDiscipline::new();
+    // erase and kill, nothing readable before the line ends
+    let echo = type_in(&mut ldisc, b"ab\x7fc");
+    kassert!(echo == b"ab\x08 \x08c");
+    kassert!(!ldisc.readable());
+    type_in(&mut ldisc, b"\x15xy\r");
+    kassert!(ldisc.read(64) == b"xy\n");
+
+    // EOF on an empty line reads empty once, after text it only ends the line
+    type_in(&mut ldisc, b"one\n\x04two\x04");
+    kassert!(ldisc.read(64) == b"one\n");
+    kassert!(ldisc.read(64).is_empty());
+    kassert!(ldisc.read(64) == b"two");
+    kassert!(!ldisc.readable());
+
+    ldisc.mode = TtyMode::empty();
+    kassert!(type_in(&mut ldisc, b"q\r").is_empty());
+    kassert!(ldisc.read(1) == b"q");
+    kassert!(ldisc.read(1) == b"\r");
+    Ok(())
+}
+ktest!(tty_canonical_editing, tty_canonical_editing);
+
+fn ioctl_encoding() -> KTestResult {
+    kassert!(ioc_size(UART_CONFIG) == core::mem::size_of::<UARTConfig>());
+    kassert!(ioc_dir(UART_CONFIG) == IOC_WRITE);
+    kassert!(ioc_type(RTC_READ_TIME) == IOC_TYPE_RTC);
+    kassert!(ioctl_arg::<VTIndex>(VT_ACTIVATE, [1u8, 0, 0, 0].to_vec()).map(|arg| arg.index) == Ok(1));
+    // wrong size, or an op without argument
+    kassert!(ioctl_arg::<VTIndex>(VT_ACTIVATE, [1u8].to_vec()).is_err());
+    kassert!(ioctl_arg::<VTIndex>(VT_GET_ACTIVE, [1u8, 0, 0, 0].to_vec()).is_err());
+    Ok(())
+}
+ktest!(ioctl_encoding, ioctl_encoding);
+
+fn driver_registry() -> KTestResult {
+    let entries = driver_entries();
+    kassert!(entries.iter().any(|entry| entry.name == "uart" && entry.compatible.contains(&"ns16550a")));
+    for (idx, entry) in entries.iter().enumerate() {
+        kassert!(!entry.compatible.is_empty());
+        kassert!(entries[idx + 1..].iter().all(|other| other.name != entry.name));
+    }
+    let uart = entries.iter().find(|entry| entry.name == "uart").unwrap();
+    kassert!(uart.enabled(&bootargs::parse("")));
+    kassert!(!uart.enabled(&bootargs::parse("driver.uart=off")));
+    kassert!(uart.enabled(&bootargs::parse("driver.rtc=off driver.uart=on")));
+    Ok(())
+}
+ktest!(driver_registry, driver_registry);
+
+fn device_tree_overlay() -> KTestResult {
+    let dev_tree = DeviceTree::synthetic();
+    kassert!(dev_tree.apply_overlay("add /soc").is_ok());
+    kassert!(dev_tree.apply_overlay("add /soc/rtc@1000 compatible=str:google,goldfish-rtc reg=u32:0,0x1000,0,0x100 interrupts=u32:11").is_ok());
+    kassert!(dev_tree.apply_overlay("add /soc/rtc@1000") == Err(ErrorNum::EEXIST));
+    kassert!(dev_tree.apply_overlay("add /nowhere/rtc@1000") == Err(ErrorNum::ENXIO));
+    kassert!(dev_tree.apply_overlay("add /soc/bad reg=u32:0x100000000") == Err(ErrorNum::EINVAL));
+
+    // the fabricated node looks like one from the blob, to lookups and to the driver
+    let node = dev_tree.node_by_path("/soc/rtc@1000").unwrap();
+    kassert!(node.acquire_r().reg_value().map(|reg| (reg[0].address, reg[0].size)) == Ok((0x1000, 0x100)));
+    kassert!(dev_tree.search_single("interrupts", DTBPropertyValue::UInt32(11)).is_ok());
+    let rtc = driver_entries().iter().find(|entry| entry.name == "rtc").unwrap();
+    kassert!(rtc.matches(&dev_tree));
+    let probed = (rtc.probe)(dev_tree.clone()).unwrap();
+    kassert!(probed.len() == 1 && probed[0].0 == node.acquire_r().driver);
+
+    // /proc/device-tree shows the raw bytes back
+    let compatible = node.acquire_r().get_value("compatible").unwrap();
+    kassert!(compatible.to_bytes() == b"google,goldfish-rtc\0");
+    kassert!(node.acquire_r().get_value("interrupts").unwrap().to_bytes() == [0, 0, 0, 11]);
+
+    kassert!(dev_tree.remove_node("/") == Err(ErrorNum::EPERM));
+    kassert!(dev_tree.apply_overlay("del /soc").is_ok());
+    kassert!(!rtc.matches(&dev_tree));
+    Ok(())
+}
+ktest!(device_tree_overlay, device_tree_overlay);
@@ -1,6 +1,11 @@
 mod device_manager;
 pub mod drivers;
 mod device_tree;
+pub mod ioctl_abi;
+pub mod vconsole;
+pub mod tty;
+pub mod registry;
+mod ktests;
 
 pub use device_manager::{
     DEVICE_MANAGER,
@@ -8,10 +13,107 @@ pub use device_manager::{
 };
 pub use device_tree::{
     DTBNode,
-    DeviceTree
+    DeviceTree,
+    scan_hart_mask,
+    scan_isa_ext_mask,
+    scan_compatible_reg,
+    scan_initrd
 };
 
-use crate::utils::RWLock;
+use alloc::vec::Vec;
+use core::{mem::size_of, sync::atomic::{AtomicUsize, Ordering}};
+use crate::{utils::{RWLock, ErrorNum, cast_bytes}, mem::PhysAddr, config::{PHYS_START_ADDR, PHYS_END_ADDR}};
+use ioctl_abi::{IOC_READ, IOC_WRITE, ioc_dir, ioc_size};
+
+/// Argument of ioctl `op`, EINVAL if op takes none or `data` is not the size op says.
+pub fn ioctl_arg<T: Copy>(op: usize, data: Vec<u8>) -> Result<T, ErrorNum> {
+    if ioc_dir(op) & IOC_WRITE == 0 || ioc_size(op) != size_of::<T>() {
+        return Err(ErrorNum::EINVAL);
+    }
+    cast_bytes(data).map_err(|_| ErrorNum::EINVAL)
+}
+
+/// For ops without argument.
+pub fn ioctl_no_arg(op: usize, data: &[u8]) -> Result<(), ErrorNum> {
+    if ioc_dir(op) & IOC_WRITE != 0 || !data.is_empty() {
+        return Err(ErrorNum::EINVAL);
+    }
+    Ok(())
+}
+
+/// Raw bytes of the result of ioctl `op`, empty if op has none.
+pub fn ioctl_res<T: Copy>(op: usize, res: &T) -> Vec<u8> {
+    if ioc_dir(op) & IOC_READ == 0 {
+        return Vec::new();
+    }
+    assert!(ioc_size(op) == size_of::<T>(), "ioctl result size mismatch");
+    unsafe{core::slice::from_raw_parts(res as *const T as *const u8, size_of::<T>())}.to_vec()
+}
+
+/// initrd found in the blob firmware passed, [start, end), 0 if none.
+static BOOT_INITRD_START: AtomicUsize = AtomicUsize::new(0);
+static BOOT_INITRD_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Note the device tree blob firmware passed in a1, called at entry after bss is cleared. Its memory isn't reserved,
+/// so what's needed from it is read right away. Lock free and without allocation. Harts started without one pass 0.
+pub fn set_boot_dtb(addr: usize) {
+    if addr < PHYS_START_ADDR.0 || addr >= PHYS_END_ADDR.0 || addr % 4 != 0 {
+        return;
+    }
+    if let Some((start, end)) = scan_initrd(PhysAddr::from(addr)) {
+        BOOT_INITRD_START.store(start, Ordering::Relaxed);
+        BOOT_INITRD_END.store(end, Ordering::Release);
+    }
+}
+
+/// initrd location, for the page allocator which comes before device manager. From the blob firmware passed, where
+/// the bootloader actually put it, or the built-in one if firmware passed none.
+pub fn initrd_range() -> Option<(PhysAddr, PhysAddr)> {
+    extern "C" {
+        fn device_tree_blob();
+    }
+    let end = BOOT_INITRD_END.load(Ordering::Acquire);
+    if end != 0 {
+        return Some((PhysAddr::from(BOOT_INITRD_START.load(Ordering::Relaxed)), PhysAddr::from(end)));
+    }
+    DeviceTree::parse(PhysAddr::from(device_tree_blob as usize)).ok()?.initrd()
+}
+
+/// Crash dump region, same as initrd_range.
+pub fn crash_region() -> Option<(PhysAddr, PhysAddr)> {
+    extern "C" {
+        fn device_tree_blob();
+    }
+    DeviceTree::parse(PhysAddr::from(device_tree_blob as usize)).ok()?.crash_region()
+}
+
+/// `pmem-region` for the persistent log. Lock free and without allocation, it's looked up by the first log line.
+pub fn pstore_region() -> Option<(PhysAddr, PhysAddr)> {
+    extern "C" {
+        fn device_tree_blob();
+    }
+    scan_compatible_reg(PhysAddr::from(device_tree_blob as usize), b"pmem-region\0")
+        .map(|(start, end)| (PhysAddr::from(start), PhysAddr::from(end)))
+}
+
+/// Harts listed in the device tree, capped to MAX_CPUS for boot stack and M mode scratch are static.
+/// Hart 0 is the boot hart so it's always there.
+pub fn present_hart_mask() -> usize {
+    extern "C" {
+        fn device_tree_blob();
+    }
+    let mask = scan_hart_mask(PhysAddr::from(device_tree_blob as usize)) & ((1 << crate::config::MAX_CPUS) - 1);
+    mask | 1
+}
+
+/// Do all present harts have ISA extension `ext`? Lock free, usable in M mode.
+pub fn harts_have_ext(ext: &str) -> bool {
+    extern "C" {
+        fn device_tree_blob();
+    }
+    let with_ext = scan_isa_ext_mask(PhysAddr::from(device_tree_blob as usize), ext.as_bytes()) | !present_hart_mask();
+    with_ext == usize::MAX
+}
 
 pub fn init() {
     for (id, driver) in DEVICE_MANAGER.acquire_r().get_device_list().iter() {
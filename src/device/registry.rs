@@ -0,0 +1,47 @@
+//! Built-in driver registry.
+//!
+//! Drivers register with `register_driver!` into the `.drivers` section, along with the DTB compatible strings
+//! they handle. At boot the device manager probes every enabled driver that has a matching node. All are enabled
+//! unless turned off with `driver.<name>=off` bootarg.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use crate::utils::{ErrorNum, UUID};
+
+use super::{DeviceTree, Driver};
+
+pub struct DriverEntry {
+    pub name: &'static str,
+    pub compatible: &'static [&'static str],
+    pub probe: fn(DeviceTree) -> Result<Vec<(UUID, Arc<dyn Driver>)>, ErrorNum>,
+}
+
+impl DriverEntry {
+    /// Does any node of `dev_tree` want this driver?
+    pub fn matches(&self, dev_tree: &DeviceTree) -> bool {
+        self.compatible.iter().any(|comp| dev_tree.serach_compatible(comp).map_or(false, |nodes| !nodes.is_empty()))
+    }
+
+    pub fn enabled(&self, bootargs: &BTreeMap<String, String>) -> bool {
+        match bootargs.get(&format!("driver.{}", self.name)).map(|s| s.as_str()) {
+            Some("off") | Some("0") => false,
+            Some("on") | Some("1") | None => true,
+            Some(other) => {
+                warning!("driver.{}={} not understood, keeping it on", self.name, other);
+                true
+            }
+        }
+    }
+}
+
+/// Every built-in driver, in link order.
+pub fn driver_entries() -> &'static [DriverEntry] {
+    extern "C" {
+        fn sdrivers();
+        fn edrivers();
+    }
+    let len = (edrivers as usize - sdrivers as usize) / core::mem::size_of::<DriverEntry>();
+    unsafe {
+        core::slice::from_raw_parts(sdrivers as usize as *const DriverEntry, len)
+    }
+}
@@ -0,0 +1,143 @@
+//! Line discipline: line editing and echo between a terminal and the program reading it.
+//!
+//! Only the basics: canonical mode with erase, kill and EOF, echo, CR to NL on input and NL to CRNL on output.
+//! No job control characters yet, Ctrl-C and Ctrl-Z are plain input. Sessions and the foreground group of a pty are
+//! in the pty itself, see dev_fs/pty.rs.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use bitflags::*;
+
+use crate::device::ioctl_abi::{TTY_CANON, TTY_ECHO, TTY_ICRNL, TTY_ONLCR};
+
+const CHAR_ERASE    : u8 = 0x7f;
+const CHAR_BACKSPACE: u8 = 0x08;
+/// Ctrl-U
+const CHAR_KILL     : u8 = 0x15;
+/// Ctrl-D
+const CHAR_EOF      : u8 = 0x04;
+
+bitflags! {
+    pub struct TtyMode: u32 {
+        /// line by line input, with editing
+        const CANON = TTY_CANON as u32;
+        const ECHO  = TTY_ECHO  as u32;
+        const ICRNL = TTY_ICRNL as u32;
+        const ONLCR = TTY_ONLCR as u32;
+    }
+}
+
+impl Default for TtyMode {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+pub struct LineDiscipline {
+    pub mode: TtyMode,
+    /// line being edited, canonical mode only
+    line: Vec<u8>,
+    /// ready for the reader
+    ready: VecDeque<u8>,
+    /// offsets in ready of EOF typed on an empty line, a read there returns nothing
+    eof_marks: VecDeque<usize>,
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        Self {
+            mode: TtyMode::default(),
+            line: Vec::new(),
+            ready: VecDeque::new(),
+            eof_marks: VecDeque::new(),
+        }
+    }
+
+    /// Feed a byte typed on the terminal, returns what to echo back.
+    pub fn input(&mut self, mut b: u8) -> Vec<u8> {
+        let mut echo = Vec::new();
+        if b == b'\r' && self.mode.contains(TtyMode::ICRNL) {
+            b = b'\n';
+        }
+        if !self.mode.contains(TtyMode::CANON) {
+            self.ready.push_back(b);
+            echo.push(b);
+        } else {
+            match b {
+                CHAR_ERASE | CHAR_BACKSPACE => {
+                    if self.line.pop().is_some() {
+                        echo.extend_from_slice(b"\x08 \x08");
+                    }
+                },
+                CHAR_KILL => {
+                    for _ in 0..self.line.len() {
+                        echo.extend_from_slice(b"\x08 \x08");
+                    }
+                    self.line.clear();
+                },
+                CHAR_EOF => {
+                    // ends the line without newline, or is an EOF on its own
+                    if self.line.is_empty() {
+                        self.eof_marks.push_back(self.ready.len());
+                    } else {
+                        self.ready.extend(self.line.drain(..));
+                    }
+                },
+                b'\n' => {
+                    self.line.push(b);
+                    self.ready.extend(self.line.drain(..));
+                    echo.push(b);
+                },
+                _ => {
+                    self.line.push(b);
+                    echo.push(b);
+                },
+            }
+        }
+        if self.mode.contains(TtyMode::ECHO) {
+            self.output(&echo)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Anything for the reader? In canonical mode that means a whole line or an EOF.
+    pub fn readable(&self) -> bool {
+        !self.ready.is_empty() || !self.eof_marks.is_empty()
+    }
+
+    /// Up to `length` bytes, canonical mode stops at line end. Empty at EOF.
+    pub fn read(&mut self, length: usize) -> Vec<u8> {
+        let mut limit = length.min(self.ready.len());
+        if let Some(mark) = self.eof_marks.front().copied() {
+            if mark == 0 {
+                self.eof_marks.pop_front();
+                return Vec::new();
+            }
+            limit = limit.min(mark);
+        }
+        if self.mode.contains(TtyMode::CANON) {
+            if let Some(nl) = self.ready.iter().position(|b| *b == b'\n') {
+                limit = limit.min(nl + 1);
+            }
+        }
+        for mark in self.eof_marks.iter_mut() {
+            *mark -= limit;
+        }
+        self.ready.drain(..limit).collect()
+    }
+
+    /// Program output on its way to the terminal.
+    pub fn output(&self, data: &[u8]) -> Vec<u8> {
+        if !self.mode.contains(TtyMode::ONLCR) {
+            return data.to_vec();
+        }
+        let mut res = Vec::with_capacity(data.len());
+        for b in data {
+            if *b == b'\n' {
+                res.push(b'\r');
+            }
+            res.push(*b);
+        }
+        res
+    }
+}
@@ -0,0 +1,169 @@
+//! Virtual consoles on the console UART, /dev/tty1 to /dev/tty<N>, enabled by `vt=<N>` bootarg.
+//!
+//! Each console has its own input buffer and scrollback, only the active one is drawn on the port. Ctrl-A then a
+//! digit switches to that console and redraws it from its scrollback, Ctrl-A twice sends one Ctrl-A.
+//! Kernel print still goes straight to the port, whichever console is active.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+use crate::{config::{VT_MAX, VT_SCROLLBACK}, device::{DEVICE_MANAGER, Driver, drivers::uart::{TtyInput, UART, console_port, tty_ports}}, process::Waker, utils::{Condvar, ErrorNum, Mutex, RWLock, SpinMutex, SpinRWLock, bootargs}};
+
+/// Ctrl-A
+const VT_ESCAPE: u8 = 0x01;
+/// clear screen and home cursor, before redrawing
+const VT_CLEAR: &[u8] = b"\x1b[2J\x1b[H";
+
+lazy_static!{
+    static ref VCONSOLES: SpinRWLock<Option<Arc<VConsoles>>> = SpinRWLock::new(None);
+}
+
+struct VirtualConsole {
+    input: SpinMutex<VecDeque<u8>>,
+    /// notified when input get filled
+    read_cond: Condvar,
+    /// last VT_SCROLLBACK bytes written
+    scrollback: SpinMutex<VecDeque<u8>>,
+}
+
+pub struct VConsoles {
+    consoles: Vec<VirtualConsole>,
+    /// held while drawing on the port, after the scrollback lock of the console
+    active: SpinMutex<usize>,
+    /// got VT_ESCAPE, next byte is a command
+    escaped: AtomicBool,
+    port: Arc<dyn Driver>,
+}
+
+/// Attach virtual consoles to the console port if asked. After bootargs and device init.
+pub fn init() {
+    let count = match bootargs::get("vt") {
+        Some(count) => count.parse::<usize>().unwrap_or(0).min(VT_MAX),
+        None => return,
+    };
+    if count == 0 {
+        warning!("vt= wants 1 to {} consoles", VT_MAX);
+        return;
+    }
+    let device_mgr = DEVICE_MANAGER.acquire_r();
+    let node = match tty_ports(&device_mgr.get_dev_tree()).get(console_port()) {
+        Some(node) => node.clone(),
+        None => {
+            warning!("vt= but console ttyS{} is not in the device tree", console_port());
+            return;
+        }
+    };
+    let port = device_mgr.get_device(node.acquire_r().driver).unwrap();
+    let uart = port.clone().as_any().downcast::<UART>().unwrap();
+    let vcs = Arc::new(VConsoles {
+        consoles: (0..count).map(|_| VirtualConsole {
+            input: SpinMutex::new("vt input", VecDeque::new()),
+            read_cond: Condvar::new("vt read"),
+            scrollback: SpinMutex::new("vt scrollback", VecDeque::new()),
+        }).collect(),
+        active: SpinMutex::new("vt active", 0),
+        escaped: AtomicBool::new(false),
+        port,
+    });
+    uart.attach(vcs.clone());
+    *VCONSOLES.acquire_w() = Some(vcs);
+    milestone!("{} virtual consoles on ttyS{}, Ctrl-A <n> to switch.", count, console_port());
+}
+
+/// None if not enabled.
+pub fn vconsoles() -> Option<Arc<VConsoles>> {
+    VCONSOLES.acquire_r().clone()
+}
+
+impl VConsoles {
+    pub fn count(&self) -> usize {
+        self.consoles.len()
+    }
+
+    pub fn active(&self) -> usize {
+        *self.active.acquire()
+    }
+
+    pub fn write(&self, idx: usize, data: &[u8]) -> Result<usize, ErrorNum> {
+        let console = self.consoles.get(idx).ok_or(ErrorNum::ENODEV)?;
+        let mut scrollback = console.scrollback.acquire();
+        scrollback.extend(data);
+        let excess = scrollback.len().saturating_sub(VT_SCROLLBACK);
+        scrollback.drain(..excess);
+        if *self.active.acquire() == idx {
+            self.port.write(data.to_vec())?;
+        }
+        Ok(data.len())
+    }
+
+    /// Wait for input, then return up to `length` bytes of it.
+    pub fn read(&self, idx: usize, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        let console = self.consoles.get(idx).ok_or(ErrorNum::ENODEV)?;
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let mut input = console.input.acquire();
+        while input.is_empty() {
+            input = console.read_cond.wait(input);
+        }
+        let len = length.min(input.len());
+        Ok(input.drain(..len).collect())
+    }
+
+    /// A read of console `idx` wouldn't block.
+    pub fn read_ready(&self, idx: usize) -> bool {
+        self.consoles.get(idx).map_or(true, |console| !console.input.acquire().is_empty())
+    }
+
+    /// `waker` is woken next time console `idx` gets input.
+    pub fn register_waker(&self, idx: usize, waker: &Arc<Waker>) {
+        if let Some(console) = self.consoles.get(idx) {
+            console.read_cond.register_waker(waker);
+        }
+    }
+
+    /// Show console `idx`, redrawn from its scrollback.
+    pub fn switch(&self, idx: usize) -> Result<(), ErrorNum> {
+        let console = self.consoles.get(idx).ok_or(ErrorNum::ENODEV)?;
+        let scrollback = console.scrollback.acquire();
+        let mut active = self.active.acquire();
+        if *active == idx {
+            return Ok(());
+        }
+        *active = idx;
+        let mut redraw = VT_CLEAR.to_vec();
+        redraw.extend(scrollback.iter());
+        self.port.write(redraw)?;
+        Ok(())
+    }
+
+    fn push_input(&self, b: u8) {
+        if let Some(console) = self.consoles.get(self.active()) {
+            console.input.acquire().push_back(b);
+            console.read_cond.notify_all();
+        }
+    }
+}
+
+impl TtyInput for VConsoles {
+    fn receive(&self, b: u8) {
+        if !self.escaped.swap(false, Ordering::AcqRel) {
+            if b == VT_ESCAPE {
+                self.escaped.store(true, Ordering::Release);
+            } else {
+                self.push_input(b);
+            }
+            return;
+        }
+        match b {
+            VT_ESCAPE => self.push_input(b),
+            b'1'..=b'9' => {
+                if let Err(e) = self.switch((b - b'1') as usize) {
+                    debug!("vt switch to {} failed: {:?}", b - b'0', e);
+                }
+            },
+            _ => debug!("Unknown vt command {:#x}", b),
+        }
+    }
+}
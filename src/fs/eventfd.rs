@@ -0,0 +1,159 @@
+//! eventfd: a 64-bit counter as a file, for wakeups without a pipe. Write adds, read takes the count and blocks
+//! while it's zero. In semaphore mode read takes one at a time.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt::Debug;
+
+use crate::{fs::{File, types::FileStat, OpenMode, Path}, process::Waker, utils::{SleepMutex, Condvar, Mutex, ErrorNum}};
+
+use super::open;
+
+/// highest the counter goes, writes that would pass it block
+const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+pub struct EventFd {
+    count: SleepMutex<u64>,
+    /// notified when the count goes up
+    read_cond: Condvar,
+    /// notified when the count goes down
+    write_cond: Condvar,
+    semaphore: bool,
+    nonblock: bool,
+}
+
+impl EventFd {
+    pub fn new(initval: u64, semaphore: bool, nonblock: bool) -> Arc<Self> {
+        Arc::new(Self {
+            count: SleepMutex::new("eventfd", initval),
+            read_cond: Condvar::new("eventfd read"),
+            write_cond: Condvar::new("eventfd write"),
+            semaphore,
+            nonblock,
+        })
+    }
+
+    pub fn count(&self) -> u64 {
+        *self.count.acquire()
+    }
+
+    /// Add `value`, block while that would pass EVENTFD_MAX, EAGAIN then if nonblocking.
+    pub fn signal(&self, value: u64) -> Result<(), ErrorNum> {
+        if value > EVENTFD_MAX {
+            return Err(ErrorNum::EINVAL);
+        }
+        let mut count = self.count.acquire();
+        while *count > EVENTFD_MAX - value {
+            if self.nonblock {
+                return Err(ErrorNum::EAGAIN);
+            }
+            count = self.write_cond.wait(count);
+        }
+        *count += value;
+        if value != 0 {
+            self.read_cond.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Take the count, or 1 in semaphore mode. Block while it's zero, EAGAIN then if nonblocking.
+    pub fn wait(&self) -> Result<u64, ErrorNum> {
+        let mut count = self.count.acquire();
+        while *count == 0 {
+            if self.nonblock {
+                return Err(ErrorNum::EAGAIN);
+            }
+            count = self.read_cond.wait(count);
+        }
+        let res = if self.semaphore {1} else {*count};
+        *count -= res;
+        self.write_cond.notify_all();
+        Ok(res)
+    }
+}
+
+impl Debug for EventFd {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "eventfd, count {}", self.count())
+    }
+}
+
+impl File for EventFd {
+    /// Exactly one native endian u64.
+    fn write (&self, data: Vec<u8>) -> Result<usize, ErrorNum> {
+        let value: [u8; 8] = data.as_slice().try_into().map_err(|_| ErrorNum::EINVAL)?;
+        self.signal(u64::from_ne_bytes(value))?;
+        Ok(8)
+    }
+
+    /// At least 8 bytes asked for, gets one native endian u64.
+    fn read (&self, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        if length < 8 {
+            return Err(ErrorNum::EINVAL);
+        }
+        Ok(self.wait()?.to_ne_bytes().to_vec())
+    }
+
+    fn as_socket <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file <'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any <'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs (&self) -> Arc<dyn crate::fs::VirtualFileSystem> {
+        open(&"proc".into(), OpenMode::SYS).unwrap().vfs()
+    }
+
+    fn stat (&self) -> Result<FileStat, ErrorNum> {
+        Ok(FileStat {
+            open_mode: OpenMode::READ | OpenMode::WRITE,
+            file_size: 0,
+            path: Path::new("[eventfd]").unwrap(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    fn poll_read_ready (&self) -> bool {
+        *self.count.acquire() != 0
+    }
+
+    /// room to add 1 at least
+    fn poll_write_ready (&self) -> bool {
+        *self.count.acquire() < EVENTFD_MAX
+    }
+
+    fn register_waker (&self, waker: &Arc<Waker>) {
+        self.read_cond.register_waker(waker);
+        self.write_cond.register_waker(waker);
+    }
+}
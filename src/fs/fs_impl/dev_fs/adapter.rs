@@ -1,11 +1,14 @@
-use alloc::{sync::{Arc, Weak}, vec::Vec};
+use alloc::{string::{String, ToString}, sync::{Arc, Weak}, vec::Vec};
 use core::fmt::Debug;
 use crate::{device::{DTBNode, Driver}, fs::{CharFile, File, VirtualFileSystem, types::FileStat}, utils::{RWLock, SpinRWLock}};
 use crate::utils::ErrorNum;
 use crate::fs::OpenMode;
-use crate::device::DEVICE_MANAGER;
+use crate::device::{DEVICE_MANAGER, drivers::uart::{UART, tty_port}};
+use crate::process::Waker;
 
 pub struct Adapter {
+    /// under /dev
+    name: String,
     driver: Arc<dyn Driver>,
     dev_node: Arc<SpinRWLock<DTBNode>>,
     fs: Weak<dyn VirtualFileSystem>,
@@ -26,12 +29,31 @@ impl Adapter {
         let dev_node = dev_tree.search_name(unit_name).unwrap();
         let driver = device_mgr.get_device(dev_node.acquire_r().driver).unwrap();
         Self {
+            name: unit_name.to_string(),
             driver,
             dev_node,
             fs,
             open_mode,
         }
     }
+
+    /// Adapter for /dev/ttyS<N>, ENOENT if there's no such port.
+    pub fn tty(name: &str, fs: Weak<dyn VirtualFileSystem>, open_mode: OpenMode) -> Result<Self, ErrorNum> {
+        let device_mgr = DEVICE_MANAGER.acquire_r();
+        let dev_node = tty_port(&device_mgr.get_dev_tree(), name).ok_or(ErrorNum::ENOENT)?;
+        let driver = device_mgr.get_device(dev_node.acquire_r().driver)?;
+        Ok(Self {
+            name: name.to_string(),
+            driver,
+            dev_node,
+            fs,
+            open_mode,
+        })
+    }
+
+    fn uart(&self) -> Option<Arc<UART>> {
+        self.driver.clone().as_any().downcast::<UART>().ok()
+    }
 }
 
 impl File for Adapter {
@@ -88,15 +110,26 @@ impl File for Adapter {
         Ok(FileStat{
             open_mode: self.open_mode,
             file_size: 0,
-            path: format!("/dev/{}", dev_node.unit_name).into(),
+            path: format!("/dev/{}", self.name).into(),
             inode: dev_node.driver.0 as u32,   // use driver lower 32-bit
             fs: self.fs.clone(),
         })
     }
-}
 
-impl CharFile for Adapter {
     fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
         self.driver.ioctl(op, data)
     }
-}
\ No newline at end of file
+
+    /// Only a UART port can block on read, other devices keep the default.
+    fn poll_read_ready(&self) -> bool {
+        self.uart().map_or(true, |uart| uart.read_ready())
+    }
+
+    fn register_waker(&self, waker: &Arc<Waker>) {
+        if let Some(uart) = self.uart() {
+            uart.register_waker(waker);
+        }
+    }
+}
+
+impl CharFile for Adapter {}
\ No newline at end of file
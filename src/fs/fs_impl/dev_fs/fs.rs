@@ -1,11 +1,11 @@
-use crate::{fs::{VirtualFileSystem, Path, File, DirFile, types::{FileStat, Permission}, OpenMode, Dirent, DummyLink}, utils::{ErrorNum, RWLock, UUID}};
-use core::fmt::Debug;
+use crate::{config::LOOP_COUNT, fs::{VirtualFileSystem, Path, File, DirFile, types::{FileStat, Permission}, OpenMode, Dirent, DummyLink}, process::{get_ctty, get_processor}, utils::{ErrorNum, RWLock, UUID}};
+use core::{fmt::Debug, sync::atomic::{AtomicUsize, Ordering}};
 
 use alloc::{borrow::ToOwned, collections::BTreeMap, string::{ToString, String}, sync::Arc, vec::Vec};
 use lazy_static::*;
-use crate::device::{DEVICE_MANAGER};
+use crate::device::{DEVICE_MANAGER, drivers::uart::{console_port, tty_ports}, vconsole::vconsoles};
 
-use super::Adapter;
+use super::{Adapter, VTFile, PtsFolder, PtyMaster, LoopFile, ShmFolder};
 
 lazy_static!{
     pub static ref DEV_FS: Arc<DevFS> = {
@@ -15,18 +15,13 @@ lazy_static!{
     };
 }
 
-lazy_static!{
-    pub static ref DEV_FOLDER: Arc<DevFolder> = {
-        let res = Arc::new(DevFolder());
-        debug!("DevFolder initialized.");
-        res
-    };
-}
-
 pub struct DevFS(pub UUID);
 
-#[derive(Debug)]
-pub struct DevFolder();
+/// a new one per open, each with its own getdents cursor
+#[derive(Debug, Default)]
+pub struct DevFolder {
+    cursor: AtomicUsize,
+}
 
 impl Debug for DevFS {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -47,12 +42,16 @@ impl VirtualFileSystem for DevFS {
         self
     }
 
+    fn fs_type(&self) -> &'static str {
+        "devfs"
+    }
+
     fn get_uuid(&self) -> crate::utils::UUID {
         self.0.clone()
     }
 
     fn root_dir(&self, _mode: OpenMode) -> Result<Arc<dyn DirFile>, ErrorNum> {
-        Ok(DEV_FOLDER.clone())
+        Ok(Arc::new(DevFolder::default()))
     }
 
     fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
@@ -118,53 +117,81 @@ impl File for DevFolder {
             fs: Arc::downgrade(&DEV_FS.clone().as_vfs()),
         })
     }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
 }
 
 impl DevFolder {
     fn compatible_devices() -> Vec<(String, UUID)> {
         let mut res = Vec::new();
-        let dev_tree = DEVICE_MANAGER.acquire_r().get_dev_tree();
-        let name_list = [
-            "google,goldfish-rtc",
-            "ns16550a",
-            "syscon-poweroff",
-            "syscon-reboot",
-            "riscv,plic0",
-            "virtio,mmio",
-        ];
-        for comp in name_list {
-            let driver_list: Vec<(String, UUID)> = dev_tree.serach_compatible(comp).unwrap().iter().map(
-                |node| -> (String, UUID) {
-                    let node_r = node.acquire_r();
-                    (node_r.unit_name.clone(), node_r.driver)
-                }
-            ).collect();
-            res.extend(driver_list);
+        let device_mgr = DEVICE_MANAGER.acquire_r();
+        let dev_tree = device_mgr.get_dev_tree();
+        for entry in device_mgr.enabled_drivers() {
+            for comp in entry.compatible {
+                let driver_list: Vec<(String, UUID)> = dev_tree.serach_compatible(comp).unwrap_or_default().iter().map(
+                    |node| -> (String, UUID) {
+                        let node_r = node.acquire_r();
+                        (node_r.unit_name.clone(), node_r.driver)
+                    }
+                ).filter(|(_, uuid)| device_mgr.get_device(*uuid).is_ok()).collect();
+                res.extend(driver_list);
+            }
         }
         res
     }
+
+    /// ttyS<N> and the driver behind it.
+    fn tty_devices() -> Vec<(String, UUID)> {
+        let dev_tree = DEVICE_MANAGER.acquire_r().get_dev_tree();
+        tty_ports(&dev_tree).iter().enumerate().map(|(idx, node)| (format!("ttyS{}", idx), node.acquire_r().driver)).collect()
+    }
 }
 
 impl DirFile for DevFolder {
-    fn open_entry(&self, entry_name: &String, mode: crate::fs::OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
-        // TODO: no more hard-coding
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, mode: crate::fs::OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
         let device_list = Self::compatible_devices();
         let device_map: BTreeMap<String, UUID> = device_list.into_iter().collect();
 
         if device_map.contains_key(entry_name) {
             Ok(Arc::new(Adapter::new(entry_name, Arc::downgrade(&DEV_FS.clone().as_vfs()), mode)))
+        } else if entry_name == "tty" {
+            // controlling terminal of the caller's session
+            let sid = get_processor().current().ok_or(ErrorNum::ENXIO)?.get_inner().sid;
+            get_ctty(sid)?.open_tty(mode)
+        } else if entry_name.starts_with("ttyS") {
+            Ok(Arc::new(Adapter::tty(entry_name, Arc::downgrade(&DEV_FS.clone().as_vfs()), mode)?))
+        } else if entry_name.starts_with("tty") {
+            Ok(Arc::new(VTFile::new(entry_name, Arc::downgrade(&DEV_FS.clone().as_vfs()), mode)?))
+        } else if entry_name == "console" {
+            Ok(Arc::new(DummyLink{
+                vfs: DEV_FS.clone(),
+                link_dest: format!("/dev/ttyS{}", console_port()).into(),
+                self_path: "/dev/console".into(),
+            }))
         } else if entry_name == "." {
             Ok(Arc::new(DummyLink{
                 vfs: DEV_FS.clone(),
                 link_dest: "/dev".into(),
                 self_path: "/dev/.".into(),
             }))
+        } else if let Some(index) = entry_name.strip_prefix("loop").and_then(|idx| idx.parse::<usize>().ok()) {
+            Ok(Arc::new(LoopFile::open(index, mode)?))
         } else if entry_name == "pts" {
-            Ok(Arc::new(DummyLink{
-                vfs: DEV_FS.clone(),
-                link_dest: "/dev/uart@10000000".into(),
-                self_path: "/dev/pts".into(),
-            }))
+            Ok(Arc::new(PtsFolder::default()))
+        } else if entry_name == "shm" {
+            Ok(Arc::new(ShmFolder::default()))
+        } else if entry_name == "ptmx" {
+            Ok(Arc::new(PtyMaster::new(mode)))
         } else if entry_name == ".." {
             Ok(Arc::new(DummyLink{
                 vfs: DEV_FS.clone(),
@@ -185,7 +212,8 @@ impl DirFile for DevFolder {
     }
 
     fn read_dirent(&self) -> Result<alloc::vec::Vec<crate::fs::Dirent>, ErrorNum> {
-        let device_list = Self::compatible_devices();
+        let mut device_list = Self::compatible_devices();
+        device_list.extend(Self::tty_devices());
         let mut result: Vec<Dirent> = Vec::new();
         for (name, uuid) in device_list.iter() {
             result.push(Dirent {
@@ -195,6 +223,14 @@ impl DirFile for DevFolder {
                 f_name: name.to_owned(),
             });
         }
+        for idx in 0..vconsoles().map_or(0, |vcs| vcs.count()) {
+            result.push(Dirent {
+                inode: (idx + 1) as u32,
+                permission: Permission::default(),
+                f_type: crate::fs::types::FileType::CHAR,
+                f_name: format!("tty{}", idx + 1),
+            });
+        }
         result.push(
             Dirent{ 
                 inode: Path::new("/dev/.").unwrap().hash(), 
@@ -209,13 +245,50 @@ impl DirFile for DevFolder {
                 f_type: crate::fs::types::FileType::LINK, 
                 f_name: "..".to_string() }
         );
+        for idx in 0..LOOP_COUNT {
+            let path: Path = format!("/dev/loop{}", idx).into();
+            result.push(Dirent {
+                inode: path.hash(),
+                permission: Permission::default(),
+                f_type: crate::fs::types::FileType::BLOCK,
+                f_name: format!("loop{}", idx),
+            });
+        }
         result.push(
             Dirent{ 
                 inode: Path::new("/dev/pts").unwrap().hash(), 
                 permission: Permission::default(), 
-                f_type: crate::fs::types::FileType::LINK, 
+                f_type: crate::fs::types::FileType::DIR, 
                 f_name: "pts".to_string() }
         );
+        result.push(
+            Dirent{ 
+                inode: Path::new("/dev/shm").unwrap().hash(), 
+                permission: Permission::default(), 
+                f_type: crate::fs::types::FileType::DIR, 
+                f_name: "shm".to_string() }
+        );
+        result.push(
+            Dirent{ 
+                inode: Path::new("/dev/tty").unwrap().hash(), 
+                permission: Permission::default(), 
+                f_type: crate::fs::types::FileType::CHAR, 
+                f_name: "tty".to_string() }
+        );
+        result.push(
+            Dirent{ 
+                inode: Path::new("/dev/ptmx").unwrap().hash(), 
+                permission: Permission::default(), 
+                f_type: crate::fs::types::FileType::CHAR, 
+                f_name: "ptmx".to_string() }
+        );
+        result.push(
+            Dirent{ 
+                inode: Path::new("/dev/console").unwrap().hash(), 
+                permission: Permission::default(), 
+                f_type: crate::fs::types::FileType::LINK, 
+                f_name: "console".to_string() }
+        );
 
         Ok(result)
     }
@@ -0,0 +1,216 @@
+//! Loop devices. /dev/loop<N> is a block device whose blocks are those of a regular file, bound with LOOP_SET_FD
+//! and released with LOOP_CLR_FD, both root only. The device holds the file taken from the fd, so the fd can be
+//! closed and the file unlinked afterwards. It reads and writes at its own cursor, leaving the fd's alone.
+//! ParchFS is still tied to its own memory region, so an image on a loop device can't be mounted as ParchFS yet,
+//! only read and written block by block.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt::Debug;
+use lazy_static::*;
+
+use crate::{config::{LOOP_COUNT, PAGE_SIZE}, device::{ioctl_abi::{LOOP_CLR_FD, LOOP_GET_STATUS, LOOP_SET_FD, LoopFd, LoopStatus}, ioctl_arg, ioctl_no_arg, ioctl_res}, fs::{BlockFile, File, OpenMode, Path, RegularFile, SeekWhence, VirtualFileSystem, types::FileStat}, mem::PageGuard, process::{FileDescriptor, get_processor}, utils::{ErrorNum, Mutex, SleepMutex, SpinMutex}};
+
+use super::fs::DEV_FS;
+
+lazy_static!{
+    static ref LOOPS: Vec<SleepMutex<Option<Arc<dyn RegularFile>>>> = (0..LOOP_COUNT).map(|_| SleepMutex::new("loop device", None)).collect();
+}
+
+pub struct LoopFile {
+    index: usize,
+    open_mode: OpenMode,
+    cursor: SpinMutex<usize>,
+}
+
+impl LoopFile {
+    /// ENOENT if there's no such device.
+    pub fn open(index: usize, open_mode: OpenMode) -> Result<Self, ErrorNum> {
+        if index >= LOOP_COUNT {
+            return Err(ErrorNum::ENOENT);
+        }
+        Ok(Self{index, open_mode, cursor: SpinMutex::new("loop cursor", 0)})
+    }
+
+    fn path(&self) -> Path {
+        format!("/dev/loop{}", self.index).into()
+    }
+
+    /// ENXIO if nothing is bound.
+    fn backing(&self) -> Result<Arc<dyn RegularFile>, ErrorNum> {
+        LOOPS[self.index].acquire().clone().ok_or(ErrorNum::ENXIO)
+    }
+
+    /// EPERM unless root.
+    fn check_privileged() -> Result<(), ErrorNum> {
+        if get_processor().current().map_or(true, |proc| proc.get_inner().cred.privileged()) {
+            Ok(())
+        } else {
+            Err(ErrorNum::EPERM)
+        }
+    }
+
+    fn set_fd(&self, fd: u32) -> Result<(), ErrorNum> {
+        let file = get_processor().current().unwrap().get_inner().get_file(FileDescriptor::from(fd as usize))?;
+        // a loop device on top of another would hold two device locks at once
+        if file.clone().as_any().downcast::<LoopFile>().is_ok() {
+            return Err(ErrorNum::EINVAL);
+        }
+        let backing = file.as_regular()?;
+        let mut slot = LOOPS[self.index].acquire();
+        if slot.is_some() {
+            return Err(ErrorNum::EBUSY);
+        }
+        *slot = Some(backing);
+        Ok(())
+    }
+}
+
+impl Debug for LoopFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "loop device /dev/loop{}", self.index)
+    }
+}
+
+impl File for LoopFile {
+    fn write(&self, data: Vec<u8>) -> Result<usize, ErrorNum> {
+        let slot = LOOPS[self.index].acquire();
+        let backing = slot.as_ref().ok_or(ErrorNum::ENXIO)?;
+        if !backing.stat()?.open_mode.contains(OpenMode::WRITE) {
+            return Err(ErrorNum::EROFS);
+        }
+        let pos = *self.cursor.acquire();
+        let len = backing.write_at(pos, data)?;
+        *self.cursor.acquire() = pos + len;
+        Ok(len)
+    }
+
+    fn read(&self, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        let slot = LOOPS[self.index].acquire();
+        let backing = slot.as_ref().ok_or(ErrorNum::ENXIO)?;
+        let pos = *self.cursor.acquire();
+        let data = backing.read_at(pos, length)?;
+        *self.cursor.acquire() = pos + data.len();
+        Ok(data)
+    }
+
+    fn as_socket<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: Arc<Self>) -> Result<Arc<dyn RegularFile + 'a>, ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_block<'a>(self: Arc<Self>) -> Result<Arc<dyn BlockFile + 'a>, ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_dir<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> Arc<dyn VirtualFileSystem> {
+        DEV_FS.clone()
+    }
+
+    /// Size is that of the backing file, 0 if unbound.
+    fn stat(&self) -> Result<FileStat, ErrorNum> {
+        let file_size = match self.backing() {
+            Ok(backing) => backing.stat()?.file_size,
+            Err(_) => 0,
+        };
+        let path = self.path();
+        Ok(FileStat{
+            open_mode: self.open_mode,
+            file_size,
+            inode: path.hash(),
+            path,
+            fs: Arc::downgrade(&DEV_FS.clone().as_vfs()),
+        })
+    }
+
+    fn sync(&self) -> Result<(), ErrorNum> {
+        self.backing()?.sync()
+    }
+
+    fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
+        match op {
+            LOOP_SET_FD => {
+                Self::check_privileged()?;
+                let arg: LoopFd = ioctl_arg(op, data)?;
+                self.set_fd(arg.fd)?;
+                Ok(Vec::new())
+            },
+            LOOP_CLR_FD => {
+                ioctl_no_arg(op, &data)?;
+                Self::check_privileged()?;
+                LOOPS[self.index].acquire().take().ok_or(ErrorNum::ENXIO)?;
+                Ok(Vec::new())
+            },
+            LOOP_GET_STATUS => {
+                ioctl_no_arg(op, &data)?;
+                let status = match self.backing() {
+                    Ok(backing) => LoopStatus{bound: 1, block_size: PAGE_SIZE as u32, size: backing.stat()?.file_size as u64},
+                    Err(_) => LoopStatus{bound: 0, block_size: PAGE_SIZE as u32, size: 0},
+                };
+                Ok(ioctl_res(op, &status))
+            },
+            _ => Err(ErrorNum::ENOTTY),
+        }
+    }
+}
+
+impl RegularFile for LoopFile {
+    fn read_at(&self, offset: usize, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        self.backing()?.read_at(offset, length)
+    }
+
+    fn write_at(&self, offset: usize, data: Vec<u8>) -> Result<usize, ErrorNum> {
+        let backing = self.backing()?;
+        if !backing.stat()?.open_mode.contains(OpenMode::WRITE) {
+            return Err(ErrorNum::EROFS);
+        }
+        backing.write_at(offset, data)
+    }
+
+    fn copy_page(&self, offset: usize) -> Result<PageGuard, ErrorNum> {
+        self.backing()?.copy_page(offset)
+    }
+
+    fn get_page(&self, offset: usize, fill: bool) -> Result<Option<PageGuard>, ErrorNum> {
+        self.backing()?.get_page(offset, fill)
+    }
+
+    fn seek(&self, offset: isize, whence: SeekWhence) -> Result<usize, ErrorNum> {
+        let size = self.stat()?.file_size;
+        let mut cursor = self.cursor.acquire();
+        *cursor = whence.resolve(offset, *cursor, size)?;
+        Ok(*cursor)
+    }
+
+    fn sync_page(&self, offset: usize, len: usize) -> Result<(), ErrorNum> {
+        self.backing()?.sync_page(offset, len)
+    }
+}
+
+impl BlockFile for LoopFile {}
@@ -1,5 +1,13 @@
 mod fs;
 mod adapter;
+mod vt;
+mod pty;
+mod loop_dev;
+mod shm;
 
 pub use fs::DEV_FS;
-pub use adapter::Adapter;
\ No newline at end of file
+pub use adapter::Adapter;
+pub use vt::VTFile;
+pub use pty::{PtyMaster, PtySlave, PtsFolder};
+pub use loop_dev::LoopFile;
+pub use shm::ShmFolder;
\ No newline at end of file
@@ -0,0 +1,485 @@
+//! Pseudo terminals. Opening /dev/ptmx makes a new pair and gives the master side, the slave side is
+//! /dev/pts/<N>, N from TTY_GET_PTY_INDEX on the master. Line discipline sits on the slave side: what the master
+//! writes is what's typed, what the slave writes is what's shown.
+//! The pair goes away with the master, slave reads get EOF and writes EIO after that.
+//! A slave can be made the controlling terminal of a session, the session gets SIGHUP when the master goes.
+
+use alloc::{collections::{BTreeMap, VecDeque}, string::{String, ToString}, sync::{Arc, Weak}, vec::Vec};
+use core::{fmt::Debug, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+use lazy_static::*;
+
+use crate::{device::{ioctl_abi::{TTY_GET_MODE, TTY_GET_PGRP, TTY_GET_PTY_INDEX, TTY_SET_CTTY, TTY_SET_MODE, TTY_SET_PGRP, TtyIndex, TtyModeArg, TtyPgrp}, ioctl_arg, ioctl_no_arg, ioctl_res, tty::{LineDiscipline, TtyMode}}, fs::{CharFile, DirFile, Dirent, DummyLink, File, OpenMode, Path, VirtualFileSystem, types::{FileStat, FileType, Permission}}, process::{ProcessID, Terminal, Waker, get_processor, hang_up_session, pgrp_in_session, set_ctty}, utils::{Condvar, ErrorNum, Mutex, SleepMutex, SpinMutex}};
+
+use super::fs::DEV_FS;
+
+lazy_static!{
+    static ref PTYS: SpinMutex<BTreeMap<usize, Weak<PtyPair>>> = SpinMutex::new("pty table", BTreeMap::new());
+}
+
+static PTY_NEXT: AtomicUsize = AtomicUsize::new(0);
+
+struct PtyPair {
+    index: usize,
+    /// typed on the master, read by the slave
+    ldisc: SleepMutex<LineDiscipline>,
+    slave_cond: Condvar,
+    /// written by the slave and echo, read by the master
+    to_master: SleepMutex<VecDeque<u8>>,
+    master_cond: Condvar,
+    slave_count: AtomicUsize,
+    /// a slave was open once, master reads fail with EIO after the last one closes
+    slave_seen: AtomicBool,
+    hung_up: AtomicBool,
+    /// session it's the controlling terminal of
+    controller: SpinMutex<Option<Controller>>,
+}
+
+#[derive(Clone, Copy)]
+struct Controller {
+    sid: ProcessID,
+    /// foreground process group
+    pgrp: ProcessID,
+}
+
+impl PtyPair {
+    fn push_master(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.to_master.acquire().extend(data);
+        self.master_cond.notify_all();
+    }
+
+    fn hang_up(&self) {
+        self.hung_up.store(true, Ordering::Release);
+        // take the lock so a reader can't miss it between check and sleep
+        drop(self.ldisc.acquire());
+        self.slave_cond.notify_all();
+        if let Some(controller) = self.controller.acquire().take() {
+            hang_up_session(controller.sid);
+        }
+    }
+
+    /// Controller if it's the caller's session, ENOTTY otherwise.
+    fn caller_controller(&self) -> Result<Controller, ErrorNum> {
+        // kernel callers have no session, so no controlling terminal
+        let sid = get_processor().current().ok_or(ErrorNum::ENOTTY)?.get_inner().sid;
+        self.controller.acquire().filter(|controller| controller.sid == sid).ok_or(ErrorNum::ENOTTY)
+    }
+
+    fn ctty_ioctl(self: &Arc<Self>, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
+        match op {
+            TTY_SET_CTTY => {
+                ioctl_no_arg(op, &data)?;
+                let proc = get_processor().current().ok_or(ErrorNum::EPERM)?;
+                let (sid, pgid) = {
+                    let proc_inner = proc.get_inner();
+                    (proc_inner.sid, proc_inner.pgid)
+                };
+                if sid != proc.pid || self.hung_up.load(Ordering::Acquire) {
+                    return Err(ErrorNum::EPERM);
+                }
+                let mut controller = self.controller.acquire();
+                match *controller {
+                    Some(old) if old.sid == sid => {},
+                    Some(_) => return Err(ErrorNum::EPERM),
+                    None => {
+                        let tty: Arc<dyn Terminal> = self.clone();
+                        set_ctty(sid, Arc::downgrade(&tty))?;
+                        *controller = Some(Controller{sid, pgrp: pgid});
+                    }
+                }
+                Ok(Vec::new())
+            },
+            TTY_GET_PGRP => {
+                ioctl_no_arg(op, &data)?;
+                let controller = self.caller_controller()?;
+                Ok(ioctl_res(op, &TtyPgrp{pgrp: controller.pgrp.0 as u32}))
+            },
+            TTY_SET_PGRP => {
+                let arg: TtyPgrp = ioctl_arg(op, data)?;
+                let pgrp = ProcessID(arg.pgrp as usize);
+                let controller = self.caller_controller()?;
+                if !pgrp_in_session(pgrp, controller.sid) {
+                    return Err(ErrorNum::EPERM);
+                }
+                if let Some(controller) = self.controller.acquire().as_mut().filter(|now| now.sid == controller.sid) {
+                    controller.pgrp = pgrp;
+                }
+                Ok(Vec::new())
+            },
+            _ => self.mode_ioctl(op, data)
+        }
+    }
+
+    fn mode_ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
+        match op {
+            TTY_GET_PTY_INDEX => {
+                ioctl_no_arg(op, &data)?;
+                Ok(ioctl_res(op, &TtyIndex{index: self.index as u32}))
+            },
+            TTY_GET_MODE => {
+                ioctl_no_arg(op, &data)?;
+                Ok(ioctl_res(op, &TtyModeArg{flags: self.ldisc.acquire().mode.bits()}))
+            },
+            TTY_SET_MODE => {
+                let arg: TtyModeArg = ioctl_arg(op, data)?;
+                self.ldisc.acquire().mode = TtyMode::from_bits(arg.flags).ok_or(ErrorNum::EINVAL)?;
+                Ok(Vec::new())
+            },
+            _ => Err(ErrorNum::ENOTTY)
+        }
+    }
+}
+
+pub struct PtyMaster {
+    pair: Arc<PtyPair>,
+    open_mode: OpenMode,
+}
+
+pub struct PtySlave {
+    pair: Arc<PtyPair>,
+    open_mode: OpenMode,
+}
+
+/// /dev/pts
+#[derive(Debug, Default)]
+pub struct PtsFolder {
+    cursor: AtomicUsize,
+}
+
+impl PtyMaster {
+    pub fn new(open_mode: OpenMode) -> Self {
+        let index = PTY_NEXT.fetch_add(1, Ordering::Relaxed);
+        let pair = Arc::new(PtyPair {
+            index,
+            ldisc: SleepMutex::new("pty ldisc", LineDiscipline::new()),
+            slave_cond: Condvar::new("pty slave"),
+            to_master: SleepMutex::new("pty master", VecDeque::new()),
+            master_cond: Condvar::new("pty master"),
+            slave_count: AtomicUsize::new(0),
+            slave_seen: AtomicBool::new(false),
+            hung_up: AtomicBool::new(false),
+            controller: SpinMutex::new("pty controller", None),
+        });
+        PTYS.acquire().insert(index, Arc::downgrade(&pair));
+        Self { pair, open_mode }
+    }
+}
+
+impl PtySlave {
+    /// ENOENT if the master is gone.
+    pub fn open(index: usize, open_mode: OpenMode) -> Result<Self, ErrorNum> {
+        let pair = PTYS.acquire().get(&index).and_then(|pair| pair.upgrade()).ok_or(ErrorNum::ENOENT)?;
+        if pair.hung_up.load(Ordering::Acquire) {
+            return Err(ErrorNum::ENOENT);
+        }
+        pair.slave_count.fetch_add(1, Ordering::AcqRel);
+        pair.slave_seen.store(true, Ordering::Release);
+        Ok(Self { pair, open_mode })
+    }
+}
+
+impl Terminal for PtyPair {
+    fn open_tty(&self, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        Ok(Arc::new(PtySlave::open(self.index, mode)?))
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        PTYS.acquire().remove(&self.pair.index);
+        self.pair.hang_up();
+    }
+}
+
+impl Drop for PtySlave {
+    fn drop(&mut self) {
+        if self.pair.slave_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            drop(self.pair.to_master.acquire());
+            self.pair.master_cond.notify_all();
+        }
+    }
+}
+
+impl Debug for PtyMaster {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "pty master {}", self.pair.index)
+    }
+}
+
+impl Debug for PtySlave {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "pty slave /dev/pts/{}", self.pair.index)
+    }
+}
+
+macro_rules! pty_file_casts {
+    () => {
+        fn as_socket<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile   + 'a>, ErrorNum> where Self: 'a {
+            Err(ErrorNum::EBADTYPE)
+        }
+
+        fn as_link<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile     + 'a>, ErrorNum> where Self: 'a {
+            Err(ErrorNum::EBADTYPE)
+        }
+
+        fn as_regular<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile  + 'a>, ErrorNum> where Self: 'a {
+            Err(ErrorNum::EBADTYPE)
+        }
+
+        fn as_block<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile    + 'a>, ErrorNum> where Self: 'a {
+            Err(ErrorNum::EBADTYPE)
+        }
+
+        fn as_dir<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile      + 'a>, ErrorNum> where Self: 'a {
+            Err(ErrorNum::EBADTYPE)
+        }
+
+        fn as_char<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile     + 'a>, ErrorNum> where Self: 'a {
+            Ok(self)
+        }
+
+        fn as_fifo<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile     + 'a>, ErrorNum> where Self: 'a {
+            Err(ErrorNum::EBADTYPE)
+        }
+
+        fn as_file<'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+            self
+        }
+
+        fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+            self
+        }
+
+        fn vfs(&self) -> Arc<dyn VirtualFileSystem> {
+            DEV_FS.clone()
+        }
+    };
+}
+
+impl File for PtyMaster {
+    /// typed into the slave
+    fn write(&self, data: Vec<u8>) -> Result<usize, ErrorNum> {
+        let mut echo = Vec::new();
+        {
+            let mut ldisc = self.pair.ldisc.acquire();
+            for b in data.iter() {
+                echo.extend(ldisc.input(*b));
+            }
+            if ldisc.readable() {
+                self.pair.slave_cond.notify_all();
+            }
+        }
+        self.pair.push_master(&echo);
+        Ok(data.len())
+    }
+
+    /// Wait for output of the slave, then return up to `length` bytes of it.
+    fn read(&self, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let mut to_master = self.pair.to_master.acquire();
+        while to_master.is_empty() {
+            if self.pair.slave_seen.load(Ordering::Acquire) && self.pair.slave_count.load(Ordering::Acquire) == 0 {
+                return Err(ErrorNum::EIO);
+            }
+            to_master = self.pair.master_cond.wait(to_master);
+        }
+        let len = length.min(to_master.len());
+        Ok(to_master.drain(..len).collect())
+    }
+
+    pty_file_casts!();
+
+    fn stat(&self) -> Result<FileStat, ErrorNum> {
+        Ok(FileStat{
+            open_mode: self.open_mode,
+            file_size: 0,
+            path: "/dev/ptmx".into(),
+            inode: Path::new("/dev/ptmx").unwrap().hash(),
+            fs: Arc::downgrade(&DEV_FS.clone().as_vfs()),
+        })
+    }
+
+    fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
+        self.pair.mode_ioctl(op, data)
+    }
+
+    /// output of the slave, or EIO once all slaves are closed
+    fn poll_read_ready(&self) -> bool {
+        !self.pair.to_master.acquire().is_empty()
+            || (self.pair.slave_seen.load(Ordering::Acquire) && self.pair.slave_count.load(Ordering::Acquire) == 0)
+    }
+
+    fn register_waker(&self, waker: &Arc<Waker>) {
+        self.pair.master_cond.register_waker(waker);
+    }
+}
+
+impl File for PtySlave {
+    /// shown on the master
+    fn write(&self, data: Vec<u8>) -> Result<usize, ErrorNum> {
+        if self.pair.hung_up.load(Ordering::Acquire) {
+            return Err(ErrorNum::EIO);
+        }
+        let out = self.pair.ldisc.acquire().output(&data);
+        self.pair.push_master(&out);
+        Ok(data.len())
+    }
+
+    /// Wait for input, a whole line in canonical mode. Empty on EOF or once the master is gone.
+    fn read(&self, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        let mut ldisc = self.pair.ldisc.acquire();
+        while !ldisc.readable() {
+            if self.pair.hung_up.load(Ordering::Acquire) {
+                return Ok(Vec::new());
+            }
+            ldisc = self.pair.slave_cond.wait(ldisc);
+        }
+        Ok(ldisc.read(length))
+    }
+
+    pty_file_casts!();
+
+    fn stat(&self) -> Result<FileStat, ErrorNum> {
+        let path: Path = format!("/dev/pts/{}", self.pair.index).into();
+        Ok(FileStat{
+            open_mode: self.open_mode,
+            file_size: 0,
+            inode: path.hash(),
+            path,
+            fs: Arc::downgrade(&DEV_FS.clone().as_vfs()),
+        })
+    }
+
+    fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
+        self.pair.ctty_ioctl(op, data)
+    }
+
+    /// input, or EOF once the master is gone
+    fn poll_read_ready(&self) -> bool {
+        self.pair.hung_up.load(Ordering::Acquire) || self.pair.ldisc.acquire().readable()
+    }
+
+    fn register_waker(&self, waker: &Arc<Waker>) {
+        self.pair.slave_cond.register_waker(waker);
+    }
+}
+
+impl CharFile for PtyMaster {}
+impl CharFile for PtySlave {}
+
+impl File for PtsFolder {
+    fn write(&self, _data: Vec<u8>) -> Result<usize, ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn read(&self, _length: usize) -> Result<Vec<u8>, ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn as_socket<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile + 'a>, ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_char<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> Arc<dyn VirtualFileSystem> {
+        DEV_FS.clone()
+    }
+
+    fn stat(&self) -> Result<FileStat, ErrorNum> {
+        Ok(FileStat{
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: "/dev/pts".into(),
+            inode: Path::new("/dev/pts").unwrap().hash(),
+            fs: Arc::downgrade(&DEV_FS.clone().as_vfs()),
+        })
+    }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
+}
+
+impl DirFile for PtsFolder {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        if entry_name == "." || entry_name == ".." {
+            let dest = if entry_name == "." {"/dev/pts"} else {"/dev"};
+            return Ok(Arc::new(DummyLink{
+                vfs: DEV_FS.clone(),
+                link_dest: dest.into(),
+                self_path: format!("/dev/pts/{}", entry_name).into(),
+            }));
+        }
+        let index: usize = entry_name.parse().map_err(|_| ErrorNum::ENOENT)?;
+        Ok(Arc::new(PtySlave::open(index, mode)?))
+    }
+
+    fn make_file(&self, _name: String, _perm: Permission, _f_type: FileType) -> Result<Arc<dyn File>, ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn remove_file(&self, _name: String) -> Result<(), ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read_dirent(&self) -> Result<Vec<Dirent>, ErrorNum> {
+        let mut result: Vec<Dirent> = PTYS.acquire().keys().map(|index| Dirent {
+            inode: Path::new(&format!("/dev/pts/{}", index)).unwrap().hash(),
+            permission: Permission::default(),
+            f_type: FileType::CHAR,
+            f_name: index.to_string(),
+        }).collect();
+        for name in [".", ".."] {
+            result.push(Dirent {
+                inode: Path::new(&format!("/dev/pts/{}", name)).unwrap().hash(),
+                permission: Permission::default(),
+                f_type: FileType::LINK,
+                f_name: name.to_string(),
+            });
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,119 @@
+//! /dev/shm, only there to be a mount point, fs::init puts a TmpFS over it. shm_open is then plain open of
+//! /dev/shm/<name> and shm_unlink a delete. Shared mmaps of TmpFS files map the file's own pages, so every
+//! process sees the same memory.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{string::{String, ToString}, sync::Arc, vec::Vec};
+
+use crate::{fs::{DirFile, Dirent, DummyLink, File, OpenMode, Path, VirtualFileSystem, types::{FileStat, FileType, Permission}}, utils::ErrorNum};
+
+use super::DEV_FS;
+
+#[derive(Debug, Default)]
+pub struct ShmFolder {
+    cursor: AtomicUsize,
+}
+
+impl File for ShmFolder {
+    fn write(&self, _data: Vec<u8>) -> Result<usize, ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn read(&self, _length: usize) -> Result<Vec<u8>, ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn as_socket<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile + 'a>, ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_char<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> Arc<dyn VirtualFileSystem> {
+        DEV_FS.clone()
+    }
+
+    fn stat(&self) -> Result<FileStat, ErrorNum> {
+        Ok(FileStat{
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: "/dev/shm".into(),
+            inode: Path::new("/dev/shm").unwrap().hash(),
+            fs: Arc::downgrade(&DEV_FS.clone().as_vfs()),
+        })
+    }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
+}
+
+impl DirFile for ShmFolder {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        if entry_name == "." || entry_name == ".." {
+            let dest = if entry_name == "." {"/dev/shm"} else {"/dev"};
+            return Ok(Arc::new(DummyLink{
+                vfs: DEV_FS.clone(),
+                link_dest: dest.into(),
+                self_path: format!("/dev/shm/{}", entry_name).into(),
+            }));
+        }
+        Err(ErrorNum::ENOENT)
+    }
+
+    fn make_file(&self, _name: String, _perm: Permission, _f_type: FileType) -> Result<Arc<dyn File>, ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn remove_file(&self, _name: String) -> Result<(), ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read_dirent(&self) -> Result<Vec<Dirent>, ErrorNum> {
+        Ok([".", ".."].iter().map(|name| Dirent {
+            inode: Path::new(&format!("/dev/shm/{}", name)).unwrap().hash(),
+            permission: Permission::default(),
+            f_type: FileType::LINK,
+            f_name: name.to_string(),
+        }).collect())
+    }
+}
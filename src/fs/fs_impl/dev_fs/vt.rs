@@ -0,0 +1,122 @@
+use alloc::{sync::{Arc, Weak}, vec::Vec};
+use crate::{device::{ioctl_abi::{VT_ACTIVATE, VT_GET_ACTIVE, VTIndex}, ioctl_arg, ioctl_no_arg, ioctl_res, vconsole::{VConsoles, vconsoles}}, fs::{CharFile, File, VirtualFileSystem, types::FileStat}, process::Waker};
+use crate::utils::ErrorNum;
+use crate::fs::OpenMode;
+
+/// /dev/tty<N>, virtual console N - 1.
+pub struct VTFile {
+    idx: usize,
+    vcs: Arc<VConsoles>,
+    fs: Weak<dyn VirtualFileSystem>,
+    open_mode: OpenMode,
+}
+
+impl core::fmt::Debug for VTFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Virtual console tty{}", self.idx + 1)
+    }
+}
+
+impl VTFile {
+    /// `name` is like tty1, ENOENT if virtual consoles are off or there's no such one.
+    pub fn new(name: &str, fs: Weak<dyn VirtualFileSystem>, open_mode: OpenMode) -> Result<Self, ErrorNum> {
+        let vcs = vconsoles().ok_or(ErrorNum::ENOENT)?;
+        let idx = Self::index_of(name).filter(|idx| *idx < vcs.count()).ok_or(ErrorNum::ENOENT)?;
+        Ok(Self {
+            idx,
+            vcs,
+            fs,
+            open_mode,
+        })
+    }
+
+    fn index_of(name: &str) -> Option<usize> {
+        let n: usize = name.strip_prefix("tty")?.parse().ok()?;
+        n.checked_sub(1)
+    }
+}
+
+impl File for VTFile {
+    fn write(&self, data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        self.vcs.write(self.idx, &data)
+    }
+
+    fn read(&self, length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        self.vcs.read(self.idx, length)
+    }
+
+    fn as_socket<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile   + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile     + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile  + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile    + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile      + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile     + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_fifo<'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile     + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> Arc<dyn crate::fs::VirtualFileSystem> {
+        self.fs.upgrade().unwrap()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat{
+            open_mode: self.open_mode,
+            file_size: 0,
+            path: format!("/dev/tty{}", self.idx + 1).into(),
+            inode: (self.idx + 1) as u32,
+            fs: self.fs.clone(),
+        })
+    }
+
+    fn poll_read_ready(&self) -> bool {
+        self.vcs.read_ready(self.idx)
+    }
+
+    fn register_waker(&self, waker: &Arc<Waker>) {
+        self.vcs.register_waker(self.idx, waker);
+    }
+
+    fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
+        match op {
+            VT_ACTIVATE => {
+                let arg: VTIndex = ioctl_arg(op, data)?;
+                self.vcs.switch(arg.index as usize)?;
+                Ok(Vec::new())
+            },
+            VT_GET_ACTIVE => {
+                ioctl_no_arg(op, &data)?;
+                Ok(ioctl_res(op, &VTIndex{index: self.vcs.active() as u32}))
+            },
+            _ => Err(ErrorNum::ENOTTY)
+        }
+    }
+}
+
+impl CharFile for VTFile {}
@@ -1,7 +1,24 @@
 mod dev_fs;
 mod parch_fs;
 mod proc_fs;
+mod tmp_fs;
 
-pub use parch_fs::PARCH_FS;
+pub use parch_fs::{PARCH_FS, writeback_daemon, parch_fs_present, parch_fs_refused, parch_fs_check, parch_fs_get_quota, parch_fs_set_quota, QuotaEntry, QuotaLimits};
+pub use tmp_fs::{TmpFS, anon_file};
 pub use dev_fs::DEV_FS;
-pub use proc_fs::PROC_FS;
\ No newline at end of file
+pub use proc_fs::PROC_FS;
+
+use alloc::sync::Arc;
+
+use super::VirtualFileSystem;
+
+/// Filesystem to mount on / for `root=` bootarg. initramfs is an empty TmpFS, filled by fs::init.
+/// None for parchfs when the image is one this kernel refuses.
+pub fn root_fs_by_name(name: &str) -> Option<Arc<dyn VirtualFileSystem>> {
+    match name {
+        "parchfs" if parch_fs_refused().is_some() => None,
+        "parchfs" => Some(PARCH_FS.clone()),
+        "initramfs" => Some(TmpFS::new("/".into())),
+        _ => None
+    }
+}
\ No newline at end of file
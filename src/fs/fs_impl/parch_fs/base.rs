@@ -1,14 +1,24 @@
-use crate::{fs::{vfs::OpenMode, fs_impl::parch_fs::{BAD_BLOCK, BLOCKNO_PER_BLK, PFS_MAXCAP, PFSType}, Path, types::FileType, Cursor}, mem::{PageGuard, claim_fs_page, alloc_vm_page, PhysPageNum, PhysAddr}, utils::{ErrorNum, Mutex, MutexGuard, time::get_real_time_epoch, UUID}};
-use super::{DIRECT_BLK_COUNT, BLK_SIZE, fs::{ParchFS, ParchFSInner}, BlockNo, INodeNo, PFSINode};
+use crate::{fs::{vfs::OpenMode, fs_impl::parch_fs::{BAD_BLOCK, BLOCKNO_PER_BLK, PFS_MAXCAP, PFSFeatures, PFSType}, Path, types::FileType, Cursor}, mem::{PageGuard, PageOwner, claim_fs_page, try_alloc_vm_page, ksm_invalidate, PhysAddr, UserBuffer}, utils::{ErrorNum, Mutex, MutexGuard, time::get_real_time_epoch, UUID}};
+use super::{DIRECT_BLK_COUNT, BLK_SIZE, fs::{ParchFS, ParchFSInner, PFSINodeHandle}, BlockNo, INodeNo, PFSINode, ReadAhead, WRITE_BEHIND_MAX, write_behind::{PendingWrite, mark_dirty}};
 
 
-use core::cmp::min;
+use core::cmp::{max, min};
+use core::ptr::copy_nonoverlapping;
 use alloc::{sync::{Weak, Arc}};
 use alloc::vec::Vec;
 
 
+/// Source for reading holes.
+static ZERO_BLOCK: [u8; BLK_SIZE] = [0; BLK_SIZE];
+
+fn zero_block() -> PhysAddr {
+    PhysAddr::from(ZERO_BLOCK.as_ptr() as usize)
+}
+
 pub struct PFSBase {
     pub inode_no: INodeNo,
+    /// shared inode object, keeps the inode alive while file is open
+    pub inode: Arc<PFSINodeHandle>,
     pub open_mode: OpenMode,
     pub fs: Weak<ParchFS>,
     pub path: Path
@@ -16,235 +26,381 @@ pub struct PFSBase {
 
 impl PFSBase {
     pub fn new(inode_no: INodeNo, path: Path, open_mode: OpenMode, fs: Weak<ParchFS>) -> Result<Self, ErrorNum> {
+        let inode = fs.upgrade().ok_or(ErrorNum::ENOENT)?.get_inode(inode_no)?;
         Ok(Self {
             inode_no,
+            inode,
             open_mode,
             fs,
             path
         })
     }
 
-    pub fn get_blockno_locked(&self, offset: usize, create: bool, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut MutexGuard<&mut PFSINode>) -> Result<BlockNo, ErrorNum> {
-        let mut offset: usize = offset as usize;
-        if offset >= PFS_MAXCAP {
-            return Err(ErrorNum::EOOR);
+    /// Which tree holds block `blk_idx` of a file: 0 for direct, 1..=3 for the single/double/triple indirect
+    /// tree, and the index inside it. None past PFS_MAXCAP.
+    pub fn locate(blk_idx: usize) -> Option<(u32, usize)> {
+        if blk_idx < DIRECT_BLK_COUNT {
+            return Some((0, blk_idx));
         }
-        if create && offset > inode.f_size {
-            inode.f_size = offset;
-        } else if offset >= inode.f_size {
-            return Err(ErrorNum::EOOR);
+        let mut rel = blk_idx - DIRECT_BLK_COUNT;
+        for depth in 1..=3 {
+            let span = BLOCKNO_PER_BLK.pow(depth);
+            if rel < span {
+                return Some((depth, rel));
+            }
+            rel -= span;
         }
-        if create {
-            for i in 0..min(DIRECT_BLK_COUNT, offset / BLK_SIZE + 1) {
-                if inode.direct_blk_no[i] == BAD_BLOCK {
-                    inode.direct_blk_no[i] = fs_inner.alloc_blk();
-                    verbose!("alloc blk {:?} (pa {:?})", inode.direct_blk_no[i], ParchFS::blockno_2_ppn(inode.direct_blk_no[i]))
+        None
+    }
+
+    fn root_slot<'a>(inode: &'a mut PFSINode, depth: u32, rel: usize) -> &'a mut BlockNo {
+        match depth {
+            0 => &mut inode.direct_blk_no[rel],
+            1 => &mut inode.indirect_blk,
+            2 => &mut inode.indirect_blk2,
+            _ => &mut inode.indirect_blk3,
+        }
+    }
+
+    /// Walk to block `blk_idx`, with `create` allocating it and the index blocks on the way. BAD_BLOCK if it's not there.
+    /// `allocated` is set if anything was.
+    fn map_block(blk_idx: usize, create: bool, allocated: &mut bool, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut PFSINode) -> Result<BlockNo, ErrorNum> {
+        let uid = inode.uid;
+        let (depth, mut rel) = Self::locate(blk_idx).unwrap();
+        let mut slot = Self::root_slot(inode, depth, rel);
+        for level in (0..=depth).rev() {
+            if *slot == BAD_BLOCK {
+                if !create {
+                    return Ok(BAD_BLOCK);
+                }
+                if depth == 3 {
+                    fs_inner.add_feature(PFSFeatures::INDIRECT3);
                 }
+                *slot = fs_inner.alloc_blk(uid)?;
+                *allocated = true;
+                // index blocks start out empty, data blocks fill a hole that read as zero
+                slot.clear_blk();
+                verbose!("alloc lv{} blk {:?} (pa {:?})", level, *slot, ParchFS::blockno_2_ppn(*slot));
             }
+            if level == 0 {
+                break;
+            }
+            if create {
+                fs_inner.journal().log_block(*slot);
+            }
+            let blocks: &'static mut [BlockNo; BLOCKNO_PER_BLK] = unsafe{ParchFS::blockno_2_pa(*slot).instantiate_volatile()};
+            let span = BLOCKNO_PER_BLK.pow(level - 1);
+            slot = &mut blocks[rel / span];
+            rel %= span;
         }
-        if offset < BLK_SIZE * DIRECT_BLK_COUNT {
-            let res = inode.direct_blk_no[offset / BLK_SIZE];
-            assert!(res != BAD_BLOCK, "Malformed fs");
-            return Ok(res);
-        }
-        offset -= BLK_SIZE * DIRECT_BLK_COUNT;
+        Ok(*slot)
+    }
 
-        // indirect 1
+    /// Block holding `offset`, None if it's in a hole. With `create`, a hole is filled in. Files only grow by
+    /// expand, blocks are allocated when written.
+    pub fn get_blockno_locked(&self, offset: usize, create: bool, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut MutexGuard<&mut PFSINode>) -> Result<Option<BlockNo>, ErrorNum> {
+        if offset >= PFS_MAXCAP || offset >= inode.f_size {
+            return Err(ErrorNum::EOOR);
+        }
         if create {
-            if inode.indirect_blk == BAD_BLOCK {
-                inode.indirect_blk = fs_inner.alloc_blk();
-                inode.indirect_blk.clear_blk();
-                verbose!("alloc indirect blk {:?} (pa {:?})", inode.indirect_blk, ParchFS::blockno_2_ppn(inode.indirect_blk))
-            }
+            fs_inner.journal().log(&***inode);
+        }
+        let mut allocated = false;
+        let res = Self::map_block(offset / BLK_SIZE, create, &mut allocated, fs_inner, inode)?;
+        if allocated {
+            self.inode.remap();
+        }
+        if res == BAD_BLOCK {
+            Ok(None)
+        } else {
+            Ok(Some(res))
+        }
+    }
 
-            let indirect_blk_pa = ParchFS::blockno_2_pa(inode.indirect_blk);
-            let blocks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe {
-                indirect_blk_pa.instantiate_volatile()
-            };
-            
-            for i in 0..min(BLOCKNO_PER_BLK, offset / BLK_SIZE + 1) {
-                if blocks[i] == BAD_BLOCK {
-                    blocks[i] = fs_inner.alloc_blk();
-                    verbose!("alloc blk {:?} (pa {:?})", blocks[i], ParchFS::blockno_2_ppn(blocks[i]))
-                }
+    /// First block index in [from, end) that is data (`data`) or hole (`!data`).
+    fn find_block(inode: &PFSINode, from: usize, end: usize, data: bool) -> Option<usize> {
+        fn scan(block_no: BlockNo, level: u32, base: usize, from: usize, end: usize, data: bool) -> Option<usize> {
+            let span = BLOCKNO_PER_BLK.pow(level);
+            if base >= end || base + span <= from {
+                return None;
+            }
+            if block_no == BAD_BLOCK {
+                // the whole subtree is a hole
+                return if data {None} else {Some(base.max(from))};
             }
+            if level == 0 {
+                return if data {Some(base)} else {None};
+            }
+            let blocks: &[BlockNo; BLOCKNO_PER_BLK] = unsafe{ParchFS::blockno_2_pa(block_no).instantiate_volatile()};
+            let child_span = span / BLOCKNO_PER_BLK;
+            blocks.iter().enumerate().find_map(|(i, &child)| scan(child, level - 1, base + i * child_span, from, end, data))
         }
-        assert!(inode.indirect_blk != BAD_BLOCK, "malformed fs");
-        if offset < BLK_SIZE * BLOCKNO_PER_BLK {
-            let indirect_blk_pa = ParchFS::blockno_2_pa(inode.indirect_blk);
-            let blocks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe {
-                indirect_blk_pa.instantiate_volatile()
-            };
-            let res = blocks[offset / BLK_SIZE];
-            assert!(res != BAD_BLOCK, "malformed fs");
-            return Ok(res);
+        let mut base = 0;
+        for (i, &block_no) in inode.direct_blk_no.iter().enumerate() {
+            if let Some(res) = scan(block_no, 0, base + i, from, end, data) {
+                return Some(res);
+            }
         }
-        offset -= BLK_SIZE * BLOCKNO_PER_BLK;
-
-        // indirect 2
-        if create {
-            if inode.indirect_blk2 == BAD_BLOCK {
-                inode.indirect_blk2 = fs_inner.alloc_blk();
-                inode.indirect_blk2.clear_blk();
-                verbose!("alloc l1 indirect blk {:?} (pa {:?})", inode.indirect_blk2, ParchFS::blockno_2_ppn(inode.indirect_blk2))
+        base += DIRECT_BLK_COUNT;
+        for (depth, block_no) in [(1, inode.indirect_blk), (2, inode.indirect_blk2), (3, inode.indirect_blk3)] {
+            if let Some(res) = scan(block_no, depth, base, from, end, data) {
+                return Some(res);
             }
+            base += BLOCKNO_PER_BLK.pow(depth);
+        }
+        None
+    }
 
-            let lv1_indirect_blk_pa = ParchFS::blockno_2_pa(inode.indirect_blk2);
-            let lv1_indirect_blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe {
-                lv1_indirect_blk_pa.instantiate_volatile()
-            };
+    /// Offset of the first data at or after `offset`, for SEEK_DATA. ENXIO if there's none before EOF.
+    pub fn next_data(&self, offset: usize) -> Result<usize, ErrorNum> {
+        self.flush()?;
+        let inode = self.inode.acquire();
+        if offset >= inode.f_size {
+            return Err(ErrorNum::ENXIO);
+        }
+        let end = (inode.f_size - 1) / BLK_SIZE + 1;
+        let blk_idx = Self::find_block(&inode, offset / BLK_SIZE, end, true).ok_or(ErrorNum::ENXIO)?;
+        Ok(offset.max(blk_idx * BLK_SIZE))
+    }
 
-            for i in 0..min(BLOCKNO_PER_BLK, offset / (BLOCKNO_PER_BLK * BLK_SIZE) + 1) {
-                if lv1_indirect_blks[i] == BAD_BLOCK {
-                    lv1_indirect_blks[i] = fs_inner.alloc_blk();
-                    lv1_indirect_blks[i].clear_blk();
-                    verbose!("alloc l2 indirect blk {:?} (pa {:?})", lv1_indirect_blks[i], ParchFS::blockno_2_ppn(lv1_indirect_blks[i]))
-                }
+    /// Has blocks before EOF that aren't allocated, for PFSFeatures::SPARSE.
+    pub fn has_hole(inode: &PFSINode) -> bool {
+        inode.f_size != 0 && Self::find_block(inode, 0, (inode.f_size - 1) / BLK_SIZE + 1, false).is_some()
+    }
 
-                let lv2_indirect_blk_pa = ParchFS::blockno_2_pa(lv1_indirect_blks[i]);
-                let lv2_indirect_blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe {
-                    lv2_indirect_blk_pa.instantiate_volatile()
-                };
-
-                for j in 0..BLOCKNO_PER_BLK {
-                    if lv2_indirect_blks[i] == BAD_BLOCK {
-                        lv2_indirect_blks[i] = fs_inner.alloc_blk();
-                        verbose!("alloc blk {:?} (pa {:?})", lv2_indirect_blks[i], ParchFS::blockno_2_ppn(lv2_indirect_blks[i]))
-                    }
-
-                    let lv2_cap = i * BLOCKNO_PER_BLK * BLK_SIZE + j * BLK_SIZE;
-                    if lv2_cap > offset {
-                        break;
-                    }
-                }
-            }
+    /// Offset of the first hole at or after `offset`, for SEEK_HOLE. EOF counts as a hole.
+    pub fn next_hole(&self, offset: usize) -> Result<usize, ErrorNum> {
+        self.flush()?;
+        let inode = self.inode.acquire();
+        if offset >= inode.f_size {
+            return Err(ErrorNum::ENXIO);
         }
-        assert!(inode.indirect_blk2 != BAD_BLOCK, "Malformed fs");
-        let blk_offset = offset / BLK_SIZE;
-        let lv1_indirect_blk_pa = ParchFS::blockno_2_pa(inode.indirect_blk2);
-        let lv1_indirect_blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe {
-            lv1_indirect_blk_pa.instantiate_volatile()
-        };
-        let lv1_blkno = lv1_indirect_blks[blk_offset / BLOCKNO_PER_BLK];
-        
-        assert!(lv1_blkno != BAD_BLOCK, "Malformed fs");
-        let lv2_indirect_blk_pa = ParchFS::blockno_2_pa(lv1_blkno);
-        let lv2_indirect_blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe {
-            lv2_indirect_blk_pa.instantiate_volatile()
-        };
-        let lv2_blkno = lv2_indirect_blks[blk_offset % BLOCKNO_PER_BLK];
+        let end = (inode.f_size - 1) / BLK_SIZE + 1;
+        match Self::find_block(&inode, offset / BLK_SIZE, end, false) {
+            Some(blk_idx) => Ok(offset.max(blk_idx * BLK_SIZE).min(inode.f_size)),
+            None => Ok(inode.f_size)
+        }
+    }
 
-        assert!(lv2_blkno != BAD_BLOCK, "Malformed fs");
-        return Ok(lv2_blkno);
+    /// Block holding `offset`, read only, None if it's past the end or not allocated. With the inode lock held, or
+    /// on an image nobody else is using yet, e.g. on mount.
+    pub fn lookup_blockno(inode: &PFSINode, offset: usize) -> Option<BlockNo> {
+        if offset >= inode.f_size || offset >= PFS_MAXCAP {
+            return None;
+        }
+        let (depth, mut rel) = Self::locate(offset / BLK_SIZE)?;
+        let mut res = match depth {
+            0 => inode.direct_blk_no[rel],
+            1 => inode.indirect_blk,
+            2 => inode.indirect_blk2,
+            _ => inode.indirect_blk3,
+        };
+        for level in (1..=depth).rev() {
+            if res == BAD_BLOCK {
+                return None;
+            }
+            let blocks: &[BlockNo; BLOCKNO_PER_BLK] = unsafe{ParchFS::blockno_2_pa(res).instantiate_volatile()};
+            let span = BLOCKNO_PER_BLK.pow(level - 1);
+            res = blocks[rel / span];
+            rel %= span;
+        }
+        if res == BAD_BLOCK {
+            None
+        } else {
+            Some(res)
+        }
     }
 
-    pub fn get_blockno(&self, offset: usize, create: bool) -> Result<BlockNo, ErrorNum> {
+    pub fn get_blockno(&self, offset: usize, create: bool) -> Result<Option<BlockNo>, ErrorNum> {
+        self.flush()?;
+        if !create {
+            let inode = self.inode.acquire();
+            if offset >= PFS_MAXCAP || offset >= inode.f_size {
+                return Err(ErrorNum::EOOR);
+            }
+            return Ok(Self::lookup_blockno(&inode, offset));
+        }
         let fs = self.fs.clone().upgrade().unwrap();
+        let _txn = fs.clone().begin();
         let mut fs_inner = fs.inner.acquire();
         let inode_guard = fs_inner.get_inode(self.inode_no)?;
         let mut inode = inode_guard.acquire();
         self.get_blockno_locked(offset, create, &mut fs_inner, &mut inode)
     }
 
-    pub fn expand(&self, offset: usize) -> Result<(), ErrorNum> {
-        self.get_blockno(offset, true)?;
-        Ok(())
+    pub fn expand(&self, new_size: usize) -> Result<(), ErrorNum> {
+        self.flush()?;
+        let fs = self.fs.clone().upgrade().unwrap();
+        let _txn = fs.clone().begin();
+        let mut fs_inner = fs.inner.acquire();
+        let inode_guard = fs_inner.get_inode(self.inode_no)?;
+        let mut inode = inode_guard.acquire();
+        self.expand_locked(new_size, &mut fs_inner, &mut inode)
     }
 
-    pub fn expand_locked(&self, offset: usize, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut MutexGuard<&mut PFSINode>) -> Result<(), ErrorNum> {
-        self.get_blockno_locked(offset, true, fs_inner, inode)?;
+    /// Data up to `end` was written in place through a shared mapping, update size and change time.
+    pub fn sync_written(&self, end: usize) -> Result<(), ErrorNum> {
+        self.flush()?;
+        let fs = self.fs.clone().upgrade().unwrap();
+        let _txn = fs.clone().begin();
+        let mut fs_inner = fs.inner.acquire();
+        let inode_guard = fs_inner.get_inode(self.inode_no)?;
+        let mut inode = inode_guard.acquire();
+        fs_inner.journal().log(&**inode);
+        inode.change_time = get_real_time_epoch();
+        self.expand_locked(end, &mut fs_inner, &mut inode)
+    }
+
+    /// Grow to `new_size`, the new part is a hole.
+    pub fn expand_locked(&self, new_size: usize, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut MutexGuard<&mut PFSINode>) -> Result<(), ErrorNum> {
+        if new_size > PFS_MAXCAP {
+            return Err(ErrorNum::EFBIG);
+        }
+        if new_size > inode.f_size {
+            fs_inner.journal().log(&***inode);
+            inode.f_size = new_size;
+            ksm_invalidate(fs_inner.uuid(), self.inode_no.0);
+        }
         Ok(())
     }
 
     pub fn resize(&self, new_size: usize) -> Result<(), ErrorNum> {
         let new_size: usize = new_size as usize;
+        self.flush()?;
         let fs = self.fs.clone().upgrade().unwrap();
+        let _txn = fs.clone().begin();
         let mut fs_inner = fs.inner.acquire();
         let inode_guard = fs_inner.get_inode(self.inode_no)?;
         let mut inode = inode_guard.acquire();
+        inode.change_time = get_real_time_epoch();
         self.resize_locked(new_size, &mut fs_inner, &mut inode)
     }
 
     pub fn resize_locked(&self,  new_size: usize, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut MutexGuard<&mut PFSINode>) -> Result<(), ErrorNum> {
         if inode.f_size < new_size {
+            if (new_size + BLK_SIZE - 1) / BLK_SIZE > (inode.f_size + BLK_SIZE - 1) / BLK_SIZE {
+                fs_inner.add_feature(PFSFeatures::SPARSE);
+            }
             return self.expand_locked(new_size, fs_inner, inode);
         }
+        Self::truncate_locked(new_size, fs_inner, inode);
+        self.inode.remap();
+        ksm_invalidate(fs_inner.uuid(), self.inode_no.0);
+        Ok(())
+    }
 
-        if inode.f_size == new_size {
-            return Ok(());
+    /// shrink only, doesn't need a file object, so orphan inode can be freed on last close.
+    pub fn truncate_locked(new_size: usize, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut MutexGuard<&mut PFSINode>) {
+        if inode.f_size <= new_size {
+            return;
         }
+        fs_inner.journal().log(&***inode);
 
+        // first block to go
         let shrink_start = if new_size == 0 {
             0
         }else{
             (new_size - 1) / BLK_SIZE + 1
         };
+        // rest of the last block kept must read as zero if the file grows again
+        if new_size % BLK_SIZE != 0 {
+            if let Some(blk) = Self::lookup_blockno(&***inode, new_size) {
+                unsafe{core::ptr::write_bytes((ParchFS::blockno_2_pa(blk) + new_size % BLK_SIZE).0 as *mut u8, 0, BLK_SIZE - new_size % BLK_SIZE)};
+            }
+        }
+        Self::punch_locked(shrink_start, PFS_MAXCAP / BLK_SIZE, fs_inner, inode);
 
-        if shrink_start <= DIRECT_BLK_COUNT + BLOCKNO_PER_BLK {
-            // all lv2 are gone
-            self.free_blockno(inode.indirect_blk2, 2, fs_inner, inode);
-            inode.indirect_blk2 = BAD_BLOCK;
-            if shrink_start <= DIRECT_BLK_COUNT {
-                // all lv1 are gone
-                self.free_blockno(inode.indirect_blk, 1, fs_inner, inode);
-                inode.indirect_blk = BAD_BLOCK;
-                // some lv0 are gone
-                for i in shrink_start..DIRECT_BLK_COUNT {
-                    self.free_blockno(inode.direct_blk_no[i], 0, fs_inner, inode);
-                    inode.direct_blk_no[i] = BAD_BLOCK;
-                }
-            } else {
-                // some lv1 are gone
-                let lv1_blks_pa = ParchFS::blockno_2_pa(inode.indirect_blk);
-                let lv1_blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe{lv1_blks_pa.instantiate_volatile()};
-                let start = shrink_start - DIRECT_BLK_COUNT;
-                for i in start..BLOCKNO_PER_BLK {
-                    self.free_blockno(lv1_blks[i], 0, fs_inner, inode);
-                    lv1_blks[i] = BAD_BLOCK;
-                }
+        inode.f_size = new_size;
+    }
+
+    /// Free blocks [from, to) of the file, they're holes after. Caller logs the inode.
+    fn punch_locked(from: usize, to: usize, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut PFSINode) {
+        let uid = inode.uid;
+        for i in from.min(DIRECT_BLK_COUNT)..to.min(DIRECT_BLK_COUNT) {
+            Self::free_blockno(inode.direct_blk_no[i], 0, uid, fs_inner);
+            inode.direct_blk_no[i] = BAD_BLOCK;
+        }
+        let mut tree_start = DIRECT_BLK_COUNT;
+        for depth in 1..=3 {
+            let span = BLOCKNO_PER_BLK.pow(depth);
+            if from < tree_start + span && to > tree_start {
+                let tree_from = from.saturating_sub(tree_start);
+                let tree_to = min(to - tree_start, span);
+                Self::punch_tree(Self::root_slot(inode, depth, 0), depth, tree_from, tree_to, uid, fs_inner);
             }
-        } else {
-            // some lv2 are gone
-            let lv2_blks_pa = ParchFS::blockno_2_pa(inode.indirect_blk2);
-            let lv2_blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe{lv2_blks_pa.instantiate_volatile()};
-            // remove lv2 entry
-            let start = shrink_start - DIRECT_BLK_COUNT - BLOCKNO_PER_BLK;
-            let lv2_start = (start - 1) / BLOCKNO_PER_BLK + 1;  // first lv2 blk should preserve, and remove part of lv1 blk within
-            for i in lv2_start..BLOCKNO_PER_BLK {
-                self.free_blockno(lv2_blks[i], 1, fs_inner, inode);
-                lv2_blks[i] = BAD_BLOCK;
+            tree_start += span;
+        }
+    }
+
+    /// Drop blocks [from, to) (counted inside this tree), in the tree of `level` rooted at `slot`.
+    /// Index blocks go only when all they cover does.
+    fn punch_tree(slot: &mut BlockNo, level: u32, from: usize, to: usize, uid: u32, fs_inner: &mut MutexGuard<ParchFSInner>) {
+        if *slot == BAD_BLOCK || from >= to {
+            return;
+        }
+        if from == 0 && to >= BLOCKNO_PER_BLK.pow(level) {
+            Self::free_blockno(*slot, level as usize, uid, fs_inner);
+            *slot = BAD_BLOCK;
+            return;
+        }
+        // part of it only, so level != 0: a lv0 tree is a single block
+        fs_inner.journal().log_block(*slot);
+        let blocks: &'static mut [BlockNo; BLOCKNO_PER_BLK] = unsafe{ParchFS::blockno_2_pa(*slot).instantiate_volatile()};
+        let span = BLOCKNO_PER_BLK.pow(level - 1);
+        for i in from / span..min((to - 1) / span + 1, BLOCKNO_PER_BLK) {
+            let base = i * span;
+            Self::punch_tree(&mut blocks[i], level - 1, from.saturating_sub(base), min(to - base, span), uid, fs_inner);
+        }
+    }
+
+    /// Free whole blocks in [offset, offset + len) and zero what's left of it, size stays. Past EOF is ignored.
+    pub fn punch_hole(&self, offset: usize, len: usize) -> Result<(), ErrorNum> {
+        self.flush()?;
+        let fs = self.fs.clone().upgrade().unwrap();
+        let _txn = fs.clone().begin();
+        let mut fs_inner = fs.inner.acquire();
+        let inode_guard = fs_inner.get_inode(self.inode_no)?;
+        let mut inode = inode_guard.acquire();
+        let end = min(offset.saturating_add(len), inode.f_size);
+        if offset >= end {
+            return Ok(());
+        }
+        fs_inner.journal().log(&**inode);
+        inode.change_time = get_real_time_epoch();
+        // partial blocks at either end are zeroed in place
+        let first = (offset + BLK_SIZE - 1) / BLK_SIZE;
+        let last = end / BLK_SIZE;
+        for (from, to) in [(offset, min(end, first * BLK_SIZE)), (max(offset, last * BLK_SIZE), end)] {
+            if from >= to {
+                continue;
             }
-            // remove first lv2 -> lv1 entry
-            let lv1_start = start % BLOCKNO_PER_BLK;
-            if lv1_start != 0 {
-                let lv1_blks_pa = ParchFS::blockno_2_pa(lv2_blks[lv2_start - 1]);
-                let lv1_blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe{lv1_blks_pa.instantiate_volatile()};
-                for i in lv1_start..BLOCKNO_PER_BLK {
-                    self.free_blockno(lv1_blks[i], 0, fs_inner, inode);
-                    lv1_blks[i] = BAD_BLOCK;
-                }
+            if let Some(blk) = Self::lookup_blockno(&inode, from) {
+                unsafe{core::ptr::write_bytes((ParchFS::blockno_2_pa(blk) + from % BLK_SIZE).0 as *mut u8, 0, to - from)};
             }
         }
-
-        inode.f_size = new_size;
+        if first < last {
+            fs_inner.add_feature(PFSFeatures::SPARSE);
+            Self::punch_locked(first, last, &mut fs_inner, &mut inode);
+        }
+        self.inode.remap();
+        ksm_invalidate(fs.uuid, self.inode_no.0);
         Ok(())
     }
 
-    /// lvl == 0: direct
-    /// lvl == 1: indirect 1
-    /// lvl == 2: indirect 2
-    /// must set block_no to BAD_BLOCK after calling this
-    pub fn free_blockno(&self, block_no: BlockNo, lvl: usize, fs_inner: &mut MutexGuard<ParchFSInner>, inode: &mut MutexGuard<&mut PFSINode>) {
+    /// lvl == 0: data block
+    /// lvl == n: index block of a lv n tree, everything under it goes too
+    /// must set block_no to BAD_BLOCK after calling this. `uid` owns the file, to give back its quota.
+    pub fn free_blockno(block_no: BlockNo, lvl: usize, uid: u32, fs_inner: &mut MutexGuard<ParchFSInner>) {
         if block_no == BAD_BLOCK {return;}
         if lvl >= 1 {
             let blks_pa = ParchFS::blockno_2_pa(block_no);
-            let blks: &mut [BlockNo; BLOCKNO_PER_BLK] = unsafe{blks_pa.instantiate_volatile()};
+            let blks: &[BlockNo; BLOCKNO_PER_BLK] = unsafe{blks_pa.instantiate_volatile()};
+            // entries are left as is, undo may bring this block back
             for i in 0..BLOCKNO_PER_BLK {
-                self.free_blockno(blks[i], lvl-1, fs_inner, inode);
-                blks[i] = BAD_BLOCK;
+                Self::free_blockno(blks[i], lvl-1, uid, fs_inner);
             }
         }
-        fs_inner.free_blk(block_no);
+        fs_inner.free_blk(block_no, uid);
     }
 
     pub fn f_type(&self) -> Result<FileType, ErrorNum> {
@@ -257,22 +413,47 @@ impl PFSBase {
     
     // if inode was gone (deleted by other process), cannot write but can still read from remained mmap.
     pub fn write(&self, data: alloc::vec::Vec::<u8>, offset: Cursor) -> Result<(), crate::utils::ErrorNum> {
+        self.write_with(data.len(), offset, |pa, src_start, cpy_size| unsafe {
+            pa.write_data(data[src_start..src_start + cpy_size].to_vec())
+        })
+    }
+
+    /// write from pinned user pages, without the intermediate Vec.
+    pub fn write_user(&self, buf: &UserBuffer, offset: Cursor) -> Result<(), ErrorNum> {
+        self.write_with(buf.len(), offset, |pa, src_start, cpy_size| unsafe {
+            buf.copy_to(src_start, pa, cpy_size)
+        })
+    }
+
+    /// `f(dst, src_offset, count)` does the actual copy for each block.
+    fn write_with<F: FnMut(PhysAddr, usize, usize)>(&self, length: usize, offset: Cursor, mut f: F) -> Result<(), ErrorNum> {
         let mut offset = offset.0;
-        if data.len() == 0 {return Ok(())}
+        if length == 0 {return Ok(())}
         let fs = self.fs.upgrade().unwrap();
+        if self.overwrite_with(length, offset, &mut f) {
+            ksm_invalidate(fs.uuid, self.inode_no.0);
+            return Ok(());
+        }
+        let _txn = fs.clone().begin();
         let mut fs_inner = fs.inner.acquire();
         let inode_guard = fs_inner.get_inode(self.inode_no)?;
         let mut inode = inode_guard.acquire();
+        fs_inner.journal().log(&**inode);
+        // directory content is metadata too, regular file data is not journaled
+        let log_old = if inode.f_type == PFSType::REGULAR {0} else {inode.f_size};
         inode.change_time = get_real_time_epoch();
         inode.access_time = get_real_time_epoch();
-        if inode.f_size < offset + data.len() {
-            self.expand_locked(offset + data.len(), &mut fs_inner, &mut inode)?;
+        if inode.f_size < offset + length {
+            // whole blocks between EOF and what's written stay holes
+            if offset / BLK_SIZE > (inode.f_size + BLK_SIZE - 1) / BLK_SIZE {
+                fs_inner.add_feature(PFSFeatures::SPARSE);
+            }
+            self.expand_locked(offset + length, &mut fs_inner, &mut inode)?;
         }
-        let length = data.len();
         let target = length + offset;
         let mut data_ptr = 0;
         while offset < target {
-            let blk = self.get_blockno_locked(offset, false, &mut fs_inner, &mut inode)?;
+            let blk = self.get_blockno_locked(offset, true, &mut fs_inner, &mut inode)?.unwrap();
             let pa = ParchFS::blockno_2_pa(blk);
             // offset to pa
             let dst_start = offset % BLK_SIZE;
@@ -286,23 +467,110 @@ impl PFSBase {
             }
             let cpy_size = dst_end - dst_start;
 
-            let src_start = data_ptr;
-            let src_end = src_start + cpy_size;
-
-            unsafe{&(pa + dst_start).write_data(data[src_start..src_end].to_vec())};
+            if offset < log_old {
+                fs_inner.journal().log_in_block(blk, dst_start, min(cpy_size, log_old - offset));
+            }
+            f(pa + dst_start, data_ptr, cpy_size);
             offset += cpy_size;
             data_ptr += cpy_size;
         }
+        ksm_invalidate(fs.uuid, self.inode_no.0);
         Ok(())
         
     }
 
-    pub fn read(&self, mut length: usize, offset: Cursor) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+    /// Write over blocks a regular file already has, under the inode lock alone so writes to different files don't
+    /// contend. False, with nothing written, if it needs a block allocated or the file to grow, which take the fs lock.
+    fn overwrite_with<F: FnMut(PhysAddr, usize, usize)>(&self, length: usize, offset: usize, f: &mut F) -> bool {
+        let mut inode = self.inode.acquire();
+        let target = offset + length;
+        if inode.f_type != PFSType::REGULAR || target > inode.f_size {
+            return false;
+        }
+        let mut blocks = Vec::new();
+        for blk_idx in offset / BLK_SIZE..(target - 1) / BLK_SIZE + 1 {
+            match Self::lookup_blockno(&inode, blk_idx * BLK_SIZE) {
+                Some(blk) => blocks.push(Some(blk)),
+                None => return false,
+            }
+        }
+        // data isn't journaled, and the times alone needn't be
+        inode.change_time = get_real_time_epoch();
+        inode.access_time = get_real_time_epoch();
+        Self::copy_out(offset, target, &blocks, f);
+        true
+    }
+
+    /// Regular file data, a short write is kept with the inode for a while, see write_behind.rs.
+    pub fn write_behind(&self, data: &[u8], offset: Cursor) -> Result<(), ErrorNum> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if offset.0 + data.len() > PFS_MAXCAP {
+            return Err(ErrorNum::EFBIG);
+        }
+        let mut pending = self.inode.pending();
+        if let Some(write) = pending.as_mut() {
+            if write.end() == offset.0 && write.data.len() + data.len() <= WRITE_BEHIND_MAX {
+                write.data.extend_from_slice(data);
+                return Ok(());
+            }
+        }
+        if let Some(write) = pending.take() {
+            self.write(write.data, Cursor(write.offset))?;
+        }
+        if data.len() < WRITE_BEHIND_MAX {
+            *pending = Some(PendingWrite{offset: offset.0, data: data.to_vec()});
+            mark_dirty(&self.inode);
+            Ok(())
+        } else {
+            self.write(data.to_vec(), offset)
+        }
+    }
+
+    /// Put the pending write of the inode on the blocks.
+    pub fn flush(&self) -> Result<(), ErrorNum> {
+        let mut pending = self.inode.pending();
+        match pending.take() {
+            Some(write) => self.write(write.data, Cursor(write.offset)),
+            None => Ok(()),
+        }
+    }
+
+    /// flush, for fsync and close, with what writeback failed on since the last one.
+    pub fn sync(&self) -> Result<(), ErrorNum> {
+        self.flush()?;
+        self.inode.take_wb_error().map_or(Ok(()), Err)
+    }
+
+    pub fn read(&self, length: usize, offset: Cursor, ra: Option<&mut ReadAhead>) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        let mut result: Vec<u8> = Vec::new();
+        self.read_with(length, offset, ra, |pa, _, cpy_size| {
+            result.append(&mut unsafe{pa.read_data(cpy_size)});
+        })?;
+        Ok(result)
+    }
+
+    /// read into pinned user pages, without the intermediate Vec. Returns bytes read.
+    pub fn read_user(&self, buf: &mut UserBuffer, offset: Cursor, ra: Option<&mut ReadAhead>) -> Result<usize, ErrorNum> {
+        let length = buf.len();
+        self.read_with(length, offset, ra, |pa, dst_start, cpy_size| unsafe {
+            buf.copy_from(dst_start, pa, cpy_size)
+        })
+    }
+
+    /// `f(src, dst_offset, count)` does the actual copy for each block. Returns bytes read.
+    /// With `ra`, a sequential read is served from the mappings it kept if it can, see readahead.rs.
+    fn read_with<F: FnMut(PhysAddr, usize, usize)>(&self, mut length: usize, offset: Cursor, mut ra: Option<&mut ReadAhead>, mut f: F) -> Result<usize, ErrorNum> {
+        self.flush()?;
         let mut offset = offset.0;
-        let fs = self.fs.upgrade().unwrap();
-        let mut fs_inner = fs.inner.acquire();
-        let inode_guard = fs_inner.get_inode(self.inode_no)?;
-        let mut inode = inode_guard.acquire();
+        let sequential = ra.as_mut().map_or(false, |ra| ra.access(offset));
+        if sequential {
+            if let Some(res) = self.read_ahead(length, offset, ra.as_deref_mut().unwrap(), &mut f) {
+                return Ok(res);
+            }
+        }
+        let mut inode = self.inode.acquire();
         inode.access_time = get_real_time_epoch();
 
         // truncate
@@ -314,12 +582,31 @@ impl PFSBase {
             }
         }
 
-        if length == 0 {return Ok(Vec::new())}
-        let mut result: Vec<u8> = Vec::new();
+        if length == 0 {return Ok(0)}
         let target = length + offset;
+        if let Some(ra) = ra {
+            if sequential {
+                // map the blocks read now and a window after them
+                let first = offset / BLK_SIZE;
+                let end = min(((target - 1) / BLK_SIZE + 1 + ra.window()) * BLK_SIZE, inode.f_size);
+                let mut blocks = Vec::new();
+                for blk_idx in first..(end - 1) / BLK_SIZE + 1 {
+                    blocks.push(Self::lookup_blockno(&inode, blk_idx * BLK_SIZE));
+                }
+                ra.fill(first, blocks, self.inode.map_gen());
+                Self::copy_out(offset, target, ra.lookup(offset, target, self.inode.map_gen()).unwrap(), &mut f);
+                ra.done(target);
+                return Ok(length);
+            }
+            ra.done(target);
+        }
+        let mut data_ptr = 0;
         while offset < target {
-            let blk = self.get_blockno_locked(offset, false, &mut fs_inner, &mut inode)?;
-            let pa = ParchFS::blockno_2_pa(blk);
+            // holes read as zero
+            let pa = match Self::lookup_blockno(&inode, offset) {
+                Some(blk) => ParchFS::blockno_2_pa(blk),
+                None => zero_block(),
+            };
 
             let cpy_start = offset % BLK_SIZE;
             let cpy_end = if target >= offset + (BLK_SIZE - cpy_start) {
@@ -328,23 +615,122 @@ impl PFSBase {
                 target % BLK_SIZE
             };
             let cpy_size = cpy_end - cpy_start;
-            result.append(&mut unsafe{(pa + cpy_start).read_data(cpy_size).clone()});
+            f(pa + cpy_start, data_ptr, cpy_size);
             offset += cpy_size;
+            data_ptr += cpy_size;
         }
-        Ok(result)
+        Ok(length)
         
     }
 
+    /// Sequential read inside what `ra` kept, without walking the index. None if it's not all there.
+    fn read_ahead<F: FnMut(PhysAddr, usize, usize)>(&self, length: usize, offset: usize, ra: &mut ReadAhead, f: &mut F) -> Option<usize> {
+        let mut inode = self.inode.acquire();
+        let length = min(length, inode.f_size.saturating_sub(offset));
+        if length == 0 {
+            return None;
+        }
+        let target = offset + length;
+        Self::copy_out(offset, target, ra.lookup(offset, target, self.inode.map_gen())?, f);
+        inode.access_time = get_real_time_epoch();
+        ra.done(target);
+        Some(length)
+    }
+
+    /// Copy [offset, target) out of `blocks`, the mapping of the blocks it spans.
+    fn copy_out<F: FnMut(PhysAddr, usize, usize)>(mut offset: usize, target: usize, blocks: &[Option<BlockNo>], f: &mut F) {
+        let first = offset / BLK_SIZE;
+        let mut data_ptr = 0;
+        while offset < target {
+            let pa = match blocks[offset / BLK_SIZE - first] {
+                Some(blk) => ParchFS::blockno_2_pa(blk),
+                None => zero_block(),
+            };
+            let cpy_start = offset % BLK_SIZE;
+            let cpy_size = min(BLK_SIZE - cpy_start, target - offset);
+            f(pa + cpy_start, data_ptr, cpy_size);
+            offset += cpy_size;
+            data_ptr += cpy_size;
+        }
+    }
+
+    /// copy between two inodes of the same fs, without bouncing through a buffer.
+    /// Block aligned parts are copied block by block. Returns bytes copied.
+    /// EXDEV if not on the same fs, EINVAL if same inode; caller should fall back to read/write.
+    pub fn copy_range(&self, src_off: Cursor, dst: &PFSBase, dst_off: Cursor, mut length: usize) -> Result<usize, ErrorNum> {
+        if !Weak::ptr_eq(&self.fs, &dst.fs) {
+            return Err(ErrorNum::EXDEV);
+        }
+        if self.inode_no == dst.inode_no {
+            return Err(ErrorNum::EINVAL);
+        }
+        self.flush()?;
+        dst.flush()?;
+        let mut src_off = src_off.0;
+        let mut dst_off = dst_off.0;
+        let fs = self.fs.upgrade().unwrap();
+        let _txn = fs.clone().begin();
+        let mut fs_inner = fs.inner.acquire();
+        let src_guard = fs_inner.get_inode(self.inode_no)?;
+        let mut src_inode = src_guard.acquire();
+        let dst_guard = fs_inner.get_inode(dst.inode_no)?;
+        let mut dst_inode = dst_guard.acquire();
+        ksm_invalidate(fs.uuid, dst.inode_no.0);
+
+        // truncate
+        if src_off >= src_inode.f_size {
+            length = 0;
+        } else if src_inode.f_size < src_off + length {
+            length = src_inode.f_size - src_off;
+        }
+        if length == 0 {return Ok(0)}
+
+        src_inode.access_time = get_real_time_epoch();
+        dst_inode.change_time = get_real_time_epoch();
+        dst_inode.access_time = get_real_time_epoch();
+        if dst_inode.f_size < dst_off + length {
+            if dst_off / BLK_SIZE > (dst_inode.f_size + BLK_SIZE - 1) / BLK_SIZE {
+                fs_inner.add_feature(PFSFeatures::SPARSE);
+            }
+            dst.expand_locked(dst_off + length, &mut fs_inner, &mut dst_inode)?;
+        }
+
+        let mut copied = 0;
+        while copied < length {
+            let src_pa = match self.get_blockno_locked(src_off, false, &mut fs_inner, &mut src_inode)? {
+                Some(blk) => ParchFS::blockno_2_pa(blk),
+                None => zero_block(),
+            };
+            let dst_pa = ParchFS::blockno_2_pa(dst.get_blockno_locked(dst_off, true, &mut fs_inner, &mut dst_inode)?.unwrap());
+            let src_start = src_off % BLK_SIZE;
+            let dst_start = dst_off % BLK_SIZE;
+            let cpy_size = min(min(BLK_SIZE - src_start, BLK_SIZE - dst_start), length - copied);
+            unsafe {
+                copy_nonoverlapping(
+                    (src_pa + src_start).0 as *const u8,
+                    (dst_pa + dst_start).0 as *mut u8,
+                    cpy_size
+                )
+            }
+            src_off += cpy_size;
+            dst_off += cpy_size;
+            copied += cpy_size;
+        }
+        Ok(copied)
+    }
+
     pub fn vfs(&self) -> Arc<dyn crate::fs::VirtualFileSystem> {
         self.fs.upgrade().unwrap()
     }
 
+    /// Tell inotify watches on this inode.
+    pub fn notify(&self, mask: u32, name: Option<&str>) {
+        crate::fs::inotify_event(self.vfs().get_uuid(), self.inode_no.0, mask, name);
+    }
+
     pub fn stat(&self) -> Result<crate::fs::types::FileStat, ErrorNum> {
-        let fs_guard = self.fs.upgrade().unwrap();
-        let mut fs = fs_guard.inner.acquire();
-        let inode_guard = fs.get_inode(self.inode_no)?;
-        let inode = inode_guard.acquire();
-        // let fs_mount_path ;
+        self.flush()?;
+        let inode = self.inode.acquire();
         Ok(crate::fs::types::FileStat { 
             open_mode: self.open_mode, 
             file_size: inode.f_size,
@@ -354,21 +740,28 @@ impl PFSBase {
         })
     }
     
+    pub fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        let inode = self.inode.acquire();
+        Ok(crate::fs::FileOwner {
+            uid: inode.uid,
+            gid: inode.gid,
+            permission: inode.permission.into(),
+        })
+    }
+
     pub fn copy_page(&self, offset: usize) -> Result<PageGuard, ErrorNum> {
-        let result = alloc_vm_page();
+        self.flush()?;
+        let result = try_alloc_vm_page()?.with_owner(PageOwner::Inode(self.inode_no.0));
         if offset % BLK_SIZE != 0 {
             let offset_nxt = offset + (BLK_SIZE - (offset % BLK_SIZE));
-            let block_no_1 = self.get_blockno(offset, false)?;
-            let block_no_2 = self.get_blockno(offset_nxt, false)?;
-            
-            let block_ppn_1 = ParchFS::blockno_2_ppn(block_no_1);
-            let block_ppn_2 = ParchFS::blockno_2_ppn(block_no_2);
+            let block_pa_1 = self.block_pa(offset)?;
+            let block_pa_2 = self.block_pa(offset_nxt)?;
 
-            let ptr_src_1 = (PhysAddr::from(block_ppn_1) + (offset % BLK_SIZE)).0 as *const u8;
+            let ptr_src_1 = (block_pa_1 + (offset % BLK_SIZE)).0 as *const u8;
             let ptr_dst_1 = PhysAddr::from(result.ppn).0 as *mut u8;
             let ptr_len_1 = BLK_SIZE - (offset % BLK_SIZE);
 
-            let ptr_src_2 = (PhysAddr::from(block_ppn_2)).0 as *const u8;
+            let ptr_src_2 = block_pa_2.0 as *const u8;
             let ptr_dst_2 = (PhysAddr::from(result.ppn) + ptr_len_1).0 as *mut u8;
             let ptr_len_2 = BLK_SIZE - ptr_len_1;
 
@@ -377,24 +770,36 @@ impl PFSBase {
                 core::ptr::copy_nonoverlapping(ptr_src_2, ptr_dst_2, ptr_len_2);
             }
         } else {
-            let block_no = self.get_blockno(offset, false)?;
-            let block_ppn = ParchFS::blockno_2_ppn(block_no);
-            unsafe {PhysPageNum::copy_page(&block_ppn,&result.ppn);}
+            let block_pa = self.block_pa(offset)?;
+            unsafe {core::ptr::copy_nonoverlapping(block_pa.0 as *const u8, PhysAddr::from(result.ppn).0 as *mut u8, BLK_SIZE);}
         }
         Ok(result)
     }
 
-    pub fn get_page(&self, offset: usize) -> Result<PageGuard, ErrorNum> {
-        let block_no = self.get_blockno(offset, false)?;
-        let block_ppn = ParchFS::blockno_2_ppn(block_no);
-        Ok(claim_fs_page(block_ppn))
+    /// Start of the block holding `offset`, the zero block for a hole.
+    fn block_pa(&self, offset: usize) -> Result<PhysAddr, ErrorNum> {
+        Ok(match self.get_blockno(offset, false)? {
+            Some(block_no) => ParchFS::blockno_2_pa(block_no),
+            None => zero_block(),
+        })
+    }
+
+    /// Block for a shared mapping. A hole is filled in with `fill`, for a write through the mapping, and is None
+    /// otherwise. EAGAIN while mappings of the file are being dropped, the fault is retried.
+    pub fn get_page(&self, offset: usize, fill: bool) -> Result<Option<PageGuard>, ErrorNum> {
+        if self.inode.unmapping() {
+            return Err(ErrorNum::EAGAIN);
+        }
+        // before the lookup, a write that fills the hole after it then sees it has to unmap
+        if !fill {
+            self.inode.map_hole();
+        }
+        let block_no = self.get_blockno(offset, fill)?;
+        Ok(block_no.map(|block_no| claim_fs_page(ParchFS::blockno_2_ppn(block_no))))
     }
 
     pub fn get_mount_uuid(&self) -> Result<UUID, ErrorNum> {
-        let fs = self.fs.upgrade().unwrap();
-        let mut fs_inner = fs.inner.acquire();
-        let inode_guard = fs_inner.get_inode(self.inode_no)?;
-        let inode = inode_guard.acquire();
+        let inode = self.inode.acquire();
         if inode.f_type != PFSType::MOUNT {
             Err(ErrorNum::EBADTYPE)
         } else {
@@ -19,7 +19,26 @@ pub const DENTRY_SIZE: usize = 128;
 pub const SUPERBLOCK_SIZE: usize = PAGE_SIZE;
 pub const INODE_BITMAP_SIZE: usize = BLK_SIZE * 8;
 pub const INODE_LIST_SIZE: usize = 512 * BLK_SIZE;
+/// dead entries of the inode cache are dropped once it's grown to this, or to twice what was alive last time
+pub const INODE_CACHE_SWEEP_MIN: usize = 64;
 
 
 pub const PFS_MAGIC: u64 = 0xBEEF_BEEF_BEEF_BEEF;
-pub const PFS_MAXCAP: usize = DIRECT_BLK_COUNT * BLK_SIZE + BLOCKNO_PER_BLK * BLK_SIZE + BLK_SIZE * BLOCKNO_PER_BLK * BLOCKNO_PER_BLK;
\ No newline at end of file
+pub const PFS_VERSION: u32 = 1;
+pub const JOURNAL_MAGIC: u64 = 0x4A4F_5552_4E41_4C21;
+/// log blocks of the journal, the header block not included
+pub const JOURNAL_BLOCKS: usize = 64;
+/// past this many bytes of log, new transactions wait for the open ones to close and the generation to commit
+pub const JOURNAL_DRAIN_BYTES: usize = JOURNAL_BLOCKS * BLK_SIZE / 2;
+pub const PFS_MAXCAP: usize = DIRECT_BLK_COUNT * BLK_SIZE + BLOCKNO_PER_BLK * BLK_SIZE + BLK_SIZE * BLOCKNO_PER_BLK * BLOCKNO_PER_BLK + BLK_SIZE * BLOCKNO_PER_BLK * BLOCKNO_PER_BLK * BLOCKNO_PER_BLK;
+/// blocks mapped ahead of a sequential read, the window starts at MIN and doubles up to MAX
+pub const READAHEAD_MIN: usize = 4;
+pub const READAHEAD_MAX: usize = 64;
+/// writes shorter than this are kept with the inode and coalesced, see write_behind.rs
+pub const WRITE_BEHIND_MAX: usize = BLK_SIZE;
+/// kept writes older than this go to the blocks from the writeback thread, default of /proc/sys/vm/dirty_writeback_ms
+pub const WRITE_BEHIND_INTERVAL_MS: usize = 500;
+/// this many inodes with a kept write start writeback at once, default of /proc/sys/vm/dirty_background_inodes
+pub const WRITE_BEHIND_DIRTY_INODES: usize = 64;
+/// low bits of a getdents cookie holding the dirent slot generation
+pub const DIR_COOKIE_GEN_BITS: usize = 16;
\ No newline at end of file
@@ -1,24 +1,172 @@
-use core::fmt::Debug;
+use core::{fmt::Debug, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
 
-use alloc::{collections::{BTreeMap}, sync::Arc};
+use alloc::{collections::{BTreeMap}, sync::{Arc, Weak}, vec::Vec, string::String};
 
-use crate::{fs::{VirtualFileSystem, fs_impl::{parch_fs::{INODE_SIZE, BLK_SIZE, PFS_MAGIC, INODE_BITMAP_SIZE, PFSDir, PFSBase}, PARCH_FS}, DirFile, OpenMode, Path}, utils::{SpinMutex, Mutex, ErrorNum, UUID}, mem::{BitMap, PhysAddr, alloc_fs_page, free_fs_page, PhysPageNum}, config::PAGE_SIZE};
+use crate::{fs::{VirtualFileSystem, FsStat, dentry_forget, inotify_forget, fs_impl::{parch_fs::{INODE_SIZE, BLK_SIZE, DENTRY_NAME_LEN, PFS_MAGIC, PFS_VERSION, PFSFeatures, INODE_BITMAP_SIZE, INODE_CACHE_SWEEP_MIN, PFSDir, PFSBase}, PARCH_FS}, DirFile, OpenMode, Path}, utils::{SpinMutex, SleepMutex, Mutex, MutexGuard, ErrorNum, UUID, bootargs}, mem::{BitMap, PhysAddr, alloc_fs_page, free_fs_page, ksm_invalidate, PhysPageNum}, process::{WaitQueue, get_processor}, config::PAGE_SIZE};
 
-use super::{PFSINode, INodeNo, SuperBlock, BlockNo, PFSDirInner};
+use super::{BAD_BLOCK, PFSINode, INodeNo, SuperBlock, BlockNo, PFSDirInner, journal::{Journal, Transaction}, quota::Quota, write_behind::{PendingWrite, mark_orphaned}};
+
+/// In-memory inode object, at most one per on-disk inode (see ParchFSInner::get_inode).
+/// Every open file hold one, so all opens share the same lock and state.
+/// When the inode was unlinked while still open, it's marked as orphan, and freed after last close, see
+/// `ParchFS::release_orphans`.
+pub struct PFSINodeHandle {
+    pub inode_no: INodeNo,
+    lock: SpinMutex<&'static mut PFSINode>,
+    orphan: SpinMutex<bool>,
+    /// Directory only. Held across a whole dirent table read-modify-write, so different PFSDir objects opened on
+    /// the same directory can't interleave. Take it before the PFSDir lock, and parent before child.
+    dir_lock: SleepMutex<()>,
+    /// bumped with the inode lock held whenever a block is mapped in or out, readahead drops what it kept then
+    map_gen: AtomicUsize,
+    /// regular file only, truncates, hole punches and writes over mapped holes under way, shared mappings don't
+    /// fault blocks in meanwhile
+    unmapping: AtomicUsize,
+    /// regular file only, a hole was mapped to the zero page, writes must drop those mappings first
+    hole_mapped: AtomicBool,
+    /// regular file only, small writes not on the blocks yet, see write_behind.rs
+    pending: SpinMutex<Option<PendingWrite>>,
+    /// what writeback of the pending write failed with, for the next fsync or close
+    wb_error: SpinMutex<Option<ErrorNum>>,
+    /// directory only, per dirent slot, bumped each time the slot is emptied so a getdents cookie names one entry
+    slot_gen: SpinMutex<Vec<u16>>,
+    fs: Weak<ParchFS>,
+}
+
+pub struct UnmapGuard(Arc<PFSINodeHandle>);
+
+impl Drop for UnmapGuard {
+    fn drop(&mut self) {
+        self.0.unmapping.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl PFSINodeHandle {
+    pub fn acquire(&self) -> MutexGuard<&'static mut PFSINode> {
+        self.lock.acquire()
+    }
+
+    pub fn lock_dir(&self) -> MutexGuard<()> {
+        self.dir_lock.acquire()
+    }
+
+    pub fn map_gen(&self) -> usize {
+        self.map_gen.load(Ordering::Acquire)
+    }
+
+    /// Block map changed, call with the inode lock held.
+    pub fn remap(&self) {
+        self.map_gen.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Shared mappings of blocks or holes are being dropped, get_page hands out none until the guard drops.
+    pub fn begin_unmap(self: &Arc<Self>) -> UnmapGuard {
+        self.unmapping.fetch_add(1, Ordering::AcqRel);
+        UnmapGuard(self.clone())
+    }
+
+    pub fn unmapping(&self) -> bool {
+        self.unmapping.load(Ordering::Acquire) != 0
+    }
+
+    /// Stays set while the inode is cached, a mapping of the hole may live that long.
+    pub fn map_hole(&self) {
+        self.hole_mapped.store(true, Ordering::Release);
+    }
+
+    pub fn hole_mapped(&self) -> bool {
+        self.hole_mapped.load(Ordering::Acquire)
+    }
+
+    pub fn slot_gen(&self, slot: usize) -> u16 {
+        self.slot_gen.acquire().get(slot).copied().unwrap_or(0)
+    }
+
+    /// Dirent slot emptied, the next entry put there gets a new cookie.
+    pub fn free_slot(&self, slot: usize) {
+        let mut gens = self.slot_gen.acquire();
+        if gens.len() <= slot {
+            gens.resize(slot + 1, 0);
+        }
+        gens[slot] = gens[slot].wrapping_add(1);
+    }
+
+    pub fn pending(&self) -> MutexGuard<Option<PendingWrite>> {
+        self.pending.acquire()
+    }
+
+    /// Put the pending write on the blocks, for when there's no open file at hand.
+    pub fn flush(self: &Arc<Self>) -> Result<(), ErrorNum> {
+        let fs = match self.fs.upgrade() {
+            Some(fs) => fs,
+            None => return Ok(()),
+        };
+        // only written through, the path is never looked at
+        PFSBase{inode_no: self.inode_no, inode: self.clone(), open_mode: OpenMode::SYS, fs: Arc::downgrade(&fs), path: "/".into()}.flush()
+    }
+
+    /// Writeback lost the pending write, tell whoever syncs or closes next.
+    pub fn set_wb_error(&self, error: ErrorNum) {
+        *self.wb_error.acquire() = Some(error);
+    }
+
+    pub fn take_wb_error(&self) -> Option<ErrorNum> {
+        self.wb_error.acquire().take()
+    }
+
+    /// Free after last close instead of now.
+    pub fn set_orphan(&self) {
+        *self.orphan.acquire() = true;
+    }
+}
+
+/// The last reference can go away anywhere, fs lock held or not, so freeing is left to `ParchFS::release_orphans`.
+impl Drop for PFSINodeHandle {
+    fn drop(&mut self) {
+        if !*self.orphan.acquire() {
+            return;
+        }
+        if let Some(fs) = self.fs.upgrade() {
+            fs.orphans.acquire().push(self.inode_no);
+            mark_orphaned(&fs);
+        }
+    }
+}
 
 pub struct ParchFSInner {
     // lock inode, not locking file (user's task)
-    inode_locks: BTreeMap<INodeNo, Arc<SpinMutex<&'static mut PFSINode>>>,
+    inode_cache: BTreeMap<INodeNo, Weak<PFSINodeHandle>>,
     superblock: &'static mut SuperBlock,    // don't need additional lock, ParchFSInner's mutex took care of that.
     // no fs_bitmap/mm_bitmap, mem module take care of that
     // XXX: move them here? multiple ParchFS in main NVM?
-    inode_bitmap: BitMap
+    inode_bitmap: BitMap,
+    journal: Journal,
+    quota: Quota,
+    /// of the ParchFS this is in, for the caches keyed by it
+    uuid: UUID,
+    /// inode_cache is swept of dead entries when it reaches this size
+    sweep_at: usize,
 }
 
+/// Locks, outermost first:
+/// - the lock of an open file object
+/// - pending write of the inode, see write_behind.rs
+/// - dir_lock of a directory inode, parent before child
+/// - `inner`, the fs lock: inode cache, inode and block allocation, journal and quota. Whatever changes the size
+///   or block map of an inode takes it, the change is journaled together with the allocation it needs.
+/// - the inode lock. Every change to an inode and its data is made holding it, so it's all that's needed to read
+///   them or to write over blocks a file already has, and reads and overwrites of different files don't contend.
+/// A transaction is opened before the fs lock, see `begin`.
 pub struct ParchFS{
     pub inner: SpinMutex<ParchFSInner>,
     pub mount_path: Path,
-    pub uuid: UUID
+    pub uuid: UUID,
+    /// names are looked up case-insensitively, the stored case is kept. From PFSFeatures::CASEFOLD of this image.
+    pub case_fold: bool,
+    /// orphan inodes whose last handle is gone, not freed yet. Taken on its own, never with another lock.
+    orphans: SpinMutex<Vec<INodeNo>>,
+    /// transactions waiting for the journal generation to commit, see Journal::must_wait
+    pub(super) committed: WaitQueue,
 }
 
 impl Debug for ParchFS {
@@ -30,10 +178,16 @@ impl Debug for ParchFS {
 impl ParchFS {
     pub fn new(mount_path: Path) -> Self {
         // TODO: if not mounted at root, set /.. to upper level fs's folder.
+        let uuid = UUID::new();
+        let inner = ParchFSInner::new(uuid);
+        let case_fold = inner.superblock.features & PFSFeatures::CASEFOLD.bits() != 0;
         Self{
-            inner: SpinMutex::new("PFS lock", ParchFSInner::new()),
+            inner: SpinMutex::new("PFS lock", inner),
             mount_path,
-            uuid: UUID::new()
+            uuid,
+            case_fold,
+            orphans: SpinMutex::new("PFS orphans", Vec::new()),
+            committed: WaitQueue::new("PFS journal commit"),
         }
     }
 
@@ -55,7 +209,7 @@ impl ParchFS {
 
     pub fn ppn_2_blockno(ppn: PhysPageNum) -> BlockNo {
         extern "C" {fn BASE_ADDRESS();}
-        BlockNo::from(PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize)) - ppn)
+        BlockNo::from(ppn - PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize)))
     }
 
     pub fn blockno_2_ppn(block_no: BlockNo) -> PhysPageNum {
@@ -63,27 +217,85 @@ impl ParchFS {
         PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize)) + (block_no.0 as usize)
     }
 
-    /// FIXME: Maybe a custom struct for Arc<SpinMutex<&'static mut INode>>, then implement Drop for auto recover?
-    /// Calculate how much extra space it need
     /// !!! MUST NOT USE RAW instantiate_volatile(), for one INode correspond to multiple File and File Mutex is not enough
-    pub fn get_inode(&self, inode_no: INodeNo) -> Result<Arc<SpinMutex<&'static mut PFSINode>>, ErrorNum> {
+    pub fn get_inode(&self, inode_no: INodeNo) -> Result<Arc<PFSINodeHandle>, ErrorNum> {
         let mut inner = self.inner.acquire();
         inner.get_inode(inode_no)
     }
 
-    pub fn alloc_blk(&self) -> BlockNo {
+    pub fn alloc_blk(&self, uid: u32) -> Result<BlockNo, ErrorNum> {
+        let mut inner = self.inner.acquire();
+        inner.alloc_blk(uid)
+    }
+
+    pub fn free_blk(&self, block_no: BlockNo, uid: u32) {
         let mut inner = self.inner.acquire();
-        inner.alloc_blk()
+        inner.free_blk(block_no, uid);
     }
 
-    pub fn free_blk(&self, block_no: BlockNo) {
+    /// Open a transaction, metadata changes until it's dropped are undone together if we crash before that.
+    /// Take it before any fs lock, transactions nest. May sleep until the journal commits when its log is filling up,
+    /// so a transaction must not take the lock of a file object or directory someone could hold while opening one.
+    pub fn begin(self: Arc<Self>) -> Transaction {
+        let owner = get_processor().current().map(|proc| proc.pid.0);
         let mut inner = self.inner.acquire();
-        inner.free_blk(block_no);
+        while inner.journal.must_wait(owner) {
+            self.committed.sleep_on(inner);
+            inner = self.inner.acquire();
+        }
+        inner.journal.begin(owner);
+        drop(inner);
+        Transaction(self, owner)
+    }
+
+    /// Free orphan inodes closed for the last time. Call without any fs or inode lock, done when a transaction
+    /// ends and by the writeback thread.
+    pub fn release_orphans(self: &Arc<Self>) {
+        let orphans = core::mem::take(&mut *self.orphans.acquire());
+        if orphans.is_empty() {
+            return;
+        }
+        let _txn = self.clone().begin();
+        for inode_no in orphans {
+            verbose!("Last close of orphan inode {}, freeing.", inode_no.0);
+            let mut fs_inner = self.inner.acquire();
+            // not an orphan to this one, it's dropped as any other handle
+            let handle = match fs_inner.get_inode(inode_no) {
+                Ok(handle) => handle,
+                Err(_) => continue,
+            };
+            let mut inode = handle.acquire();
+            PFSBase::truncate_locked(0, &mut fs_inner, &mut inode);
+            drop(inode);
+            fs_inner.free_inode(inode_no);
+        }
     }
 }
 
+/// Is there a formatted ParchFS image in reserved memory?
+pub fn parch_fs_present() -> bool {
+    extern "C" {fn SUPERBLOCK_ADDRESS();}
+    let magic: u64 = unsafe{PhysAddr::from(SUPERBLOCK_ADDRESS as usize).read_volatile()};
+    magic == PFS_MAGIC
+}
+
+/// Why the image in reserved memory can't be mounted by this kernel, if it can't.
+pub fn parch_fs_refused() -> Option<String> {
+    extern "C" {fn SUPERBLOCK_ADDRESS();}
+    if !parch_fs_present() {
+        return None;
+    }
+    let superblock: &SuperBlock = unsafe{PhysAddr::from(SUPERBLOCK_ADDRESS as usize).instantiate_volatile()};
+    ParchFSInner::check_format(superblock).err()
+}
+
+/// Is there an image this kernel can mount? The page bitmaps of a refused one are still left alone.
+pub fn parch_fs_mountable() -> bool {
+    parch_fs_present() && parch_fs_refused().is_none()
+}
+
 impl ParchFSInner {
-    pub fn new() -> Self {
+    pub fn new(uuid: UUID) -> Self {
         extern "C" {
             fn INODE_BITMAP_ADDRESS();
             fn SUPERBLOCK_ADDRESS();
@@ -91,60 +303,199 @@ impl ParchFSInner {
         let inode_bitmap_start = PhysAddr::from(INODE_BITMAP_ADDRESS as usize);
         let superblock_start = PhysAddr::from(SUPERBLOCK_ADDRESS as usize);
         let superblock: &mut SuperBlock = unsafe{superblock_start.instantiate_volatile()};
+        if superblock.magic != PFS_MAGIC {
+            #[cfg(feature = "mkfs")]
+            super::mkfs::mkfs(superblock, inode_bitmap_start);
+        }
+        assert!(superblock.magic == PFS_MAGIC, "Bad FS Magic");
+        if let Err(reason) = Self::check_format(superblock) {
+            // callers check parch_fs_refused() before touching PARCH_FS
+            panic!("{}", reason);
+        }
+        let unflagged = Self::upgrade_format(superblock);
+        // undo first, it may change the inode bitmap
+        let journal = Journal::open(superblock, inode_bitmap_start);
+        let quota = Quota::open(superblock);
 
-        let res = Self {
-            inode_locks: BTreeMap::new(),
+        let mut res = Self {
+            inode_cache: BTreeMap::new(),
             superblock,
-            inode_bitmap: BitMap::new(inode_bitmap_start, INODE_BITMAP_SIZE)
+            inode_bitmap: BitMap::new(inode_bitmap_start, INODE_BITMAP_SIZE),
+            journal,
+            quota,
+            uuid,
+            sweep_at: INODE_CACHE_SWEEP_MIN,
         };
-        assert!(res.superblock.magic == PFS_MAGIC, "Bad FS Magic");
+        if unflagged {
+            res.detect_features();
+        }
+        // case folding belongs to the image, once asked for it stays on
+        if bootargs::get("pfs_casefold").is_some() {
+            res.add_feature(PFSFeatures::CASEFOLD);
+        }
         res
     }
 
-    /// FIXME: Maybe a custom struct for Arc<SpinMutex<&'static mut INode>>, then implement Drop for auto recover?
-    /// Calculate how much extra space it need
+    pub fn uuid(&self) -> UUID {
+        self.uuid
+    }
+
+    /// Refuse images from a newer kernel, with the reason.
+    fn check_format(superblock: &SuperBlock) -> Result<(), String> {
+        if superblock.version > PFS_VERSION {
+            return Err(format!("ParchFS: image version {} is newer than supported {}, refusing to mount", superblock.version, PFS_VERSION));
+        }
+        let unknown = superblock.features & !PFSFeatures::all().bits();
+        if unknown != 0 {
+            return Err(format!("ParchFS: image has unknown feature bits {:#x}, refusing to mount", unknown));
+        }
+        Ok(())
+    }
+
+    /// Bring an accepted older image up to date. True if it's from before feature bits, so what it has in use
+    /// is yet to be found out. Bits are otherwise set as their structure is first written, see add_feature.
+    fn upgrade_format(superblock: &mut SuperBlock) -> bool {
+        let unflagged = superblock.version == 0;
+        if superblock.version < PFS_VERSION {
+            milestone!("ParchFS: upgrading image from version {} to {}", superblock.version, PFS_VERSION);
+            superblock.version = PFS_VERSION;
+        }
+        unflagged
+    }
+
+    /// Image from before feature bits, set the ones for what its files use. Journal and quota set theirs on open.
+    fn detect_features(&mut self) {
+        for inode_no in 0..self.superblock.inode_count as u32 {
+            if !self.inode_bitmap.get(inode_no as usize) {
+                continue;
+            }
+            let inode: &PFSINode = unsafe{ParchFS::inodeno_2_pa(INodeNo(inode_no)).instantiate_volatile()};
+            if inode.indirect_blk3 != BAD_BLOCK {
+                self.add_feature(PFSFeatures::INDIRECT3);
+            }
+            if PFSBase::has_hole(inode) {
+                self.add_feature(PFSFeatures::SPARSE);
+            }
+        }
+    }
+
+    /// The image has a structure of `feature` from now on, kernels that don't know it refuse to mount it. Not
+    /// logged, a bit that outlives an undo only makes older kernels refuse an image they could have taken.
+    pub fn add_feature(&mut self, feature: PFSFeatures) {
+        if self.superblock.features & feature.bits() != feature.bits() {
+            verbose!("ParchFS: {:?} in use", feature);
+            self.superblock.features |= feature.bits();
+        }
+    }
+
+    /// Get the shared inode object, create one if nobody is holding it.
     /// !!! MUST NOT USE RAW instantiate_volatile(), for one INode correspond to multiple File and File Mutex is not enough
     /// if holding lock of PFSInner, use this function instead of outer wrappers' function to avoid deadlock
-    pub fn get_inode(&mut self, inode_no: INodeNo) -> Result<Arc<SpinMutex<&'static mut PFSINode>>, ErrorNum> {
+    pub fn get_inode(&mut self, inode_no: INodeNo) -> Result<Arc<PFSINodeHandle>, ErrorNum> {
         if self.inode_bitmap.get(inode_no.0 as usize) == false {
             // remove lock
-            self.inode_locks.remove(&inode_no);
+            self.inode_cache.remove(&inode_no);
             // prevent summon it again
             return Err(ErrorNum::ENOENT);
         }
-        if self.inode_locks.contains_key(&inode_no) {
-            Ok(self.inode_locks.get(&inode_no).unwrap().clone())
-        } else {
-            let pa = ParchFS::inodeno_2_pa(inode_no);
-            let inode: &mut PFSINode = unsafe{pa.instantiate_volatile()};
-            let mutex = Arc::new(SpinMutex::new("INode lock", inode));
-            self.inode_locks.insert(inode_no, mutex.clone());
-            return Ok(mutex);
+        if let Some(handle) = self.inode_cache.get(&inode_no).and_then(|w| w.upgrade()) {
+            return Ok(handle);
+        }
+        // sweeping on every miss would make opening a file O(open files)
+        if self.inode_cache.len() >= self.sweep_at {
+            self.inode_cache.retain(|_, w| w.strong_count() > 0);
+            self.sweep_at = (self.inode_cache.len() * 2).max(INODE_CACHE_SWEEP_MIN);
         }
+        let pa = ParchFS::inodeno_2_pa(inode_no);
+        let inode: &mut PFSINode = unsafe{pa.instantiate_volatile()};
+        let handle = Arc::new(PFSINodeHandle {
+            inode_no,
+            lock: SpinMutex::new("INode lock", inode),
+            orphan: SpinMutex::new("INode orphan", false),
+            dir_lock: SleepMutex::new("INode dirents", ()),
+            map_gen: AtomicUsize::new(0),
+            unmapping: AtomicUsize::new(0),
+            hole_mapped: AtomicBool::new(false),
+            pending: SpinMutex::new("INode write-behind", None),
+            wb_error: SpinMutex::new("INode writeback error", None),
+            slot_gen: SpinMutex::new("INode dirent generations", Vec::new()),
+            fs: Arc::downgrade(&PARCH_FS.clone()),
+        });
+        self.inode_cache.insert(inode_no, Arc::downgrade(&handle));
+        Ok(handle)
     }
 
-    pub fn alloc_blk(&mut self) -> BlockNo {
+    /// Charged to `uid`, the owner of the file it's for.
+    pub fn alloc_blk(&mut self, uid: u32) -> Result<BlockNo, ErrorNum> {
+        self.quota.charge_block(uid, &mut self.journal)?;
+        self.journal.log(&self.superblock.free_block);
         self.superblock.free_block -= 1;
         let pa = alloc_fs_page();
-        ParchFS::pa_2_blockno(pa.into())
+        let block_no = ParchFS::pa_2_blockno(pa.into());
+        self.journal.log_alloc_blk(block_no);
+        Ok(block_no)
     }
 
-    pub fn free_blk(&mut self, block_no: BlockNo) {
+    /// In a transaction, the block is only released when it's over, undo may need its content.
+    pub fn free_blk(&mut self, block_no: BlockNo, uid: u32) {
+        self.quota.release_block(uid, &mut self.journal);
+        self.journal.log(&self.superblock.free_block);
         self.superblock.free_block += 1;
-        let ppn = ParchFS::blockno_2_ppn(block_no);
-        free_fs_page(ppn)
+        if !self.journal.defer_free(block_no) {
+            free_fs_page(ParchFS::blockno_2_ppn(block_no))
+        }
     }
 
-    pub fn alloc_inode(&mut self) -> INodeNo {
+    /// Charged to `uid`, who creates it.
+    pub fn alloc_inode(&mut self, uid: u32) -> Result<INodeNo, ErrorNum> {
+        self.quota.charge_inode(uid, &mut self.journal)?;
         let inode_no = self.inode_bitmap.first_empty().unwrap();
+        self.journal.log(&self.superblock.free_inode);
+        self.journal.log_alloc_inode(inode_no.into());
         self.inode_bitmap.set(inode_no);
-        inode_no.into()
+        self.superblock.free_inode = self.superblock.free_inode.saturating_sub(1);
+        Ok(inode_no.into())
+    }
+
+    pub fn inode_allocated(&self, inode_no: INodeNo) -> bool {
+        self.inode_bitmap.get(inode_no.0 as usize)
+    }
+
+    pub fn root_inode(&self) -> INodeNo {
+        self.superblock.root_inode.into()
+    }
+
+    pub fn superblock(&mut self) -> &mut SuperBlock {
+        self.superblock
+    }
+
+    pub fn journal(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+
+    /// With the journal, as changes to the table are logged.
+    pub fn quota(&mut self) -> (&mut Quota, &mut Journal) {
+        (&mut self.quota, &mut self.journal)
+    }
+
+    /// Someone holds a handle on it, so it may be an orphan waiting for last close.
+    pub fn inode_open(&self, inode_no: INodeNo) -> bool {
+        self.inode_cache.get(&inode_no).map_or(false, |w| w.strong_count() > 0)
     }
 
     pub fn free_inode(&mut self, inode_no: INodeNo) {
         let inode_no = inode_no.0 as usize;
         assert!(self.inode_bitmap.get(inode_no), "Freeing free inode");
+        let owner: &PFSINode = unsafe{ParchFS::inodeno_2_pa(inode_no.into()).instantiate_volatile()};
+        self.quota.release_inode(owner.uid, &mut self.journal);
+        self.journal.log(&self.superblock.free_inode);
+        self.journal.log_free_inode(inode_no.into());
         self.inode_bitmap.clear(inode_no);
+        self.superblock.free_inode += 1;
+        dentry_forget(self.uuid, inode_no as u32);
+        inotify_forget(self.uuid, inode_no as u32);
+        // the number may be handed out again, its old pages mustn't be
+        ksm_invalidate(self.uuid, inode_no as u32);
     }
 }
 
@@ -161,19 +512,42 @@ impl VirtualFileSystem for ParchFS {
         self
     }
 
+    fn fs_type(&self) -> &'static str {
+        "parchfs"
+    }
+
     fn get_uuid(&self) -> crate::utils::UUID {
         self.uuid
     }
 
     fn root_dir(&self, open_mode: OpenMode) -> Result<Arc<dyn DirFile>, ErrorNum> {
+        let mut inner = self.inner.acquire();
+        let inode_no: INodeNo = inner.superblock.root_inode.into();
+        let inode = inner.get_inode(inode_no)?;
+        drop(inner);
         Ok(Arc::new(PFSDir(SpinMutex::new("PFSFile", PFSDirInner{
             base: PFSBase { 
-                inode_no: self.inner.acquire().superblock.root_inode.into(), 
+                inode_no, 
+                inode,
                 open_mode,
                 fs: Arc::downgrade(&PARCH_FS.clone()), 
                 path: "/".into() 
             }
-        }))))
+        }), AtomicUsize::new(0))))
+    }
+
+    /// Straight from the superblock counters.
+    fn statfs(&self) -> Result<FsStat, ErrorNum> {
+        let mut inner = self.inner.acquire();
+        let superblock = inner.superblock();
+        Ok(FsStat {
+            block_size: BLK_SIZE,
+            blocks: superblock.block_count as usize,
+            free_blocks: superblock.free_block as usize,
+            inodes: superblock.inode_count as usize,
+            free_inodes: superblock.free_inode as usize,
+            name_max: DENTRY_NAME_LEN,
+        })
     }
 
     fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
@@ -0,0 +1,352 @@
+//! ParchFS consistency checker. Runs on mount before anything is opened, and on demand from /proc/fsck.
+//! Reads inodes and blocks directly instead of going through the inode cache, as PARCH_FS itself may still be
+//! initializing. On a live fs, inodes with open handles are left alone, and an operation half way through may show
+//! up as a problem, so repair is only done on mount (`fsck=repair` bootarg).
+
+use core::fmt::Display;
+
+use alloc::{collections::{BTreeMap, BTreeSet}, string::String, vec::Vec};
+
+use crate::{fs::dentry_invalidate, mem::{PhysAddr, PhysPageNum, fs_pages_in_use, mark_fs_page, free_fs_page}, utils::{SpinMutex, Mutex, MutexGuard}};
+
+use super::{BAD_BLOCK, BAD_INODE, BLK_SIZE, BLOCKNO_PER_BLK, DENTRY_SIZE, INODE_BITMAP_SIZE, INODE_LIST_SIZE, INODE_SIZE, BlockNo, INodeNo, PFSBase, PFSDEntry, PFSINode, PFSType, fs::{ParchFS, ParchFSInner}};
+
+#[derive(Default)]
+pub struct FsckReport {
+    pub problems: Vec<String>,
+    pub repaired: usize,
+    pub dirs: usize,
+    pub inodes: usize,
+    pub blocks: usize,
+}
+
+impl FsckReport {
+    fn problem(&mut self, msg: String) {
+        error!("fsck: {}", msg);
+        self.problems.push(msg);
+    }
+}
+
+impl Display for FsckReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for problem in self.problems.iter() {
+            writeln!(f, "{}", problem)?;
+        }
+        writeln!(f, "{} directories, {} inodes, {} blocks checked, {} problem(s), {} repaired", self.dirs, self.inodes, self.blocks, self.problems.len(), self.repaired)
+    }
+}
+
+/// Quota usage against what the inodes hold. A table created on this mount is filled in without a complaint.
+fn check_quota(fs_inner: &mut MutexGuard<ParchFSInner>, mut usage: BTreeMap<u32, (u64, u64)>, repair: bool, report: &mut FsckReport) {
+    let (quota, journal) = fs_inner.quota();
+    let fresh = core::mem::take(&mut quota.fresh);
+    // uids that have nothing left still need their entry zeroed
+    for entry in quota.entries() {
+        usage.entry(entry.uid).or_default();
+    }
+    for (uid, (blocks, inodes)) in usage {
+        let entry = quota.get(uid);
+        if entry.blocks == blocks && entry.inodes == inodes {
+            continue;
+        }
+        if !fresh {
+            report.problem(format!("quota of uid {} says {} blocks {} inodes, counted {} blocks {} inodes", uid, entry.blocks, entry.inodes, blocks, inodes));
+        }
+        if repair || fresh {
+            if quota.set_usage(uid, blocks, inodes, journal) {
+                if !fresh {
+                    report.repaired += 1;
+                }
+            } else {
+                report.problem(format!("quota table full, uid {} not accounted", uid));
+            }
+        }
+    }
+}
+
+fn raw_inode(inode_no: INodeNo) -> &'static mut PFSINode {
+    unsafe{ParchFS::inodeno_2_pa(inode_no).instantiate_volatile()}
+}
+
+fn index_blk(block_no: BlockNo) -> &'static [BlockNo; BLOCKNO_PER_BLK] {
+    unsafe{ParchFS::blockno_2_pa(block_no).instantiate_volatile()}
+}
+
+/// Blocks live between the kernel image and the reserved area at the end of memory.
+fn block_valid(block_no: BlockNo) -> bool {
+    extern "C" {
+        fn ekernel();
+        fn INODE_LIST_ADDRESS();
+    }
+    let ppn = ParchFS::blockno_2_ppn(block_no);
+    ppn >= PhysAddr::from(ekernel as usize).to_ppn_ceil() && ppn < PhysAddr::from(INODE_LIST_ADDRESS as usize).into()
+}
+
+/// Pages in the fs bitmap that belong to no inode by design.
+fn page_reserved(ppn: PhysPageNum) -> bool {
+    extern "C" {
+        fn ekernel();
+        fn INODE_LIST_ADDRESS();
+    }
+    ppn < PhysAddr::from(ekernel as usize).to_ppn_ceil() || ppn >= PhysAddr::from(INODE_LIST_ADDRESS as usize).into()
+}
+
+/// Every block an inode owns, index blocks included. Err with the first out of range block, as following it
+/// would read garbage.
+fn inode_blocks(inode: &PFSINode) -> Result<Vec<BlockNo>, BlockNo> {
+    fn take(block_no: BlockNo, level: usize, res: &mut Vec<BlockNo>) -> Result<(), BlockNo> {
+        if block_no == BAD_BLOCK {
+            return Ok(());
+        }
+        if !block_valid(block_no) {
+            return Err(block_no);
+        }
+        res.push(block_no);
+        if level != 0 {
+            for &child in index_blk(block_no).iter() {
+                take(child, level - 1, res)?;
+            }
+        }
+        Ok(())
+    }
+    let mut res = Vec::new();
+    for &block_no in inode.direct_blk_no.iter() {
+        take(block_no, 0, &mut res)?;
+    }
+    take(inode.indirect_blk, 1, &mut res)?;
+    take(inode.indirect_blk2, 2, &mut res)?;
+    take(inode.indirect_blk3, 3, &mut res)?;
+    Ok(res)
+}
+
+/// Dirent table of a directory straight from its blocks, with where each entry lives. Unallocated pieces are skipped.
+fn raw_dirents(inode: &PFSINode) -> Vec<(PhysAddr, PFSDEntry)> {
+    (0..inode.f_size / DENTRY_SIZE).filter_map(|idx| {
+        let offset = idx * DENTRY_SIZE;
+        let pa = ParchFS::blockno_2_pa(PFSBase::lookup_blockno(inode, offset)?) + offset % BLK_SIZE;
+        Some((pa, unsafe{pa.read_volatile()}))
+    }).collect()
+}
+
+/// Walk every directory reachable from root and validate its dirent table: size, names, no duplicates, entries
+/// point to allocated inodes of the recorded type, and `.`/`..` are there. Returns names pointing to each inode,
+/// `..` excluded, the way hard_link_count counts them.
+fn check_dirents(fs_inner: &ParchFSInner, repair: bool, report: &mut FsckReport) -> BTreeMap<INodeNo, u32> {
+    let root = fs_inner.root_inode();
+    // root's own name is the mount point
+    let mut links = BTreeMap::from([(root, 1)]);
+    let mut visited = BTreeSet::new();
+    // (dir, its parent, path for report)
+    let mut pending = vec![(root, root, String::from("/"))];
+    while let Some((dir_no, parent_no, path)) = pending.pop() {
+        if !visited.insert(dir_no) {
+            report.problem(format!("{} is reachable twice (inode {})", path, dir_no.0));
+            continue;
+        }
+        if !fs_inner.inode_allocated(dir_no) {
+            report.problem(format!("{} is free inode {}", path, dir_no.0));
+            continue;
+        }
+        let dir = raw_inode(dir_no);
+        if dir.f_size % DENTRY_SIZE != 0 {
+            report.problem(format!("{} dirent table size {} is not a multiple of {}", path, dir.f_size, DENTRY_SIZE));
+            continue;
+        }
+        if let Err(block_no) = inode_blocks(dir) {
+            report.problem(format!("{} points to bad block {}", path, block_no.0));
+            continue;
+        }
+        let mut names = BTreeSet::new();
+        let mut has_dot = false;
+        let mut has_dotdot = false;
+        for (idx, (pa, mut e)) in raw_dirents(dir).into_iter().enumerate() {
+            let inode_no = e.inode_no();
+            if inode_no == BAD_INODE {
+                continue;
+            }
+            let name = match e.name() {
+                Ok(name) => name,
+                Err(_) => {
+                    report.problem(format!("{} entry {} has a bad name", path, idx));
+                    if repair {
+                        unsafe{pa.write_volatile(&PFSDEntry::empty())};
+                        report.repaired += 1;
+                    }
+                    continue;
+                }
+            };
+            if !names.insert(name.clone()) {
+                report.problem(format!("{} has duplicated entry {}", path, name));
+            }
+            if !fs_inner.inode_allocated(inode_no) {
+                report.problem(format!("{}{} points to free inode {}", path, name, inode_no.0));
+                if repair {
+                    unsafe{pa.write_volatile(&PFSDEntry::empty())};
+                    dentry_invalidate(fs_inner.uuid(), dir_no.0, &name);
+                    report.repaired += 1;
+                }
+                continue;
+            }
+            let child = raw_inode(inode_no);
+            if child.f_type != e.pfs_type() {
+                report.problem(format!("{}{} recorded as {:?}, inode {} is {:?}", path, name, e.pfs_type(), inode_no.0, child.f_type));
+                if repair {
+                    e.set_pfs_type(child.f_type);
+                    unsafe{pa.write_volatile(&e)};
+                    report.repaired += 1;
+                }
+            }
+            if name != ".." {
+                *links.entry(inode_no).or_insert(0) += 1;
+            }
+            match name.as_str() {
+                "." => {
+                    has_dot = true;
+                    if inode_no != dir_no {
+                        report.problem(format!("{}. points to inode {} instead of {}", path, inode_no.0, dir_no.0));
+                    }
+                },
+                ".." => {
+                    has_dotdot = true;
+                    // root's parent depends on where it's mounted
+                    if dir_no != root && inode_no != parent_no {
+                        report.problem(format!("{}.. points to inode {} instead of {}", path, inode_no.0, parent_no.0));
+                    }
+                },
+                _ => {
+                    if child.f_type == PFSType::DIR {
+                        pending.push((inode_no, dir_no, format!("{}{}/", path, name)));
+                    }
+                }
+            }
+        }
+        if !has_dot || !has_dotdot {
+            report.problem(format!("{} is missing . or ..", path));
+        }
+    }
+    report.dirs = visited.len();
+    links
+}
+
+/// Full check: dirent tables, inode reachability and hard_link_count, block ownership against the fs page bitmap,
+/// superblock free counters and quota usage. Journal and quota blocks are owned by inode 0. With `repair`, bad entries are dropped, unreachable inodes and unowned blocks
+/// freed, and counts rewritten to what was found. Blocks claimed by two inodes are only reported.
+pub fn fsck(fs_inner: &mut MutexGuard<ParchFSInner>, repair: bool) -> FsckReport {
+    let mut report = FsckReport::default();
+    let links = check_dirents(fs_inner, repair, &mut report);
+
+    let inode_max = (fs_inner.superblock().inode_count as usize).min(INODE_LIST_SIZE / INODE_SIZE).min(INODE_BITMAP_SIZE);
+    // inode 0 is never handed out, but its bit is set
+    let mut inode_used = 1;
+    let mut owned: BTreeMap<BlockNo, INodeNo> = BTreeMap::new();
+    // uid -> (blocks, inodes)
+    let mut usage: BTreeMap<u32, (u64, u64)> = BTreeMap::new();
+    for no in 1..inode_max {
+        let inode_no = INodeNo::from(no);
+        if !fs_inner.inode_allocated(inode_no) {
+            continue;
+        }
+        let inode = raw_inode(inode_no);
+        let blocks = match inode_blocks(inode) {
+            Ok(blocks) => blocks,
+            Err(block_no) => {
+                report.problem(format!("inode {} points to bad block {}", no, block_no.0));
+                inode_used += 1;
+                usage.entry(inode.uid).or_default().1 += 1;
+                continue;
+            }
+        };
+        match links.get(&inode_no) {
+            // unlinked while open, freed on last close
+            None if fs_inner.inode_open(inode_no) => {},
+            None => {
+                report.problem(format!("inode {} is allocated but unreachable", no));
+                if repair {
+                    let lock = SpinMutex::new("fsck inode", inode);
+                    PFSBase::truncate_locked(0, fs_inner, &mut lock.acquire());
+                    fs_inner.free_inode(inode_no);
+                    report.repaired += 1;
+                    continue;
+                }
+            },
+            Some(&count) => {
+                if count != inode.hard_link_count {
+                    report.problem(format!("inode {} has {} link(s), hard_link_count says {}", no, count, inode.hard_link_count));
+                    if repair {
+                        inode.hard_link_count = count;
+                        report.repaired += 1;
+                    }
+                }
+            }
+        }
+        inode_used += 1;
+        let uid_usage = usage.entry(inode.uid).or_default();
+        uid_usage.0 += blocks.len() as u64;
+        uid_usage.1 += 1;
+        for block_no in blocks {
+            if let Some(other) = owned.insert(block_no, inode_no) {
+                report.problem(format!("block {} is shared by inode {} and {}", block_no.0, other.0, no));
+            }
+        }
+    }
+    for block_no in fs_inner.journal().blocks() {
+        if let Some(other) = owned.insert(block_no, BAD_INODE) {
+            report.problem(format!("journal block {} is also used by inode {}", block_no.0, other.0));
+        }
+    }
+    let quota_blk = fs_inner.quota().0.block();
+    if let Some(other) = owned.insert(quota_blk, BAD_INODE) {
+        report.problem(format!("quota block {} is also used by inode {}", quota_blk.0, other.0));
+    }
+    report.inodes = inode_used - 1;
+    report.blocks = owned.len();
+
+    let in_use: BTreeSet<PhysPageNum> = fs_pages_in_use().into_iter().collect();
+    for (&block_no, &inode_no) in owned.iter() {
+        let ppn = ParchFS::blockno_2_ppn(block_no);
+        if in_use.contains(&ppn) {
+            continue;
+        }
+        report.problem(format!("block {} of inode {} is not marked in use", block_no.0, inode_no.0));
+        if repair {
+            if mark_fs_page(ppn) {
+                report.repaired += 1;
+            } else {
+                report.problem(format!("block {} was already handed out as memory", block_no.0));
+            }
+        }
+    }
+    for ppn in in_use.into_iter().filter(|&ppn| !page_reserved(ppn)) {
+        let block_no = ParchFS::ppn_2_blockno(ppn);
+        if owned.contains_key(&block_no) {
+            continue;
+        }
+        report.problem(format!("block {} is marked in use but owned by no inode", block_no.0));
+        if repair {
+            free_fs_page(ppn);
+            report.repaired += 1;
+        }
+    }
+
+    let superblock = fs_inner.superblock();
+    let free_inode = (inode_max - inode_used) as u64;
+    if superblock.free_inode != free_inode {
+        report.problem(format!("superblock free_inode is {}, counted {}", superblock.free_inode, free_inode));
+        if repair {
+            superblock.free_inode = free_inode;
+            report.repaired += 1;
+        }
+    }
+    let free_block = superblock.block_count.saturating_sub(owned.len() as u64);
+    if superblock.free_block != free_block {
+        report.problem(format!("superblock free_block is {}, counted {}", superblock.free_block, free_block));
+        if repair {
+            superblock.free_block = free_block;
+            report.repaired += 1;
+        }
+    }
+    check_quota(fs_inner, usage, repair, &mut report);
+
+    milestone!("fsck: {} directories, {} inodes, {} blocks, {} problem(s), {} repaired", report.dirs, report.inodes, report.blocks, report.problems.len(), report.repaired);
+    report
+}
@@ -0,0 +1,331 @@
+//! Undo journal for ParchFS metadata.
+//!
+//! Metadata is updated in place, so before the first change to a piece of it, its old bytes are appended to the log.
+//! Block and inode allocations are logged so they can be released, and freed blocks are held back, as their content
+//! may still be needed for undo. Transactions nest and overlap: a generation runs from the first one opened until the
+//! last one is closed, and ending it commits it by emptying the log, since the image is consistent again at that point.
+//! Once the log is half full, new transactions wait for that, so the generation ends even under steady load, before
+//! the log overflows, and held back blocks are released.
+//! On mount, a non-empty log means a crash mid-generation, undoing it in reverse brings back the image from its start.
+
+use core::mem::size_of;
+
+use alloc::{collections::{BTreeMap, BTreeSet}, vec::Vec};
+
+use crate::{mem::{PhysAddr, alloc_fs_page, free_fs_page, unmark_fs_page}, utils::Mutex};
+
+use super::{BAD_BLOCK, BLK_SIZE, BlockNo, INodeNo, JOURNAL_BLOCKS, JOURNAL_DRAIN_BYTES, JOURNAL_MAGIC, PFSFeatures, SuperBlock, fs::ParchFS};
+
+/// target: pa, followed by the old bytes
+const RECORD_RAW: u32 = 1;
+/// target: first block of a run, len: run length
+const RECORD_ALLOC_BLK: u32 = 2;
+/// target: inode no
+const RECORD_ALLOC_INODE: u32 = 3;
+/// target: inode no
+const RECORD_FREE_INODE: u32 = 4;
+
+#[repr(C)]
+struct JournalHeader {
+    magic: u64,
+    /// bytes of records in the log, 0 when there's nothing to undo
+    len: u64,
+    /// log ran out mid-generation, undo is not possible any more
+    overflow: u64,
+    log_blk: [BlockNo; JOURNAL_BLOCKS],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    kind: u32,
+    len: u32,
+    target: u64,
+}
+
+pub struct Journal {
+    header: &'static mut JournalHeader,
+    depth: usize,
+    /// open transactions by pid, a process nesting one in its own must not wait for the generation to end
+    holders: BTreeMap<usize, usize>,
+    /// (pa, len) of regions already logged this generation, first copy is the one to restore.
+    /// Same pa with another len is logged again, undo goes backwards so the first copy still wins.
+    logged: BTreeSet<(usize, usize)>,
+    /// blocks allocated this generation, as (first, count) runs. Nothing to log when changing them.
+    fresh: Vec<(u32, u32)>,
+    /// log offset of the record for the last run, extended while allocations are contiguous
+    last_alloc: Option<usize>,
+    pending_free: Vec<BlockNo>,
+    /// crashed with an overflowed log, only fsck can bring the image back
+    pub needs_repair: bool,
+}
+
+impl Journal {
+    /// Attach to the journal area of the image, create it on first mount, and undo whatever a crash left in it.
+    /// Called before the inode bitmap is loaded, as undo may change it.
+    pub fn open(superblock: &mut SuperBlock, inode_bitmap: PhysAddr) -> Self {
+        if superblock.journal_blk == BAD_BLOCK.0 {
+            let header_blk = ParchFS::ppn_2_blockno(alloc_fs_page());
+            header_blk.clear_blk();
+            let header: &mut JournalHeader = unsafe{header_blk.to_pa().instantiate_volatile()};
+            for blk in header.log_blk.iter_mut() {
+                *blk = ParchFS::ppn_2_blockno(alloc_fs_page());
+            }
+            header.magic = JOURNAL_MAGIC;
+            superblock.free_block = superblock.free_block.saturating_sub(JOURNAL_BLOCKS as u64 + 1);
+            superblock.journal_blk = header_blk.0;
+            superblock.features |= PFSFeatures::JOURNAL.bits();
+            milestone!("ParchFS: journal created at block {}", header_blk.0);
+        }
+        let header: &'static mut JournalHeader = unsafe{BlockNo(superblock.journal_blk).to_pa().instantiate_volatile()};
+        assert!(header.magic == JOURNAL_MAGIC, "Bad journal magic");
+        let mut res = Self {
+            header,
+            depth: 0,
+            holders: BTreeMap::new(),
+            logged: BTreeSet::new(),
+            fresh: Vec::new(),
+            last_alloc: None,
+            pending_free: Vec::new(),
+            needs_repair: false,
+        };
+        res.recover(inode_bitmap);
+        res
+    }
+
+    /// Blocks of the journal area, header first. They belong to no inode.
+    pub fn blocks(&self) -> Vec<BlockNo> {
+        let mut res = vec![ParchFS::pa_2_blockno(PhysAddr::from(&*self.header as *const JournalHeader as usize))];
+        res.extend(self.header.log_blk.iter());
+        res
+    }
+
+    fn recover(&mut self, inode_bitmap: PhysAddr) {
+        if self.header.overflow != 0 {
+            warning!("ParchFS: unclean shutdown with journal overflowed, can't undo, full fsck needed.");
+            self.needs_repair = true;
+            self.reset();
+            return;
+        }
+        if self.header.len == 0 {
+            return;
+        }
+        warning!("ParchFS: unclean shutdown, undoing {} bytes of journal.", self.header.len);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < self.header.len as usize {
+            let record: Record = self.read_log(offset);
+            records.push((offset + size_of::<Record>(), record));
+            offset += size_of::<Record>() + Self::payload_size(&record);
+        }
+        for (payload, record) in records.into_iter().rev() {
+            match record.kind {
+                RECORD_RAW => {
+                    let target = PhysAddr::from(record.target as usize);
+                    for i in 0..record.len as usize {
+                        let byte: u8 = self.read_log(payload + i);
+                        unsafe{(target + i).write_volatile(&byte)};
+                    }
+                },
+                RECORD_ALLOC_BLK => {
+                    for blk in record.target..record.target + record.len as u64 {
+                        unmark_fs_page(ParchFS::blockno_2_ppn(BlockNo(blk as u32)));
+                    }
+                },
+                RECORD_ALLOC_INODE | RECORD_FREE_INODE => {
+                    let no = record.target as usize;
+                    let word = inode_bitmap + no / 64 * size_of::<u64>();
+                    let bits: u64 = unsafe{word.read_volatile()};
+                    let bits = if record.kind == RECORD_ALLOC_INODE {bits & !(1 << (no % 64))} else {bits | (1 << (no % 64))};
+                    unsafe{word.write_volatile(&bits)};
+                },
+                kind => {
+                    warning!("ParchFS: bad journal record kind {}, giving up undo.", kind);
+                    self.needs_repair = true;
+                    break;
+                }
+            }
+        }
+        self.reset();
+    }
+
+    fn payload_size(record: &Record) -> usize {
+        if record.kind == RECORD_RAW {
+            (record.len as usize + 7) / 8 * 8
+        } else {
+            0
+        }
+    }
+
+    fn log_pa(&self, offset: usize) -> PhysAddr {
+        self.header.log_blk[offset / BLK_SIZE].to_pa() + offset % BLK_SIZE
+    }
+
+    fn read_log<T: Copy>(&self, offset: usize) -> T {
+        let mut res = core::mem::MaybeUninit::<T>::uninit();
+        let dst = res.as_mut_ptr() as *mut u8;
+        for i in 0..size_of::<T>() {
+            unsafe{*dst.add(i) = self.log_pa(offset + i).read_volatile()};
+        }
+        unsafe{res.assume_init()}
+    }
+
+    fn write_log(&self, offset: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            unsafe{self.log_pa(offset + i).write_volatile(byte)};
+        }
+    }
+
+    /// Record and its payload are in place before len covers them, so a torn append is never undone.
+    fn append(&mut self, record: Record, payload: PhysAddr) -> Option<usize> {
+        if self.header.overflow != 0 {
+            return None;
+        }
+        let offset = self.header.len as usize;
+        let size = size_of::<Record>() + Self::payload_size(&record);
+        if offset + size > JOURNAL_BLOCKS * BLK_SIZE {
+            warning!("ParchFS: journal overflowed, rest of this generation is not protected.");
+            self.header.overflow = 1;
+            return None;
+        }
+        self.write_log(offset, unsafe{core::slice::from_raw_parts(&record as *const Record as *const u8, size_of::<Record>())});
+        if record.kind == RECORD_RAW {
+            let old = unsafe{payload.read_data(record.len as usize)};
+            self.write_log(offset + size_of::<Record>(), &old);
+        }
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+        self.header.len = (offset + size) as u64;
+        Some(offset)
+    }
+
+    /// `owner` is the pid of the caller, None in scheduler context.
+    pub fn begin(&mut self, owner: Option<usize>) {
+        self.depth += 1;
+        if let Some(pid) = owner {
+            *self.holders.entry(pid).or_insert(0) += 1;
+        }
+    }
+
+    /// Should a new transaction of `owner` wait for the generation to commit first? Not if it has one open already,
+    /// that would never end, nor in scheduler context, that can't sleep.
+    pub fn must_wait(&self, owner: Option<usize>) -> bool {
+        let full = self.header.len as usize >= JOURNAL_DRAIN_BYTES || self.header.overflow != 0;
+        match owner {
+            Some(pid) => self.depth != 0 && full && !self.holders.contains_key(&pid),
+            None => false,
+        }
+    }
+
+    /// Close a transaction. If it was the last one the generation is committed, returns blocks to free then.
+    pub fn end(&mut self, owner: Option<usize>) -> Option<Vec<BlockNo>> {
+        self.depth -= 1;
+        if let Some(pid) = owner {
+            let count = self.holders.get_mut(&pid).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.holders.remove(&pid);
+            }
+        }
+        if self.depth != 0 {
+            return None;
+        }
+        self.reset();
+        Some(core::mem::take(&mut self.pending_free))
+    }
+
+    fn reset(&mut self) {
+        self.header.len = 0;
+        self.header.overflow = 0;
+        self.logged.clear();
+        self.fresh.clear();
+        self.last_alloc = None;
+    }
+
+    /// Save old content of a piece of metadata before changing it. No-op outside a transaction.
+    pub fn log_raw(&mut self, pa: PhysAddr, len: usize) {
+        if self.depth == 0 || !self.logged.insert((pa.0, len)) {
+            return;
+        }
+        self.append(Record{kind: RECORD_RAW, len: len as u32, target: pa.0 as u64}, pa);
+    }
+
+    pub fn log<T>(&mut self, obj: &T) {
+        self.log_raw(PhysAddr::from(obj as *const T as usize), size_of::<T>());
+    }
+
+    /// Blocks allocated in this generation are dropped on undo anyway, no need to log their content.
+    fn fresh(&self, block_no: BlockNo) -> bool {
+        self.fresh.iter().any(|&(first, count)| (first..first + count).contains(&block_no.0))
+    }
+
+    /// Whole block, for index blocks.
+    pub fn log_block(&mut self, block_no: BlockNo) {
+        self.log_in_block(block_no, 0, BLK_SIZE);
+    }
+
+    /// Part of a block, for directory content.
+    pub fn log_in_block(&mut self, block_no: BlockNo, offset: usize, len: usize) {
+        if !self.fresh(block_no) {
+            self.log_raw(block_no.to_pa() + offset, len);
+        }
+    }
+
+    pub fn log_alloc_blk(&mut self, block_no: BlockNo) {
+        if self.depth == 0 {
+            return;
+        }
+        if let (Some(offset), Some((first, count))) = (self.last_alloc, self.fresh.last_mut()) {
+            if *first + *count == block_no.0 {
+                *count += 1;
+                // len is the second word of the record
+                self.write_log(offset + size_of::<u32>(), &count.to_ne_bytes());
+                return;
+            }
+        }
+        self.last_alloc = self.append(Record{kind: RECORD_ALLOC_BLK, len: 1, target: block_no.0 as u64}, PhysAddr::from(0));
+        if self.last_alloc.is_some() {
+            self.fresh.push((block_no.0, 1));
+        }
+    }
+
+    pub fn log_alloc_inode(&mut self, inode_no: INodeNo) {
+        if self.depth != 0 {
+            self.append(Record{kind: RECORD_ALLOC_INODE, len: 0, target: inode_no.0 as u64}, PhysAddr::from(0));
+        }
+    }
+
+    pub fn log_free_inode(&mut self, inode_no: INodeNo) {
+        if self.depth != 0 {
+            self.append(Record{kind: RECORD_FREE_INODE, len: 0, target: inode_no.0 as u64}, PhysAddr::from(0));
+        }
+    }
+
+    /// Hold a freed block until the generation ends. False outside a transaction, free it right away.
+    pub fn defer_free(&mut self, block_no: BlockNo) -> bool {
+        if self.depth == 0 {
+            return false;
+        }
+        self.pending_free.push(block_no);
+        true
+    }
+}
+
+/// Open transaction on a ParchFS, with the pid that opened it, see ParchFS::begin. Must not be dropped while holding
+/// the fs lock.
+pub struct Transaction(pub(super) alloc::sync::Arc<ParchFS>, pub(super) Option<usize>);
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        let mut fs_inner = self.0.inner.acquire();
+        let committed = fs_inner.journal().end(self.1);
+        drop(fs_inner);
+        if let Some(freed) = committed {
+            for block_no in freed {
+                free_fs_page(ParchFS::blockno_2_ppn(block_no));
+            }
+            self.0.committed.wake_all();
+        }
+        // opened before the fs lock, so none is held here
+        self.0.release_orphans();
+    }
+}
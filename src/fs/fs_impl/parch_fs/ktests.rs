@@ -0,0 +1,66 @@
+//! Self tests for ParchFS block mapping, names and readahead, see utils::ktest.
+
+use alloc::vec;
+
+use crate::utils::{ErrorNum, ktest::KTestResult};
+
+use super::{BAD_INODE, BLK_SIZE, BLOCKNO_PER_BLK, DENTRY_NAME_LEN, DIRECT_BLK_COUNT, PFS_MAXCAP, PFSBase, PFSDEntry, PFSPerm, PFSType, READAHEAD_MAX, READAHEAD_MIN, ReadAhead, BlockNo, check_name, name_eq};
+
+fn parch_fs_locate_boundaries() -> KTestResult {
+    const PER: usize = BLOCKNO_PER_BLK;
+    let lv1 = DIRECT_BLK_COUNT;
+    let lv2 = lv1 + PER;
+    let lv3 = lv2 + PER * PER;
+    kassert!(PFSBase::locate(0) == Some((0, 0)));
+    kassert!(PFSBase::locate(lv1 - 1) == Some((0, lv1 - 1)));
+    kassert!(PFSBase::locate(lv1) == Some((1, 0)));
+    kassert!(PFSBase::locate(lv2 - 1) == Some((1, PER - 1)));
+    kassert!(PFSBase::locate(lv2) == Some((2, 0)));
+    kassert!(PFSBase::locate(lv3 - 1) == Some((2, PER * PER - 1)));
+    kassert!(PFSBase::locate(lv3) == Some((3, 0)));
+    kassert!(PFSBase::locate(PFS_MAXCAP / BLK_SIZE - 1) == Some((3, PER * PER * PER - 1)));
+    kassert!(PFSBase::locate(PFS_MAXCAP / BLK_SIZE) == None);
+    Ok(())
+}
+ktest!(parch_fs_locate_boundaries, parch_fs_locate_boundaries);
+
+fn parch_fs_name_policy() -> KTestResult {
+    kassert!(check_name("hello.txt") == Ok(()));
+    kassert!(check_name("") == Err(ErrorNum::EINVAL));
+    kassert!(check_name("a/b") == Err(ErrorNum::EINVAL));
+    kassert!(check_name("a\0b") == Err(ErrorNum::EINVAL));
+    kassert!(check_name(&"x".repeat(DENTRY_NAME_LEN + 1)) == Err(ErrorNum::ENAMETOOLONG));
+    kassert!(name_eq("Foo", "fOO", true));
+    kassert!(!name_eq("Foo", "fOO", false));
+
+    // invalid UTF-8 on disk is an error, not a panic
+    let bad = PFSDEntry::new(BAD_INODE, PFSPerm::empty(), PFSType::REGULAR, &[b'a', 0xff]);
+    kassert!(bad.name() == Err(ErrorNum::EINVAL));
+    kassert!(PFSDEntry::new(BAD_INODE, PFSPerm::empty(), PFSType::REGULAR, b"ok").name().as_deref() == Ok("ok"));
+    Ok(())
+}
+ktest!(parch_fs_name_policy, parch_fs_name_policy);
+
+fn parch_fs_readahead_window() -> KTestResult {
+    let mut ra = ReadAhead::new();
+    kassert!(ra.access(0));
+    kassert!(ra.window() == READAHEAD_MIN);
+    ra.fill(0, vec![Some(BlockNo(5)), None], 0);
+    ra.done(100);
+    kassert!(ra.access(100));
+    kassert!(ra.window() == READAHEAD_MIN * 2);
+    kassert!(ra.lookup(100, BLK_SIZE + 1, 0) == Some(&[Some(BlockNo(5)), None][..]));
+    // past what was kept, or the block map changed since
+    kassert!(ra.lookup(100, 2 * BLK_SIZE + 1, 0).is_none());
+    kassert!(ra.lookup(100, 200, 1).is_none());
+    for _ in 0..16 {
+        ra.access(100);
+    }
+    kassert!(ra.window() == READAHEAD_MAX);
+    // random read drops it all
+    kassert!(!ra.access(7 * BLK_SIZE));
+    kassert!(ra.window() == 0);
+    kassert!(ra.lookup(100, 200, 0).is_none());
+    Ok(())
+}
+ktest!(parch_fs_readahead_window, parch_fs_readahead_window);
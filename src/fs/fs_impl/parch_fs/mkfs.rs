@@ -0,0 +1,72 @@
+//! Format a blank ParchFS image in place, so a fresh machine boots without an image built beforehand.
+//!
+//! The page allocator already formatted the page bitmaps when it found no magic, so only the superblock, the inode
+//! bitmap and the root directory are left. The journal is created by the first mount, like on older images.
+
+use core::mem::size_of;
+
+use crate::{mem::{PhysAddr, alloc_fs_page}, utils::time::get_real_time_epoch};
+
+use super::{BAD_BLOCK, BLK_SIZE, DIRECT_BLK_COUNT, INODE_BITMAP_SIZE, INODE_LIST_SIZE, INODE_SIZE, PFS_MAGIC, PFS_VERSION, ROOT_INODE, fs::ParchFS, PFSDEntry, PFSFeatures, PFSINode, PFSPerm, PFSType, SuperBlock};
+
+pub fn mkfs(superblock: &mut SuperBlock, inode_bitmap: PhysAddr) {
+    extern "C" {
+        fn ekernel();
+        fn INODE_LIST_ADDRESS();
+    }
+    warning!("ParchFS: no valid image, formatting.");
+
+    // inode 0 is never handed out, but its bit is set
+    unsafe{core::ptr::write_bytes(inode_bitmap.0 as *mut u8, 0, INODE_BITMAP_SIZE / 8)};
+    let bits: u64 = 1 << 0 | 1 << ROOT_INODE.0;
+    unsafe{inode_bitmap.write_volatile(&bits)};
+
+    let root_blk = ParchFS::ppn_2_blockno(alloc_fs_page());
+    root_blk.clear_blk();
+    let perm = PFSPerm::from_bits_truncate(0o755);
+    let dirents = [
+        PFSDEntry::new(ROOT_INODE, perm, PFSType::DIR, b"."),
+        PFSDEntry::new(ROOT_INODE, perm, PFSType::DIR, b".."),
+    ];
+    unsafe{root_blk.to_pa().write_volatile(&dirents)};
+
+    let root: &mut PFSINode = unsafe{ParchFS::inodeno_2_pa(ROOT_INODE).instantiate_volatile()};
+    *root = PFSINode {
+        permission: perm,
+        f_type: PFSType::DIR,
+        uid: 0,
+        gid: 0,
+        flags: 0,
+        // "." and the one the mount point stands for
+        hard_link_count: 2,
+        direct_blk_no: [BAD_BLOCK; DIRECT_BLK_COUNT],
+        indirect_blk: BAD_BLOCK,
+        indirect_blk2: BAD_BLOCK,
+        indirect_blk3: BAD_BLOCK,
+        f_size: size_of::<[PFSDEntry; 2]>(),
+        access_time: get_real_time_epoch(),
+        change_time: get_real_time_epoch(),
+        create_time: get_real_time_epoch(),
+        mount_info: [0; 16],
+        reserved: [0; 112],
+    };
+    root.direct_blk_no[0] = root_blk;
+
+    let inode_count = (INODE_LIST_SIZE / INODE_SIZE).min(INODE_BITMAP_SIZE) as u64;
+    let block_count = ((INODE_LIST_ADDRESS as usize - ekernel as usize) / BLK_SIZE) as u64;
+    unsafe{core::ptr::write_bytes(superblock as *mut SuperBlock as *mut u8, 0, size_of::<SuperBlock>())};
+    superblock.inode_count = inode_count;
+    superblock.block_count = block_count;
+    superblock.free_inode = inode_count - 2;
+    superblock.free_block = block_count - 1;
+    superblock.last_access = get_real_time_epoch() as u64;
+    superblock.root_inode = ROOT_INODE.0;
+    superblock.journal_blk = BAD_BLOCK.0;
+    superblock.version = PFS_VERSION;
+    // bits go on as their structures are first written, journal and quota right on mount
+    superblock.features = PFSFeatures::empty().bits();
+    // last, a crash before this just formats again
+    core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+    superblock.magic = PFS_MAGIC;
+    milestone!("ParchFS: formatted, {} inodes, {} blocks.", inode_count, block_count);
+}
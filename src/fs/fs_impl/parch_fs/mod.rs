@@ -2,23 +2,67 @@ mod types;
 mod fs;
 mod config;
 mod base;
+mod fsck;
+mod journal;
+mod mkfs;
+mod quota;
+mod readahead;
+mod write_behind;
+mod ktests;
 
 pub use config::*;
 pub use types::*;
 
 use lazy_static::*;
 
-use crate::fs::Path;
+use alloc::string::{String, ToString};
+
+use crate::{fs::Path, utils::{Mutex, ErrorNum, bootargs}};
 
 use self::fs::ParchFS;
+pub use self::fs::{parch_fs_present, parch_fs_refused, parch_fs_mountable};
 
 lazy_static!{
     pub static ref PARCH_FS: alloc::sync::Arc<ParchFS> = {
         let root_path: Path = "/".into();
         let res = alloc::sync::Arc::new(ParchFS::new(root_path.clone()));
+        let repair = bootargs::get("fsck").as_deref() == Some("repair") || res.inner.acquire().journal().needs_repair;
+        let report = fsck::fsck(&mut res.inner.acquire(), repair);
+        if !report.problems.is_empty() {
+            warning!("ParchFS: {} problem(s) found on mount, {} repaired, see above.", report.problems.len(), report.repaired);
+        }
         milestone!("ParchFS initialized on {:?}", root_path);
         res
     };
 }
 
-pub use base::PFSBase;
\ No newline at end of file
+pub use base::PFSBase;
+pub use quota::{QuotaEntry, QuotaLimits};
+pub use readahead::ReadAhead;
+pub use write_behind::{writeback_daemon, get_writeback_interval, set_writeback_interval, get_dirty_threshold, set_dirty_threshold};
+
+/// Check the mounted ParchFS without repairing, for /proc/fsck.
+pub fn parch_fs_check() -> String {
+    if !parch_fs_mountable() {
+        return "ParchFS not present\n".to_string();
+    }
+    fsck::fsck(&mut PARCH_FS.inner.acquire(), false).to_string()
+}
+
+/// Usage and limits of `uid` on the mounted ParchFS.
+pub fn parch_fs_get_quota(uid: u32) -> Result<QuotaEntry, ErrorNum> {
+    if !parch_fs_mountable() {
+        return Err(ErrorNum::ENODEV);
+    }
+    Ok(PARCH_FS.inner.acquire().quota().0.get(uid))
+}
+
+pub fn parch_fs_set_quota(uid: u32, limits: QuotaLimits) -> Result<(), ErrorNum> {
+    if !parch_fs_mountable() {
+        return Err(ErrorNum::ENODEV);
+    }
+    let _txn = PARCH_FS.clone().begin();
+    let mut fs_inner = PARCH_FS.inner.acquire();
+    let (quota, journal) = fs_inner.quota();
+    quota.set_limits(uid, limits, journal)
+}
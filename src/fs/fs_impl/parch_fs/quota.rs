@@ -0,0 +1,171 @@
+//! Per uid block and inode usage, with soft and hard limits.
+//!
+//! The table lives in one block, pointed to by the superblock, and is changed under the journal like other metadata.
+//! Blocks are charged to the owner of the file, inodes to whoever creates them. Going over a hard limit fails with
+//! EDQUOT, a soft limit only warns, there's no grace period. Usage is recounted by fsck.
+
+use core::mem::size_of;
+
+use crate::{mem::alloc_fs_page, process::get_processor, utils::ErrorNum};
+
+use super::{BAD_BLOCK, BLK_SIZE, BlockNo, PFSFeatures, SuperBlock, fs::ParchFS, journal::Journal};
+
+pub const QUOTA_ENTRIES: usize = BLK_SIZE / size_of::<QuotaEntry>();
+
+/// 0 for no limit.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct QuotaLimits {
+    pub soft_blocks : u64,
+    pub hard_blocks : u64,
+    pub soft_inodes : u64,
+    pub hard_inodes : u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct QuotaEntry {
+    pub uid     : u32,
+    pub in_use  : u32,
+    pub blocks  : u64,
+    pub inodes  : u64,
+    pub limits  : QuotaLimits,
+}
+
+/// Who new files belong to: the effective ids of the current process, root in kernel context.
+/// Takes the pcb lock, so only on create. Blocks go to the file's owner, as they're also allocated on writeback and
+/// page faults with the pcb locked.
+pub fn current_owner() -> (u32, u32) {
+    get_processor().current().map_or((0, 0), |proc| {
+        let cred = proc.get_inner().cred;
+        (cred.euid, cred.egid)
+    })
+}
+
+pub struct Quota {
+    table: &'static mut [QuotaEntry; QUOTA_ENTRIES],
+    /// table was just created on an image that has files already, usage needs a recount
+    pub fresh: bool,
+}
+
+impl Quota {
+    /// Attach to the quota table of the image, create it on first mount.
+    pub fn open(superblock: &mut SuperBlock) -> Self {
+        let mut fresh = false;
+        if superblock.quota_blk == BAD_BLOCK.0 {
+            let blk = ParchFS::ppn_2_blockno(alloc_fs_page());
+            blk.clear_blk();
+            superblock.free_block = superblock.free_block.saturating_sub(1);
+            superblock.quota_blk = blk.0;
+            superblock.features |= PFSFeatures::QUOTA.bits();
+            fresh = true;
+            milestone!("ParchFS: quota table created at block {}", blk.0);
+        }
+        Self {
+            table: unsafe{BlockNo(superblock.quota_blk).to_pa().instantiate_volatile()},
+            fresh,
+        }
+    }
+
+    pub fn block(&self) -> BlockNo {
+        ParchFS::pa_2_blockno(crate::mem::PhysAddr::from(self.table.as_ptr() as usize))
+    }
+
+    fn find(&self, uid: u32) -> Option<usize> {
+        self.table.iter().position(|e| e.in_use != 0 && e.uid == uid)
+    }
+
+    /// Entry of `uid`, a free slot is taken if it has none. None if the table is full.
+    fn entry(&mut self, uid: u32, journal: &mut Journal) -> Option<&mut QuotaEntry> {
+        let idx = match self.find(uid) {
+            Some(idx) => idx,
+            None => {
+                let idx = self.table.iter().position(|e| e.in_use == 0)?;
+                journal.log(&self.table[idx]);
+                self.table[idx] = QuotaEntry{uid, in_use: 1, ..Default::default()};
+                idx
+            }
+        };
+        journal.log(&self.table[idx]);
+        Some(&mut self.table[idx])
+    }
+
+    fn charge(&mut self, uid: u32, inode: bool, journal: &mut Journal) -> Result<(), ErrorNum> {
+        let entry = match self.entry(uid, journal) {
+            Some(entry) => entry,
+            None => {
+                warning!("ParchFS: quota table full, uid {} not accounted.", uid);
+                return Ok(());
+            }
+        };
+        let (used, soft, hard) = if inode {
+            (&mut entry.inodes, entry.limits.soft_inodes, entry.limits.hard_inodes)
+        } else {
+            (&mut entry.blocks, entry.limits.soft_blocks, entry.limits.hard_blocks)
+        };
+        if hard != 0 && *used >= hard {
+            return Err(ErrorNum::EDQUOT);
+        }
+        if soft != 0 && *used == soft {
+            warning!("ParchFS: uid {} over soft {} quota of {}", uid, if inode {"inode"} else {"block"}, soft);
+        }
+        *used += 1;
+        Ok(())
+    }
+
+    fn release(&mut self, uid: u32, inode: bool, journal: &mut Journal) {
+        if let Some(idx) = self.find(uid) {
+            journal.log(&self.table[idx]);
+            let entry = &mut self.table[idx];
+            let used = if inode {&mut entry.inodes} else {&mut entry.blocks};
+            *used = used.saturating_sub(1);
+        }
+    }
+
+    pub fn charge_block(&mut self, uid: u32, journal: &mut Journal) -> Result<(), ErrorNum> {
+        self.charge(uid, false, journal)
+    }
+
+    pub fn charge_inode(&mut self, uid: u32, journal: &mut Journal) -> Result<(), ErrorNum> {
+        self.charge(uid, true, journal)
+    }
+
+    pub fn release_block(&mut self, uid: u32, journal: &mut Journal) {
+        self.release(uid, false, journal)
+    }
+
+    pub fn release_inode(&mut self, uid: u32, journal: &mut Journal) {
+        self.release(uid, true, journal)
+    }
+
+    /// Usage and limits of `uid`, all zero if it never had any.
+    pub fn get(&self, uid: u32) -> QuotaEntry {
+        match self.find(uid) {
+            Some(idx) => self.table[idx],
+            None => QuotaEntry{uid, ..Default::default()},
+        }
+    }
+
+    pub fn set_limits(&mut self, uid: u32, limits: QuotaLimits, journal: &mut Journal) -> Result<(), ErrorNum> {
+        let entry = self.entry(uid, journal).ok_or(ErrorNum::EUSERS)?;
+        entry.limits = limits;
+        Ok(())
+    }
+
+    /// Entries in use, for fsck.
+    pub fn entries(&self) -> impl Iterator<Item = &QuotaEntry> {
+        self.table.iter().filter(|e| e.in_use != 0)
+    }
+
+    /// Overwrite usage of `uid` with a recount. False if the table is full.
+    pub fn set_usage(&mut self, uid: u32, blocks: u64, inodes: u64, journal: &mut Journal) -> bool {
+        match self.entry(uid, journal) {
+            Some(entry) => {
+                entry.blocks = blocks;
+                entry.inodes = inodes;
+                true
+            },
+            None => false
+        }
+    }
+}
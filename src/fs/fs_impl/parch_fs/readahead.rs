@@ -0,0 +1,69 @@
+//! Readahead for sequential reads of an open file.
+//!
+//! ParchFS blocks are memory, there's no device to fetch them from ahead of time. What a streaming read does pay
+//! for is an index walk, up to three levels deep, for every block. Once reads of a file look sequential, the
+//! mapping of the blocks after the one being read is looked up in the same pass and kept with the file, and later
+//! reads that stay inside it skip the walk. The window starts at READAHEAD_MIN blocks and doubles on every sequential
+//! read up to READAHEAD_MAX; a read anywhere else drops it. What's kept is thrown away once the block map of the
+//! inode changes, see PFSINodeHandle::map_gen.
+
+use alloc::vec::Vec;
+
+use super::{BLK_SIZE, BlockNo, READAHEAD_MAX, READAHEAD_MIN};
+
+pub struct ReadAhead {
+    /// where the next read starts if it's sequential
+    next: usize,
+    /// in blocks, 0 while reads look random
+    window: usize,
+    /// block index of blocks[0]
+    start: usize,
+    /// None for a hole
+    blocks: Vec<Option<BlockNo>>,
+    /// map_gen of the inode when blocks was filled
+    map_gen: usize,
+}
+
+impl ReadAhead {
+    pub fn new() -> Self {
+        Self { next: 0, window: 0, start: 0, blocks: Vec::new(), map_gen: 0 }
+    }
+
+    /// A read at `offset` is about to happen, true if it follows the last one.
+    pub fn access(&mut self, offset: usize) -> bool {
+        if offset == self.next {
+            self.window = (self.window * 2).clamp(READAHEAD_MIN, READAHEAD_MAX);
+            true
+        } else {
+            self.window = 0;
+            self.blocks.clear();
+            false
+        }
+    }
+
+    /// The read ended at `end`.
+    pub fn done(&mut self, end: usize) {
+        self.next = end;
+    }
+
+    /// Blocks to map after the ones being read.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Mapping of every block of [offset, end), if all of it is kept and still good.
+    pub fn lookup(&self, offset: usize, end: usize, map_gen: usize) -> Option<&[Option<BlockNo>]> {
+        if map_gen != self.map_gen || offset < self.start * BLK_SIZE || end > (self.start + self.blocks.len()) * BLK_SIZE {
+            return None;
+        }
+        let first = offset / BLK_SIZE - self.start;
+        let last = (end - 1) / BLK_SIZE - self.start;
+        Some(&self.blocks[first..=last])
+    }
+
+    pub fn fill(&mut self, start: usize, blocks: Vec<Option<BlockNo>>, map_gen: usize) {
+        self.start = start;
+        self.blocks = blocks;
+        self.map_gen = map_gen;
+    }
+}
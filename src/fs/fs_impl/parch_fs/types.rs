@@ -1,7 +1,8 @@
-use crate::{mem::{PhysAddr}, utils::{SpinMutex, Mutex, ErrorNum, time::get_real_time_epoch}, fs::{RegularFile, File, BlockFile, DirFile, OpenMode, types::{FileType, Permission, Dirent}, Cursor, LinkFile}};
-use super::{DIRECT_BLK_COUNT, INODE_SIZE, DENTRY_NAME_LEN, DENTRY_SIZE, fs::{ParchFS}, PFSBase, BAD_BLOCK, BAD_INODE};
+use crate::{mem::{PhysAddr}, process::process_list, utils::{SpinMutex, Mutex, ErrorNum, UUID, time::get_real_time_epoch}, fs::{RegularFile, SeekWhence, File, BlockFile, DirFile, OpenMode, types::{FileType, Permission, Dirent}, Cursor, LinkFile, IN_MODIFY, IN_CREATE, IN_DELETE, dentry_invalidate}};
+use super::{DIRECT_BLK_COUNT, BLK_SIZE, INODE_SIZE, DENTRY_NAME_LEN, DENTRY_SIZE, SUPERBLOCK_SIZE, fs::{ParchFS, PFSINodeHandle, UnmapGuard}, quota::current_owner, PFSBase, ReadAhead, BAD_BLOCK, BAD_INODE, WRITE_BEHIND_MAX, DIR_COOKIE_GEN_BITS};
 
 use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::slice::from_raw_parts;
 use bitflags::*;
 use core::fmt::Debug;
@@ -72,6 +73,25 @@ bitflags! {
         const OTHER_R = 0o004;
         const OTHER_W = 0o002;
         const OTHER_X = 0o001;
+        const SETUID  = 0o4000;
+        const SETGID  = 0o2000;
+    }
+}
+
+bitflags! {
+    /// What the image may contain, so a kernel that doesn't know about it won't mount and break it.
+    #[repr(C)]
+    pub struct PFSFeatures: u32 {
+        /// superblock.journal_blk is valid
+        const JOURNAL   = 1 << 0;
+        /// inode.indirect_blk3 is in use
+        const INDIRECT3 = 1 << 1;
+        /// files may have unallocated blocks before EOF
+        const SPARSE    = 1 << 2;
+        /// superblock.quota_blk is valid
+        const QUOTA     = 1 << 3;
+        /// names are looked up case-insensitively, a directory may not hold two that differ only in case
+        const CASEFOLD  = 1 << 4;
     }
 }
 
@@ -127,6 +147,7 @@ pub struct PFSINode {
     pub direct_blk_no       : [BlockNo; DIRECT_BLK_COUNT],
     pub indirect_blk        : BlockNo,
     pub indirect_blk2       : BlockNo,
+    pub indirect_blk3       : BlockNo,
     pub f_size              : usize,
     pub access_time         : usize,
     pub change_time         : usize,
@@ -149,10 +170,67 @@ pub struct PFSDEntry {
 
 assert_eq_size!(PFSDEntry, [u8; DENTRY_SIZE]);
 
+/// Names are non-empty UTF-8 without '/' or NUL, up to DENTRY_NAME_LEN bytes.
+pub fn check_name(name: &str) -> Result<(), ErrorNum> {
+    if name.len() > DENTRY_NAME_LEN {
+        return Err(ErrorNum::ENAMETOOLONG);
+    }
+    if name.is_empty() || name.contains('/') || name.contains('\0') {
+        return Err(ErrorNum::EINVAL);
+    }
+    Ok(())
+}
+
+/// Compare names for lookup, case folded on a case-insensitive mount.
+pub fn name_eq(a: &str, b: &str, case_fold: bool) -> bool {
+    if case_fold {
+        a.to_lowercase() == b.to_lowercase()
+    } else {
+        a == b
+    }
+}
+
 impl PFSDEntry {
-    pub fn name(&self) -> String {
-        let res = String::from_utf8(self.f_name[0..(self.name_len as usize)].to_vec()).unwrap();
-        res.chars().filter(|&x| x != '\0').collect()
+    /// EINVAL if what's on disk is not a valid name.
+    pub fn name(&self) -> Result<String, ErrorNum> {
+        let name = core::str::from_utf8(self.raw_name()).map_err(|_| ErrorNum::EINVAL)?;
+        check_name(name)?;
+        Ok(String::from(name))
+    }
+
+    /// For paths and messages, never fails.
+    pub fn display_name(&self) -> String {
+        String::from_utf8_lossy(self.raw_name()).into_owned()
+    }
+
+    pub fn inode_no(&self) -> INodeNo {
+        self.inode
+    }
+
+    pub fn pfs_type(&self) -> PFSType {
+        self.f_type
+    }
+
+    pub fn set_pfs_type(&mut self, f_type: PFSType) {
+        self.f_type = f_type;
+    }
+
+    /// Name bytes as on disk, name_len clamped so a corrupted entry can't overrun.
+    pub fn raw_name(&self) -> &[u8] {
+        &self.f_name[0..(self.name_len as usize).min(DENTRY_NAME_LEN)]
+    }
+
+    /// `name` must fit in DENTRY_NAME_LEN.
+    pub fn new(inode: INodeNo, permission: PFSPerm, f_type: PFSType, name: &[u8]) -> Self {
+        let mut f_name = [0; DENTRY_NAME_LEN];
+        f_name[0..name.len()].copy_from_slice(name);
+        Self {
+            inode,
+            permission,
+            f_type,
+            name_len: name.len() as u16,
+            f_name,
+        }
     }
 
     pub fn empty() -> Self {
@@ -166,14 +244,16 @@ impl PFSDEntry {
     }
 }
 
-impl Into<Dirent> for PFSDEntry {
-    fn into(self) -> Dirent {
-        Dirent { 
-            inode: self.inode.0, 
-            permission: self.permission.into(), 
-            f_type: self.f_type.into(), 
-            f_name: self.name()
-        }
+impl TryInto<Dirent> for PFSDEntry {
+    type Error = ErrorNum;
+
+    fn try_into(self) -> Result<Dirent, ErrorNum> {
+        Ok(Dirent {
+            inode: self.inode.0,
+            permission: self.permission.into(),
+            f_type: self.f_type.into(),
+            f_name: self.name()?
+        })
     }
 }
 
@@ -188,12 +268,23 @@ pub struct SuperBlock {
     pub free_block          : u64,
     pub last_access         : u64,
     pub root_inode          : u32,
-    pub reserved            : [u8; 3788]
+    /// header block of the journal, BAD_BLOCK until first mount creates it
+    pub journal_blk         : u32,
+    /// on-disk format version, 0 for images made before there's one
+    pub version             : u32,
+    /// PFSFeatures bits
+    pub features            : u32,
+    /// quota table block, BAD_BLOCK until first mount creates it
+    pub quota_blk           : u32,
+    pub reserved            : [u8; 3772]
 }
 
+assert_eq_size!(SuperBlock, [u8; SUPERBLOCK_SIZE]);
+
 pub struct PFSRegularInner {
     pub base: PFSBase,
     pub cursor: Cursor,
+    pub readahead: ReadAhead,
 }
 
 pub struct PFSRegular(SpinMutex<PFSRegularInner>);
@@ -205,9 +296,49 @@ impl Debug for PFSRegular {
     }
 }
 
+impl PFSRegular {
+    fn inode_key(&self) -> Result<(Arc<PFSINodeHandle>, (UUID, u32)), ErrorNum> {
+        let inner = self.0.acquire();
+        let uuid = inner.base.fs.upgrade().ok_or(ErrorNum::ENOENT)?.uuid;
+        Ok((inner.base.inode.clone(), (uuid, inner.base.inode_no.0)))
+    }
+
+    /// Blocks wholly inside [from, to) are about to be freed, drop every shared mapping of them first. Faults on
+    /// them back off until the returned guard drops, so hold it until the blocks are gone.
+    fn unmap_shared(&self, from: usize, to: usize) -> Result<UnmapGuard, ErrorNum> {
+        let (inode, key) = self.inode_key()?;
+        let guard = inode.begin_unmap();
+        let from = from.saturating_add(BLK_SIZE - 1) / BLK_SIZE * BLK_SIZE;
+        let to = to / BLK_SIZE * BLK_SIZE;
+        if from < to {
+            // no lock held here, a fault takes the pcb lock before the file lock
+            for proc in process_list() {
+                proc.get_inner().mem_layout.unmap_file(key, from, to, false);
+            }
+        }
+        Ok(guard)
+    }
+
+    /// After a write, which may have filled holes mapped to the zero page. Those fault again and find the data.
+    fn unmap_holes(&self) {
+        let (inode, key) = match self.inode_key() {
+            Ok(res) => res,
+            Err(_) => return,
+        };
+        if inode.hole_mapped() {
+            for proc in process_list() {
+                proc.get_inner().mem_layout.unmap_file(key, 0, usize::MAX, true);
+            }
+        }
+    }
+}
+
 impl Drop for PFSRegular {
     fn drop(&mut self) {
-        // do nothing
+        let inner = self.0.acquire();
+        if let Err(e) = inner.base.flush() {
+            warning!("ParchFS: write-behind of {:?} failed with {:?} on close, data lost.", inner.base.path, e);
+        }
     }
 }
 
@@ -215,18 +346,58 @@ impl File for PFSRegular {
     fn write(&self, data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
         let mut inner = self.0.acquire();
         let len = data.len();
-        inner.base.write(data, inner.cursor)?;
+        inner.base.write_behind(&data, inner.cursor)?;
         inner.cursor.0 += len;
+        inner.base.notify(IN_MODIFY, None);
+        drop(inner);
+        self.unmap_holes();
         Ok(len)
     }
 
     fn read(&self, length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
-        let mut inner = self.0.acquire();
-        let res = inner.base.read(length, inner.cursor)?;
+        let mut guard = self.0.acquire();
+        let inner = &mut *guard;
+        let res = inner.base.read(length, inner.cursor, Some(&mut inner.readahead))?;
         inner.cursor.0 += res.len();
         Ok(res)
     }
 
+    fn write_user(&self, buf: &crate::mem::UserBuffer) -> Result<usize, ErrorNum> {
+        let mut inner = self.0.acquire();
+        if buf.len() < WRITE_BEHIND_MAX {
+            inner.base.write_behind(&buf.to_vec(), inner.cursor)?;
+        } else {
+            inner.base.flush()?;
+            inner.base.write_user(buf, inner.cursor)?;
+        }
+        inner.cursor.0 += buf.len();
+        inner.base.notify(IN_MODIFY, None);
+        drop(inner);
+        self.unmap_holes();
+        Ok(buf.len())
+    }
+
+    fn read_user(&self, buf: &mut crate::mem::UserBuffer) -> Result<usize, ErrorNum> {
+        let mut guard = self.0.acquire();
+        let inner = &mut *guard;
+        let len = inner.base.read_user(buf, inner.cursor, Some(&mut inner.readahead))?;
+        inner.cursor.0 += len;
+        Ok(len)
+    }
+
+    /// all extents in one go, under one inode lock.
+    fn writev(&self, bufs: &[crate::mem::UserBuffer]) -> Result<usize, ErrorNum> {
+        self.write_user(&crate::mem::UserBuffer::join(bufs))
+    }
+
+    fn readv(&self, bufs: &mut [crate::mem::UserBuffer]) -> Result<usize, ErrorNum> {
+        self.read_user(&mut crate::mem::UserBuffer::join(bufs))
+    }
+
+    fn sync(&self) -> Result<(), ErrorNum> {
+        self.0.acquire().base.sync()
+    }
+
     fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile   + 'a>, crate::utils::ErrorNum> where Self: 'a {
         Err(ErrorNum::EBADTYPE)
     }
@@ -270,25 +441,109 @@ impl File for PFSRegular {
     fn stat(&self) -> Result<crate::fs::types::FileStat, ErrorNum> {
         self.0.acquire().base.stat()
     }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        self.0.acquire().base.owner()
+    }
+
+    fn reopen(&self, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        let (inode_no, path, fs) = {
+            let inner = self.0.acquire();
+            (inner.base.inode_no, inner.base.path.clone(), inner.base.fs.clone())
+        };
+        let base = PFSBase::new(inode_no, path, mode, fs)?;
+        Ok(Arc::new(PFSRegular(SpinMutex::new("PFSFile lock", PFSRegularInner{base, cursor: Cursor(0), readahead: ReadAhead::new()}))))
+    }
 }
 
 impl RegularFile for PFSRegular {
+    fn read_at(&self, offset: usize, length: usize) -> Result<alloc::vec::Vec<u8>, ErrorNum> {
+        self.0.acquire().base.read(length, Cursor(offset), None)
+    }
+
+    fn write_at(&self, offset: usize, data: alloc::vec::Vec<u8>) -> Result<usize, ErrorNum> {
+        let inner = self.0.acquire();
+        inner.base.write_behind(&data, Cursor(offset))?;
+        inner.base.notify(IN_MODIFY, None);
+        drop(inner);
+        self.unmap_holes();
+        Ok(data.len())
+    }
+
     fn copy_page(&self, offset: usize) -> Result<crate::mem::PageGuard, crate::utils::ErrorNum> {
         self.0.acquire().base.copy_page(offset)
     }
 
-    fn get_page(&self, offset: usize) -> Result<crate::mem::PageGuard, crate::utils::ErrorNum> {
-        self.0.acquire().base.get_page(offset)
+    fn get_page(&self, offset: usize, fill: bool) -> Result<Option<crate::mem::PageGuard>, crate::utils::ErrorNum> {
+        self.0.acquire().base.get_page(offset, fill)
     }
 
-    fn seek(&self, mut offset: usize) -> Result<usize, ErrorNum> {
+    fn seek(&self, offset: isize, whence: SeekWhence) -> Result<usize, ErrorNum> {
         let mut inner = self.0.acquire();
-        let len = inner.base.stat().unwrap().file_size;
-        if offset > len {
-            offset = len;
+        let len = inner.base.stat()?.file_size;
+        let pos = match whence {
+            SeekWhence::Data => inner.base.next_data(SeekWhence::Set.resolve(offset, 0, len)?)?,
+            SeekWhence::Hole => inner.base.next_hole(SeekWhence::Set.resolve(offset, 0, len)?)?,
+            _ => whence.resolve(offset, inner.cursor.0, len)?,
+        };
+        inner.cursor.0 = pos;
+        Ok(pos)
+    }
+
+    fn next_data(&self, offset: usize) -> Result<usize, ErrorNum> {
+        self.0.acquire().base.next_data(offset)
+    }
+
+    fn next_hole(&self, offset: usize) -> Result<usize, ErrorNum> {
+        self.0.acquire().base.next_hole(offset)
+    }
+
+    fn sync_page(&self, offset: usize, len: usize) -> Result<(), ErrorNum> {
+        self.0.acquire().base.sync_written(offset + len)
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), ErrorNum> {
+        let _unmap = self.unmap_shared(size, usize::MAX)?;
+        let inner = self.0.acquire();
+        inner.base.resize(size)?;
+        inner.base.notify(IN_MODIFY, None);
+        Ok(())
+    }
+
+    fn punch_hole(&self, offset: usize, len: usize) -> Result<(), ErrorNum> {
+        let _unmap = self.unmap_shared(offset, offset.saturating_add(len))?;
+        let inner = self.0.acquire();
+        inner.base.punch_hole(offset, len)?;
+        inner.base.notify(IN_MODIFY, None);
+        Ok(())
+    }
+
+    fn copy_range_to(&self, dst: Arc<dyn RegularFile>, length: usize) -> Result<usize, ErrorNum> {
+        if let Ok(dst_pfs) = dst.clone().as_any().downcast::<PFSRegular>() {
+            if !core::ptr::eq(self, dst_pfs.as_ref()) {
+                // lock in address order, avoid deadlock with copy in the other direction
+                let (mut src_inner, mut dst_inner) = if (self as *const Self) < Arc::as_ptr(&dst_pfs) {
+                    let src_inner = self.0.acquire();
+                    (src_inner, dst_pfs.0.acquire())
+                } else {
+                    let dst_inner = dst_pfs.0.acquire();
+                    (self.0.acquire(), dst_inner)
+                };
+                match src_inner.base.copy_range(src_inner.cursor, &dst_inner.base, dst_inner.cursor, length) {
+                    Ok(copied) => {
+                        src_inner.cursor.0 += copied;
+                        dst_inner.cursor.0 += copied;
+                        drop(src_inner);
+                        drop(dst_inner);
+                        dst_pfs.unmap_holes();
+                        return Ok(copied);
+                    },
+                    Err(ErrorNum::EXDEV) | Err(ErrorNum::EINVAL) => {},
+                    Err(e) => return Err(e)
+                }
+            }
         }
-        inner.cursor.0 = offset;
-        Ok(inner.cursor.0)
+        crate::fs::types::copy_from_cursor(self, dst.as_ref(), length)
     }
 }
 
@@ -297,7 +552,8 @@ impl BlockFile for PFSRegular {}
 pub struct PFSDirInner {
     pub base: PFSBase
 }
-pub struct PFSDir(pub SpinMutex<PFSDirInner>);
+/// The getdents cursor is apart from the inner lock, listing takes that lock itself.
+pub struct PFSDir(pub SpinMutex<PFSDirInner>, pub AtomicUsize);
 
 impl Drop for PFSDir {
     fn drop(&mut self) {
@@ -319,7 +575,7 @@ impl PFSDirInner {
             panic!("Malformed FS")
         }
         let dirent_count = stat.file_size / size_of::<PFSDEntry>();
-        let buffer = self.base.read(stat.file_size, Cursor::at_start())?;
+        let buffer = self.base.read(stat.file_size, Cursor::at_start(), None)?;
         let buffer = buffer.as_ptr() as *mut PFSDEntry;
         let buffer = unsafe{from_raw_parts(buffer, dirent_count).to_vec()};
         Ok(buffer)
@@ -338,9 +594,18 @@ impl PFSDirInner {
         let buffer = buffer as *const u8;
         let buffer = unsafe{from_raw_parts(buffer, size_of::<PFSDEntry>()).to_vec()};
         self.base.write(buffer, Cursor(pos * size_of::<PFSDEntry>()))?;
+        if dirent.inode == BAD_INODE {
+            self.base.inode.free_slot(pos);
+        }
         Ok(())
     }
 
+    /// Cookie of the entry in `slot`: slot in the high bits so cookies follow table order, slot generation in the low.
+    fn dirent_cookie(&self, slot: usize) -> usize {
+        ((slot + 1) << DIR_COOKIE_GEN_BITS) | self.base.inode.slot_gen(slot) as usize
+    }
+
+    /// Caller holds the dir lock (PFSINodeHandle::lock_dir).
     fn add_dirent(&self, dirent: PFSDEntry) -> Result<(), ErrorNum> {
         let dirents = self.read_dirent_raw()?;
         let mut empty_dirent = None;
@@ -358,46 +623,48 @@ impl PFSDirInner {
         self.write_dirent_at(dirent, pos)
     }
 
+    /// Caller holds the parent's dir lock, and must not hold any PFSDir lock.
     fn remove_self(&self) {
+        let dir_inode = self.base.inode.clone();
+        let _dir_guard = dir_inode.lock_dir();
         let entries = self.read_dirent_raw().unwrap();
-        let mut children_dir: Vec<PFSDir> = Vec::new();
+        let mut children_dir: Vec<PFSDirInner> = Vec::new();
         for (idx, e) in entries.iter().enumerate() {
             if e.inode != BAD_INODE {
                 let fs = self.base.fs.upgrade().unwrap();
                 let mut fs_inner = fs.inner.acquire();
                 let inode_guard = fs_inner.get_inode(e.inode.into()).unwrap();
                 let mut inode = inode_guard.acquire();
+                fs_inner.journal().log(&**inode);
                 inode.hard_link_count -= 1;
                 if inode.hard_link_count == 0 {
                     if inode.f_type == PFSType::DIR {
-                        children_dir.push(PFSDir(SpinMutex::new("PFS", PFSDirInner{
+                        children_dir.push(PFSDirInner{
                             base: PFSBase{
                                 inode_no: e.inode,
+                                inode: inode_guard.clone(),
                                 open_mode: OpenMode::SYS,
                                 fs: self.base.fs.clone(),
-                                path: self.base.path.append(e.name()).unwrap(),
-                            }})
-                        ));
+                                path: self.base.path.append(e.display_name()).unwrap(),
+                            }
+                        });
                         // keep the inode and free after it's children are freed.
+                    } else if Arc::strong_count(&inode_guard) > 1 {
+                        // still opened somewhere, free on last close
+                        inode_guard.set_orphan();
                     } else {
-                        let base = PFSBase{
-                            inode_no: e.inode,
-                            open_mode: OpenMode::SYS,
-                            fs: self.base.fs.clone(),
-                            path: self.base.path.append(e.name()).unwrap(),
-                        };
-                        base.resize_locked(0, &mut fs_inner, &mut inode).unwrap();
+                        PFSBase::truncate_locked(0, &mut fs_inner, &mut inode);
                         fs_inner.free_inode(e.inode.into());    
                     }
                 }
                 drop(inode);
-                drop(inode_guard);
                 drop(fs_inner);
+                drop(inode_guard);
                 self.write_dirent_at(PFSDEntry::empty(), idx).unwrap();
             }
         }
         for c in children_dir {
-            c.0.acquire().remove_self();
+            c.remove_self();
         }
         self.base.resize(0).unwrap();
         self.base.fs.upgrade().unwrap().inner.acquire().free_inode(self.base.inode_no);
@@ -456,47 +723,75 @@ impl File for PFSDir {
     fn stat(&self) -> Result<crate::fs::types::FileStat, ErrorNum> {
         self.0.acquire().base.stat()
     }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        self.0.acquire().base.owner()
+    }
+}
+
+impl PFSDir {
+    /// Inode of this directory, for `lock_dir`.
+    fn dir_inode(&self) -> Arc<PFSINodeHandle> {
+        self.0.acquire().base.inode.clone()
+    }
+
+    fn case_fold(&self) -> bool {
+        self.0.acquire().base.fs.upgrade().unwrap().case_fold
+    }
+
+    /// instantiate the file object of a child whose inode is known.
+    fn open_child(&self, entry_name: &str, inode_no: u32, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        let inner = self.0.acquire();
+        let base = PFSBase::new(
+            inode_no.into(), 
+            inner.base.path.append(entry_name.into())?,
+            mode,
+            inner.base.fs.clone()
+        )?;
+        let f_type = base.f_type()?;
+        let inode = inner.base.fs.upgrade().unwrap().get_inode(inode_no.into())?;
+        let mut inode_inner = inode.acquire();
+        inode_inner.access_time = get_real_time_epoch();
+        let res: Arc<dyn File> = match f_type {
+            FileType::REGULAR => {
+                Arc::new(PFSRegular(SpinMutex::new("PFSFile lock", PFSRegularInner{base, cursor: Cursor(0), readahead: ReadAhead::new()})))
+            },
+            FileType::DIR => {
+                Arc::new(PFSDir(SpinMutex::new("PFSFile lock", PFSDirInner{base}), AtomicUsize::new(0)))
+            },
+            FileType::LINK => {
+                Arc::new(PFSLink(SpinMutex::new("PFSFile lock", PFSLinkInner{base})))
+            },
+            _ => {
+                panic!("Malformed fs, bad type")
+            }
+        };
+        Ok(res)
+    }
 }
 
 impl DirFile for PFSDir {
-    fn open_entry(&self, entry_name: &String, mode: OpenMode) -> Result<alloc::sync::Arc<dyn File>, ErrorNum> {
+    fn dir_cursor(&self) -> usize {
+        self.1.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.1.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, mode: OpenMode) -> Result<alloc::sync::Arc<dyn File>, ErrorNum> {
         let entries = self.read_dirent()?;
-        let inner = self.0.acquire();
+        let case_fold = self.case_fold();
         for e in &entries {
-            // verbose!("Opendir looking for {}, f_type {:?}, target {}", e.f_name, e.f_type, rel_path.components[0]);
-            if e.f_name.eq(entry_name) {
-                let base = PFSBase::new(
-                    e.inode.into(), 
-                    inner.base.path.append(e.f_name.clone())?,
-                    mode,
-                    inner.base.fs.clone()
-                )?;
-                let f_type = base.f_type()?;
-                let inode = inner.base.fs.upgrade().unwrap().get_inode(e.inode.into())?;
-                let mut inode_inner = inode.acquire();
-                inode_inner.access_time = get_real_time_epoch();
-                let res: Arc<dyn File> = match f_type {
-                    FileType::REGULAR => {
-                        Arc::new(PFSRegular(SpinMutex::new("PFSFile lock", PFSRegularInner{base, cursor: Cursor(0)})))
-                    },
-                    FileType::DIR => {
-                        Arc::new(PFSDir(SpinMutex::new("PFSFile lock", PFSDirInner{base})))
-                    },
-                    FileType::LINK => {
-                        Arc::new(PFSLink(SpinMutex::new("PFSFile lock", PFSLinkInner{base})))
-                    },
-                    _ => {
-                        panic!("Malformed fs, bad type")
-                    }
-                };
-                return Ok(res);
+            // verbose!("Opendir looking for {}, f_type {:?}, target {}", e.f_name, e.f_type, rel_path.component(0));
+            if name_eq(&e.f_name, entry_name, case_fold) {
+                return self.open_child(&e.f_name, e.inode, mode);
             }
         }
         if mode.contains(OpenMode::CREATE) {
             // default to create regular file
-            drop(inner);
-            self.make_file(entry_name.clone(), Permission::default(), FileType::REGULAR)?;
-            self.open_entry(&entry_name, mode)
+            self.make_file(entry_name.into(), Permission::default(), FileType::REGULAR)?;
+            self.open_entry(entry_name, mode)
         } else {
             Err(ErrorNum::ENOENT)
         }
@@ -506,33 +801,42 @@ impl DirFile for PFSDir {
         if f_type != FileType::REGULAR && f_type != FileType::DIR {
             return Err(ErrorNum::EBADTYPE);
         }
-        if name.bytes().len() > DENTRY_NAME_LEN {
-            return Err(ErrorNum::ENAMETOOLONG);
-        }
-        let dirents = self.read_dirent()?;
-        for d in dirents {
-            if d.f_name == name {
+        check_name(&name)?;
+        // before any fs lock is taken
+        let (uid, gid) = current_owner();
+        let case_fold = self.case_fold();
+        // not with our lock held, begin may wait for transactions that need it
+        let fs = self.0.acquire().base.fs.upgrade().unwrap();
+        let _txn = fs.begin();
+        // check and insert as one step, or two creates of the same name both succeed
+        let dir_inode = self.dir_inode();
+        let dir_guard = dir_inode.lock_dir();
+        let inner = self.0.acquire();
+        for d in inner.read_dirent_raw()? {
+            if d.inode != BAD_INODE && d.name().map_or(false, |d_name| name_eq(&d_name, &name, case_fold)) {
                 return Err(ErrorNum::EEXIST);
             }
         }
-        
-        let inner = self.0.acquire();
+
         let parent_inode = inner.base.inode_no;
         let fs = inner.base.fs.upgrade().unwrap();
+        let uuid = fs.uuid;
         let mut fs_inner = fs.inner.acquire();
-        let inode_no = fs_inner.alloc_inode();
+        let inode_no = fs_inner.alloc_inode(uid)?;
         let inode_guard = fs_inner.get_inode(inode_no)?;
         let mut inode = inode_guard.acquire();
+        fs_inner.journal().log(&**inode);
         
         inode.permission = perm.into();
         inode.f_type = f_type.into();
-        inode.uid = 0;
-        inode.gid = 0;
+        inode.uid = uid;
+        inode.gid = gid;
         inode.flags = 0;
         inode.hard_link_count = if f_type == FileType::DIR {2} else {1};
         inode.direct_blk_no = [BAD_BLOCK; DIRECT_BLK_COUNT];
         inode.indirect_blk = BAD_BLOCK;
         inode.indirect_blk2 = BAD_BLOCK;
+        inode.indirect_blk3 = BAD_BLOCK;
         inode.f_size = 0;
         inode.access_time = get_real_time_epoch();
         inode.change_time = get_real_time_epoch();
@@ -554,12 +858,17 @@ impl DirFile for PFSDir {
             name_len: bytes.len() as u16,
             f_name,
         })?;
+        dentry_invalidate(uuid, parent_inode.0, &name);
         
+        inner.base.notify(IN_CREATE, Some(&name));
         drop(inner);
+        drop(dir_guard);
 
-        let res = self.open_entry(&name.into(), OpenMode::SYS)?;
+        let res = self.open_entry(&name, OpenMode::SYS)?;
         if let Ok(dir) = res.clone().as_dir() {
             let dir: Arc<PFSDir> = Arc::downcast(dir.as_any()).unwrap();
+            let child_inode = dir.dir_inode();
+            let _child_guard = child_inode.lock_dir();
             let mut dot_name = [0u8; DENTRY_NAME_LEN];
             dot_name[0] = b'.';
             dir.0.acquire().add_dirent( PFSDEntry {
@@ -587,42 +896,58 @@ impl DirFile for PFSDir {
     }
 
     fn remove_file(&self, name: String) -> Result<(), ErrorNum> {
-        let entries = self.read_dirent()?;
+        let case_fold = self.case_fold();
+        let fs = self.0.acquire().base.fs.upgrade().unwrap();
+        let _txn = fs.begin();
+        let dir_inode = self.dir_inode();
+        let _dir_guard = dir_inode.lock_dir();
+        let entries = self.0.acquire().read_dirent_raw()?;
         for (idx, e) in entries.iter().enumerate() {
-            if e.f_name == name {
+            if e.inode != BAD_INODE && e.name().map_or(false, |e_name| name_eq(&e_name, &name, case_fold)) {
                 let inner = self.0.acquire();
                 let fs = inner.base.fs.upgrade().unwrap();
                 let mut fs_inner = fs.inner.acquire();
-                let inode_guard = fs_inner.get_inode(e.inode.into())?;
+                let inode_guard = fs_inner.get_inode(e.inode)?;
                 let mut inode = inode_guard.acquire();
                 if inode.f_type == PFSType::DIR {
                     let child_inner = PFSDirInner {
                         base: PFSBase {
-                            inode_no: e.inode.into(),
+                            inode_no: e.inode,
+                            inode: inode_guard.clone(),
                             open_mode: OpenMode::SYS,
                             fs: inner.base.fs.clone(),
-                            path: inner.base.path.append(e.f_name.clone()).unwrap(),
+                            path: inner.base.path.append(e.display_name()).unwrap(),
                         }
                     };
                     drop(fs_inner);
                     drop(inode);
+                    // child takes its own dir lock, which can sleep
+                    drop(inner);
                     child_inner.remove_self();
+                    let inner = self.0.acquire();
+                    inner.write_dirent_at(PFSDEntry::empty(), idx)?;
+                    dentry_invalidate(inner.base.fs.upgrade().unwrap().uuid, inner.base.inode_no.0, &name);
+                    inner.base.notify(IN_DELETE, Some(&name));
+                    return Ok(());
                 } else {
+                    fs_inner.journal().log(&**inode);
                     inode.hard_link_count -= 1;
                     if inode.hard_link_count == 0 {
-                        let base = PFSBase {
-                            inode_no: e.inode.into(),
-                            open_mode: OpenMode::SYS,
-                            fs: inner.base.fs.clone(),
-                            path: inner.base.path.append(e.f_name.clone()).unwrap(),
-                        };
-                        base.resize_locked(0, &mut fs_inner, &mut inode).unwrap();
-                        fs_inner.free_inode(e.inode.into());
+                        if Arc::strong_count(&inode_guard) > 1 {
+                            // still opened somewhere, free on last close
+                            inode_guard.set_orphan();
+                        } else {
+                            PFSBase::truncate_locked(0, &mut fs_inner, &mut inode);
+                            fs_inner.free_inode(e.inode.into());
+                        }
                     }
                     drop(fs_inner);
                     drop(inode);
+                    drop(inode_guard);
                 }
                 inner.write_dirent_at(PFSDEntry::empty(), idx)?;
+                dentry_invalidate(inner.base.fs.upgrade().unwrap().uuid, inner.base.inode_no.0, &name);
+                inner.base.notify(IN_DELETE, Some(&name));
                 return Ok(());
             }
         }
@@ -630,9 +955,33 @@ impl DirFile for PFSDir {
     }
 
     fn read_dirent(&self) -> Result<alloc::vec::Vec<Dirent>, ErrorNum> {
-        let mut res = self.0.acquire().read_dirent_raw()?;
-        res.retain(|&x| x.inode != BAD_INODE);
-        Ok(res.iter().map(|&x| x.into()).collect())
+        Ok(self.read_dirent_cookies()?.into_iter().map(|(_, dirent)| dirent).collect())
+    }
+
+    /// Entries never move between slots and a freed slot gets a new generation, so an entry keeps its cookie for
+    /// as long as it exists and a reused slot never hands out an old one.
+    fn read_dirent_cookies(&self) -> Result<alloc::vec::Vec<(usize, Dirent)>, ErrorNum> {
+        let dir_inode = self.dir_inode();
+        let _dir_guard = dir_inode.lock_dir();
+        let inner = self.0.acquire();
+        let raw = inner.read_dirent_raw()?;
+        Ok(raw.iter().enumerate().filter(|(_, x)| x.inode != BAD_INODE).filter_map(|(slot, &x)| match x.try_into() {
+            Ok(dirent) => Some((inner.dirent_cookie(slot), dirent)),
+            Err(_) => {
+                // left for fsck
+                warning!("ParchFS: skipping entry with bad name {:?} in {:?}", x.display_name(), inner.base.path);
+                None
+            }
+        }).collect())
+    }
+
+    /// Cache is keyed by the name looked up, one entry could show up under many names when case folded.
+    fn dentry_cacheable(&self) -> bool {
+        !self.case_fold()
+    }
+
+    fn open_entry_inode(&self, entry_name: &str, inode: u32, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        self.open_child(entry_name, inode, mode)
     }
 }
 
@@ -0,0 +1,108 @@
+//! Write-behind for small writes to regular files.
+//!
+//! A write shorter than WRITE_BEHIND_MAX is kept with the inode instead of going to the blocks right away, and
+//! the next one starting where it ends is appended to it, so a run of small writes opens one transaction and takes
+//! the fs lock once. What's kept goes to the blocks when a write doesn't continue it or would grow it past
+//! WRITE_BEHIND_MAX, before anything else looks at the inode (read, stat, mapping or faulting a page in, resize...),
+//! on fsync and on close, and by writeback.
+//! Writeback is the first kernel thread. It wakes every dirty_writeback_ms (0 turns that off), and right away once
+//! dirty_background_inodes inodes have a kept write, both under /proc/sys/vm. Besides kept writes, it frees the
+//! inodes of orphans closed for the last time outside a transaction, which would wait for the next one otherwise.
+//! An error of a deferred write (ENOSPC, EDQUOT) comes back from whatever flushed it. One writeback ran into is
+//! logged and kept with the inode for the next fsync or close, close of the last reference can only log it.
+//!
+//! Lock order: file, then the inode's pending write, then fs and inode.
+
+use alloc::{sync::{Arc, Weak}, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+use crate::{process::Alarm, utils::{ErrorNum, Mutex, SpinMutex}};
+
+use super::{WRITE_BEHIND_INTERVAL_MS, WRITE_BEHIND_DIRTY_INODES, fs::{PFSINodeHandle, ParchFS}};
+
+pub struct PendingWrite {
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+impl PendingWrite {
+    pub fn end(&self) -> usize {
+        self.offset + self.data.len()
+    }
+}
+
+lazy_static!{
+    /// inodes that got a pending write since the last writeback
+    static ref DIRTY: SpinMutex<Vec<Weak<PFSINodeHandle>>> = SpinMutex::new("write-behind list", Vec::new());
+    /// filesystems with orphans waiting to be freed
+    static ref ORPHANED: SpinMutex<Vec<Weak<ParchFS>>> = SpinMutex::new("write-behind orphans", Vec::new());
+    static ref WRITEBACK_ALARM: Arc<Alarm> = Alarm::new("writeback");
+}
+
+static WRITEBACK_INTERVAL_MS: AtomicUsize = AtomicUsize::new(WRITE_BEHIND_INTERVAL_MS);
+static DIRTY_THRESHOLD: AtomicUsize = AtomicUsize::new(WRITE_BEHIND_DIRTY_INODES);
+
+/// Caller holds the inode's pending write, so this can't flush by itself.
+pub fn mark_dirty(inode: &Arc<PFSINodeHandle>) {
+    let mut dirty = DIRTY.acquire();
+    dirty.push(Arc::downgrade(inode));
+    let over = dirty.len() >= DIRTY_THRESHOLD.load(Ordering::Relaxed);
+    drop(dirty);
+    if over {
+        WRITEBACK_ALARM.kick();
+    }
+}
+
+/// Last handle of an orphan of `fs` is gone. Maybe with the fs lock held, so only noted for writeback.
+pub fn mark_orphaned(fs: &Arc<ParchFS>) {
+    let mut orphaned = ORPHANED.acquire();
+    if !orphaned.iter().any(|other| core::ptr::eq(other.as_ptr(), Arc::as_ptr(fs))) {
+        orphaned.push(Arc::downgrade(fs));
+    }
+}
+
+pub fn get_writeback_interval() -> usize {
+    WRITEBACK_INTERVAL_MS.load(Ordering::Relaxed)
+}
+
+/// Takes effect now, not after the old interval.
+pub fn set_writeback_interval(ms: usize) -> Result<(), ErrorNum> {
+    WRITEBACK_INTERVAL_MS.store(ms, Ordering::Relaxed);
+    WRITEBACK_ALARM.kick();
+    Ok(())
+}
+
+pub fn get_dirty_threshold() -> usize {
+    DIRTY_THRESHOLD.load(Ordering::Relaxed)
+}
+
+pub fn set_dirty_threshold(count: usize) -> Result<(), ErrorNum> {
+    if count == 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    DIRTY_THRESHOLD.store(count, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Body of the writeback kernel thread.
+pub fn writeback_daemon() -> ! {
+    loop {
+        WRITEBACK_ALARM.wait(WRITEBACK_INTERVAL_MS.load(Ordering::Relaxed));
+        writeback();
+    }
+}
+
+fn writeback() {
+    let dirty = core::mem::take(&mut *DIRTY.acquire());
+    for inode in dirty.iter().filter_map(|inode| inode.upgrade()) {
+        if let Err(e) = inode.flush() {
+            warning!("ParchFS: write-behind of inode {} failed with {:?}, data lost.", inode.inode_no.0, e);
+            inode.set_wb_error(e);
+        }
+    }
+    let orphaned = core::mem::take(&mut *ORPHANED.acquire());
+    for fs in orphaned.iter().filter_map(|fs| fs.upgrade()) {
+        fs.release_orphans();
+    }
+}
@@ -0,0 +1,270 @@
+use core::{fmt::Debug, sync::atomic::{AtomicUsize, Ordering}};
+
+use alloc::{sync::Arc, vec::Vec, string::{String, ToString}};
+
+use crate::{fs::{File, DirFile, types::{FileStat, Permission, FileType}, OpenMode, Path, Dirent, DummyLink}, utils::{ErrorNum, RWLock}, device::DEVICE_MANAGER, process::get_processor};
+
+use super::{PROC_FS, text_file::ProcTextFile};
+
+/// /proc/device-tree and below, a directory per node and a file per property holding its raw bytes as in the DTB.
+/// Nodes are looked up by path on each access, so injected ones show up and removed ones go away.
+#[derive(Debug)]
+pub struct DeviceTreeDir {
+    /// of the node in the device tree, `/` for the root
+    path: String,
+    cursor: AtomicUsize,
+}
+
+impl DeviceTreeDir {
+    /// /proc/device-tree
+    pub fn root() -> Self {
+        Self{path: "/".to_string(), cursor: AtomicUsize::new(0)}
+    }
+
+    fn proc_path(&self) -> String {
+        format!("/proc/device-tree{}", self.path.trim_end_matches('/'))
+    }
+
+    fn child_path(&self, name: &str) -> String {
+        format!("{}/{}", self.path.trim_end_matches('/'), name)
+    }
+}
+
+impl File for DeviceTreeDir {
+    fn write(&self, _data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn read(&self, _length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::RegularFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn crate::fs::VirtualFileSystem> {
+        PROC_FS.clone()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat{
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: self.proc_path().into(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
+}
+
+impl DirFile for DeviceTreeDir {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        let proc_path = self.proc_path();
+        if entry_name == ".." {
+            Ok(Arc::new(DummyLink{
+                vfs: PROC_FS.clone(),
+                link_dest: proc_path.rsplit_once('/').map_or("/", |(parent, _)| parent).into(),
+                self_path: format!("{}/..", proc_path).into(),
+            }))
+        } else if entry_name == "." {
+            Ok(Arc::new(DummyLink{
+                vfs: PROC_FS.clone(),
+                link_dest: proc_path.as_str().into(),
+                self_path: format!("{}/.", proc_path).into(),
+            }))
+        } else if entry_name == ".overlay" && self.path == "/" && cfg!(debug_assertions) {
+            // rewrites what drivers probe against, root only
+            if !get_processor().current().map_or(true, |proc| proc.get_inner().cred.privileged()) {
+                return Err(ErrorNum::EACCES);
+            }
+            Ok(Arc::new(OverlayFile{}))
+        } else {
+            let dev_tree = DEVICE_MANAGER.acquire_r().get_dev_tree();
+            let node = dev_tree.node_by_path(&self.path).map_err(|_| ErrorNum::ENOENT)?;
+            let node_r = node.acquire_r();
+            if node_r.children.iter().any(|child| &child.acquire_r().unit_name == entry_name) {
+                Ok(Arc::new(DeviceTreeDir{path: self.child_path(entry_name), cursor: AtomicUsize::new(0)}))
+            } else {
+                let value = node_r.get_value(entry_name).map_err(|_| ErrorNum::ENOENT)?;
+                Ok(Arc::new(ProcTextFile::raw(Path::new_s(format!("{}/{}", proc_path, entry_name))?, value.to_bytes())))
+            }
+        }
+    }
+
+    fn make_file(&self, _name: alloc::string::String, _perm: crate::fs::types::Permission, _f_type: crate::fs::types::FileType) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn remove_file(&self, _name: alloc::string::String) -> Result<(), crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read_dirent(&self) -> Result<alloc::vec::Vec<crate::fs::Dirent>, crate::utils::ErrorNum> {
+        let mut result = Vec::new();
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o440),
+            f_type: FileType::LINK,
+            f_name: ".".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o440),
+            f_type: FileType::LINK,
+            f_name: "..".to_string(),
+        });
+
+        let dev_tree = DEVICE_MANAGER.acquire_r().get_dev_tree();
+        let node = dev_tree.node_by_path(&self.path).map_err(|_| ErrorNum::ENOENT)?;
+        let node_r = node.acquire_r();
+        for (name, _) in node_r.properties.iter() {
+            result.push(Dirent {
+                inode: 0,
+                permission: Permission::from_bits_truncate(0o444),
+                f_type: FileType::REGULAR,
+                f_name: name.clone(),
+            });
+        }
+        for child in node_r.children.iter() {
+            result.push(Dirent {
+                inode: 0,
+                permission: Permission::from_bits_truncate(0o555),
+                f_type: FileType::DIR,
+                f_name: child.acquire_r().unit_name.clone(),
+            });
+        }
+
+        if self.path == "/" && cfg!(debug_assertions) {
+            result.push(Dirent {
+                inode: 0,
+                permission: Permission::from_bits_truncate(0o200),
+                f_type: FileType::REGULAR,
+                f_name: ".overlay".to_string(),
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// Debug builds only. Each line written is a DeviceTree::apply_overlay command on the live tree.
+pub struct OverlayFile;
+
+impl Debug for OverlayFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "/proc/device-tree/.overlay")
+    }
+}
+
+impl File for OverlayFile {
+    fn write(&self, data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        let len = data.len();
+        let cmds = String::from_utf8(data).map_err(|_| ErrorNum::EINVAL)?;
+        let dev_tree = DEVICE_MANAGER.acquire_r().get_dev_tree();
+        for cmd in cmds.lines().filter(|cmd| !cmd.trim().is_empty()) {
+            dev_tree.apply_overlay(cmd)?;
+            info!("Device tree overlay applied: {}", cmd.trim());
+        }
+        Ok(len)
+    }
+
+    fn read(&self, _length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::RegularFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn crate::fs::VirtualFileSystem> {
+        PROC_FS.clone()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat{
+            open_mode: OpenMode::WRITE,
+            file_size: 0,
+            path: "/proc/device-tree/.overlay".into(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+}
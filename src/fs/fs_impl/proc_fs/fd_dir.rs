@@ -1,13 +1,22 @@
-use alloc::{sync::Arc, vec::Vec, string::ToString};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{process::{ProcessID, FileDescriptor, get_process}, fs::{File, DirFile, LinkFile, types::{FileStat, Permission}, OpenMode, Dirent, VirtualFileSystem}, utils::ErrorNum};
+use alloc::{sync::Arc, vec::Vec, string::{String, ToString}};
 
-use super::PROC_FS;
+use crate::{process::{ProcessID, FileDescriptor, get_process, get_processor}, fs::{File, DirFile, LinkFile, types::{FileStat, Permission}, OpenMode, Dirent, VirtualFileSystem, SeekWhence}, utils::ErrorNum};
+
+use super::{PROC_FS, text_file::ProcTextFile};
 
 
 #[derive(Debug)]
 pub struct FDDir {
     pub pid: ProcessID,
+    cursor: AtomicUsize,
+}
+
+impl FDDir {
+    pub fn new(pid: ProcessID) -> Self {
+        Self{pid, cursor: AtomicUsize::new(0)}
+    }
 }
 
 impl File for FDDir {
@@ -68,10 +77,22 @@ impl File for FDDir {
             fs: Arc::downgrade(&self.vfs()),
         })
     }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
 }
 
 impl DirFile for FDDir {
-    fn open_entry(&self, entry_name: &alloc::string::String, _mode: crate::fs::OpenMode) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, _mode: crate::fs::OpenMode) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
         let fd: FileDescriptor = entry_name.parse::<usize>().map_err(|_| ErrorNum::ENOENT)?.into();
         let _fd_file_stat = get_process(self.pid)?.get_inner().get_file(fd)?.stat()?;
         Ok(Arc::new(FDLink{
@@ -197,4 +218,202 @@ impl LinkFile for FDLink {
     fn write_link(&self, _path: &crate::fs::Path) -> Result<(), crate::utils::ErrorNum> {
         Err(ErrorNum::EPERM)
     }
-}
\ No newline at end of file
+
+    /// The open file, so pipes and unlinked files can be reopened through here.
+    fn follow_link(&self, mode: OpenMode) -> Result<Option<Arc<dyn File>>, ErrorNum> {
+        let file = get_process(self.pid)?.get_inner().get_file(self.fd)?;
+        Ok(Some(open_as_caller(self.pid, file, mode)?))
+    }
+}
+
+/// EACCES unless the caller is privileged or runs as the same euid as process `pid`. The kernel itself sees all.
+pub(super) fn check_caller(pid: ProcessID) -> Result<(), ErrorNum> {
+    // one pcb locked at a time, `pid` may be us
+    let cred = match get_processor().current() {
+        Some(proc) => proc.get_inner().cred,
+        None => return Ok(()),
+    };
+    let target_euid = get_process(pid)?.get_inner().cred.euid;
+    if !cred.privileged() && cred.euid != target_euid {
+        return Err(ErrorNum::EACCES);
+    }
+    Ok(())
+}
+
+/// Open `file` of process `pid` through a magic link, for the current process. Only its own processes' unless
+/// privileged. Files with an inode are opened again with `mode` and checked against its permission bits, so the
+/// target's cursor and open mode stay its own. Pipes and devices have neither, they're shared but not for more
+/// than they were opened for.
+pub(super) fn open_as_caller(pid: ProcessID, file: Arc<dyn File>, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+    check_caller(pid)?;
+    let cred = get_processor().current().unwrap().get_inner().cred;
+    match file.reopen(mode) {
+        Ok(reopened) => {
+            let mut want = Permission::empty();
+            if mode.contains(OpenMode::READ) {
+                want |= Permission::OTHER_R;
+            }
+            if mode.contains(OpenMode::WRITE) {
+                want |= Permission::OTHER_W;
+            }
+            if !cred.permits(reopened.owner()?, want, true) {
+                return Err(ErrorNum::EACCES);
+            }
+            Ok(reopened)
+        },
+        Err(ErrorNum::EBADTYPE) if file.clone().as_regular().is_err() => {
+            if !file.stat()?.open_mode.contains(mode & (OpenMode::READ | OpenMode::WRITE)) {
+                return Err(ErrorNum::EACCES);
+            }
+            Ok(file)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// `pos`, `flags` and `path` of an open file, as in /proc/<pid>/fdinfo/<fd>. pos is 0 for anything that can't seek.
+pub fn fd_info(file: &Arc<dyn File>) -> Result<String, ErrorNum> {
+    let stat = file.stat()?;
+    let pos = match file.clone().as_dir() {
+        Ok(dir) => dir.dir_cursor(),
+        Err(_) => file.clone().as_regular().ok().and_then(|f| f.seek(0, SeekWhence::Cur).ok()).unwrap_or(0),
+    };
+    Ok(format!("pos:\t{}\nflags:\t{:?}\npath:\t{:?}\n", pos, stat.open_mode, stat.path))
+}
+
+#[derive(Debug)]
+pub struct FDInfoDir {
+    pub pid: ProcessID,
+    cursor: AtomicUsize,
+}
+
+impl FDInfoDir {
+    pub fn new(pid: ProcessID) -> Self {
+        Self{pid, cursor: AtomicUsize::new(0)}
+    }
+}
+
+impl File for FDInfoDir {
+    fn write(&self, _data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read(&self, _length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::RegularFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn crate::fs::VirtualFileSystem> {
+        PROC_FS.clone()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat {
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: format!("/proc/{}/fdinfo", self.pid).into(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
+}
+
+impl DirFile for FDInfoDir {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, _mode: crate::fs::OpenMode) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
+        let fd: FileDescriptor = entry_name.parse::<usize>().map_err(|_| ErrorNum::ENOENT)?.into();
+        check_caller(self.pid)?;
+        // stat might need the pcb (procfs), don't hold it
+        let file = get_process(self.pid)?.get_inner().get_file(fd)?;
+        Ok(Arc::new(ProcTextFile::new(
+            format!("/proc/{}/fdinfo/{}", self.pid.0, fd.0).into(),
+            fd_info(&file)?
+        )))
+    }
+
+    fn make_file(&self, _name: alloc::string::String, _perm: crate::fs::types::Permission, _f_type: crate::fs::types::FileType) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn remove_file(&self, _name: alloc::string::String) -> Result<(), crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read_dirent(&self) -> Result<alloc::vec::Vec<crate::fs::Dirent>, crate::utils::ErrorNum> {
+        let mut res = Vec::new();
+
+        res.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o755),
+            f_type: crate::fs::types::FileType::LINK,
+            f_name: ".".to_string(),
+        });
+
+        res.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o755),
+            f_type: crate::fs::types::FileType::LINK,
+            f_name: "..".to_string(),
+        });
+
+        check_caller(self.pid)?;
+        let proc = get_process(self.pid)?;
+        let proc_inner = proc.get_inner();
+        for fd in proc_inner.files.keys() {
+            res.push(Dirent {
+                inode: 0,
+                permission: Permission::from_bits_truncate(0o444),
+                f_type: crate::fs::types::FileType::REGULAR,
+                f_name: format!("{}", fd.0),
+            });
+        }
+
+        Ok(res)
+    }
+}
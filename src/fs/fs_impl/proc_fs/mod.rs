@@ -3,10 +3,13 @@ use crate::{fs::VirtualFileSystem, utils::{ErrorNum, UUID}};
 mod proc_dir;
 mod root_dir;
 mod fd_dir;
+mod sys_dir;
+mod dt_dir;
+mod text_file;
 
 use lazy_static::*;
 
-use self::root_dir::ROOT_DIR;
+use self::root_dir::RootDir;
 
 lazy_static!{
     pub static ref PROC_FS: alloc::sync::Arc<ProcFS> = alloc::sync::Arc::new(ProcFS{uuid: UUID::new()});
@@ -30,12 +33,16 @@ impl VirtualFileSystem for ProcFS {
         self
     }
 
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+
     fn get_uuid(&self) -> crate::utils::UUID {
         self.uuid
     }
 
     fn root_dir(&self, _mode: crate::fs::OpenMode) -> Result<alloc::sync::Arc<dyn crate::fs::DirFile>, crate::utils::ErrorNum> {
-        Ok(ROOT_DIR.clone())
+        Ok(alloc::sync::Arc::new(RootDir::default()))
     }
 
     fn as_any<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn core::any::Any + Send + Sync> {
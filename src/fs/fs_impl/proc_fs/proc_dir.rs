@@ -1,8 +1,10 @@
-use alloc::{sync::Arc, vec::Vec, string::ToString};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{fs::{File, DirFile, LinkFile, types::{FileStat, Permission}, OpenMode, Path, VirtualFileSystem, Dirent, DummyLink}, process::{ProcessID, get_process, get_processor}, utils::ErrorNum};
+use alloc::{sync::Arc, vec::Vec, string::{String, ToString}};
 
-use super::{PROC_FS, fd_dir::FDDir};
+use crate::{config::PAGE_SIZE, fs::{File, DirFile, LinkFile, types::{FileStat, Permission}, OpenMode, Path, VirtualFileSystem, Dirent, DummyLink}, process::{ProcessID, get_process, get_processor}, utils::ErrorNum};
+
+use super::{PROC_FS, fd_dir::{FDDir, FDInfoDir, open_as_caller}, text_file::ProcTextFile};
 
 #[derive(Debug)]
 pub struct SelfProcDir;
@@ -63,23 +65,144 @@ impl File for SelfProcDir {
     }
 
     fn as_any       <'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
-        todo!()
+        self
     }
 }
 
 impl LinkFile for SelfProcDir {
+    /// Computed on each lookup, so it's always whoever is asking.
     fn read_link(&self) -> Result<crate::fs::Path, crate::utils::ErrorNum> {
         Ok(format!("/proc/{}", get_processor().current().unwrap().pid.0).into())
     }
 
     fn write_link(&self, _path: &Path) -> Result<(), ErrorNum> {
-        todo!()
+        Err(ErrorNum::EPERM)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MagicLinkKind {
+    Cwd,
+    Exe,
+}
+
+/// /proc/<pid>/cwd and /proc/<pid>/exe, target looked up from the process on each access.
+#[derive(Debug)]
+pub struct ProcMagicLink {
+    pub pid: ProcessID,
+    pub kind: MagicLinkKind,
+}
+
+impl ProcMagicLink {
+    fn name(&self) -> &'static str {
+        match self.kind {
+            MagicLinkKind::Cwd => "cwd",
+            MagicLinkKind::Exe => "exe",
+        }
+    }
+}
+
+impl File for ProcMagicLink {
+    fn write(&self, _data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read(&self, _length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile   + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::RegularFile  + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn crate::fs::VirtualFileSystem> {
+        PROC_FS.clone()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat {
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: format!("/proc/{}/{}", self.pid.0, self.name()).into(),
+            inode: 0,
+            fs: Arc::downgrade(&PROC_FS.clone().as_vfs()),
+        })
+    }
+}
+
+impl LinkFile for ProcMagicLink {
+    fn read_link(&self) -> Result<crate::fs::Path, crate::utils::ErrorNum> {
+        let proc = get_process(self.pid)?;
+        let proc_inner = proc.get_inner();
+        match self.kind {
+            MagicLinkKind::Cwd => Ok(proc_inner.cwd.clone()),
+            MagicLinkKind::Exe => {
+                let elf_file = proc_inner.elf_file.clone();
+                // stat might need the pcb (procfs), don't hold it
+                drop(proc_inner);
+                Ok(elf_file.stat()?.path)
+            }
+        }
+    }
+
+    fn write_link(&self, _path: &Path) -> Result<(), ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    /// exe is the running image even if its path got deleted or replaced since exec.
+    fn follow_link(&self, mode: OpenMode) -> Result<Option<Arc<dyn File>>, ErrorNum> {
+        match self.kind {
+            MagicLinkKind::Cwd => Ok(None),
+            MagicLinkKind::Exe => {
+                let elf_file = get_process(self.pid)?.get_inner().elf_file.clone().as_file();
+                Ok(Some(open_as_caller(self.pid, elf_file, mode)?))
+            }
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct PidProcDir{
-    pub pid: ProcessID
+    pub pid: ProcessID,
+    cursor: AtomicUsize,
+}
+
+impl PidProcDir {
+    pub fn new(pid: ProcessID) -> Self {
+        Self{pid, cursor: AtomicUsize::new(0)}
+    }
 }
 
 impl File for PidProcDir {
@@ -140,9 +263,21 @@ impl File for PidProcDir {
             fs: Arc::downgrade(&PROC_FS.clone().as_vfs()),
         })
     }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
 }
 
 impl DirFile for PidProcDir {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
     fn make_file(&self, _name: alloc::string::String, _perm: crate::fs::types::Permission, _f_type: crate::fs::types::FileType) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
         Err(ErrorNum::EPERM)
     }
@@ -168,6 +303,35 @@ impl DirFile for PidProcDir {
             f_name: "..".to_string(),
         });
 
+        res.push(Dirent{
+            inode: 0,
+            permission: Permission::default(),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "status".to_string(),
+        });
+        res.push(Dirent{
+            inode: 0,
+            permission: Permission::default(),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "stat".to_string(),
+        });
+
+        res.push(Dirent{
+            inode: 0,
+            permission: Permission::default(),
+            f_type: crate::fs::types::FileType::DIR,
+            f_name: "fdinfo".to_string(),
+        });
+
+        for name in ["cwd", "exe"] {
+            res.push(Dirent{
+                inode: 0,
+                permission: Permission::default(),
+                f_type: crate::fs::types::FileType::LINK,
+                f_name: name.to_string(),
+            });
+        }
+
         let proc = get_process(self.pid)?;
         let proc_inner = proc.get_inner();
         
@@ -184,10 +348,8 @@ impl DirFile for PidProcDir {
         Ok(res)
     }
 
-    fn open_entry(&self, entry_name: &alloc::string::String, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
-        if entry_name == "self" {
-            Ok(Arc::new(SelfProcDir{}))
-        } else if entry_name == ".." {
+    fn open_entry(&self, entry_name: &str, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        if entry_name == ".." {
             Ok(Arc::new(DummyLink{
                 vfs: PROC_FS.clone(),
                 link_dest: "/proc".into(),
@@ -200,11 +362,23 @@ impl DirFile for PidProcDir {
                 self_path: format!("/proc/{}/.", self.pid).into(),
             }))
         } else if entry_name == "fd" {
-            Ok(Arc::new(
-                FDDir{
-                    pid: self.pid,
-                }
-            ))
+            Ok(Arc::new(FDDir::new(self.pid)))
+        } else if entry_name == "fdinfo" {
+            Ok(Arc::new(FDInfoDir::new(self.pid)))
+        } else if entry_name == "cwd" {
+            Ok(Arc::new(ProcMagicLink{pid: self.pid, kind: MagicLinkKind::Cwd}))
+        } else if entry_name == "exe" {
+            Ok(Arc::new(ProcMagicLink{pid: self.pid, kind: MagicLinkKind::Exe}))
+        } else if entry_name == "status" {
+            Ok(Arc::new(ProcTextFile::new(
+                format!("/proc/{}/status", self.pid.0).into(),
+                self.status()?
+            )))
+        } else if entry_name == "stat" {
+            Ok(Arc::new(ProcTextFile::new(
+                format!("/proc/{}/stat", self.pid.0).into(),
+                self.stat()?
+            )))
         } else {
             Err(ErrorNum::ENOENT)
         }
@@ -212,6 +386,58 @@ impl DirFile for PidProcDir {
 }
 
 impl PidProcDir {
+    /// Content of /proc/<pid>/status. Memory in kB, RssShared counts pages also mapped by others (e.g. COW after fork).
+    fn status(&self) -> Result<String, ErrorNum> {
+        let proc = get_process(self.pid)?;
+        let proc_inner = proc.get_inner();
+        let usage = proc_inner.mem_layout.mem_usage();
+        let vm_size = proc_inner.mem_layout.user_size();
+        let vm_lck = proc_inner.mem_layout.locked.len() * PAGE_SIZE;
+        let state = format!("{:?}", proc_inner.status);
+        let cpu_ticks = proc_inner.cpu_ticks;
+        let (pgid, sid) = (proc_inner.pgid, proc_inner.sid);
+        let cred = proc_inner.cred;
+        let elf_file = proc_inner.elf_file.clone();
+        drop(proc_inner);
+        let name = elf_file.stat()?.path;
+        Ok(format!(
+            "Name:\t{:?}\nPid:\t{}\nTgid:\t{}\nPgid:\t{}\nSid:\t{}\nUid:\t{}\t{}\t{}\nGid:\t{}\t{}\t{}\nState:\t{}\nVmSize:\t{} kB\nVmLck:\t{} kB\nVmRSS:\t{} kB\nRssShared:\t{} kB\nPss:\t{} kB\nCpuTicks:\t{}\n",
+            name,
+            self.pid.0,
+            proc.tgid.0,
+            pgid.0,
+            sid.0,
+            cred.uid, cred.euid, cred.suid,
+            cred.gid, cred.egid, cred.sgid,
+            state,
+            vm_size / 1024,
+            vm_lck / 1024,
+            usage.resident * PAGE_SIZE / 1024,
+            usage.shared * PAGE_SIZE / 1024,
+            usage.pss / 1024,
+            cpu_ticks
+        ))
+    }
+
+    /// One line for scripts: pid, state, minor, COW and file faults, cpu ticks, VmSize and RSS in bytes.
+    fn stat(&self) -> Result<String, ErrorNum> {
+        let proc = get_process(self.pid)?;
+        let proc_inner = proc.get_inner();
+        let faults = proc_inner.mem_layout.faults;
+        let usage = proc_inner.mem_layout.mem_usage();
+        Ok(format!(
+            "{} {:?} {} {} {} {} {} {}\n",
+            self.pid.0,
+            proc_inner.status,
+            faults.minor,
+            faults.cow,
+            faults.file,
+            proc_inner.cpu_ticks,
+            proc_inner.mem_layout.user_size(),
+            usage.resident * PAGE_SIZE
+        ))
+    }
+
     fn new(&self, pid: ProcessID) -> Result<Self, ErrorNum> {
         let _proc = get_process(pid)?; // check process exist
         Ok(Self{pid})
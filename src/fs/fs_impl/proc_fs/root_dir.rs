@@ -1,18 +1,17 @@
-use alloc::{sync::Arc, vec::Vec, string::ToString};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{fs::{File, DirFile, types::{FileStat, Permission}, OpenMode, VirtualFileSystem, fs_impl::proc_fs::{proc_dir::{PidProcDir, SelfProcDir}}, Dirent, DummyLink}, utils::ErrorNum, process::{ProcessID, get_process, process_list}};
+use alloc::{sync::Arc, vec::Vec, string::{String, ToString}};
 
-use super::{PROC_FS};
+use crate::{fs::{mount_ns, File, DirFile, types::{FileStat, Permission}, OpenMode, VirtualFileSystem, fs_impl::{proc_fs::{proc_dir::{PidProcDir, SelfProcDir}}, parch_fs_check}, Dirent, DummyLink}, utils::{ErrorNum, symbols::symbols, ktest, trace, crash_dump}, process::{ProcessID, FileDescriptor, get_process, get_processor, process_list, present_harts, hart_online, sched_stat}, device::{DEVICE_MANAGER, drivers::pstore::{pstore_text, pstore_present}}, syscall::stats as syscall_stats, mem::page_owners};
 
-use lazy_static::*;
+use super::{PROC_FS, dt_dir::DeviceTreeDir, sys_dir::SysDir, text_file::ProcTextFile};
 
-lazy_static!{
-    pub static ref ROOT_DIR: Arc<RootDir> = Arc::new(RootDir{});
+/// a new one per open, each with its own getdents cursor
+#[derive(Debug, Default)]
+pub struct RootDir {
+    cursor: AtomicUsize,
 }
 
-#[derive(Debug)]
-pub struct RootDir;
-
 impl File for RootDir {
     fn write(&self, _data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
         Err(ErrorNum::EISDIR)
@@ -71,12 +70,68 @@ impl File for RootDir {
             fs: Arc::downgrade(&PROC_FS.clone().as_vfs()),
         })
     }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
 }
 
 impl DirFile for RootDir {
-    fn open_entry(&self, entry_name: &alloc::string::String, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
         if entry_name == "self" {
             Ok(Arc::new(SelfProcDir{}))
+        } else if entry_name == "sys" {
+            Ok(Arc::new(SysDir::root()))
+        } else if entry_name == "device-tree" {
+            Ok(Arc::new(DeviceTreeDir::root()))
+        } else if entry_name == "kallsyms" {
+            Ok(Arc::new(ProcTextFile::new("/proc/kallsyms".into(), kallsyms())))
+        } else if entry_name == "cpuinfo" {
+            Ok(Arc::new(ProcTextFile::new("/proc/cpuinfo".into(), cpuinfo())))
+        } else if entry_name == "ktest" {
+            // opening runs the self test, the report is the content. It writes to the live root fs, root only.
+            if !get_processor().current().map_or(true, |proc| proc.get_inner().cred.privileged()) {
+                return Err(ErrorNum::EACCES);
+            }
+            Ok(Arc::new(ProcTextFile::new("/proc/ktest".into(), ktest::run_all())))
+        } else if entry_name == "schedstat" {
+            Ok(Arc::new(ProcTextFile::new("/proc/schedstat".into(), sched_stat::report())))
+        } else if entry_name == "syscall_stats" {
+            Ok(Arc::new(ProcTextFile::new("/proc/syscall_stats".into(), syscall_stats::report())))
+        } else if entry_name == "trace_pipe" {
+            // opening takes what's recorded so far, see utils::trace. Events of every process, root only.
+            if !get_processor().current().map_or(true, |proc| proc.get_inner().cred.privileged()) {
+                return Err(ErrorNum::EACCES);
+            }
+            Ok(Arc::new(ProcTextFile::new("/proc/trace_pipe".into(), trace::drain())))
+        } else if entry_name == "mounts" {
+            Ok(Arc::new(ProcTextFile::new("/proc/mounts".into(), mounts())))
+        } else if entry_name == "files" {
+            Ok(Arc::new(ProcTextFile::new("/proc/files".into(), open_files()?)))
+        } else if entry_name == "lastcrash" {
+            // what the crash dump kept from last boot, ENOENT if it didn't crash
+            Ok(Arc::new(ProcTextFile::new("/proc/lastcrash".into(), crash_dump::last_crash().ok_or(ErrorNum::ENOENT)?)))
+        } else if entry_name == "pageowners" {
+            // live pages by owner and allocation site, debug builds only
+            Ok(Arc::new(ProcTextFile::new("/proc/pageowners".into(), page_owners())))
+        } else if entry_name == "pstore" {
+            // persistent log of milestones and fatals, across boots
+            Ok(Arc::new(ProcTextFile::new("/proc/pstore".into(), pstore_text().ok_or(ErrorNum::ENOENT)?)))
+        } else if entry_name == "fsck" {
+            // same as ktest, opening runs a check-only fsck on ParchFS. It walks every inode and block with the fs
+            // locked, so it's for root only.
+            if !get_processor().current().map_or(true, |proc| proc.get_inner().cred.privileged()) {
+                return Err(ErrorNum::EACCES);
+            }
+            Ok(Arc::new(ProcTextFile::new("/proc/fsck".into(), parch_fs_check())))
         } else if entry_name == ".." {
             Ok(Arc::new(DummyLink{
                 vfs: PROC_FS.clone(),
@@ -92,7 +147,7 @@ impl DirFile for RootDir {
         } else {
             let pid: ProcessID = entry_name.parse::<usize>().map_err(|_| ErrorNum::ENOENT)?.into();
             let _proc = get_process(pid)?;  // make sure there is such process.
-            Ok(Arc::new(PidProcDir { pid }))
+            Ok(Arc::new(PidProcDir::new(pid)))
         }
     }
 
@@ -128,6 +183,108 @@ impl DirFile for RootDir {
             f_name: "self".to_string(),
         });
 
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o555),
+            f_type: crate::fs::types::FileType::DIR,
+            f_name: "sys".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o555),
+            f_type: crate::fs::types::FileType::DIR,
+            f_name: "device-tree".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o444),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "kallsyms".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o444),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "cpuinfo".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o400),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "ktest".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o400),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "fsck".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o444),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "mounts".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o444),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "files".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o400),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "trace_pipe".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o444),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "syscall_stats".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o444),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "schedstat".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o400),
+            f_type: crate::fs::types::FileType::REGULAR,
+            f_name: "pageowners".to_string(),
+        });
+
+        if crash_dump::last_crash().is_some() {
+            result.push(Dirent {
+                inode: 0,
+                permission: Permission::from_bits_truncate(0o400),
+                f_type: crate::fs::types::FileType::REGULAR,
+                f_name: "lastcrash".to_string(),
+            });
+        }
+
+        if pstore_present() {
+            result.push(Dirent {
+                inode: 0,
+                permission: Permission::from_bits_truncate(0o400),
+                f_type: crate::fs::types::FileType::REGULAR,
+                f_name: "pstore".to_string(),
+            });
+        }
+
         let process_list = process_list();
         for pcb in process_list {
             let dentry = Dirent {
@@ -140,4 +297,71 @@ impl DirFile for RootDir {
         }
         Ok(result)
     }
-}
\ No newline at end of file
+}
+
+/// One block per present hart, online means it reached the scheduler.
+fn cpuinfo() -> String {
+    let dev_tree = DEVICE_MANAGER.acquire_r().get_dev_tree();
+    let mut res = String::new();
+    for hart in present_harts() {
+        res += &format!("processor\t: {}\n", hart);
+        res += &format!("hart\t\t: {}\n", hart);
+        res += &format!("isa\t\t: {}\n", dev_tree.hart_isa(hart).unwrap_or("unknown".to_string()));
+        res += "mmu\t\t: sv39\n";
+        res += &format!("online\t\t: {}\n\n", if hart_online(hart) {"yes"} else {"no"});
+    }
+    res
+}
+
+/// `address T name` per line, like linux.
+fn kallsyms() -> String {
+    let mut res = String::new();
+    for (addr, name) in symbols() {
+        res += &format!("{:016x} T {}\n", addr, name);
+    }
+    res
+}
+
+/// `device path type options 0 0` per mount of the caller's namespace, like linux. Device is the fs type, there's no
+/// block device behind.
+fn mounts() -> String {
+    let mut res = String::new();
+    for (path, vfs, flags) in mount_ns().snapshot().mounts() {
+        res += &format!("{} {:?} {} {} 0 0\n", vfs.fs_type(), path, vfs.fs_type(), flags.options());
+    }
+    res
+}
+
+/// Every open file object, `refs mode path pid:fd,...` per line. refs counts descriptors across all fd tables, so
+/// one that keeps growing is a leak. Only the caller's own processes unless privileged, as for /proc/<pid>/fdinfo.
+fn open_files() -> Result<String, ErrorNum> {
+    let caller = get_processor().current().map(|proc| proc.get_inner().cred);
+    let mut files: Vec<(Arc<dyn File>, Vec<(ProcessID, FileDescriptor)>)> = Vec::new();
+    for pcb in process_list() {
+        // stat might need the pcb (procfs), don't hold it
+        let table: Vec<_> = {
+            let pcb_inner = pcb.get_inner();
+            if caller.map_or(false, |cred| !cred.privileged() && cred.euid != pcb_inner.cred.euid) {
+                continue;
+            }
+            pcb_inner.files.iter().map(|(fd, file)| (*fd, file.clone())).collect()
+        };
+        for (fd, file) in table {
+            if let Some((_, holders)) = files.iter_mut().find(|(f, _)| Arc::ptr_eq(f, &file)) {
+                holders.push((pcb.pid, fd));
+            } else {
+                files.push((file, vec![(pcb.pid, fd)]));
+            }
+        }
+    }
+
+    let mut res = String::new();
+    for (file, holders) in files {
+        let holder_list: Vec<String> = holders.iter().map(|(pid, fd)| format!("{}:{}", pid.0, fd.0)).collect();
+        match file.stat() {
+            Ok(stat) => res += &format!("{} {:?} {:?} {}\n", holders.len(), stat.open_mode, stat.path, holder_list.join(",")),
+            Err(e) => res += &format!("{} ? <{:?}> {}\n", holders.len(), e, holder_list.join(",")),
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,307 @@
+use core::{fmt::Debug, sync::atomic::{AtomicUsize, Ordering}};
+
+use alloc::{sync::Arc, vec::Vec, string::{String, ToString}};
+
+use crate::{fs::{File, DirFile, types::{FileStat, Permission, FileType}, OpenMode, Dirent, DummyLink, fs_impl::parch_fs::{get_writeback_interval, set_writeback_interval, get_dirty_threshold, set_dirty_threshold}}, utils::{ErrorNum, SpinMutex, Mutex, trace::{set_trace_enabled, trace_enabled}}, mem::RANDOMIZE_VA_SPACE};
+
+use super::PROC_FS;
+
+/// name, getter, setter
+type KnobEntry = (&'static str, fn() -> usize, fn(usize) -> Result<(), ErrorNum>);
+
+/// Tunables under /proc/sys.
+const KNOBS: &[KnobEntry] = &[
+    ("randomize_va_space", get_randomize_va_space, set_randomize_va_space),
+    ("trace_events", get_trace_events, set_trace_events),
+];
+
+/// /proc/sys/vm, ParchFS writeback, see write_behind.rs
+const VM_KNOBS: &[KnobEntry] = &[
+    ("dirty_writeback_ms", get_writeback_interval, set_writeback_interval),
+    ("dirty_background_inodes", get_dirty_threshold, set_dirty_threshold),
+];
+
+/// Subdirectories of /proc/sys, one level only.
+const SUB_DIRS: &[(&str, &[KnobEntry])] = &[
+    ("vm", VM_KNOBS),
+];
+
+fn get_randomize_va_space() -> usize {
+    RANDOMIZE_VA_SPACE.load(Ordering::Relaxed)
+}
+
+fn set_randomize_va_space(val: usize) -> Result<(), ErrorNum> {
+    if val > 1 {
+        return Err(ErrorNum::EINVAL);
+    }
+    RANDOMIZE_VA_SPACE.store(val, Ordering::Relaxed);
+    Ok(())
+}
+
+fn get_trace_events() -> usize {
+    trace_enabled() as usize
+}
+
+/// 1 to start recording into /proc/trace_pipe, see utils::trace.
+fn set_trace_events(val: usize) -> Result<(), ErrorNum> {
+    if val > 1 {
+        return Err(ErrorNum::EINVAL);
+    }
+    set_trace_enabled(val == 1);
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct SysDir {
+    sub: Option<&'static str>,
+    knobs: &'static [KnobEntry],
+    cursor: AtomicUsize,
+}
+
+impl SysDir {
+    /// /proc/sys
+    pub fn root() -> Self {
+        Self{sub: None, knobs: KNOBS, cursor: AtomicUsize::new(0)}
+    }
+
+    fn path(&self) -> String {
+        match self.sub {
+            Some(sub) => format!("/proc/sys/{}", sub),
+            None => "/proc/sys".to_string(),
+        }
+    }
+}
+
+impl File for SysDir {
+    fn write(&self, _data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn read(&self, _length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EISDIR)
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::RegularFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Ok(self)
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn crate::fs::VirtualFileSystem> {
+        PROC_FS.clone()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat{
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: self.path().into(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner::public_dir())
+    }
+}
+
+impl DirFile for SysDir {
+    fn dir_cursor(&self) -> usize {
+        self.cursor.load(Ordering::Relaxed)
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        self.cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    fn open_entry(&self, entry_name: &str, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        if entry_name == ".." {
+            Ok(Arc::new(DummyLink{
+                vfs: PROC_FS.clone(),
+                link_dest: if self.sub.is_some() {"/proc/sys"} else {"/proc"}.into(),
+                self_path: format!("{}/..", self.path()).into(),
+            }))
+        } else if entry_name == "." {
+            Ok(Arc::new(DummyLink{
+                vfs: PROC_FS.clone(),
+                link_dest: self.path().into(),
+                self_path: format!("{}/.", self.path()).into(),
+            }))
+        } else if let Some((sub, knobs)) = SUB_DIRS.iter().find(|(sub, _)| self.sub.is_none() && *sub == entry_name) {
+            Ok(Arc::new(SysDir{sub: Some(*sub), knobs: *knobs, cursor: AtomicUsize::new(0)}))
+        } else {
+            let (name, get, set) = self.knobs.iter().find(|(name, _, _)| *name == entry_name).ok_or(ErrorNum::ENOENT)?;
+            Ok(Arc::new(SysKnob {
+                dir: self.path(),
+                name: *name,
+                get: *get,
+                set: *set,
+                cursor: SpinMutex::new("SysKnob cursor", 0),
+            }))
+        }
+    }
+
+    fn make_file(&self, _name: alloc::string::String, _perm: crate::fs::types::Permission, _f_type: crate::fs::types::FileType) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn remove_file(&self, _name: alloc::string::String) -> Result<(), crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read_dirent(&self) -> Result<alloc::vec::Vec<crate::fs::Dirent>, crate::utils::ErrorNum> {
+        let mut result = Vec::new();
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o440),
+            f_type: FileType::LINK,
+            f_name: ".".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o440),
+            f_type: FileType::LINK,
+            f_name: "..".to_string(),
+        });
+
+        if self.sub.is_none() {
+            for (sub, _) in SUB_DIRS {
+                result.push(Dirent {
+                    inode: 0,
+                    permission: Permission::from_bits_truncate(0o555),
+                    f_type: FileType::DIR,
+                    f_name: sub.to_string(),
+                });
+            }
+        }
+
+        for (name, _, _) in self.knobs {
+            result.push(Dirent {
+                inode: 0,
+                permission: Permission::from_bits_truncate(0o644),
+                f_type: FileType::REGULAR,
+                f_name: name.to_string(),
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// One integer tunable. Read gives the value in decimal, write parses it.
+pub struct SysKnob {
+    dir: String,
+    name: &'static str,
+    get: fn() -> usize,
+    set: fn(usize) -> Result<(), ErrorNum>,
+    cursor: SpinMutex<usize>,
+}
+
+impl Debug for SysKnob {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.dir, self.name)
+    }
+}
+
+impl File for SysKnob {
+    fn write(&self, data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        let len = data.len();
+        let val = String::from_utf8(data).map_err(|_| ErrorNum::EINVAL)?
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| ErrorNum::EINVAL)?;
+        (self.set)(val)?;
+        Ok(len)
+    }
+
+    fn read(&self, length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        let content = format!("{}\n", (self.get)()).into_bytes();
+        let mut cursor = self.cursor.acquire();
+        let start = core::cmp::min(*cursor, content.len());
+        let end = core::cmp::min(start + length, content.len());
+        *cursor = end;
+        Ok(content[start..end].to_vec())
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::RegularFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn crate::fs::VirtualFileSystem> {
+        PROC_FS.clone()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat{
+            open_mode: OpenMode::READ | OpenMode::WRITE,
+            file_size: 0,
+            path: format!("{}/{}", self.dir, self.name).into(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+}
@@ -0,0 +1,101 @@
+use alloc::{sync::Arc, vec::Vec, string::String};
+
+use crate::{fs::{File, DirFile, types::FileStat, OpenMode, Path}, utils::{ErrorNum, SpinMutex, Mutex}};
+
+use super::PROC_FS;
+
+/// Read only procfs file, content is generated when opened.
+pub struct ProcTextFile {
+    path: Path,
+    content: Vec<u8>,
+    cursor: SpinMutex<usize>,
+}
+
+impl core::fmt::Debug for ProcTextFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ProcTextFile {:?}", self.path)
+    }
+}
+
+impl ProcTextFile {
+    pub fn new(path: Path, content: String) -> Self {
+        Self {
+            path,
+            content: content.into_bytes(),
+            cursor: SpinMutex::new("ProcTextFile cursor", 0),
+        }
+    }
+
+    /// Content that isn't text, like device tree properties.
+    pub fn raw(path: Path, content: Vec<u8>) -> Self {
+        Self {
+            path,
+            content,
+            cursor: SpinMutex::new("ProcTextFile cursor", 0),
+        }
+    }
+}
+
+impl File for ProcTextFile {
+    fn write(&self, _data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn read(&self, length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        let mut cursor = self.cursor.acquire();
+        let start = core::cmp::min(*cursor, self.content.len());
+        let end = core::cmp::min(start + length, self.content.len());
+        *cursor = end;
+        Ok(self.content[start..end].to_vec())
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::RegularFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn crate::fs::VirtualFileSystem> {
+        PROC_FS.clone()
+    }
+
+    fn stat(&self) -> Result<crate::fs::types::FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat {
+            open_mode: OpenMode::READ,
+            file_size: self.content.len(),
+            path: self.path.clone(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+}
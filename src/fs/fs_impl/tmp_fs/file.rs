@@ -0,0 +1,474 @@
+use core::{fmt::Debug, cmp::min};
+
+use alloc::{sync::{Arc, Weak}, collections::BTreeMap, string::{String, ToString}, vec::Vec};
+
+use crate::{fs::{File, DirFile, RegularFile, SeekWhence, LinkFile, Dirent, DummyLink, OpenMode, Path, VirtualFileSystem, types::{FileStat, FileType, Permission}}, mem::{PageGuard, PageOwner, PhysAddr, try_alloc_vm_page}, utils::{SpinMutex, Mutex, ErrorNum}, config::PAGE_SIZE};
+
+use super::TmpFS;
+
+enum TmpContent {
+    Dir(BTreeMap<String, Arc<TmpINode>>),
+    /// pages by index, and byte length. Sparse, a missing page inside the length reads as zeros.
+    Regular(BTreeMap<usize, PageGuard>, usize),
+    Link(Path),
+}
+
+struct TmpINodeInner {
+    perm: Permission,
+    content: TmpContent,
+}
+
+/// One file in TmpFS. Lives as long as its directory entry or any open TmpFile holds it.
+pub struct TmpINode {
+    pub inode: u32,
+    f_type: FileType,
+    /// dangling for fs root
+    parent: Weak<TmpINode>,
+    inner: SpinMutex<TmpINodeInner>,
+}
+
+impl TmpINode {
+    pub fn new(inode: u32, f_type: FileType, perm: Permission, parent: Weak<TmpINode>) -> Arc<Self> {
+        let content = match f_type {
+            FileType::DIR => TmpContent::Dir(BTreeMap::new()),
+            FileType::LINK => TmpContent::Link(Path::root()),
+            _ => TmpContent::Regular(BTreeMap::new(), 0),
+        };
+        Arc::new(Self {
+            inode,
+            f_type,
+            parent,
+            inner: SpinMutex::new("TmpINode", TmpINodeInner { perm, content }),
+        })
+    }
+
+    fn new_page(&self) -> Result<PageGuard, ErrorNum> {
+        let page = try_alloc_vm_page()?.with_owner(PageOwner::Inode(self.inode));
+        unsafe{page.ppn.clear_content();}
+        Ok(page)
+    }
+
+    fn read_at(&self, offset: usize, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        let inner = self.inner.acquire();
+        let (pages, size) = match &inner.content {
+            TmpContent::Regular(pages, size) => (pages, *size),
+            TmpContent::Dir(_) => return Err(ErrorNum::EISDIR),
+            TmpContent::Link(_) => return Err(ErrorNum::EBADTYPE),
+        };
+        let start = min(offset, size);
+        let end = min(offset.saturating_add(length), size);
+        let mut res = Vec::with_capacity(end - start);
+        let mut pos = start;
+        while pos < end {
+            let in_page = pos % PAGE_SIZE;
+            let len = min(PAGE_SIZE - in_page, end - pos);
+            match pages.get(&(pos / PAGE_SIZE)) {
+                Some(page) => {
+                    let src = (PhysAddr::from(page.ppn) + in_page).0 as *const u8;
+                    res.extend_from_slice(unsafe{core::slice::from_raw_parts(src, len)});
+                },
+                None => res.resize(res.len() + len, 0),
+            }
+            pos += len;
+        }
+        Ok(res)
+    }
+
+    /// Only the pages written to are allocated, a write far past the end leaves a hole.
+    fn write_at(&self, offset: usize, data: &[u8]) -> Result<usize, ErrorNum> {
+        let end = offset.checked_add(data.len()).ok_or(ErrorNum::EFBIG)?;
+        let mut inner = self.inner.acquire();
+        let (pages, size) = match &mut inner.content {
+            TmpContent::Regular(pages, size) => (pages, size),
+            TmpContent::Dir(_) => return Err(ErrorNum::EISDIR),
+            TmpContent::Link(_) => return Err(ErrorNum::EBADTYPE),
+        };
+        // all or nothing, new pages only go in once every one of them is there
+        let mut new_pages = Vec::new();
+        if end > offset {
+            for idx in offset / PAGE_SIZE..=(end - 1) / PAGE_SIZE {
+                if !pages.contains_key(&idx) {
+                    new_pages.push((idx, self.new_page()?));
+                }
+            }
+        }
+        pages.extend(new_pages);
+        let mut pos = offset;
+        while pos < end {
+            let in_page = pos % PAGE_SIZE;
+            let len = min(PAGE_SIZE - in_page, end - pos);
+            let dst = (PhysAddr::from(pages[&(pos / PAGE_SIZE)].ppn) + in_page).0 as *mut u8;
+            unsafe{core::ptr::copy_nonoverlapping(data[pos - offset..].as_ptr(), dst, len)};
+            pos += len;
+        }
+        *size = core::cmp::max(*size, end);
+        Ok(data.len())
+    }
+
+    /// Grow with a hole, or shrink.
+    fn set_size(&self, new_size: usize) -> Result<(), ErrorNum> {
+        let mut inner = self.inner.acquire();
+        let (pages, size) = match &mut inner.content {
+            TmpContent::Regular(pages, size) => (pages, size),
+            TmpContent::Dir(_) => return Err(ErrorNum::EISDIR),
+            TmpContent::Link(_) => return Err(ErrorNum::EBADTYPE),
+        };
+        if new_size < *size {
+            let page_count = (new_size + PAGE_SIZE - 1) / PAGE_SIZE;
+            pages.retain(|idx, _| *idx < page_count);
+            // rest of the last page must read as zero if it grows again
+            let in_page = new_size % PAGE_SIZE;
+            if in_page != 0 {
+                if let Some(page) = pages.get(&(new_size / PAGE_SIZE)) {
+                    let dst = (PhysAddr::from(page.ppn) + in_page).0 as *mut u8;
+                    unsafe{core::ptr::write_bytes(dst, 0, PAGE_SIZE - in_page)};
+                }
+            }
+        }
+        *size = new_size;
+        Ok(())
+    }
+
+    /// Zeroed in place rather than dropped, as a shared mapping may hold the page.
+    fn zero_range(&self, offset: usize, len: usize) -> Result<(), ErrorNum> {
+        let inner = self.inner.acquire();
+        let (pages, size) = match &inner.content {
+            TmpContent::Regular(pages, size) => (pages, *size),
+            TmpContent::Dir(_) => return Err(ErrorNum::EISDIR),
+            TmpContent::Link(_) => return Err(ErrorNum::EBADTYPE),
+        };
+        let end = min(offset.saturating_add(len), size);
+        let mut pos = offset;
+        while pos < end {
+            let in_page = pos % PAGE_SIZE;
+            let len = min(PAGE_SIZE - in_page, end - pos);
+            if let Some(page) = pages.get(&(pos / PAGE_SIZE)) {
+                let dst = (PhysAddr::from(page.ppn) + in_page).0 as *mut u8;
+                unsafe{core::ptr::write_bytes(dst, 0, len)};
+            }
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Page at index `idx` for a shared mapping, filling a hole with a zeroed page. EOOR past the end.
+    fn map_page(&self, idx: usize) -> Result<PageGuard, ErrorNum> {
+        let mut inner = self.inner.acquire();
+        let (pages, size) = match &mut inner.content {
+            TmpContent::Regular(pages, size) => (pages, *size),
+            _ => return Err(ErrorNum::EBADTYPE),
+        };
+        if idx * PAGE_SIZE >= size {
+            return Err(ErrorNum::EOOR);
+        }
+        if let Some(page) = pages.get(&idx) {
+            return Ok(page.clone());
+        }
+        let page = self.new_page()?;
+        pages.insert(idx, page.clone());
+        Ok(page)
+    }
+
+    fn size(&self) -> usize {
+        match &self.inner.acquire().content {
+            TmpContent::Regular(_, size) => *size,
+            TmpContent::Dir(entries) => entries.len(),
+            TmpContent::Link(_) => 0,
+        }
+    }
+}
+
+/// Opened TmpINode, with its own cursor.
+pub struct TmpFile {
+    fs: Arc<TmpFS>,
+    node: Arc<TmpINode>,
+    path: Path,
+    open_mode: OpenMode,
+    cursor: SpinMutex<usize>,
+}
+
+impl Debug for TmpFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "TmpFile {:?}", self.path)
+    }
+}
+
+impl TmpFile {
+    pub fn new(fs: Arc<TmpFS>, node: Arc<TmpINode>, path: Path, open_mode: OpenMode) -> Self {
+        Self {
+            fs,
+            node,
+            path,
+            open_mode,
+            cursor: SpinMutex::new("TmpFile cursor", 0),
+        }
+    }
+
+    fn open_node(&self, node: Arc<TmpINode>, path: Path, mode: OpenMode) -> Arc<dyn File> {
+        Arc::new(Self::new(self.fs.clone(), node, path, mode))
+    }
+
+    /// EBADF unless opened for `want`, kernel opens can do anything.
+    fn check_mode(&self, want: OpenMode) -> Result<(), ErrorNum> {
+        if self.open_mode.contains(OpenMode::SYS) || self.open_mode.contains(want) {
+            Ok(())
+        } else {
+            Err(ErrorNum::EBADF)
+        }
+    }
+}
+
+impl File for TmpFile {
+    fn write(&self, data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
+        self.check_mode(OpenMode::WRITE)?;
+        let mut cursor = self.cursor.acquire();
+        let len = self.node.write_at(*cursor, &data)?;
+        *cursor += len;
+        Ok(len)
+    }
+
+    fn read(&self, length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
+        self.check_mode(OpenMode::READ)?;
+        let mut cursor = self.cursor.acquire();
+        let res = self.node.read_at(*cursor, length)?;
+        *cursor += res.len();
+        Ok(res)
+    }
+
+    fn as_socket<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::SocketFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn LinkFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        if self.node.f_type == FileType::LINK {Ok(self)} else {Err(ErrorNum::EBADTYPE)}
+    }
+
+    fn as_regular<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn RegularFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        if self.node.f_type == FileType::REGULAR {Ok(self)} else {Err(ErrorNum::EBADTYPE)}
+    }
+
+    fn as_block<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::BlockFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn DirFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        if self.node.f_type == FileType::DIR {Ok(self)} else {Err(ErrorNum::EBADTYPE)}
+    }
+
+    fn as_char<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::CharFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo<'a>(self: alloc::sync::Arc<Self>) -> Result<alloc::sync::Arc<dyn crate::fs::FIFOFile + 'a>, crate::utils::ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file<'a>(self: alloc::sync::Arc<Self>) -> alloc::sync::Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs(&self) -> alloc::sync::Arc<dyn VirtualFileSystem> {
+        self.fs.clone()
+    }
+
+    fn stat(&self) -> Result<FileStat, crate::utils::ErrorNum> {
+        Ok(FileStat {
+            open_mode: self.open_mode,
+            file_size: self.node.size(),
+            path: self.path.clone(),
+            inode: self.node.inode,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    fn reopen(&self, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        Ok(self.open_node(self.node.clone(), self.path.clone(), mode))
+    }
+
+    /// no owners kept, root's with the mode it was made with
+    fn owner(&self) -> Result<crate::fs::FileOwner, ErrorNum> {
+        Ok(crate::fs::FileOwner { uid: 0, gid: 0, permission: self.node.inner.acquire().perm })
+    }
+}
+
+impl DirFile for TmpFile {
+    /// the same cursor as a regular file's, a node is never both
+    fn dir_cursor(&self) -> usize {
+        *self.cursor.acquire()
+    }
+
+    fn set_dir_cursor(&self, cursor: usize) {
+        *self.cursor.acquire() = cursor;
+    }
+
+    fn open_entry(&self, entry_name: &str, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        if entry_name == "." {
+            return Ok(self.open_node(self.node.clone(), self.path.clone(), mode));
+        }
+        if entry_name == ".." {
+            return match self.node.parent.upgrade() {
+                Some(parent) => Ok(self.open_node(parent, self.path.strip_tail(), mode)),
+                // fs root, go back to where we are mounted
+                None => Ok(Arc::new(DummyLink {
+                    vfs: self.fs.clone(),
+                    link_dest: self.path.strip_tail(),
+                    self_path: self.path.append("..".to_string())?,
+                })),
+            };
+        }
+        let child = match &self.node.inner.acquire().content {
+            TmpContent::Dir(entries) => entries.get(entry_name).cloned(),
+            _ => return Err(ErrorNum::ENOTDIR),
+        };
+        match child {
+            Some(child) => Ok(self.open_node(child, self.path.append(entry_name.to_string())?, mode)),
+            None if mode.contains(OpenMode::CREATE) => {
+                self.make_file(entry_name.to_string(), Permission::default(), FileType::REGULAR)?;
+                self.open_entry(entry_name, mode)
+            },
+            None => Err(ErrorNum::ENOENT),
+        }
+    }
+
+    fn make_file(&self, name: String, perm: Permission, f_type: FileType) -> Result<Arc<dyn File>, ErrorNum> {
+        if f_type != FileType::REGULAR && f_type != FileType::DIR && f_type != FileType::LINK {
+            return Err(ErrorNum::EBADTYPE);
+        }
+        if name == "." || name == ".." {
+            return Err(ErrorNum::EEXIST);
+        }
+        let path = self.path.append(name.clone())?;
+        let node = match &mut self.node.inner.acquire().content {
+            TmpContent::Dir(entries) => {
+                // before taking an inode number, a failed create uses up nothing
+                if entries.contains_key(&name) {
+                    return Err(ErrorNum::EEXIST);
+                }
+                let node = TmpINode::new(self.fs.alloc_inode(), f_type, perm, Arc::downgrade(&self.node));
+                entries.insert(name, node.clone());
+                node
+            },
+            _ => return Err(ErrorNum::ENOTDIR),
+        };
+        Ok(self.open_node(node, path, OpenMode::SYS))
+    }
+
+    fn remove_file(&self, name: String) -> Result<(), ErrorNum> {
+        let mut inner = self.node.inner.acquire();
+        let entries = match &mut inner.content {
+            TmpContent::Dir(entries) => entries,
+            _ => return Err(ErrorNum::ENOTDIR),
+        };
+        let child = entries.get(&name).ok_or(ErrorNum::ENOENT)?;
+        if let TmpContent::Dir(grand_children) = &child.inner.acquire().content {
+            if !grand_children.is_empty() {
+                return Err(ErrorNum::ENOTEMPTY);
+            }
+        }
+        // content is freed on last close
+        entries.remove(&name);
+        Ok(())
+    }
+
+    fn read_dirent(&self) -> Result<Vec<Dirent>, ErrorNum> {
+        let mut result = Vec::new();
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o440),
+            f_type: FileType::LINK,
+            f_name: ".".to_string(),
+        });
+
+        result.push(Dirent {
+            inode: 0,
+            permission: Permission::from_bits_truncate(0o440),
+            f_type: FileType::LINK,
+            f_name: "..".to_string(),
+        });
+
+        match &self.node.inner.acquire().content {
+            TmpContent::Dir(entries) => {
+                for (name, node) in entries.iter() {
+                    result.push(Dirent {
+                        inode: node.inode,
+                        permission: node.inner.acquire().perm,
+                        f_type: node.f_type,
+                        f_name: name.clone(),
+                    });
+                }
+            },
+            _ => return Err(ErrorNum::ENOTDIR),
+        }
+        Ok(result)
+    }
+}
+
+impl RegularFile for TmpFile {
+    fn read_at(&self, offset: usize, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        self.check_mode(OpenMode::READ)?;
+        self.node.read_at(offset, length)
+    }
+
+    fn write_at(&self, offset: usize, data: Vec<u8>) -> Result<usize, ErrorNum> {
+        self.check_mode(OpenMode::WRITE)?;
+        self.node.write_at(offset, &data)
+    }
+
+    fn copy_page(&self, offset: usize) -> Result<PageGuard, ErrorNum> {
+        let data = self.node.read_at(offset, PAGE_SIZE)?;
+        let page = try_alloc_vm_page()?.with_owner(PageOwner::Inode(self.node.inode));
+        unsafe {
+            page.ppn.clear_content();
+            core::ptr::copy_nonoverlapping(data.as_ptr(), PhysAddr::from(page.ppn).0 as *mut u8, data.len());
+        }
+        Ok(page)
+    }
+
+    /// Holes are memory here too, they are always filled.
+    fn get_page(&self, offset: usize, _fill: bool) -> Result<Option<PageGuard>, ErrorNum> {
+        if offset % PAGE_SIZE != 0 {
+            return Err(ErrorNum::ENOTALIGNED);
+        }
+        self.node.map_page(offset / PAGE_SIZE).map(Some)
+    }
+
+    fn seek(&self, offset: isize, whence: SeekWhence) -> Result<usize, ErrorNum> {
+        let mut cursor = self.cursor.acquire();
+        *cursor = whence.resolve(offset, *cursor, self.node.size())?;
+        Ok(*cursor)
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), ErrorNum> {
+        self.check_mode(OpenMode::WRITE)?;
+        self.node.set_size(size)
+    }
+
+    fn punch_hole(&self, offset: usize, len: usize) -> Result<(), ErrorNum> {
+        self.check_mode(OpenMode::WRITE)?;
+        self.node.zero_range(offset, len)
+    }
+}
+
+impl LinkFile for TmpFile {
+    fn read_link(&self) -> Result<Path, ErrorNum> {
+        match &self.node.inner.acquire().content {
+            TmpContent::Link(dest) => Ok(dest.clone()),
+            _ => Err(ErrorNum::EBADTYPE),
+        }
+    }
+
+    fn write_link(&self, path: &Path) -> Result<(), ErrorNum> {
+        match &mut self.node.inner.acquire().content {
+            TmpContent::Link(dest) => {
+                *dest = path.clone();
+                Ok(())
+            },
+            _ => Err(ErrorNum::EBADTYPE),
+        }
+    }
+}
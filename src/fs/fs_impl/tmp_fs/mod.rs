@@ -0,0 +1,107 @@
+mod file;
+
+use core::{fmt::Debug, sync::atomic::{AtomicU32, Ordering}};
+
+use alloc::sync::{Arc, Weak};
+use lazy_static::*;
+
+use crate::{config::{PAGE_SIZE, PHYS_END_ADDR}, fs::{VirtualFileSystem, FsStat, Path, OpenMode, DirFile, File, RegularFile, types::{Permission, FileType}}, mem::stat_mem, utils::{ErrorNum, UUID}};
+
+pub use file::{TmpINode, TmpFile};
+
+/// Filesystem living only in memory. Every node is a TmpINode tree node, regular file content is a sparse map of
+/// vm pages, so mmap shares them directly. Used as root when booting from initramfs.
+pub struct TmpFS {
+    uuid: UUID,
+    mount_path: Path,
+    root: Arc<TmpINode>,
+    next_inode: AtomicU32,
+    self_ref: Weak<TmpFS>,
+}
+
+impl Debug for TmpFS {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "TmpFS on {:?}", self.mount_path)
+    }
+}
+
+impl TmpFS {
+    pub fn new(mount_path: Path) -> Arc<Self> {
+        Self::with_root_perm(mount_path, Permission::from_bits_truncate(0o755))
+    }
+
+    pub fn with_root_perm(mount_path: Path, perm: Permission) -> Arc<Self> {
+        Arc::new_cyclic(|self_ref| Self {
+            uuid: UUID::new(),
+            mount_path,
+            root: TmpINode::new(1, FileType::DIR, perm, Weak::new()),
+            next_inode: AtomicU32::new(2),
+            self_ref: self_ref.clone(),
+        })
+    }
+
+    pub fn alloc_inode(&self) -> u32 {
+        self.next_inode.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+lazy_static!{
+    /// Owns the files of anon_file, which are in no directory of it.
+    static ref ANON_FS: Arc<TmpFS> = TmpFS::new("/".into());
+}
+
+/// Empty read only file in no directory, stat gives `path`. Image of kernel threads.
+pub fn anon_file(path: Path) -> Arc<dyn RegularFile> {
+    let node = TmpINode::new(ANON_FS.alloc_inode(), FileType::REGULAR, Permission::from_bits_truncate(0o444), Weak::new());
+    Arc::new(TmpFile::new(ANON_FS.clone(), node, path, OpenMode::READ))
+}
+
+impl VirtualFileSystem for TmpFS {
+    fn link(&self, _dest: Arc<dyn File>, _link_file: &Path) -> Result<Arc<dyn File>, ErrorNum> {
+        Err(ErrorNum::EPERM)
+    }
+
+    fn mount_path(&self) -> Path {
+        self.mount_path.clone()
+    }
+
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn get_uuid(&self) -> UUID {
+        self.uuid
+    }
+
+    fn root_dir(&self, mode: OpenMode) -> Result<Arc<dyn DirFile>, ErrorNum> {
+        let fs = self.self_ref.upgrade().unwrap();
+        Ok(Arc::new(TmpFile::new(fs, self.root.clone(), self.mount_path.clone(), mode)))
+    }
+
+    /// Content is vm pages, so the room left is the memory left, shared with everything else. Inode numbers are
+    /// never reused, what's left of them is what's left of u32.
+    fn statfs(&self) -> Result<FsStat, ErrorNum> {
+        extern "C" {
+            fn ekernel();
+        }
+        let (fs_usage, mm_usage) = stat_mem();
+        let total = PHYS_END_ADDR.0 - ekernel as usize;
+        let used_inodes = self.next_inode.load(Ordering::Relaxed) as usize;
+        Ok(FsStat {
+            block_size: PAGE_SIZE,
+            blocks: total / PAGE_SIZE,
+            free_blocks: total.saturating_sub(fs_usage + mm_usage) / PAGE_SIZE,
+            inodes: u32::MAX as usize,
+            free_inodes: (u32::MAX as usize).saturating_sub(used_inodes),
+            name_max: 0,
+        })
+    }
+
+    fn as_vfs<'a>(self: Arc<Self>) -> Arc<dyn VirtualFileSystem + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+}
@@ -0,0 +1,130 @@
+//! initramfs loader. Unpacks the CPIO newc archive QEMU loads with `-initrd` into the root TmpFS.
+//!
+//! The location comes from `linux,initrd-start` / `linux,initrd-end` in /chosen, so the embedded dtb must be dumped
+//! with the same `-initrd` argument the kernel is booted with.
+
+use alloc::string::String;
+
+use crate::{device::initrd_range, mem::release_boot_reserved, utils::ErrorNum};
+
+use super::{MOUNT_MANAGER, OpenMode, Path, Permission, FileType};
+
+const NEWC_MAGIC: &[u8] = b"070701";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32   = 0o170000;
+const S_IFDIR: u32  = 0o040000;
+const S_IFREG: u32  = 0o100000;
+const S_IFLNK: u32  = 0o120000;
+
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+/// Parse the idx-th 8 char hex field following magic.
+fn header_field(header: &[u8], idx: usize) -> Result<u32, ErrorNum> {
+    let start = NEWC_MAGIC.len() + idx * 8;
+    let field = core::str::from_utf8(&header[start..start + 8]).map_err(|_| ErrorNum::EINVAL)?;
+    u32::from_str_radix(field, 16).map_err(|_| ErrorNum::EINVAL)
+}
+
+struct CpioEntry<'a> {
+    name: String,
+    mode: u32,
+    data: &'a [u8],
+}
+
+/// Entry at offset, and the offset of next one.
+fn parse_entry(archive: &[u8], offset: usize) -> Result<(CpioEntry, usize), ErrorNum> {
+    let header = archive.get(offset..offset + HEADER_SIZE).ok_or(ErrorNum::EINVAL)?;
+    if &header[0..NEWC_MAGIC.len()] != NEWC_MAGIC {
+        error!("Bad cpio magic at offset {:#x}", offset);
+        return Err(ErrorNum::EINVAL);
+    }
+    let mode = header_field(header, 1)?;
+    let file_size = header_field(header, 6)? as usize;
+    let name_size = header_field(header, 11)? as usize;
+
+    let name_start = offset + HEADER_SIZE;
+    // name_size counts the trailing \0
+    let name = archive.get(name_start..name_start + name_size.saturating_sub(1)).ok_or(ErrorNum::EINVAL)?;
+    let name = String::from(core::str::from_utf8(name).map_err(|_| ErrorNum::EINVAL)?);
+    let data_start = align4(name_start + name_size);
+    let data = archive.get(data_start..data_start + file_size).ok_or(ErrorNum::EINVAL)?;
+    Ok((CpioEntry{name, mode, data}, align4(data_start + file_size)))
+}
+
+fn unpack_entry(entry: &CpioEntry) -> Result<(), ErrorNum> {
+    let mm = MOUNT_MANAGER.snapshot();
+    let path: Path = alloc::format!("/{}", entry.name).as_str().into();
+    let perm = Permission::from_bits_truncate((entry.mode & 0o7777) as u16);
+    match entry.mode & S_IFMT {
+        S_IFDIR => {
+            match mm.make_file(&path, perm, FileType::DIR) {
+                Ok(()) | Err(ErrorNum::EEXIST) => Ok(()),
+                Err(e) => Err(e)
+            }
+        },
+        S_IFREG => {
+            mm.make_file(&path, perm, FileType::REGULAR)?;
+            let file = mm.open(&path, OpenMode::WRITE | OpenMode::SYS)?;
+            let mut written = 0;
+            while written < entry.data.len() {
+                written += file.write(entry.data[written..].to_vec())?;
+            }
+            Ok(())
+        },
+        S_IFLNK => {
+            let target = core::str::from_utf8(entry.data).map_err(|_| ErrorNum::EINVAL)?;
+            mm.sym_link(&Path::new(target)?, &path, perm)?;
+            Ok(())
+        },
+        _ => {
+            warning!("initramfs: skipping {:?}, unsupported mode {:#o}", path, entry.mode);
+            Ok(())
+        }
+    }
+}
+
+fn unpack(archive: &[u8]) -> Result<usize, ErrorNum> {
+    let mut offset = 0;
+    let mut count = 0;
+    loop {
+        let (entry, next) = parse_entry(archive, offset)?;
+        if entry.name == TRAILER {
+            break;
+        }
+        if entry.name != "." && !entry.name.is_empty() {
+            verbose!("initramfs: {} mode {:#o} size {}", entry.name, entry.mode, entry.data.len());
+            unpack_entry(&entry).map_err(|e| {
+                error!("initramfs: failed to unpack {}: {:?}", entry.name, e);
+                e
+            })?;
+            count += 1;
+        }
+        offset = next;
+    }
+    Ok(count)
+}
+
+/// Unpack initrd into root, then give its pages back to the allocator. Without an initrd, or with a malformed one,
+/// root stays empty, or has what was unpacked before the bad entry. Mount points still go on it, so we get as far as
+/// a shell on /dev to look around.
+pub fn load() {
+    let (start, end) = match initrd_range() {
+        Some(range) => range,
+        None => {
+            error!("Booting from initramfs but no initrd found in device tree, root is empty.");
+            return;
+        }
+    };
+    let archive = unsafe {
+        core::slice::from_raw_parts(start.0 as *const u8, end.0 - start.0)
+    };
+    match unpack(archive) {
+        Ok(count) => milestone!("initramfs unpacked, {} entries from {:?} - {:?}", count, start, end),
+        Err(e) => error!("Failed to unpack initramfs from {:?} - {:?}: {:?}, keeping what was unpacked.", start, end, e),
+    }
+    release_boot_reserved();
+}
@@ -0,0 +1,268 @@
+//! inotify: watches on inodes, events read from an fd. A watch is on (fs uuid, inode number), a directory's one
+//! also hears about its entries, with their name. Filesystems report with `inotify_event`, only ParchFS does so far.
+//! The fs calls `inotify_forget` when it frees an inode, so its watches end with IN_IGNORED rather than carry on to
+//! whatever reuses the number.
+//! Events don't pile up without bound, past INOTIFY_QUEUE_MAX the rest are dropped and one IN_Q_OVERFLOW is queued.
+
+use alloc::{collections::VecDeque, string::String, sync::{Arc, Weak}, vec::Vec};
+use core::{fmt::Debug, sync::atomic::{AtomicUsize, Ordering}};
+use lazy_static::*;
+
+use crate::{config::INOTIFY_QUEUE_MAX, fs::{File, types::FileStat, OpenMode, Path}, process::Waker, utils::{Condvar, ErrorNum, Mutex, SpinMutex, UUID}};
+
+use super::open;
+
+pub const IN_MODIFY     : u32 = 0x0002;
+pub const IN_MOVED_FROM : u32 = 0x0040;
+pub const IN_MOVED_TO   : u32 = 0x0080;
+pub const IN_CREATE     : u32 = 0x0100;
+pub const IN_DELETE     : u32 = 0x0200;
+pub const IN_Q_OVERFLOW : u32 = 0x4000;
+pub const IN_IGNORED    : u32 = 0x8000;
+/// events a watch can ask for, rename ones once there's a rename
+pub const IN_ALL_EVENTS : u32 = IN_MODIFY | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+
+struct Watch {
+    fs: UUID,
+    inode: u32,
+    mask: u32,
+    wd: u32,
+    target: Weak<Inotify>,
+}
+
+lazy_static!{
+    static ref WATCHES: SpinMutex<Vec<Watch>> = SpinMutex::new("inotify watches", Vec::new());
+}
+
+/// WATCHES length, so writes skip the lock while nobody watches anything
+static WATCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+struct Event {
+    wd: u32,
+    mask: u32,
+    name: Option<String>,
+}
+
+impl Event {
+    /// struct inotify_event: wd, mask, cookie, len, then name NUL padded to 4 bytes.
+    fn encode(&self) -> Vec<u8> {
+        let name = self.name.as_deref().unwrap_or("").as_bytes();
+        let len = if name.is_empty() {0} else {(name.len() + 1 + 3) & !3};
+        let mut res = Vec::with_capacity(16 + len);
+        res.extend_from_slice(&self.wd.to_ne_bytes());
+        res.extend_from_slice(&self.mask.to_ne_bytes());
+        res.extend_from_slice(&0u32.to_ne_bytes());
+        res.extend_from_slice(&(len as u32).to_ne_bytes());
+        res.extend_from_slice(name);
+        res.resize(16 + len, 0);
+        res
+    }
+}
+
+/// `mask` happened to inode `inode` of fs `fs`, or to its entry `name` if it's a directory. Only takes spin locks,
+/// so fine with the file locked.
+pub fn inotify_event(fs: UUID, inode: u32, mask: u32, name: Option<&str>) {
+    if WATCH_COUNT.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let hit: Vec<(Arc<Inotify>, u32)> = WATCHES.acquire().iter()
+        .filter(|w| w.fs == fs && w.inode == inode && w.mask & mask != 0)
+        .filter_map(|w| w.target.upgrade().map(|t| (t, w.wd)))
+        .collect();
+    for (target, wd) in hit {
+        target.push(Event{wd, mask, name: name.map(String::from)});
+    }
+}
+
+/// Inode `inode` of fs `fs` is freed, its number may be handed out again. Its watches are dropped, each one with an
+/// IN_IGNORED. Only takes spin locks, like inotify_event.
+pub fn inotify_forget(fs: UUID, inode: u32) {
+    if WATCH_COUNT.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut gone: Vec<(Weak<Inotify>, u32)> = Vec::new();
+    let mut watches = WATCHES.acquire();
+    watches.retain(|w| {
+        if w.fs == fs && w.inode == inode {
+            gone.push((w.target.clone(), w.wd));
+            false
+        } else {
+            true
+        }
+    });
+    WATCH_COUNT.store(watches.len(), Ordering::Relaxed);
+    // the last ref of an Inotify may go here, its drop takes WATCHES
+    drop(watches);
+    for (target, wd) in gone {
+        if let Some(target) = target.upgrade() {
+            target.push(Event{wd, mask: IN_IGNORED, name: None});
+        }
+    }
+}
+
+pub struct Inotify {
+    events: SpinMutex<VecDeque<Event>>,
+    /// notified on event
+    read_cond: Condvar,
+    next_wd: AtomicUsize,
+    nonblock: bool,
+    self_ref: Weak<Inotify>,
+}
+
+impl Inotify {
+    pub fn new(nonblock: bool) -> Arc<Self> {
+        Arc::new_cyclic(|self_ref| Self {
+            events: SpinMutex::new("inotify events", VecDeque::new()),
+            read_cond: Condvar::new("inotify read"),
+            next_wd: AtomicUsize::new(1),
+            nonblock,
+            self_ref: self_ref.clone(),
+        })
+    }
+
+    /// Watch what `file` is for the events in `mask`, replacing the mask if it's watched already. Returns the
+    /// watch descriptor, what its events carry.
+    pub fn add_watch(&self, file: &Arc<dyn File>, mask: u32) -> Result<u32, ErrorNum> {
+        let mask = mask & IN_ALL_EVENTS;
+        if mask == 0 {
+            return Err(ErrorNum::EINVAL);
+        }
+        let stat = file.stat()?;
+        let fs = stat.fs.upgrade().ok_or(ErrorNum::ENOENT)?.get_uuid();
+        let mut watches = WATCHES.acquire();
+        let this = self.self_ref.as_ptr();
+        if let Some(w) = watches.iter_mut().find(|w| w.target.as_ptr() == this && w.fs == fs && w.inode == stat.inode) {
+            w.mask = mask;
+            return Ok(w.wd);
+        }
+        let wd = self.next_wd.fetch_add(1, Ordering::Relaxed) as u32;
+        watches.push(Watch{fs, inode: stat.inode, mask, wd, target: self.self_ref.clone()});
+        WATCH_COUNT.store(watches.len(), Ordering::Relaxed);
+        Ok(wd)
+    }
+
+    /// EINVAL if it's not one of ours.
+    pub fn rm_watch(&self, wd: u32) -> Result<(), ErrorNum> {
+        let mut watches = WATCHES.acquire();
+        let this = self.self_ref.as_ptr();
+        let idx = watches.iter().position(|w| w.target.as_ptr() == this && w.wd == wd).ok_or(ErrorNum::EINVAL)?;
+        watches.remove(idx);
+        WATCH_COUNT.store(watches.len(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn push(&self, event: Event) {
+        let mut events = self.events.acquire();
+        if events.len() < INOTIFY_QUEUE_MAX {
+            events.push_back(event);
+        } else if events.back().map_or(true, |e| e.mask != IN_Q_OVERFLOW) {
+            events.push_back(Event{wd: u32::MAX, mask: IN_Q_OVERFLOW, name: None});
+        }
+        drop(events);
+        self.read_cond.notify_all();
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        let mut watches = WATCHES.acquire();
+        let this: *const Inotify = self;
+        watches.retain(|w| w.target.as_ptr() != this);
+        WATCH_COUNT.store(watches.len(), Ordering::Relaxed);
+    }
+}
+
+impl Debug for Inotify {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "inotify, {} events queued", self.events.acquire().len())
+    }
+}
+
+impl File for Inotify {
+    fn write (&self, _data: Vec<u8>) -> Result<usize, ErrorNum> {
+        Err(ErrorNum::EINVAL)
+    }
+
+    /// As many whole events as fit, blocking until there's one. EAGAIN then if nonblocking, EINVAL if the next one
+    /// doesn't fit at all.
+    fn read (&self, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        let mut events = self.events.acquire();
+        while events.is_empty() {
+            if self.nonblock {
+                return Err(ErrorNum::EAGAIN);
+            }
+            events = self.read_cond.wait(events);
+        }
+        let mut res = Vec::new();
+        while let Some(event) = events.front() {
+            let record = event.encode();
+            if res.len() + record.len() > length {
+                break;
+            }
+            res.extend_from_slice(&record);
+            events.pop_front();
+        }
+        if res.is_empty() {
+            return Err(ErrorNum::EINVAL);
+        }
+        Ok(res)
+    }
+
+    fn as_socket <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file <'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any <'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs (&self) -> Arc<dyn crate::fs::VirtualFileSystem> {
+        open(&"proc".into(), OpenMode::SYS).unwrap().vfs()
+    }
+
+    fn stat (&self) -> Result<FileStat, ErrorNum> {
+        Ok(FileStat {
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: Path::new("[inotify]").unwrap(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    fn poll_read_ready (&self) -> bool {
+        !self.events.acquire().is_empty()
+    }
+
+    fn register_waker (&self, waker: &Arc<Waker>) {
+        self.read_cond.register_waker(waker);
+    }
+}
@@ -0,0 +1,658 @@
+//! Self tests for ParchFS and pipes, see utils::ktest.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{config::{PAGE_SIZE, PIPE_DEFAULT_SIZE, PATH_MAX, NAME_MAX}, mem::{MemLayout, MMAPType, VirtPageNum}, process::{Waker, Credentials, get_processor, process_list}, utils::{ErrorNum, ktest::KTestResult}};
+
+use super::{open, open_searching, mount_ns, MountFlags, make_file, delete, new_pipe, PipeEnd, EventFd, Inotify, IN_CREATE, IN_DELETE, IN_MODIFY, IN_IGNORED, mount_flags, bind_mount, umount, parch_fs_get_quota, parch_fs_set_quota, File, OpenMode, Permission, FileType, Path, SeekWhence, QuotaLimits};
+
+fn parch_fs_create_resize_remove() -> KTestResult {
+    let path: Path = "/ktest_tmp".into();
+    // leftover from an aborted run
+    let _ = delete(&path);
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    kassert!(file.stat().map(|stat| stat.file_size) == Ok(0));
+
+    // grow across page boundaries
+    let data: Vec<u8> = (0..3 * PAGE_SIZE + 17).map(|i| (i % 251) as u8).collect();
+    kassert!(file.write(data.clone()) == Ok(data.len()));
+    kassert!(file.stat().map(|stat| stat.file_size) == Ok(data.len()));
+    file.write(vec![0xff; PAGE_SIZE]).map_err(|e| format!("grow: {:?}", e))?;
+    kassert!(file.stat().map(|stat| stat.file_size) == Ok(data.len() + PAGE_SIZE));
+
+    let regular = file.clone().as_regular().map_err(|e| format!("as_regular: {:?}", e))?;
+    regular.seek(0, SeekWhence::Set).map_err(|e| format!("seek: {:?}", e))?;
+    kassert!(file.read(data.len()).as_ref() == Ok(&data));
+    drop(regular);
+    drop(file);
+
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    kassert!(open(&path, OpenMode::READ).err() == Some(ErrorNum::ENOENT));
+    Ok(())
+}
+ktest!(parch_fs_create_resize_remove, parch_fs_create_resize_remove);
+
+fn parch_fs_truncate_punch_hole() -> KTestResult {
+    let path: Path = "/ktest_punch".into();
+    let _ = delete(&path);
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let regular = file.clone().as_regular().map_err(|e| format!("as_regular: {:?}", e))?;
+    file.write(vec![0xaa; 4 * PAGE_SIZE]).map_err(|e| format!("write: {:?}", e))?;
+
+    // a whole block in the middle goes, the partial ones around it are zeroed
+    regular.punch_hole(PAGE_SIZE - 10, PAGE_SIZE + 20).map_err(|e| format!("punch: {:?}", e))?;
+    kassert!(file.stat().map(|stat| stat.file_size) == Ok(4 * PAGE_SIZE));
+    kassert!(regular.next_hole(0) == Ok(PAGE_SIZE));
+    kassert!(regular.next_data(PAGE_SIZE) == Ok(2 * PAGE_SIZE));
+    regular.seek((PAGE_SIZE - 11) as isize, SeekWhence::Set).map_err(|e| format!("seek: {:?}", e))?;
+    let mut expected = vec![0xaa];
+    expected.extend(vec![0; PAGE_SIZE + 20]);
+    expected.push(0xaa);
+    kassert!(file.read(PAGE_SIZE + 22) == Ok(expected));
+
+    // shrink to the middle of a block, growing back reads zeros there
+    regular.truncate(10).map_err(|e| format!("shrink: {:?}", e))?;
+    regular.truncate(20).map_err(|e| format!("grow: {:?}", e))?;
+    regular.seek(0, SeekWhence::Set).map_err(|e| format!("seek: {:?}", e))?;
+    let mut expected = vec![0xaa; 10];
+    expected.extend(vec![0; 10]);
+    kassert!(file.read(PAGE_SIZE) == Ok(expected));
+    regular.truncate(0).map_err(|e| format!("to zero: {:?}", e))?;
+    kassert!(file.stat().map(|stat| stat.file_size) == Ok(0));
+    drop(regular);
+    drop(file);
+
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    Ok(())
+}
+ktest!(parch_fs_truncate_punch_hole, parch_fs_truncate_punch_hole);
+
+fn parch_fs_truncate_unmaps_shared() -> KTestResult {
+    let path: Path = "/ktest_truncate_mmap".into();
+    let _ = delete(&path);
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let regular = file.clone().as_regular().map_err(|e| format!("as_regular: {:?}", e))?;
+    file.write(vec![0x5a; 2 * PAGE_SIZE]).map_err(|e| format!("write: {:?}", e))?;
+
+    let mut layout = MemLayout::new();
+    let vpn = layout.mmap_file(regular.clone(), 0, 2 * PAGE_SIZE, MMAPType::Shared).map_err(|e| format!("mmap: {:?}", e))?;
+    layout.do_map();
+    layout.populate(vpn.into(), 2 * PAGE_SIZE).map_err(|e| format!("populate: {:?}", e))?;
+    let seg = layout.get_segment(vpn).map_err(|e| format!("segment: {:?}", e))?;
+    let tail = VirtPageNum(vpn.0 + 1);
+    kassert!(!seg.is_lazy(tail));
+
+    // the block behind the second page is about to go, only that page faults again
+    let inode = (file.vfs().get_uuid(), file.stat().map_err(|e| format!("stat: {:?}", e))?.inode);
+    layout.unmap_file(inode, PAGE_SIZE, usize::MAX, false);
+    kassert!(!seg.is_lazy(vpn));
+    kassert!(seg.is_lazy(tail));
+    kassert!(layout.pagetable.translate(tail).is_err());
+    drop(seg);
+    drop(layout);
+    drop(regular);
+    drop(file);
+
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    Ok(())
+}
+ktest!(parch_fs_truncate_unmaps_shared, parch_fs_truncate_unmaps_shared);
+
+fn parch_fs_mmap_hole() -> KTestResult {
+    let path: Path = "/ktest_mmap_hole".into();
+    let _ = delete(&path);
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let regular = file.clone().as_regular().map_err(|e| format!("as_regular: {:?}", e))?;
+    regular.truncate(2 * PAGE_SIZE).map_err(|e| format!("grow: {:?}", e))?;
+
+    let mut layout = MemLayout::new();
+    let vpn = layout.mmap_file(regular.clone(), 0, 2 * PAGE_SIZE, MMAPType::Shared).map_err(|e| format!("mmap: {:?}", e))?;
+    layout.do_map();
+    layout.populate(vpn.into(), 2 * PAGE_SIZE).map_err(|e| format!("populate: {:?}", e))?;
+    // read faults share one zero page and allocate nothing
+    kassert!(regular.next_hole(0) == Ok(0));
+    let zero = layout.pagetable.translate(vpn).map_err(|e| format!("translate: {:?}", e))?;
+    kassert!(layout.pagetable.translate(VirtPageNum(vpn.0 + 1)) == Ok(zero));
+
+    // the write fault on it fills the hole in
+    layout.do_lazy(vpn).map_err(|e| format!("write fault: {:?}", e))?;
+    kassert!(regular.next_hole(0) == Ok(PAGE_SIZE));
+    kassert!(layout.pagetable.translate(vpn) != Ok(zero));
+    drop(layout);
+    drop(regular);
+    drop(file);
+
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    Ok(())
+}
+ktest!(parch_fs_mmap_hole, parch_fs_mmap_hole);
+
+fn parch_fs_dirent_cookies() -> KTestResult {
+    let dir_path: Path = "/ktest_cookies".into();
+    let _ = delete(&dir_path);
+    make_file(&dir_path, Permission::from_bits_truncate(0o755), FileType::DIR).map_err(|e| format!("mkdir: {:?}", e))?;
+    for name in ["a", "b", "c"] {
+        make_file(&dir_path.append(name.into()).unwrap(), Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create {}: {:?}", name, e))?;
+    }
+    let dir = open(&dir_path, OpenMode::READ).map_err(|e| format!("open: {:?}", e))?.as_dir().map_err(|e| format!("as_dir: {:?}", e))?;
+    let cookie = |name: &str| dir.read_dirent_cookies().ok()?.into_iter().find(|(_, d)| d.f_name == name).map(|(c, _)| c);
+    let (a, b, c) = (cookie("a"), cookie("b"), cookie("c"));
+    kassert!(a.is_some() && a < b && b < c);
+
+    // the others keep their cookies, the reused slot gets a new one in the same place
+    delete(&dir_path.append("b".into()).unwrap()).map_err(|e| format!("delete: {:?}", e))?;
+    kassert!(cookie("a") == a && cookie("c") == c);
+    make_file(&dir_path.append("d".into()).unwrap(), Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create d: {:?}", e))?;
+    let d = cookie("d");
+    kassert!(d.is_some() && d != b && a < d && d < c);
+
+    drop(dir);
+    delete(&dir_path).map_err(|e| format!("delete dir: {:?}", e))?;
+    Ok(())
+}
+ktest!(parch_fs_dirent_cookies, parch_fs_dirent_cookies);
+
+fn dir_cursor_per_open() -> KTestResult {
+    // procfs and devfs roots too, once the same object for every open
+    for path in ["/", "/proc", "/dev"] {
+        let first = open(&path.into(), OpenMode::READ).map_err(|e| format!("open {}: {:?}", path, e))?.as_dir().map_err(|e| format!("as_dir {}: {:?}", path, e))?;
+        let second = open(&path.into(), OpenMode::READ).map_err(|e| format!("open {}: {:?}", path, e))?.as_dir().map_err(|e| format!("as_dir {}: {:?}", path, e))?;
+        first.set_dir_cursor(3);
+        kassert!(first.dir_cursor() == 3);
+        kassert!(second.dir_cursor() == 0);
+    }
+    Ok(())
+}
+ktest!(dir_cursor_per_open, dir_cursor_per_open);
+
+fn parch_fs_dentry_cache_invalidation() -> KTestResult {
+    let dir_path: Path = "/ktest_dcache".into();
+    let _ = delete(&dir_path.append("sub".into()).unwrap().append("g".into()).unwrap());
+    let _ = delete(&dir_path.append("sub".into()).unwrap());
+    let _ = delete(&dir_path.append("f".into()).unwrap());
+    let _ = delete(&dir_path);
+    make_file(&dir_path, Permission::from_bits_truncate(0o755), FileType::DIR).map_err(|e| format!("mkdir: {:?}", e))?;
+    let dir = open(&dir_path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?.as_dir().map_err(|e| format!("as_dir: {:?}", e))?;
+    let f_path = dir_path.append("f".into()).unwrap();
+    let g_path = dir_path.append("sub".into()).unwrap().append("g".into()).unwrap();
+
+    // cached by the first open, removed without going through the mount manager
+    make_file(&f_path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create f: {:?}", e))?;
+    drop(open(&f_path, OpenMode::READ).map_err(|e| format!("open f: {:?}", e))?);
+    dir.remove_file("f".into()).map_err(|e| format!("remove f: {:?}", e))?;
+    kassert!(open(&f_path, OpenMode::READ).err() == Some(ErrorNum::ENOENT));
+
+    // same name comes back as something else
+    dir.make_file("f".into(), Permission::from_bits_truncate(0o755), FileType::DIR).map_err(|e| format!("mkdir f: {:?}", e))?;
+    kassert!(open(&f_path, OpenMode::READ).and_then(|f| f.as_dir()).is_ok());
+    dir.remove_file("f".into()).map_err(|e| format!("rmdir f: {:?}", e))?;
+
+    // entries inside a removed dir go with it
+    dir.make_file("sub".into(), Permission::from_bits_truncate(0o755), FileType::DIR).map_err(|e| format!("mkdir sub: {:?}", e))?;
+    make_file(&g_path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create g: {:?}", e))?;
+    drop(open(&g_path, OpenMode::READ).map_err(|e| format!("open g: {:?}", e))?);
+    dir.remove_file("sub".into()).map_err(|e| format!("rmdir sub: {:?}", e))?;
+    dir.make_file("sub".into(), Permission::from_bits_truncate(0o755), FileType::DIR).map_err(|e| format!("mkdir sub again: {:?}", e))?;
+    kassert!(open(&g_path, OpenMode::READ).err() == Some(ErrorNum::ENOENT));
+    dir.remove_file("sub".into()).map_err(|e| format!("rmdir sub again: {:?}", e))?;
+
+    drop(dir);
+    delete(&dir_path).map_err(|e| format!("delete dir: {:?}", e))?;
+    Ok(())
+}
+ktest!(parch_fs_dentry_cache_invalidation, parch_fs_dentry_cache_invalidation);
+
+fn parch_fs_orphan_release() -> KTestResult {
+    let path: Path = "/ktest_orphan".into();
+    let _ = delete(&path);
+    let free_inodes = || open(&"/".into(), OpenMode::SYS).and_then(|root| root.vfs().statfs()).map(|stat| stat.free_inodes);
+    let before = free_inodes().map_err(|e| format!("statfs: {:?}", e))?;
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    // still usable, and still taking an inode
+    kassert!(file.write(vec![7; PAGE_SIZE + 1]) == Ok(PAGE_SIZE + 1));
+    kassert!(free_inodes() == Ok(before - 1));
+
+    // freed once a transaction ends after the last close
+    drop(file);
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create again: {:?}", e))?;
+    delete(&path).map_err(|e| format!("delete again: {:?}", e))?;
+    kassert!(free_inodes() == Ok(before));
+    Ok(())
+}
+ktest!(parch_fs_orphan_release, parch_fs_orphan_release);
+
+fn parch_fs_indirect_boundary() -> KTestResult {
+    let path: Path = "/ktest_tmp_indirect".into();
+    let _ = delete(&path);
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    // last direct block, then into the single indirect tree
+    let data: Vec<u8> = (0..17 * PAGE_SIZE + 5).map(|i| (i / PAGE_SIZE + i % 7) as u8).collect();
+    kassert!(file.write(data.clone()) == Ok(data.len()));
+
+    let regular = file.clone().as_regular().map_err(|e| format!("as_regular: {:?}", e))?;
+    regular.seek(16 * PAGE_SIZE as isize - 3, SeekWhence::Set).map_err(|e| format!("seek: {:?}", e))?;
+    kassert!(file.read(6).as_ref() == Ok(&data[16 * PAGE_SIZE - 3..16 * PAGE_SIZE + 3].to_vec()));
+    regular.seek(0, SeekWhence::Set).map_err(|e| format!("seek: {:?}", e))?;
+    kassert!(file.read(data.len()).as_ref() == Ok(&data));
+    drop(regular);
+    drop(file);
+
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    Ok(())
+}
+ktest!(parch_fs_indirect_boundary, parch_fs_indirect_boundary);
+
+fn parch_fs_sparse_seek() -> KTestResult {
+    let path: Path = "/ktest_tmp_sparse".into();
+    let _ = delete(&path);
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let regular = file.clone().as_regular().map_err(|e| format!("as_regular: {:?}", e))?;
+    kassert!(file.write(vec![1; 10]) == Ok(10));
+    // leave pages 1 and 2 as a hole
+    kassert!(regular.seek(3 * PAGE_SIZE as isize - 10, SeekWhence::Cur) == Ok(3 * PAGE_SIZE));
+    kassert!(file.write(vec![2; 10]) == Ok(10));
+    kassert!(regular.seek(0, SeekWhence::End) == Ok(3 * PAGE_SIZE + 10));
+    kassert!(regular.seek(-10, SeekWhence::End) == Ok(3 * PAGE_SIZE));
+    kassert!(regular.seek(-1, SeekWhence::Set) == Err(ErrorNum::EINVAL));
+
+    kassert!(regular.seek(10, SeekWhence::Hole) == Ok(PAGE_SIZE));
+    kassert!(regular.seek(PAGE_SIZE as isize, SeekWhence::Data) == Ok(3 * PAGE_SIZE));
+    kassert!(regular.seek(3 * PAGE_SIZE as isize, SeekWhence::Hole) == Ok(3 * PAGE_SIZE + 10));
+    kassert!(regular.seek(3 * PAGE_SIZE as isize + 10, SeekWhence::Data) == Err(ErrorNum::ENXIO));
+
+    regular.seek(PAGE_SIZE as isize - 5, SeekWhence::Set).map_err(|e| format!("seek: {:?}", e))?;
+    kassert!(file.read(10) == Ok(vec![0; 10]));
+    drop(regular);
+    drop(file);
+
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    Ok(())
+}
+ktest!(parch_fs_sparse_seek, parch_fs_sparse_seek);
+
+fn parch_fs_quota_hard_limit() -> KTestResult {
+    let path: Path = "/ktest_tmp_quota".into();
+    let _ = delete(&path);
+
+    make_file(&path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let before = parch_fs_get_quota(0).map_err(|e| format!("get quota: {:?}", e))?;
+    // room for exactly one more block
+    parch_fs_set_quota(0, QuotaLimits{hard_blocks: before.blocks + 1, ..before.limits}).map_err(|e| format!("set quota: {:?}", e))?;
+    let res = file.write(vec![1; 2 * PAGE_SIZE]);
+    parch_fs_set_quota(0, before.limits).map_err(|e| format!("restore quota: {:?}", e))?;
+    kassert!(res == Err(ErrorNum::EDQUOT));
+    kassert!(parch_fs_get_quota(0).map(|q| q.blocks) == Ok(before.blocks + 1));
+    drop(file);
+
+    delete(&path).map_err(|e| format!("delete: {:?}", e))?;
+    kassert!(parch_fs_get_quota(0).map(|q| (q.blocks, q.inodes)) == Ok((before.blocks, before.inodes - 1)));
+    Ok(())
+}
+ktest!(parch_fs_quota_hard_limit, parch_fs_quota_hard_limit);
+
+fn pipe_semantics() -> KTestResult {
+    let (read_end, write_end) = new_pipe();
+    kassert!(read_end.write(vec![1]) == Err(ErrorNum::EPERM));
+    kassert!(write_end.read(1) == Err(ErrorNum::EPERM));
+
+    write_end.write(b"hello".to_vec()).map_err(|e| format!("write: {:?}", e))?;
+    kassert!(read_end.read(3) == Ok(b"hel".to_vec()));
+    kassert!(read_end.read(2) == Ok(b"lo".to_vec()));
+
+    // reader gets what's left once all writers are gone, then EPIPE
+    write_end.write(b"bye".to_vec()).map_err(|e| format!("write: {:?}", e))?;
+    drop(write_end);
+    kassert!(read_end.read(5) == Ok(b"bye".to_vec()));
+    kassert!(read_end.read(1) == Err(ErrorNum::EPIPE));
+    Ok(())
+}
+ktest!(pipe_semantics, pipe_semantics);
+
+fn pipe_readiness() -> KTestResult {
+    let (read_end, write_end) = new_pipe();
+    let waker = Waker::new();
+    read_end.register_waker(&waker);
+    kassert!(!read_end.poll_read_ready());
+    kassert!(write_end.poll_write_ready());
+    kassert!(!waker.woken());
+
+    write_end.write(vec![1]).map_err(|e| format!("write: {:?}", e))?;
+    kassert!(waker.woken());
+    kassert!(read_end.poll_read_ready());
+
+    // EOF counts as readable
+    read_end.read(1).map_err(|e| format!("read: {:?}", e))?;
+    drop(write_end);
+    kassert!(read_end.poll_read_ready());
+    Ok(())
+}
+ktest!(pipe_readiness, pipe_readiness);
+
+fn pipe_capacity() -> KTestResult {
+    let (read_end, write_end) = new_pipe();
+    let pipe = write_end.pipe().unwrap();
+    kassert!(pipe.capacity() == PIPE_DEFAULT_SIZE);
+    kassert!(pipe.set_capacity(1) == Ok(PAGE_SIZE));
+    kassert!(pipe.set_capacity(usize::MAX) == Err(ErrorNum::EINVAL));
+    write_end.set_nonblock(true);
+    read_end.set_nonblock(true);
+
+    // a long write stops at the limit, a short one goes in whole or not at all
+    kassert!(write_end.write(vec![1; PAGE_SIZE + 10]) == Ok(PAGE_SIZE));
+    kassert!(write_end.write(vec![2]) == Err(ErrorNum::EAGAIN));
+    kassert!(read_end.read(1) == Ok(vec![1]));
+    kassert!(write_end.write(vec![2; 2]) == Err(ErrorNum::EAGAIN));
+    kassert!(write_end.write(vec![2]) == Ok(1));
+    kassert!(pipe.set_capacity(1) == Ok(PAGE_SIZE));
+
+    // a full pipe is a complete read, even when asked for more
+    let mut expected = vec![1; PAGE_SIZE - 1];
+    expected.push(2);
+    kassert!(read_end.read(PAGE_SIZE + 10) == Ok(expected));
+    kassert!(read_end.read(1) == Err(ErrorNum::EAGAIN));
+
+    // writer sees EPIPE once the reader is gone
+    drop(read_end);
+    kassert!(write_end.write(vec![3]) == Err(ErrorNum::EPIPE));
+    Ok(())
+}
+ktest!(pipe_capacity, pipe_capacity);
+
+fn pipe_splice_short_write() -> KTestResult {
+    let (src_read, src_write) = new_pipe();
+    let (dst_read, dst_write) = new_pipe();
+    let src = src_read.pipe().unwrap();
+    src_write.write(b"0123456789".to_vec()).map_err(|e| format!("write: {:?}", e))?;
+
+    // a full destination takes nothing, a broken one fails, either way the source keeps it all
+    dst_write.pipe().unwrap().set_capacity(1).map_err(|e| format!("set_capacity: {:?}", e))?;
+    dst_write.set_nonblock(true);
+    dst_write.write(vec![0; PAGE_SIZE - 4]).map_err(|e| format!("fill: {:?}", e))?;
+    kassert!(src.splice_to(dst_write.as_ref(), 10, true) == Err(ErrorNum::EAGAIN));
+    kassert!(src.splice_to(dst_write.as_ref(), 4, true) == Ok(4));
+    drop(dst_read);
+    kassert!(src.splice_to(dst_write.as_ref(), 10, true) == Err(ErrorNum::EPIPE));
+    src_read.set_nonblock(true);
+    kassert!(src_read.read(10) == Ok(b"456789".to_vec()));
+    Ok(())
+}
+ktest!(pipe_splice_short_write, pipe_splice_short_write);
+
+fn eventfd_counter() -> KTestResult {
+    let event = EventFd::new(0, false, true);
+    kassert!(event.read(8) == Err(ErrorNum::EAGAIN));
+    kassert!(event.write(vec![0; 4]) == Err(ErrorNum::EINVAL));
+    event.write(3u64.to_ne_bytes().to_vec()).map_err(|e| format!("write: {:?}", e))?;
+    event.write(4u64.to_ne_bytes().to_vec()).map_err(|e| format!("write: {:?}", e))?;
+    kassert!(event.read(8) == Ok(7u64.to_ne_bytes().to_vec()));
+    kassert!(event.read(8) == Err(ErrorNum::EAGAIN));
+
+    // semaphore mode hands out one at a time
+    let sem = EventFd::new(2, true, true);
+    kassert!(sem.read(8) == Ok(1u64.to_ne_bytes().to_vec()));
+    kassert!(sem.read(8) == Ok(1u64.to_ne_bytes().to_vec()));
+    kassert!(sem.read(8) == Err(ErrorNum::EAGAIN));
+    kassert!(sem.write(u64::MAX.to_ne_bytes().to_vec()) == Err(ErrorNum::EINVAL));
+    Ok(())
+}
+ktest!(eventfd_counter, eventfd_counter);
+
+fn inotify_events() -> KTestResult {
+    let dir_path: Path = "/ktest_inotify".into();
+    let file_path = dir_path.append("f".into()).unwrap();
+    let _ = delete(&file_path);
+    let _ = delete(&dir_path);
+    make_file(&dir_path, Permission::from_bits_truncate(0o755), FileType::DIR).map_err(|e| format!("mkdir: {:?}", e))?;
+    let inotify = Inotify::new(true);
+    let dir = open(&dir_path, OpenMode::SYS).map_err(|e| format!("open dir: {:?}", e))?;
+    let dir_wd = inotify.add_watch(&dir, IN_CREATE | IN_DELETE).map_err(|e| format!("watch dir: {:?}", e))?;
+    kassert!(inotify.add_watch(&dir, IN_CREATE | IN_DELETE) == Ok(dir_wd));
+    kassert!(inotify.read(64) == Err(ErrorNum::EAGAIN));
+
+    // wd, mask, cookie, len, name padded to 4
+    let event = |wd: u32, mask: u32, name: &str| {
+        let mut res = Vec::new();
+        let len = if name.is_empty() {0} else {(name.len() + 4) & !3};
+        for x in [wd, mask, 0, len as u32] {
+            res.extend_from_slice(&x.to_ne_bytes());
+        }
+        res.extend_from_slice(name.as_bytes());
+        res.resize(16 + len, 0);
+        res
+    };
+    make_file(&file_path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    kassert!(inotify.read(64) == Ok(event(dir_wd, IN_CREATE, "f")));
+
+    let file = open(&file_path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let file_wd = inotify.add_watch(&file, IN_MODIFY).map_err(|e| format!("watch file: {:?}", e))?;
+    kassert!(file_wd != dir_wd);
+    file.write(vec![1, 2, 3]).map_err(|e| format!("write: {:?}", e))?;
+    kassert!(inotify.read(64) == Ok(event(file_wd, IN_MODIFY, "")));
+    inotify.rm_watch(file_wd).map_err(|e| format!("rm watch: {:?}", e))?;
+    kassert!(inotify.rm_watch(file_wd) == Err(ErrorNum::EINVAL));
+    file.write(vec![4]).map_err(|e| format!("write: {:?}", e))?;
+    kassert!(inotify.read(64) == Err(ErrorNum::EAGAIN));
+    let file_wd = inotify.add_watch(&file, IN_MODIFY).map_err(|e| format!("watch file again: {:?}", e))?;
+    drop(file);
+
+    delete(&file_path).map_err(|e| format!("delete: {:?}", e))?;
+    // too short for even one event
+    kassert!(inotify.read(8) == Err(ErrorNum::EINVAL));
+    // the freed inode's watch goes, its number may be reused
+    let (deleted, ignored) = (event(dir_wd, IN_DELETE, "f"), event(file_wd, IN_IGNORED, ""));
+    let read = inotify.read(64).map_err(|e| format!("read: {:?}", e))?;
+    kassert!(read == [deleted.clone(), ignored.clone()].concat() || read == [ignored.clone(), deleted].concat());
+    kassert!(inotify.rm_watch(file_wd) == Err(ErrorNum::EINVAL));
+    drop(dir);
+    delete(&dir_path).map_err(|e| format!("delete dir: {:?}", e))?;
+    Ok(())
+}
+ktest!(inotify_events, inotify_events);
+
+fn proc_mount_flags() -> KTestResult {
+    let path: Path = "/proc/cpuinfo".into();
+    let file = open(&path, OpenMode::READ).map_err(|e| format!("open: {:?}", e))?;
+    kassert!(mount_flags(&file.vfs()).options() == "rw,nosuid,noexec");
+    kassert!(open(&path, OpenMode::READ | OpenMode::EXEC).map(|_| ()) == Err(ErrorNum::EACCES));
+    // kernel opens don't care
+    kassert!(open(&path, OpenMode::SYS | OpenMode::EXEC).is_ok());
+    Ok(())
+}
+ktest!(proc_mount_flags, proc_mount_flags);
+
+fn proc_sys_vm_knobs() -> KTestResult {
+    let path: Path = "/proc/sys/vm/dirty_background_inodes".into();
+    let knob = open(&path, OpenMode::READ | OpenMode::WRITE | OpenMode::SYS).map_err(|e| format!("open: {:?}", e))?;
+    let old = knob.read(32).map_err(|e| format!("read: {:?}", e))?;
+    kassert!(knob.write(b"0".to_vec()) == Err(ErrorNum::EINVAL));
+    kassert!(knob.write(b"16\n".to_vec()) == Ok(3));
+    kassert!(open(&path, OpenMode::READ | OpenMode::SYS).and_then(|f| f.read(32)) == Ok(b"16\n".to_vec()));
+    knob.write(old).map_err(|e| format!("restore: {:?}", e))?;
+    kassert!(open(&"/proc/sys/vm/nonexistent".into(), OpenMode::READ | OpenMode::SYS).map(|_| ()) == Err(ErrorNum::ENOENT));
+    Ok(())
+}
+ktest!(proc_sys_vm_knobs, proc_sys_vm_knobs);
+
+fn bind_mount_umount() -> KTestResult {
+    let path: Path = "/ktest_bind".into();
+    let _ = umount(&path);
+    let _ = delete(&path);
+    make_file(&path, Permission::from_bits_truncate(0o755), FileType::DIR).map_err(|e| format!("mkdir: {:?}", e))?;
+    bind_mount(&"/proc".into(), &path).map_err(|e| format!("bind: {:?}", e))?;
+    let shown = open(&"/ktest_bind/cpuinfo".into(), OpenMode::READ).map(|file| file.vfs().fs_type());
+    kassert!(shown == Ok("proc"));
+    // a copy of /proc's flags, checked on opens through the bind
+    let listed = mount_ns().snapshot().mounts().into_iter().find(|(mount_path, ..)| *mount_path == path).map(|(.., flags)| flags);
+    kassert!(listed == Some(MountFlags::NOSUID | MountFlags::NOEXEC));
+    kassert!(open(&"/ktest_bind/cpuinfo".into(), OpenMode::READ | OpenMode::EXEC).map(|_| ()) == Err(ErrorNum::EACCES));
+    // binding a dir onto itself would loop forever on lookup
+    kassert!(bind_mount(&path, &path) == Err(ErrorNum::EINVAL));
+    umount(&path).map_err(|e| format!("umount: {:?}", e))?;
+    kassert!(open(&"/ktest_bind/cpuinfo".into(), OpenMode::READ).map(|_| ()) == Err(ErrorNum::ENOENT));
+    kassert!(umount(&path) == Err(ErrorNum::ENOENT));
+    delete(&path).map_err(|e| format!("rmdir: {:?}", e))?;
+    Ok(())
+}
+ktest!(bind_mount_umount, bind_mount_umount);
+
+fn dev_shm_semantics() -> KTestResult {
+    let path: Path = "/dev/shm/ktest_shm".into();
+    let _ = delete(&path);
+    // shm_open(O_CREAT), then a second shm_open of the same name
+    make_file(&path, Permission::from_bits_truncate(0o600), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let first = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let second = open(&path, OpenMode::READ).map_err(|e| format!("reopen: {:?}", e))?;
+    kassert!(first.vfs().fs_type() == "tmpfs");
+    kassert!(mount_flags(&first.vfs()).options() == "rw,nosuid");
+    let data: Vec<u8> = (0..PAGE_SIZE + 5).map(|i| (i % 13) as u8).collect();
+    kassert!(first.write(data.clone()) == Ok(data.len()));
+    kassert!(second.read(data.len()) == Ok(data));
+    // opened read only
+    kassert!(second.write(b"x".to_vec()) == Err(ErrorNum::EBADF));
+    kassert!(make_file(&path, Permission::from_bits_truncate(0o600), FileType::REGULAR) == Err(ErrorNum::EEXIST));
+    // shm_unlink, the open ones keep the object
+    delete(&path).map_err(|e| format!("unlink: {:?}", e))?;
+    kassert!(open(&path, OpenMode::READ).map(|_| ()) == Err(ErrorNum::ENOENT));
+    kassert!(second.stat().map(|stat| stat.file_size) == Ok(PAGE_SIZE + 5));
+    Ok(())
+}
+ktest!(dev_shm_semantics, dev_shm_semantics);
+
+fn tmp_fs_sparse_write() -> KTestResult {
+    let path: Path = "/dev/shm/ktest_sparse".into();
+    let _ = delete(&path);
+    make_file(&path, Permission::from_bits_truncate(0o600), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let file = open(&path, OpenMode::READ | OpenMode::WRITE).map_err(|e| format!("open: {:?}", e))?;
+    let regular = file.clone().as_regular().map_err(|e| format!("as_regular: {:?}", e))?;
+    // far more than there is memory, only the page written to gets allocated
+    let far = 1 << 40;
+    regular.seek(far as isize, SeekWhence::Set).map_err(|e| format!("seek: {:?}", e))?;
+    kassert!(file.write(vec![7]) == Ok(1));
+    kassert!(file.stat().map(|stat| stat.file_size) == Ok(far + 1));
+    regular.seek(far as isize - 3, SeekWhence::Set).map_err(|e| format!("seek back: {:?}", e))?;
+    kassert!(file.read(8) == Ok(vec![0, 0, 0, 7]));
+    regular.truncate(PAGE_SIZE).map_err(|e| format!("truncate: {:?}", e))?;
+    kassert!(file.stat().map(|stat| stat.file_size) == Ok(PAGE_SIZE));
+    delete(&path).map_err(|e| format!("unlink: {:?}", e))?;
+    Ok(())
+}
+ktest!(tmp_fs_sparse_write, tmp_fs_sparse_write);
+
+fn path_limits() -> KTestResult {
+    kassert!(Path::new("/a/b/") == Ok("/a/b".into()));
+    kassert!(Path::new("a//") == Ok("a".into()));
+    kassert!(Path::new("///").map(|path| path.is_root()) == Ok(true));
+    let name = "n".repeat(NAME_MAX);
+    kassert!(Path::new(&name).is_ok());
+    kassert!(Path::new(&format!("/{}n", name)) == Err(ErrorNum::ENAMETOOLONG));
+    kassert!(Path::root().append(format!("{}n", name)) == Err(ErrorNum::ENAMETOOLONG));
+    kassert!(Path::new(&"a/".repeat(PATH_MAX / 2 + 1)) == Err(ErrorNum::ENAMETOOLONG));
+    // each half fits, the two together don't
+    let half = Path::new(&"a/".repeat(PATH_MAX / 4)).unwrap();
+    kassert!(half.concat(&half).is_ok());
+    kassert!(half.concat(&half).and_then(|p| p.concat(&"b".into())) == Err(ErrorNum::ENAMETOOLONG));
+    Ok(())
+}
+ktest!(path_limits, path_limits);
+
+fn path_shares_components() -> KTestResult {
+    let base: Path = "/usr/lib".into();
+    let full = base.concat(&"libc.so".into()).map_err(|e| format!("concat: {:?}", e))?;
+    kassert!(format!("{:?}", full) == "/usr/lib/libc.so");
+    kassert!(full.iter().eq(["usr", "lib", "libc.so"].into_iter()));
+    // no component string copied by concat or strip
+    kassert!(Arc::ptr_eq(base.component(1), full.component(1)));
+    kassert!(Arc::ptr_eq(full.strip_head().component(0), base.component(1)));
+    kassert!(Path::new("/a/./b/../c").map(|path| path.to_reduce()) == Ok("/a/c".into()));
+    Ok(())
+}
+ktest!(path_shares_components, path_shares_components);
+
+fn proc_fdinfo_access() -> KTestResult {
+    let proc = match get_processor().current() {
+        Some(proc) => proc,
+        // nobody to check against at boot
+        None => return Ok(()),
+    };
+    let fd = match proc.get_inner().files.keys().next() {
+        Some(fd) => *fd,
+        None => return Ok(()),
+    };
+    let own: Path = format!("/proc/{}/fdinfo/{}", proc.pid.0, fd.0).into();
+    let info = open(&own, OpenMode::READ).and_then(|file| file.read(256)).map_err(|e| format!("own fdinfo: {:?}", e))?;
+    kassert!(info.starts_with(b"pos:"));
+
+    // someone else's, as an unprivileged user
+    let other = process_list().into_iter().find(|pcb| pcb.pid != proc.pid && !pcb.get_inner().files.is_empty());
+    let saved = proc.get_inner().cred;
+    proc.get_inner().cred.euid = 1000;
+    let other_info = other.as_ref().map(|pcb| {
+        let fd = *pcb.get_inner().files.keys().next().unwrap();
+        open(&format!("/proc/{}/fdinfo/{}", pcb.pid.0, fd.0).into(), OpenMode::READ).map(|_| ())
+    });
+    let own_still = open(&own, OpenMode::READ).map(|_| ());
+    let listed = open(&"/proc/files".into(), OpenMode::READ).and_then(|file| file.read(PAGE_SIZE * 4));
+    proc.get_inner().cred = saved;
+
+    kassert!(own_still == Ok(()));
+    let listed = listed.map_err(|e| format!("/proc/files: {:?}", e))?;
+    let listed = alloc::string::String::from_utf8_lossy(&listed).into_owned();
+    if let (Some(pcb), Some(res)) = (other, other_info) {
+        if pcb.get_inner().cred.euid != 1000 {
+            kassert!(res == Err(ErrorNum::EACCES));
+            let prefix = format!("{}:", pcb.pid.0);
+            kassert!(!listed.lines().any(|line| line.rsplit(' ').next().unwrap_or("").split(',').any(|holder| holder.starts_with(&prefix))));
+        }
+    }
+    Ok(())
+}
+ktest!(proc_fdinfo_access, proc_fdinfo_access);
+
+fn search_permission() -> KTestResult {
+    let dir_path: Path = "/ktest_search".into();
+    let file_path = dir_path.append("f".into()).unwrap();
+    let _ = delete(&file_path);
+    let _ = delete(&dir_path);
+    make_file(&dir_path, Permission::from_bits_truncate(0o700), FileType::DIR).map_err(|e| format!("mkdir: {:?}", e))?;
+    make_file(&file_path, Permission::from_bits_truncate(0o644), FileType::REGULAR).map_err(|e| format!("create: {:?}", e))?;
+    let user = Credentials { uid: 1000, euid: 1000, suid: 1000, gid: 1000, egid: 1000, sgid: 1000 };
+    let root = Credentials::root();
+    let as_user = open_searching(None, &file_path, OpenMode::SYS, user, true).map(|_| ());
+    let as_root = open_searching(None, &file_path, OpenMode::SYS, root, true).map(|_| ());
+    // the dir itself isn't searched, only walked to
+    let dir_as_user = open_searching(None, &dir_path, OpenMode::SYS, user, true).map(|_| ());
+    // real ids can say otherwise than effective ones
+    let setuid_root = Credentials { euid: 0, ..user };
+    let by_real = open_searching(None, &file_path, OpenMode::SYS, setuid_root, false).map(|_| ());
+    let by_effective = open_searching(None, &file_path, OpenMode::SYS, setuid_root, true).map(|_| ());
+    // fs without owners can be walked by anyone
+    let proc_root = open(&"/proc".into(), OpenMode::SYS).map_err(|e| format!("open /proc: {:?}", e))?;
+    let proc_as_user = open_searching(Some(proc_root), &"self/status".into(), OpenMode::SYS, user, true).map(|_| ());
+    delete(&file_path).map_err(|e| format!("delete: {:?}", e))?;
+    delete(&dir_path).map_err(|e| format!("rmdir: {:?}", e))?;
+
+    kassert!(as_user == Err(ErrorNum::EACCES));
+    kassert!(as_root == Ok(()));
+    kassert!(dir_as_user == Ok(()));
+    kassert!(by_real == Err(ErrorNum::EACCES));
+    kassert!(by_effective == Ok(()));
+    kassert!(proc_as_user == Ok(()));
+    Ok(())
+}
+ktest!(search_permission, search_permission);
@@ -1,29 +1,156 @@
-use alloc::borrow::ToOwned;
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
-use crate::config::{MAX_LINK_RECURSE};
-use crate::utils::{SpinRWLock, ErrorNum, UUID};
+use alloc::vec::Vec;
+use lazy_static::*;
+use crate::config::{MAX_LINK_RECURSE, DENTRY_CACHE_SIZE};
+use crate::utils::{SpinMutex, SleepMutex, Mutex, ErrorNum, UUID};
+use crate::process::Credentials;
 use super::DirFile;
 use super::types::{FileType, Permission};
-use super::{Path, VirtualFileSystem, File, vfs::OpenMode, LinkFile};
+use super::{Path, VirtualFileSystem, File, vfs::{OpenMode, MountFlags}, LinkFile};
 
+/// RCU-like mount table. Lookups work on an immutable snapshot without holding any lock,
+/// mount/umount copy the table, modify it and swap the new one in.
+/// Old snapshot is freed when the last lookup using it drops its Arc.
 pub struct MountManager{
-    // TODO: Change this to R/W lock
-    pub inner: SpinRWLock<MountManagerInner>
+    /// only held for cloning / replacing the Arc
+    current: SpinMutex<Arc<MountManagerInner>>,
+    /// serialize writers, so no update get lost
+    writer: SleepMutex<()>
 }
 
 impl MountManager {
-    pub fn new(root_fs: Arc<dyn VirtualFileSystem>) -> Self {
+    pub fn new(root_fs: Arc<dyn VirtualFileSystem>, root_flags: MountFlags) -> Self {
         Self {
-            inner: SpinRWLock::new(MountManagerInner::new(root_fs))
+            current: SpinMutex::new("mount table", Arc::new(MountManagerInner::new(root_fs, root_flags))),
+            writer: SleepMutex::new("mount writer", ())
         }
     }
+
+    /// Separate table starting as a copy of this one, for a new mount namespace.
+    pub fn copy(&self) -> Self {
+        Self {
+            current: SpinMutex::new("mount table", self.snapshot()),
+            writer: SleepMutex::new("mount writer", ())
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<MountManagerInner> {
+        self.current.acquire().clone()
+    }
+
+    /// Apply modification on a copy of current mount table, then publish it.
+    /// Nothing is published if op failed.
+    pub fn update<F>(&self, op: F) -> Result<(), ErrorNum> where F: FnOnce(&mut MountManagerInner) -> Result<(), ErrorNum> {
+        let _writer = self.writer.acquire();
+        let mut new_table = (*self.snapshot()).clone();
+        op(&mut new_table)?;
+        *self.current.acquire() = Arc::new(new_table);
+        Ok(())
+    }
+
+    pub fn mount(&self, path: Path, vfs: Arc<dyn VirtualFileSystem>, flags: MountFlags) -> Result<(), ErrorNum> {
+        self.update(|table| table.mount(path, vfs, flags))
+    }
+
+    pub fn bind(&self, source: Path, target: Path) -> Result<(), ErrorNum> {
+        self.update(|table| table.bind(source, target))
+    }
+
+    pub fn umount(&self, path: Path, force: bool) -> Result<(), ErrorNum> {
+        self.update(|table| table.umount(path, force))
+    }
+}
+
+lazy_static!{
+    pub static ref DENTRY_CACHE: SpinMutex<DentryCache> = SpinMutex::new("dentry cache", DentryCache::new());
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+struct DentryKey {
+    pub parent: MountPoint,
+    pub name: Arc<str>,
+}
+
+/// (parent dir, name) -> inode, so repeated open of same path don't rescan dirents.
+/// Only positive entries are kept, and only for dirs with stable inode number. The fs keeps it right, with
+/// `dentry_invalidate` and `dentry_forget`, as dirents can change without going through the mount manager.
+/// Evicted in insertion order.
+pub struct DentryCache {
+    entries: BTreeMap<DentryKey, u32>,
+    order: VecDeque<DentryKey>,
+}
+
+impl DentryCache {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn lookup(&self, parent: MountPoint, name: &Arc<str>) -> Option<u32> {
+        self.entries.get(&DentryKey{parent, name: name.clone()}).cloned()
+    }
+
+    fn insert(&mut self, parent: MountPoint, name: &Arc<str>, inode: u32) {
+        let key = DentryKey{parent, name: name.clone()};
+        if self.entries.insert(key.clone(), inode).is_none() {
+            self.order.push_back(key);
+        }
+        while self.entries.len() > DENTRY_CACHE_SIZE {
+            let victim = self.order.pop_front().unwrap();
+            self.entries.remove(&victim);
+        }
+    }
+
+    fn invalidate(&mut self, parent: MountPoint, name: &str) {
+        let key = DentryKey{parent, name: name.into()};
+        if self.entries.remove(&key).is_some() {
+            self.order.retain(|k| *k != key);
+        }
+    }
+
+    /// `inode` is freed and its number may be reused: drop entries naming it, and entries in it if it was a dir.
+    fn forget_inode(&mut self, fs: UUID, inode: u32) {
+        let gone = MountPoint{fs, inode};
+        let before = self.entries.len();
+        self.entries.retain(|k, v| k.parent != gone && !(k.parent.fs == fs && *v == inode));
+        if self.entries.len() != before {
+            let entries = &self.entries;
+            self.order.retain(|k| entries.contains_key(k));
+        }
+    }
+}
+
+/// `name` in dir `dir` of fs `fs` was created or removed. Fs with cacheable dirs call this on every change to a
+/// dirent, however it's made, the cache has no way to tell on its own.
+pub fn dentry_invalidate(fs: UUID, dir: u32, name: &str) {
+    DENTRY_CACHE.acquire().invalidate(MountPoint{fs, inode: dir}, name);
+}
+
+/// Inode `inode` of fs `fs` is freed, see `DentryCache::forget_inode`.
+pub fn dentry_forget(fs: UUID, inode: u32) {
+    DENTRY_CACHE.acquire().forget_inode(fs, inode);
 }
 
+#[derive(Clone)]
 pub struct MountManagerInner {
     root_fs: Arc<dyn VirtualFileSystem>,
     fs: BTreeMap<UUID, Arc<dyn VirtualFileSystem>>,
-    mount_point: BTreeMap<MountPoint, UUID>
+    mount_point: BTreeMap<MountPoint, MountTarget>,
+    /// of each fs in `fs`, a fs is mounted only once. Bind mounts keep their own, see MountTarget::Bind.
+    flags: BTreeMap<UUID, MountFlags>
+}
+
+/// What shows up at a mount point.
+#[derive(Clone)]
+enum MountTarget {
+    /// root of a fs in `fs`
+    Fs(UUID),
+    /// a dir from elsewhere, opened when it was bound. `path` is where it's bound to. `flags` are a copy of the
+    /// source mount's when it was bound, what's opened through here is checked against them.
+    Bind { dir: Arc<dyn DirFile>, path: Path, flags: MountFlags },
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone)]
@@ -47,13 +174,16 @@ impl MountPoint {
 }
 
 impl MountManagerInner {
-    pub fn new(root_fs: Arc<dyn VirtualFileSystem>) -> Self {
+    pub fn new(root_fs: Arc<dyn VirtualFileSystem>, root_flags: MountFlags) -> Self {
         let mut fs = BTreeMap::new();
         fs.insert(root_fs.get_uuid(), root_fs.clone());
+        let mut flags = BTreeMap::new();
+        flags.insert(root_fs.get_uuid(), root_flags);
         Self {
             root_fs,
             fs,
             mount_point: BTreeMap::new(),
+            flags,
         }
     }
 
@@ -61,38 +191,104 @@ impl MountManagerInner {
         if mode.contains(OpenMode::CREATE) {
             self.make_file(path, Permission::default(), FileType::REGULAR)?;
         }
-        self.open_path_inner(self.root_fs.root_dir(mode)?.as_file(), path, mode, 0)
+        let (res, bind_flags) = self.open_path_inner(self.root_fs.root_dir(mode)?.as_file(), path, mode, None, 0)?;
+        self.check_flags(&res, bind_flags, mode)?;
+        Ok(res)
     }
 
     pub fn open_at(&self, src: Arc<dyn File>, path: &Path, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
         if mode.contains(OpenMode::CREATE) {
             self.make_file_at(path, src.clone(), Permission::default(), FileType::REGULAR)?;
         }
-        self.open_path_inner(src, path, mode, 0)
+        let (res, bind_flags) = self.open_path_inner(src, path, mode, None, 0)?;
+        self.check_flags(&res, bind_flags, mode)?;
+        Ok(res)
+    }
+
+    /// As open_at, from root if `src` is None, but each dir walked through needs search permission for `search`,
+    /// credentials and whether it goes by their effective ids. No create and no mount flag checks.
+    pub fn open_searching(&self, src: Option<Arc<dyn File>>, path: &Path, mode: OpenMode, search: (Credentials, bool)) -> Result<Arc<dyn File>, ErrorNum> {
+        let src = match src {
+            Some(src) => src,
+            None => self.root_fs.root_dir(mode)?.as_file(),
+        };
+        Ok(self.open_path_inner(src, path, mode, Some(search), 0)?.0)
     }
 
-    fn open_path_inner(&self, mut lookup: Arc<dyn File>, path: &Path, mode: OpenMode, recurse_count: usize) -> Result<Arc<dyn File>, ErrorNum> {
+    /// Flags of the mount `vfs` is on.
+    pub fn mount_flags(&self, vfs: &Arc<dyn VirtualFileSystem>) -> MountFlags {
+        self.flags.get(&vfs.get_uuid()).copied().unwrap_or(MountFlags::empty())
+    }
+
+    /// Opening `file` with `mode` is allowed by its mount, the bind mount it was reached through if `bind_flags`
+    /// says so. Kernel opens aren't checked. Creating, removing and linking all open the dir for write, so they
+    /// fail on a read-only mount too.
+    fn check_flags(&self, file: &Arc<dyn File>, bind_flags: Option<MountFlags>, mode: OpenMode) -> Result<(), ErrorNum> {
+        if mode.contains(OpenMode::SYS) {
+            return Ok(());
+        }
+        let flags = bind_flags.unwrap_or_else(|| self.mount_flags(&file.vfs()));
+        if flags.contains(MountFlags::RDONLY) && mode.contains(OpenMode::WRITE) {
+            return Err(ErrorNum::EROFS);
+        }
+        if flags.contains(MountFlags::NOEXEC) && mode.contains(OpenMode::EXEC) {
+            return Err(ErrorNum::EACCES);
+        }
+        Ok(())
+    }
+
+    /// Mount path, fs and flags of every mount, bind mounts included, by path.
+    pub fn mounts(&self) -> Vec<(Path, Arc<dyn VirtualFileSystem>, MountFlags)> {
+        let mut res: Vec<_> = self.fs.values().map(|vfs| (vfs.mount_path(), vfs.clone(), self.mount_flags(vfs))).collect();
+        for target in self.mount_point.values() {
+            if let MountTarget::Bind { dir, path, flags } = target {
+                res.push((path.clone(), dir.vfs(), *flags));
+            }
+        }
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        res
+    }
+
+    /// What's mounted on `mp`, None if it's not a mount point. With the flags of the bind mount if it's one.
+    fn mounted(&self, mp: &MountPoint, mode: OpenMode) -> Result<Option<(Arc<dyn File>, Option<MountFlags>)>, ErrorNum> {
+        match self.mount_point.get(mp) {
+            Some(MountTarget::Fs(uuid)) => Ok(Some((self.get_fs(*uuid)?.root_dir(mode)?.as_file(), None))),
+            Some(MountTarget::Bind { dir, flags, .. }) => Ok(Some((dir.clone().as_file(), Some(*flags)))),
+            None => Ok(None)
+        }
+    }
+
+    /// The file, and the flags of the bind mount it was last reached through, None if it was a plain mount.
+    fn open_path_inner(&self, mut lookup: Arc<dyn File>, path: &Path, mode: OpenMode, search: Option<(Credentials, bool)>, recurse_count: usize) -> Result<(Arc<dyn File>, Option<MountFlags>), ErrorNum> {
         if recurse_count >= MAX_LINK_RECURSE {
             return Err(ErrorNum::EMLINK)
         }
-        let mut path = path.to_owned();
-        while !path.is_root() {
+        let mut bind_flags = None;
+        // index of the next component, the path itself is never copied
+        let mut next = 0;
+        while next < path.len() {
             verbose!("Opening {:?} -> {:?}", lookup, path);
             if let Ok(dir) = lookup.clone().as_dir() {
                 let mp = MountPoint::from_dir(dir.clone())?;
-                if self.mount_point.contains_key(&mp) {
+                if let Some((mounted, flags)) = self.mounted(&mp, mode)? {
                     verbose!("Following mount.");
-                    lookup = self.get_fs(*self.mount_point.get(&mp).unwrap()).unwrap().root_dir(mode)?.as_file();
+                    lookup = mounted;
+                    bind_flags = flags;
                 } else {
-                    lookup = dir.open_entry(&path.components[0], mode)?;
-                    path = path.strip_head();
+                    if let Some((cred, effective)) = search {
+                        if !cred.may_search(dir.owner()?, effective) {
+                            return Err(ErrorNum::EACCES);
+                        }
+                    }
+                    lookup = Self::open_entry_cached(dir, mp, path.component(next), mode)?;
+                    next += 1;
                 }
             } else if let Ok(link) = lookup.clone().as_link() {
                 if mode.contains(OpenMode::NO_FOLLOW) {
                     return Err(ErrorNum::ENOENT)
                 }
                 verbose!("Following link.");
-                lookup = self.open_path_inner(self.root_fs.root_dir(mode)?.as_file(), &link.read_link()?, mode, recurse_count + 1)?;
+                (lookup, bind_flags) = self.follow_link(link, mode, search, recurse_count)?;
             } else {
                 return Err(ErrorNum::ENOENT)
             }
@@ -100,41 +296,96 @@ impl MountManagerInner {
         // mount root cannot be a link, so first check link (recursively) then check mount
         if let Ok(link) = lookup.clone().as_link() {
             if !mode.contains(OpenMode::NO_FOLLOW) {
-                lookup = self.open_path_inner(self.root_fs.root_dir(mode)?.as_file(), &link.read_link()?, mode, recurse_count+1)?;
+                (lookup, bind_flags) = self.follow_link(link, mode, search, recurse_count)?;
             }
         }
-        if let Ok(dir) = lookup.clone().as_dir() {
-            let mp = MountPoint::from_dir(dir)?;
-            if self.mount_point.contains_key(&mp) {
-                verbose!("Following mount.");
-                lookup = self.get_fs(*self.mount_point.get(&mp).unwrap()).unwrap().root_dir(mode)?.as_file();
+        // a bind mount may show a mount point, keep following
+        while let Ok(dir) = lookup.clone().as_dir() {
+            match self.mounted(&MountPoint::from_dir(dir)?, mode)? {
+                Some((mounted, flags)) => {
+                    verbose!("Following mount.");
+                    lookup = mounted;
+                    bind_flags = flags;
+                },
+                None => break
             }
         }
-        Ok(lookup)
+        Ok((lookup, bind_flags))
     }
 
-    pub fn mount(&mut self, path: Path, vfs: Arc<dyn VirtualFileSystem>) -> Result<(), ErrorNum> {
+    fn follow_link(&self, link: Arc<dyn LinkFile>, mode: OpenMode, search: Option<(Credentials, bool)>, recurse_count: usize) -> Result<(Arc<dyn File>, Option<MountFlags>), ErrorNum> {
+        if let Some(target) = link.follow_link(mode)? {
+            return Ok((target, None));
+        }
+        self.open_path_inner(self.root_fs.root_dir(mode)?.as_file(), &link.read_link()?, mode, search, recurse_count + 1)
+    }
+
+    fn open_entry_cached(dir: Arc<dyn DirFile>, mp: MountPoint, name: &Arc<str>, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        if !dir.dentry_cacheable() {
+            return dir.open_entry(name, mode);
+        }
+        let cached = DENTRY_CACHE.acquire().lookup(mp, name);
+        if let Some(inode) = cached {
+            if let Ok(res) = dir.open_entry_inode(name, inode, mode) {
+                return Ok(res);
+            }
+            // stale
+            DENTRY_CACHE.acquire().invalidate(mp, name);
+        }
+        let res = dir.open_entry(name, mode)?;
+        let inode = res.stat()?.inode;
+        DENTRY_CACHE.acquire().insert(mp, name, inode);
+        Ok(res)
+    }
+
+    pub fn mount(&mut self, path: Path, vfs: Arc<dyn VirtualFileSystem>, flags: MountFlags) -> Result<(), ErrorNum> {
         let stat = self.open(&path, OpenMode::SYS)?.stat()?;
         let mount_point = MountPoint{
             fs: stat.fs.upgrade().unwrap().get_uuid(),
             inode: stat.inode,
         };
-        self.mount_point.insert(mount_point, vfs.get_uuid());
+        self.mount_point.insert(mount_point, MountTarget::Fs(vfs.get_uuid()));
+        self.flags.insert(vfs.get_uuid(), flags);
         self.fs.insert(vfs.get_uuid(), vfs);
         Ok(())
         // mount_vfs.mount(mount_dir, path.last(), vfs)
     }
 
+    /// Make dir `source` show up at `target` too, no new fs. Where `source` resolves to can't be a mount point, so
+    /// following binds always ends.
+    pub fn bind(&mut self, source: Path, target: Path) -> Result<(), ErrorNum> {
+        let dir = self.open(&source, OpenMode::SYS)?.as_dir()?;
+        let mount_point = MountPoint::from_dir(self.open(&target, OpenMode::SYS)?.as_dir()?)?;
+        if MountPoint::from_dir(dir.clone())? == mount_point {
+            return Err(ErrorNum::EINVAL);
+        }
+        let flags = self.mount_flags(&dir.vfs());
+        self.mount_point.insert(mount_point, MountTarget::Bind { dir, path: target, flags });
+        Ok(())
+    }
+
+    /// Topmost mount on `path`, a bind mount or a fs. Mounts stacked below it show up again. ENOENT if `path`
+    /// isn't a mount point, EBUSY for root.
     pub fn umount(&mut self, path: Path, _force: bool) -> Result<(), ErrorNum> {
-        let mount_dir = self.open(&path, OpenMode::SYS)?.as_dir()?;
-        let mp = MountPoint::from_dir(mount_dir)?;
-        if self.mount_point.contains_key(&mp) {
-            let fs = self.mount_point.remove(&mp).unwrap();
+        if path.is_root() {
+            return Err(ErrorNum::EBUSY);
+        }
+        // the dir itself, not what's mounted on it
+        let parent = self.open(&path.strip_tail(), OpenMode::SYS)?.as_dir()?;
+        let mut mp = MountPoint::from_dir(parent.open_entry(&path.last(), OpenMode::SYS)?.as_dir()?)?;
+        loop {
+            let (mounted, _) = self.mounted(&mp, OpenMode::SYS)?.ok_or(ErrorNum::ENOENT)?;
+            let next = MountPoint::from_dir(mounted.as_dir()?)?;
+            if !self.mount_point.contains_key(&next) {
+                break;
+            }
+            mp = next;
+        }
+        if let Some(MountTarget::Fs(fs)) = self.mount_point.remove(&mp) {
             self.fs.remove(&fs).unwrap();
-            Ok(())
-        } else {
-            Err(ErrorNum::ENOENT)
+            self.flags.remove(&fs);
         }
+        Ok(())
     }
     
     pub fn make_file(&self, path: &Path, perm: Permission, f_type: FileType) -> Result<(), ErrorNum> {
@@ -178,6 +429,10 @@ impl MountManagerInner {
         }
     }
 
+    pub fn root_fs(&self) -> Arc<dyn VirtualFileSystem> {
+        self.root_fs.clone()
+    }
+
     pub fn get_fs(&self, uuid: UUID) -> Result<Arc<dyn VirtualFileSystem>, ErrorNum> {
         self.fs.get(&uuid).cloned().ok_or(ErrorNum::ENOENT)
     }
@@ -3,12 +3,21 @@ mod types;
 mod fs_impl;
 mod vfs;
 mod pipes;
+mod eventfd;
+mod timerfd;
+mod signalfd;
+mod inotify;
+mod ktests;
+mod initramfs;
 
 // pub use mount_point::MountPoint;
 
 use alloc::sync::Arc;
 pub use manager::{
-    MountManager
+    MountManager,
+    MountManagerInner,
+    dentry_invalidate,
+    dentry_forget,
 };
 
 pub use types::{
@@ -16,6 +25,7 @@ pub use types::{
     SocketFile  ,
     LinkFile    ,
     RegularFile ,
+    SeekWhence  ,
     BlockFile   ,
     DirFile     ,
     CharFile    ,
@@ -24,61 +34,159 @@ pub use types::{
     Cursor      ,
     Dirent      ,
     FileType    ,
-    Permission
+    Permission  ,
+    FileOwner   ,
+    copy_range_bounce
 };
 
 pub use vfs::{
     VirtualFileSystem,
     Path,
-    OpenMode
+    OpenMode,
+    MountFlags,
+    FsStat
 };
 
+pub use fs_impl::{anon_file, writeback_daemon, parch_fs_present, parch_fs_get_quota, parch_fs_set_quota, QuotaEntry, QuotaLimits};
+
 pub use pipes::{
     PipeReadEnd,
     PipeWriteEnd,
-    new_pipe
+    PipeEnd,
+    new_pipe,
+    as_pipe_end
+};
+
+pub use eventfd::EventFd;
+
+pub use timerfd::TimerFd;
+
+pub use signalfd::{SignalFd, SIGNALFD_RECORD_SIZE};
+
+pub use inotify::{
+    Inotify,
+    inotify_event,
+    inotify_forget,
+    IN_MODIFY,
+    IN_MOVED_FROM,
+    IN_MOVED_TO,
+    IN_CREATE,
+    IN_DELETE,
+    IN_Q_OVERFLOW,
+    IN_IGNORED,
+    IN_ALL_EVENTS
 };
 
 use lazy_static::*;
 
-use crate::utils::{RWLock, ErrorNum};
+use crate::{process::{get_processor, Credentials}, utils::ErrorNum};
 
 lazy_static!{
-    pub static ref MOUNT_MANAGER: MountManager = {
-        let root_fs = fs_impl::PARCH_FS.clone();
-        let res = MountManager::new(root_fs);
+    /// Mount table of the kernel, and of processes that never unshared theirs.
+    pub static ref MOUNT_MANAGER: Arc<MountManager> = {
+        let refused = fs_impl::parch_fs_refused();
+        if let Some(reason) = &refused {
+            error!("{}, falling back to initramfs", reason);
+        }
+        let default_root = || match refused {
+            // leave the image alone, an empty initramfs is still better than corrupting it
+            Some(_) => fs_impl::root_fs_by_name("initramfs").unwrap(),
+            // nothing formatted in memory but got an initrd, boot from that
+            None if !parch_fs_present() && crate::device::initrd_range().is_some() => fs_impl::root_fs_by_name("initramfs").unwrap(),
+            None => fs_impl::PARCH_FS.clone(),
+        };
+        let root_fs = match crate::utils::bootargs::get("root") {
+            Some(name) => fs_impl::root_fs_by_name(&name).unwrap_or_else(|| {
+                warning!("Can't use root filesystem {}, using the default", name);
+                default_root()
+            }),
+            None => default_root(),
+        };
+        // "ro" in bootargs, as Linux
+        let root_flags = if crate::utils::bootargs::has("ro") { MountFlags::RDONLY } else { MountFlags::empty() };
+        let res = MountManager::new(root_fs, root_flags);
         verbose!("Mount manager initialized");
-        res
+        Arc::new(res)
     };
 }
 
+/// Mount table of the caller, paths below are looked up in it.
+pub fn mount_ns() -> Arc<MountManager> {
+    get_processor().current().and_then(|proc| proc.mount_ns()).unwrap_or_else(|| MOUNT_MANAGER.clone())
+}
+
+/// Caller gets a copy of its mount table, later mounts and umounts on either side don't show on the other.
+pub fn unshare_mount_ns() {
+    if let Some(proc) = get_processor().current() {
+        let copy = Arc::new(mount_ns().copy());
+        *proc.mount_ns.acquire() = Some(copy);
+    }
+}
+
 pub fn open(path: &Path, mode: OpenMode) -> Result<alloc::sync::Arc<dyn File>, crate::utils::ErrorNum> {
-    MOUNT_MANAGER.inner.acquire_r().open(path, mode)
+    mount_ns().snapshot().open(path, mode)
 }
 
 pub fn open_at(file: Arc<dyn File>, rel_path: &Path, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
-    MOUNT_MANAGER.inner.acquire_r().open_at(file, rel_path, mode)
+    mount_ns().snapshot().open_at(file, rel_path, mode)
+}
+
+/// As open_at, or open if `file` is None, needing search permission on each dir on the way for `cred`, by its
+/// effective ids or its real ones.
+pub fn open_searching(file: Option<Arc<dyn File>>, rel_path: &Path, mode: OpenMode, cred: Credentials, effective: bool) -> Result<Arc<dyn File>, ErrorNum> {
+    mount_ns().snapshot().open_searching(file, rel_path, mode, (cred, effective))
 }
 
 pub fn delete(path: &Path) -> Result<(), ErrorNum> {
-    MOUNT_MANAGER.inner.acquire_r().remove(path)
+    mount_ns().snapshot().remove(path)
 }
 
 pub fn make_file(path: &Path, permission: Permission, f_type: FileType) -> Result<(), ErrorNum> {
-    MOUNT_MANAGER.inner.acquire_r().make_file(path, permission, f_type)
+    mount_ns().snapshot().make_file(path, permission, f_type)
 }
 
 pub fn make_file_at(path: &Path, root: Arc<dyn File>, permission: Permission, f_type: FileType) -> Result<(), ErrorNum> {
-    MOUNT_MANAGER.inner.acquire_r().make_file_at(path, root, permission, f_type)
+    mount_ns().snapshot().make_file_at(path, root, permission, f_type)
+}
+
+/// Flags of the mount `vfs` is on.
+pub fn mount_flags(vfs: &Arc<dyn VirtualFileSystem>) -> MountFlags {
+    mount_ns().snapshot().mount_flags(vfs)
+}
+
+/// Dir `source` shows up at `target` too.
+pub fn bind_mount(source: &Path, target: &Path) -> Result<(), ErrorNum> {
+    mount_ns().bind(source.clone(), target.clone())
+}
+
+pub fn umount(target: &Path) -> Result<(), ErrorNum> {
+    mount_ns().umount(target.clone(), false)
+}
+
+/// Mount point may already be there, in the image or in initramfs.
+fn make_mount_point(path: &Path) -> Result<(), ErrorNum> {
+    match MOUNT_MANAGER.snapshot().make_file(path, Permission::from_bits_truncate(0o544), types::FileType::DIR) {
+        Ok(()) | Err(ErrorNum::EEXIST) => Ok(()),
+        // read-only root, it has to be there already
+        Err(ErrorNum::EROFS) if open(path, OpenMode::SYS).is_ok() => Ok(()),
+        Err(e) => Err(e)
+    }
 }
 
 pub fn init() {
+    if MOUNT_MANAGER.snapshot().root_fs().as_any().downcast::<fs_impl::TmpFS>().is_ok() {
+        verbose!("Unpacking initramfs");
+        initramfs::load();
+    }
     verbose!("Initializing /dev mount point");
-    MOUNT_MANAGER.inner.acquire_r().make_file(&"/dev".into(), Permission::from_bits_truncate(0o544), types::FileType::DIR).expect("Failed to create dev fs mount point.");
+    make_mount_point(&"/dev".into()).expect("Failed to create dev fs mount point.");
     verbose!("Initializing /dev");
-    MOUNT_MANAGER.inner.acquire_w().mount("/dev".into(), fs_impl::DEV_FS.clone()).expect("Failed to mount dev fs.");
+    MOUNT_MANAGER.mount("/dev".into(), fs_impl::DEV_FS.clone(), MountFlags::NOSUID).expect("Failed to mount dev fs.");
+    verbose!("Initializing /dev/shm");
+    let shm = fs_impl::TmpFS::with_root_perm("/dev/shm".into(), Permission::from_bits_truncate(0o777));
+    MOUNT_MANAGER.mount("/dev/shm".into(), shm, MountFlags::NOSUID).expect("Failed to mount shm fs.");
     verbose!("Initializing /proc mount point");
-    MOUNT_MANAGER.inner.acquire_r().make_file(&"/proc".into(), Permission::from_bits_truncate(0o544), types::FileType::DIR).expect("Failed to create proc fs mount point.");
+    make_mount_point(&"/proc".into()).expect("Failed to create proc fs mount point.");
     verbose!("Initializing /proc");
-    MOUNT_MANAGER.inner.acquire_w().mount("/proc".into(), fs_impl::PROC_FS.clone()).expect("Failed to mount proc fs.");
+    MOUNT_MANAGER.mount("/proc".into(), fs_impl::PROC_FS.clone(), MountFlags::NOSUID | MountFlags::NOEXEC).expect("Failed to mount proc fs.");
 }
\ No newline at end of file
@@ -1,67 +1,264 @@
 use alloc::{sync::{Arc, Weak}, collections::VecDeque, vec::Vec};
-use core::fmt::Debug;
+use core::{cmp::min, fmt::Debug, sync::atomic::{AtomicBool, Ordering}};
 
-use crate::{fs::{File, FIFOFile, types::FileStat, OpenMode, Path}, utils::{SpinMutex, Mutex, ErrorNum}, process::get_processor};
+use crate::{config::{PAGE_SIZE, PIPE_BUF, PIPE_DEFAULT_SIZE}, fs::{File, FIFOFile, types::FileStat, OpenMode, Path}, process::Waker, utils::{SleepMutex, Condvar, Mutex, ErrorNum}};
 
 use super::open;
 
 pub struct PipeBuffer {
-    pub inner: SpinMutex<PipeBufferInner>
+    pub inner: SleepMutex<PipeBufferInner>,
+    /// notified on write, on write end close and on resize
+    pub read_cond: Condvar,
+    /// notified on read, on read end close and on resize
+    pub write_cond: Condvar,
 }
 
 pub struct PipeBufferInner {
-    pub buffer: VecDeque<u8>
+    pub buffer: VecDeque<u8>,
+    /// most bytes buffer holds, F_SETPIPE_SZ
+    pub capacity: usize,
+    pub write_closed: bool,
+    pub read_closed: bool,
 }
 
 impl PipeBufferInner {
     pub fn new() -> Self {
-        Self {buffer: VecDeque::new()}
+        Self {buffer: VecDeque::new(), capacity: PIPE_DEFAULT_SIZE, write_closed: false, read_closed: false}
+    }
+
+    fn room(&self) -> usize {
+        self.capacity.saturating_sub(self.buffer.len())
     }
 }
 
 impl PipeBuffer {
     pub fn new() -> Arc<Self> {
-        Arc::new(Self {inner: SpinMutex::new("pipe", PipeBufferInner::new())})
+        Arc::new(Self {
+            inner: SleepMutex::new("pipe", PipeBufferInner::new()),
+            read_cond: Condvar::new("pipe read"),
+            write_cond: Condvar::new("pipe write"),
+        })
     }
 
     pub fn byte_count(&self) -> usize {
         self.inner.acquire().buffer.len()
     }
 
-    // TODO: implement size limit
-    pub fn write(&self, data: Vec<u8>) {
+    pub fn capacity(&self) -> usize {
+        self.inner.acquire().capacity
+    }
+
+    /// data, or EOF
+    pub fn read_ready(&self) -> bool {
+        let inner = self.inner.acquire();
+        !inner.buffer.is_empty() || inner.write_closed
+    }
+
+    /// room, or EPIPE
+    pub fn write_ready(&self) -> bool {
+        let inner = self.inner.acquire();
+        inner.room() > 0 || inner.read_closed
+    }
+
+    /// Rounded up to whole pages, so it never drops below PIPE_BUF. EBUSY if what's buffered wouldn't fit, EINVAL
+    /// if rounding up overflows.
+    pub fn set_capacity(&self, size: usize) -> Result<usize, ErrorNum> {
+        let size = size.max(1).checked_add(PAGE_SIZE - 1).ok_or(ErrorNum::EINVAL)? / PAGE_SIZE * PAGE_SIZE;
         let mut inner = self.inner.acquire();
-        inner.buffer.extend(data.iter());
+        if inner.buffer.len() > size {
+            return Err(ErrorNum::EBUSY);
+        }
+        inner.capacity = size;
+        self.read_cond.notify_all();
+        self.write_cond.notify_all();
+        Ok(size)
     }
 
-    pub fn read(&self, length: usize) -> Option<Vec<u8>> {
+    /// Up to PIPE_BUF bytes go in at once, never mixed with another writer's. Longer writes go in as room frees up.
+    /// Block while full, or EAGAIN if `nonblock` and nothing is written yet. EPIPE once the read end is gone.
+    pub fn write(&self, data: &[u8], nonblock: bool) -> Result<usize, ErrorNum> {
         let mut inner = self.inner.acquire();
-        if length <= inner.buffer.len() {
-            let new_buf = inner.buffer.split_off(length);
-            let res = inner.buffer.clone();
-            inner.buffer = new_buf;
-            Some(res.into())
-        } else {
-            None
+        let mut written = 0;
+        while written < data.len() {
+            if inner.read_closed {
+                return if written == 0 {Err(ErrorNum::EPIPE)} else {Ok(written)};
+            }
+            let left = data.len() - written;
+            let needed = if data.len() <= PIPE_BUF {left} else {1};
+            if inner.room() < needed {
+                if nonblock {
+                    return if written == 0 {Err(ErrorNum::EAGAIN)} else {Ok(written)};
+                }
+                inner = self.write_cond.wait(inner);
+                continue;
+            }
+            let len = min(left, inner.room());
+            inner.buffer.extend(data[written..written + len].iter());
+            written += len;
+            self.read_cond.notify_all();
+        }
+        Ok(written)
+    }
+
+    /// Block until there's anything, then take up to `length` bytes, for splice. Empty once the write end is closed
+    /// and all is read, EAGAIN if `nonblock` and there's nothing yet.
+    pub fn take(&self, length: usize, nonblock: bool) -> Result<Vec<u8>, ErrorNum> {
+        let mut inner = self.inner.acquire();
+        while inner.buffer.is_empty() && !inner.write_closed {
+            if nonblock {
+                return Err(ErrorNum::EAGAIN);
+            }
+            inner = self.read_cond.wait(inner);
+        }
+        let len = min(length, inner.buffer.len());
+        let res = inner.buffer.drain(..len).collect();
+        self.write_cond.notify_all();
+        Ok(res)
+    }
+
+    /// Put bytes taken but not used back in front, in order.
+    pub fn put_back(&self, data: &[u8]) {
+        let mut inner = self.inner.acquire();
+        for byte in data.iter().rev() {
+            inner.buffer.push_front(*byte);
+        }
+        self.read_cond.notify_all();
+    }
+
+    /// Move up to a page to `out`, for splice. What `out` didn't take goes back in front, so a short or failed write
+    /// loses nothing, though another reader may have got bytes past it meanwhile.
+    pub fn splice_to(&self, out: &dyn File, length: usize, nonblock: bool) -> Result<usize, ErrorNum> {
+        let data = self.take(min(length, PAGE_SIZE), nonblock)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let res = out.write(data.clone());
+        let written = *res.as_ref().unwrap_or(&0);
+        if written < data.len() {
+            self.put_back(&data[written..]);
         }
+        res
+    }
+
+    /// Bytes that can be written without blocking.
+    pub fn room(&self) -> usize {
+        self.inner.acquire().room()
+    }
+
+    /// Block until length bytes are available, or as many as the pipe holds if that's less. With `nonblock` take what's
+    /// there, EAGAIN if nothing is. Once the write end is closed, take what's left, EPIPE if nothing is.
+    pub fn read(&self, length: usize, nonblock: bool) -> Result<Vec<u8>, ErrorNum> {
+        let mut inner = self.inner.acquire();
+        while min(length, inner.capacity) > inner.buffer.len() {
+            if inner.write_closed {
+                if inner.buffer.is_empty() {
+                    return Err(ErrorNum::EPIPE);
+                }
+                break;
+            }
+            if nonblock {
+                if inner.buffer.is_empty() {
+                    return Err(ErrorNum::EAGAIN);
+                }
+                break;
+            }
+            inner = self.read_cond.wait(inner);
+        }
+        let len = min(length, inner.buffer.len());
+        let res = inner.buffer.drain(..len).collect();
+        self.write_cond.notify_all();
+        Ok(res)
+    }
+
+    pub fn close_write(&self) {
+        let mut inner = self.inner.acquire();
+        inner.write_closed = true;
+        self.read_cond.notify_all();
+    }
+
+    pub fn close_read(&self) {
+        let mut inner = self.inner.acquire();
+        inner.read_closed = true;
+        self.write_cond.notify_all();
     }
 }
 
+/// Either end of a pipe, for fcntl.
+pub trait PipeEnd {
+    /// None once the pipe is broken.
+    fn pipe(&self) -> Option<Arc<PipeBuffer>>;
+    fn nonblock(&self) -> bool;
+    fn set_nonblock(&self, nonblock: bool);
+}
+
 pub struct PipeWriteEnd {
-    pub buffer: Arc<PipeBuffer>
+    pub buffer: Arc<PipeBuffer>,
+    nonblock: AtomicBool,
 }
 
 pub struct PipeReadEnd {
-    pub buffer: Weak<PipeBuffer>
+    pub buffer: Weak<PipeBuffer>,
+    nonblock: AtomicBool,
 }
 
 pub fn new_pipe() -> (Arc<PipeReadEnd>, Arc<PipeWriteEnd>) {
     let buffer = PipeBuffer::new();
-    let r = Arc::new(PipeReadEnd{buffer: Arc::downgrade(&buffer)});
-    let w = Arc::new(PipeWriteEnd{buffer});
+    let r = Arc::new(PipeReadEnd{buffer: Arc::downgrade(&buffer), nonblock: AtomicBool::new(false)});
+    let w = Arc::new(PipeWriteEnd{buffer, nonblock: AtomicBool::new(false)});
  (r, w)
 }
 
+/// None if `file` isn't a pipe.
+pub fn as_pipe_end(file: Arc<dyn File>) -> Option<Arc<dyn PipeEnd>> {
+    let any = file.as_any();
+    if let Ok(w) = any.clone().downcast::<PipeWriteEnd>() {
+        return Some(w);
+    }
+    any.downcast::<PipeReadEnd>().ok().map(|r| r as Arc<dyn PipeEnd>)
+}
+
+impl PipeEnd for PipeWriteEnd {
+    fn pipe(&self) -> Option<Arc<PipeBuffer>> {
+        Some(self.buffer.clone())
+    }
+
+    fn nonblock(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+}
+
+impl PipeEnd for PipeReadEnd {
+    fn pipe(&self) -> Option<Arc<PipeBuffer>> {
+        self.buffer.upgrade()
+    }
+
+    fn nonblock(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+}
+
+impl Drop for PipeWriteEnd {
+    fn drop(&mut self) {
+        self.buffer.close_write();
+    }
+}
+
+impl Drop for PipeReadEnd {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buffer.upgrade() {
+            buf.close_read();
+        }
+    }
+}
+
 impl Debug for PipeWriteEnd {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "Pipe write end, buffer size {}, writer count {}", self.buffer.byte_count(), Arc::strong_count(&self.buffer))
@@ -80,9 +277,7 @@ impl Debug for PipeReadEnd {
 
 impl File for PipeWriteEnd {
     fn write (&self, data: alloc::vec::Vec::<u8>) -> Result<usize, crate::utils::ErrorNum> {
-        let len = data.len();
-        self.buffer.write(data);
-        Ok(len)
+        self.buffer.write(&data, self.nonblock())
     }
 
     fn read (&self, _length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
@@ -138,6 +333,14 @@ impl File for PipeWriteEnd {
             fs: Arc::downgrade(&self.vfs()),
         })
     }
+
+    fn poll_write_ready (&self) -> bool {
+        self.buffer.write_ready()
+    }
+
+    fn register_waker (&self, waker: &Arc<Waker>) {
+        self.buffer.write_cond.register_waker(waker);
+    }
 }
 
 impl File for PipeReadEnd {
@@ -146,16 +349,10 @@ impl File for PipeReadEnd {
     }
 
     fn read (&self, length: usize) -> Result<alloc::vec::Vec<u8>, crate::utils::ErrorNum> {
-        loop {
-            if let Some(buf) = self.buffer.upgrade() {
-                if let Some(res) = buf.read(length) {
-                    return Ok(res);
-                } else {
-                    get_processor().suspend_switch();
-                }
-            } else {
-                return Err(ErrorNum::EPIPE);
-            }
+        if let Some(buf) = self.buffer.upgrade() {
+            buf.read(length, self.nonblock())
+        } else {
+            Err(ErrorNum::EPIPE)
         }
     }
 
@@ -208,6 +405,16 @@ impl File for PipeReadEnd {
             fs: Arc::downgrade(&self.vfs()),
         })
     }
+
+    fn poll_read_ready (&self) -> bool {
+        self.buffer.upgrade().map_or(true, |buf| buf.read_ready())
+    }
+
+    fn register_waker (&self, waker: &Arc<Waker>) {
+        if let Some(buf) = self.buffer.upgrade() {
+            buf.read_cond.register_waker(waker);
+        }
+    }
 }
 
 impl FIFOFile for PipeWriteEnd {}
@@ -0,0 +1,137 @@
+//! signalfd: pending signals of a set read as records instead of running handlers. The set should be blocked with
+//! sigprocmask too, or a signal may be delivered before it's read. Reads take from the queue of whoever reads.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{fmt::Debug, sync::atomic::{AtomicU64, Ordering}};
+
+use crate::{fs::{File, types::FileStat, OpenMode, Path}, process::{SignalNum, get_processor}, utils::ErrorNum};
+
+use super::open;
+
+/// One record per signal read: signal number as a native endian u32, the rest zero for now.
+pub const SIGNALFD_RECORD_SIZE: usize = 16;
+
+pub struct SignalFd {
+    /// SignalNum::bit set
+    mask: AtomicU64,
+    nonblock: bool,
+}
+
+impl SignalFd {
+    pub fn new(mask: u64, nonblock: bool) -> Arc<Self> {
+        Arc::new(Self {
+            mask: AtomicU64::new(mask),
+            nonblock,
+        })
+    }
+
+    pub fn set_mask(&self, mask: u64) {
+        self.mask.store(mask, Ordering::Relaxed);
+    }
+
+    fn record(signal: SignalNum) -> [u8; SIGNALFD_RECORD_SIZE] {
+        let mut res = [0; SIGNALFD_RECORD_SIZE];
+        res[0..4].copy_from_slice(&(signal as u32).to_ne_bytes());
+        res
+    }
+}
+
+impl Debug for SignalFd {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "signalfd, mask {:#x}", self.mask.load(Ordering::Relaxed))
+    }
+}
+
+impl File for SignalFd {
+    fn write (&self, _data: Vec<u8>) -> Result<usize, ErrorNum> {
+        Err(ErrorNum::EINVAL)
+    }
+
+    /// As many records as fit and are pending, blocking until there's one. EAGAIN then if nonblocking, EINTR if
+    /// a signal outside the set is to be delivered.
+    fn read (&self, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        if length < SIGNALFD_RECORD_SIZE {
+            return Err(ErrorNum::EINVAL);
+        }
+        let proc = get_processor().current().unwrap();
+        let mut res = Vec::new();
+        loop {
+            let mut proc_inner = proc.get_inner();
+            let mask = self.mask.load(Ordering::Relaxed);
+            while res.len() + SIGNALFD_RECORD_SIZE <= length {
+                match proc_inner.take_signal(mask) {
+                    Some(signal) => res.extend_from_slice(&Self::record(signal)),
+                    None => break,
+                }
+            }
+            if !res.is_empty() {
+                return Ok(res);
+            }
+            if self.nonblock {
+                return Err(ErrorNum::EAGAIN);
+            }
+            if proc_inner.has_deliverable_signal() {
+                return Err(ErrorNum::EINTR);
+            }
+            // sender queues with our pcb locked, then wakes us, so it's held until we're asleep
+            get_processor().pause_switch(proc_inner);
+        }
+    }
+
+    fn as_socket <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file <'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any <'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs (&self) -> Arc<dyn crate::fs::VirtualFileSystem> {
+        open(&"proc".into(), OpenMode::SYS).unwrap().vfs()
+    }
+
+    fn stat (&self) -> Result<FileStat, ErrorNum> {
+        Ok(FileStat {
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: Path::new("[signalfd]").unwrap(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    /// A signal of the set pending for the caller. Sending a signal wakes the process whatever it sleeps on, so
+    /// there's no waker to register.
+    fn poll_read_ready (&self) -> bool {
+        let mask = self.mask.load(Ordering::Relaxed);
+        get_processor().current().map_or(false, |proc| proc.get_inner().signal_pending_in(mask))
+    }
+}
@@ -0,0 +1,180 @@
+//! timerfd: a timer read as a file. Read gives how many times it expired since the last read and blocks while
+//! that's zero. Expirations come from the kernel timer queue, so at tick granularity.
+
+use alloc::{sync::{Arc, Weak}, vec::Vec};
+use core::fmt::Debug;
+
+use crate::{fs::{File, types::FileStat, OpenMode, Path}, process::{TimerKey, TimerTarget, Waker, add_timer, cancel_timer, cycles_to_ms, ms_to_cycles}, utils::{SpinMutex, Condvar, Mutex, ErrorNum, time::get_cycle}};
+
+use super::open;
+
+struct TimerFdInner {
+    /// cycle of the next expiry, None if disarmed
+    deadline: Option<usize>,
+    /// cycles between expiries, 0 for one shot
+    interval: usize,
+    expirations: u64,
+    key: Option<TimerKey>,
+}
+
+pub struct TimerFd {
+    inner: SpinMutex<TimerFdInner>,
+    /// notified on expiry
+    read_cond: Condvar,
+    nonblock: bool,
+    self_ref: Weak<TimerFd>,
+}
+
+impl TimerFd {
+    /// Disarmed.
+    pub fn new(nonblock: bool) -> Arc<Self> {
+        Arc::new_cyclic(|self_ref| Self {
+            inner: SpinMutex::new("timerfd", TimerFdInner{deadline: None, interval: 0, expirations: 0, key: None}),
+            read_cond: Condvar::new("timerfd read"),
+            nonblock,
+            self_ref: self_ref.clone(),
+        })
+    }
+
+    /// Expire in `initial_ms` then every `interval_ms`, or disarm if `initial_ms` is 0. Expirations not read yet
+    /// are dropped. Returns ms that were left to the old setting's next expiry, 0 if it was disarmed.
+    pub fn set_time(&self, initial_ms: usize, interval_ms: usize) -> usize {
+        let mut inner = self.inner.acquire();
+        let now = get_cycle();
+        let left = inner.deadline.map_or(0, |deadline| cycles_to_ms(deadline.saturating_sub(now)));
+        if let Some(key) = inner.key.take() {
+            cancel_timer(key);
+        }
+        inner.expirations = 0;
+        inner.interval = ms_to_cycles(interval_ms);
+        inner.deadline = None;
+        if initial_ms != 0 {
+            let deadline = now.saturating_add(ms_to_cycles(initial_ms));
+            inner.deadline = Some(deadline);
+            inner.key = Some(add_timer(deadline, self.self_ref.clone()));
+        }
+        left
+    }
+
+    /// Take the expiration count. Block while it's zero, EAGAIN then if nonblocking.
+    pub fn wait(&self) -> Result<u64, ErrorNum> {
+        let mut inner = self.inner.acquire();
+        while inner.expirations == 0 {
+            if self.nonblock {
+                return Err(ErrorNum::EAGAIN);
+            }
+            inner = self.read_cond.wait(inner);
+        }
+        Ok(core::mem::take(&mut inner.expirations))
+    }
+}
+
+impl TimerTarget for TimerFd {
+    /// Expiries missed while the scheduler was busy count too.
+    fn expire(&self, deadline: usize) {
+        let mut inner = self.inner.acquire();
+        // set_time in between, it's not ours any more
+        if inner.deadline != Some(deadline) {
+            return;
+        }
+        inner.key = None;
+        inner.deadline = None;
+        if inner.interval == 0 {
+            inner.expirations += 1;
+        } else {
+            let passed = (get_cycle().saturating_sub(deadline) / inner.interval) + 1;
+            inner.expirations += passed as u64;
+            let next = deadline + passed * inner.interval;
+            inner.deadline = Some(next);
+            inner.key = Some(add_timer(next, self.self_ref.clone()));
+        }
+        self.read_cond.notify_all();
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        if let Some(key) = self.inner.acquire().key.take() {
+            cancel_timer(key);
+        }
+    }
+}
+
+impl Debug for TimerFd {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let inner = self.inner.acquire();
+        write!(f, "timerfd, next {:?}, interval {}, expirations {}", inner.deadline, inner.interval, inner.expirations)
+    }
+}
+
+impl File for TimerFd {
+    fn write (&self, _data: Vec<u8>) -> Result<usize, ErrorNum> {
+        Err(ErrorNum::EINVAL)
+    }
+
+    /// At least 8 bytes asked for, gets the expiration count as one native endian u64.
+    fn read (&self, length: usize) -> Result<Vec<u8>, ErrorNum> {
+        if length < 8 {
+            return Err(ErrorNum::EINVAL);
+        }
+        Ok(self.wait()?.to_ne_bytes().to_vec())
+    }
+
+    fn as_socket <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::SocketFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_link <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::LinkFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_regular <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::RegularFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_block <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::BlockFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_dir <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::DirFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_char <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::CharFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_fifo <'a>(self: Arc<Self>) -> Result<Arc<dyn crate::fs::FIFOFile + 'a>, ErrorNum> where Self: 'a {
+        Err(ErrorNum::EBADTYPE)
+    }
+
+    fn as_file <'a>(self: Arc<Self>) -> Arc<dyn File + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any <'a>(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync + 'a> where Self: 'a {
+        self
+    }
+
+    fn vfs (&self) -> Arc<dyn crate::fs::VirtualFileSystem> {
+        open(&"proc".into(), OpenMode::SYS).unwrap().vfs()
+    }
+
+    fn stat (&self) -> Result<FileStat, ErrorNum> {
+        Ok(FileStat {
+            open_mode: OpenMode::READ,
+            file_size: 0,
+            path: Path::new("[timerfd]").unwrap(),
+            inode: 0,
+            fs: Arc::downgrade(&self.vfs()),
+        })
+    }
+
+    fn poll_read_ready (&self) -> bool {
+        self.inner.acquire().expirations != 0
+    }
+
+    fn register_waker (&self, waker: &Arc<Waker>) {
+        self.read_cond.register_waker(waker);
+    }
+}
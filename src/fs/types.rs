@@ -1,12 +1,15 @@
 use core::fmt::Debug;
 use core::any::Any;
+use core::cmp::min;
 
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 
-use crate::mem::{PageGuard};
+use crate::config::PAGE_SIZE;
+use crate::mem::{PageGuard, UserBuffer};
 use crate::utils::{ErrorNum};
+use crate::process::Waker;
 
 use super::vfs::OpenMode;
 use super::{VirtualFileSystem, Path};
@@ -22,6 +25,50 @@ pub struct FileStat {
     // TODO: uid/gid/times
 }
 
+/// lseek whence, numbered as in Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekWhence {
+    Set,
+    Cur,
+    End,
+    /// next data at or after offset
+    Data,
+    /// next hole at or after offset, EOF counts as one
+    Hole,
+}
+
+impl TryFrom<usize> for SeekWhence {
+    type Error = ErrorNum;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Set),
+            1 => Ok(Self::Cur),
+            2 => Ok(Self::End),
+            3 => Ok(Self::Data),
+            4 => Ok(Self::Hole),
+            _ => Err(ErrorNum::EINVAL),
+        }
+    }
+}
+
+impl SeekWhence {
+    /// New cursor for a file with no holes. Going past EOF is fine, a write there leaves a hole.
+    pub fn resolve(self, offset: isize, cursor: usize, size: usize) -> Result<usize, ErrorNum> {
+        let base = match self {
+            Self::Cur => cursor,
+            Self::End => size,
+            _ => 0,
+        };
+        let pos = base.checked_add_signed(offset).ok_or(ErrorNum::EINVAL)?;
+        match self {
+            Self::Data | Self::Hole if pos >= size => Err(ErrorNum::ENXIO),
+            Self::Hole => Ok(size),
+            _ => Ok(pos),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Dirent {
     pub inode       : u32,
@@ -42,6 +89,10 @@ bitflags! {
         const OTHER_R = 0o004;
         const OTHER_W = 0o002;
         const OTHER_X = 0o001;
+        /// exec runs as the owner
+        const SETUID  = 0o4000;
+        /// exec runs as the group
+        const SETGID  = 0o2000;
     }
 }
 
@@ -101,33 +152,205 @@ pub trait File: Send + Sync + Debug {
     fn as_any       <'a>(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'a> where Self: 'a;
     fn vfs              (&self) -> Arc<dyn VirtualFileSystem>;
     fn stat             (&self) -> Result<FileStat, ErrorNum>;
+    /// Owner and mode, for setuid and setgid exec and access checks. Default one is root's, with neither bit.
+    fn owner            (&self) -> Result<FileOwner, ErrorNum> {
+        Ok(FileOwner::default())
+    }
+    /// write directly from pinned user pages. Default one copies into a Vec.
+    fn write_user       (&self, buf: &UserBuffer) -> Result<usize, ErrorNum> {
+        self.write(buf.to_vec())
+    }
+    /// read directly into pinned user pages. Default one copies from a Vec.
+    fn read_user        (&self, buf: &mut UserBuffer) -> Result<usize, ErrorNum> {
+        let data = self.read(buf.len())?;
+        Ok(buf.write_slice(0, &data))
+    }
+    /// scatter-gather write. Default one writes each buffer in turn.
+    fn writev           (&self, bufs: &[UserBuffer]) -> Result<usize, ErrorNum> {
+        let mut total = 0;
+        for buf in bufs {
+            total += self.write_user(buf)?;
+        }
+        Ok(total)
+    }
+    /// scatter-gather read. Default one reads each buffer in turn, stop on short read.
+    fn readv            (&self, bufs: &mut [UserBuffer]) -> Result<usize, ErrorNum> {
+        let mut total = 0;
+        for buf in bufs.iter_mut() {
+            let len = self.read_user(buf)?;
+            total += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+    /// put what's written so far where it's going, for fsync. Default one has nothing held back.
+    fn sync             (&self) -> Result<(), ErrorNum> {
+        Ok(())
+    }
+    /// device specific control operation, returns the result buffer. Default one has none.
+    fn ioctl            (&self, _op: usize, _data: Vec<u8>) -> Result<Vec<u8>, ErrorNum> {
+        Err(ErrorNum::ENOTTY)
+    }
+    /// a read now wouldn't block: there's data, EOF or an error to return. Default one never blocks.
+    fn poll_read_ready  (&self) -> bool {
+        true
+    }
+    /// a write now wouldn't block. Default one never blocks.
+    fn poll_write_ready (&self) -> bool {
+        true
+    }
+    /// wake `waker` once, next time either readiness may have changed. Default one has nothing to wait for.
+    fn register_waker   (&self, _waker: &Arc<Waker>) {}
+    /// Another open of the same inode with `mode` and a cursor of its own, as opening its path again would give.
+    /// EBADTYPE when there's no inode behind it to open again.
+    fn reopen           (&self, _mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        Err(ErrorNum::EBADTYPE)
+    }
 }
 
 pub trait SocketFile    : File {}
 pub trait LinkFile      : File {
     fn read_link(&self) -> Result<Path, ErrorNum>;
     fn write_link(&self, path: &Path) -> Result<(), ErrorNum>;
+    /// Magic links (procfs fd, exe...) resolve to the file itself rather than a path, so they still work when
+    /// the path is gone or never existed, e.g. a pipe. None to follow `read_link` as usual.
+    fn follow_link(&self, _mode: OpenMode) -> Result<Option<Arc<dyn File>>, ErrorNum> {
+        Ok(None)
+    }
 }
 pub trait RegularFile   : File {
     /// alloc a page and copy into it.
     fn copy_page(&self, offset: usize) -> Result<PageGuard, ErrorNum>;
-    /// get the original page, fail if not aligned.
-    fn get_page(&self, offset: usize) -> Result<PageGuard, ErrorNum>;
-    /// seek cursor
-    fn seek(&self, offset: usize) -> Result<usize, ErrorNum>;
+    /// get the original page, fail if not aligned. None for a hole, unless `fill` has one allocated.
+    fn get_page(&self, offset: usize, fill: bool) -> Result<Option<PageGuard>, ErrorNum>;
+    /// move cursor as lseek does, returns the new position
+    fn seek(&self, offset: isize, whence: SeekWhence) -> Result<usize, ErrorNum>;
+    /// Read from `offset` instead of the cursor, which stays where it is.
+    fn read_at(&self, offset: usize, length: usize) -> Result<Vec<u8>, ErrorNum>;
+    /// Write at `offset` instead of the cursor, which stays where it is.
+    fn write_at(&self, offset: usize, data: Vec<u8>) -> Result<usize, ErrorNum>;
+    /// copy `length` bytes from cursor of self to cursor of dst, inside kernel.
+    /// Default one bounces through a page sized buffer.
+    fn copy_range_to(&self, dst: Arc<dyn RegularFile>, length: usize) -> Result<usize, ErrorNum> {
+        copy_from_cursor(self, dst.as_ref(), length)
+    }
+    /// First data at or after `offset`, ENXIO if none before EOF. Default one has no holes.
+    fn next_data(&self, offset: usize) -> Result<usize, ErrorNum> {
+        if offset < self.stat()?.file_size {
+            Ok(offset)
+        } else {
+            Err(ErrorNum::ENXIO)
+        }
+    }
+    /// First hole at or after `offset`, EOF if there's none, ENXIO if past EOF.
+    fn next_hole(&self, offset: usize) -> Result<usize, ErrorNum> {
+        let size = self.stat()?.file_size;
+        if offset < size {
+            Ok(size)
+        } else {
+            Err(ErrorNum::ENXIO)
+        }
+    }
+    /// Set the size as ftruncate does, growing with a hole. Default one can't.
+    fn truncate(&self, _size: usize) -> Result<(), ErrorNum> {
+        Err(ErrorNum::EINVAL)
+    }
+    /// [offset, offset + len) reads as zero and takes no space after, size stays. Default one can't.
+    fn punch_hole(&self, _offset: usize, _len: usize) -> Result<(), ErrorNum> {
+        Err(ErrorNum::EOPNOTSUPP)
+    }
+    /// [offset, offset + len) was written through a shared mapping, of a page got by get_page.
+    fn sync_page(&self, _offset: usize, _len: usize) -> Result<(), ErrorNum> {
+        Ok(())
+    }
     
     // fn register_mmap(self: Arc<Self>, mem_layout: &mut MemLayout, offset: usize, length: usize) -> Result<VirtPageNum, ErrorNum>;
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct FileOwner {
+    pub uid: u32,
+    pub gid: u32,
+    pub permission: Permission,
+}
+
+impl Default for FileOwner {
+    fn default() -> Self {
+        Self { uid: 0, gid: 0, permission: Permission::default() }
+    }
+}
+
+impl FileOwner {
+    /// Root's dir anyone can list and walk through, for fs that keep no owners.
+    pub fn public_dir() -> Self {
+        Self { uid: 0, gid: 0, permission: Permission::from_bits_truncate(0o555) }
+    }
+}
+
+/// Generic copy to `dst` one page at a time, `read(done, len)` reads the next `len` bytes of the source, `done`
+/// bytes in. A page is written until `dst` took all of it. Short at the end of the source, or once `dst` takes
+/// nothing more or either side fails after something was copied.
+pub fn copy_range_bounce<D: File + ?Sized>(mut read: impl FnMut(usize, usize) -> Result<Vec<u8>, ErrorNum>, dst: &D, length: usize) -> Result<usize, ErrorNum> {
+    let mut copied = 0;
+    while copied < length {
+        let data = match read(copied, min(PAGE_SIZE, length - copied)) {
+            Ok(data) => data,
+            Err(e) if copied == 0 => return Err(e),
+            Err(_) => break,
+        };
+        if data.is_empty() {
+            break;
+        }
+        let mut written = 0;
+        while written < data.len() {
+            match dst.write(data[written..].to_vec()) {
+                Ok(0) => return Ok(copied + written),
+                Ok(len) => written += len,
+                Err(e) if copied + written == 0 => return Err(e),
+                Err(_) => return Ok(copied + written),
+            }
+        }
+        copied += written;
+    }
+    Ok(copied)
+}
+
+/// copy_range_bounce from the cursor of `src`, which is left right after what was copied.
+pub fn copy_from_cursor<S: RegularFile + ?Sized, D: File + ?Sized>(src: &S, dst: &D, length: usize) -> Result<usize, ErrorNum> {
+    let start = src.seek(0, SeekWhence::Cur)?;
+    let copied = copy_range_bounce(|done, len| src.read_at(start + done, len), dst, length)?;
+    src.seek((start + copied) as isize, SeekWhence::Set)?;
+    Ok(copied)
+}
+
 pub trait BlockFile     : File {}
 pub trait DirFile       : File {
-    fn open_entry(&self, entry_name: &String, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum>;
+    fn open_entry(&self, entry_name: &str, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum>;
     fn make_file(&self, name: String, perm: Permission, f_type: FileType) -> Result<Arc<dyn File>, ErrorNum>;
     fn remove_file(&self, name: String) -> Result<(), ErrorNum>;
     fn read_dirent(&self) -> Result<Vec<Dirent>, ErrorNum>;
+    /// getdents64 position of this open, the cookie of the last entry handed out. fds dup'ed or inherited from
+    /// it share it, another open of the directory starts from 0.
+    fn dir_cursor(&self) -> usize;
+    fn set_dir_cursor(&self, cursor: usize);
+    /// Entries with their getdents cookie, ascending. Cookies are nonzero and listing resumes after the last one
+    /// handed out, so a fs keeping an entry's cookie fixed while it exists never skips or repeats it under
+    /// concurrent changes. By default cookies are list positions, which shift on removal.
+    fn read_dirent_cookies(&self) -> Result<Vec<(usize, Dirent)>, ErrorNum> {
+        Ok(self.read_dirent()?.into_iter().enumerate().map(|(idx, dirent)| (idx + 1, dirent)).collect())
+    }
+    /// can entries of this dir be kept in dentry cache? only if inode number is stable.
+    fn dentry_cacheable(&self) -> bool {
+        false
+    }
+    /// open entry whose inode is already known (from dentry cache), skipping the dirent scan.
+    fn open_entry_inode(&self, entry_name: &str, _inode: u32, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum> {
+        self.open_entry(entry_name, mode)
+    }
 }
-pub trait CharFile      : File {
-    fn ioctl(&self, op: usize, data: Vec<u8>) -> Result<Vec<u8>, ErrorNum>;
-}
+pub trait CharFile      : File {}
 
 pub trait FIFOFile      : File {}
 
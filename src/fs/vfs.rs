@@ -7,6 +7,7 @@ use alloc::{sync::Arc, string::String, vec::Vec};
 use alloc::collections::VecDeque;
 use bitflags::*;
 use super::{File, DirFile};
+use crate::config::{PAGE_SIZE, PATH_MAX, NAME_MAX};
 use crate::mem::SegmentFlags;
 use crate::utils::{ErrorNum, UUID};
 
@@ -22,6 +23,32 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// per mount options, numbered as Linux MS_*
+    pub struct MountFlags: usize {
+        /// opening for write fails with EROFS
+        const RDONLY    = 1 << 0;
+        /// exec ignores setuid and setgid bits
+        const NOSUID    = 1 << 1;
+        /// opening for exec fails with EACCES
+        const NOEXEC    = 1 << 3;
+    }
+}
+
+impl MountFlags {
+    /// As in /proc/mounts, e.g. `ro,nosuid`.
+    pub fn options(&self) -> String {
+        let mut res = String::from(if self.contains(MountFlags::RDONLY) { "ro" } else { "rw" });
+        if self.contains(MountFlags::NOSUID) {
+            res.push_str(",nosuid");
+        }
+        if self.contains(MountFlags::NOEXEC) {
+            res.push_str(",noexec");
+        }
+        res
+    }
+}
+
 impl Into<SegmentFlags> for OpenMode {
     fn into(self) -> SegmentFlags {
         if self.contains(OpenMode::SYS) {
@@ -44,56 +71,80 @@ impl Into<SegmentFlags> for OpenMode {
 pub trait VirtualFileSystem : Send + Sync + Debug {
     fn link(&self, dest: Arc<dyn File>, link_file: &Path) -> Result<Arc<dyn File>, ErrorNum>;
     fn mount_path(&self) -> Path;
+    /// name in /proc/mounts
+    fn fs_type(&self) -> &'static str;
     fn get_uuid(&self) -> UUID;
     fn root_dir(&self, mode: OpenMode) -> Result<Arc<dyn DirFile>, ErrorNum>;
+    /// Space and inode usage, for statfs. Default one has no backing store to speak of, all zero.
+    fn statfs(&self) -> Result<FsStat, ErrorNum> {
+        Ok(FsStat{block_size: PAGE_SIZE, ..Default::default()})
+    }
     fn as_vfs<'a>(self: Arc<Self>) -> Arc<dyn VirtualFileSystem + 'a> where Self: 'a;
     fn as_any<'a>(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
 }
 
+/// Counts in blocks of `block_size` bytes. `name_max` is the longest name an entry can have, 0 if no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsStat {
+    pub block_size  : usize,
+    pub blocks      : usize,
+    pub free_blocks : usize,
+    pub inodes      : usize,
+    pub free_inodes : usize,
+    pub name_max    : usize,
+}
+
+/// Components are shared, so cloning, concat and strip_* only bump refcounts, a component's string is allocated
+/// once when the path is parsed.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Path {
-    pub components  : Vec<String>
+    components  : Vec<Arc<str>>
 }
 
 impl Debug for Path {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "/")?;
-        for p in &self.components {
-            write!(f, "{}", p)?;
-            if self.components.last().unwrap() != p {
+        for (i, p) in self.components.iter().enumerate() {
+            if i != 0 {
                 write!(f, "/")?;
             }
+            write!(f, "{}", p)?;
         }
         Ok(())
     }
 }
 
 impl Path {
+    /// ENAMETOOLONG past PATH_MAX bytes, or with a component past NAME_MAX. Trailing slashes are dropped, "/a/b//"
+    /// is "/a/b"; callers that care about them (must be a dir) look at the string themselves.
     pub fn new_s(path: String) -> Result<Self, ErrorNum> {
-        let mut list: VecDeque<String> = path.split('/').map(|s| String::from(s)).collect();
-        if path.starts_with('/') {
-            list.pop_front();
+        Self::new(&path)
+    }
+
+    pub fn new(path: &str) -> Result<Self, ErrorNum> {
+        if path.len() > PATH_MAX {
+            return Err(ErrorNum::ENAMETOOLONG);
         }
-        if path.ends_with('/') {
-            list.pop_back();
+        let mut list = path.trim_end_matches('/').split('/');
+        if path.starts_with('/') {
+            list.next();
         }
+        let list: Vec<&str> = list.collect();
         for c in &list {
             if c.is_empty() && list.len() != 1 {
                 return Err(ErrorNum::ENOENT);
             }
-            // TODO: check illegal sequence?
+            if c.len() > NAME_MAX {
+                return Err(ErrorNum::ENAMETOOLONG);
+            }
         }
         Ok(
             Self {
-                components: list.into()
+                components: list.into_iter().map(Arc::from).collect()
             }
         )
     }
 
-    pub fn new(path: &str) -> Result<Self, ErrorNum> {
-        Self::new_s(path.to_string())
-    }
-
     pub fn is_root(&self) -> bool {
         return self.components.len() == 0;
     }
@@ -104,6 +155,16 @@ impl Path {
         res
     }
 
+    /// Components in order, borrowed.
+    pub fn iter(&self) -> impl Iterator<Item = &str> + '_ {
+        self.components.iter().map(|c| &**c)
+    }
+
+    /// Shared handle of component `i`, for keeping it without copying the string.
+    pub fn component(&self, i: usize) -> &Arc<str> {
+        &self.components[i]
+    }
+
     pub fn starts_with(&self, prefix: &Path) -> bool {
         if prefix.len() == 0 {return true;}
         if prefix.len() > self.len() {return false;}
@@ -132,8 +193,10 @@ impl Path {
 
     pub fn append(&self, comp: String) -> Result<Path, ErrorNum> {
         if comp.contains('/') {return Err(ErrorNum::ENOENT);}
-        let mut components = self.components.clone();
-        components.push(comp);
+        if comp.len() > NAME_MAX {return Err(ErrorNum::ENAMETOOLONG);}
+        let mut components = Vec::with_capacity(self.components.len() + 1);
+        components.extend_from_slice(&self.components);
+        components.push(Arc::from(comp));
         Ok(Self {
             components
         })
@@ -155,15 +218,25 @@ impl Path {
 
     pub fn last(&self) -> String {
         if self.is_root() {panic!("is_root")}
-        return self.components[self.len() - 1].clone();
+        return self.components[self.len() - 1].to_string();
     }
 
-    pub fn concat(&self, rhs: &Path) -> Self {
-        let mut components = self.components.clone();
-        components.append(&mut rhs.components.clone());
-        Self {
-            components
+    /// ENAMETOOLONG if the result would be past PATH_MAX, as Path::new would say of it.
+    pub fn concat(&self, rhs: &Path) -> Result<Self, ErrorNum> {
+        if self.str_len() + rhs.str_len() > PATH_MAX {
+            return Err(ErrorNum::ENAMETOOLONG);
         }
+        let mut components = Vec::with_capacity(self.components.len() + rhs.components.len());
+        components.extend_from_slice(&self.components);
+        components.extend_from_slice(&rhs.components);
+        Ok(Self {
+            components
+        })
+    }
+
+    /// Bytes of the absolute path string, a slash before each component.
+    fn str_len(&self) -> usize {
+        self.components.iter().map(|c| c.len() + 1).sum()
     }
 
     pub fn reduce(&mut self) {
@@ -172,16 +245,16 @@ impl Path {
 
     
     pub fn to_reduce(&self) -> Self {
-        let mut new_component = VecDeque::new();
-        for c in self.components.clone().into_iter() {
-            if c == ".." && new_component.len() != 0{
+        let mut new_component: VecDeque<Arc<str>> = VecDeque::new();
+        for c in self.components.iter() {
+            if &**c == ".." && new_component.len() != 0{
                 new_component.pop_back();
-            } else if c != "." {
-                new_component.push_back(c);
+            } else if &**c != "." {
+                new_component.push_back(c.clone());
             }
         }
         while let Some(f) = new_component.front() {
-            if f == ".." {
+            if &**f == ".." {
                 new_component.pop_front();
             } else {
                 break;
@@ -201,7 +274,7 @@ impl Path {
 
     /// Using the sbdm
     /// res * 65599 + b
-    fn hash_str(src: &String) -> u32 {
+    fn hash_str(src: &str) -> u32 {
         let mut res = 0u32;
         for b in src.bytes() {
             res = (b as u32).wrapping_add(res.wrapping_shl(6)).wrapping_add(res.wrapping_shl(16)).wrapping_sub(res);
@@ -27,4 +27,11 @@ impl Clint {
             (self.address + 0x4000 + 8 * hart).write_volatile(&nxt_int);
         }
     }
-}
\ No newline at end of file
+
+    /// Raise machine software interrupt on `hart`.
+    pub fn send_soft(&self, hart: usize) {
+        unsafe {
+            (self.address + 4 * hart).write_volatile(&1u32);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Inter-processor interrupts: cross-hart calls on supervisor software interrupt.
+//!
+//! A handler is registered once and gets an IpiId, one bit in a per hart pending mask. send_ipi sets the bit on
+//! the target and raises its software interrupt, through SBI if booted by firmware or CLINT msip otherwise, which
+//! timervec forwards to S mode. The target runs every handler pending in its SupervisorSoft trap, with interrupts
+//! off. Several sends before the target gets to it run the handler once.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+use crate::{config::MAX_CPUS, process::{get_hart_id, hart_online, online_harts}, utils::{ErrorNum, RWLock, SpinRWLock, park_if_panicking}};
+
+use super::{CLINT, sbi};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpiId(usize);
+
+lazy_static!{
+    /// name and handler, indexed by IpiId
+    static ref IPI_HANDLERS: SpinRWLock<Vec<(&'static str, fn())>> = SpinRWLock::new(Vec::new());
+}
+
+const PENDING_INIT: AtomicUsize = AtomicUsize::new(0);
+static PENDING: [AtomicUsize; MAX_CPUS] = [PENDING_INIT; MAX_CPUS];
+
+/// Register `handler` to be run on a hart when it's sent the returned IpiId. Usually once at boot.
+pub fn register_ipi(name: &'static str, handler: fn()) -> IpiId {
+    let mut handlers = IPI_HANDLERS.acquire_w();
+    assert!(handlers.len() < usize::BITS as usize, "Too many IPI handlers");
+    handlers.push((name, handler));
+    IpiId(handlers.len() - 1)
+}
+
+/// Raise the software interrupt of `hart`, with nothing pending it only makes the hart leave wfi.
+pub fn raise(hart: usize) {
+    if sbi::sbi_boot() {
+        if let Err(e) = sbi::send_ipi(1 << hart, 0) {
+            warning!("SBI send_ipi to hart {} failed with {}", hart, e);
+        }
+    } else {
+        CLINT.send_soft(hart);
+    }
+}
+
+/// Run `id` on `hart`. Sending to self works too, it runs when this hart next takes interrupts.
+pub fn send_ipi(hart: usize, id: IpiId) -> Result<(), ErrorNum> {
+    if hart >= MAX_CPUS || !hart_online(hart) {
+        return Err(ErrorNum::ENODEV);
+    }
+    PENDING[hart].fetch_or(1 << id.0, Ordering::AcqRel);
+    raise(hart);
+    Ok(())
+}
+
+/// Run `id` on every online hart but this one.
+pub fn broadcast_ipi(id: IpiId) {
+    let self_id = get_hart_id();
+    for hart in online_harts().filter(|hart| *hart != self_id) {
+        // went offline in between, nothing to do there
+        let _ = send_ipi(hart, id);
+    }
+}
+
+/// SupervisorSoft on this hart, run what was sent here.
+pub fn handle_ipi() {
+    park_if_panicking();
+    let pending = PENDING[get_hart_id()].swap(0, Ordering::AcqRel);
+    if pending == 0 {
+        return;
+    }
+    let handlers = IPI_HANDLERS.acquire_r();
+    for (idx, (name, handler)) in handlers.iter().enumerate() {
+        if pending & (1 << idx) != 0 {
+            verbose!("IPI {} on hart {}", name, get_hart_id());
+            handler();
+        }
+    }
+}
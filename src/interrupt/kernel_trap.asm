@@ -183,14 +183,43 @@ kernel_vec:
 timervec:
 	# start.c has set up the memory that mscratch points to:
 	# scratch[0,8,16] : register save area.
+	# scratch[24] : mepc of last timer interrupt.
 	# scratch[32] : address of CLINT's MTIMECMP register.
 	# scratch[40] : desired interval between interrupts.
+	# scratch[48] : address of CLINT's MSIP register.
+	# scratch[56] : set here on each timer tick forwarded.
+	# scratch[64] : park request from a panicking hart.
 	
 	csrrw a0, mscratch, a0
 	sd a1, 0(a0)
 	sd a2, 8(a0)
 	sd a3, 16(a0)
 
+	# machine software interrupt is an IPI, see interrupt::ipi
+	csrr a1, mcause
+	slli a1, a1, 1
+	srli a1, a1, 1
+	li a2, 3
+	bne a1, a2, 1f
+	ld a1, 48(a0)
+	sw zero, 0(a1)
+	# sent by a panicking hart, park this hart for good so it stops touching anything.
+	ld a1, 64(a0)
+	beqz a1, 3f
+	csrw mie, zero
+2:
+	wfi
+	j 2b
+3:
+	# otherwise forward to S mode
+	li a1, 2
+	csrs sip, a1
+	j 4f
+1:
+	# save interrupted pc to scratch[24] for the watchdog
+	csrr a1, mepc
+	sd a1, 24(a0)
+
 	# schedule the next timer interrupt
 	# by adding interval to mtimecmp.
 	ld a1, 32(a0) # CLINT_MTIMECMP(hart)
@@ -199,10 +228,13 @@ timervec:
 	add a3, a3, a2
 	sd a3, 0(a1)
 
-	# raise a supervisor software interrupt.
+	# raise a supervisor software interrupt, marked as a tick.
+	li a1, 1
+	sd a1, 56(a0)
 	li a1, 2
-	csrw sip, a1
+	csrs sip, a1
 
+4:
 	ld a3, 16(a0)
 	ld a2, 8(a0)
 	ld a1, 0(a0)
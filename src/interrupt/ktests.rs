@@ -0,0 +1,39 @@
+//! Self tests for IPI dispatch, see utils::ktest.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{process::{get_hart_id, pop_intr_off, push_intr_off}, utils::ktest::KTestResult};
+
+use super::ipi::{IpiId, handle_ipi, register_ipi, send_ipi};
+
+use lazy_static::*;
+
+lazy_static!{
+    /// once, /proc/ktest can run this again
+    static ref TEST_IPI: IpiId = register_ipi("ktest", ipi_test_handler);
+}
+
+static IPI_TEST_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+fn ipi_test_handler() {
+    IPI_TEST_RUNS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn ipi_send_to_self() -> KTestResult {
+    let id = *TEST_IPI;
+    // with interrupts off the trap can't take it first, two sends before handling run it once
+    push_intr_off();
+    let before = IPI_TEST_RUNS.load(Ordering::SeqCst);
+    let sent = send_ipi(get_hart_id(), id).and_then(|_| send_ipi(get_hart_id(), id));
+    handle_ipi();
+    let runs = IPI_TEST_RUNS.load(Ordering::SeqCst) - before;
+    handle_ipi();
+    let reruns = IPI_TEST_RUNS.load(Ordering::SeqCst) - before - runs;
+    pop_intr_off();
+    kassert!(sent.is_ok());
+    kassert!(runs == 1);
+    kassert!(reruns == 0);
+    kassert!(send_ipi(usize::MAX, id).is_err());
+    Ok(())
+}
+ktest!(ipi_send_to_self, ipi_send_to_self);
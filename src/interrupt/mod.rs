@@ -3,11 +3,18 @@ mod plic;
 mod clint;
 pub mod int_callback;
 pub mod trap_context;
+mod watchdog;
+mod ktests;
+pub mod sbi;
+pub mod timer;
+pub mod ipi;
 
 // pub use plic::PLIC0;
 
 pub use clint::CLINT;
 
+pub use watchdog::watchdog_tick;
+
 pub use trap_handler::{trap_return, set_kernel_trap_entry, fork_return};
 
 
@@ -0,0 +1,81 @@
+//! Supervisor Binary Interface, used when booted in S mode by OpenSBI/RustSBI instead of running genesis_m.
+//! Timer, IPI and hart start go through firmware then, as CLINT is PMP protected.
+
+use core::{arch::asm, sync::atomic::{AtomicBool, Ordering}};
+
+/// Set once in genesis_sbi, never cleared.
+static SBI_BOOT: AtomicBool = AtomicBool::new(false);
+/// Firmware has the TIME extension, probed with SBI_BOOT so set_timer doesn't ask every tick.
+static HAS_TIME_EXT: AtomicBool = AtomicBool::new(false);
+
+const EID_LEGACY_SET_TIMER: usize = 0x00;
+const EID_BASE: usize = 0x10;
+const EID_TIME: usize = 0x54494D45;
+const EID_IPI: usize = 0x735049;
+const EID_HSM: usize = 0x48534D;
+
+const FID_BASE_PROBE_EXTENSION: usize = 3;
+const FID_TIME_SET_TIMER: usize = 0;
+const FID_IPI_SEND_IPI: usize = 0;
+const FID_HSM_HART_START: usize = 0;
+
+pub fn set_sbi_boot() {
+    HAS_TIME_EXT.store(probe_extension(EID_TIME), Ordering::Relaxed);
+    SBI_BOOT.store(true, Ordering::Release);
+}
+
+/// Are we under SBI firmware? Decides timer/IPI path at runtime.
+pub fn sbi_boot() -> bool {
+    SBI_BOOT.load(Ordering::Acquire)
+}
+
+/// (error, value), error 0 for success.
+fn sbi_call(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> (isize, usize) {
+    let error: isize;
+    let value: usize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a2") arg2,
+            in("a6") fid,
+            in("a7") eid,
+        );
+    }
+    (error, value)
+}
+
+fn probe_extension(eid: usize) -> bool {
+    let (error, value) = sbi_call(EID_BASE, FID_BASE_PROBE_EXTENSION, eid, 0, 0);
+    error == 0 && value != 0
+}
+
+/// Program next S mode timer interrupt, at absolute time `stime`. Also clears pending STIP.
+pub fn set_timer(stime: usize) {
+    if HAS_TIME_EXT.load(Ordering::Relaxed) {
+        sbi_call(EID_TIME, FID_TIME_SET_TIMER, stime, 0, 0);
+    } else {
+        sbi_call(EID_LEGACY_SET_TIMER, 0, stime, 0, 0);
+    }
+}
+
+/// Raise S mode software interrupt on harts in `hart_mask`, bit 0 is `hart_mask_base`.
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), isize> {
+    match sbi_call(EID_IPI, FID_IPI_SEND_IPI, hart_mask, hart_mask_base, 0) {
+        (0, _) => Ok(()),
+        (error, _) => Err(error),
+    }
+}
+
+/// Start a stopped hart in S mode at `start_addr`, with a0 = hart id and a1 = `opaque`.
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> Result<(), isize> {
+    if !probe_extension(EID_HSM) {
+        // legacy firmware starts every hart by itself
+        return Err(-2);
+    }
+    match sbi_call(EID_HSM, FID_HSM_HART_START, hart_id, start_addr, opaque) {
+        (0, _) => Ok(()),
+        (error, _) => Err(error),
+    }
+}
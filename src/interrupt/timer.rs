@@ -0,0 +1,79 @@
+//! Where S mode timer ticks come from. Picked at runtime:
+//!
+//! - Sstc: S mode programs `stimecmp` itself and gets SupervisorTimer, no M mode involved.
+//! - SBI: `sbi_set_timer` on each tick, also SupervisorTimer.
+//! - CLINT: genesis_m programs mtimecmp, timervec re-arms it and forwards as SupervisorSoft, marked so it's
+//!   told apart from IPI.
+
+use core::{arch::asm, sync::atomic::{AtomicUsize, Ordering}};
+
+use lazy_static::*;
+
+use crate::{config::{CLOCK_FREQ, TIMER_FRAC}, utils::time::get_cycle};
+
+use super::sbi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerBackend {
+    Sstc,
+    Sbi,
+    Clint,
+}
+
+lazy_static!{
+    /// From DTB ISA string. Scanned lock free, for genesis_m asks before anything is up.
+    static ref HAS_SSTC: bool = crate::device::harts_have_ext("sstc");
+}
+
+pub fn has_sstc() -> bool {
+    *HAS_SSTC
+}
+
+pub fn backend() -> TimerBackend {
+    if has_sstc() {
+        TimerBackend::Sstc
+    } else if sbi::sbi_boot() {
+        TimerBackend::Sbi
+    } else {
+        TimerBackend::Clint
+    }
+}
+
+const fn interval() -> usize {
+    CLOCK_FREQ / TIMER_FRAC
+}
+
+fn write_stimecmp(value: usize) {
+    unsafe {
+        // stimecmp, not every assembler knows the name yet
+        asm!("csrw 0x14d, {0}", in(reg) value);
+    }
+}
+
+/// Arm the first tick on this hart. CLINT path is armed by genesis_m already.
+pub fn init_hart() {
+    match backend() {
+        TimerBackend::Sstc => write_stimecmp(get_cycle() + interval()),
+        TimerBackend::Sbi => sbi::set_timer(get_cycle() + interval()),
+        TimerBackend::Clint => {},
+    }
+}
+
+/// SupervisorSoft on CLINT path: was a tick forwarded since last asked? IPIs come the same way.
+pub fn take_forwarded_tick(hart_id: usize) -> bool {
+    // timervec can write it between our load and store, one amoswap is safe against that
+    let flag = unsafe { &*(&crate::MSCRATCH_ARR[hart_id][7] as *const usize as *const AtomicUsize) };
+    flag.swap(0, Ordering::AcqRel) != 0
+}
+
+/// SupervisorTimer: arm the next one, and leave the interrupted pc for watchdog like timervec does.
+pub fn tick(hart_id: usize, sepc: usize) {
+    unsafe {
+        (&mut crate::MSCRATCH_ARR[hart_id][3] as *mut usize).write_volatile(sepc);
+    }
+    match backend() {
+        TimerBackend::Sstc => write_stimecmp(get_cycle() + interval()),
+        TimerBackend::Sbi => sbi::set_timer(get_cycle() + interval()),
+        TimerBackend::Clint => warning!("SupervisorTimer on CLINT timer path"),
+    }
+}
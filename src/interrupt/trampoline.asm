@@ -85,7 +85,19 @@ uservec:
         sd t4,  256(a0)
         sd t5,  264(a0)
         sd t6,  272(a0)
-        
+
+	# save the user a0 in p->trapframe->a0
+        csrr t0, sscratch
+        sd t0, 104(a0)
+
+        # save FP state only if user wrote it since userret loaded it (sstatus.FS Dirty),
+        # it's Off for processes that never used it
+        csrr t0, sstatus
+        srli t0, t0, 13
+        andi t0, t0, 3
+        li t1, 3
+        bne t0, t1, uservec_fp_saved
+
         fsd ft0 , 280(a0)
         fsd ft1 , 288(a0)
         fsd ft2 , 296(a0)
@@ -118,10 +130,13 @@ uservec:
         fsd ft9 , 512(a0)
         fsd ft10, 520(a0)
         fsd ft11, 528(a0)
+        frcsr t0
+        sd t0, 536(a0)
 
-	# save the user a0 in p->trapframe->a0
-        csrr t0, sscratch
-        sd t0, 104(a0)
+uservec_fp_saved:
+        # kernel runs with FP on
+        li t0, 0x6000
+        csrs sstatus, t0
 
         # restore kernel stack pointer from p->trapframe->kernel_sp
         ld sp, 0(a0)
@@ -156,37 +171,11 @@ userret:
         ld t0, 104(a0)
         csrw sscratch, t0
 
-        # restore all but a0 from TRAPFRAME
-        ld ra,   32(a0)
-        ld sp,   40(a0)
-        ld gp,   48(a0)
-        ld tp,   56(a0)
-        ld t0,   64(a0)
-        ld t1,   72(a0)
-        ld t2,   80(a0)
-        ld s0,   88(a0)
-        ld s1,   96(a0)
-        ld a1,  112(a0)
-        ld a2,  120(a0)
-        ld a3,  128(a0)
-        ld a4,  136(a0)
-        ld a5,  144(a0)
-        ld a6,  152(a0)
-        ld a7,  160(a0)
-        ld s2,  168(a0)
-        ld s3,  176(a0)
-        ld s4,  184(a0)
-        ld s5,  192(a0)
-        ld s6,  200(a0)
-        ld s7,  208(a0)
-        ld s8,  216(a0)
-        ld s9,  224(a0)
-        ld s10, 232(a0)
-        ld s11, 240(a0)
-        ld t3,  248(a0)
-        ld t4,  256(a0)
-        ld t5,  264(a0)
-        ld t6,  272(a0)
+        # load FP state if the process uses it, leaving FS Clean so uservec knows if it has to save it again.
+        # FS Off otherwise, first use traps and user_trap turns it on.
+        li t0, 0x6000
+        ld t1, 544(a0)
+        beqz t1, userret_fp_off
 
         fld ft0 , 280(a0)
         fld ft1 , 288(a0)
@@ -220,6 +209,48 @@ userret:
         fld ft9 , 512(a0)
         fld ft10, 520(a0)
         fld ft11, 528(a0)
+        ld t1, 536(a0)
+        fscsr t1
+        csrc sstatus, t0
+        li t0, 0x4000
+        csrs sstatus, t0
+        j userret_fp_done
+
+userret_fp_off:
+        csrc sstatus, t0
+
+userret_fp_done:
+        # restore all but a0 from TRAPFRAME
+        ld ra,   32(a0)
+        ld sp,   40(a0)
+        ld gp,   48(a0)
+        ld tp,   56(a0)
+        ld t0,   64(a0)
+        ld t1,   72(a0)
+        ld t2,   80(a0)
+        ld s0,   88(a0)
+        ld s1,   96(a0)
+        ld a1,  112(a0)
+        ld a2,  120(a0)
+        ld a3,  128(a0)
+        ld a4,  136(a0)
+        ld a5,  144(a0)
+        ld a6,  152(a0)
+        ld a7,  160(a0)
+        ld s2,  168(a0)
+        ld s3,  176(a0)
+        ld s4,  184(a0)
+        ld s5,  192(a0)
+        ld s6,  200(a0)
+        ld s7,  208(a0)
+        ld s8,  216(a0)
+        ld s9,  224(a0)
+        ld s10, 232(a0)
+        ld s11, 240(a0)
+        ld t3,  248(a0)
+        ld t4,  256(a0)
+        ld t5,  264(a0)
+        ld t6,  272(a0)
 
 	# restore user a0, and save TRAPFRAME in sscratch
         csrrw a0, sscratch, a0
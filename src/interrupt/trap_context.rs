@@ -73,6 +73,9 @@ pub struct TrapContext {
     pub ft9         : f64,          /* 512 */
     pub ft10        : f64,          /* 520 */
     pub ft11        : f64,          /* 528 */
+    pub fcsr        : usize,        /* 536 */
+    /// user has FP on, set on first use by user_trap. Until then the FP registers above are never saved or loaded
+    pub fp_enabled  : usize,        /* 544 */
 }
 
 impl TrapContext {
@@ -82,6 +85,15 @@ impl TrapContext {
         }
     }
     
+    /// First FP instruction trapped, start from zeroed registers. userret loads them from now on.
+    pub fn enable_fp(&mut self) {
+        unsafe {
+            core::ptr::write_bytes(&mut self.ft0 as *mut f64, 0, 32);
+        }
+        self.fcsr = 0;
+        self.fp_enabled = 1;
+    }
+
     pub fn current_ref() -> &'static mut TrapContext {
         unsafe {(TRAP_CONTEXT_ADDR.0 as * mut TrapContext).as_mut().unwrap()}
     }
@@ -1,6 +1,6 @@
 use core::{panic, arch::asm};
 
-use alloc::borrow::ToOwned;
+use alloc::{borrow::ToOwned, boxed::Box};
 use riscv::register::{scause::{   // s cause register
         self,
         Trap,
@@ -9,7 +9,7 @@ use riscv::register::{scause::{   // s cause register
     }, sepc, sip, sstatus::{self, SPP}, stval, stvec};
 
 // use super::PLIC0;
-use crate::{config::{TRAMPOLINE_ADDR, PROC_K_STACK_ADDR, PROC_K_STACK_SIZE, TRAP_CONTEXT_ADDR, PROC_U_STACK_ADDR, PROC_U_STACK_SIZE, U_TRAMPOLINE_ADDR}, interrupt::trap_context::TrapContext, mem::{VirtAddr}, process::{ProcessStatus, SignalNum, def_handler::{usr_sigreturn}, get_hart_id, get_processor, intr_off, intr_on}, syscall::{syscall, syscall_num::SYSCALL_EXEC}, utils::{Mutex, RWLock}};
+use crate::{config::{TRAMPOLINE_ADDR, PROC_K_STACK_ADDR, PROC_K_STACK_SIZE, TRAP_CONTEXT_ADDR, PROC_U_STACK_ADDR, PROC_U_STACK_SIZE, U_TRAMPOLINE_ADDR, PAGE_SIZE}, interrupt::{trap_context::TrapContext, watchdog_tick, timer, ipi}, mem::{VirtAddr}, process::{PCBInner, ProcessStatus, SignalNum, def_handler::{usr_sigreturn}, get_hart_id, get_processor, intr_off, intr_on, oom_kill, SyscallAbi, VectorState, has_vector, vector_dirty, vector_off}, syscall::{syscall, linux_syscall, linux_errno, LINUX_EXECVE, syscall_num::SYSCALL_EXEC}, utils::{Mutex, RWLock, ErrorNum, stack_guard::check_kernel_stack, trace::{TraceEvent, trace}}};
 use crate::device::DEVICE_MANAGER;
 
 /// Set trap entry to kernel trap handling function.
@@ -31,6 +31,7 @@ pub fn kernel_trap() {
 
     assert!(sstatus.spp() == SPP::Supervisor, "kerneltrap not from supervisor mode");
     assert!(!sstatus.sie(), "kernel interrupt is enabled");
+    check_kernel_stack("kernel_trap");
     
     match scause.cause() {
         // PLIC interrupt
@@ -61,8 +62,16 @@ pub fn kernel_trap() {
                 };
             }
             assert!(sip::read().bits() & 2 == 0, "Failed to clear ssip");
-            // Not doing time like xv6 here, we use CLINT for time.
-            // ?: No Timer Vec then?
+            ipi::handle_ipi();
+            if timer::take_forwarded_tick(get_hart_id()) {
+                // Not doing time like xv6 here, we use CLINT for time.
+                // ?: No Timer Vec then?
+                watchdog_tick();
+            }
+        },
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            timer::tick(get_hart_id(), sepc);
+            watchdog_tick();
         },
         Trap::Exception(Exception::InstructionPageFault)    |
         Trap::Exception(Exception::LoadPageFault)           |
@@ -70,7 +79,18 @@ pub fn kernel_trap() {
             if let Some(proc) = get_processor().current() {
                 let proc_inner = unsafe{proc.inner.leak()};
                 let lazy_res = proc_inner.mem_layout.do_lazy(VirtAddr::from(stval).into());
-                if lazy_res.is_err() {
+                if matches!(lazy_res, Err(e) if e == ErrorNum::ENOMEM) {
+                    // can't kill ourselves halfway through a kernel access, pick someone else and retry
+                    if oom_kill(Some(proc.pid)).is_err() {
+                        fatal!("Kernel Pagefault out of memory, OOM killer found no victim.");
+                        fatal!("STVAL: {:x}", stval);
+                        fatal!("SEPC : {:x}", sepc);
+                        panic!("Kernel panic");
+                    }
+                } else if matches!(lazy_res, Err(e) if e == ErrorNum::EAGAIN) {
+                    // shared file page being truncated away, fault again once it's done
+                    verbose!("kernel lazy on {:x} retried.", stval);
+                } else if lazy_res.is_err() {
                     fatal!("Kernel Pagefault, lazy failed with {:?}.", lazy_res.unwrap_err());
                     fatal!("STVAL: {:x}", stval);
                     fatal!("SEPC : {:x}", sepc);
@@ -79,8 +99,11 @@ pub fn kernel_trap() {
                     verbose!("kernel lazy done.");
                 }
             } else {
-                if get_processor().do_lazy(VirtAddr::from(stval).into()).is_err() {
-                    fatal!("Kernel Pagefault.");
+                let lazy_res = get_processor().do_lazy(VirtAddr::from(stval).into());
+                if matches!(lazy_res, Err(e) if e == ErrorNum::ENOMEM) && oom_kill(None).is_ok() {
+                    verbose!("kernel lazy on {:x} retried after OOM kill.", stval);
+                } else if let Err(e) = lazy_res {
+                    fatal!("Kernel Pagefault, lazy failed with {:?}.", e);
                     fatal!("STVAL: {:x}", stval);
                     fatal!("SEPC : {:x}", sepc);
                     panic!("Kernel panic");
@@ -139,9 +162,15 @@ pub fn user_trap() -> ! {
         let sepc = sepc::read();
         let trap_context = TrapContext::current_ref();
         trap_context.epc = sepc.into();
+        check_kernel_stack("user_trap");
     
         assert!(sstatus.spp() == SPP::User, "user_trap not from user mode");
         assert!(!sstatus.sie(), "kernel interrupt is enabled");
+        if vector_dirty() {
+            if let Some(vector) = get_processor().current().unwrap().get_inner().vector.as_mut() {
+                vector.save();
+            }
+        }
         match scause.cause() {
             Trap::Exception(Exception::UserEnvCall) => {
                 let syscall_id = trap_context.a7;
@@ -153,23 +182,43 @@ pub fn user_trap() -> ! {
                     trap_context.a4,
                     trap_context.a5,
                 ];
+                // the user trampoline (signal return, default handlers) is ours, native whatever the image speaks
+                let abi = if (U_TRAMPOLINE_ADDR.0..U_TRAMPOLINE_ADDR.0 + PAGE_SIZE).contains(&trap_context.epc.0) {
+                    SyscallAbi::Native
+                } else {
+                    get_processor().current().unwrap().get_inner().syscall_abi
+                };
                 trap_context.epc += 4;
                 intr_on();
-                let res = syscall(syscall_id, args);
-                if let Ok(ret_val) = res {
-                    if syscall_id != SYSCALL_EXEC{
+                let (res, is_exec) = match abi {
+                    SyscallAbi::Native => (syscall(syscall_id, args), syscall_id == SYSCALL_EXEC),
+                    SyscallAbi::Linux => (linux_syscall(syscall_id, args), syscall_id == LINUX_EXECVE),
+                };
+                match (res, abi) {
+                    (Ok(_), _) if is_exec => (),
+                    (Ok(ret_val), SyscallAbi::Native) => {
                         trap_context.a0 = ret_val;
                         trap_context.a1 = 0;
-                    }
-                } else {
-                    warning!("Syscall {} failed with {:?}", syscall_id, res);
-                    trap_context.a0 = res.unwrap_err().to_ret();
-                    trap_context.a1 = usize::MAX;
+                    },
+                    // linux leaves every register but a0 as it was
+                    (Ok(ret_val), SyscallAbi::Linux) => trap_context.a0 = ret_val,
+                    (Err(err), SyscallAbi::Native) => {
+                        warning!("Syscall {} failed with {:?}", syscall_id, err);
+                        trap_context.a0 = err.to_ret();
+                        trap_context.a1 = usize::MAX;
+                    },
+                    (Err(err), SyscallAbi::Linux) => {
+                        warning!("Linux syscall {} failed with {:?}", syscall_id, err);
+                        trap_context.a0 = linux_errno(err).to_ret();
+                    },
                 }
             },
             Trap::Interrupt(Interrupt::SupervisorTimer) => {
                 verbose!("SupervisorTimer");
-                get_processor().suspend_switch();
+                timer::tick(get_hart_id(), sepc::read());
+                watchdog_tick();
+                get_processor().current().unwrap().get_inner().account_tick();
+                get_processor().suspend_switch(false);
             },
             Trap::Interrupt(Interrupt::SupervisorSoft) => {
                 let cleared_sip = sip::read().bits() & !2;
@@ -181,7 +230,14 @@ pub fn user_trap() -> ! {
                 }
                 assert!(sip::read().bits() & 2 == 0, "Failed to clear ssip");
                 verbose!("SupervisorSoft");
-                get_processor().suspend_switch();
+                ipi::handle_ipi();
+                if !timer::take_forwarded_tick(get_hart_id()) {
+                    return trap_return();
+                }
+                watchdog_tick();
+                // timer tick forwarded from M mode
+                get_processor().current().unwrap().get_inner().account_tick();
+                get_processor().suspend_switch(false);
             },
             // PLIC interrupt
             Trap::Interrupt(Interrupt::SupervisorExternal) => {
@@ -199,12 +255,33 @@ pub fn user_trap() -> ! {
                 //     }
                 // }
             },
+            // FP is Off until first use, turn it on and retry. One that's still illegal falls through next time.
+            Trap::Exception(Exception::IllegalInstruction) if trap_context.fp_enabled == 0 => {
+                verbose!("First FP use at {:x}, enabling.", sepc);
+                trap_context.enable_fp();
+            },
+            // same for V, on harts that have it
+            Trap::Exception(Exception::IllegalInstruction) if has_vector() && get_processor().current().unwrap().get_inner().vector.is_none() => {
+                verbose!("First vector use at {:x}, enabling.", sepc);
+                get_processor().current().unwrap().get_inner().vector = VectorState::new().map(Box::new);
+            },
             Trap::Exception(Exception::InstructionPageFault)    |
             Trap::Exception(Exception::LoadPageFault)           |
             Trap::Exception(Exception::StorePageFault)          => {
                 let proc = get_processor().current().unwrap();
                 let mut proc_inner = proc.get_inner();
-                if let Err(e) = proc_inner.mem_layout.do_lazy(VirtAddr::from(stval).into()) {
+                let lazy_res = proc_inner.mem_layout.do_lazy(VirtAddr::from(stval).into());
+                if matches!(lazy_res, Err(e) if e == ErrorNum::ENOMEM) {
+                    // retry the faulting instruction after someone was killed, or die if it's us
+                    drop(proc_inner);
+                    if oom_kill(None).is_err() {
+                        fatal!("OOM killer found no victim, killing current process.");
+                        proc.get_inner().recv_signal(SignalNum::SIGKILL).unwrap();
+                    }
+                } else if matches!(lazy_res, Err(e) if e == ErrorNum::EAGAIN) {
+                    // shared file page being truncated away, fault again once it's done
+                    verbose!("User lazy on {:x} retried.", stval);
+                } else if let Err(e) = lazy_res {
                     fatal!("User Pagefault, do lazy failed with {:?}.", e);
                     fatal!("STVAL: {:x}", stval);
                     fatal!("SEPC : {:x}", sepc);
@@ -266,7 +343,10 @@ pub fn fork_return() -> ! {
         let trap_context = TrapContext::current_ref();
         if pcb_inner.status == ProcessStatus::Init {
             let elf_file = pcb_inner.elf_file.clone();
-            (pcb_inner.entry_point, pcb_inner.data_end) = pcb_inner.mem_layout.map_elf(elf_file).unwrap();
+            let elf_info = pcb_inner.mem_layout.map_elf(elf_file, 0).unwrap();
+            pcb_inner.entry_point = elf_info.entry;
+            pcb_inner.heap_start = pcb_inner.mem_layout.map_heap(elf_info.image_end);
+            pcb_inner.brk = pcb_inner.heap_start;
             pcb_inner.status = ProcessStatus::Running;
             *trap_context = TrapContext::new();
             trap_context.epc = pcb_inner.entry_point;
@@ -289,6 +369,7 @@ pub fn trap_return() -> ! {
     }
     {
         intr_off();
+        check_kernel_stack("trap_return");
         let pcb = get_processor().current().unwrap();
         let mut pcb_inner = pcb.get_inner();
         assert!(pcb_inner.status == ProcessStatus::Running);
@@ -301,17 +382,29 @@ pub fn trap_return() -> ! {
         // Process pending signal
         // current TrapContext will be archieved
         // new TrapContext will have epc = SignalHandlerVA, ra = __user_restore_from_handler in UTrampoline
-        if pcb_inner.pending_signal.len() > 0 {
-            let signal = pcb_inner.pending_signal.pop_front().unwrap();
+        let mut stopped_parent = None;
+        if let Some(signal) = pcb_inner.next_signal() {
             debug!("Processing signal {:?} for process {:?}", signal, pcb.pid);
+            trace(pcb.pid.0, TraceEvent::Signal { signal });
             pcb_inner.signal_contexts.push(trap_context.clone());
             
             extern "C" {fn sutrampoline(); }
             let sigreturn_va = U_TRAMPOLINE_ADDR + (usr_sigreturn as usize - sutrampoline as usize);
             trap_context.ra = sigreturn_va.0;
             trap_context.epc = pcb_inner.signal_handler.get(&signal).unwrap().to_owned();
+            if signal.is_stop() && PCBInner::default_hander().get(&signal) == Some(&trap_context.epc) {
+                stopped_parent = pcb_inner.parent.clone().and_then(|p| p.upgrade());
+            }
+        }
+        match &pcb_inner.vector {
+            Some(vector) => vector.restore(),
+            None => vector_off(),
         }
         drop(pcb_inner);
+        // parent lock after ours is released, waitpid locks parent first
+        if let Some(parent) = stopped_parent {
+            parent.child_state_changed();
+        }
         unsafe {
             stvec::write(uservec_addr.0, stvec::TrapMode::Direct);
             sstatus::set_spie();
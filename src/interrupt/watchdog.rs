@@ -0,0 +1,53 @@
+//! Soft lockup detector.
+//!
+//! Every hart stamps its heartbeat when it takes a timer tick in S mode, which can only happen with interrupts on.
+//! On the same tick it looks at the other harts, and reports those that have not ticked for `WATCHDOG_THRESH`
+//! seconds. The stuck hart's pc comes from mscratch, where timervec saves mepc on every M mode tick, which still
+//! fires with S mode interrupts off.
+
+use core::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+
+use crate::{config::{MAX_CPUS, WATCHDOG_THRESH, CLOCK_FREQ}, process::{get_hart_id, get_processor, online_harts}, utils::{time::get_cycle, symbols::SymbolizedPC}};
+
+const NO_PID: usize = usize::MAX;
+
+// array initializers, each element gets a fresh copy
+const BEAT_INIT: AtomicUsize = AtomicUsize::new(0);
+const PID_INIT: AtomicUsize = AtomicUsize::new(NO_PID);
+const REPORTED_INIT: AtomicBool = AtomicBool::new(false);
+
+// not yet ticked is 0
+static LAST_BEAT: [AtomicUsize; MAX_CPUS] = [BEAT_INIT; MAX_CPUS];
+static LAST_PID: [AtomicUsize; MAX_CPUS] = [PID_INIT; MAX_CPUS];
+static REPORTED: [AtomicBool; MAX_CPUS] = [REPORTED_INIT; MAX_CPUS];
+
+/// Called on every S mode timer tick.
+pub fn watchdog_tick() {
+    let hart_id = get_hart_id();
+    let now = get_cycle();
+    let pid = get_processor().current().map_or(NO_PID, |pcb| pcb.pid.0);
+    LAST_BEAT[hart_id].store(now, Ordering::Relaxed);
+    LAST_PID[hart_id].store(pid, Ordering::Relaxed);
+    if REPORTED[hart_id].swap(false, Ordering::Relaxed) {
+        warning!("Hart {} recovered from soft lockup", hart_id);
+    }
+
+    for hart in online_harts() {
+        let beat = LAST_BEAT[hart].load(Ordering::Relaxed);
+        if hart == hart_id || beat == 0 || now.saturating_sub(beat) < WATCHDOG_THRESH * CLOCK_FREQ {
+            continue;
+        }
+        if REPORTED[hart].swap(true, Ordering::Relaxed) {
+            continue;
+        }
+        let last_pc = unsafe {(&crate::MSCRATCH_ARR[hart][3] as *const usize).read_volatile()};
+        let last_pid = LAST_PID[hart].load(Ordering::Relaxed);
+        error!(
+            "Soft lockup: hart {} stuck for {}s, last pid {}, pc {}",
+            hart,
+            (now - beat) / CLOCK_FREQ,
+            if last_pid == NO_PID {format!("<idle>")} else {format!("{}", last_pid)},
+            SymbolizedPC(last_pc)
+        );
+    }
+}
@@ -38,19 +38,21 @@ extern crate fdt_rs;
 
 use core::{arch::{global_asm, asm}, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
 
-global_asm!(include_str!("crt_setup.asm"));
+global_asm!(include_str!("crt_setup.asm"), max_cpus = const config::MAX_CPUS, boot_stack_size = const config::BOOT_STACK_SIZE);
 global_asm!(include_str!("interrupt/kernel_trap.asm"));
 global_asm!(include_str!("interrupt/trampoline.asm"));
 global_asm!(include_str!("interrupt/u_trampoline.asm"));
 
 
-use riscv::register::{medeleg, mepc, mideleg, mie, mscratch, mstatus, mtvec, pmpaddr0, pmpcfg0, satp, sie};
+use riscv::register::{medeleg, mepc, mideleg, mie, mscratch, mstatus, mtvec, pmpaddr0, pmpcfg0, satp, sie, sstatus};
 
 use crate::{process::get_hart_id};
 
+// M mode state is touched before anything is up, so it's static and capped by MAX_CPUS.
+// Which harts actually exist comes from device tree, see process::present_harts.
 #[no_mangle]
 #[link_section = ".bss"]
-static mut MSCRATCH_ARR: [[usize; 6]; config::MAX_CPUS] = [[0; 6]; config::MAX_CPUS];
+static mut MSCRATCH_ARR: [[usize; 9]; config::MAX_CPUS] = [[0; 9]; config::MAX_CPUS];
 #[no_mangle]
 #[link_section = ".bss"]
 static mut HART_REGISTER: [bool; config::MAX_CPUS] = [false; config::MAX_CPUS];
@@ -60,14 +62,19 @@ static LV1_BOOT_FIN: AtomicBool = AtomicBool::new(false);
 #[no_mangle]
 #[link_section = ".bss"]
 static LV2_BOOT_FIN: AtomicUsize = AtomicUsize::new(0);
+#[no_mangle]
+#[link_section = ".bss"]
+static SBI_HARTS_STARTED: AtomicBool = AtomicBool::new(false);
 
 #[no_mangle]
-extern "C" fn genesis_m(hart_id: usize) -> ! {
+extern "C" fn genesis_m(hart_id: usize, dtb: usize) -> ! {
     // set mstatus previous privilege
     extern "C" {
         fn genesis_s();
         fn timervec();
     }
+    mem::clear_bss();
+    device::set_boot_dtb(dtb);
     // every hart will go through this and set their tps
     unsafe {
         // set previous priviledge mode
@@ -113,15 +120,29 @@ extern "C" fn genesis_m(hart_id: usize) -> ! {
         // set timer interrupt and set up mscratch
         // mscratch for the cpu will store registers used in timervec
         // scratch[0,1,2] : register save area.
+        // scratch[3] : pc of last timer interrupt, for watchdog.
         // scratch[4] : address of CLINT's MTIMECMP register.
         // scratch[5] : desired interval between interrupts.
-        interrupt::CLINT.set_mtimecmp(hart_id, interrupt::CLINT.get_time() + (config::CLOCK_FREQ / config::TIMER_FRAC) as usize);
+        // scratch[6] : address of CLINT's MSIP register.
+        // scratch[7] : set when a timer tick is forwarded, see interrupt::timer::take_forwarded_tick.
+        // scratch[8] : set by a panicking hart before its IPI, to park this one in M mode.
         MSCRATCH_ARR[hart_id][4] = (config::CLINT_ADDR + 0x4000 + 8 * hart_id).0;
         MSCRATCH_ARR[hart_id][5] = config::CLOCK_FREQ / config::TIMER_FRAC;
+        MSCRATCH_ARR[hart_id][6] = (config::CLINT_ADDR + 4 * hart_id).0;
         mscratch::write(MSCRATCH_ARR[hart_id].as_ptr() as usize);
         mtvec::write(timervec as usize, mtvec::TrapMode::Direct);
-        // only enableling timer interrupt so should be fine
-        mie::set_mtimer();
+        if interrupt::timer::has_sstc() {
+            // S mode does its own timer with stimecmp, see interrupt::timer.
+            // menvcfg.STCE, and mcounteren.TM so it can read time to compare against
+            asm!("csrs 0x30a, {0}", in(reg) 1usize << 63);
+        } else {
+            interrupt::CLINT.set_mtimecmp(hart_id, interrupt::CLINT.get_time() + (config::CLOCK_FREQ / config::TIMER_FRAC) as usize);
+            mie::set_mtimer();
+        }
+        // software interrupt for IPI and panic halt
+        mie::set_msoft();
+        // mcounteren.TM, let S (and U, see utils::vdso) read time
+        asm!("csrs mcounteren, {0}", in(reg) 2usize);
         mstatus::set_mie();
         // set thread pointer and return
         asm! {
@@ -133,20 +154,70 @@ extern "C" fn genesis_m(hart_id: usize) -> ! {
 }
 
 
+/// Entry when firmware runs us in S mode. Does what genesis_m would, minus the M mode part that firmware owns.
+#[no_mangle]
+extern "C" fn genesis_sbi(hart_id: usize, dtb: usize) -> ! {
+    extern "C" {
+        fn _start_sbi();
+    }
+    mem::clear_bss();
+    device::set_boot_dtb(dtb);
+    interrupt::sbi::set_sbi_boot();
+    unsafe {
+        sstatus::set_fs(sstatus::FS::Initial);
+        satp::set(satp::Mode::Bare, 0, 0);
+        sie::set_sext();
+        sie::set_ssoft();
+        sie::set_stimer();
+        HART_REGISTER[hart_id] = true;
+    }
+    // HSM firmware only starts one hart, which may not be hart 0. Whoever comes first brings up the rest,
+    // legacy firmware already started all of them and just fails these calls.
+    if !SBI_HARTS_STARTED.swap(true, Ordering::SeqCst) {
+        let present = device::present_hart_mask();
+        for hart in 0..config::MAX_CPUS {
+            if hart != hart_id && present & (1 << hart) != 0 {
+                let _ = interrupt::sbi::hart_start(hart, _start_sbi as usize, 0);
+            }
+        }
+    }
+    genesis_s()
+}
+
 #[no_mangle]
 extern "C" fn genesis_s() -> ! {
     process::intr_off();
+    if !process::hart_present(get_hart_id()) {
+        // not in device tree, has no processor struct, so can't even log
+        loop {
+            unsafe{asm!("wfi")};
+        }
+    }
+    utils::stack_guard::init_boot_stack_canary();
     interrupt::set_kernel_trap_entry();
+    interrupt::timer::init_hart();
+    unsafe {
+        // scounteren.TM, for vDSO
+        asm!("csrs scounteren, {0}", in(reg) 2usize);
+    }
     if get_hart_id() == 0 {
         // common init code (mm/fs)
         mem::init();
         device::init();
+        utils::bootargs::init();
+        device::vconsole::init();
+        utils::vdso::init();
         mem::hart_init();
 
         println!("\r\n\n\n\nParch OS\n");
         println!("Ver\t: {}", version::VERSION);
 
         fs::init();
+        utils::crash_dump::init();
+
+        if utils::bootargs::has("selftest") {
+            utils::ktest::run_all();
+        }
 
         process::init();
 
@@ -158,8 +229,7 @@ extern "C" fn genesis_s() -> ! {
         // interrupt::init_hart();
     }
     LV2_BOOT_FIN.fetch_add(1, Ordering::SeqCst);
-    // while LV2_BOOT_FIN.load(Ordering::SeqCst) < device::DEVICE_MANAGER.acquire_r().get_dev_tree().hart_count() {}
-    if get_hart_id() == 0 {milestone!("Boot finished. Starting scheduler.");}
+    if get_hart_id() == 0 {milestone!("Boot finished. Starting scheduler, {} harts present.", process::present_hart_count());}
     process::hart_init();
     
     unreachable!();
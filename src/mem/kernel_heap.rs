@@ -1,13 +1,29 @@
 //! Kernem dynamic memory allocator for oshit kernel.
 
+use core::alloc::{GlobalAlloc, Layout};
 use buddy_system_allocator::LockedHeap;
-use crate::config::KERNEL_HEAP_SIZE;
+use crate::config::{KERNEL_HEAP_SIZE, POISON_BYTE};
 
+/// LockedHeap that fills freed blocks with POISON_BYTE in debug builds, so use after free reads garbage
+/// pointers and faults instead of quietly seeing the old object.
+struct PoisonedHeap(LockedHeap<64>);
 
+unsafe impl GlobalAlloc for PoisonedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if cfg!(debug_assertions) {
+            core::ptr::write_bytes(ptr, POISON_BYTE, layout.size());
+        }
+        self.0.dealloc(ptr, layout)
+    }
+}
 
 /// The global allocator, enables us to use extern alloc crate.
 #[global_allocator]
-static KERNEL_HEAP_ALLOCATOR: LockedHeap<64> = LockedHeap::empty();
+static KERNEL_HEAP_ALLOCATOR: PoisonedHeap = PoisonedHeap(LockedHeap::empty());
 
 /// The empty space to use as kernel heap.
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
@@ -16,7 +32,7 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 /// *Don't call this multiple times!*
 pub fn init_kernel_heap() {
     unsafe {
-        KERNEL_HEAP_ALLOCATOR.lock().init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+        KERNEL_HEAP_ALLOCATOR.0.lock().init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
     verbose!("kernel heap initialzed, size = {}", KERNEL_HEAP_SIZE);
 }
@@ -0,0 +1,39 @@
+use alloc::{collections::BTreeMap, sync::{Arc, Weak}};
+use lazy_static::*;
+
+use crate::{fs::RegularFile, utils::{SpinMutex, Mutex, ErrorNum, UUID}};
+
+use super::{PageGuard, page_allocator::PageGuardInner};
+
+/// (fs uuid, inode, file offset)
+type KsmKey = (UUID, u32, usize);
+
+lazy_static!{
+    /// Read-only program pages shared across processes. Holds no reference, a page is freed with its last mapping.
+    /// Leaf lock: never take other locks while holding it.
+    static ref KSM_CACHE: SpinMutex<BTreeMap<KsmKey, Weak<PageGuardInner>>> = SpinMutex::new("KSM cache", BTreeMap::new());
+}
+
+/// Get the page of `file` at `offset`, shared with every other read-only mapping of it.
+/// Only for mappings that are never written to, or COW will write through into the shared frame.
+pub fn ksm_get_page(file: &Arc<dyn RegularFile>, offset: usize) -> Result<PageGuard, ErrorNum> {
+    let key = (file.vfs().get_uuid(), file.stat()?.inode, offset);
+    if let Some(page) = KSM_CACHE.acquire().get(&key).and_then(|w| w.upgrade()) {
+        return Ok(PageGuard(page));
+    }
+    // copy without cache lock held, file lock is taken in here
+    let page = file.copy_page(offset)?;
+    let mut cache = KSM_CACHE.acquire();
+    if let Some(exist) = cache.get(&key).and_then(|w| w.upgrade()) {
+        // someone beat us
+        return Ok(PageGuard(exist));
+    }
+    cache.retain(|_, w| w.strong_count() > 0);
+    cache.insert(key, Arc::downgrade(&page.0));
+    Ok(page)
+}
+
+/// File content changed, stop handing out old pages. Existing mappings keep their copy.
+pub fn ksm_invalidate(fs_uuid: UUID, inode: u32) {
+    KSM_CACHE.acquire().retain(|(uuid, ino, _), _| !(*uuid == fs_uuid && *ino == inode));
+}
@@ -0,0 +1,162 @@
+//! Self tests for pagetable, segment, mlock, free poisoning, page owners and page zeroing, see utils::ktest.
+
+use alloc::string::String;
+
+use crate::{config::{PAGE_SIZE, POISON_BYTE}, utils::{ErrorNum, ktest::KTestResult}};
+
+use super::{PageTable, PTEFlags, VirtPageNum, VirtAddr, VPNRange, PhysAddr, MemLayout, ManagedSegment, SegmentFlags, FaultKind, FaultStats, PageOwner, alloc_vm_page, page_owners, zero_free_pages};
+
+// far from anything the kernel maps
+const TEST_VPN: VirtPageNum = VirtPageNum(0x12_3456);
+
+fn pagetable_map_unmap() -> KTestResult {
+    let mut pagetable = PageTable::new_empty();
+    let page = alloc_vm_page();
+    kassert!(pagetable.translate(TEST_VPN).is_err());
+    pagetable.map(TEST_VPN, page.ppn, PTEFlags::R | PTEFlags::W);
+    kassert!(pagetable.translate(TEST_VPN) == Ok(page.ppn));
+
+    let other = alloc_vm_page();
+    pagetable.remap(TEST_VPN, other.ppn, PTEFlags::R);
+    kassert!(pagetable.translate(TEST_VPN) == Ok(other.ppn));
+
+    pagetable.unmap(TEST_VPN);
+    kassert!(pagetable.translate(TEST_VPN).is_err());
+    // neighbour in the same leaf table was never touched
+    kassert!(pagetable.translate(VirtPageNum(TEST_VPN.0 + 1)).is_err());
+    Ok(())
+}
+ktest!(pagetable_map_unmap, pagetable_map_unmap);
+
+fn segment_clone_cow() -> KTestResult {
+    let range = VPNRange::new(TEST_VPN, VirtPageNum(TEST_VPN.0 + 1));
+    let flag = SegmentFlags::R | SegmentFlags::W | SegmentFlags::U;
+    let mut parent_pt = PageTable::new_empty();
+    let mut child_pt = PageTable::new_empty();
+    let parent = ManagedSegment::new(range, flag, 0);
+    parent.do_map(&mut parent_pt).map_err(|e| format!("map: {:?}", e))?;
+    kassert!(parent.get_page(TEST_VPN).is_none());
+    kassert!(parent.is_lazy(TEST_VPN));
+
+    // lazy alloc on first touch
+    let mut faults = FaultStats::default();
+    faults.count(parent.do_lazy(TEST_VPN, &mut parent_pt).map_err(|e| format!("lazy alloc: {:?}", e))?);
+    let parent_page = parent.get_page(TEST_VPN).ok_or(String::from("no page after lazy alloc"))?;
+    kassert!(parent_pt.translate(TEST_VPN) == Ok(parent_page.ppn));
+    kassert!(!parent.is_lazy(TEST_VPN));
+    unsafe {PhysAddr::from(parent_page.ppn).write_volatile(&0x5a5au16)};
+
+    // fork shares the frame read only
+    let child = parent.clone_seg(&mut parent_pt).map_err(|e| format!("clone: {:?}", e))?;
+    child.do_map(&mut child_pt).map_err(|e| format!("map child: {:?}", e))?;
+    kassert!(child_pt.translate(TEST_VPN) == Ok(parent_page.ppn));
+    // resident already, populate leaves it to the write fault
+    kassert!(!child.is_lazy(TEST_VPN));
+
+    // child write copies
+    kassert!(child.do_lazy(TEST_VPN, &mut child_pt) == Ok(FaultKind::Cow));
+    let child_page = child.get_page(TEST_VPN).ok_or(String::from("no page after cow"))?;
+    kassert!(child_page.ppn != parent_page.ppn);
+    kassert!(unsafe{PhysAddr::from(child_page.ppn).read_volatile::<u16>()} == 0x5a5a);
+
+    // parent is the last user, takes the page back without copy
+    let parent_ppn = parent_page.ppn;
+    drop(parent_page);
+    faults.count(parent.do_lazy(TEST_VPN, &mut parent_pt).map_err(|e| format!("parent cow: {:?}", e))?);
+    kassert!(parent.get_page(TEST_VPN).map(|pg| pg.ppn) == Some(parent_ppn));
+    kassert!(faults.minor == 1 && faults.cow == 1 && faults.file == 0);
+    Ok(())
+}
+ktest!(segment_clone_cow, segment_clone_cow);
+
+fn managed_segment_resize() -> KTestResult {
+    let flag = SegmentFlags::R | SegmentFlags::W | SegmentFlags::U;
+    let mut pagetable = PageTable::new_empty();
+    let heap = ManagedSegment::new(VPNRange::new(TEST_VPN, TEST_VPN), flag, 0);
+    heap.do_map(&mut pagetable).map_err(|e| format!("map: {:?}", e))?;
+    kassert!(!heap.contains(TEST_VPN));
+
+    let managed = heap.clone().as_managed().map_err(|e| format!("as_managed: {:?}", e))?;
+    managed.resize(PAGE_SIZE + 1, &mut pagetable);
+    kassert!(managed.end() == VirtPageNum(TEST_VPN.0 + 2));
+    kassert!(heap.do_lazy(VirtPageNum(TEST_VPN.0 + 1), &mut pagetable) == Ok(FaultKind::Minor));
+    kassert!(pagetable.translate(VirtPageNum(TEST_VPN.0 + 1)).is_ok());
+
+    // shrinking drops the mapping
+    managed.resize(PAGE_SIZE, &mut pagetable);
+    kassert!(!heap.contains(VirtPageNum(TEST_VPN.0 + 1)));
+    kassert!(pagetable.translate(VirtPageNum(TEST_VPN.0 + 1)).is_err());
+    Ok(())
+}
+ktest!(managed_segment_resize, managed_segment_resize);
+
+fn mlock_populated_range() -> KTestResult {
+    let mut layout = MemLayout::new();
+    let head = VirtAddr::from(TEST_VPN);
+    layout.register_segment(ManagedSegment::new(VPNRange::new(TEST_VPN, VirtPageNum(TEST_VPN.0 + 4)), SegmentFlags::R | SegmentFlags::W | SegmentFlags::U, 4 * PAGE_SIZE));
+    layout.do_map();
+    // as MAP_POPULATE does, then nothing is left for mlock to fault in
+    layout.populate(head, 4 * PAGE_SIZE).map_err(|e| format!("populate: {:?}", e))?;
+    kassert!(layout.lock_growth(head, 4 * PAGE_SIZE) == Ok(4));
+    layout.mlock(head + PAGE_SIZE, 2 * PAGE_SIZE).map_err(|e| format!("mlock: {:?}", e))?;
+    kassert!(layout.lock_growth(head, 4 * PAGE_SIZE) == Ok(2));
+    kassert!(layout.lock_growth(head + PAGE_SIZE, 1) == Ok(0));
+
+    kassert!(layout.lock_growth(head, 5 * PAGE_SIZE) == Err(ErrorNum::ENOMEM));
+    kassert!(layout.lock_growth(head, usize::MAX) == Err(ErrorNum::EINVAL));
+    layout.munlock(head, 4 * PAGE_SIZE).map_err(|e| format!("munlock: {:?}", e))?;
+    kassert!(layout.locked.is_empty());
+    Ok(())
+}
+ktest!(mlock_populated_range, mlock_populated_range);
+
+fn freed_memory_poisoned() -> KTestResult {
+    if !cfg!(debug_assertions) {
+        return Ok(());
+    }
+    let page = alloc_vm_page();
+    let ppn = page.ppn;
+    drop(page);
+    // still identity mapped, only sitting in this hart's magazine
+    let content = unsafe {core::slice::from_raw_parts((ppn.0 * PAGE_SIZE) as *const u8, PAGE_SIZE)};
+    kassert!(content.iter().all(|&b| b == POISON_BYTE));
+
+    let block = alloc::boxed::Box::new([0x5Au8; 64]);
+    let ptr = alloc::boxed::Box::into_raw(block);
+    unsafe {drop(alloc::boxed::Box::from_raw(ptr))};
+    // buddy allocator keeps its free list link in the first word
+    let tail = unsafe {core::slice::from_raw_parts((ptr as *const u8).add(8), 56)};
+    kassert!(tail.iter().all(|&b| b == POISON_BYTE));
+    Ok(())
+}
+ktest!(freed_memory_poisoned, freed_memory_poisoned);
+
+fn page_owner_registry() -> KTestResult {
+    if !cfg!(debug_assertions) {
+        return Ok(());
+    }
+    let page = alloc_vm_page().with_owner(PageOwner::Inode(12345));
+    let site = format!("{}:{}", file!(), line!() - 1);
+    kassert!(page_owners().lines().any(|l| l.contains("inode 12345") && l.ends_with(&site)));
+    drop(page);
+    kassert!(!page_owners().lines().any(|l| l.ends_with(&site)));
+    Ok(())
+}
+ktest!(page_owner_registry, page_owner_registry);
+
+fn vm_pages_zeroed() -> KTestResult {
+    let dirty: alloc::vec::Vec<_> = (0..4).map(|_| alloc_vm_page()).collect();
+    for page in dirty.iter() {
+        unsafe {core::ptr::write_bytes((page.ppn.0 * PAGE_SIZE) as *mut u8, 0xA5, PAGE_SIZE)};
+    }
+    drop(dirty);
+    // whether the pool or the magazine hands them out, they come back clean
+    zero_free_pages();
+    for _ in 0..8 {
+        let page = alloc_vm_page();
+        let content = unsafe {core::slice::from_raw_parts((page.ppn.0 * PAGE_SIZE) as *const u8, PAGE_SIZE)};
+        kassert!(content.iter().all(|&b| b == 0));
+    }
+    Ok(())
+}
+ktest!(vm_pages_zeroed, vm_pages_zeroed);
@@ -1,18 +1,70 @@
-use core::{arch::asm};
+use core::{arch::asm, sync::atomic::{AtomicUsize, Ordering}};
 
-use alloc::{vec::Vec, sync::Arc, string::String};
+use alloc::{collections::BTreeSet, vec::Vec, sync::Arc, string::String};
 use riscv::register::{satp};
-use crate::{config::{PHYS_END_ADDR, PAGE_SIZE, PROC_U_STACK_ADDR}, fs::RegularFile, mem::{TrampolineSegment, UTrampolineSegment, TrapContextSegment, IdenticalMappingSegment, segment::{SegmentFlags, ProgramSegment}, VirtAddr, types::VPNRange, VMASegment}, process::{get_processor, get_hart_id}, utils::{ErrorNum, RWLock}};
-use super::{ArcSegment, MMAPType, PageTable, ProcKStackSegment, Segment, VirtPageNum, segment::ProcUStackSegment};
+use crate::{config::{PHYS_END_ADDR, PAGE_SIZE, PROC_ARGS_ADDR, ARG_MAX, ASLR_MMAP_RAND_PAGES}, fs::{RegularFile, Path, SeekWhence}, mem::{TrampolineSegment, UTrampolineSegment, VdsoSegment, TrapContextSegment, IdenticalMappingSegment, segment::{SegmentFlags, ProgramSegment}, VirtAddr, types::VPNRange, VMASegment}, process::{get_processor, get_hart_id}, utils::{ErrorNum, KernelError, RWLock, rand_usize, UUID}};
+use super::{ArcSegment, DirtyPages, FaultKind, ManagedSegment, MemUsage, MMAPType, PageTable, ProcKStackSegment, Segment, VirtPageNum, segment::ProcUStackSegment};
 use crate::device::DEVICE_MANAGER;
 use crate::utils::elf_rs_wrapper::read_elf;
 use elf_rs::*;
 
 use elf_rs::ElfFile;
 
+/// What exec needs to know about a loaded elf.
+pub struct ElfInfo {
+    pub entry: VirtAddr,
+    /// end of the highest PT_LOAD, heap goes after this
+    pub image_end: VirtAddr,
+    /// program headers in user memory, for AT_PHDR
+    pub phdr: VirtAddr,
+    pub phent: usize,
+    pub phnum: usize,
+    /// PT_INTERP
+    pub interp: Option<Path>,
+    /// e_ident[EI_OSABI]
+    pub os_abi: u8,
+}
+
+/// /proc/sys/randomize_va_space. 0 for fixed layout, otherwise randomize stack top, mmap base and interpreter base on exec.
+pub static RANDOMIZE_VA_SPACE: AtomicUsize = AtomicUsize::new(1);
+
+/// Random offset in `[0, range)`, multiple of `align`. Always 0 if ASLR is disabled.
+pub fn aslr_offset(range: usize, align: usize) -> usize {
+    if RANDOMIZE_VA_SPACE.load(Ordering::Relaxed) == 0 || range < align {
+        0
+    } else {
+        (rand_usize() % (range / align)) * align
+    }
+}
+
+/// Faults resolved by do_lazy. Survive exec, start from zero in a forked child.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaultStats {
+    pub minor: usize,
+    pub cow: usize,
+    pub file: usize,
+}
+
+impl FaultStats {
+    pub fn count(&mut self, kind: FaultKind) {
+        match kind {
+            FaultKind::Minor => self.minor += 1,
+            FaultKind::Cow => self.cow += 1,
+            FaultKind::File => self.file += 1,
+        }
+    }
+}
+
 pub struct MemLayout {
     pub pagetable: PageTable,
-    pub segments: Vec<ArcSegment>
+    pub segments: Vec<ArcSegment>,
+    /// get_space() search downward from here.
+    pub mmap_top: VirtPageNum,
+    pub faults: FaultStats,
+    /// mlocked pages, not inherited on fork and gone with their mapping. Reclaim must leave these resident.
+    pub locked: BTreeSet<VirtPageNum>,
+    /// written shared pages of what was unmapped or msynced, see take_unsynced
+    unsynced: DirtyPages,
 }
 
 
@@ -21,7 +73,11 @@ impl MemLayout {
         verbose!("Initializing MemLayout...");
         let mut layout = Self {
             pagetable: PageTable::new(),
-            segments: Vec::new()
+            segments: Vec::new(),
+            mmap_top: Self::default_mmap_top(),
+            faults: FaultStats::default(),
+            locked: BTreeSet::new(),
+            unsynced: Vec::new(),
         };
 
         extern "C" {
@@ -41,6 +97,9 @@ impl MemLayout {
         // u_trampoline
         verbose!("Registering UTrampoline...");
         layout.register_segment(UTrampolineSegment::new());
+        // vdso data
+        verbose!("Registering vDSO data...");
+        layout.register_segment(VdsoSegment::new());
         // trap_context
         verbose!("Registering TrapContext...");
         layout.register_segment(TrapContextSegment::new());
@@ -169,6 +228,14 @@ impl MemLayout {
 
         // let basic = basic.into_iter().map(|va| VirtPageNum::from(va)).collect::<Vec<VirtPageNum>>();
 
+        self.take_user_segments()?;
+        Ok(())
+    }
+
+    /// Unmap and remove the program image, mmaps and heap, handing them to the caller. Their pages go when the
+    /// segments are dropped, and that may write back shared file mappings, so a caller holding a spin lock
+    /// drops them after releasing it.
+    pub fn take_user_segments(&mut self) -> Result<Vec<ArcSegment>, ErrorNum> {
         let mut to_clear = Vec::new();
         for seg in self.segments.iter() {
             verbose!("reset checking {:?}...", seg);
@@ -182,22 +249,70 @@ impl MemLayout {
                 to_clear.push(seg.clone());
             }
         }
-        for seg in to_clear {
-            self.remove_segment(seg)?;
+        for seg in to_clear.iter() {
+            self.remove_segment(seg.clone())?;
         }
-        Ok(())
+        Ok(to_clear)
+    }
+
+    fn default_mmap_top() -> VirtPageNum {
+        VirtPageNum::from(VirtAddr::from(PROC_ARGS_ADDR - PAGE_SIZE))
+    }
+
+    /// Pick a new mmap base for a fresh program image.
+    pub fn randomize_mmap_top(&mut self) {
+        self.mmap_top = Self::default_mmap_top() - aslr_offset(ASLR_MMAP_RAND_PAGES * PAGE_SIZE, PAGE_SIZE) / PAGE_SIZE;
     }
 
     pub fn register_segment(&mut self, seg: ArcSegment) {
         self.segments.push(seg);
     }
 
+    /// Empty heap right after an image ending at `image_end`, returns where it starts. Grown by set_brk.
+    pub fn map_heap(&mut self, image_end: VirtAddr) -> VirtAddr {
+        let start = image_end.to_vpn_ceil();
+        let heap = ManagedSegment::new(VPNRange::new(start, start), SegmentFlags::R | SegmentFlags::W | SegmentFlags::U, 0);
+        heap.do_map(&mut self.pagetable).unwrap();
+        self.register_segment(heap);
+        start.into()
+    }
+
+    /// Move the end of the heap starting at `heap_start` to `brk`. ENOMEM if it would run into another mapping.
+    pub fn set_brk(&mut self, heap_start: VirtAddr, brk: VirtAddr) -> Result<(), ErrorNum> {
+        let start_vpn: VirtPageNum = heap_start.into();
+        let heap = self.segments.iter()
+            .filter_map(|seg| seg.clone().as_managed().ok())
+            .find(|seg| seg.start() == start_vpn)
+            .ok_or(ErrorNum::ENOSEG)?;
+        for vpn in VPNRange::new(heap.end(), brk.to_vpn_ceil()) {
+            if self.occupied(vpn) {
+                return Err(ErrorNum::ENOMEM);
+            }
+        }
+        heap.resize(brk - heap_start, &mut self.pagetable);
+        Ok(())
+    }
+
+    /// Area for the argv and envp strings of a new image, below the user stack. Cleared by reset.
+    pub fn map_args(&mut self) {
+        let start = VirtPageNum::from(PROC_ARGS_ADDR);
+        let args = ManagedSegment::new(VPNRange::new(start, start + ARG_MAX / PAGE_SIZE), SegmentFlags::R | SegmentFlags::W | SegmentFlags::U, ARG_MAX);
+        args.do_map(&mut self.pagetable).unwrap();
+        self.register_segment(args);
+    }
+
     pub fn map_proc_stack(&mut self) {
         self.register_segment(ProcKStackSegment::new());
         self.register_segment(ProcUStackSegment::new());
         self.do_map();
     }
 
+    /// Just the kernel stack, for kernel threads.
+    pub fn map_kernel_stack(&mut self) {
+        self.register_segment(ProcKStackSegment::new());
+        self.do_map();
+    }
+
     pub fn do_map(&mut self) {
         debug!("Memlayout @ {:?} mapping.", self.pagetable.root_ppn);
         for seg in self.segments.iter() {
@@ -225,13 +340,14 @@ impl MemLayout {
         }   
     }
 
+    /// Mapped, or belongs to a segment that is not populated yet (lazy / stack).
     pub fn occupied(&self, vpn: VirtPageNum) -> bool {
-        self.pagetable.translate(vpn).is_ok()
+        self.pagetable.translate(vpn).is_ok() || self.segments.iter().any(|seg| seg.contains(vpn))
     }
 
     // length in byte
     pub fn get_space(&self, length: usize) -> Result<VirtPageNum, ErrorNum> {
-        let vpn_top = VirtPageNum::from(VirtAddr::from(PROC_U_STACK_ADDR - PAGE_SIZE));
+        let vpn_top = self.mmap_top;
         let vpn_bottom = VirtPageNum::from(VirtAddr::from(PHYS_END_ADDR.0));
         let page_count = (length / PAGE_SIZE) + 2; // guard page
         for vpn_s in VPNRange::new(vpn_top - page_count, vpn_bottom) {
@@ -251,6 +367,20 @@ impl MemLayout {
         Err(ErrorNum::ENOMEM)
     }
 
+    /// User address space reserved, in bytes, whether the pages are resident or not.
+    pub fn user_size(&self) -> usize {
+        self.segments.iter().map(|seg| seg.user_size()).sum()
+    }
+
+    pub fn mem_usage(&self) -> MemUsage {
+        self.segments.iter().fold(MemUsage::default(), |acc, seg| acc + seg.mem_usage())
+    }
+
+    /// Pages actually backed by frames, used as OOM badness.
+    pub fn resident_pages(&self) -> usize {
+        self.mem_usage().resident
+    }
+
     pub fn get_segment(&self, vpn: VirtPageNum) -> Result<ArcSegment, ErrorNum> {
         for seg in self.segments.iter() {
             if seg.contains(vpn) {
@@ -262,12 +392,14 @@ impl MemLayout {
 
     pub fn unmap_segment_by_vpn(&mut self, vpn: VirtPageNum) -> Result<(), ErrorNum> {
         let seg = self.get_segment(vpn)?;
-        seg.do_unmap(&mut self.pagetable)?;
-        Ok(())
+        self.unmap_segment(&seg)
     }
 
     pub fn unmap_segment(&mut self, seg: &ArcSegment) -> Result<(), ErrorNum> {
         seg.do_unmap(&mut self.pagetable)?;
+        if let Ok(vma) = seg.clone().as_vma() {
+            self.unsynced.append(&mut vma.take_unsynced());
+        }
         Ok(())
     }
 
@@ -278,6 +410,7 @@ impl MemLayout {
 
     pub fn remove_segment(&mut self, seg: ArcSegment) -> Result<(), ErrorNum> {
         if self.segments.contains(&seg) {
+            self.locked.retain(|vpn| !seg.contains(*vpn));
             self.unmap_segment(&seg)?;
             self.segments.retain(|x| x.clone() != seg);
             Ok(())
@@ -287,7 +420,7 @@ impl MemLayout {
     }
 
     pub fn mmap_file(&mut self, file: Arc<dyn RegularFile>, offset: usize, length: usize, mmap_type: MMAPType) -> Result<VirtPageNum, ErrorNum> {
-        if mmap_type == MMAPType::Shared && offset % PAGE_SIZE == 0 {
+        if mmap_type == MMAPType::Shared && offset % PAGE_SIZE != 0 {
             return Err(ErrorNum::ENOTALIGNED);
         }
         let stat = file.stat()?;
@@ -303,24 +436,35 @@ impl MemLayout {
         Ok(start_vpn)
     }
 
-    pub fn map_elf(&mut self, elf_file: Arc<dyn RegularFile>) -> Result<(VirtAddr, VirtAddr), ErrorNum> {
+    /// Map LOAD segments of elf_file, shifted by `bias` (0 for ET_EXEC).
+    pub fn map_elf(&mut self, elf_file: Arc<dyn RegularFile>, bias: usize) -> Result<ElfInfo, ErrorNum> {
         verbose!("Mapping elf into memory space");
         // first map it for easy reading...
         let stat = elf_file.stat()?;
+        let mut copied = Vec::new();
         let first_map = if get_processor().current().is_none() {
-            get_processor().map_file(elf_file.clone())
+            Some(get_processor().map_file(elf_file.clone()))
+        } else if satp::read().ppn() != self.pagetable.root_ppn.0 {
+            // not the active address space (spawn building a child), nothing to read through, copy it in.
+            elf_file.seek(0, SeekWhence::Set)?;
+            copied = elf_file.read(stat.file_size)?;
+            None
         } else {
             // a little bit faster without copying.
             let res = self.mmap_file(elf_file.clone(), 0, stat.file_size, MMAPType::Private)?;
             self.do_map();
-            res
+            Some(res)
         };
         verbose!("init map start {:?} len {:?}", first_map, stat.file_size);
 
         // some dirty trick for zero copy
-        let start_va: VirtAddr = first_map.into();
-        let start_ptr = start_va.0 as *mut u8;
-        let buffer = unsafe{core::slice::from_raw_parts(start_ptr, stat.file_size)};
+        let buffer = match first_map {
+            Some(first_map) => {
+                let start_va: VirtAddr = first_map.into();
+                unsafe{core::slice::from_raw_parts(start_va.0 as *const u8, stat.file_size)}
+            },
+            None => &copied[..],
+        };
 
         let elf = read_elf(buffer)?;
 
@@ -329,18 +473,34 @@ impl MemLayout {
         verbose!("elf Info: {:?}", elf);
         verbose!("Header Info: {:?}", elf.elf_header());
 
-        let mut data_end: VirtAddr = 0.into();
-        for h in elf.section_header_iter() {
-            let mapping = String::from_utf8(h.section_name().to_vec()).map_err(|_| ErrorNum::ENOEXEC)?;
-            if mapping.contains("data") {
-                data_end = ((h.addr() + h.size()) as usize).into();
-            }
-        }
-
+        let mut image_end: VirtAddr = 0.into();
+        let ph_offset = elf.elf_header().program_header_offset() as usize;
+        let mut phdr: VirtAddr = 0.into();
+        let mut interp = None;
         for p in elf.program_header_iter() {
             verbose!("Handling PH {:x?}", p);
+            if p.ph_type() == ProgramType::INTERP {
+                let start = p.offset() as usize;
+                let end = start + p.filesz() as usize;
+                if end > buffer.len() {
+                    return Err(ErrorNum::ENOEXEC);
+                }
+                // null terminated
+                let path_bytes: Vec<u8> = buffer[start..end].iter().cloned().take_while(|&b| b != 0).collect();
+                let path = String::from_utf8(path_bytes).map_err(|_| ErrorNum::ENOEXEC)?;
+                verbose!("Elf requested interpreter {}", path);
+                interp = Some(Path::new_s(path)?);
+            }
+            if p.ph_type() == ProgramType::PHDR {
+                phdr = (p.vaddr() as usize + bias).into();
+            }
             if p.ph_type() == ProgramType::LOAD {
-                let seg_start: VirtAddr = (p.vaddr() as usize).into();
+                let file_start = p.offset() as usize;
+                if phdr.0 == 0 && ph_offset >= file_start && ph_offset < file_start + p.filesz() as usize {
+                    phdr = (p.vaddr() as usize + bias + ph_offset - file_start).into();
+                }
+                let seg_start: VirtAddr = (p.vaddr() as usize + bias).into();
+                image_end = image_end.max(seg_start + p.memsz() as usize);
                 if seg_start.0 % PAGE_SIZE != 0 {
                     panic!("Program header not aligned!")
                 }
@@ -367,22 +527,37 @@ impl MemLayout {
                 self.register_segment(segment);
             }
         }
-        let entry_point = elf.entry_point() as usize;
+        let res = ElfInfo {
+            entry: (elf.entry_point() as usize + bias).into(),
+            image_end,
+            phdr,
+            phent: elf.elf_header().program_header_entry_size() as usize,
+            phnum: elf.elf_header().program_header_entry_num() as usize,
+            interp,
+            os_abi: buffer[7],
+        };
         // free the first mmap...
-        if get_processor().current().is_none() {
-            get_processor().unmap_file(first_map);
-        } else {
-            self.remove_segment_by_vpn(first_map).unwrap();
+        if let Some(first_map) = first_map {
+            if get_processor().current().is_none() {
+                get_processor().unmap_file(first_map);
+            } else {
+                self.remove_segment_by_vpn(first_map).unwrap();
+            }
         }
-        Ok((entry_point.into(), data_end.into()))
+        Ok(res)
     }
 
     pub fn fork(&mut self) -> Result<Self, ErrorNum> {
         debug!("Forking memlayout @ {:?}", self.pagetable.root_ppn);
         let mut layout = Self {
             pagetable: PageTable::new(),
-            segments: Vec::new()
+            segments: Vec::new(),
+            mmap_top: Self::default_mmap_top(),
+            faults: FaultStats::default(),
+            locked: BTreeSet::new(),
+            unsynced: Vec::new(),
         };
+        layout.mmap_top = self.mmap_top;
         debug!("New memlayout @ {:?}", layout.pagetable.root_ppn);
 
         for seg in self.segments.iter() {
@@ -393,19 +568,93 @@ impl MemLayout {
         Ok(layout)
     }
 
-    pub fn do_lazy(&mut self, vpn: VirtPageNum) -> Result<(), ErrorNum> {
+    pub fn do_lazy(&mut self, vpn: VirtPageNum) -> Result<(), KernelError> {
         for seg in self.segments.iter() {
             if seg.contains(vpn) {
-                return seg.do_lazy(vpn, &mut self.pagetable);
+                let kind = seg.do_lazy(vpn, &mut self.pagetable)?;
+                self.faults.count(kind);
+                return Ok(());
+            }
+        }
+        Err(ErrorNum::ENOSEG.ctx("no segment for the address"))
+    }
+
+    /// Fault in every lazy page of [head, head + length) now. ENOMEM if part of it is not mapped.
+    pub fn populate(&mut self, head: VirtAddr, length: usize) -> Result<(), ErrorNum> {
+        let range = VPNRange::new(head.into(), (head + length).to_vpn_ceil());
+        for vpn in range {
+            let seg = self.get_segment(vpn).map_err(|_| ErrorNum::ENOMEM)?;
+            if seg.is_lazy(vpn) {
+                let kind = seg.do_lazy(vpn, &mut self.pagetable)?;
+                self.faults.count(kind);
+            }
+        }
+        unsafe { asm!("sfence.vma"); }
+        Ok(())
+    }
+
+    /// Populate [head, head + length) and keep it resident until munlock or unmap.
+    pub fn mlock(&mut self, head: VirtAddr, length: usize) -> Result<(), ErrorNum> {
+        self.populate(head, length)?;
+        self.locked.extend(VPNRange::new(head.into(), (head + length).to_vpn_ceil()));
+        Ok(())
+    }
+
+    pub fn munlock(&mut self, head: VirtAddr, length: usize) -> Result<(), ErrorNum> {
+        let range = VPNRange::new(head.into(), (head + length).to_vpn_ceil());
+        if range.into_iter().any(|vpn| !self.occupied(vpn)) {
+            return Err(ErrorNum::ENOMEM);
+        }
+        self.locked.retain(|vpn| !range.contains(*vpn));
+        Ok(())
+    }
+
+    /// Pages a range would add to the locked set, for RLIMIT_MEMLOCK. EINVAL if it wraps around, ENOMEM if part
+    /// of it is not mapped.
+    pub fn lock_growth(&self, head: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
+        let end = head.0.checked_add(length).and_then(|end| end.checked_add(PAGE_SIZE - 1)).ok_or(ErrorNum::EINVAL)?;
+        let range = VPNRange::new(head.into(), VirtAddr::from(end - (PAGE_SIZE - 1)).to_vpn_ceil());
+        if range.into_iter().any(|vpn| !self.occupied(vpn)) {
+            return Err(ErrorNum::ENOMEM);
+        }
+        let already = self.locked.range(range.start()..range.end()).count();
+        Ok(range.end().0.saturating_sub(range.start().0) - already)
+    }
+
+    /// Collect writes through shared mappings in [head, head + length), of every mapping in it, for take_unsynced.
+    pub fn msync(&mut self, head: VirtAddr, length: usize) -> Result<(), ErrorNum> {
+        self.get_segment(head.into())?;
+        let range = VPNRange::new(head.into(), (head + length).to_vpn_ceil());
+        for seg in self.segments.iter() {
+            if let Ok(vma) = seg.clone().as_vma() {
+                vma.collect_dirty(range, &mut self.pagetable, &mut self.unsynced);
             }
         }
-        error!("Cannot find lazy entry for {:?}", vpn);
-        Err(ErrorNum::ENOSEG)
+        unsafe { asm!("sfence.vma"); }
+        Ok(())
+    }
+
+    /// Shared mapping pages written before they were msynced or unmapped. Sync them with sync_dirty after
+    /// dropping the pcb lock, that runs filesystem transactions.
+    pub fn take_unsynced(&mut self) -> DirtyPages {
+        core::mem::take(&mut self.unsynced)
+    }
+
+    /// Drop shared mappings of file bytes [from, to) of `inode`, see VMASegment::unmap_file.
+    pub fn unmap_file(&mut self, inode: (UUID, u32), from: usize, to: usize, holes_only: bool) {
+        for seg in self.segments.iter() {
+            if let Ok(vma) = seg.clone().as_vma() {
+                vma.unmap_file(inode, from, to, holes_only, &mut self.pagetable);
+            }
+        }
+        unsafe { asm!("sfence.vma"); }
     }
 
     pub fn unmap_vma(&mut self, head: VirtAddr, length: usize) -> Result<(), ErrorNum> {
         let seg = self.get_segment(head.into())?.as_vma()?;
-        seg.unmap_part(head, length, &mut self.pagetable)?;
+        seg.unmap_part(head, length, &mut self.pagetable, &mut self.unsynced)?;
+        let range = VPNRange::new(head.into(), (head + length).to_vpn_ceil());
+        self.locked.retain(|vpn| !range.contains(*vpn));
         if seg.is_empty() {
             self.remove_segment(ArcSegment(seg.as_segment()))?;
         }
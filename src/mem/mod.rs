@@ -5,11 +5,22 @@ mod types;
 mod pagetable;
 mod mem_layout;
 mod segment;
+mod user_buffer;
+mod ksm;
+mod ktests;
 
 pub use phys_bitmap::BitMap;
 
+pub use user_buffer::{UserBuffer, copy_from_user, copy_to_user, bytes_of, read_user_cstr, read_user_str, read_user, write_user};
+
+pub use ksm::{ksm_get_page, ksm_invalidate};
+
 pub use mem_layout::{
-    MemLayout
+    MemLayout,
+    FaultStats,
+    ElfInfo,
+    RANDOMIZE_VA_SPACE,
+    aslr_offset
 };
 
 pub use kernel_heap::{init_kernel_heap};
@@ -27,23 +38,38 @@ pub use types::{
 
 pub use page_allocator::{
     alloc_vm_page,
+    try_alloc_vm_page,
     alloc_fs_page,
     free_fs_page,
+    fs_pages_in_use,
+    mark_fs_page,
+    unmark_fs_page,
     claim_vm_page,
     claim_fs_page,
     stat_mem,
-    PageGuard
+    flush_page_magazine,
+    zero_free_pages,
+    zero_daemon,
+    release_boot_reserved,
+    page_owners,
+    PageGuard,
+    PageOwner
 };
 
 pub use segment::{
     MMAPType,
+    MemUsage,
+    FaultKind,
     Segment,
     ArcSegment,
     IdenticalMappingSegment,
     ManagedSegment,
     VMASegment,
+    DirtyPages,
+    sync_dirty,
     TrampolineSegment,
     UTrampolineSegment,
+    VdsoSegment,
     TrapContextSegment,
     ProcKStackSegment,
     SegmentFlags
@@ -55,9 +81,39 @@ pub use pagetable::{
     PTEFlags
 };
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use crate::{process::get_processor};
 
+const BSS_DIRTY     : usize = 0;
+const BSS_CLEARING  : usize = 1;
+const BSS_CLEAN     : usize = 2;
+
+/// Guards .bss so it can't live there.
+#[link_section = ".data"]
+static BSS_STATE: AtomicUsize = AtomicUsize::new(BSS_DIRTY);
+
+/// Zero .bss, except the boot stacks in .bss.stack below sbss that we're running on. QEMU's ELF loader does it
+/// for us, firmware loading a flat image doesn't. Every hart calls it first thing in genesis, before any static
+/// in .bss is touched: first one in clears, the rest wait for it.
+pub fn clear_bss() {
+    extern "C" {
+        fn sbss();
+        fn ebss();
+    }
+    if BSS_STATE.compare_exchange(BSS_DIRTY, BSS_CLEARING, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+        unsafe {
+            core::ptr::write_bytes(sbss as usize as *mut u8, 0, ebss as usize - sbss as usize);
+        }
+        BSS_STATE.store(BSS_CLEAN, Ordering::Release);
+    } else {
+        while BSS_STATE.load(Ordering::Acquire) != BSS_CLEAN {}
+    }
+}
+
 pub fn init() {
+    // heap is in .bss, clearing it after this would take every allocation with it
+    assert!(BSS_STATE.load(Ordering::Acquire) == BSS_CLEAN, ".bss not cleared before heap init");
     init_kernel_heap();
     verbose!("Kernel heap activated");
     extern "C" {
@@ -66,13 +122,6 @@ pub fn init() {
     }
     info!("SBSS: {:x}", sbss as usize);
     info!("EBSS: {:x}", ebss as usize);
-
-    // unsafe{ 
-    //     let clear_start = sbss as usize as *mut u8;
-    //     let length = ebss as usize - sbss as usize;
-    //     core::ptr::write_bytes(clear_start, 0, length); 
-    // }
-    
     milestone!("Memory initialized.");
 }
 
@@ -1,11 +1,13 @@
-use crate::{utils::{Mutex, SpinMutex}, config::PAGE_SIZE};
-use alloc::sync::Arc;
+use crate::{fs::parch_fs_present, device::{initrd_range, crash_region}, interrupt::sbi::sbi_boot, utils::{Mutex, SpinMutex, ErrorNum}, config::{PAGE_SIZE, MAX_CPUS, PAGE_MAGAZINE_SIZE, PAGE_MAGAZINE_BATCH, ZEROED_POOL_SIZE, ZEROED_POOL_BATCH, ZEROED_POOL_INTERVAL_MS}, process::{Alarm, get_hart_id, push_intr_off, pop_intr_off, present_harts, has_ready}};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
 use lazy_static::*;
-use super::{PhysAddr, phys_bitmap::BitMap, types::PhysPageNum};
-use core::fmt::Debug;
+use super::{PhysAddr, phys_bitmap::BitMap, types::{PhysPageNum, PPNRange}};
+use core::fmt::{Debug, Write};
 use core::ops::Deref;
+use core::panic::Location;
 
 extern "C" {
+	fn skernel();
 	fn ekernel();
 	fn BASE_ADDRESS();
 	fn PHYS_END_ADDRESS ();
@@ -27,6 +29,47 @@ lazy_static!{
 			)
 		)
 	};
+
+	/// Per hart free vm pages, still marked used in bitmap_mm. Saves the global lock on most alloc / free.
+	static ref PAGE_MAGAZINES: Vec<SpinMutex<Vec<PhysPageNum>>> = (0..MAX_CPUS).map(|_| SpinMutex::new("PageMagazine", Vec::new())).collect();
+
+	/// Free vm pages already zeroed by the zeropage kernel thread, still marked used in bitmap_mm. try_alloc_vm_page
+	/// takes from here first.
+	static ref ZEROED_POOL: SpinMutex<Vec<PhysPageNum>> = SpinMutex::new("ZeroedPool", Vec::new());
+	static ref ZEROPAGE_ALARM: Arc<Alarm> = Alarm::new("zeropage");
+
+	/// Live allocated PageGuards, debug builds only. Claimed pages aren't ours to leak so they're not here.
+	static ref PAGE_OWNERS: SpinMutex<BTreeMap<PhysPageNum, PageOwnerRecord>> = SpinMutex::new("PageOwners", BTreeMap::new());
+}
+
+/// Who holds a page, tagged by the holder after allocation.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PageOwner {
+	/// not tagged
+	Unknown,
+	/// PageTable by its root ppn
+	PageTable(usize),
+	/// Segment by its type
+	Segment(&'static str),
+	/// page cache or tmpfs data of the inode
+	Inode(u32),
+}
+
+impl Debug for PageOwner {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::Unknown => write!(f, "unknown"),
+			Self::PageTable(root) => write!(f, "pagetable 0x{:x}", root),
+			Self::Segment(kind) => write!(f, "{} segment", kind),
+			Self::Inode(inode) => write!(f, "inode {}", inode),
+		}
+	}
+}
+
+struct PageOwnerRecord {
+	owner: PageOwner,
+	/// caller of alloc_vm_page / try_alloc_vm_page
+	site: &'static Location<'static>,
 }
 
 trait PageAllocator {
@@ -38,7 +81,7 @@ trait PageAllocator {
 }
 
 #[derive(Clone)]
-pub struct PageGuard(Arc<PageGuardInner>);
+pub struct PageGuard(pub(super) Arc<PageGuardInner>);
 
 impl Deref for PageGuard {
     type Target = Arc<PageGuardInner>;
@@ -58,6 +101,16 @@ impl PageGuard {
 	pub fn new(inner: PageGuardInner) -> Self {
 		Self(Arc::new(inner))
 	}
+
+	/// Tag the page for /proc/pageowners. No-op outside debug builds.
+	pub fn with_owner(self, owner: PageOwner) -> Self {
+		if cfg!(debug_assertions) {
+			if let Some(record) = PAGE_OWNERS.acquire().get_mut(&self.ppn) {
+				record.owner = owner;
+			}
+		}
+		self
+	}
 }
 
 pub struct PageGuardInner {
@@ -75,16 +128,125 @@ impl PageGuardInner {
 impl Drop for PageGuardInner {
 	fn drop(&mut self) {
 		if self.do_free {
-			PAGE_ALLOCATOR.acquire().free(self.ppn, self.is_exec);
+			if cfg!(debug_assertions) {
+				PAGE_OWNERS.acquire().remove(&self.ppn);
+			}
+			if self.is_exec {
+				// free poisons the rest, but magazine pages don't get there until drained
+				if cfg!(debug_assertions) {
+					unsafe{self.ppn.poison_content();}
+				}
+				magazine_push(self.ppn);
+			} else {
+				PAGE_ALLOCATOR.acquire().free(self.ppn, self.is_exec);
+			}
+		}
+	}
+}
+
+/// Take a page from this hart's magazine, refill a batch from bitmap if empty.
+fn magazine_pop() -> Option<PhysPageNum> {
+	push_intr_off();
+	let mut magazine = PAGE_MAGAZINES[get_hart_id()].acquire();
+	if magazine.is_empty() {
+		let mut allocator = PAGE_ALLOCATOR.acquire();
+		for _ in 0..PAGE_MAGAZINE_BATCH {
+			match allocator.alloc(true) {
+				Some(ppn) => magazine.push(ppn),
+				None => break
+			}
+		}
+	}
+	let res = magazine.pop();
+	drop(magazine);
+	pop_intr_off();
+	res
+}
+
+/// Put a freed page in this hart's magazine, drain a batch to bitmap if full.
+fn magazine_push(ppn: PhysPageNum) {
+	push_intr_off();
+	let mut magazine = PAGE_MAGAZINES[get_hart_id()].acquire();
+	magazine.push(ppn);
+	if magazine.len() > PAGE_MAGAZINE_SIZE {
+		let mut allocator = PAGE_ALLOCATOR.acquire();
+		for _ in 0..PAGE_MAGAZINE_BATCH {
+			allocator.free(magazine.pop().unwrap(), true);
 		}
 	}
+	drop(magazine);
+	pop_intr_off();
+}
+
+/// Give cached pages of this hart back to bitmap, called when hart goes idle.
+pub fn flush_page_magazine() {
+	push_intr_off();
+	drain_magazine(get_hart_id());
+	pop_intr_off();
+}
+
+fn drain_magazine(hart_id: usize) {
+	let mut magazine = PAGE_MAGAZINES[hart_id].acquire();
+	if magazine.is_empty() {
+		return;
+	}
+	let mut allocator = PAGE_ALLOCATOR.acquire();
+	for ppn in magazine.drain(..) {
+		allocator.free(ppn, true);
+	}
+}
+
+/// Zero a batch of free pages into the zeroed pool. False if there's nothing to do, the pool being full or memory
+/// used up.
+pub fn zero_free_pages() -> bool {
+	let want = ZEROED_POOL_SIZE.saturating_sub(ZEROED_POOL.acquire().len()).min(ZEROED_POOL_BATCH);
+	if want == 0 {
+		return false;
+	}
+	let mut pages = Vec::with_capacity(want);
+	let mut allocator = PAGE_ALLOCATOR.acquire();
+	while pages.len() < want {
+		match allocator.alloc(true) {
+			Some(ppn) => pages.push(ppn),
+			None => break
+		}
+	}
+	drop(allocator);
+	if pages.is_empty() {
+		return false;
+	}
+	// no lock held while zeroing
+	for ppn in pages.iter() {
+		unsafe{ppn.clear_content();}
+	}
+	ZEROED_POOL.acquire().extend(pages);
+	true
+}
+
+/// Body of the zeropage kernel thread. Low priority: it tops the pool up only while no one else is ready to run,
+/// and otherwise waits for the next round.
+pub fn zero_daemon() -> ! {
+	loop {
+		ZEROPAGE_ALARM.wait(ZEROED_POOL_INTERVAL_MS);
+		while !has_ready() && zero_free_pages() {}
+	}
+}
+
+fn drain_zeroed_pool() {
+	let pages = core::mem::take(&mut *ZEROED_POOL.acquire());
+	let mut allocator = PAGE_ALLOCATOR.acquire();
+	for ppn in pages {
+		allocator.free(ppn, true);
+	}
 }
 
 /// bitmap_fs is for all allocated page, either for exec or file
 /// bitmap_mm is for exec memory, and overlaps with bitmap_fs
 pub struct BitMapPageAllocator {
 	bitmap_mm: BitMap,
-	bitmap_fs: BitMap
+	bitmap_fs: BitMap,
+	/// free pages taken at boot for data loaded by the firmware (initrd), see release_boot_reserved
+	boot_reserved: Vec<PhysPageNum>
 }
 
 impl BitMapPageAllocator {
@@ -103,15 +265,80 @@ impl BitMapPageAllocator {
 		}
 		self.bitmap_mm.clear(index);
 	}
+
+	/// No ParchFS image in memory, so the bitmaps in reserved area are garbage. Start over with
+	/// only the kernel image and the reserved area itself in use.
+	fn format(&mut self) {
+		warning!("No ParchFS image, formatting page bitmaps.");
+		self.bitmap_fs.clear_all();
+		self.bitmap_mm.clear_all();
+		let kernel = PPNRange::new(PhysAddr::from(BASE_ADDRESS as usize).into(), PhysAddr::from(ekernel as usize).to_ppn_ceil());
+		let reserve = PPNRange::new(PhysAddr::from(INODE_LIST_ADDRESS as usize).into(), PhysAddr::from(PHYS_END_ADDRESS as usize).into());
+		for ppn in kernel.into_iter().chain(reserve.into_iter()) {
+			self.mark_unavailable(ppn, false);
+		}
+	}
+
+	/// Take the free pages in [start, end) before anyone can alloc them.
+	fn reserve(&mut self, start: PhysAddr, end: PhysAddr) {
+		for ppn in PPNRange::new(start.into(), end.to_ppn_ceil()) {
+			let index = ppn - PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize));
+			if self.bitmap_mm.get(index) {
+				warning!("Boot reserved page {:?} already in use, content may be clobbered.", ppn);
+				continue;
+			}
+			self.mark_unavailable(ppn, true);
+			self.boot_reserved.push(ppn);
+		}
+	}
+}
+
+impl BitMapPageAllocator {
+	/// Take [start, end) for good, for the crash dump. False if it's outside RAM or ParchFS has pages there.
+	fn keep(&mut self, start: PhysAddr, end: PhysAddr) -> bool {
+		if !crate::utils::crash_dump::region_usable(start, end) {
+			warning!("Crash dump region {:?} - {:?} unusable.", start, end);
+			return false;
+		}
+		let range = PPNRange::new(start.into(), end.to_ppn_ceil());
+		let base = PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize));
+		if range.into_iter().any(|ppn| self.bitmap_fs.get(ppn - base)) {
+			warning!("Crash dump region {:?} - {:?} overlaps ParchFS, not used.", start, end);
+			return false;
+		}
+		for ppn in range {
+			self.mark_unavailable(ppn, true);
+		}
+		true
+	}
 }
 
 impl PageAllocator for BitMapPageAllocator {
     fn new(_begin: PhysAddr, _length: usize) -> Self {
 		verbose!("Initializeing BitMapPageAllocator");
-        let res = Self {
+        let mut res = Self {
 			bitmap_mm: BitMap::new((PAGE_BITMAP_MM_ADDRESS as usize).into(), (PAGE_BITMAP_FS_ADDRESS as usize - PAGE_BITMAP_MM_ADDRESS as usize) * 8),
-			bitmap_fs: BitMap::new((PAGE_BITMAP_FS_ADDRESS as usize).into(), (SUPERBLOCK_ADDRESS as usize - PAGE_BITMAP_FS_ADDRESS as usize) * 8)
+			bitmap_fs: BitMap::new((PAGE_BITMAP_FS_ADDRESS as usize).into(), (SUPERBLOCK_ADDRESS as usize - PAGE_BITMAP_FS_ADDRESS as usize) * 8),
+			boot_reserved: Vec::new()
 		};
+		if !parch_fs_present() {
+			res.format();
+		}
+		if sbi_boot() {
+			// firmware lives below the kernel image
+			let firmware = PPNRange::new(PhysAddr::from(BASE_ADDRESS as usize).into(), PhysAddr::from(skernel as usize).into());
+			for ppn in firmware {
+				res.mark_unavailable(ppn, true);
+			}
+		}
+		if let Some((start, end)) = initrd_range() {
+			res.reserve(start, end);
+		}
+		if let Some((start, end)) = crash_region() {
+			if res.keep(start, end) {
+				crate::utils::crash_dump::set_region(start, end);
+			}
+		}
 
 		// parchfs did this for us on formating
 		// // mark unavailable
@@ -147,7 +374,7 @@ impl PageAllocator for BitMapPageAllocator {
 			assert!(self.bitmap_fs.get(block_id), "Freeing exec page");
 		}
 		if cfg!(debug_assertions) {
-			unsafe{to_free.clear_content();}
+			unsafe{to_free.poison_content();}
 		}
         self.mark_available(to_free, is_exec);
     }
@@ -167,17 +394,47 @@ impl PageAllocator for BitMapPageAllocator {
 	}
 }
 
+/// For kernel's own structures, which can't recover from OOM.
+#[track_caller]
 pub fn alloc_vm_page() -> PageGuard {
-	let ppn = PAGE_ALLOCATOR.acquire().alloc(true).unwrap();
-	if cfg!(debug_assertions) {
+	try_alloc_vm_page().expect("Out of memory on kernel allocation")
+}
+
+/// For user memory, caller fail with ENOMEM and let OOM killer make room.
+/// Always zeroed, from the zeroed pool if it has any.
+#[track_caller]
+pub fn try_alloc_vm_page() -> Result<PageGuard, ErrorNum> {
+	let pooled = ZEROED_POOL.acquire().pop();
+	let ppn = match pooled.or_else(magazine_pop) {
+		Some(ppn) => ppn,
+		None => {
+			// pages may be sitting in other harts' magazines
+			for hart_id in present_harts() {
+				drain_magazine(hart_id);
+			}
+			magazine_pop().ok_or(ErrorNum::ENOMEM)?
+		}
+	};
+	if pooled.is_none() {
 		unsafe{ppn.clear_content();}
 	}
-	PageGuard::new(PageGuardInner::new(ppn, true, true))
+	if cfg!(debug_assertions) {
+		PAGE_OWNERS.acquire().insert(ppn, PageOwnerRecord{owner: PageOwner::Unknown, site: Location::caller()});
+	}
+	Ok(PageGuard::new(PageGuardInner::new(ppn, true, true)))
 }
 
 /// fs pages persist across boots, so RAII won't work for them, must explicit free
 pub fn alloc_fs_page() -> PhysPageNum {
-	let ppn = PAGE_ALLOCATOR.acquire().alloc(false).unwrap();
+	let ppn = PAGE_ALLOCATOR.acquire().alloc(false);
+	let ppn = ppn.unwrap_or_else(|| {
+		// the pool and magazines hold free pages too
+		drain_zeroed_pool();
+		for hart_id in present_harts() {
+			drain_magazine(hart_id);
+		}
+		PAGE_ALLOCATOR.acquire().alloc(false).unwrap()
+	});
 	if cfg!(debug_assertions) {
 		unsafe{ppn.clear_content();}
 	}
@@ -198,6 +455,66 @@ pub fn claim_fs_page(to_claim: PhysPageNum) -> PageGuard {
 	PageGuard::new(PageGuardInner::new(to_claim, false, false))
 }
 
+/// Pages marked in the fs bitmap, for fsck. Includes the kernel image and the reserved area, which have no owner inode.
+pub fn fs_pages_in_use() -> Vec<PhysPageNum> {
+	let allocator = PAGE_ALLOCATOR.acquire();
+	let base = PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize));
+	let end = PhysPageNum::from(PhysAddr::from(PHYS_END_ADDRESS as usize));
+	(0..end - base).filter(|&index| allocator.bitmap_fs.get(index)).map(|index| base + index).collect()
+}
+
+/// fsck repair: mark a page referenced by an inode as fs page. Fails if it was handed out as memory meanwhile.
+pub fn mark_fs_page(ppn: PhysPageNum) -> bool {
+	let mut allocator = PAGE_ALLOCATOR.acquire();
+	let index = ppn - PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize));
+	if allocator.bitmap_mm.get(index) && !allocator.bitmap_fs.get(index) {
+		return false;
+	}
+	allocator.mark_unavailable(ppn, false);
+	true
+}
+
+/// Journal undo: release a page that was allocated as fs page before the crash. No-op if it's not marked.
+pub fn unmark_fs_page(ppn: PhysPageNum) {
+	let mut allocator = PAGE_ALLOCATOR.acquire();
+	let index = ppn - PhysPageNum::from(PhysAddr::from(BASE_ADDRESS as usize));
+	if allocator.bitmap_fs.get(index) {
+		allocator.mark_available(ppn, false);
+	}
+}
+
+/// Give back pages reserved at boot, once their content was consumed.
+pub fn release_boot_reserved() {
+	let mut allocator = PAGE_ALLOCATOR.acquire();
+	let reserved = core::mem::take(&mut allocator.boot_reserved);
+	debug!("Releasing {} boot reserved pages.", reserved.len());
+	for ppn in reserved {
+		allocator.free(ppn, true);
+	}
+}
+
+/// Live PageGuards counted by owner and allocation site, biggest first. A site whose count keeps growing
+/// with its owner gone is a leak.
+pub fn page_owners() -> String {
+	if !cfg!(debug_assertions) {
+		return String::from("page owner tracking is in debug builds only\n");
+	}
+	let mut count: BTreeMap<(PageOwner, &'static str, u32), usize> = BTreeMap::new();
+	for record in PAGE_OWNERS.acquire().values() {
+		*count.entry((record.owner, record.site.file(), record.site.line())).or_insert(0) += 1;
+	}
+	let mut count: Vec<_> = count.into_iter().collect();
+	count.sort_by(|a, b| b.1.cmp(&a.1));
+	let mut res = String::new();
+	for ((owner, file, line), pages) in count {
+		writeln!(res, "{:>8} {:<24} {}:{}", pages, format!("{:?}", owner), file, line).unwrap();
+	}
+	res
+}
+
+/// (fs usage, mm usage) in bytes. Pages cached in magazines or the zeroed pool are free, so not counted.
 pub fn stat_mem() -> (usize, usize) {
-	PAGE_ALLOCATOR.acquire().stat()
+	let cached: usize = PAGE_MAGAZINES.iter().map(|m| m.acquire().len()).sum::<usize>() + ZEROED_POOL.acquire().len();
+	let (fs_usage, mm_usage) = PAGE_ALLOCATOR.acquire().stat();
+	(fs_usage, mm_usage.saturating_sub(cached * PAGE_SIZE))
 }
\ No newline at end of file
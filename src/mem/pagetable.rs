@@ -7,7 +7,7 @@ use core::fmt::{self, Debug, Formatter};
 
 use crate::{utils::{LogLevel, ErrorNum}, config::{PAGE_SIZE, PHYS_END_ADDR}, process::ProcessID, mem::{VirtAddr, VPNRange}};
 
-use super::{PageGuard, PhysAddr, alloc_vm_page, types::{PhysPageNum, VirtPageNum}};
+use super::{PageGuard, PageOwner, PhysAddr, alloc_vm_page, types::{PhysPageNum, VirtPageNum}};
 
 use lazy_static::*;
 
@@ -188,6 +188,8 @@ pub struct PageTable {
 impl PageTable {
     pub fn new_empty() -> Self {
         let root = alloc_vm_page();
+        let root_ppn = root.ppn;
+        let root = root.with_owner(PageOwner::PageTable(root_ppn.0));
         unsafe{root.ppn.clear_content();}
         Self {
             root_ppn: root.ppn,
@@ -259,7 +261,7 @@ impl PageTable {
             let mut pte_content = unsafe{pte_addr.read_volatile::<PageTableEntry>()};
             if !pte_content.valid() {
                 if do_create {
-                    let pg = alloc_vm_page();
+                    let pg = alloc_vm_page().with_owner(PageOwner::PageTable(self.root_ppn.0));
                     pte_content.bits = 0;
                     pte_content.set_ppn(pg.ppn);
                     pte_content.set_flags(PTEFlags::V);   // not leaf
@@ -288,7 +290,7 @@ impl PageTable {
             }
             let mut pte_content = unsafe{pte_addr.read_volatile::<PageTableEntry>()};
             if !pte_content.valid() {
-                let pg = alloc_vm_page();
+                let pg = alloc_vm_page().with_owner(PageOwner::PageTable(self.root_ppn.0));
                 pte_content.bits = 0;
                 pte_content.set_ppn(pg.ppn);
                 pte_content.set_flags(PTEFlags::V);   // not leaf
@@ -367,6 +369,21 @@ impl PageTable {
         }
     }
 
+    /// Clear the dirty bit of a mapped page, returns whether it was set. Caller flushes the TLB.
+    pub fn clear_dirty(&mut self, vpn: VirtPageNum) -> bool {
+        let pte_addr = match self.walk_find(vpn) {
+            Some(pte_addr) => pte_addr,
+            None => return false,
+        };
+        let mut pte: PageTableEntry = unsafe{pte_addr.read_volatile()};
+        if !pte.valid() || !pte.dirty() {
+            return false;
+        }
+        pte.set_flags(pte.flags() - PTEFlags::D);
+        unsafe{pte_addr.write_volatile(&pte)};
+        true
+    }
+
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         if let Some(pte_addr) = self.walk_find(vpn) {
             unsafe{pte_addr.write_volatile(&PageTableEntry::empty())}
@@ -2,11 +2,13 @@ use core::fmt::{self, Debug, Formatter};
 use _core::any::Any;
 use alloc::{sync::{Arc}, collections::BTreeMap, vec::Vec, borrow::ToOwned};
 use bitflags::*;
-use crate::{config::{PAGE_SIZE, PROC_K_STACK_SIZE, PROC_K_STACK_ADDR, PROC_U_STACK_SIZE, PROC_U_STACK_ADDR}, utils::{SpinMutex, Mutex}};
-use crate::{fs::{RegularFile}, utils::ErrorNum, config::{TRAMPOLINE_ADDR, U_TRAMPOLINE_ADDR, TRAP_CONTEXT_ADDR}};
+use crate::{config::{PAGE_SIZE, PROC_K_STACK_SIZE, PROC_K_STACK_ADDR, PROC_U_STACK_SIZE, PROC_U_STACK_ADDR}, utils::{SpinMutex, Mutex, stack_guard::set_canary}};
+use crate::{fs::{RegularFile}, utils::{ErrorNum, KernelError, UUID}, config::{TRAMPOLINE_ADDR, U_TRAMPOLINE_ADDR, VDSO_DATA_ADDR, TRAP_CONTEXT_ADDR}, utils::vdso::vdso_page_ppn};
 
 use super::{VirtAddr, PageTableEntry};
-use super::{types::{VPNRange, VirtPageNum, PhysPageNum}, PageGuard, pagetable::{PageTable, PTEFlags}, alloc_vm_page, PhysAddr};
+use lazy_static::*;
+
+use super::{types::{VPNRange, VirtPageNum, PhysPageNum}, PageGuard, PageOwner, pagetable::{PageTable, PTEFlags}, alloc_vm_page, try_alloc_vm_page, ksm_get_page, PhysAddr};
 
 bitflags! {
     /// Segment flags indicaing privilege.
@@ -48,6 +50,7 @@ pub enum SegmentType {
     VMA,
     Trampoline,
     UTrampoline,
+    Vdso,
     TrapContext
 }
 
@@ -59,9 +62,85 @@ pub enum PageGuardSlot {
     CopyOnWrite(PageGuard),
     LazyVMAPrivate((Arc<dyn RegularFile>, usize)),    // file & offset // TODO: change this to Arc<dyn File>, for we might be able to mmap device file.
     LazyVMAShared((Arc<dyn RegularFile>, usize)),    // file & offset // TODO: change this to Arc<dyn File>, for we might be able to mmap device file.
+    /// hole of a shared mapping read so far, mapped read only to ZERO_PAGE. Writing it fills the hole in.
+    SharedHole((Arc<dyn RegularFile>, usize)),
+}
+
+lazy_static!{
+    /// What holes of shared file mappings read as until written, never freed.
+    static ref ZERO_PAGE: PageGuard = alloc_vm_page().with_owner(PageOwner::Segment("zero"));
+}
+
+/// What a successful do_lazy had to do, for the per process fault counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// fresh zeroed frame, or nothing to allocate at all
+    Minor,
+    /// copied, or took over, a frame shared after fork
+    Cow,
+    /// read in from a mapped file
+    File,
+}
+
+/// Per process memory usage. A frame held by n owners (COW after fork, mmap page cache) adds PAGE_SIZE / n to pss.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemUsage {
+    /// pages with a frame behind them
+    pub resident: usize,
+    /// resident pages also held by someone else
+    pub shared: usize,
+    /// proportional set size in bytes
+    pub pss: usize,
+}
+
+impl core::ops::Add for MemUsage {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            resident: self.resident + rhs.resident,
+            shared: self.shared + rhs.shared,
+            pss: self.pss + rhs.pss,
+        }
+    }
+}
+
+impl MemUsage {
+    /// Sum up the populated slots of a segment.
+    pub fn of_frames<'a, I: Iterator<Item = &'a PageGuardSlot>>(slots: I) -> Self {
+        let mut res = Self::default();
+        for slot in slots {
+            if let PageGuardSlot::Populated(pg) | PageGuardSlot::CopyOnWrite(pg) = slot {
+                let owners = Arc::strong_count(pg);
+                res.resident += 1;
+                if owners > 1 {
+                    res.shared += 1;
+                }
+                res.pss += PAGE_SIZE / owners;
+            }
+        }
+        res
+    }
 }
 
 impl PageGuardSlot {
+    /// Kind of fault that resolves this slot.
+    pub fn fault_kind(&self) -> FaultKind {
+        match self {
+            Self::CopyOnWrite(_) => FaultKind::Cow,
+            Self::LazyVMAPrivate(_) | Self::LazyVMAShared(_) | Self::SharedHole(_) => FaultKind::File,
+            _ => FaultKind::Minor,
+        }
+    }
+
+    /// Returns the frame if this slot holds one.
+    pub fn page(&self) -> Option<PageGuard> {
+        match self {
+            Self::Populated(pg) | Self::CopyOnWrite(pg) => Some(pg.clone()),
+            _ => None
+        }
+    }
+
     /// Returns `true` if the page guard slot is [`Unmapped`].
     ///
     /// [`Unmapped`]: PageGuardSlot::Unmapped
@@ -69,11 +148,9 @@ impl PageGuardSlot {
         matches!(self, Self::Unmapped)
     }
 
-    /// Returns `true` if the page guard slot is [`Lazy`].
-    ///
-    /// [`Lazy`]: PageGuardSlot::Lazy
+    /// Returns `true` if the slot has no frame yet but will get one on first touch, zeroed or from a file.
     pub fn is_lazy(&self) -> bool {
-        matches!(self, Self::LazyAlloc)
+        matches!(self, Self::LazyAlloc | Self::LazyVMAPrivate(_) | Self::LazyVMAShared(_))
     }
 }
 
@@ -90,7 +167,23 @@ pub trait Segment: Debug + Send + Sync {
     fn seg_type(&self) -> SegmentType;
     fn contains(&self, vpn: VirtPageNum) -> bool;
     fn clone_seg(self: Arc<Self>, pagetable: &mut PageTable) -> Result<ArcSegment, ErrorNum>;
-    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<(), ErrorNum>;
+    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<FaultKind, KernelError>;
+    /// Get the frame backing vpn, used to pin user pages. None if not populated or not managed by this segment.
+    fn get_page(&self, _vpn: VirtPageNum) -> Option<PageGuard> {
+        None
+    }
+    /// vpn has no frame yet and do_lazy would give it one, for MAP_POPULATE and mlock.
+    fn is_lazy(&self, _vpn: VirtPageNum) -> bool {
+        false
+    }
+    /// Bytes of user address space reserved by this segment, for RLIMIT_AS. 0 for kernel owned segments.
+    fn user_size(&self) -> usize {
+        0
+    }
+    /// Resident / shared / proportional usage of user pages in this segment.
+    fn mem_usage(&self) -> MemUsage {
+        MemUsage::default()
+    }
 }
 
 pub struct ArcSegment(pub Arc<dyn Segment>);
@@ -161,9 +254,21 @@ impl ArcSegment {
     pub fn clone_seg(&self, pagetable: &mut PageTable) -> Result<ArcSegment, ErrorNum>{
         self.0.clone().clone_seg(pagetable)
     }
-    pub fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    pub fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         self.0.do_lazy(vpn, pagetable)
     }
+    pub fn get_page(&self, vpn: VirtPageNum) -> Option<PageGuard> {
+        self.0.get_page(vpn)
+    }
+    pub fn is_lazy(&self, vpn: VirtPageNum) -> bool {
+        self.0.is_lazy(vpn)
+    }
+    pub fn user_size(&self) -> usize {
+        self.0.user_size()
+    }
+    pub fn mem_usage(&self) -> MemUsage {
+        self.0.mem_usage()
+    }
 }
 
 pub struct IdenticalMappingSegment (SpinMutex<IdenticalMappingSegmentInner>);
@@ -186,13 +291,37 @@ pub struct ManagedSegmentInner {
 pub struct VMASegment (SpinMutex<VMASegmentInner>);
 pub struct VMASegmentInner {
     frames: BTreeMap<VirtPageNum, PageGuardSlot>,
-    // file: Arc<dyn RegularFile>,
+    file: Arc<dyn RegularFile>,
     flag: SegmentFlags,
     status: SegmentStatus,
     start_vpn: VirtPageNum,
     mmap_type: MMAPType,
-    // file_offset: usize,  /* file_offset in page */
-    // length: usize,  /* length in page */
+    /// of start_vpn, in bytes
+    file_offset: usize,
+    /// in bytes
+    length: usize,
+    /// dirty shared pages found by do_unmap, for the caller to sync once its locks are dropped
+    unsynced: DirtyPages,
+    /// (fs uuid, inode) of file, for unmap_file
+    inode: (UUID, u32),
+}
+
+/// Parts of files written through shared mappings, (file, offset, length). Collected with the pcb and segment
+/// locks held and synced by `sync_dirty` after they are dropped, as syncing runs a filesystem transaction.
+pub type DirtyPages = Vec<(Arc<dyn RegularFile>, usize, usize)>;
+
+/// Tell the files about pages collected from shared mappings. Must not hold a spin lock.
+/// Every entry is tried, one failing doesn't lose the rest, and the first error is returned.
+pub fn sync_dirty(dirty: DirtyPages) -> Result<(), ErrorNum> {
+    let mut res = Ok(());
+    for (file, offset, len) in dirty {
+        if let Err(e) = file.sync_page(offset, len) {
+            if res.is_ok() {
+                res = Err(e);
+            }
+        }
+    }
+    res
 }
 
 pub struct TrampolineSegment (SpinMutex<TrampolineSegmentInner>);
@@ -205,6 +334,12 @@ pub struct UTrampolineSegmentInner {
     status: SegmentStatus
 }
 
+/// utils::vdso time page, read only for user.
+pub struct VdsoSegment (SpinMutex<VdsoSegmentInner>);
+pub struct VdsoSegmentInner {
+    status: SegmentStatus
+}
+
 pub struct TrapContextSegment (pub SpinMutex<TrapContextSegmentInner>);
 pub struct TrapContextSegmentInner {
     pub status: SegmentStatus,
@@ -230,6 +365,8 @@ pub struct ProgramSegmentInner {
     status: SegmentStatus,
     start_vpn: VirtPageNum,
     mem_length: usize,
+    /// Unmapped slots in frames, kept so user_size needn't walk them
+    unmapped: usize,
 }
 
 impl Debug for IdenticalMappingSegment {
@@ -342,14 +479,14 @@ impl Segment for IdenticalMappingSegment {
         Ok(Self::new(inner.range, inner.flag))
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         let inner = self.0.acquire();
         if inner.range.contains(vpn) {
             let ppn = PhysPageNum(vpn.0);
             pagetable.map(vpn, ppn, inner.flag.into());
-            Ok(())
+            Ok(FaultKind::Minor)
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
 }
@@ -403,7 +540,7 @@ impl Segment for ManagedSegment {
     }
 
     fn contains(&self, vpn: VirtPageNum) -> bool {
-        self.0.acquire().frames.keys().any(|&x| x == vpn)
+        self.0.acquire().frames.contains_key(&vpn)
     }
 
     fn clone_seg(self: Arc<Self>, pagetable: &mut PageTable) -> Result<ArcSegment, ErrorNum> {
@@ -419,7 +556,8 @@ impl Segment for ManagedSegment {
                 },
                 PageGuardSlot::CopyOnWrite(content) => PageGuardSlot::CopyOnWrite(content.clone()),
                 PageGuardSlot::LazyVMAPrivate(_) |
-                PageGuardSlot::LazyVMAShared(_)
+                PageGuardSlot::LazyVMAShared(_) |
+                PageGuardSlot::SharedHole(_)
                     => panic!("no vma in managed."),
             };
             (*vpn, new_slot)
@@ -438,15 +576,16 @@ impl Segment for ManagedSegment {
         Ok(Arc::new(res).as_segment().into())
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         let mut inner = self.0.acquire();
 
         if inner.range.contains(vpn) {
             let pageslot = inner.frames.get(&vpn).cloned().unwrap();
+            let kind = pageslot.fault_kind();
             if let PageGuardSlot::CopyOnWrite(cow_source) = pageslot {
                 if !inner.flag.contains(SegmentFlags::W) {
                     // real pagefault
-                    return Err(ErrorNum::EPERM)
+                    return Err(ErrorNum::EPERM.ctx("write to read only page"))
                 }
 
                 // one here, one remain in frames
@@ -456,7 +595,7 @@ impl Segment for ManagedSegment {
                     cow_source
                 } else {
                     verbose!("COW triggered for managed.");
-                    let pageguard = alloc_vm_page();
+                    let pageguard = try_alloc_vm_page()?.with_owner(PageOwner::Segment("managed"));
                     unsafe {PhysPageNum::copy_page(&cow_source.ppn, &pageguard.ppn)}
                     inner.frames.insert(vpn, PageGuardSlot::Populated(pageguard.clone()));
                     pageguard
@@ -464,21 +603,38 @@ impl Segment for ManagedSegment {
                 pagetable.remap(vpn, tgt_page.ppn, inner.flag.into())
             } else if let PageGuardSlot::LazyAlloc = pageslot {
                 verbose!("Lazy alloc triggered.");
-                let pageguard = alloc_vm_page();
+                let pageguard = try_alloc_vm_page()?.with_owner(PageOwner::Segment("managed"));
                 let ppn = pageguard.ppn;
                 pagetable.map(vpn, ppn, inner.flag.into());
                 inner.frames.insert(vpn, PageGuardSlot::Populated(pageguard));
             } else if let PageGuardSlot::Populated(_) = pageslot {
                 verbose!("real pagefault.");
-                return Err(ErrorNum::EPERM);
+                return Err(ErrorNum::EPERM.ctx("page already mapped"));
             } else {
                 panic!("No VMA in managed segement.");
             }
-            Ok(())
+            Ok(kind)
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
+
+    fn get_page(&self, vpn: VirtPageNum) -> Option<PageGuard> {
+        self.0.acquire().frames.get(&vpn).and_then(|slot| slot.page())
+    }
+
+    fn is_lazy(&self, vpn: VirtPageNum) -> bool {
+        self.0.acquire().frames.get(&vpn).map_or(false, |slot| slot.is_lazy())
+    }
+
+    fn user_size(&self) -> usize {
+        let inner = self.0.acquire();
+        (inner.range.end() - inner.range.start()) * PAGE_SIZE
+    }
+
+    fn mem_usage(&self) -> MemUsage {
+        MemUsage::of_frames(self.0.acquire().frames.values())
+    }
 }
 
 impl Segment for VMASegment {
@@ -513,10 +669,15 @@ impl Segment for VMASegment {
             return Err(ErrorNum::ENOSEG);
         }
         assert!(inner.status == SegmentStatus::Mapped);
+        let range = VPNRange::new(inner.start_vpn, (VirtAddr::from(inner.start_vpn) + inner.length).to_vpn_ceil());
+        let mut unsynced = Vec::new();
+        Self::collect_dirty_locked(&inner, range, pagetable, &mut unsynced);
+        inner.unsynced.append(&mut unsynced);
         for (vpn, pg) in inner.frames.iter() {
             match pg {
                 PageGuardSlot::Populated(_) |
-                PageGuardSlot::CopyOnWrite(_) => pagetable.unmap(*vpn),
+                PageGuardSlot::CopyOnWrite(_) |
+                PageGuardSlot::SharedHole(_) => pagetable.unmap(*vpn),
                 _ => {/* nothing */}
             }
         }
@@ -534,14 +695,20 @@ impl Segment for VMASegment {
     }
 
     fn contains(&self, vpn: VirtPageNum) -> bool {
-        self.0.acquire().frames.keys().any(|&x| x == vpn)
+        self.0.acquire().frames.contains_key(&vpn)
     }
 
     fn clone_seg(self: Arc<Self>, pagetable: &mut PageTable) -> Result<ArcSegment, ErrorNum> {
         let mut inner = self.0.acquire();
-    
+        let shared = inner.mmap_type == MMAPType::Shared;
+
         let new_frames: BTreeMap<VirtPageNum, PageGuardSlot> = inner.frames.iter().map(|(vpn, slot)| -> (VirtPageNum, PageGuardSlot) {
             let new_slot = match slot {
+                // both sides keep using the file's own page, child faults it in again
+                PageGuardSlot::Populated(_) | PageGuardSlot::LazyVMAShared(_) | PageGuardSlot::SharedHole(_) if shared => {
+                    let offset = inner.file_offset + (*vpn - inner.start_vpn) * PAGE_SIZE;
+                    PageGuardSlot::LazyVMAShared((inner.file.clone(), offset))
+                },
                 PageGuardSlot::CopyOnWrite(content) => PageGuardSlot::CopyOnWrite(content.clone()),
                 PageGuardSlot::Populated(content) => {
                     pagetable.remap(*vpn, content.ppn, (inner.flag & SegmentFlags::W.complement()).into()); // disable write to trigger cow
@@ -555,35 +722,43 @@ impl Segment for VMASegment {
             (*vpn, new_slot)
         }).collect();
 
-        inner.frames = new_frames.clone();
+        if !shared {
+            inner.frames = new_frames.clone();
+        }
 
         let res = Self (SpinMutex::new("segment", VMASegmentInner {
             frames: new_frames,
+            file: inner.file.clone(),
             flag: inner.flag,
             status: SegmentStatus::Initialized,
             start_vpn: inner.start_vpn,
             mmap_type: inner.mmap_type,
+            file_offset: inner.file_offset,
+            length: inner.length,
+            unsynced: Vec::new(),
+            inode: inner.inode,
         }));
 
         Ok(Arc::new(res).as_segment().into())
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         let mut inner = self.0.acquire();
 
         if inner.frames.contains_key(&vpn) {
             let pageslot = inner.frames.get(&vpn).cloned().unwrap();
+            let kind = pageslot.fault_kind();
 
             match pageslot {
-                PageGuardSlot::Unmapped => return Err(ErrorNum::EPERM), // was unmapped
+                PageGuardSlot::Unmapped => return Err(ErrorNum::EPERM.ctx("page was unmapped")),
                 PageGuardSlot::LazyAlloc => {
                     panic!("bad type, no lazy alloc on vma")
                 },
-                PageGuardSlot::Populated(_) => return Err(ErrorNum::EPERM), // real pagefault
+                PageGuardSlot::Populated(_) => return Err(ErrorNum::EPERM.ctx("page already mapped")),
                 PageGuardSlot::CopyOnWrite(content) => {
                     if !inner.flag.contains(SegmentFlags::W) {
                         // real pagefault
-                        return Err(ErrorNum::EPERM)
+                        return Err(ErrorNum::EPERM.ctx("write to read only page"))
                     }
     
                     debug_assert!(inner.flag.contains(SegmentFlags::R) && inner.flag.contains(SegmentFlags::W), "lazy bad seg");
@@ -597,7 +772,7 @@ impl Segment for VMASegment {
                         content
                     } else {
                         verbose!("COW triggered.");
-                        let pageguard = alloc_vm_page();
+                        let pageguard = try_alloc_vm_page()?.with_owner(PageOwner::Segment("vma"));
                         unsafe {PhysPageNum::copy_page(&content.ppn, &pageguard.ppn)}
                         inner.frames.insert(vpn, PageGuardSlot::Populated(pageguard.clone()));
                         pageguard
@@ -612,17 +787,48 @@ impl Segment for VMASegment {
                 },
                 PageGuardSlot::LazyVMAShared((file, offset)) => {
                     verbose!("lazy vma shared triggered");
-                    let pg = file.get_page(offset)?;
-                    verbose!("fs report actual content at {:?}", pg);
-                    pagetable.map(vpn, pg.ppn, inner.flag.into());
+                    if let Some(pg) = file.get_page(offset, false)? {
+                        verbose!("fs report actual content at {:?}", pg);
+                        pagetable.map(vpn, pg.ppn, inner.flag.into());
+                        inner.frames.insert(vpn, PageGuardSlot::Populated(pg));
+                    } else {
+                        // can't tell a read from a write here, a write faults again below
+                        pagetable.map(vpn, ZERO_PAGE.ppn, (inner.flag & SegmentFlags::W.complement()).into());
+                        inner.frames.insert(vpn, PageGuardSlot::SharedHole((file, offset)));
+                    }
+                },
+                PageGuardSlot::SharedHole((file, offset)) => {
+                    if !inner.flag.contains(SegmentFlags::W) {
+                        return Err(ErrorNum::EPERM.ctx("write to read only page"))
+                    }
+                    verbose!("hole of shared vma written");
+                    let pg = file.get_page(offset, true)?.unwrap();
+                    pagetable.remap(vpn, pg.ppn, inner.flag.into());
                     inner.frames.insert(vpn, PageGuardSlot::Populated(pg));
                 },
             }
-            Ok(())
+            Ok(kind)
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
+
+    fn get_page(&self, vpn: VirtPageNum) -> Option<PageGuard> {
+        self.0.acquire().frames.get(&vpn).and_then(|slot| slot.page())
+    }
+
+    fn is_lazy(&self, vpn: VirtPageNum) -> bool {
+        self.0.acquire().frames.get(&vpn).map_or(false, |slot| slot.is_lazy())
+    }
+
+    /// Slots dropped by unmap_part don't stay, so every one is reserved.
+    fn user_size(&self) -> usize {
+        self.0.acquire().frames.len() * PAGE_SIZE
+    }
+
+    fn mem_usage(&self) -> MemUsage {
+        MemUsage::of_frames(self.0.acquire().frames.values())
+    }
 }
 
 impl Segment for TrampolineSegment {
@@ -673,11 +879,11 @@ impl Segment for TrampolineSegment {
         Ok(Self::new())
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         if vpn == TRAMPOLINE_ADDR.into() {
-            Err(ErrorNum::EPERM)
+            Err(ErrorNum::EPERM.ctx("trampoline is never lazy"))
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
 }
@@ -730,11 +936,65 @@ impl Segment for UTrampolineSegment {
         Ok(Self::new())
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         if vpn == U_TRAMPOLINE_ADDR.into() {
-            Err(ErrorNum::EPERM)
+            Err(ErrorNum::EPERM.ctx("user trampoline is never lazy"))
+        } else {
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
+        }
+    }
+}
+
+
+impl Segment for VdsoSegment {
+    fn as_segment   <'a>(self: Arc<Self>) -> Arc<dyn Segment + 'a> where Self: 'a {
+        self
+    }
+
+    fn as_any<'a>(self: Arc<Self>) -> Arc<dyn Any + Send + Sync + 'static>
+    where Self: 'a {
+        self
+    }
+
+    fn do_map(&self, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+        let mut inner = self.0.acquire();
+        if inner.status != SegmentStatus::Initialized {
+            return Err(ErrorNum::EMMAPED);
+        }
+        pagetable.map(
+            VDSO_DATA_ADDR.into(),
+            vdso_page_ppn(),
+            PTEFlags::R | PTEFlags::U
+        );
+        inner.status = SegmentStatus::Mapped;
+        Ok(())
+    }
+
+    fn do_unmap(&self, _pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+        panic!("Don't unmap vdso!")
+    }
+
+    fn status(&self) -> SegmentStatus {
+        self.0.acquire().status
+    }
+
+    fn seg_type(&self) -> SegmentType {
+        SegmentType::Vdso
+    }
+
+    fn contains(&self, vpn: VirtPageNum) -> bool {
+        vpn == VDSO_DATA_ADDR.into()
+    }
+
+    fn clone_seg(self: Arc<Self>, _pagetable: &mut PageTable) -> Result<ArcSegment, ErrorNum> {
+        Ok(Self::new())
+    }
+
+    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
+        if vpn == VDSO_DATA_ADDR.into() {
+            Err(ErrorNum::EPERM.ctx("vdso data is never lazy"))
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
 }
@@ -762,7 +1022,7 @@ impl Segment for TrapContextSegment {
                 PTEFlags::R | PTEFlags::W
             );
         } else {
-            let pageguard = alloc_vm_page();
+            let pageguard = alloc_vm_page().with_owner(PageOwner::Segment("trap context"));
             let ppn = pageguard.ppn;
             pagetable.map(
                 TRAP_CONTEXT_ADDR.into(),
@@ -794,7 +1054,7 @@ impl Segment for TrapContextSegment {
     fn clone_seg(self: Arc<Self>, _pagetable: &mut PageTable) -> Result<ArcSegment, ErrorNum> {
         // Ok(Self::new(Some(self.clone())))
         let inner = self.0.acquire();
-        let new_page = alloc_vm_page();
+        let new_page = alloc_vm_page().with_owner(PageOwner::Segment("trap context"));
         unsafe{PhysPageNum::copy_page(&inner.page.as_ref().unwrap().ppn, &new_page.ppn)}
         let res = TrapContextSegmentInner{
             status: SegmentStatus::Initialized,
@@ -803,11 +1063,11 @@ impl Segment for TrapContextSegment {
         Ok(Arc::new(Self(SpinMutex::new("segment", res))).as_segment().into())
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         if vpn == TRAP_CONTEXT_ADDR.into() {
-            Err(ErrorNum::EPERM)
+            Err(ErrorNum::EPERM.ctx("trap context is never lazy"))
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
 }
@@ -831,8 +1091,10 @@ impl Segment for ProcKStackSegment {
         assert!(PROC_K_STACK_SIZE % PAGE_SIZE == 0, "Proc KStack size misaligned");
         let page_count = PROC_K_STACK_SIZE / PAGE_SIZE;
         let start_vpn: VirtPageNum = PROC_K_STACK_ADDR.into();
+        // stack grows down, canary goes on the page check_kernel_stack reads it from
+        let bottom_vpn = VirtPageNum::from(PROC_K_STACK_ADDR);
         for i in 0..page_count {
-            let pageguard = alloc_vm_page();
+            let pageguard = alloc_vm_page().with_owner(PageOwner::Segment("kstack"));
             let ppn = pageguard.ppn;
             let vpn = start_vpn + i;
             pagetable.map(
@@ -840,6 +1102,9 @@ impl Segment for ProcKStackSegment {
                 ppn, 
                 PTEFlags::R | PTEFlags::W
             );
+            if vpn == bottom_vpn {
+                unsafe {set_canary(PhysAddr::from(ppn).0 + PROC_K_STACK_ADDR.0 % PAGE_SIZE)};
+            }
             inner.pages.push(pageguard)
         }
         inner.status = SegmentStatus::Mapped;
@@ -874,11 +1139,11 @@ impl Segment for ProcKStackSegment {
         Ok(Self::new())
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, _pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         if VPNRange::new(PROC_K_STACK_ADDR.into(), (PROC_K_STACK_ADDR + PROC_K_STACK_SIZE).into()).contains(vpn) {
-            Err(ErrorNum::EPERM)
+            Err(ErrorNum::EPERM.ctx("kernel stack is never lazy"))
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
 }
@@ -957,14 +1222,15 @@ impl Segment for ProcUStackSegment {
         // Ok(Self::new(Some(self.clone())))
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         let mut inner = self.0.acquire();
         if  let Some(pageslot) = inner.frames.get(&vpn).cloned() {
-            match pageslot.clone() {
+            let kind = pageslot.fault_kind();
+            match pageslot {
                 PageGuardSlot::Unmapped => panic!("unmapped proc u stack"),
                 PageGuardSlot::LazyAlloc => {
                     verbose!("Lazy alloc triggered.");
-                    let pageguard = alloc_vm_page();
+                    let pageguard = try_alloc_vm_page()?.with_owner(PageOwner::Segment("ustack"));
                     let ppn = pageguard.ppn;
                     pagetable.map(vpn, ppn, PTEFlags::R | PTEFlags::W | PTEFlags::U);
                     inner.frames.insert(vpn, PageGuardSlot::Populated(pageguard));
@@ -972,7 +1238,7 @@ impl Segment for ProcUStackSegment {
                 PageGuardSlot::Populated(_) => return {
                     let pte: PageTableEntry = unsafe{pagetable.walk_find(vpn).unwrap().read_volatile()};
                     error!("Populated lazy triggered for Proc U stack. wut? flag {:?}", pte.flags());
-                    Err(ErrorNum::EPERM)
+                    Err(ErrorNum::EPERM.ctx("page already mapped"))
                 },
                 PageGuardSlot::CopyOnWrite(cow_source) => {
                     // debug_assert!(inner.flag.contains(SegmentFlags::R) && inner.flag.contains(SegmentFlags::W), "lazy bad seg");
@@ -986,20 +1252,37 @@ impl Segment for ProcUStackSegment {
                         cow_source
                     } else {
                         verbose!("COW triggered for u stack.");
-                        let pageguard = alloc_vm_page();
+                        let pageguard = try_alloc_vm_page()?.with_owner(PageOwner::Segment("ustack"));
                         unsafe {PhysPageNum::copy_page(&cow_source.ppn, &pageguard.ppn)}
                         inner.frames.insert(vpn, PageGuardSlot::Populated(pageguard.clone()));
                         pageguard
                     };
                     pagetable.do_map(vpn, tgt_page.ppn, PTEFlags::R | PTEFlags::W | PTEFlags::U);
                 },
-                PageGuardSlot::LazyVMAPrivate(_) | PageGuardSlot::LazyVMAShared(_) => panic!("lazy vma in proc u stack"),
+                PageGuardSlot::LazyVMAPrivate(_) | PageGuardSlot::LazyVMAShared(_) | PageGuardSlot::SharedHole(_) => panic!("lazy vma in proc u stack"),
             }
-            Ok(())
+            Ok(kind)
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
+
+    fn get_page(&self, vpn: VirtPageNum) -> Option<PageGuard> {
+        self.0.acquire().frames.get(&vpn).and_then(|slot| slot.page())
+    }
+
+    fn is_lazy(&self, vpn: VirtPageNum) -> bool {
+        self.0.acquire().frames.get(&vpn).map_or(false, |slot| slot.is_lazy())
+    }
+
+    /// Never unmapped in part.
+    fn user_size(&self) -> usize {
+        self.0.acquire().frames.len() * PAGE_SIZE
+    }
+
+    fn mem_usage(&self) -> MemUsage {
+        MemUsage::of_frames(self.0.acquire().frames.values())
+    }
 }
 
 
@@ -1043,6 +1326,7 @@ impl Segment for ProgramSegment {
             }
         }
         inner.frames.clear();
+        inner.unmapped = 0;
         inner.status = SegmentStatus::Zombie;
         Ok(())
     }
@@ -1056,7 +1340,7 @@ impl Segment for ProgramSegment {
     }
 
     fn contains(&self, vpn: VirtPageNum) -> bool {
-        self.0.acquire().frames.keys().any(|&x| x == vpn)
+        self.0.acquire().frames.contains_key(&vpn)
     }
 
     fn clone_seg(self: Arc<Self>, pagetable: &mut PageTable) -> Result<ArcSegment, ErrorNum> {
@@ -1084,31 +1368,33 @@ impl Segment for ProgramSegment {
             flag: inner.flag,
             status: SegmentStatus::Initialized,
             start_vpn: inner.start_vpn,
-            mem_length: inner.mem_length
+            mem_length: inner.mem_length,
+            unmapped: inner.unmapped,
         }));
 
         Ok(Arc::new(res).as_segment().into())
     }
 
-    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    fn do_lazy(&self, vpn: VirtPageNum, pagetable: &mut PageTable) -> Result<FaultKind, KernelError> {
         let mut inner = self.0.acquire();
 
         if inner.frames.contains_key(&vpn) {
             let pageslot = inner.frames.get(&vpn).cloned().unwrap();
+            let kind = pageslot.fault_kind();
 
             match pageslot {
-                PageGuardSlot::Unmapped => return Err(ErrorNum::EPERM), // was unmapped
+                PageGuardSlot::Unmapped => return Err(ErrorNum::EPERM.ctx("page was unmapped")),
                 PageGuardSlot::LazyAlloc => {
                     verbose!("lazy alloc triggered.");
-                    let pg = alloc_vm_page();
+                    let pg = try_alloc_vm_page()?.with_owner(PageOwner::Segment("program"));
                     inner.frames.insert(vpn, PageGuardSlot::Populated(pg.clone()));
                     pagetable.map(vpn, pg.ppn, inner.flag.into())
                 },
-                PageGuardSlot::Populated(_) => return Err(ErrorNum::EPERM), // real pagefault
+                PageGuardSlot::Populated(_) => return Err(ErrorNum::EPERM.ctx("page already mapped")),
                 PageGuardSlot::CopyOnWrite(content) => {
                     if !inner.flag.contains(SegmentFlags::W) {
                         // real pagefault
-                        return Err(ErrorNum::EPERM)
+                        return Err(ErrorNum::EPERM.ctx("write to read only page"))
                     }
     
                     debug_assert!(inner.flag.contains(SegmentFlags::R) && inner.flag.contains(SegmentFlags::W), "lazy bad seg");
@@ -1122,7 +1408,7 @@ impl Segment for ProgramSegment {
                         content
                     } else {
                         verbose!("COW triggered for program.");
-                        let pageguard = alloc_vm_page();
+                        let pageguard = try_alloc_vm_page()?.with_owner(PageOwner::Segment("program"));
                         unsafe {PhysPageNum::copy_page(&content.ppn, &pageguard.ppn)}
                         inner.frames.insert(vpn, PageGuardSlot::Populated(pageguard.clone()));
                         pageguard
@@ -1131,19 +1417,41 @@ impl Segment for ProgramSegment {
                 },
                 PageGuardSlot::LazyVMAPrivate((file, offset)) => {
                     verbose!("lazy vma triggered.");
-                    let pg = file.copy_page(offset)?;
+                    // text / rodata never change, share them with other processes running the same binary
+                    let pg = if inner.flag.contains(SegmentFlags::W) {
+                        file.copy_page(offset)?
+                    } else {
+                        ksm_get_page(&file, offset)?
+                    };
                     pagetable.map(vpn, pg.ppn, inner.flag.into());
                     inner.frames.insert(vpn, PageGuardSlot::Populated(pg));
                 },
-                PageGuardSlot::LazyVMAShared(_) => {
+                PageGuardSlot::LazyVMAShared(_) | PageGuardSlot::SharedHole(_) => {
                     panic!("program segment cannot be mapped as shared mmap.")
                 },
             }
-            Ok(())
+            Ok(kind)
         } else {
-            Err(ErrorNum::EOOR)
+            Err(ErrorNum::EOOR.ctx("not in this segment"))
         }
     }
+
+    fn get_page(&self, vpn: VirtPageNum) -> Option<PageGuard> {
+        self.0.acquire().frames.get(&vpn).and_then(|slot| slot.page())
+    }
+
+    fn is_lazy(&self, vpn: VirtPageNum) -> bool {
+        self.0.acquire().frames.get(&vpn).map_or(false, |slot| slot.is_lazy())
+    }
+
+    fn user_size(&self) -> usize {
+        let inner = self.0.acquire();
+        (inner.frames.len() - inner.unmapped) * PAGE_SIZE
+    }
+
+    fn mem_usage(&self) -> MemUsage {
+        MemUsage::of_frames(self.0.acquire().frames.values())
+    }
 }
 
 impl IdenticalMappingSegment {
@@ -1168,6 +1476,37 @@ impl ManagedSegment {
         }))).as_segment().into()
     }
 
+    pub fn start(&self) -> VirtPageNum {
+        self.0.acquire().range.start()
+    }
+
+    pub fn end(&self) -> VirtPageNum {
+        self.0.acquire().range.end()
+    }
+
+    /// Move the end to `byte_len` from the start, for the heap. Pages added are lazy, pages dropped are unmapped.
+    pub fn resize(&self, byte_len: usize, pagetable: &mut PageTable) {
+        let mut inner = self.0.acquire();
+        let start = inner.range.start();
+        let old_end = inner.range.end();
+        let new_end = (VirtAddr::from(start) + byte_len).to_vpn_ceil();
+        if new_end > old_end {
+            for vpn in VPNRange::new(old_end, new_end) {
+                inner.frames.insert(vpn, PageGuardSlot::LazyAlloc);
+            }
+        } else {
+            for vpn in VPNRange::new(new_end, old_end) {
+                match inner.frames.remove(&vpn) {
+                    Some(PageGuardSlot::Populated(_))   |
+                    Some(PageGuardSlot::CopyOnWrite(_)) => pagetable.unmap(vpn),
+                    _ => {/* do nothing since not mapped */},
+                }
+            }
+        }
+        inner.range = VPNRange::new(start, new_end);
+        inner.byte_len = byte_len;
+    }
+
     pub fn alter_permission(&self, flag: SegmentFlags, pagetable: &mut PageTable) -> SegmentFlags {
         let mut inner = self.0.acquire();
         assert!(inner.status == SegmentStatus::Mapped, "altering bad segment's flag");
@@ -1198,7 +1537,9 @@ impl ManagedSegment {
 impl VMASegment {
     /// file_offset and length are in bytes
     pub fn new_at(start_vpn: VirtPageNum, file: Arc<dyn RegularFile>, flag: SegmentFlags, file_offset: usize, length: usize, mmap_type: MMAPType) -> Result<ArcSegment, ErrorNum> {
-        let file_size = file.stat()?.file_size;
+        let stat = file.stat()?;
+        let file_size = stat.file_size;
+        let inode = (file.vfs().get_uuid(), stat.inode);
         let frames = VPNRange::new(
             start_vpn, 
             (VirtAddr::from(start_vpn) + length).to_vpn_ceil()
@@ -1206,7 +1547,8 @@ impl VMASegment {
             .into_iter()
             .map(|vpn| -> (VirtPageNum, PageGuardSlot) {
                 let offset_to_file = file_offset + (vpn - start_vpn) * PAGE_SIZE;
-                if offset_to_file >= file_size {
+                // shared pages past the end show up once the file grows, as they do for everyone else
+                if offset_to_file >= file_size && mmap_type == MMAPType::Private {
                     (vpn, PageGuardSlot::LazyAlloc)
                 } else {
                     match mmap_type {
@@ -1218,24 +1560,33 @@ impl VMASegment {
             .collect();
         let res = VMASegmentInner {
             frames,
+            file,
             flag,
             status: SegmentStatus::Initialized,
             start_vpn,
-            mmap_type
+            mmap_type,
+            file_offset,
+            length,
+            unsynced: Vec::new(),
+            inode,
         };
         Ok(Arc::new(VMASegment(SpinMutex::new("Segment lock", res))).as_segment().into())
     }
     
-    pub fn unmap_part(&self, start_va: VirtAddr, length: usize, pagetable: &mut PageTable) -> Result<(), ErrorNum> {
+    /// Dirty shared pages of the part go to `dirty`, see sync_dirty.
+    pub fn unmap_part(&self, start_va: VirtAddr, length: usize, pagetable: &mut PageTable, dirty: &mut DirtyPages) -> Result<(), ErrorNum> {
         let end_va = start_va + length;
         let start_vpn: VirtPageNum = start_va.to_vpn_ceil();
         let end_vpn: VirtPageNum = end_va.into();
         let mut inner = self.0.acquire();
+        Self::collect_dirty_locked(&inner, VPNRange::new(start_vpn, end_vpn), pagetable, dirty);
+        let mut res = Ok(());
         for vpn in VPNRange::new(start_vpn, end_vpn) {
             if let Some(pgs) = inner.frames.insert(vpn, PageGuardSlot::Unmapped) {
                 match pgs {
                     PageGuardSlot::CopyOnWrite(_) |
-                    PageGuardSlot::Populated(_) => {
+                    PageGuardSlot::Populated(_) |
+                    PageGuardSlot::SharedHole(_) => {
                         pagetable.unmap(vpn);
                     },
                     _ => {
@@ -1244,16 +1595,67 @@ impl VMASegment {
                 }
             } else {
                 inner.frames.remove(&vpn).unwrap();
-                return Err(ErrorNum::EACCES);
+                res = Err(ErrorNum::EACCES);
+                break;
             }
         }
+        // also what was unmapped before an error, so frames.len() stays what's reserved
         inner.frames.retain(|_k, v| !v.is_unmapped());
-        Ok(())
+        res
     }
 
     pub fn is_empty(&self) -> bool {
         self.0.acquire().frames.is_empty()
     }
+
+    /// Pages in `range` written through a shared mapping since last time go to `dirty`, for msync.
+    /// Caller flushes the TLB, as dirty bits are cleared.
+    pub fn collect_dirty(&self, range: VPNRange, pagetable: &mut PageTable, dirty: &mut DirtyPages) {
+        Self::collect_dirty_locked(&self.0.acquire(), range, pagetable, dirty)
+    }
+
+    /// What do_unmap found dirty.
+    pub fn take_unsynced(&self) -> DirtyPages {
+        core::mem::take(&mut self.0.acquire().unsynced)
+    }
+
+    /// Shared pages of the file at `inode` touching file bytes [from, to) go back to lazy, so the blocks behind
+    /// them can be freed. Only those mapped to ZERO_PAGE with `holes_only`, once holes were filled in. Caller
+    /// flushes the TLB.
+    pub fn unmap_file(&self, inode: (UUID, u32), from: usize, to: usize, holes_only: bool, pagetable: &mut PageTable) {
+        let mut inner = self.0.acquire();
+        if inner.mmap_type != MMAPType::Shared || inner.inode != inode {
+            return;
+        }
+        let (start_vpn, file_offset, file) = (inner.start_vpn, inner.file_offset, inner.file.clone());
+        for (vpn, slot) in inner.frames.iter_mut() {
+            let offset = file_offset + (*vpn - start_vpn) * PAGE_SIZE;
+            let mapped = match slot {
+                PageGuardSlot::Populated(_) => !holes_only,
+                PageGuardSlot::SharedHole(_) => true,
+                _ => false,
+            };
+            if mapped && offset + PAGE_SIZE > from && offset < to {
+                pagetable.unmap(*vpn);
+                *slot = PageGuardSlot::LazyVMAShared((file.clone(), offset));
+            }
+        }
+    }
+
+    fn collect_dirty_locked(inner: &VMASegmentInner, range: VPNRange, pagetable: &mut PageTable, dirty: &mut DirtyPages) {
+        if inner.mmap_type != MMAPType::Shared {
+            return;
+        }
+        for vpn in range {
+            if !matches!(inner.frames.get(&vpn), Some(PageGuardSlot::Populated(_))) || !pagetable.clear_dirty(vpn) {
+                continue;
+            }
+            // only what the mapping covers, the rest of the last page is not part of the file
+            let start = (vpn - inner.start_vpn) * PAGE_SIZE;
+            let len = PAGE_SIZE.min(inner.length - start);
+            dirty.push((inner.file.clone(), inner.file_offset + start, len));
+        }
+    }
 }
 
 impl TrampolineSegment {
@@ -1268,6 +1670,12 @@ impl UTrampolineSegment {
     }
 }
 
+impl VdsoSegment {
+    pub fn new() -> ArcSegment {
+        Arc::new(Self( SpinMutex::new("Segment lock", VdsoSegmentInner{ status: SegmentStatus::Initialized } ))).as_segment().into()
+    }
+}
+
 impl TrapContextSegment {
     pub fn new() -> ArcSegment {
         Arc::new(Self(SpinMutex::new("Segment lock",  TrapContextSegmentInner{ status: SegmentStatus::Initialized, page: None} ))).as_segment().into()
@@ -1314,6 +1722,7 @@ impl ProgramSegment {
             status: SegmentStatus::Initialized,
             start_vpn,
             mem_length,
+            unmapped: 0,
         };
         Ok(Arc::new(ProgramSegment(SpinMutex::new("Segment lock", res))).as_segment().into())
     }
@@ -1326,7 +1735,9 @@ impl ProgramSegment {
             let grow_start = current_last_va.to_vpn_ceil();
             let grow_end = (current_last_va + alteration as usize).to_vpn_ceil();
             for vpn in VPNRange::new(grow_start, grow_end) {
-                inner.frames.insert(vpn, PageGuardSlot::LazyAlloc);
+                if let Some(PageGuardSlot::Unmapped) = inner.frames.insert(vpn, PageGuardSlot::LazyAlloc) {
+                    inner.unmapped -= 1;
+                }
             }
             inner.mem_length += alteration as usize;
         } else if alteration < 0 {
@@ -1336,6 +1747,7 @@ impl ProgramSegment {
                 match inner.frames.remove(&vpn).unwrap() {
                     PageGuardSlot::Populated(_)   |
                     PageGuardSlot::CopyOnWrite(_) => pagetable.unmap(vpn),
+                    PageGuardSlot::Unmapped => inner.unmapped -= 1,
                     _ => {/* do nothing since not mapped */},
                 }
             }
@@ -1351,6 +1763,9 @@ impl ProgramSegment {
         let mut inner = self.0.acquire();
         for vpn in VPNRange::new(start_vpn, end_vpn) {
             if let Some(pgs) = inner.frames.insert(vpn, PageGuardSlot::Unmapped) {
+                if !pgs.is_unmapped() {
+                    inner.unmapped += 1;
+                }
                 match pgs {
                     PageGuardSlot::CopyOnWrite(_)|
                     PageGuardSlot::Populated(_) => {
@@ -9,13 +9,9 @@ use core::ptr::{read_volatile, write_volatile, copy_nonoverlapping};
 use alloc::string::String;
 use alloc::vec::Vec;
 
-use crate::config::{PAGE_OFFSET, PAGE_SIZE};
-use crate::process::{get_processor};
-use crate::utils::ErrorNum;
+use crate::config::{PAGE_OFFSET, PAGE_SIZE, POISON_BYTE};
 use crate::utils::range::{StepUp, StepDown, Range};
 
-use super::PageTable;
-
 #[repr(C)]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct VirtAddr(pub usize);
@@ -211,35 +207,6 @@ impl VirtAddr {
         from_raw_parts_mut(self.0 as *mut u8, length).to_vec()
     }
 
-    pub fn read_cstr(&self) -> Result<(String, usize), ErrorNum> {
-        let bytes = self.read_cstr_raw(1024);
-        let len = bytes.len();
-        if let Ok(s) = String::from_utf8(bytes) {
-            Ok((s, len))
-        } else {
-            warning!("Bad utf-8 sequence.");
-            Err(ErrorNum::EBADCODEX)
-        }
-    }
-
-    // TODO: check page mapping
-    pub fn read_cstr_raw(&self, size_limit: usize) -> Vec<u8> {
-        let hart = get_processor();
-        hart.push_sum_on();
-        let mut bytes = Vec::new();
-        let mut va = self.clone();
-        loop {
-            let b: u8 = unsafe{va.read_volatile()};
-            if b == 0 || bytes.len() >= size_limit {
-                break;
-            }
-            bytes.push(b);
-            va = va + size_of::<u8>();
-        }
-        hart.pop_sum_on();
-        bytes
-    }
-
     pub fn to_vpn_ceil(&self) -> VirtPageNum {
         if self.0 == 0 {
             1.into()
@@ -247,31 +214,6 @@ impl VirtAddr {
             VirtPageNum(((self.0 - 1) >> PAGE_OFFSET) + 1)
         }
     }
-
-    pub fn write_user<T: Clone>(&self, pagetable: &PageTable, data: &T) -> Result<(), ()> {
-        pagetable.translate(VirtPageNum::from(*self)).map_err(|_| ())?;
-        let hart = get_processor();
-        hart.push_sum_on();
-        unsafe {
-            self.write_volatile(data);
-        }
-        hart.pop_sum_on();
-        Ok(())
-    }
-
-    pub fn write_user_data(&self, pagetable: &PageTable, data: Vec<u8>) -> Result<(), ()> {
-        for vpn in VPNRange::new(VirtPageNum::from(*self), VirtPageNum::from(*self + data.len())) {
-            pagetable.translate(VirtPageNum::from(vpn)).map_err(|_| ())?;
-        }
-        if data.len() == 0 {return Ok(());}
-        let hart = get_processor();
-        hart.push_sum_on();
-        unsafe {
-            copy_nonoverlapping(data.as_ptr(), self.0 as * mut u8, data.len());
-        }
-        hart.pop_sum_on();
-        Ok(())
-    }
 }
 
 impl From<PhysAddr> for PhysPageNum {
@@ -462,6 +404,11 @@ impl PhysPageNum {
         core::ptr::copy_nonoverlapping(src, (self.0 << PAGE_OFFSET) as *mut u8, PAGE_SIZE);
     }
 
+    /// Fill with POISON_BYTE, so stale pointers into a freed page read garbage instead of old data.
+    pub unsafe fn poison_content(&self) {
+        core::ptr::write_bytes((self.0 << PAGE_OFFSET) as *mut u8, POISON_BYTE, PAGE_SIZE);
+    }
+
     pub unsafe fn copy_page(src: &Self, dst: &Self) {
         let src = (src.0 << PAGE_OFFSET) as *const u8;
         let dst = (dst.0 << PAGE_OFFSET) as *mut u8;
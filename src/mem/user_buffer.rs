@@ -0,0 +1,228 @@
+use core::cmp::min;
+use core::ptr::copy_nonoverlapping;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::config::PAGE_SIZE;
+use crate::utils::ErrorNum;
+
+use super::{MemLayout, PageGuard, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum, PTEFlags, PageTableEntry};
+
+/// One physically contiguous piece of a user buffer.
+#[derive(Clone)]
+struct UserBufferChunk {
+    /// Keeps the frame alive while kernel is accessing it, even if user unmap it meanwhile.
+    _pin: Option<PageGuard>,
+    pa: PhysAddr,
+    len: usize,
+}
+
+/// A user buffer translated through the process pagetable and pinned page by page.
+/// Lets drivers and filesystems access user memory directly, without SUM and without copying into a Vec first.
+pub struct UserBuffer {
+    chunks: Vec<UserBufferChunk>,
+    len: usize,
+}
+
+impl UserBuffer {
+    /// Translate and pin `[va, va + len)`. Lazy and COW pages are resolved first, so `writable` buffers
+    /// point to the frames user will actually see.
+    pub fn new(mem_layout: &mut MemLayout, va: VirtAddr, len: usize, writable: bool) -> Result<Self, ErrorNum> {
+        let mut res = Self {
+            chunks: Vec::new(),
+            len: 0
+        };
+        res.append(mem_layout, va, len, writable)?;
+        Ok(res)
+    }
+
+    /// Join several buffers into one, e.g. the entries of an iovec. Pages stay pinned by both.
+    pub fn join(bufs: &[UserBuffer]) -> Self {
+        let mut res = Self {
+            chunks: Vec::new(),
+            len: 0
+        };
+        for buf in bufs {
+            res.chunks.extend(buf.chunks.iter().cloned());
+            res.len += buf.len;
+        }
+        res
+    }
+
+    /// Translate, pin and append `[va, va + len)` to the end of this buffer.
+    pub fn append(&mut self, mem_layout: &mut MemLayout, va: VirtAddr, len: usize, writable: bool) -> Result<(), ErrorNum> {
+        let mut cur = va;
+        // wrapping around is never a valid user range
+        let end = VirtAddr(va.0.checked_add(len).ok_or(ErrorNum::EFAULT)?);
+        while cur < end {
+            let vpn = VirtPageNum::from(cur);
+            let page_off = cur.0 % PAGE_SIZE;
+            let chunk_len = min(PAGE_SIZE - page_off, end - cur);
+            let ppn = Self::resolve(mem_layout, vpn, writable)?;
+            let seg = mem_layout.get_segment(vpn).map_err(|_| ErrorNum::EFAULT)?;
+            self.chunks.push(UserBufferChunk {
+                _pin: seg.get_page(vpn),
+                pa: PhysAddr::from(ppn) + page_off,
+                len: chunk_len
+            });
+            cur += chunk_len;
+        }
+        self.len += len;
+        Ok(())
+    }
+
+    fn resolve(mem_layout: &mut MemLayout, vpn: VirtPageNum, writable: bool) -> Result<PhysPageNum, ErrorNum> {
+        // at most three times: lazy alloc, fill in a hole mapped read only for a write, then check again
+        for _ in 0..3 {
+            if let Some(pte_addr) = mem_layout.pagetable.walk_find(vpn) {
+                let pte: PageTableEntry = unsafe{pte_addr.read_volatile()};
+                let flags = pte.flags();
+                if flags.contains(PTEFlags::V) {
+                    if !flags.contains(PTEFlags::U) || !flags.contains(PTEFlags::R) {
+                        return Err(ErrorNum::EFAULT);
+                    }
+                    if !writable || flags.contains(PTEFlags::W) {
+                        return Ok(pte.ppn());
+                    }
+                }
+            }
+            // keep ENOMEM, it's not user's fault
+            mem_layout.do_lazy(vpn).map_err(|e| if e == ErrorNum::ENOMEM {ErrorNum::ENOMEM} else {ErrorNum::EFAULT})?;
+        }
+        Err(ErrorNum::EFAULT)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the pinned pieces.
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.chunks.iter().map(|c| unsafe{from_raw_parts(c.pa.0 as *const u8, c.len)})
+    }
+
+    /// Iterate over the pinned pieces, mutable.
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        self.chunks.iter_mut().map(|c| unsafe{from_raw_parts_mut(c.pa.0 as *mut u8, c.len)})
+    }
+
+    /// Copy `len` bytes starting at `offset` of this buffer to `dst`.
+    pub unsafe fn copy_to(&self, offset: usize, dst: PhysAddr, len: usize) {
+        self.walk(offset, len, |pa, done, cnt| copy_nonoverlapping(pa.0 as *const u8, (dst + done).0 as *mut u8, cnt));
+    }
+
+    /// Copy `len` bytes from `src` into this buffer, starting at `offset`.
+    pub unsafe fn copy_from(&mut self, offset: usize, src: PhysAddr, len: usize) {
+        self.walk(offset, len, |pa, done, cnt| copy_nonoverlapping((src + done).0 as *const u8, pa.0 as *mut u8, cnt));
+    }
+
+    /// Copy a slice into this buffer, return bytes copied.
+    pub fn write_slice(&mut self, offset: usize, data: &[u8]) -> usize {
+        let len = min(data.len(), self.len.saturating_sub(offset));
+        unsafe{self.copy_from(offset, PhysAddr(data.as_ptr() as usize), len)};
+        len
+    }
+
+    /// Fallback for files that still want a Vec.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(self.len);
+        for c in self.chunks() {
+            res.extend_from_slice(c);
+        }
+        res
+    }
+
+    /// Call `f(pa, bytes_done, count)` for each physical piece of `[offset, offset + len)`.
+    unsafe fn walk<F: FnMut(PhysAddr, usize, usize)>(&self, mut offset: usize, len: usize, mut f: F) {
+        assert!(offset + len <= self.len, "UserBuffer access out of range");
+        let mut done = 0;
+        for c in self.chunks.iter() {
+            if done == len {
+                break;
+            }
+            if offset >= c.len {
+                offset -= c.len;
+                continue;
+            }
+            let cnt = min(c.len - offset, len - done);
+            f(c.pa + offset, done, cnt);
+            done += cnt;
+            offset = 0;
+        }
+    }
+}
+
+/// Copy `[va, va + len)` out of user memory. EFAULT if any byte of it is not mapped readable for user.
+pub fn copy_from_user(mem_layout: &mut MemLayout, va: VirtAddr, len: usize) -> Result<Vec<u8>, ErrorNum> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    Ok(UserBuffer::new(mem_layout, va, len, false)?.to_vec())
+}
+
+/// Copy `data` into user memory at `va`. The whole range is checked first, so nothing is written on EFAULT.
+pub fn copy_to_user(mem_layout: &mut MemLayout, va: VirtAddr, data: &[u8]) -> Result<(), ErrorNum> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    UserBuffer::new(mem_layout, va, data.len(), true)?.write_slice(0, data);
+    Ok(())
+}
+
+/// Read a NUL terminated string from user, NUL not included. Pages are checked one at a time as the scan reaches
+/// them, so a string running into an unmapped page is EFAULT. ENAMETOOLONG if there's no NUL in `max_len` bytes.
+pub fn read_user_cstr(mem_layout: &mut MemLayout, va: VirtAddr, max_len: usize) -> Result<Vec<u8>, ErrorNum> {
+    let mut res = Vec::new();
+    let mut cur = va;
+    loop {
+        let chunk_len = PAGE_SIZE - cur.0 % PAGE_SIZE;
+        let buf = UserBuffer::new(mem_layout, cur, chunk_len, false)?;
+        // within one page, so exactly one chunk
+        let page = buf.chunks().next().unwrap();
+        let found = page.iter().position(|b| *b == 0);
+        res.extend_from_slice(&page[..found.unwrap_or(chunk_len)]);
+        if res.len() > max_len {
+            return Err(ErrorNum::ENAMETOOLONG);
+        }
+        if found.is_some() {
+            return Ok(res);
+        }
+        cur += chunk_len;
+    }
+}
+
+/// `read_user_cstr`, then utf-8 checked.
+pub fn read_user_str(mem_layout: &mut MemLayout, va: VirtAddr, max_len: usize) -> Result<String, ErrorNum> {
+    String::from_utf8(read_user_cstr(mem_layout, va, max_len)?).map_err(|_| {
+        warning!("Bad utf-8 sequence.");
+        ErrorNum::EBADCODEX
+    })
+}
+
+/// Read a `T` from user. EFAULT if `va` is not aligned for `T`, or not mapped readable for user.
+pub fn read_user<T: Copy>(mem_layout: &mut MemLayout, va: VirtAddr) -> Result<T, ErrorNum> {
+    if va.0 % core::mem::align_of::<T>() != 0 {
+        return Err(ErrorNum::EFAULT);
+    }
+    let raw = copy_from_user(mem_layout, va, core::mem::size_of::<T>())?;
+    Ok(unsafe{(raw.as_ptr() as *const T).read_unaligned()})
+}
+
+/// Write a `T` to user. EFAULT if `va` is not aligned for `T`, or not mapped writable for user.
+pub fn write_user<T: Copy>(mem_layout: &mut MemLayout, va: VirtAddr, value: &T) -> Result<(), ErrorNum> {
+    if va.0 % core::mem::align_of::<T>() != 0 {
+        return Err(ErrorNum::EFAULT);
+    }
+    copy_to_user(mem_layout, va, bytes_of(value))
+}
+
+/// Raw bytes of a plain `repr(C)` value, for passing structs to `copy_to_user`.
+pub fn bytes_of<T: Copy>(value: &T) -> &[u8] {
+    unsafe{from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())}
+}
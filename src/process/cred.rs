@@ -0,0 +1,96 @@
+//! Who a process runs as. Real ids are who started it, effective ones are what it acts as, saved ones keep what a
+//! setuid or setgid exec gave, so it can drop that and take it back later. Euid 0 is privileged.
+
+use crate::{fs::{FileOwner, Permission}, utils::ErrorNum};
+
+/// Argument of setresuid and setresgid that leaves the id as it is.
+pub const ID_UNCHANGED: u32 = u32::MAX;
+
+/// Also what SYSCALL_GETCRED writes to user.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid : u32,
+    pub euid: u32,
+    pub suid: u32,
+    pub gid : u32,
+    pub egid: u32,
+    pub sgid: u32,
+}
+
+impl Credentials {
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    pub fn privileged(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Take on the owner of a setuid or setgid image being exec'd, if `allowed`. Saved ids follow the effective
+    /// ones either way. True if we now act as someone other than who started us, for AT_SECURE.
+    pub fn exec(&mut self, owner: FileOwner, allowed: bool) -> bool {
+        if allowed && owner.permission.contains(Permission::SETUID) {
+            self.euid = owner.uid;
+        }
+        if allowed && owner.permission.contains(Permission::SETGID) {
+            self.egid = owner.gid;
+        }
+        self.suid = self.euid;
+        self.sgid = self.egid;
+        self.euid != self.uid || self.egid != self.gid
+    }
+
+    /// `want`, some of the OTHER_R, OTHER_W, OTHER_X bits, is granted on a file of `owner`. Judged by the real ids
+    /// as access() does, or by the effective ones. Uid 0 gets read and write anyway, exec only if some x bit is set.
+    pub fn permits(&self, owner: FileOwner, want: Permission, effective: bool) -> bool {
+        let (uid, gid) = if effective {(self.euid, self.egid)} else {(self.uid, self.gid)};
+        let mode = owner.permission.bits();
+        if uid == 0 {
+            return !want.contains(Permission::OTHER_X) || mode & 0o111 != 0;
+        }
+        let granted = if uid == owner.uid {
+            mode >> 6
+        } else if gid == owner.gid {
+            mode >> 3
+        } else {
+            mode
+        } & 0o7;
+        granted & want.bits() == want.bits()
+    }
+
+    /// Can walk through a dir of `owner` in path lookup, its x bit for us. Uid 0 always can.
+    pub fn may_search(&self, owner: FileOwner, effective: bool) -> bool {
+        let uid = if effective {self.euid} else {self.uid};
+        uid == 0 || self.permits(owner, Permission::OTHER_X, effective)
+    }
+
+    /// Unprivileged callers can only shuffle ids they already have.
+    pub fn set_resuid(&mut self, uid: u32, euid: u32, suid: u32) -> Result<(), ErrorNum> {
+        let current = [self.uid, self.euid, self.suid];
+        let new = Self::resolve([uid, euid, suid], current, self.privileged())?;
+        [self.uid, self.euid, self.suid] = new;
+        Ok(())
+    }
+
+    pub fn set_resgid(&mut self, gid: u32, egid: u32, sgid: u32) -> Result<(), ErrorNum> {
+        let current = [self.gid, self.egid, self.sgid];
+        let new = Self::resolve([gid, egid, sgid], current, self.privileged())?;
+        [self.gid, self.egid, self.sgid] = new;
+        Ok(())
+    }
+
+    fn resolve(wanted: [u32; 3], current: [u32; 3], privileged: bool) -> Result<[u32; 3], ErrorNum> {
+        let mut res = current;
+        for (slot, id) in res.iter_mut().zip(wanted) {
+            if id == ID_UNCHANGED {
+                continue;
+            }
+            if !privileged && !current.contains(&id) {
+                return Err(ErrorNum::EPERM);
+            }
+            *slot = id;
+        }
+        Ok(res)
+    }
+}
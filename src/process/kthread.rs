@@ -0,0 +1,79 @@
+//! Kernel threads: processes that run a kernel function and never go to U mode. They are scheduled, put to sleep
+//! and woken as any other process, but kernel code is never preempted, so one must sleep or yield on its own.
+//! Their address space is the kernel and a kernel stack, their image an empty file named `[name]` for /proc.
+//! They are no one's children, never exit, and take no signals.
+
+use alloc::sync::{Arc, Weak};
+
+use crate::{fs::anon_file, utils::{Mutex, SpinMutex, time::get_cycle}};
+
+use super::{ProcessControlBlock, WaitQueue, TimerKey, TimerTarget, add_timer, cancel_timer, enqueue, get_processor, ms_to_cycles};
+
+/// Start running `entry` in a new kernel thread.
+pub fn spawn_kthread(name: &str, entry: fn() -> !) -> Arc<ProcessControlBlock> {
+    let proc = ProcessControlBlock::new_kthread(anon_file(format!("/[{}]", name).into()), entry);
+    milestone!("Kernel thread {} started as {:?}.", name, proc.pid);
+    enqueue(proc.clone());
+    proc
+}
+
+/// Where a kernel thread is first switched to, its pcb locked by the scheduler as for fork_return.
+pub fn kthread_start() -> ! {
+    let entry = {
+        let processor = get_processor();
+        let pcb = processor.current().unwrap();
+        let pcb_inner = unsafe {pcb.inner.from_locked()};
+        pcb_inner.kthread.expect("kthread_start on a user process")
+    };
+    entry()
+}
+
+/// Lets a kernel thread sleep for some time or until kicked, whichever comes first.
+pub struct Alarm {
+    kicked: SpinMutex<bool>,
+    queue: WaitQueue,
+    self_ref: Weak<Alarm>,
+}
+
+impl Alarm {
+    pub fn new(name: &str) -> Arc<Self> {
+        Arc::new_cyclic(|self_ref| Self {
+            kicked: SpinMutex::new(name, false),
+            queue: WaitQueue::new(name),
+            self_ref: self_ref.clone(),
+        })
+    }
+
+    /// Sleep up to `ms`, 0 for until kicked. A kick since the last wait returns at once.
+    pub fn wait(&self, ms: usize) {
+        let kicked = self.kicked.acquire();
+        if !*kicked {
+            let key: Option<TimerKey> = if ms != 0 {
+                Some(add_timer(get_cycle().saturating_add(ms_to_cycles(ms)), self.self_ref.clone()))
+            } else {
+                None
+            };
+            self.queue.sleep_on(kicked);
+            if let Some(key) = key {
+                cancel_timer(key);
+            }
+        } else {
+            drop(kicked);
+        }
+        *self.kicked.acquire() = false;
+    }
+
+    /// Wake the waiter now. Don't call with a pcb lock held.
+    pub fn kick(&self) {
+        *self.kicked.acquire() = true;
+        self.queue.wake_all();
+    }
+}
+
+impl TimerTarget for Alarm {
+    fn expire(&self, _deadline: usize) {
+        // the waiter is queued once its lock is released
+        drop(self.kicked.acquire());
+        self.queue.wake_all();
+    }
+}
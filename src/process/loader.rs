@@ -0,0 +1,68 @@
+use alloc::{vec::Vec, sync::Arc, string::String};
+
+use crate::{fs::{Path, RegularFile, OpenMode, open}, config::{MAX_SHEBANG_RECURSE, SHEBANG_LINE_MAX}, utils::ErrorNum};
+
+/// Resolve what to actually load for exec, following `#!` scripts.
+/// `args` start with argv[0]. For each script level, argv[0] is replaced with the script path and
+/// `interpreter [optional-arg]` is put in front, like Linux does.
+pub fn resolve_exec(cwd: &Path, path: Path, mut args: Vec<Vec<u8>>) -> Result<(Arc<dyn RegularFile>, Vec<Vec<u8>>), ErrorNum> {
+    let mut exec_path = path;
+    for _ in 0..=MAX_SHEBANG_RECURSE {
+        let file = open(&exec_path, OpenMode::READ | OpenMode::EXEC)?;
+        let head = file.read(SHEBANG_LINE_MAX)?;
+        let (interp, interp_arg) = match parse_shebang(&head)? {
+            Some(res) => res,
+            None => return Ok((open(&exec_path, OpenMode::SYS)?.as_regular()?, args)),
+        };
+        info!("shebang discovered in {:?}, interpreter {}", exec_path, interp);
+
+        let mut new_args = Vec::new();
+        new_args.push(cstr(&interp));
+        if let Some(arg) = interp_arg {
+            new_args.push(cstr(&arg));
+        }
+        new_args.push(cstr(&format!("{:?}", exec_path)));
+        new_args.extend(args.into_iter().skip(1));
+        args = new_args;
+
+        exec_path = if interp.starts_with('/') {
+            Path::new_s(interp)?
+        } else {
+            cwd.concat(&Path::new_s(interp)?)?
+        };
+    }
+    Err(ErrorNum::ELOOP)
+}
+
+/// `#! /path/to/interp  optional arg \n` -> (interp, arg). Everything after the interpreter is a single argument.
+/// A file that ends before the newline is one line. None if it's not a script.
+fn parse_shebang(head: &[u8]) -> Result<Option<(String, Option<String>)>, ErrorNum> {
+    if head.len() < 2 || head[0] != b'#' || head[1] != b'!' {
+        return Ok(None);
+    }
+    let line_end = match head.iter().position(|&c| c == b'\n') {
+        Some(pos) => pos,
+        // short read means EOF, a full one means the line is too long
+        None if head.len() < SHEBANG_LINE_MAX => head.len(),
+        None => return Err(ErrorNum::ENOEXEC),
+    };
+    let line = core::str::from_utf8(&head[2..line_end]).map_err(|_| ErrorNum::ENOEXEC)?;
+    let line = line.trim_matches(|c| c == ' ' || c == '\t' || c == '\r');
+    if line.is_empty() {
+        return Err(ErrorNum::ENOEXEC);
+    }
+    match line.find(|c| c == ' ' || c == '\t') {
+        Some(split) => {
+            let interp = String::from(&line[..split]);
+            let arg = line[split..].trim_matches(|c| c == ' ' || c == '\t');
+            Ok(Some((interp, if arg.is_empty() {None} else {Some(String::from(arg))})))
+        },
+        None => Ok(Some((String::from(line), None)))
+    }
+}
+
+fn cstr(s: &str) -> Vec<u8> {
+    let mut res = Vec::from(s.as_bytes());
+    res.push(0);
+    res
+}
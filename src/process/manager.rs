@@ -1,11 +1,11 @@
 use core::sync::atomic::{Ordering, AtomicUsize};
 
-use alloc::{collections::{VecDeque}, sync::{Arc, Weak}, vec::Vec};
+use alloc::{collections::{VecDeque, BTreeMap}, sync::{Arc, Weak}, vec::Vec};
 use lazy_static::*;
 
 use crate::{utils::{SpinMutex, MutexGuard, Mutex, ErrorNum}, config::MAX_CPUS};
 
-use super::{ProcessControlBlock, get_hart_id};
+use super::{ProcessControlBlock, ProcessStatus, get_hart_id, processor::kick_idle_hart, sched_stat};
 
 lazy_static!{
     static ref PROCESS_MANAGER: ProcessManager = ProcessManager::new();
@@ -28,7 +28,9 @@ impl ProcessManager {
 
 struct ProcessManagerInner{
     pub process_list: VecDeque<Arc<ProcessControlBlock>>,
-    pub running_list: [Option<Weak<ProcessControlBlock>>; MAX_CPUS]
+    pub running_list: [Option<Weak<ProcessControlBlock>>; MAX_CPUS],
+    /// processes sleeping on a SleepMutex / Condvar, waiting for wake_up
+    pub blocked_list: BTreeMap<ProcessID, Arc<ProcessControlBlock>>
 }
 
 impl ProcessManagerInner {
@@ -36,6 +38,7 @@ impl ProcessManagerInner {
         Self {
             process_list: VecDeque::new(),
             running_list: Default::default(),
+            blocked_list: BTreeMap::new(),
         }
     }
 
@@ -46,6 +49,7 @@ impl ProcessManagerInner {
 
     /// guard by mutex, intr off, get_hart_id safe.
     pub fn dequeue(&mut self) -> Option<Arc<ProcessControlBlock>> {
+        sched_stat::sample_queue(self.process_list.len());
         if let Some(proc ) = self.process_list.pop_front() {
             self.running_list[get_hart_id()] = Some(Arc::downgrade(&proc));
             Some(proc)
@@ -54,8 +58,19 @@ impl ProcessManagerInner {
         }
     }
 
+    /// move current process from running list to blocked list
+    pub fn block(&mut self, process: Arc<ProcessControlBlock>) {
+        self.running_list[get_hart_id()].take();
+        self.blocked_list.insert(process.pid, process);
+    }
+
+    pub fn unblock(&mut self, pid: ProcessID) -> Option<Arc<ProcessControlBlock>> {
+        self.blocked_list.remove(&pid)
+    }
+
+    /// Nothing runs on this hart anymore. May be clear already, enqueue from the running process does that too.
     pub fn free_current(&mut self) {
-        self.running_list[get_hart_id()].take().expect("No process is running.");
+        self.running_list[get_hart_id()].take();
     }
 
     pub fn get_process(&self, pid: ProcessID) -> Result<Arc<ProcessControlBlock>, ErrorNum> {
@@ -73,6 +88,9 @@ impl ProcessManagerInner {
                 }
             }
         }
+        if let Some(proc) = self.blocked_list.get(&pid) {
+            return Ok(proc.clone());
+        }
         Err(ErrorNum::ESRCH)
     }
 
@@ -85,18 +103,74 @@ impl ProcessManagerInner {
                 }
             }
         }
+        res.extend(self.blocked_list.values().cloned());
         res
     }
 }
 
 pub fn enqueue(process: Arc<ProcessControlBlock>) {
     PROCESS_MANAGER.inner_locked().enqueue(process);
+    kick_idle_hart();
+}
+
+/// Anything in the ready queue?
+pub fn has_ready() -> bool {
+    !PROCESS_MANAGER.inner_locked().process_list.is_empty()
 }
 
 pub fn dequeue() -> Option<Arc<ProcessControlBlock>> {
     PROCESS_MANAGER.inner_locked().dequeue()
 }
 
+pub fn block(process: Arc<ProcessControlBlock>) {
+    PROCESS_MANAGER.inner_locked().block(process);
+}
+
+/// Put a sleeping process back into the ready queue, whatever it's sleeping on.
+/// Spins on the pcb lock until the sleeper has fully switched out, so no wakeup is lost.
+pub fn wake_up(pid: ProcessID) -> bool {
+    let proc = PROCESS_MANAGER.inner_locked().unblock(pid);
+    if let Some(proc) = proc {
+        let mut pcb_inner = proc.get_inner();
+        assert!(pcb_inner.status == ProcessStatus::Sleeping, "Waking up process that is not sleeping");
+        pcb_inner.status = ProcessStatus::Ready;
+        pcb_inner.wait_channel = None;
+        PROCESS_MANAGER.inner_locked().process_list.push_back(proc.clone());
+        drop(pcb_inner);
+        kick_idle_hart();
+        true
+    } else {
+        false
+    }
+}
+
+/// Like wake_up, but only if `pid` is still sleeping on `channel`. False for stale WaitQueue entries,
+/// left behind by processes some other path (signal, oom, hangup) already woke.
+pub fn wake_up_on(pid: ProcessID, channel: usize) -> bool {
+    let proc = PROCESS_MANAGER.inner_locked().blocked_list.get(&pid).cloned();
+    if let Some(proc) = proc {
+        // pcb lock before the manager's, as in sleep_switch
+        let mut pcb_inner = proc.get_inner();
+        if pcb_inner.status != ProcessStatus::Sleeping || pcb_inner.wait_channel != Some(channel) {
+            return false;
+        }
+        let mut manager = PROCESS_MANAGER.inner_locked();
+        if manager.unblock(pid).is_none() {
+            // wake_up got it first, and is spinning on the pcb lock
+            return false;
+        }
+        pcb_inner.status = ProcessStatus::Ready;
+        pcb_inner.wait_channel = None;
+        manager.process_list.push_back(proc.clone());
+        drop(manager);
+        drop(pcb_inner);
+        kick_idle_hart();
+        true
+    } else {
+        false
+    }
+}
+
 pub fn free_current() {
     PROCESS_MANAGER.inner_locked().free_current();
 }
@@ -109,6 +183,15 @@ pub fn process_list() -> Vec<Arc<ProcessControlBlock>> {
     PROCESS_MANAGER.inner_locked().enumerate_process()
 }
 
+/// Members of thread group `tgid` that haven't exited, leader first. Locks each member, don't hold their locks.
+pub fn thread_group(tgid: ProcessID) -> Vec<Arc<ProcessControlBlock>> {
+    let mut res: Vec<_> = process_list().into_iter()
+        .filter(|proc| proc.tgid == tgid && proc.get_inner().status != ProcessStatus::Zombie)
+        .collect();
+    res.sort_by_key(|proc| proc.pid);
+    res
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ProcessID(pub usize);
 
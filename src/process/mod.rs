@@ -1,10 +1,24 @@
 mod pcb;
 mod manager;
 mod processor;
+mod wait_queue;
+mod waker;
+mod loader;
+mod rlimit;
+mod syscall_filter;
+mod syscall_abi;
+mod oom;
+mod session;
+mod cred;
+mod timer_queue;
+mod vector;
+mod kthread;
+pub mod sched_stat;
 use alloc::sync::Arc;
 pub use pcb::{
     ProcessStatus,
     ProcessControlBlock,
+    PCBInner,
     FileDescriptor
 };
 pub mod def_handler;
@@ -12,14 +26,82 @@ mod signal_num;
 
 pub use signal_num::SignalNum;
 
+pub use wait_queue::WaitQueue;
+pub use waker::Waker;
+
+pub use loader::resolve_exec;
+
+pub use oom::oom_kill;
+
+pub use cred::Credentials;
+
+pub use kthread::{
+    spawn_kthread,
+    Alarm
+};
+
+pub use vector::{
+    VectorState,
+    has_vector,
+    vector_dirty,
+    vector_off
+};
+
+pub use timer_queue::{
+    TimerTarget,
+    TimerKey,
+    add_timer,
+    cancel_timer,
+    run_timers,
+    ms_to_cycles,
+    cycles_to_ms
+};
+
+pub use session::{
+    Terminal,
+    set_ctty,
+    get_ctty,
+    hang_up_session,
+    session_members,
+    pgrp_in_session
+};
+
+pub use syscall_filter::{
+    SyscallFilter,
+    FILTER_MODE_ALLOW,
+    FILTER_MODE_DENY,
+    FILTER_BITMAP_MAX
+};
+
+pub use syscall_abi::{
+    SyscallAbi,
+    ELFOSABI_LINUX
+};
+
+pub use rlimit::{
+    RLimit,
+    RLIMIT_CPU,
+    RLIMIT_NOFILE,
+    RLIMIT_MEMLOCK,
+    RLIMIT_AS,
+    RLIMIT_COUNT,
+    RLIM_INFINITY,
+    rlimit_ceiling
+};
+
 pub use manager::{
     enqueue,
     dequeue,
+    has_ready,
     ProcessID,
     new_pid,
     get_process,
     process_list,
-    free_current
+    thread_group,
+    free_current,
+    block,
+    wake_up,
+    wake_up_on
 };
 
 pub use processor::{
@@ -31,16 +113,22 @@ pub use processor::{
     pop_sum_on,
     get_processor,
     get_hart_id,
+    hart_present,
+    present_harts,
+    present_hart_count,
+    set_hart_online,
+    hart_online,
+    online_harts,
     PROCESSOR_MANAGER
 };
 
 use lazy_static::*;
 lazy_static!{
     pub static ref INIT_PROCESS: Arc<ProcessControlBlock> = {
-        let init = ProcessControlBlock::new(crate::config::INIT_PROCESS_PATH.into()).unwrap();
+        let init = ProcessControlBlock::new(crate::utils::bootargs::init_path().into()).unwrap();
         // let mut init_inner = init.get_inner();
         // let elf_file = init_inner.elf_file.clone();
-        // (init_inner.entry_point, init_inner.data_end) = init_inner.mem_layout.map_elf(elf_file).unwrap();
+        // (init_inner.entry_point, init_inner.data_end) = init_inner.mem_layout.map_elf(elf_file, 0).unwrap();
         // drop(init_inner);
         init
     };
@@ -49,9 +137,12 @@ lazy_static!{
 pub fn init() {
     enqueue(INIT_PROCESS.clone());
     milestone!("Init_process initialzed and enqueued for execution.");
+    spawn_kthread("writeback", crate::fs::writeback_daemon);
+    spawn_kthread("zeropage", crate::mem::zero_daemon);
 }
 
 pub fn hart_init() {
     milestone!("Starting scheduler on hart {}...", get_hart_id());
+    set_hart_online(get_hart_id());
     get_processor().run();
 }
\ No newline at end of file
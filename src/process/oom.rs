@@ -0,0 +1,32 @@
+use alloc::sync::Arc;
+
+use crate::utils::ErrorNum;
+
+use super::{process_list, wake_up, ProcessControlBlock, ProcessID, ProcessStatus, SignalNum, INIT_PROCESS};
+
+/// Out of memory, kill the process with most resident pages to make room.
+/// Init, kernel threads, zombies and `spare` are never chosen. Caller must not hold any PCB lock.
+pub fn oom_kill(spare: Option<ProcessID>) -> Result<(), ErrorNum> {
+    let mut victim: Option<(Arc<ProcessControlBlock>, usize)> = None;
+    for proc in process_list() {
+        if proc.pid == INIT_PROCESS.pid || Some(proc.pid) == spare {
+            continue;
+        }
+        let proc_inner = proc.get_inner();
+        if proc_inner.status == ProcessStatus::Zombie || proc_inner.kthread.is_some() {
+            continue;
+        }
+        let badness = proc_inner.mem_layout.resident_pages();
+        drop(proc_inner);
+        if victim.as_ref().map_or(true, |(_, max)| badness > *max) {
+            victim = Some((proc, badness));
+        }
+    }
+
+    let (victim, badness) = victim.ok_or(ErrorNum::ENOMEM)?;
+    fatal!("Out of memory: killing process {:?} with {} resident pages.", victim.pid, badness);
+    victim.get_inner().recv_signal(SignalNum::SIGKILL)?;
+    // let it run and die
+    wake_up(victim.pid);
+    Ok(())
+}
@@ -1,22 +1,48 @@
 use core::{mem::size_of, cmp::Ordering};
 
-use alloc::{collections::{BTreeMap, LinkedList, VecDeque}, sync::{Arc, Weak}, vec::Vec};
-
-use crate::{mem::{MemLayout, VirtAddr, VirtPageNum}, utils::{SpinMutex, MutexGuard, Mutex, ErrorNum}, fs::{Path, open, OpenMode, RegularFile, File}, interrupt::trap_context::TrapContext, config::{TRAP_CONTEXT_ADDR, PROC_U_STACK_ADDR, PROC_U_STACK_SIZE, U_TRAMPOLINE_ADDR, MAX_FD, MAX_SYSCALL}, process::{def_handler::*, get_processor}, syscall::syscall_num::{SYSCALL_WRITE, SYSCALL_READ}};
-
-use super::{ProcessID, new_pid, processor::ProcessContext, SignalNum};
-
-#[derive(PartialEq, Eq)]
+use alloc::{boxed::Box, collections::{BTreeMap, LinkedList, VecDeque}, sync::{Arc, Weak}, vec::Vec};
+
+use crate::{mem::{ArcSegment, DirtyPages, MemLayout, VirtAddr, VirtPageNum, aslr_offset, copy_to_user, write_user}, utils::{SpinMutex, MutexGuard, Mutex, ErrorNum, vdso}, fs::{Path, open, OpenMode, Permission, RegularFile, File, MountFlags, MountManager, mount_flags}, interrupt::trap_context::TrapContext, config::{MAX_FD, TRAP_CONTEXT_ADDR, VDSO_DATA_ADDR, PROC_U_STACK_ADDR, PROC_U_STACK_SIZE, PROC_ARGS_ADDR, ARG_MAX, U_TRAMPOLINE_ADDR, MAX_SYSCALL, TIMER_FRAC, PAGE_SIZE, ELF_INTERP_BASE, ELF_INTERP_RAND_PAGES, ASLR_STACK_RAND_PAGES}, process::def_handler::*, syscall::syscall_num::{SYSCALL_WRITE, SYSCALL_READ}};
+
+use super::{ProcessID, new_pid, thread_group, wake_up, processor::ProcessContext, SignalNum, WaitQueue, rlimit::{RLimit, RLIMIT_COUNT, RLIMIT_NOFILE, RLIMIT_MEMLOCK, RLIMIT_AS, RLIMIT_CPU, default_rlimits}, SyscallFilter, SyscallAbi, cred::Credentials, VectorState};
+
+// auxv types passed to user on exec, same value as linux
+pub const AT_NULL   : usize = 0;
+pub const AT_PHDR   : usize = 3;
+pub const AT_PHENT  : usize = 4;
+pub const AT_PHNUM  : usize = 5;
+pub const AT_PAGESZ : usize = 6;
+pub const AT_BASE   : usize = 7;
+pub const AT_ENTRY  : usize = 9;
+pub const AT_UID    : usize = 11;
+pub const AT_EUID   : usize = 12;
+pub const AT_GID    : usize = 13;
+pub const AT_EGID   : usize = 14;
+/// 1 if exec changed who we run as, the image shouldn't trust environment it got
+pub const AT_SECURE : usize = 23;
+// ours, not linux: vDSO data page and user side time helpers, see utils::vdso
+pub const AT_PARCH_VDSO_DATA    : usize = 0x1000;
+pub const AT_PARCH_VDSO_TIME_MS : usize = 0x1001;
+pub const AT_PARCH_VDSO_REALTIME: usize = 0x1002;
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum ProcessStatus {
     Init,
     Ready,
     Running,
+    Sleeping,
     Zombie
 }
 
 pub struct ProcessControlBlock {
     pub pid: ProcessID,
-    pub inner: SpinMutex<PCBInner>
+    /// thread group, pid of its leader. Every process leads its own until threads can be created.
+    pub tgid: ProcessID,
+    pub inner: SpinMutex<PCBInner>,
+    /// own mount table after unshare, None for the global one. Not in `inner`, fs lookups take it with that locked.
+    pub mount_ns: SpinMutex<Option<Arc<MountManager>>>,
+    /// parent sleep here in waitpid, woke by exiting children
+    pub child_exit: WaitQueue
 }
 
 impl Eq for ProcessControlBlock {}
@@ -56,17 +82,50 @@ pub struct PCBInner {
     pub status: ProcessStatus,
     pub proc_context: ProcessContext,
     pub entry_point: VirtAddr,
-    pub data_end: VirtAddr,
+    /// start of the heap segment, right after the elf image
+    pub heap_start: VirtAddr,
+    /// program break, end of the heap
+    pub brk: VirtAddr,
     pub files: BTreeMap<FileDescriptor, Arc<dyn File>>,
     pub signal_handler: BTreeMap<SignalNum, VirtAddr>,
     pub pending_signal: VecDeque<SignalNum>,
     pub signal_contexts: Vec<TrapContext>,
     pub signal_enable: BTreeMap<SignalNum, bool>,
+    /// SignalNum::bit set, these stay pending instead of being delivered, for sigprocmask and signalfd
+    pub signal_blocked: u64,
+    /// V extension registers, None until first used
+    pub vector: Option<Box<VectorState>>,
     pub children: LinkedList<Arc<ProcessControlBlock>>,
     pub parent: Option<Weak<ProcessControlBlock>>,
+    /// reparented to init after our parent exited
+    pub orphan: bool,
+    /// session, pid of its leader
+    pub sid: ProcessID,
+    /// process group, pid of its leader
+    pub pgid: ProcessID,
+    pub cred: Credentials,
+    /// bits taken off the permission of files this process creates, kept across fork and exec
+    pub umask: Permission,
     pub exit_code: Option<isize>,
     pub cwd: Path,
-    pub trace_enabled: [bool; MAX_SYSCALL]
+    pub trace_enabled: [bool; MAX_SYSCALL],
+    /// set by SYSCALL_SET_FILTER, violation gets SIGSYS
+    pub syscall_filter: Option<SyscallFilter>,
+    /// what this image's syscalls are taken as
+    pub syscall_abi: SyscallAbi,
+    /// for images exec'ed from now on that aren't branded, set by prctl, kept across fork and exec
+    pub exec_abi: SyscallAbi,
+    pub rlimits: [RLimit; RLIMIT_COUNT],
+    /// timer ticks spent in user mode, for RLIMIT_CPU
+    pub cpu_ticks: usize,
+    /// gave up the hart to wait, or yielded
+    pub voluntary_switches: usize,
+    /// preempted by timer tick
+    pub involuntary_switches: usize,
+    /// WaitQueue we're sleeping on, cleared by whoever wakes us. A queue only wakes pids still sleeping on it.
+    pub wait_channel: Option<usize>,
+    /// what a kernel thread runs, None for user processes, see kthread.rs
+    pub kthread: Option<fn() -> !>,
 }
 
 impl ProcessControlBlock {
@@ -78,7 +137,10 @@ impl ProcessControlBlock {
         let pid = new_pid();
         let res = Arc::new(Self {
             pid,
-            inner: SpinMutex::new("pcb lock", PCBInner::new(mem_layout, elf_file))
+            tgid: pid,
+            inner: SpinMutex::new("pcb lock", PCBInner::new(pid, mem_layout, elf_file)),
+            mount_ns: SpinMutex::new("mount ns", None),
+            child_exit: WaitQueue::new("child exit")
         });
         verbose!("PCB for {:?} Initialized", elf_path);
         Ok(res)
@@ -88,12 +150,87 @@ impl ProcessControlBlock {
         self.inner.acquire()
     }
 
+    pub fn mount_ns(&self) -> Option<Arc<MountManager>> {
+        self.mount_ns.acquire().clone()
+    }
+
     pub fn fork(self: &Arc<Self>) -> Result<Arc<Self>, ErrorNum> {
+        let pid = new_pid();
         Ok(Arc::new(Self {
-            pid: new_pid(),
-            inner: SpinMutex::new("pcb lock", self.get_inner().fork(Arc::downgrade(self))?)
+            pid,
+            tgid: pid,
+            inner: SpinMutex::new("pcb lock", self.get_inner().fork(Arc::downgrade(self))?),
+            mount_ns: SpinMutex::new("mount ns", self.mount_ns()),
+            child_exit: WaitQueue::new("child exit")
         }))
     }
+
+    /// One of our children exited or stopped. SIGCHLD if we handle it, ignored ones are not even queued, so it
+    /// doesn't interrupt anything. Always wakes waitpid.
+    pub fn child_state_changed(&self) {
+        let mut inner = self.get_inner();
+        let handles = inner.handles_sigchld();
+        if handles {
+            let _ = inner.recv_signal(SignalNum::SIGCHLD);
+        }
+        // released before waking, so that waitpid either sees the change, or is already sleeping in child_exit.
+        drop(inner);
+        self.child_exit.wake_all();
+        if handles {
+            wake_up(self.pid);
+        }
+    }
+
+    /// Kernel thread running `entry`, ready to be enqueued. See kthread.rs.
+    pub fn new_kthread(image: Arc<dyn RegularFile>, entry: fn() -> !) -> Arc<Self> {
+        let mut mem_layout = MemLayout::new();
+        mem_layout.map_kernel_stack();
+        let pid = new_pid();
+        let mut inner = PCBInner::new(pid, mem_layout, image);
+        inner.status = ProcessStatus::Ready;
+        inner.proc_context = ProcessContext::kthread();
+        inner.kthread = Some(entry);
+        for enabled in inner.signal_enable.values_mut() {
+            *enabled = false;
+        }
+        Arc::new(Self {
+            pid,
+            tgid: pid,
+            inner: SpinMutex::new("pcb lock", inner),
+            mount_ns: SpinMutex::new("mount ns", None),
+            child_exit: WaitQueue::new("child exit")
+        })
+    }
+
+    /// Child for spawn, with a fresh address space of just the stacks instead of a copy of ours. Caller execs it.
+    pub fn spawn(self: &Arc<Self>) -> Arc<Self> {
+        let mut mem_layout = MemLayout::new();
+        mem_layout.map_proc_stack();
+        let pid = new_pid();
+        Arc::new(Self {
+            pid,
+            tgid: pid,
+            inner: SpinMutex::new("pcb lock", self.get_inner().inherit(Arc::downgrade(self), mem_layout)),
+            mount_ns: SpinMutex::new("mount ns", self.mount_ns()),
+            child_exit: WaitQueue::new("child exit")
+        })
+    }
+}
+
+impl ProcessControlBlock {
+    /// Reaped by its parent, which should hold the last reference. Anything else still holding one keeps the PCB
+    /// and its kernel stack around, so it's reported. Another hart briefly looking at the process list can also
+    /// trip this, hence a warning rather than a panic.
+    pub fn release(self: Arc<Self>) {
+        {
+            let inner = self.get_inner();
+            assert!(inner.files.is_empty() && inner.children.is_empty(), "{:?} reaped without teardown", self.pid);
+        }
+        let count = Arc::strong_count(&self);
+        if count != 1 {
+            warning!("{:?} reaped with {} other references, leaked?", self.pid, count - 1);
+        }
+    }
 }
 
 impl Drop for ProcessControlBlock {
@@ -122,7 +259,8 @@ impl PCBInner {
         }
     }
 
-    pub fn new(mem_layout: MemLayout, elf_file: Arc<dyn RegularFile>) -> Self {
+    /// A process that didn't come from another leads its own session and group.
+    pub fn new(pid: ProcessID, mem_layout: MemLayout, elf_file: Arc<dyn RegularFile>) -> Self {
         let signal_handler = Self::default_hander();
         let signal_enable = Self::defualt_mask();
 
@@ -131,18 +269,35 @@ impl PCBInner {
             mem_layout,
             status: ProcessStatus::Init,
             entry_point: 0.into(),
-            data_end: 0.into(),
+            heap_start: 0.into(),
+            brk: 0.into(),
             proc_context: ProcessContext::new(),
             files: Self::default_fds().unwrap(),
             trace_enabled: Self::default_trace(),
+            syscall_filter: None,
+            syscall_abi: SyscallAbi::Native,
+            exec_abi: SyscallAbi::Native,
             signal_handler,
             signal_contexts: Vec::new(),
             signal_enable,
+            signal_blocked: 0,
+            vector: None,
             children: LinkedList::new(),
             parent: None,
+            orphan: false,
+            sid: pid,
+            pgid: pid,
+            cred: Credentials::root(),
+            umask: Permission::GROUP_W | Permission::OTHER_W,
             exit_code: None,
             cwd: Path::root(),
             pending_signal: VecDeque::new(),
+            rlimits: default_rlimits(),
+            cpu_ticks: 0,
+            voluntary_switches: 0,
+            involuntary_switches: 0,
+            wait_channel: None,
+            kthread: None,
         }
     }
 
@@ -190,6 +345,33 @@ impl PCBInner {
         signal_handler
     }
 
+    /// Installed a SIGCHLD handler of its own?
+    pub fn handles_sigchld(&self) -> bool {
+        self.signal_handler.get(&SignalNum::SIGCHLD) != Self::default_hander().get(&SignalNum::SIGCHLD)
+    }
+
+    /// Drop orphans that already exited, except `except`, which is still on its way out.
+    /// For init, which can't wait for children it doesn't know about.
+    pub fn reap_orphans(&mut self, except: ProcessID) {
+        let reaped_list: Vec<_> = self.children.drain_filter(|child| {
+            if child.pid == except {
+                return false;
+            }
+            let exited = {
+                let child_inner = child.get_inner();
+                child_inner.orphan && child_inner.status == ProcessStatus::Zombie
+            };
+            exited && thread_group(child.tgid).is_empty()
+        }).collect();
+        let reaped = reaped_list.len();
+        for corpse in reaped_list {
+            corpse.release();
+        }
+        if reaped != 0 {
+            info!("Reaped {} orphans.", reaped);
+        }
+    }
+
     pub fn defualt_mask() -> BTreeMap<SignalNum, bool> {
         let mut signal_mask = BTreeMap::new();
         signal_mask.insert(SignalNum::SIGHUP   , true);
@@ -231,24 +413,47 @@ impl PCBInner {
     }
 
     pub fn fork(&mut self, parent: Weak<ProcessControlBlock>) -> Result<Self, ErrorNum> {
-        Ok(Self {
+        let mem_layout = self.mem_layout.fork()?;
+        Ok(self.inherit(parent, mem_layout))
+    }
+
+    /// Child state of fork and spawn, everything but the address space comes from us.
+    fn inherit(&self, parent: Weak<ProcessControlBlock>, mem_layout: MemLayout) -> Self {
+        Self {
             elf_file: self.elf_file.clone(),
-            mem_layout: self.mem_layout.fork()?,
+            mem_layout,
             status: ProcessStatus::Ready,
             proc_context: ProcessContext::new(),
             entry_point: self.entry_point,
-            data_end: self.data_end,
+            heap_start: self.heap_start,
+            brk: self.brk,
             files: self.files.clone(),
             trace_enabled: self.trace_enabled.clone(),
+            syscall_filter: self.syscall_filter,
+            syscall_abi: self.syscall_abi,
+            exec_abi: self.exec_abi,
             signal_contexts: Vec::new(),
             signal_handler: self.signal_handler.clone(),    // save signal handler
             signal_enable: self.signal_enable.clone(),
+            signal_blocked: self.signal_blocked,
+            vector: self.vector.clone(),
             children: LinkedList::new(),
             parent: Some(parent),
+            orphan: false,
+            sid: self.sid,
+            pgid: self.pgid,
+            cred: self.cred,
+            umask: self.umask,
             exit_code: None,
             cwd: self.cwd.clone(),
             pending_signal: VecDeque::new(),    // clear pending signal
-        })
+            rlimits: self.rlimits,
+            cpu_ticks: 0,
+            voluntary_switches: 0,
+            involuntary_switches: 0,
+            wait_channel: None,
+            kthread: None,
+        }
     }
 
     pub fn trap_context(&self) -> &'static mut TrapContext {
@@ -265,12 +470,53 @@ impl PCBInner {
         Ok(())
     }
 
+    fn blocked(&self, signal: SignalNum) -> bool {
+        self.signal_blocked & signal.bit() != 0 && !signal.unblockable()
+    }
+
+    /// A pending signal would be delivered on return to user, so a blocking syscall should give up with EINTR.
+    pub fn has_deliverable_signal(&self) -> bool {
+        self.pending_signal.iter().any(|&s| !self.blocked(s))
+    }
+
+    /// Oldest pending signal that isn't blocked, taken off the queue for delivery.
+    pub fn next_signal(&mut self) -> Option<SignalNum> {
+        let idx = self.pending_signal.iter().position(|&s| !self.blocked(s))?;
+        self.pending_signal.remove(idx)
+    }
+
+    /// Any pending signal in SignalNum::bit set `mask`, blocked or not.
+    pub fn signal_pending_in(&self, mask: u64) -> bool {
+        self.pending_signal.iter().any(|s| mask & s.bit() != 0)
+    }
+
+    /// Oldest pending signal in SignalNum::bit set `mask`, blocked or not, for signalfd.
+    pub fn take_signal(&mut self, mask: u64) -> Option<SignalNum> {
+        let idx = self.pending_signal.iter().position(|s| mask & s.bit() != 0)?;
+        self.pending_signal.remove(idx)
+    }
+
+    /// On exit, while still current: let go of files, user memory and saved state, so a zombie holds
+    /// no more than its exit code and kernel stack until reaped. Files, segments and dirty shared pages are handed
+    /// back to be dropped or synced after the lock, closing a file or writing back a shared mapping may sleep.
+    pub fn teardown(&mut self) -> (BTreeMap<FileDescriptor, Arc<dyn File>>, Vec<ArcSegment>, DirtyPages) {
+        let files = core::mem::take(&mut self.files);
+        self.signal_contexts.clear();
+        self.pending_signal.clear();
+        self.vector = None;
+        let segments = self.mem_layout.take_user_segments().unwrap_or_else(|e| {
+            warning!("Failed to unmap user memory on exit: {:?}", e);
+            Vec::new()
+        });
+        (files, segments, self.mem_layout.take_unsynced())
+    }
+
     pub fn get_file(&self, fd: FileDescriptor) -> Result<Arc<dyn File>, ErrorNum> {
         self.files.get(&fd).ok_or(ErrorNum::EBADFD).cloned()
     }
 
     pub fn register_file(&mut self, file: Arc<dyn File>) -> Result<FileDescriptor, ErrorNum> {
-        if self.files.len() > MAX_FD {
+        if self.files.len() >= self.rlimits[RLIMIT_NOFILE].cur {
             return Err(ErrorNum::EMFILE)
         }
         let mut fd = FileDescriptor::from(0);
@@ -285,6 +531,60 @@ impl PCBInner {
         Ok(fd)
     }
 
+    /// Put `file` at `fd`, EBADF past RLIMIT_NOFILE. Returns what was there, drop it after the pcb lock.
+    pub fn install_file(&mut self, fd: FileDescriptor, file: Arc<dyn File>) -> Result<Option<Arc<dyn File>>, ErrorNum> {
+        if fd.0 >= self.rlimits[RLIMIT_NOFILE].cur.min(MAX_FD) {
+            return Err(ErrorNum::EBADF);
+        }
+        Ok(self.files.insert(fd, file))
+    }
+
+    /// Move the end of the heap, checked against RLIMIT_AS. Can't go below the heap start.
+    pub fn set_brk(&mut self, brk: VirtAddr) -> Result<(), ErrorNum> {
+        if brk < self.heap_start {
+            return Err(ErrorNum::EINVAL);
+        }
+        if brk > self.brk {
+            self.check_as_growth(brk.to_vpn_ceil().0.saturating_sub(self.brk.to_vpn_ceil().0) * PAGE_SIZE)?;
+        }
+        self.mem_layout.set_brk(self.heap_start, brk)?;
+        self.brk = brk;
+        Ok(())
+    }
+
+    /// Check if user address space can grow by `length` bytes under RLIMIT_AS.
+    pub fn check_as_growth(&self, length: usize) -> Result<(), ErrorNum> {
+        if self.mem_layout.user_size().saturating_add(length) > self.rlimits[RLIMIT_AS].cur {
+            Err(ErrorNum::ENOMEM)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// mlock of [head, head + length) under RLIMIT_MEMLOCK, then lock it.
+    pub fn mlock(&mut self, head: VirtAddr, length: usize) -> Result<(), ErrorNum> {
+        let pages = self.mem_layout.locked.len() + self.mem_layout.lock_growth(head, length)?;
+        if pages.saturating_mul(PAGE_SIZE) > self.rlimits[RLIMIT_MEMLOCK].cur {
+            return Err(ErrorNum::ENOMEM);
+        }
+        self.mem_layout.mlock(head, length)
+    }
+
+    /// Called on each timer tick taken from user mode. SIGXCPU every second over soft limit, SIGKILL over hard limit.
+    pub fn account_tick(&mut self) {
+        self.cpu_ticks += 1;
+        if self.cpu_ticks % TIMER_FRAC != 0 {
+            return;
+        }
+        let cpu_sec = self.cpu_ticks / TIMER_FRAC;
+        let limit = self.rlimits[RLIMIT_CPU];
+        if cpu_sec >= limit.max {
+            let _ = self.recv_signal(SignalNum::SIGKILL);
+        } else if cpu_sec >= limit.cur {
+            let _ = self.recv_signal(SignalNum::SIGXCPU);
+        }
+    }
+
     pub fn close_file(&mut self, fd: FileDescriptor) -> Result<(), ErrorNum> {
         self.files.remove(&fd).map(|_| ()).ok_or(ErrorNum::EBADFD)
     }
@@ -294,15 +594,61 @@ impl PCBInner {
         self.register_file(to_dup)
     }
 
-    pub fn exec(&mut self, elf_file: Arc<dyn RegularFile>, args: Vec<Vec<u8>>) -> Result<(), ErrorNum> {
-        assert!(self.status == ProcessStatus::Running, "Exec on process that is not running");
+    /// Replace current image with `elf_file`. `args` and `envs` are NUL terminated strings, E2BIG if they and their
+    /// pointers take more than ARG_MAX. The strings go to the args area below the stack.
+    /// On return to user: a0 = argc, a1 = argv, a2 = envp, a3 = auxv, sp = argv. Linux ABI images get argc at sp
+    /// and argv right above it, as their libc expects.
+    pub fn exec(&mut self, elf_file: Arc<dyn RegularFile>, args: Vec<Vec<u8>>, envs: Vec<Vec<u8>>) -> Result<(), ErrorNum> {
+        assert!(self.status == ProcessStatus::Running || self.status == ProcessStatus::Ready, "Exec on process that is not running");
+        let args_size: usize = args.iter().chain(envs.iter()).map(|s| s.len() + size_of::<VirtAddr>()).sum();
+        if args_size + 2 * size_of::<VirtAddr>() > ARG_MAX {
+            return Err(ErrorNum::E2BIG);
+        }
         self.mem_layout.reset()?;
+        self.mem_layout.map_args();
+        self.mem_layout.randomize_mmap_top();
         self.elf_file = elf_file.clone();
-        let (entry, data) = self.mem_layout.map_elf(elf_file.clone())?;
+        let elf_info = self.mem_layout.map_elf(elf_file.clone(), 0)?;
+        self.syscall_abi = SyscallAbi::of_image(elf_info.os_abi, self.exec_abi);
+        // a sandboxed process must not get out by running something setuid
+        let nosuid = mount_flags(&elf_file.vfs()).contains(MountFlags::NOSUID);
+        let secure = self.cred.exec(elf_file.owner()?, self.syscall_filter.is_none() && !nosuid);
+        let mut auxv = vec![
+            (AT_PHDR, elf_info.phdr.0),
+            (AT_PHENT, elf_info.phent),
+            (AT_PHNUM, elf_info.phnum),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_ENTRY, elf_info.entry.0),
+            (AT_PARCH_VDSO_DATA, VDSO_DATA_ADDR.0),
+            (AT_PARCH_VDSO_TIME_MS, vdso::user_address_of(vdso::vdso_time_ms as usize)),
+            (AT_PARCH_VDSO_REALTIME, vdso::user_address_of(vdso::vdso_realtime as usize)),
+            (AT_UID, self.cred.uid as usize),
+            (AT_EUID, self.cred.euid as usize),
+            (AT_GID, self.cred.gid as usize),
+            (AT_EGID, self.cred.egid as usize),
+            (AT_SECURE, secure as usize),
+        ];
+        // dynamic linked: load interpreter at random base and start from there
+        let start_pc = if let Some(interp_path) = &elf_info.interp {
+            let interp_file = open(interp_path, OpenMode::SYS)?.as_regular()?;
+            let interp_base = ELF_INTERP_BASE.0 + aslr_offset(ELF_INTERP_RAND_PAGES * PAGE_SIZE, PAGE_SIZE);
+            let interp_info = self.mem_layout.map_elf(interp_file, interp_base)?;
+            if interp_info.interp.is_some() {
+                // interpreter must be static
+                return Err(ErrorNum::ELIBBAD);
+            }
+            debug!("Interpreter {:?} loaded at {:#x}", interp_path, interp_base);
+            auxv.push((AT_BASE, interp_base));
+            interp_info.entry
+        } else {
+            elf_info.entry
+        };
+        auxv.push((AT_NULL, 0));
+        self.heap_start = self.mem_layout.map_heap(elf_info.image_end);
+        self.brk = self.heap_start;
         self.mem_layout.do_map();
         verbose!("mem_layout done");
-        self.entry_point = entry;
-        self.data_end = data;
+        self.entry_point = elf_info.entry;
         // preserve file descriptor table
         // self.files = Self::default_fds()?;
         self.trace_enabled = Self::default_trace();
@@ -311,31 +657,57 @@ impl PCBInner {
         self.signal_enable = Self::defualt_mask();
         self.pending_signal.clear();
         
-        let processor_guard = get_processor();
-        processor_guard.push_sum_on();
-        // copy args into user stack
-        let mut ptr = PROC_U_STACK_ADDR + PROC_U_STACK_SIZE;
+        // written through our own pagetable, not the active one, spawn builds a child with this.
+        // strings into the args area, top down
+        let mut ptr = PROC_ARGS_ADDR + ARG_MAX;
         let mut argv = Vec::new();
         for arg in args {
             ptr = ptr - arg.len();
-            unsafe{ptr.write_data(arg)};
+            copy_to_user(&mut self.mem_layout, ptr, &arg)?;
             argv.push(ptr);
         }
         argv.push(0.into());
-        let argv_ptr = ptr - argv.len() * size_of::<VirtAddr>();
+        let mut envp = Vec::new();
+        for env in envs {
+            ptr = ptr - env.len();
+            copy_to_user(&mut self.mem_layout, ptr, &env)?;
+            envp.push(ptr);
+        }
+        envp.push(0.into());
+        // argv[], NULL, envp[], NULL, then auxv pairs right after, on the stack below a randomized top
+        let stack_top = PROC_U_STACK_ADDR + PROC_U_STACK_SIZE - aslr_offset(ASLR_STACK_RAND_PAGES * PAGE_SIZE, size_of::<usize>() * 2);
+        let argc_size = if self.syscall_abi == SyscallAbi::Linux {size_of::<usize>()} else {0};
+        let vec_size = argc_size + (argv.len() + envp.len()) * size_of::<VirtAddr>() + auxv.len() * size_of::<(usize, usize)>();
+        let sp = VirtAddr((stack_top.0 - vec_size) & !(size_of::<usize>() * 2 - 1));
+        if argc_size != 0 {
+            write_user(&mut self.mem_layout, sp, &(argv.len() - 1))?;
+        }
+        let argv_ptr = sp + argc_size;
         ptr = argv_ptr;
         for arg_ptr in argv.iter() {
-            unsafe{ptr.write_volatile(arg_ptr)};
+            write_user(&mut self.mem_layout, ptr, arg_ptr)?;
             ptr = ptr + size_of::<VirtAddr>();
         }
-        processor_guard.pop_sum_on();
+        let envp_ptr = ptr;
+        for env_ptr in envp.iter() {
+            write_user(&mut self.mem_layout, ptr, env_ptr)?;
+            ptr = ptr + size_of::<VirtAddr>();
+        }
+        let auxv_ptr = ptr;
+        for entry in auxv.iter() {
+            write_user(&mut self.mem_layout, ptr, entry)?;
+            ptr = ptr + size_of::<(usize, usize)>();
+        }
 
-        let trap_context = TrapContext::current_ref();
+        self.vector = None;
+        let trap_context = self.trap_context();
         *trap_context = TrapContext::new();
         trap_context.a0 = argv.len() - 1;
         trap_context.a1 = argv_ptr.0;
-        trap_context.sp = argv_ptr.0;
-        trap_context.epc = entry;
+        trap_context.a2 = envp_ptr.0;
+        trap_context.a3 = auxv_ptr.0;
+        trap_context.sp = sp.0;
+        trap_context.epc = start_pc;
 
         Ok(())
     }
@@ -1,4 +1,5 @@
 use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::cell::{RefCell};
 use core::ops::Deref;
 
@@ -9,16 +10,16 @@ use riscv::register::{
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use lazy_static::*;
-use crate::config::{MAX_CPUS, PROC_K_STACK_ADDR, PROC_K_STACK_SIZE};
+use crate::config::{PROC_K_STACK_ADDR, PROC_K_STACK_SIZE};
 use crate::fs::RegularFile;
-use crate::interrupt::{fork_return};
-use crate::mem::{MemLayout, VirtPageNum, MMAPType};
+use crate::interrupt::{fork_return, ipi::{IpiId, register_ipi, send_ipi}};
+use crate::mem::{MemLayout, VirtPageNum, MMAPType, flush_page_magazine};
 use crate::process::ProcessControlBlock;
 use crate::process::pcb::ProcessStatus;
-use crate::utils::{MutexGuard, ErrorNum};
+use crate::utils::{MutexGuard, KernelError, time::get_cycle, trace::{TraceEvent, trace}};
 
 use super::pcb::PCBInner;
-use super::{dequeue, enqueue, INIT_PROCESS};
+use super::{dequeue, enqueue, block, free_current, has_ready, sched_stat, INIT_PROCESS, session::deliver_hangups, timer_queue::run_timers, kthread::kthread_start};
 
 global_asm!(include_str!("swtch.asm"));
 
@@ -50,19 +51,28 @@ impl ProcessContext {
             s_fregs:[0.0; 12]
         }
     }
+
+    /// First switch goes to kthread_start instead of fork_return.
+    pub fn kthread() -> Self {
+        Self {
+            ra: kthread_start as usize,
+            ..Self::new()
+        }
+    }
 }
 
 pub struct ProcessorManager {
-    processor_list: Vec<Arc<Processor>>
+    /// indexed by hart id, None for harts not in device tree
+    processor_list: Vec<Option<Arc<Processor>>>
 }
 
 impl ProcessorManager {
-    pub fn new(processor_list: Vec<Arc<Processor>>) -> Self{
+    pub fn new(processor_list: Vec<Option<Arc<Processor>>>) -> Self{
         Self {processor_list}
     }
 
     pub fn get_processor(&self, hart: usize) -> Arc<Processor> {
-        self.processor_list[hart].clone()
+        self.processor_list.get(hart).and_then(|p| p.clone()).expect("Hart not present")
     }
 }
 
@@ -70,15 +80,74 @@ impl ProcessorManager {
 unsafe impl Sync for ProcessorManager{}
 
 lazy_static!{
+    /// Harts in device tree. Scanned without lock, for PROCESSOR_MANAGER is needed by every lock.
+    static ref PRESENT_HARTS: usize = crate::device::present_hart_mask();
     pub static ref PROCESSOR_MANAGER: ProcessorManager = {
-        let mut cpus = Vec::new();
-        for i in 0..MAX_CPUS {
-            cpus.push(Arc::new(Processor::new(i)))
-        }
+        let max_hart = usize::BITS as usize - PRESENT_HARTS.leading_zeros() as usize;
+        let cpus = (0..max_hart).map(|i| {
+            if hart_present(i) {
+                Some(Arc::new(Processor::new(i)))
+            } else {
+                None
+            }
+        }).collect();
         ProcessorManager::new(cpus)
     };
 }
 
+/// Harts that made it to the scheduler.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn hart_present(hart_id: usize) -> bool {
+    hart_id < usize::BITS as usize && *PRESENT_HARTS & (1 << hart_id) != 0
+}
+
+pub fn present_harts() -> impl Iterator<Item = usize> {
+    (0..usize::BITS as usize).filter(|&i| hart_present(i))
+}
+
+pub fn present_hart_count() -> usize {
+    PRESENT_HARTS.count_ones() as usize
+}
+
+pub fn set_hart_online(hart_id: usize) {
+    ONLINE_HARTS.fetch_or(1 << hart_id, Ordering::SeqCst);
+}
+
+pub fn hart_online(hart_id: usize) -> bool {
+    ONLINE_HARTS.load(Ordering::SeqCst) & (1 << hart_id) != 0
+}
+
+pub fn online_harts() -> impl Iterator<Item = usize> {
+    let mask = ONLINE_HARTS.load(Ordering::SeqCst);
+    (0..usize::BITS as usize).filter(move |&i| mask & (1 << i) != 0)
+}
+
+/// Harts in wfi for lack of work, see Processor::stall.
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static!{
+    /// Does nothing, taking the interrupt is enough to get out of wfi and back to dequeue.
+    static ref KICK_IPI: IpiId = register_ipi("sched kick", || {});
+}
+
+/// Wake an idle hart other than this one for newly ready work, instead of leaving it to the next tick.
+/// Each idle hart is kicked once, the next call goes to another one.
+pub fn kick_idle_hart() {
+    let self_bit = 1usize << get_hart_id();
+    loop {
+        let idle = IDLE_HARTS.load(Ordering::SeqCst) & !self_bit;
+        if idle == 0 {
+            return;
+        }
+        let hart = idle.trailing_zeros() as usize;
+        if IDLE_HARTS.fetch_and(!(1 << hart), Ordering::SeqCst) & (1 << hart) != 0 {
+            let _ = send_ipi(hart, *KICK_IPI);
+            return;
+        }
+    }
+}
+
 /// Struct that repersent CPU's state
 pub struct Processor {
     pub hart_id: usize,
@@ -173,6 +242,12 @@ impl Processor {
         self.inner.borrow().pcb.clone()
     }
 
+    /// Sleeping is only allowed in process context, with no spin lock held.
+    pub fn can_sleep(&self) -> bool {
+        // 1 for the ProcessorGuard used to call this
+        self.current().is_some() && self.get_int_cnt() == 1
+    }
+
     pub fn take_current(&self) -> Option<Arc<ProcessControlBlock>> {
         self.inner.borrow_mut().pcb.take()
     }
@@ -185,7 +260,7 @@ impl Processor {
         self.mem_layout.borrow_mut().as_mut().unwrap().remove_segment_by_vpn(start_vpn).unwrap();
     }
     
-    pub fn do_lazy(&self, vpn: VirtPageNum) -> Result<(), ErrorNum> {
+    pub fn do_lazy(&self, vpn: VirtPageNum) -> Result<(), KernelError> {
         self.mem_layout.borrow_mut().as_mut().unwrap().do_lazy(vpn)
     }
 
@@ -194,6 +269,8 @@ impl Processor {
     pub fn run(&self) -> ! {
         loop {
             intr_on();
+            deliver_hangups();
+            run_timers();
             if let Some(proc) = dequeue() {
                 let mut pcb_inner = proc.get_inner();
                 assert!(pcb_inner.status == ProcessStatus::Ready || pcb_inner.status == ProcessStatus::Init);
@@ -206,6 +283,8 @@ impl Processor {
                 let proc_satp = pcb_inner.mem_layout.pagetable.satp(Some(proc.pid));
                 let scheuler_satp = self.mem_layout.borrow_mut().as_ref().unwrap().pagetable.satp(None);
                 self.inner.borrow_mut().pcb = Some(proc.clone());
+                trace(proc.pid.0, TraceEvent::Switch);
+                sched_stat::count_switch();
                 // 1st return form scheduler, pcb_inner is locked for fork_ret();
                 // 2nd+ return from scheduler, pcb_inner is locked for to_scheduler().
                 unsafe {
@@ -218,14 +297,26 @@ impl Processor {
                 // must switched back by to_scheduler, locked by suspend_switch or exit_switch
                 pcb_inner.check_intergrity();
             } else {
+                flush_page_magazine();
                 self.stall();
             }
         }
     }
 
     pub fn stall(&self) {
+        let self_bit = 1usize << self.hart_id;
+        IDLE_HARTS.fetch_or(self_bit, Ordering::SeqCst);
+        // enqueue between our dequeue and the bit above didn't see us idle, look again before sleeping.
+        // Interrupts off from the look to wfi, one coming in between stays pending and wfi returns at once.
+        intr_off();
+        if !has_ready() {
+            let start = get_cycle();
+            unsafe { asm!("wfi") };
+            sched_stat::count_idle(get_cycle() - start);
+        }
+        // take what woke us
         intr_on();
-        unsafe { asm!("wfi") };
+        IDLE_HARTS.fetch_and(!self_bit, Ordering::SeqCst);
     }
 
     pub fn to_scheduler(&self, mut proc_inner: MutexGuard<PCBInner>) {
@@ -242,7 +333,8 @@ impl Processor {
         proc_inner.check_intergrity();
     }
     
-    pub fn suspend_switch(&self) {
+    /// Back to the ready queue. `voluntary` if the process yields, not preempted, for sched_stat.
+    pub fn suspend_switch(&self, voluntary: bool) {
         let processor = get_processor();
         let int_ena = processor.get_int_ena();
         let int_cnt = processor.get_int_cnt();
@@ -250,6 +342,11 @@ impl Processor {
         let process = self.take_current().expect("Suspend switch need running process to work");
         let mut pcb_inner = process.get_inner();
         pcb_inner.status = ProcessStatus::Ready;
+        if voluntary {
+            pcb_inner.voluntary_switches += 1;
+        } else {
+            pcb_inner.involuntary_switches += 1;
+        }
         enqueue(process.clone());
 
         // pcb_inner was locked for scheduler
@@ -261,6 +358,53 @@ impl Processor {
         processor.set_int_ena(int_ena);
     }
 
+    /// Put current process to sleep on `channel`, releasing `guard` only after the process is marked as sleeping.
+    /// Whoever wakes it up must acquire `guard`'s lock first, so wakeups won't get lost.
+    pub fn sleep_switch<T>(&self, guard: MutexGuard<T>, channel: usize) {
+        let processor = get_processor();
+        let int_ena = processor.get_int_ena();
+        // the guard will be released before switching
+        let int_cnt = processor.get_int_cnt() - 1;
+
+        let process = self.take_current().expect("Sleep switch need running process to work");
+        let mut pcb_inner = process.get_inner();
+        pcb_inner.status = ProcessStatus::Sleeping;
+        pcb_inner.wait_channel = Some(channel);
+        pcb_inner.voluntary_switches += 1;
+        block(process.clone());
+        drop(guard);
+
+        // pcb_inner was locked for scheduler
+        drop(processor);
+        self.to_scheduler(pcb_inner);
+
+        let processor = get_processor();
+        processor.set_int_cnt(int_cnt);
+        processor.set_int_ena(int_ena);
+    }
+
+    /// sleep_switch on nothing but wake_up, e.g. until a signal arrives. `pcb_inner` is the current pcb's own lock,
+    /// held until switched out, so a sender locking it to queue the signal can't slip in before we sleep.
+    pub fn pause_switch(&self, mut pcb_inner: MutexGuard<PCBInner>) {
+        let processor = get_processor();
+        let int_ena = processor.get_int_ena();
+        // the guard will be released when switched back
+        let int_cnt = processor.get_int_cnt() - 1;
+
+        let process = self.take_current().expect("Pause switch need running process to work");
+        pcb_inner.status = ProcessStatus::Sleeping;
+        pcb_inner.wait_channel = None;
+        pcb_inner.voluntary_switches += 1;
+        block(process);
+
+        drop(processor);
+        self.to_scheduler(pcb_inner);
+
+        let processor = get_processor();
+        processor.set_int_cnt(int_cnt);
+        processor.set_int_ena(int_ena);
+    }
+
     pub fn exit_switch(&self, exit_code: isize) -> ! {
         // get init first, to avoid deadlock
         // in waitpid, we always get self.inner first, then get childres;
@@ -268,18 +412,38 @@ impl Processor {
         // that is, parent first, children last.
         let mut init_inner = INIT_PROCESS.get_inner();
         let proc = self.take_current().unwrap();
+        trace(proc.pid.0, TraceEvent::Exit { code: exit_code });
         let mut pcb_inner = proc.get_inner();
         pcb_inner.status = ProcessStatus::Zombie;
         pcb_inner.exit_code = Some(exit_code);
+        // not running anymore, nor will be
+        free_current();
 
         for child in &pcb_inner.children {
-            child.get_inner().parent = Some(Arc::downgrade(&INIT_PROCESS));
+            let mut child_inner = child.get_inner();
+            child_inner.parent = Some(Arc::downgrade(&INIT_PROCESS));
+            child_inner.orphan = true;
             init_inner.children.push_back(child.clone());
         }
         
+        let reparented = !pcb_inner.children.is_empty();
+        let parent = pcb_inner.parent.clone();
         pcb_inner.children.clear();
         drop(pcb_inner);
+        // init with default SIGCHLD only waits for what it forked, orphans exited so far are reaped here instead
+        if !init_inner.handles_sigchld() {
+            init_inner.reap_orphans(proc.pid);
+        }
         drop(init_inner);
+
+        if let Some(parent) = parent.and_then(|p| p.upgrade()) {
+            parent.child_state_changed();
+        }
+        if reparented {
+            // reparented children might be zombies already
+            drop(INIT_PROCESS.get_inner());
+            INIT_PROCESS.child_exit.wake_all();
+        }
         // deduct proc's refcnt for it will not be dropped.
         // Arc's final drop will not happen here, for parent of this process must held ref to this process, so it's safe to do so.
         unsafe {
@@ -0,0 +1,58 @@
+use crate::{config::MAX_FD, utils::ErrorNum};
+
+// resource number, same value as linux
+pub const RLIMIT_CPU    : usize = 0;
+pub const RLIMIT_NOFILE : usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS     : usize = 9;
+pub const RLIMIT_COUNT  : usize = 16;
+
+pub const RLIM_INFINITY : usize = usize::MAX;
+
+/// Soft and hard limit of one resource, layout shared with user.
+/// CPU in seconds, MEMLOCK in bytes, NOFILE in number of fds.
+/// AS in bytes of reserved user pages, lazy ones included from when they're mapped, not just resident ones.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+impl RLimit {
+    pub const fn infinity() -> Self {
+        Self {
+            cur: RLIM_INFINITY,
+            max: RLIM_INFINITY
+        }
+    }
+
+    /// cur over RLIM_INFINITY or max is invalid, max can only grow up to `ceiling`.
+    pub fn validate(&self, old: &RLimit, ceiling: usize) -> Result<(), ErrorNum> {
+        if self.cur > self.max || self.max > ceiling {
+            return Err(ErrorNum::EINVAL);
+        }
+        // no privilege model yet, so nobody may raise hard limit
+        if self.max > old.max {
+            return Err(ErrorNum::EPERM);
+        }
+        Ok(())
+    }
+}
+
+pub fn default_rlimits() -> [RLimit; RLIMIT_COUNT] {
+    let mut res = [RLimit::infinity(); RLIMIT_COUNT];
+    res[RLIMIT_NOFILE] = RLimit {
+        cur: MAX_FD,
+        max: MAX_FD
+    };
+    res
+}
+
+/// Upper bound of hard limit for each resource.
+pub fn rlimit_ceiling(resource: usize) -> usize {
+    match resource {
+        RLIMIT_NOFILE => MAX_FD,
+        _ => RLIM_INFINITY
+    }
+}
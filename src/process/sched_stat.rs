@@ -0,0 +1,74 @@
+//! Scheduler counters for /proc/schedstat: per hart context switches, idle time and ready queue length samples.
+//! Per process voluntary and involuntary switch counts live in PCBInner.
+
+use alloc::string::String;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::{CLOCK_FREQ, MAX_CPUS};
+
+use super::{get_hart_id, present_harts, process_list};
+
+struct HartSchedStat {
+    /// processes switched in
+    switches: AtomicU64,
+    idle_cycles: AtomicU64,
+    /// ready queue length summed over each dequeue, with how many times
+    queue_len_sum: AtomicU64,
+    queue_samples: AtomicU64,
+}
+
+const HART_STAT_INIT: HartSchedStat = HartSchedStat {
+    switches: AtomicU64::new(0),
+    idle_cycles: AtomicU64::new(0),
+    queue_len_sum: AtomicU64::new(0),
+    queue_samples: AtomicU64::new(0),
+};
+
+static HART_SCHED_STATS: [HartSchedStat; MAX_CPUS] = [HART_STAT_INIT; MAX_CPUS];
+
+fn this_hart() -> &'static HartSchedStat {
+    &HART_SCHED_STATS[get_hart_id()]
+}
+
+pub fn count_switch() {
+    this_hart().switches.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn count_idle(cycles: usize) {
+    this_hart().idle_cycles.fetch_add(cycles as u64, Ordering::Relaxed);
+}
+
+/// Ready queue length as a hart looks for work.
+pub fn sample_queue(len: usize) {
+    let stat = this_hart();
+    stat.queue_len_sum.fetch_add(len as u64, Ordering::Relaxed);
+    stat.queue_samples.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Harts first, then processes.
+pub fn report() -> String {
+    let mut res = String::from("# hart switches idle_ms queue_samples queue_len_avg\n");
+    for hart in present_harts().filter(|hart| *hart < MAX_CPUS) {
+        let stat = &HART_SCHED_STATS[hart];
+        let samples = stat.queue_samples.load(Ordering::Relaxed);
+        let queue_len_avg = if samples == 0 {
+            0.0
+        } else {
+            stat.queue_len_sum.load(Ordering::Relaxed) as f64 / samples as f64
+        };
+        res += &format!(
+            "hart{} {} {} {} {:.2}\n",
+            hart,
+            stat.switches.load(Ordering::Relaxed),
+            stat.idle_cycles.load(Ordering::Relaxed) / (CLOCK_FREQ as u64 / 1000),
+            samples,
+            queue_len_avg
+        );
+    }
+    res += "# pid voluntary involuntary\n";
+    for proc in process_list() {
+        let proc_inner = proc.get_inner();
+        res += &format!("{} {} {}\n", proc.pid.0, proc_inner.voluntary_switches, proc_inner.involuntary_switches);
+    }
+    res
+}
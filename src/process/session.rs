@@ -0,0 +1,73 @@
+//! Sessions and their controlling terminal.
+//!
+//! A session is every process sharing a `sid`, started by setsid and led by the process whose pid it is. Process
+//! groups split a session further, the terminal's foreground group is the one it talks to. The leader can make a
+//! terminal no other session controls its controlling terminal with TTY_SET_CTTY; /dev/tty opens that terminal for
+//! anyone in the session, and a hangup of it sends SIGHUP to the whole session.
+
+use alloc::{collections::BTreeMap, sync::{Arc, Weak}, vec::Vec};
+use lazy_static::*;
+
+use crate::{fs::{File, OpenMode}, utils::{ErrorNum, Mutex, SpinMutex}};
+
+use super::{ProcessControlBlock, ProcessID, SignalNum, process_list, wake_up};
+
+/// A terminal that can become a controlling one.
+pub trait Terminal: Send + Sync {
+    /// Another file on the terminal, what /dev/tty opens to.
+    fn open_tty(&self, mode: OpenMode) -> Result<Arc<dyn File>, ErrorNum>;
+}
+
+lazy_static!{
+    static ref CTTYS: SpinMutex<BTreeMap<ProcessID, Weak<dyn Terminal>>> = SpinMutex::new("ctty table", BTreeMap::new());
+    /// Sessions whose terminal hung up, SIGHUP'd from the scheduler. A terminal is often dropped with some pcb
+    /// locked (close, reaping), signaling right there could deadlock.
+    static ref HANGUPS: SpinMutex<Vec<ProcessID>> = SpinMutex::new("hangup list", Vec::new());
+}
+
+/// EPERM if session `sid` already has a live one.
+pub fn set_ctty(sid: ProcessID, tty: Weak<dyn Terminal>) -> Result<(), ErrorNum> {
+    let mut cttys = CTTYS.acquire();
+    if cttys.get(&sid).and_then(|old| old.upgrade()).is_some() {
+        return Err(ErrorNum::EPERM);
+    }
+    cttys.insert(sid, tty);
+    Ok(())
+}
+
+/// ENXIO if the session has none, or it's gone.
+pub fn get_ctty(sid: ProcessID) -> Result<Arc<dyn Terminal>, ErrorNum> {
+    CTTYS.acquire().get(&sid).and_then(|tty| tty.upgrade()).ok_or(ErrorNum::ENXIO)
+}
+
+/// Controlling terminal of `sid` is gone, the session gets SIGHUP soon.
+pub fn hang_up_session(sid: ProcessID) {
+    CTTYS.acquire().remove(&sid);
+    HANGUPS.acquire().push(sid);
+}
+
+/// Called by the scheduler with nothing locked.
+pub fn deliver_hangups() {
+    let sids = core::mem::take(&mut *HANGUPS.acquire());
+    for sid in sids {
+        signal_session(sid, SignalNum::SIGHUP);
+    }
+}
+
+pub fn session_members(sid: ProcessID) -> Vec<Arc<ProcessControlBlock>> {
+    process_list().into_iter().filter(|proc| proc.get_inner().sid == sid).collect()
+}
+
+/// Some process of session `sid` is in group `pgid`.
+pub fn pgrp_in_session(pgid: ProcessID, sid: ProcessID) -> bool {
+    session_members(sid).iter().any(|proc| proc.get_inner().pgid == pgid)
+}
+
+/// `signal` to every member that has it enabled, woken so blocking syscalls see it.
+pub fn signal_session(sid: ProcessID, signal: SignalNum) {
+    for proc in session_members(sid) {
+        if proc.get_inner().recv_signal(signal).is_ok() {
+            wake_up(proc.pid);
+        }
+    }
+}
@@ -36,6 +36,23 @@ enum_with_tryfrom_usize!{
 	}
 }
 
+impl SignalNum {
+    /// Stops the process under default handling.
+    pub fn is_stop(&self) -> bool {
+        matches!(self, Self::SIGSTOP | Self::SIGTSTP | Self::SIGTTIN | Self::SIGTTOU)
+    }
+
+    /// Bit of it in a signal set, bit 0 for signal 1.
+    pub fn bit(&self) -> u64 {
+        1 << (*self as usize - 1)
+    }
+
+    /// Always delivered, can't be blocked.
+    pub fn unblockable(&self) -> bool {
+        matches!(self, Self::SIGKILL | Self::SIGSTOP)
+    }
+}
+
 impl core::fmt::Display for SignalNum {
 	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
 		core::fmt::Debug::fmt(self, f)
@@ -0,0 +1,24 @@
+/// e_ident[EI_OSABI] of an image built for Linux
+pub const ELFOSABI_LINUX: u8 = 3;
+
+enum_with_tryfrom_usize!{
+    /// Which syscall numbers and struct layouts a process speaks, see syscall::linux.
+    #[repr(usize)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum SyscallAbi {
+        Native  = 0,
+        /// rv64 Linux, for binaries built against a standard libc
+        Linux   = 1,
+    }
+}
+
+impl SyscallAbi {
+    /// ABI an image runs with: Linux if it's branded so, else what the exec'ing process asked for.
+    pub fn of_image(os_abi: u8, requested: SyscallAbi) -> Self {
+        if os_abi == ELFOSABI_LINUX {
+            SyscallAbi::Linux
+        } else {
+            requested
+        }
+    }
+}
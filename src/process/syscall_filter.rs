@@ -0,0 +1,48 @@
+use static_assertions::const_assert;
+
+use crate::{config::MAX_SYSCALL, syscall::syscall_num::{SYSCALL_EXIT, SYSCALL_EXIT_GROUP, SYSCALL_SIGRETURN}, utils::ErrorNum};
+
+// mode of SYSCALL_SET_FILTER
+pub const FILTER_MODE_ALLOW : usize = 0;   // bitmap lists allowed syscalls, everything else denied
+pub const FILTER_MODE_DENY  : usize = 1;   // bitmap lists denied syscalls, everything else allowed
+
+/// Most bytes of the bitmap user may pass, one bit per syscall id. The length is passed along with it, so the
+/// bitmap doesn't change size when syscalls are added. Ids past its end are unlisted.
+pub const FILTER_BITMAP_MAX: usize = 64;
+// every syscall can be listed
+const_assert!(MAX_SYSCALL <= FILTER_BITMAP_MAX * 8);
+
+/// Per process syscall filter. Installed once and never changed after, inherited by fork and kept across exec,
+/// so a sandboxed process can't get out by exec'ing something else.
+#[derive(Clone, Copy)]
+pub struct SyscallFilter {
+    allowed: [bool; MAX_SYSCALL],
+}
+
+impl SyscallFilter {
+    pub fn from_bitmap(mode: usize, bitmap: &[u8]) -> Result<Self, ErrorNum> {
+        let listed_allowed = match mode {
+            FILTER_MODE_ALLOW => true,
+            FILTER_MODE_DENY => false,
+            _ => return Err(ErrorNum::EINVAL)
+        };
+        if bitmap.len() > FILTER_BITMAP_MAX {
+            return Err(ErrorNum::EINVAL);
+        }
+        let mut allowed = [!listed_allowed; MAX_SYSCALL];
+        for (id, allow) in allowed.iter_mut().enumerate() {
+            if bitmap.get(id / 8).map_or(false, |byte| byte & (1 << (id % 8)) != 0) {
+                *allow = listed_allowed;
+            }
+        }
+        // a killed sandbox still have to get out of its SIGSYS handler and exit
+        allowed[SYSCALL_EXIT] = true;
+        allowed[SYSCALL_EXIT_GROUP] = true;
+        allowed[SYSCALL_SIGRETURN] = true;
+        Ok(Self { allowed })
+    }
+
+    pub fn allows(&self, syscall_id: usize) -> bool {
+        syscall_id < MAX_SYSCALL && self.allowed[syscall_id]
+    }
+}
@@ -0,0 +1,63 @@
+//! Kernel timers. Whatever wants telling at some point in time adds itself with a deadline in cycles, expired ones
+//! are fired from the scheduler with nothing locked, so no finer than a tick (1 / TIMER_FRAC second) and later if
+//! every hart is busy with user code.
+
+use alloc::{collections::BTreeMap, sync::Weak};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+use crate::{config::CLOCK_FREQ, utils::{Mutex, SpinMutex, time::{get_cycle, MILLI_PER_SECOND}}};
+
+pub trait TimerTarget: Send + Sync {
+    /// `deadline` it was added with has passed. Called with nothing locked.
+    fn expire(&self, deadline: usize);
+}
+
+/// What add_timer returns, to cancel it with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerKey(usize, usize);
+
+lazy_static!{
+    /// by deadline, then by order added
+    static ref TIMERS: SpinMutex<BTreeMap<(usize, usize), Weak<dyn TimerTarget>>> = SpinMutex::new("timer queue", BTreeMap::new());
+}
+
+static NEXT_TIMER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn ms_to_cycles(ms: usize) -> usize {
+    ms.saturating_mul(CLOCK_FREQ / MILLI_PER_SECOND)
+}
+
+pub fn cycles_to_ms(cycles: usize) -> usize {
+    cycles / (CLOCK_FREQ / MILLI_PER_SECOND)
+}
+
+/// Fires once, a target gone by then is skipped.
+pub fn add_timer(deadline: usize, target: Weak<dyn TimerTarget>) -> TimerKey {
+    let key = (deadline, NEXT_TIMER.fetch_add(1, Ordering::Relaxed));
+    TIMERS.acquire().insert(key, target);
+    TimerKey(key.0, key.1)
+}
+
+/// Nothing if it fired already.
+pub fn cancel_timer(key: TimerKey) {
+    TIMERS.acquire().remove(&(key.0, key.1));
+}
+
+/// Called by the scheduler with nothing locked.
+pub fn run_timers() {
+    let now = get_cycle();
+    let expired = {
+        let mut timers = TIMERS.acquire();
+        if timers.keys().next().map_or(true, |&(deadline, _)| deadline > now) {
+            return;
+        }
+        let later = timers.split_off(&(now + 1, 0));
+        core::mem::replace(&mut *timers, later)
+    };
+    for ((deadline, _), target) in expired {
+        if let Some(target) = target.upgrade() {
+            target.expire(deadline);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! V extension state of user processes. Kernel is built without V, so the registers only change in user mode:
+//! saved in user_trap when sstatus.VS says user wrote them, loaded in trap_return when this hart last held
+//! someone else's. Off until a process first uses it, the trap that causes gets it an area sized by vlenb.
+//! Signal handlers share the state with the code they interrupted, it's not part of the saved TrapContext.
+
+use alloc::{vec, vec::Vec};
+use core::{arch::asm, sync::atomic::{AtomicUsize, Ordering}};
+use lazy_static::*;
+
+use crate::{config::MAX_CPUS, process::get_hart_id};
+
+/// sstatus.VS
+const SSTATUS_VS        : usize = 0x600;
+const SSTATUS_VS_INITIAL: usize = 0x200;
+const SSTATUS_VS_CLEAN  : usize = 0x400;
+const SSTATUS_VS_DIRTY  : usize = 0x600;
+
+lazy_static!{
+    /// From DTB ISA string, every hart has to have it.
+    static ref HAS_VECTOR: bool = crate::device::harts_have_ext("v");
+}
+
+pub fn has_vector() -> bool {
+    *HAS_VECTOR
+}
+
+const OWNER_INIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Stamp of the state each hart's registers hold, 0 for none.
+static HART_OWNER: [AtomicUsize; MAX_CPUS] = [OWNER_INIT; MAX_CPUS];
+/// Every save gets a new stamp, so a copy left on another hart is never taken for the current one.
+static NEXT_STAMP: AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Clone)]
+pub struct VectorState {
+    vl: usize,
+    vtype: usize,
+    vstart: usize,
+    vcsr: usize,
+    /// bytes of one register
+    vlenb: usize,
+    /// v0 to v31
+    regs: Vec<u8>,
+    stamp: usize,
+}
+
+fn set_vs(state: usize) {
+    unsafe {
+        asm!("csrc sstatus, {0}", in(reg) SSTATUS_VS);
+        asm!("csrs sstatus, {0}", in(reg) state);
+    }
+}
+
+/// User wrote the registers since trap_return.
+pub fn vector_dirty() -> bool {
+    let sstatus: usize;
+    unsafe {asm!("csrr {0}, sstatus", out(reg) sstatus)};
+    sstatus & SSTATUS_VS == SSTATUS_VS_DIRTY
+}
+
+/// Leave V off for a process that doesn't use it, its first vector instruction traps.
+pub fn vector_off() {
+    set_vs(0);
+}
+
+impl VectorState {
+    /// Zeroed registers, vtype with vill set like after reset. None if harts don't have V.
+    pub fn new() -> Option<Self> {
+        if !has_vector() {
+            return None;
+        }
+        set_vs(SSTATUS_VS_INITIAL);
+        let vlenb: usize;
+        unsafe {asm!(".option push", ".option arch, +v", "csrr {0}, vlenb", ".option pop", out(reg) vlenb)};
+        Some(Self {
+            vl: 0,
+            vtype: 1 << (usize::BITS - 1),
+            vstart: 0,
+            vcsr: 0,
+            vlenb,
+            regs: vec![0; 32 * vlenb],
+            stamp: NEXT_STAMP.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    /// Take the registers from this hart, in user_trap while VS is dirty. Leaves VS clean.
+    pub fn save(&mut self) {
+        let group = 8 * self.vlenb;
+        unsafe {
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "csrr {vl}, vl",
+                "csrr {vtype}, vtype",
+                "csrr {vstart}, vstart",
+                "csrr {vcsr}, vcsr",
+                "vs8r.v v0, ({p})",
+                "add {p}, {p}, {group}",
+                "vs8r.v v8, ({p})",
+                "add {p}, {p}, {group}",
+                "vs8r.v v16, ({p})",
+                "add {p}, {p}, {group}",
+                "vs8r.v v24, ({p})",
+                ".option pop",
+                p = inout(reg) self.regs.as_mut_ptr() => _,
+                group = in(reg) group,
+                vl = out(reg) self.vl,
+                vtype = out(reg) self.vtype,
+                vstart = out(reg) self.vstart,
+                vcsr = out(reg) self.vcsr,
+            );
+        }
+        self.stamp = NEXT_STAMP.fetch_add(1, Ordering::Relaxed);
+        HART_OWNER[get_hart_id()].store(self.stamp, Ordering::Relaxed);
+        set_vs(SSTATUS_VS_CLEAN);
+    }
+
+    /// Put the registers on this hart for returning to user, skipped if it still holds them. Leaves VS clean.
+    pub fn restore(&self) {
+        let owner = &HART_OWNER[get_hart_id()];
+        if owner.load(Ordering::Relaxed) == self.stamp {
+            set_vs(SSTATUS_VS_CLEAN);
+            return;
+        }
+        set_vs(SSTATUS_VS_INITIAL);
+        let group = 8 * self.vlenb;
+        unsafe {
+            // whole register loads ignore vl and vtype, and clear vstart, so those go last
+            asm!(
+                ".option push",
+                ".option arch, +v",
+                "vl8re8.v v0, ({p})",
+                "add {p}, {p}, {group}",
+                "vl8re8.v v8, ({p})",
+                "add {p}, {p}, {group}",
+                "vl8re8.v v16, ({p})",
+                "add {p}, {p}, {group}",
+                "vl8re8.v v24, ({p})",
+                "vsetvl x0, {vl}, {vtype}",
+                "csrw vstart, {vstart}",
+                "csrw vcsr, {vcsr}",
+                ".option pop",
+                p = inout(reg) self.regs.as_ptr() => _,
+                group = in(reg) group,
+                vl = in(reg) self.vl,
+                vtype = in(reg) self.vtype,
+                vstart = in(reg) self.vstart,
+                vcsr = in(reg) self.vcsr,
+            );
+        }
+        owner.store(self.stamp, Ordering::Relaxed);
+        set_vs(SSTATUS_VS_CLEAN);
+    }
+}
@@ -0,0 +1,90 @@
+use alloc::{collections::VecDeque, sync::{Arc, Weak}, vec::Vec};
+
+use crate::utils::{SpinMutex, Mutex, MutexGuard};
+
+use super::{ProcessID, Waker, get_processor, wake_up_on};
+
+/// Queue of sleeping processes, replacing suspend_switch polling loops.
+/// Waker should hold the lock passed to sleep_on when changing the condition,
+/// or at least acquire & release it before waking.
+/// Wakers registered for poll are woken by any wake, once.
+/// Sleepers woken by some other path stay queued, so entries are checked against the pcb's wait_channel on wake.
+pub struct WaitQueue {
+    queue: SpinMutex<VecDeque<ProcessID>>,
+    pollers: SpinMutex<Vec<Weak<Waker>>>,
+}
+
+impl WaitQueue {
+    pub fn new(name: &str) -> Self {
+        Self {
+            queue: SpinMutex::new(name, VecDeque::new()),
+            pollers: SpinMutex::new(name, Vec::new()),
+        }
+    }
+
+    pub fn register_waker(&self, waker: &Arc<Waker>) {
+        let mut pollers = self.pollers.acquire();
+        pollers.retain(|w| w.strong_count() != 0);
+        pollers.push(Arc::downgrade(waker));
+    }
+
+    fn channel(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn wake_pollers(&self) {
+        let pollers = core::mem::take(&mut *self.pollers.acquire());
+        for waker in pollers.iter().filter_map(Weak::upgrade) {
+            waker.wake();
+        }
+    }
+
+    /// Put current process to sleep. `guard` is released after current process is in queue,
+    /// so no wakeup will be lost. Caller must not hold any other spin lock. `guard` may be the current pcb's own,
+    /// it's released before the pcb is locked again to mark it sleeping.
+    /// Returns immediately in scheduler context, caller should spin on condition instead.
+    /// Spurious wakeup is possible, always recheck condition.
+    pub fn sleep_on<T>(&self, guard: MutexGuard<T>) {
+        let current = get_processor().current();
+        if let Some(proc) = current {
+            let mut queue = self.queue.acquire();
+            // may still be here from a sleep some other path woke us from
+            if !queue.contains(&proc.pid) {
+                queue.push_back(proc.pid);
+            }
+            drop(proc);
+            drop(guard);
+            // wakers take the queue lock before checking wait_channel, which sleep_switch sets with it still held
+            get_processor().sleep_switch(queue, self.channel());
+        } else {
+            drop(guard);
+        }
+    }
+
+    pub fn wake_one(&self) -> bool {
+        self.wake_pollers();
+        let mut queue = self.queue.acquire();
+        while let Some(pid) = queue.pop_front() {
+            if wake_up_on(pid, self.channel()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn wake_all(&self) -> usize {
+        self.wake_pollers();
+        let mut queue = self.queue.acquire();
+        let mut count = 0;
+        while let Some(pid) = queue.pop_front() {
+            if wake_up_on(pid, self.channel()) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.acquire().is_empty()
+    }
+}
@@ -0,0 +1,43 @@
+//! Waker, for waiting on several files at once. Register one with each file (File::register_waker), recheck
+//! readiness, then `wait`. Registrations are one shot, register again after each wakeup.
+
+use alloc::sync::Arc;
+
+use crate::utils::{SpinMutex, Mutex};
+
+use super::WaitQueue;
+
+pub struct Waker {
+    woken: SpinMutex<bool>,
+    waiters: WaitQueue,
+}
+
+impl Waker {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            woken: SpinMutex::new("waker", false),
+            waiters: WaitQueue::new("waker"),
+        })
+    }
+
+    /// Sleep until woken, returns at once if it was since the last wait. A signal sent to the waiting process
+    /// wakes it too, so recheck what's waited for.
+    pub fn wait(&self) {
+        let woken = self.woken.acquire();
+        if *woken {
+            drop(woken);
+        } else {
+            self.waiters.sleep_on(woken);
+        }
+        *self.woken.acquire() = false;
+    }
+
+    pub fn wake(&self) {
+        *self.woken.acquire() = true;
+        self.waiters.wake_all();
+    }
+
+    pub fn woken(&self) -> bool {
+        *self.woken.acquire()
+    }
+}
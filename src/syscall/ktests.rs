@@ -0,0 +1,67 @@
+//! Self tests for syscall latency stats, the Linux ABI translation and waitpid, see utils::ktest.
+
+use alloc::sync::Arc;
+
+use crate::{config::{CLOCK_FREQ, MAX_SYSCALL}, fs::{OpenMode, anon_file}, process::{ProcessControlBlock, enqueue, get_processor}, utils::{ErrorNum, ktest::KTestResult}};
+
+use super::{linux::{linux_errno, mmap_flag, open_mode}, stats::{LATENCY_BUCKETS, latency_bucket, record, report}, syscall::{sys_exit, wait_child}, types::MMAPFlag};
+
+fn syscall_latency_buckets() -> KTestResult {
+    let us = CLOCK_FREQ / 1_000_000;
+    kassert!(latency_bucket(0) == 0);
+    kassert!(latency_bucket(us - 1) == 0);
+    kassert!(latency_bucket(us) == 1);
+    kassert!(latency_bucket(2 * us - 1) == 1);
+    kassert!(latency_bucket(2 * us) == 2);
+    kassert!(latency_bucket(1000 * us) == 10);
+    kassert!(latency_bucket(usize::MAX) == LATENCY_BUCKETS - 1);
+    Ok(())
+}
+ktest!(syscall_latency_buckets, syscall_latency_buckets);
+
+fn syscall_stats_overflow() -> KTestResult {
+    // out of range numbers still count, in a row of their own
+    record(MAX_SYSCALL, 0);
+    record(usize::MAX, 0);
+    kassert!(report().lines().any(|line| line.starts_with("<other>")));
+    Ok(())
+}
+ktest!(syscall_stats_overflow, syscall_stats_overflow);
+
+fn linux_abi_translation() -> KTestResult {
+    kassert!(open_mode(0o0).unwrap() == OpenMode::READ);
+    kassert!(open_mode(0o2 | 0o400000).unwrap() == OpenMode::READ | OpenMode::WRITE | OpenMode::NO_FOLLOW);
+    kassert!(open_mode(0o3).is_err());
+    kassert!(mmap_flag(0x01).unwrap() == MMAPFlag::SHARED);
+    kassert!(mmap_flag(0x02 | 0x20 | 0x10).unwrap() == MMAPFlag::PRIVATE | MMAPFlag::ANONYMOUS | MMAPFlag::FIXED);
+    kassert!(mmap_flag(0x20).is_err());
+    kassert!(linux_errno(ErrorNum::EBADFD) == ErrorNum::EBADF);
+    kassert!(linux_errno(ErrorNum::ENOTINTC) == ErrorNum::ENODEV);
+    kassert!(linux_errno(ErrorNum::ENOENT) == ErrorNum::ENOENT);
+    Ok(())
+}
+ktest!(linux_abi_translation, linux_abi_translation);
+
+fn waitpid_child_exit() -> ! {
+    let _ = sys_exit(42);
+    unreachable!()
+}
+
+/// Parent sleeps in waitpid, with its own pcb lock as the sleep guard, until a child exits.
+fn waitpid_sleep_wake() -> KTestResult {
+    // boot time runs have nothing to put to sleep
+    let parent = match get_processor().current() {
+        Some(parent) => parent,
+        None => return Ok(()),
+    };
+    let child = ProcessControlBlock::new_kthread(anon_file("/[ktest_child]".into()), waitpid_child_exit);
+    let pid = child.pid.0;
+    child.get_inner().parent = Some(Arc::downgrade(&parent));
+    parent.get_inner().children.push_back(child.clone());
+    drop(parent);
+    // kernel code isn't preempted, so the child only runs once we sleep, on this hart at least
+    enqueue(child);
+    kassert!(wait_child(pid as isize, false) == Ok(Some((pid, 42))));
+    Ok(())
+}
+ktest!(waitpid_sleep_wake, waitpid_sleep_wake);
@@ -0,0 +1,560 @@
+//! Linux rv64 syscall ABI, for binaries built against a standard libc. A process speaks it when its image is branded
+//! ELFOSABI_LINUX, or was exec'ed after prctl(PR_SET_SYSCALL_ABI). Most calls are renumbered, their flags and
+//! structs converted, and handed to the native one, so the syscall filter, tracing and stats see native ids. The
+//! few without a native counterpart (stat, clock_gettime, uname, ids) are done here, checked and counted as the
+//! nearest native one. Anything else is ENOSYS. Errors go back as -errno, see `linux_errno`.
+//! clone only forks, so no threads and no vfork based posix_spawn yet.
+
+use alloc::sync::Arc;
+
+use crate::{config::{CLOCK_FREQ, MAX_FD, PAGE_SIZE, USER_STR_MAX, U_TRAMPOLINE_ADDR}, fs::{File, OpenMode, new_pipe, open, open_at}, mem::{VirtAddr, read_user, read_user_str, write_user}, process::{FileDescriptor, PCBInner, SignalNum, get_processor, def_handler::def_ignore, RLimit, RLIMIT_NOFILE}, utils::{ErrorNum, time::get_cycle}, version::{COMPILE_EPOCH, VERSION}};
+
+use super::{stats, syscall::{syscall, check_filter, at_dir, wait_child}, syscall_num::*, types::{MMAPFlag, AT_FDCWD, AT_SYMLINK_NOFOLLOW, SIG_BLOCK}};
+
+const LINUX_GETCWD          : usize =  17;
+const LINUX_DUP             : usize =  23;
+const LINUX_DUP3            : usize =  24;
+const LINUX_FCNTL           : usize =  25;
+const LINUX_IOCTL           : usize =  29;
+const LINUX_MKDIRAT         : usize =  34;
+const LINUX_UNLINKAT        : usize =  35;
+const LINUX_TRUNCATE        : usize =  45;
+const LINUX_FTRUNCATE       : usize =  46;
+const LINUX_FALLOCATE       : usize =  47;
+const LINUX_FACCESSAT       : usize =  48;
+const LINUX_CHDIR           : usize =  49;
+const LINUX_OPENAT          : usize =  56;
+const LINUX_CLOSE           : usize =  57;
+const LINUX_PIPE2           : usize =  59;
+const LINUX_GETDENTS64      : usize =  61;
+const LINUX_LSEEK           : usize =  62;
+const LINUX_READ            : usize =  63;
+const LINUX_WRITE           : usize =  64;
+const LINUX_READV           : usize =  65;
+const LINUX_WRITEV          : usize =  66;
+const LINUX_NEWFSTATAT      : usize =  79;
+const LINUX_FSTAT           : usize =  80;
+const LINUX_FSYNC           : usize =  82;
+const LINUX_EXIT            : usize =  93;
+const LINUX_EXIT_GROUP      : usize =  94;
+const LINUX_SET_TID_ADDRESS : usize =  96;
+const LINUX_CLOCK_GETTIME   : usize = 113;
+const LINUX_KILL            : usize = 129;
+const LINUX_RT_SIGACTION    : usize = 134;
+const LINUX_RT_SIGPROCMASK  : usize = 135;
+const LINUX_SETRESUID       : usize = 147;
+const LINUX_SETRESGID       : usize = 149;
+const LINUX_SETPGID         : usize = 154;
+const LINUX_GETPGID         : usize = 155;
+const LINUX_SETSID          : usize = 157;
+const LINUX_UNAME           : usize = 160;
+const LINUX_GETRLIMIT       : usize = 163;
+const LINUX_SETRLIMIT       : usize = 164;
+const LINUX_UMASK           : usize = 166;
+const LINUX_PRCTL           : usize = 167;
+const LINUX_GETTIMEOFDAY    : usize = 169;
+const LINUX_GETPID          : usize = 172;
+const LINUX_GETPPID         : usize = 173;
+const LINUX_GETUID          : usize = 174;
+const LINUX_GETEUID         : usize = 175;
+const LINUX_GETGID          : usize = 176;
+const LINUX_GETEGID         : usize = 177;
+const LINUX_GETTID          : usize = 178;
+const LINUX_BRK             : usize = 214;
+const LINUX_MUNMAP          : usize = 215;
+const LINUX_CLONE           : usize = 220;
+pub const LINUX_EXECVE      : usize = 221;
+const LINUX_MMAP            : usize = 222;
+const LINUX_MSYNC           : usize = 227;
+const LINUX_MLOCK           : usize = 228;
+const LINUX_MUNLOCK         : usize = 229;
+const LINUX_WAIT4           : usize = 260;
+const LINUX_PRLIMIT64       : usize = 261;
+const LINUX_PVM_READV       : usize = 270;
+const LINUX_PVM_WRITEV      : usize = 271;
+
+/// open flags
+const O_ACCMODE     : usize = 0o3;
+const O_RDONLY      : usize = 0o0;
+const O_WRONLY      : usize = 0o1;
+const O_RDWR        : usize = 0o2;
+const O_CREAT       : usize = 0o100;
+const O_EXCL        : usize = 0o200;
+const O_TRUNC       : usize = 0o1000;
+const O_APPEND      : usize = 0o2000;
+const O_NOFOLLOW    : usize = 0o400000;
+
+/// mmap flags that differ from MMAPFlag, FIXED and ANONYMOUS are the same
+const MAP_SHARED    : usize = 0x01;
+const MAP_PRIVATE   : usize = 0x02;
+const MAP_FIXED     : usize = 0x10;
+const MAP_ANONYMOUS : usize = 0x20;
+const MAP_POPULATE  : usize = 0x8000;
+
+/// fcntl commands taken here, the rest are the same as native
+const F_DUPFD       : usize = 0;
+const F_GETFD       : usize = 1;
+const F_SETFD       : usize = 2;
+const F_DUPFD_CLOEXEC: usize = 1030;
+
+const AT_EMPTY_PATH : usize = 0x1000;
+const AT_REMOVEDIR  : usize = 0x200;
+
+const CLOCK_REALTIME: usize = 0;
+const SIGCHLD       : usize = 17;
+const WNOHANG       : usize = 1;
+const SIG_DFL       : usize = 0;
+const SIG_IGN       : usize = 1;
+
+const S_IFIFO       : u32 = 0o010000;
+const S_IFCHR       : u32 = 0o020000;
+const S_IFDIR       : u32 = 0o040000;
+const S_IFBLK       : u32 = 0o060000;
+const S_IFREG       : u32 = 0o100000;
+const S_IFLNK       : u32 = 0o120000;
+const S_IFSOCK      : u32 = 0o140000;
+
+/// asm-generic struct stat. No timestamps kept, those are 0.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct LinuxStat {
+    dev: u64,
+    ino: u64,
+    mode: u32,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+    pad1: u64,
+    size: i64,
+    blksize: i32,
+    pad2: i32,
+    blocks: i64,
+    times: [i64; 6],
+    unused: [u32; 2],
+}
+static_assertions::assert_eq_size!(LinuxStat, [u8; 128]);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LinuxTimespec {
+    sec: i64,
+    /// nanoseconds for timespec, microseconds for timeval
+    frac: i64,
+}
+
+/// struct utsname, sysname, nodename, release, version, machine, domainname
+type LinuxUtsname = [[u8; 65]; 6];
+
+/// struct sigaction, riscv has no sa_restorer
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LinuxSigaction {
+    handler: usize,
+    flags: usize,
+    mask: u64,
+}
+
+pub fn linux_syscall(syscall_id: usize, args: [usize; 6]) -> Result<usize, ErrorNum> {
+    let n = |id: usize, native_args: &[usize]| {
+        let mut a = [0; 6];
+        a[..native_args.len()].copy_from_slice(native_args);
+        syscall(id, a)
+    };
+    match syscall_id {
+        LINUX_GETCWD        => n(SYSCALL_GETCWD     , &args[..2]),
+        LINUX_DUP           => n(SYSCALL_DUP        , &args[..1]),
+        LINUX_DUP3          => compat(syscall_id, SYSCALL_DUP, || dup3(FileDescriptor::from(args[0]), FileDescriptor::from(args[1]))),
+        LINUX_FCNTL         => match args[1] {
+            F_DUPFD | F_DUPFD_CLOEXEC => n(SYSCALL_DUP, &args[..1]),
+            // no close on exec
+            F_GETFD | F_SETFD => compat(syscall_id, SYSCALL_FCNTL, || get_processor().current().unwrap().get_inner().get_file(FileDescriptor::from(args[0])).map(|_| 0)),
+            _ => n(SYSCALL_FCNTL, &args[..3]),
+        },
+        // no terminal ioctls, isatty says no
+        LINUX_IOCTL         => Err(ErrorNum::ENOTTY),
+        LINUX_MKDIRAT       => {
+            check_cwd(args[0])?;
+            n(SYSCALL_MKDIR, &args[1..3])
+        },
+        LINUX_UNLINKAT      => {
+            check_cwd(args[0])?;
+            if args[2] & !AT_REMOVEDIR != 0 {
+                return Err(ErrorNum::EINVAL);
+            }
+            n(SYSCALL_DELETE, &args[1..2])
+        },
+        LINUX_TRUNCATE      => n(SYSCALL_TRUNCATE   , &args[..2]),
+        LINUX_FTRUNCATE     => n(SYSCALL_FTRUNCATE  , &args[..2]),
+        LINUX_FALLOCATE     => n(SYSCALL_FALLOCATE  , &args[..4]),
+        LINUX_FACCESSAT     => n(SYSCALL_FACCESSAT  , &[args[0], args[1], args[2], 0]),
+        LINUX_CHDIR         => n(SYSCALL_CHDIR      , &args[..1]),
+        LINUX_OPENAT        => openat(args[0], args[1], args[2]),
+        LINUX_CLOSE         => n(SYSCALL_CLOSE      , &args[..1]),
+        LINUX_PIPE2         => compat(syscall_id, SYSCALL_PIPE, || pipe2(VirtAddr::from(args[0]))),
+        LINUX_GETDENTS64    => n(SYSCALL_GETDENTS64 , &args[..3]),
+        LINUX_LSEEK         => n(SYSCALL_SEEK       , &args[..3]),
+        LINUX_READ          => n(SYSCALL_READ       , &args[..3]),
+        LINUX_WRITE         => n(SYSCALL_WRITE      , &args[..3]),
+        LINUX_READV         => n(SYSCALL_READV      , &args[..3]),
+        LINUX_WRITEV        => n(SYSCALL_WRITEV     , &args[..3]),
+        LINUX_NEWFSTATAT    => compat(syscall_id, SYSCALL_FACCESSAT, || newfstatat(FileDescriptor::from(args[0]), VirtAddr::from(args[1]), VirtAddr::from(args[2]), args[3])),
+        LINUX_FSTAT         => compat(syscall_id, SYSCALL_FACCESSAT, || fstat(FileDescriptor::from(args[0]), VirtAddr::from(args[1]))),
+        LINUX_FSYNC         => n(SYSCALL_FSYNC      , &args[..1]),
+        LINUX_EXIT          => n(SYSCALL_EXIT       , &[args[0] as i32 as usize]),
+        LINUX_EXIT_GROUP    => n(SYSCALL_EXIT_GROUP , &[args[0] as i32 as usize]),
+        // no clear_child_tid, nothing to wake on exit without threads
+        LINUX_SET_TID_ADDRESS => compat(syscall_id, SYSCALL_GETPGID, || Ok(get_processor().current().unwrap().pid.0)),
+        LINUX_CLOCK_GETTIME => compat(syscall_id, SYSCALL_TIME, || clock_gettime(args[0], VirtAddr::from(args[1]), false)),
+        LINUX_GETTIMEOFDAY  => compat(syscall_id, SYSCALL_TIME, || clock_gettime(CLOCK_REALTIME, VirtAddr::from(args[0]), true)),
+        LINUX_KILL          => n(SYSCALL_SIGNAL     , &args[..2]),
+        LINUX_RT_SIGACTION  => compat(syscall_id, SYSCALL_SIGACTION, || rt_sigaction(args[0], VirtAddr::from(args[1]), VirtAddr::from(args[2]))),
+        LINUX_RT_SIGPROCMASK=> rt_sigprocmask(args[0], VirtAddr::from(args[1]), VirtAddr::from(args[2])),
+        LINUX_SETRESUID     => n(SYSCALL_SETRESUID  , &args[..3]),
+        LINUX_SETRESGID     => n(SYSCALL_SETRESGID  , &args[..3]),
+        LINUX_SETPGID       => n(SYSCALL_SETPGID    , &args[..2]),
+        LINUX_GETPGID       => n(SYSCALL_GETPGID    , &args[..1]),
+        LINUX_SETSID        => n(SYSCALL_SETSID     , &[]),
+        LINUX_UNAME         => compat(syscall_id, SYSCALL_SYSSTAT, || uname(VirtAddr::from(args[0]))),
+        LINUX_GETRLIMIT     => n(SYSCALL_GETRLIMIT  , &args[..2]),
+        LINUX_SETRLIMIT     => n(SYSCALL_SETRLIMIT  , &args[..2]),
+        LINUX_UMASK         => n(SYSCALL_UMASK      , &args[..1]),
+        LINUX_PRCTL         => n(SYSCALL_PRCTL      , &args[..2]),
+        LINUX_GETPID        => compat(syscall_id, SYSCALL_GETPGID, || Ok(get_processor().current().unwrap().tgid.0)),
+        LINUX_GETTID        => compat(syscall_id, SYSCALL_GETPGID, || Ok(get_processor().current().unwrap().pid.0)),
+        LINUX_GETPPID       => compat(syscall_id, SYSCALL_GETPGID, || {
+            let parent = get_processor().current().unwrap().get_inner().parent.clone();
+            Ok(parent.and_then(|p| p.upgrade()).map_or(0, |p| p.pid.0))
+        }),
+        LINUX_GETUID        => compat(syscall_id, SYSCALL_GETCRED, || Ok(get_processor().current().unwrap().get_inner().cred.uid as usize)),
+        LINUX_GETEUID       => compat(syscall_id, SYSCALL_GETCRED, || Ok(get_processor().current().unwrap().get_inner().cred.euid as usize)),
+        LINUX_GETGID        => compat(syscall_id, SYSCALL_GETCRED, || Ok(get_processor().current().unwrap().get_inner().cred.gid as usize)),
+        LINUX_GETEGID       => compat(syscall_id, SYSCALL_GETCRED, || Ok(get_processor().current().unwrap().get_inner().cred.egid as usize)),
+        LINUX_BRK           => n(SYSCALL_BRK        , &args[..1]),
+        LINUX_MUNMAP        => n(SYSCALL_MUNMAP     , &args[..2]),
+        // fork only, no CLONE_VM and no stack of its own
+        LINUX_CLONE         => {
+            if args[0] != SIGCHLD || args[1] != 0 {
+                return Err(ErrorNum::EINVAL);
+            }
+            n(SYSCALL_FORK, &[])
+        },
+        LINUX_EXECVE        => n(SYSCALL_EXEC       , &args[..3]),
+        LINUX_MMAP          => n(SYSCALL_MMAP       , &[args[0], args[1], args[2], mmap_flag(args[3])?.bits(), args[4], args[5]]),
+        LINUX_MSYNC         => n(SYSCALL_MSYNC      , &args[..3]),
+        LINUX_MLOCK         => n(SYSCALL_MLOCK      , &args[..2]),
+        LINUX_MUNLOCK       => n(SYSCALL_MUNLOCK    , &args[..2]),
+        LINUX_WAIT4         => compat(syscall_id, SYSCALL_WAITPID, || wait4(args[0] as isize, VirtAddr::from(args[1]), args[2])),
+        LINUX_PRLIMIT64     => prlimit64(args[0], args[1], args[2], args[3]),
+        LINUX_PVM_READV     => n(SYSCALL_PVM_READ   , &args[..5]),
+        LINUX_PVM_WRITEV    => n(SYSCALL_PVM_WRITE  , &args[..5]),
+        _ => {
+            error!("Unknown linux syscall id {}", syscall_id);
+            Err(ErrorNum::ENOSYS)
+        }
+    }
+}
+
+/// Done here rather than by a native call, but filtered and counted as `native_id`.
+fn compat(syscall_id: usize, native_id: usize, f: impl FnOnce() -> Result<usize, ErrorNum>) -> Result<usize, ErrorNum> {
+    let do_trace = check_filter(native_id)?;
+    let start = get_cycle();
+    let res = f();
+    stats::record(native_id, get_cycle() - start);
+    if do_trace {
+        info!("LINUX SYSCALL {} CALLED BY {:?} RESULT {:?}", syscall_id, get_processor().current().unwrap().pid, res);
+    }
+    res
+}
+
+/// Errno a Linux program understands: EBADFD is what native calls give for a bad fd, kernel only ones fold into
+/// the closest.
+pub fn linux_errno(err: ErrorNum) -> ErrorNum {
+    match err {
+        ErrorNum::EBADFD        => ErrorNum::EBADF,
+        ErrorNum::EWRONGSEG
+        | ErrorNum::EEMPTY
+        | ErrorNum::ENOTALIGNED
+        | ErrorNum::ENOSEG
+        | ErrorNum::ENOSIG      => ErrorNum::EINVAL,
+        ErrorNum::EOOR          => ErrorNum::EFAULT,
+        ErrorNum::EBADTYPE      => ErrorNum::ENOTDIR,
+        ErrorNum::EMMAPED       => ErrorNum::EEXIST,
+        ErrorNum::ESIGDISABLED  => ErrorNum::EPERM,
+        ErrorNum::ENOTMAPPED
+        | ErrorNum::EBADCODEX   => ErrorNum::EIO,
+        ErrorNum::EBADDTB
+        | ErrorNum::ENOTINTC    => ErrorNum::ENODEV,
+        _ => err,
+    }
+}
+
+/// OpenMode of open flags, none of the ones that need more than a mode.
+pub fn open_mode(flags: usize) -> Result<OpenMode, ErrorNum> {
+    let mut mode = match flags & O_ACCMODE {
+        O_RDONLY => OpenMode::READ,
+        O_WRONLY => OpenMode::WRITE,
+        O_RDWR => OpenMode::READ | OpenMode::WRITE,
+        _ => return Err(ErrorNum::EINVAL),
+    };
+    if flags & O_NOFOLLOW != 0 {
+        mode |= OpenMode::NO_FOLLOW;
+    }
+    Ok(mode)
+}
+
+/// MMAPFlag of mmap flags, hints we don't take are dropped.
+pub fn mmap_flag(flags: usize) -> Result<MMAPFlag, ErrorNum> {
+    let mut res = MMAPFlag::from_bits_truncate(flags & (MAP_FIXED | MAP_ANONYMOUS));
+    match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => res |= MMAPFlag::SHARED,
+        MAP_PRIVATE => res |= MMAPFlag::PRIVATE,
+        _ => return Err(ErrorNum::EINVAL),
+    }
+    if flags & MAP_POPULATE != 0 {
+        res |= MMAPFlag::POPULATE;
+    }
+    Ok(res)
+}
+
+/// Native *at calls without a dirfd take relative paths against the cwd, so that's the only dirfd they can have.
+fn check_cwd(dirfd: usize) -> Result<(), ErrorNum> {
+    if dirfd == AT_FDCWD {
+        Ok(())
+    } else {
+        Err(ErrorNum::EOPNOTSUPP)
+    }
+}
+
+/// Native CREATE fails on a file that's there, so O_CREAT without O_EXCL tries opening first. O_TRUNC and
+/// O_APPEND are done after, O_APPEND only as a seek to the end.
+fn openat(dirfd: usize, path: usize, flags: usize) -> Result<usize, ErrorNum> {
+    let mode = open_mode(flags)?;
+    let open = |mode: OpenMode| syscall(SYSCALL_OPENAT, [dirfd, path, mode.bits(), 0, 0, 0]);
+    let fd = if flags & O_CREAT == 0 {
+        open(mode)?
+    } else if flags & O_EXCL != 0 {
+        open(mode | OpenMode::CREATE)?
+    } else {
+        match open(mode) {
+            Err(ErrorNum::ENOENT) => open(mode | OpenMode::CREATE)?,
+            res => res?,
+        }
+    };
+    if flags & O_TRUNC != 0 && mode.contains(OpenMode::WRITE) {
+        // EINVAL is for what can't be truncated, there's nothing to
+        match syscall(SYSCALL_FTRUNCATE, [fd, 0, 0, 0, 0, 0]) {
+            Ok(_) | Err(ErrorNum::EINVAL) => (),
+            Err(e) => {
+                syscall(SYSCALL_CLOSE, [fd, 0, 0, 0, 0, 0]).ok();
+                return Err(e);
+            },
+        }
+    }
+    if flags & O_APPEND != 0 {
+        syscall(SYSCALL_SEEK, [fd, 0, 2, 0, 0, 0]).ok();
+    }
+    Ok(fd)
+}
+
+fn dup3(old: FileDescriptor, new: FileDescriptor) -> Result<usize, ErrorNum> {
+    if old == new {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if new.0 >= proc_inner.rlimits[RLIMIT_NOFILE].cur.min(MAX_FD) {
+        return Err(ErrorNum::EBADF);
+    }
+    let file = proc_inner.get_file(old)?;
+    let displaced = proc_inner.files.insert(new, file);
+    // closing may sleep, not under the pcb lock
+    drop(proc_inner);
+    drop(displaced);
+    Ok(new.0)
+}
+
+/// int[2] rather than native's usize[2].
+fn pipe2(fds: VirtAddr) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let (r, w) = new_pipe();
+    let r_fd = proc_inner.register_file(r)?;
+    let w_fd = match proc_inner.register_file(w) {
+        Ok(fd) => fd,
+        Err(e) => {
+            proc_inner.close_file(r_fd).unwrap();
+            return Err(e);
+        },
+    };
+    if let Err(e) = write_user(&mut proc_inner.mem_layout, fds, &[r_fd.0 as i32, w_fd.0 as i32]) {
+        proc_inner.close_file(r_fd).unwrap();
+        proc_inner.close_file(w_fd).unwrap();
+        return Err(e);
+    }
+    Ok(0)
+}
+
+fn stat_of(file: Arc<dyn File>) -> Result<LinuxStat, ErrorNum> {
+    let stat = file.stat()?;
+    let owner = file.owner()?;
+    let kind = if file.clone().as_link().is_ok() {
+        S_IFLNK
+    } else if file.clone().as_dir().is_ok() {
+        S_IFDIR
+    } else if file.clone().as_regular().is_ok() {
+        S_IFREG
+    } else if file.clone().as_char().is_ok() {
+        S_IFCHR
+    } else if file.clone().as_block().is_ok() {
+        S_IFBLK
+    } else if file.clone().as_fifo().is_ok() {
+        S_IFIFO
+    } else if file.clone().as_socket().is_ok() {
+        S_IFSOCK
+    } else {
+        0
+    };
+    Ok(LinuxStat {
+        dev: stat.fs.upgrade().map_or(0, |fs| fs.get_uuid().0 as u64),
+        ino: stat.inode as u64,
+        mode: kind | owner.permission.bits() as u32,
+        nlink: 1,
+        uid: owner.uid,
+        gid: owner.gid,
+        size: stat.file_size as i64,
+        blksize: PAGE_SIZE as i32,
+        blocks: ((stat.file_size + 511) / 512) as i64,
+        ..Default::default()
+    })
+}
+
+fn fstat(fd: FileDescriptor, buf: VirtAddr) -> Result<usize, ErrorNum> {
+    let file = get_processor().current().unwrap().get_inner().get_file(fd)?;
+    let stat = stat_of(file)?;
+    write_user(&mut get_processor().current().unwrap().get_inner().mem_layout, buf, &stat)?;
+    Ok(0)
+}
+
+fn newfstatat(dirfd: FileDescriptor, path: VirtAddr, buf: VirtAddr, flags: usize) -> Result<usize, ErrorNum> {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let path = read_user_str(&mut proc_inner.mem_layout, path, USER_STR_MAX)?;
+    if path.is_empty() {
+        drop(proc_inner);
+        return if flags & AT_EMPTY_PATH != 0 {fstat(dirfd, buf)} else {Err(ErrorNum::ENOENT)};
+    }
+    let (dir_file, path) = at_dir(&proc_inner, dirfd, path)?;
+    // procfs needs self inner
+    drop(proc_inner);
+    let mode = if flags & AT_SYMLINK_NOFOLLOW != 0 {OpenMode::SYS | OpenMode::NO_FOLLOW} else {OpenMode::SYS};
+    let file = match dir_file {
+        Some(dir_file) => open_at(dir_file, &path, mode)?,
+        None => open(&path, mode)?,
+    };
+    let stat = stat_of(file)?;
+    write_user(&mut proc.get_inner().mem_layout, buf, &stat)?;
+    Ok(0)
+}
+
+/// CLOCK_REALTIME counts from the build time, there's no RTC. Every other clock is time since boot.
+fn clock_gettime(clock: usize, buf: VirtAddr, timeval: bool) -> Result<usize, ErrorNum> {
+    let ns = (get_cycle() as u128 * 1_000_000_000 / CLOCK_FREQ as u128) as u64;
+    let mut sec = (ns / 1_000_000_000) as i64;
+    let mut frac = (ns % 1_000_000_000) as i64;
+    if clock == CLOCK_REALTIME {
+        sec += COMPILE_EPOCH as i64;
+    }
+    if timeval {
+        frac /= 1000;
+    }
+    write_user(&mut get_processor().current().unwrap().get_inner().mem_layout, buf, &LinuxTimespec{sec, frac})?;
+    Ok(0)
+}
+
+fn uname(buf: VirtAddr) -> Result<usize, ErrorNum> {
+    let mut res: LinuxUtsname = [[0; 65]; 6];
+    for (field, value) in res.iter_mut().zip(["ParchKernel", "parch", env!("CARGO_PKG_VERSION"), VERSION, "riscv64", ""]) {
+        let len = value.len().min(64);
+        field[..len].copy_from_slice(&value.as_bytes()[..len]);
+    }
+    write_user(&mut get_processor().current().unwrap().get_inner().mem_layout, buf, &res)?;
+    Ok(0)
+}
+
+/// Only the handler, there are no sa_flags or sa_mask to go by. Default handlers live in the user trampoline and
+/// read back as SIG_DFL.
+fn rt_sigaction(signum: usize, act: VirtAddr, old_act: VirtAddr) -> Result<usize, ErrorNum> {
+    extern "C" {fn sutrampoline();}
+    let signal = SignalNum::try_from(signum).map_err(|_| ErrorNum::EINVAL)?;
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if old_act.0 != 0 {
+        let handler = proc_inner.signal_handler.get(&signal).map_or(SIG_DFL, |h| h.0);
+        let handler = if (U_TRAMPOLINE_ADDR.0..U_TRAMPOLINE_ADDR.0 + PAGE_SIZE).contains(&handler) {SIG_DFL} else {handler};
+        write_user(&mut proc_inner.mem_layout, old_act, &LinuxSigaction{handler, flags: 0, mask: 0})?;
+    }
+    if act.0 != 0 {
+        let new: LinuxSigaction = read_user(&mut proc_inner.mem_layout, act)?;
+        let handler = match new.handler {
+            SIG_DFL => PCBInner::default_hander().get(&signal).copied(),
+            SIG_IGN => Some(U_TRAMPOLINE_ADDR + (def_ignore as usize - sutrampoline as usize)),
+            handler => Some(VirtAddr::from(handler)),
+        };
+        match handler {
+            Some(handler) => proc_inner.signal_handler.insert(signal, handler),
+            None => proc_inner.signal_handler.remove(&signal),
+        };
+    }
+    Ok(0)
+}
+
+/// Sets are 64 bit masks of bit signum - 1, like SignalNum::bit.
+fn rt_sigprocmask(how: usize, set: VirtAddr, old_set: VirtAddr) -> Result<usize, ErrorNum> {
+    let (how, set) = if set.0 == 0 {
+        (SIG_BLOCK, 0)
+    } else {
+        (how, read_user::<u64>(&mut get_processor().current().unwrap().get_inner().mem_layout, set)?)
+    };
+    let old = syscall(SYSCALL_SIGPROCMASK, [how, set as usize, 0, 0, 0, 0])? as u64;
+    if old_set.0 != 0 {
+        write_user(&mut get_processor().current().unwrap().get_inner().mem_layout, old_set, &old)?;
+    }
+    Ok(0)
+}
+
+/// Any child like native waitpid, status as exited with the low 8 bits of the code. No rusage.
+fn wait4(pid: isize, status: VirtAddr, options: usize) -> Result<usize, ErrorNum> {
+    if options & !WNOHANG != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let (pid, code) = match wait_child(pid, options & WNOHANG != 0)? {
+        Some(res) => res,
+        None => return Ok(0),
+    };
+    if status.0 != 0 {
+        let wstatus = ((code & 0xff) << 8) as i32;
+        write_user(&mut get_processor().current().unwrap().get_inner().mem_layout, status, &wstatus)?;
+    }
+    Ok(pid)
+}
+
+/// Own limits only.
+fn prlimit64(pid: usize, resource: usize, new: usize, old: usize) -> Result<usize, ErrorNum> {
+    if pid != 0 && pid != get_processor().current().unwrap().tgid.0 {
+        return Err(ErrorNum::EPERM);
+    }
+    if old != 0 {
+        syscall(SYSCALL_GETRLIMIT, [resource, old, 0, 0, 0, 0])?;
+    }
+    if new != 0 {
+        syscall(SYSCALL_SETRLIMIT, [resource, new, 0, 0, 0, 0])?;
+    }
+    Ok(0)
+}
+
+// native RLimit is struct rlimit as is
+static_assertions::assert_eq_size!(RLimit, [u64; 2]);
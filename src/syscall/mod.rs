@@ -1,5 +1,9 @@
 mod syscall;
+mod linux;
 pub mod syscall_num;
 mod types;
+pub mod stats;
+mod ktests;
 
-pub use syscall::syscall;
\ No newline at end of file
+pub use syscall::syscall;
+pub use linux::{linux_syscall, linux_errno, LINUX_EXECVE};
\ No newline at end of file
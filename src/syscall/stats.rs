@@ -0,0 +1,70 @@
+//! Per hart syscall latency histograms, for /proc/syscall_stats.
+//!
+//! Latency is from dispatch to return, blocking included, recorded on the hart the syscall returns on.
+//! Bucket 0 counts calls under 1us, bucket i those in [2^(i-1), 2^i) us, the last one everything above.
+//! sys_exit never returns and isn't counted. Numbers past MAX_SYSCALL share one row, shown as `<other>`.
+
+use alloc::string::String;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::{config::{CLOCK_FREQ, MAX_CPUS, MAX_SYSCALL}, process::{get_hart_id, present_harts}};
+
+use super::syscall_num::SYSCALL_NAMES;
+
+pub const LATENCY_BUCKETS: usize = 20;
+
+struct SyscallStat {
+    buckets: [AtomicU32; LATENCY_BUCKETS],
+    /// for the mean
+    total_cycles: AtomicU64,
+}
+
+const STAT_INIT: SyscallStat = SyscallStat {
+    buckets: [BUCKET_INIT; LATENCY_BUCKETS],
+    total_cycles: AtomicU64::new(0),
+};
+const BUCKET_INIT: AtomicU32 = AtomicU32::new(0);
+/// the last one is for numbers past MAX_SYSCALL
+const HART_INIT: [SyscallStat; MAX_SYSCALL + 1] = [STAT_INIT; MAX_SYSCALL + 1];
+
+/// Only the owning hart writes its row, relaxed is enough.
+static SYSCALL_STATS: [[SyscallStat; MAX_SYSCALL + 1]; MAX_CPUS] = [HART_INIT; MAX_CPUS];
+
+const fn cycles_per_us() -> usize {
+    CLOCK_FREQ / 1_000_000
+}
+
+pub fn latency_bucket(cycles: usize) -> usize {
+    let us = cycles / cycles_per_us();
+    let bucket = (usize::BITS - us.leading_zeros()) as usize;
+    bucket.min(LATENCY_BUCKETS - 1)
+}
+
+pub fn record(syscall_id: usize, cycles: usize) {
+    let stat = &SYSCALL_STATS[get_hart_id()][syscall_id.min(MAX_SYSCALL)];
+    stat.buckets[latency_bucket(cycles)].fetch_add(1, Ordering::Relaxed);
+    stat.total_cycles.fetch_add(cycles as u64, Ordering::Relaxed);
+}
+
+/// One line per syscall and hart that saw any: name, hart, calls, mean us, then the buckets.
+pub fn report() -> String {
+    let mut res = String::from("# syscall hart calls mean_us <1us <2us <4us ... (log2 us buckets)\n");
+    let named = SYSCALL_NAMES.iter().filter(|(id, _)| *id < MAX_SYSCALL).copied();
+    for (id, name) in named.chain(core::iter::once((MAX_SYSCALL, "<other>"))) {
+        for hart in present_harts().filter(|hart| *hart < MAX_CPUS) {
+            let stat = &SYSCALL_STATS[hart][id];
+            let buckets: [u32; LATENCY_BUCKETS] = core::array::from_fn(|i| stat.buckets[i].load(Ordering::Relaxed));
+            let calls: u64 = buckets.iter().map(|count| *count as u64).sum();
+            if calls == 0 {
+                continue;
+            }
+            let mean_us = stat.total_cycles.load(Ordering::Relaxed) / calls / cycles_per_us() as u64;
+            res += &format!("{:<12} {:>2} {:>8} {:>8}", name, hart, calls, mean_us);
+            for count in buckets {
+                res += &format!(" {}", count);
+            }
+            res += "\n";
+        }
+    }
+    res
+}
@@ -1,13 +1,36 @@
-use core::{mem::size_of};
+use core::{cmp::min, mem::size_of};
 
 use alloc::{vec::Vec, sync::Arc, collections::LinkedList, borrow::ToOwned, string::String};
 
-use crate::{config::PHYS_END_ADDR, fs::{FileType, OpenMode, Path, Permission, delete, make_file, new_pipe, open, open_at}, interrupt::trap_context::TrapContext, mem::{VirtAddr, VMASegment, SegmentFlags, ManagedSegment, VPNRange, stat_mem, MMAPType}, process::{FileDescriptor, get_processor, push_sum_on, pop_sum_on, enqueue, ProcessStatus, ProcessID, get_process, SignalNum}, utils::{ErrorNum}};
+use crate::{device::{ioctl_abi::{IOCTL_ABI_VERSION, IOCTL_GET_ABI_VERSION, IOCtlVersion}, ioctl_res}, config::{PHYS_END_ADDR, PIPE_MAX_SIZE, MAX_IOV, MAX_SPAWN_ACTIONS, PAGE_SIZE, USER_STR_MAX, ARG_MAX}, fs::{FileType, MountFlags, OpenMode, Path, Permission, SeekWhence, bind_mount, mount_flags, delete, umount, unshare_mount_ns, parch_fs_get_quota, parch_fs_set_quota, make_file, make_file_at, new_pipe, as_pipe_end, copy_range_bounce, EventFd, TimerFd, SignalFd, Inotify, open, open_at, open_searching, File}, interrupt::trap_context::TrapContext, mem::{VirtAddr, VirtPageNum, VMASegment, sync_dirty, SegmentFlags, ManagedSegment, VPNRange, stat_mem, MMAPType, UserBuffer, MemLayout, copy_from_user, copy_to_user, bytes_of, read_user_cstr, read_user_str, read_user, write_user}, process::{FileDescriptor, get_processor, enqueue, thread_group, ProcessStatus, ProcessID, get_process, pgrp_in_session, SignalNum, wake_up, resolve_exec, RLimit, RLIMIT_COUNT, rlimit_ceiling, SyscallFilter, FILTER_BITMAP_MAX, PCBInner, SyscallAbi}, utils::{ErrorNum, time::get_cycle, trace::{TraceEvent, trace}}};
 
-use super::{syscall_num::*, types::{MMAPProt, MMAPFlag, SyscallDirent, SyscallStat}};
+use super::{stats, syscall_num::*, types::{MMAPProt, MMAPFlag, SyscallDirent, SyscallStat, SyscallStatFs, SyscallIOVec, SyscallRUsage, SyscallQuota, SyscallSpawnAction, QUOTACTL_GET, QUOTACTL_SET, SPAWN_CLOSE, SPAWN_DUP2, SPAWN_OPEN, MOUNT_BIND, CLONE_NEWNS, FALLOC_KEEP_SIZE, FALLOC_PUNCH_HOLE, R_OK, W_OK, X_OK, AT_FDCWD, AT_SYMLINK_NOFOLLOW, AT_EACCESS, F_GETFL, F_SETFL, F_GETPIPE_SZ, F_SETPIPE_SZ, O_NONBLOCK, SPLICE_F_NONBLOCK, EFD_SEMAPHORE, EFD_NONBLOCK, TFD_NONBLOCK, SIG_BLOCK, SIG_UNBLOCK, SIG_SETMASK, SFD_NONBLOCK, IN_NONBLOCK, PR_SET_SYSCALL_ABI, PR_GET_SYSCALL_ABI, encode_dirent64}};
 
 pub fn syscall(syscall_id: usize, args: [usize; 6]) -> Result<usize, ErrorNum> {
-    let do_trace = get_processor().current().unwrap().get_inner().trace_enabled[syscall_id];
+    let start = get_cycle();
+    let res = dispatch(syscall_id, args);
+    stats::record(syscall_id, get_cycle() - start);
+    res
+}
+
+/// Filter of the current process lets `syscall_id` through, else it gets SIGSYS and the call EPERM. Returns whether
+/// the call is traced.
+pub(super) fn check_filter(syscall_id: usize) -> Result<bool, ErrorNum> {
+    // don't keep the pcb past here, sys_exit does not return
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if let Some(filter) = proc_inner.syscall_filter {
+        if !filter.allows(syscall_id) {
+            warning!("Syscall {} of {:?} blocked by syscall filter", syscall_id, proc.pid);
+            proc_inner.recv_signal(SignalNum::SIGSYS).ok();
+            return Err(ErrorNum::EPERM);
+        }
+    }
+    Ok(proc_inner.trace_enabled.get(syscall_id).copied().unwrap_or(true))
+}
+
+fn dispatch(syscall_id: usize, args: [usize; 6]) -> Result<usize, ErrorNum> {
+    let do_trace = check_filter(syscall_id)?;
     match syscall_id {
         SYSCALL_WRITE       => CALL_SYSCALL!(do_trace, sys_write        , FileDescriptor::from(args[0]), VirtAddr::from(args[1]), args[2]),
         SYSCALL_READ        => CALL_SYSCALL!(do_trace, sys_read         , FileDescriptor::from(args[0]), VirtAddr::from(args[1]), args[2]),
@@ -16,7 +39,7 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> Result<usize, ErrorNum> {
         SYSCALL_CLOSE       => CALL_SYSCALL!(do_trace, sys_close        , FileDescriptor::from(args[0])),
         SYSCALL_DUP         => CALL_SYSCALL!(do_trace, sys_dup          , FileDescriptor::from(args[0])),
         SYSCALL_FORK        => CALL_SYSCALL!(do_trace, sys_fork         ),
-        SYSCALL_EXEC        => CALL_SYSCALL!(do_trace, sys_exec         , VirtAddr::from(args[0]), VirtAddr::from(args[1])),
+        SYSCALL_EXEC        => CALL_SYSCALL!(do_trace, sys_exec         , VirtAddr::from(args[0]), VirtAddr::from(args[1]), VirtAddr::from(args[2])),
         SYSCALL_EXIT        => CALL_SYSCALL!(do_trace, sys_exit         , args[0] as isize),
         SYSCALL_MMAP        => CALL_SYSCALL!(do_trace, sys_mmap         , VirtAddr::from(args[0]), args[1], MMAPProt::from_bits(args[2]).ok_or(ErrorNum::EINVAL)?, MMAPFlag::from_bits(args[3]).ok_or(ErrorNum::EINVAL)?, FileDescriptor::from(args[4]), args[5]),
         SYSCALL_MUNMAP      => CALL_SYSCALL!(do_trace, sys_munmap       , VirtAddr::from(args[0]), args[1]),
@@ -33,69 +56,167 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> Result<usize, ErrorNum> {
         SYSCALL_IOCTL       => CALL_SYSCALL!(do_trace, sys_ioctl        , FileDescriptor::from(args[0]), args[1], VirtAddr::from(args[2]), args[3], VirtAddr::from(args[4]), args[5]),
         SYSCALL_DELETE      => CALL_SYSCALL!(do_trace, sys_delete       , VirtAddr::from(args[0])),
         SYSCALL_MKDIR       => CALL_SYSCALL!(do_trace, sys_mkdir        , VirtAddr::from(args[0]), Permission::from_bits_truncate(args[1] as u16)),
-        SYSCALL_SEEK        => CALL_SYSCALL!(do_trace, sys_seek         , FileDescriptor::from(args[0]), args[1]),
+        SYSCALL_SEEK        => CALL_SYSCALL!(do_trace, sys_seek         , FileDescriptor::from(args[0]), args[1] as isize, args[2]),
         SYSCALL_TIME        => CALL_SYSCALL!(do_trace, sys_time         ),
+        SYSCALL_READV       => CALL_SYSCALL!(do_trace, sys_readv        , FileDescriptor::from(args[0]), VirtAddr::from(args[1]), args[2]),
+        SYSCALL_WRITEV      => CALL_SYSCALL!(do_trace, sys_writev       , FileDescriptor::from(args[0]), VirtAddr::from(args[1]), args[2]),
+        SYSCALL_COPY_FILE_RANGE => CALL_SYSCALL!(do_trace, sys_copy_file_range, FileDescriptor::from(args[0]), FileDescriptor::from(args[1]), args[2]),
+        SYSCALL_SENDFILE    => CALL_SYSCALL!(do_trace, sys_sendfile     , FileDescriptor::from(args[0]), FileDescriptor::from(args[1]), VirtAddr::from(args[2]), args[3]),
+        SYSCALL_GETRLIMIT   => CALL_SYSCALL!(do_trace, sys_getrlimit    , args[0], VirtAddr::from(args[1])),
+        SYSCALL_SETRLIMIT   => CALL_SYSCALL!(do_trace, sys_setrlimit    , args[0], VirtAddr::from(args[1])),
+        SYSCALL_GETRUSAGE   => CALL_SYSCALL!(do_trace, sys_getrusage    , args[0], VirtAddr::from(args[1])),
+        SYSCALL_SET_FILTER  => CALL_SYSCALL!(do_trace, sys_set_filter   , args[0], VirtAddr::from(args[1]), args[2]),
+        SYSCALL_GETDENTS64  => CALL_SYSCALL!(do_trace, sys_getdents64   , FileDescriptor::from(args[0]), VirtAddr::from(args[1]), args[2]),
+        SYSCALL_QUOTACTL    => CALL_SYSCALL!(do_trace, sys_quotactl     , args[0], args[1] as u32, VirtAddr::from(args[2])),
+        SYSCALL_MSYNC       => CALL_SYSCALL!(do_trace, sys_msync        , VirtAddr::from(args[0]), args[1], args[2]),
+        SYSCALL_BRK         => CALL_SYSCALL!(do_trace, sys_brk          , VirtAddr::from(args[0])),
+        SYSCALL_EXIT_GROUP  => CALL_SYSCALL!(do_trace, sys_exit_group   , args[0] as isize),
+        SYSCALL_SPAWN       => CALL_SYSCALL!(do_trace, sys_spawn        , VirtAddr::from(args[0]), VirtAddr::from(args[1]), VirtAddr::from(args[2]), VirtAddr::from(args[3]), args[4]),
+        SYSCALL_SETSID      => CALL_SYSCALL!(do_trace, sys_setsid       ),
+        SYSCALL_SETPGID     => CALL_SYSCALL!(do_trace, sys_setpgid      , ProcessID::from(args[0]), ProcessID::from(args[1])),
+        SYSCALL_GETPGID     => CALL_SYSCALL!(do_trace, sys_getpgid      , ProcessID::from(args[0])),
+        SYSCALL_GETCRED     => CALL_SYSCALL!(do_trace, sys_getcred      , VirtAddr::from(args[0])),
+        SYSCALL_SETRESUID   => CALL_SYSCALL!(do_trace, sys_setresuid    , args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_SETRESGID   => CALL_SYSCALL!(do_trace, sys_setresgid    , args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_MOUNT       => CALL_SYSCALL!(do_trace, sys_mount        , VirtAddr::from(args[0]), VirtAddr::from(args[1]), args[2]),
+        SYSCALL_UMOUNT      => CALL_SYSCALL!(do_trace, sys_umount       , VirtAddr::from(args[0])),
+        SYSCALL_UNSHARE     => CALL_SYSCALL!(do_trace, sys_unshare      , args[0]),
+        SYSCALL_FSYNC       => CALL_SYSCALL!(do_trace, sys_fsync        , FileDescriptor::from(args[0])),
+        SYSCALL_FTRUNCATE   => CALL_SYSCALL!(do_trace, sys_ftruncate    , FileDescriptor::from(args[0]), args[1]),
+        SYSCALL_TRUNCATE    => CALL_SYSCALL!(do_trace, sys_truncate     , VirtAddr::from(args[0]), args[1]),
+        SYSCALL_FALLOCATE   => CALL_SYSCALL!(do_trace, sys_fallocate    , FileDescriptor::from(args[0]), args[1], args[2], args[3]),
+        SYSCALL_FACCESSAT   => CALL_SYSCALL!(do_trace, sys_faccessat    , FileDescriptor::from(args[0]), VirtAddr::from(args[1]), args[2], args[3]),
+        SYSCALL_UMASK       => CALL_SYSCALL!(do_trace, sys_umask        , args[0]),
+        SYSCALL_STATFS      => CALL_SYSCALL!(do_trace, sys_statfs       , VirtAddr::from(args[0]), VirtAddr::from(args[1])),
+        SYSCALL_FCNTL       => CALL_SYSCALL!(do_trace, sys_fcntl        , FileDescriptor::from(args[0]), args[1], args[2]),
+        SYSCALL_SPLICE      => CALL_SYSCALL!(do_trace, sys_splice       , FileDescriptor::from(args[0]), FileDescriptor::from(args[1]), args[2], args[3]),
+        SYSCALL_EVENTFD     => CALL_SYSCALL!(do_trace, sys_eventfd      , args[0], args[1]),
+        SYSCALL_TIMERFD_CREATE  => CALL_SYSCALL!(do_trace, sys_timerfd_create , args[0]),
+        SYSCALL_TIMERFD_SETTIME => CALL_SYSCALL!(do_trace, sys_timerfd_settime, FileDescriptor::from(args[0]), args[1], args[2]),
+        SYSCALL_SIGPROCMASK => CALL_SYSCALL!(do_trace, sys_sigprocmask  , args[0], args[1] as u64),
+        SYSCALL_SIGNALFD    => CALL_SYSCALL!(do_trace, sys_signalfd     , args[0], args[1] as u64, args[2]),
+        SYSCALL_INOTIFY_INIT     => CALL_SYSCALL!(do_trace, sys_inotify_init     , args[0]),
+        SYSCALL_INOTIFY_ADD_WATCH=> CALL_SYSCALL!(do_trace, sys_inotify_add_watch, FileDescriptor::from(args[0]), VirtAddr::from(args[1]), args[2] as u32),
+        SYSCALL_INOTIFY_RM_WATCH => CALL_SYSCALL!(do_trace, sys_inotify_rm_watch , FileDescriptor::from(args[0]), args[1] as u32),
+        SYSCALL_PVM_READ    => CALL_SYSCALL!(do_trace, sys_pvm_read     , ProcessID(args[0]), VirtAddr::from(args[1]), args[2], VirtAddr::from(args[3]), args[4]),
+        SYSCALL_PVM_WRITE   => CALL_SYSCALL!(do_trace, sys_pvm_write    , ProcessID(args[0]), VirtAddr::from(args[1]), args[2], VirtAddr::from(args[3]), args[4]),
+        SYSCALL_PRCTL       => CALL_SYSCALL!(do_trace, sys_prctl        , args[0], args[1]),
+        SYSCALL_MLOCK       => CALL_SYSCALL!(do_trace, sys_mlock        , VirtAddr::from(args[0]), args[1]),
+        SYSCALL_MUNLOCK     => CALL_SYSCALL!(do_trace, sys_munlock      , VirtAddr::from(args[0]), args[1]),
         _ => CALL_SYSCALL!(true, sys_unknown, syscall_id)
     }
 }
 
 pub fn sys_write(fd: FileDescriptor, buf: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
-    let file = get_processor().current().unwrap().get_inner().get_file(fd)?.clone();
-    // TODO: register MMAP if needed
-    push_sum_on();
-    let data = unsafe{buf.read_data(length)};
-    pop_sum_on();
-    file.write(data)?;
-    Ok(length)
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let file = proc_inner.get_file(fd)?.clone();
+    let user_buf = UserBuffer::new(&mut proc_inner.mem_layout, buf, length, false)?;
+    // file might block, don't hold pcb lock
+    drop(proc_inner);
+    file.write_user(&user_buf)
 }
 
 pub fn sys_read(fd: FileDescriptor, buf: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
-    let file = get_processor().current().unwrap().get_inner().get_file(fd)?.clone();
-    // TODO: register MMAP if needed
-    let res = file.read(length)?;
-    let length = res.len();
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
-    if buf.write_user_data(&proc_inner.mem_layout.pagetable, res).is_err() {
-        proc_inner.recv_signal(SignalNum::SIGSEGV).unwrap();
+    let file = proc_inner.get_file(fd)?.clone();
+    let mut user_buf = UserBuffer::new(&mut proc_inner.mem_layout, buf, length, true)?;
+    drop(proc_inner);
+    file.read_user(&mut user_buf)
+}
+
+/// open, or open_at if `dir` is given. With CREATE the file is made first, the default permission less `umask`.
+/// `dir_only` for a path given with a trailing slash: ENOTDIR if it's not a dir, and nothing is created for it.
+fn open_masked(dir: Option<Arc<dyn File>>, path: &Path, mode: OpenMode, umask: Permission, dir_only: bool) -> Result<Arc<dyn File>, ErrorNum> {
+    if dir_only {
+        if mode.contains(OpenMode::CREATE) {
+            return Err(ErrorNum::EISDIR);
+        }
+        let file = open_masked(dir, path, mode, umask, false)?;
+        file.clone().as_dir().map_err(|_| ErrorNum::ENOTDIR)?;
+        return Ok(file);
+    }
+    if !mode.contains(OpenMode::CREATE) {
+        return match dir {
+            Some(dir) => open_at(dir, path, mode),
+            None => open(path, mode),
+        };
+    }
+    let mode = mode - OpenMode::CREATE;
+    let permission = Permission::default() - umask;
+    match dir {
+        Some(dir) => {
+            make_file_at(path, dir.clone(), permission, FileType::REGULAR)?;
+            open_at(dir, path, mode)
+        },
+        None => {
+            make_file(path, permission, FileType::REGULAR)?;
+            open(path, mode)
+        },
     }
-    Ok(length)
 }
 
 pub fn sys_open(path: VirtAddr, open_mode: usize) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
-    let proc_inner = proc.get_inner();
+    let mut proc_inner = proc.get_inner();
     let open_mode = OpenMode::from_bits_truncate(open_mode);
-    let path = path.read_cstr()?.0;
-    let path: Path = if path.starts_with('/') {
-        path.into()
-    } else {
-        proc_inner.cwd.concat(&path.into())
-    };
+    let path = read_user_str(&mut proc_inner.mem_layout, path, USER_STR_MAX)?;
+    let dir_only = path.ends_with('/');
+    let path = user_path(&proc_inner, path)?;
+    let umask = proc_inner.umask;
     // path.reduce();
     // open procfs need self inner, so unlock first
     drop(proc_inner);
-    let file = open(&path, open_mode)?;
+    let file = open_masked(None, &path, open_mode, umask, dir_only)?;
     Ok(get_processor().current().unwrap().get_inner().register_file(file)?.0)
 }
 
+/// Path from user, relative ones to cwd.
+pub(super) fn user_path(proc_inner: &PCBInner, path: String) -> Result<Path, ErrorNum> {
+    let absolute = path.starts_with('/');
+    let path = Path::new_s(path)?;
+    if absolute {Ok(path)} else {proc_inner.cwd.concat(&path)}
+}
+
+/// Where `path` of an *at call starts from: the directory `dirfd`, or for AT_FDCWD none, and `path` is made absolute
+/// against the cwd.
+pub(super) fn at_dir(proc_inner: &PCBInner, dirfd: FileDescriptor, path: String) -> Result<(Option<Arc<dyn File>>, Path), ErrorNum> {
+    if dirfd.0 != AT_FDCWD {
+        return Ok((Some(proc_inner.get_file(dirfd)?.as_dir()?.as_file()), Path::new_s(path)?));
+    }
+    Ok((None, user_path(proc_inner, path)?))
+}
+
 pub fn sys_openat(dirfd: FileDescriptor, path: VirtAddr, open_mode: usize) -> Result<usize, ErrorNum>  {
     let proc = get_processor().current().unwrap();
-    let proc_inner = proc.get_inner();
+    let mut proc_inner = proc.get_inner();
     let open_mode = OpenMode::from_bits_truncate(open_mode);
-    let (path, _) = path.read_cstr()?;
-    let path: Path = path.into();
-    let dir_file = proc_inner.get_file(dirfd)?.as_dir()?;
+    let path = read_user_str(&mut proc_inner.mem_layout, path, USER_STR_MAX)?;
+    let dir_only = path.ends_with('/');
+    let (dir_file, path) = at_dir(&proc_inner, dirfd, path)?;
+    let umask = proc_inner.umask;
     // open procfs need self inner, so unlock first
     drop(proc_inner);
-    let file = open_at(dir_file.as_file(), &path, open_mode)?;
+    let file = open_masked(dir_file, &path, open_mode, umask, dir_only)?;
     get_processor().current().unwrap().get_inner().register_file(file).map(|fd| fd.0)
 }
 
+/// Closed even if it fails, the error is from writing out what the file held back.
 pub fn sys_close(fd: FileDescriptor) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
+    let file = proc_inner.get_file(fd)?;
     proc_inner.close_file(fd)?;
+    drop(proc_inner);
+    file.sync()?;
+    Ok(0)
+}
+
+/// Written data of the file that's still held back goes where it's going.
+pub fn sys_fsync(fd: FileDescriptor) -> Result<usize, ErrorNum> {
+    let file = get_processor().current().unwrap().get_inner().get_file(fd)?;
+    file.sync()?;
     Ok(0)
 }
 
@@ -115,83 +236,214 @@ pub fn sys_fork() -> Result<usize, ErrorNum> {
     let pid = child.pid.0;
     child_inner.trap_context().a0 = 0;
     child_inner.trap_context().a1 = 0;
+    trace(proc.pid.0, TraceEvent::Fork { child: pid });
     enqueue(child.clone());
     Ok(pid)
 }
 
-pub fn sys_exec(elf_path: VirtAddr, argv: VirtAddr) -> Result<usize, ErrorNum> {
+/// Read a NULL terminated array of C strings from user, e.g. argv or envp. Each string keep its trailing NUL, and it
+/// and its pointer are taken from `budget`, E2BIG when it runs out, so a huge argv is refused before it's all copied in.
+fn read_cstr_array(mem_layout: &mut MemLayout, mut p: VirtAddr, res: &mut Vec<Vec<u8>>, budget: &mut usize) -> Result<(), ErrorNum> {
+    if p.0 == 0 {
+        return Ok(());
+    }
+    loop {
+        let str_ptr: VirtAddr = read_user(mem_layout, p)?;
+        if str_ptr.0 == 0 {break;}
+        let mut bytes = read_user_cstr(mem_layout, str_ptr, USER_STR_MAX)?;
+        bytes.push(0);
+        *budget = budget.checked_sub(bytes.len() + size_of::<VirtAddr>()).ok_or(ErrorNum::E2BIG)?;
+        res.push(bytes);
+        p += size_of::<VirtAddr>();
+    }
+    Ok(())
+}
+
+pub fn sys_exec(elf_path: VirtAddr, argv: VirtAddr, envp: VirtAddr) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
-    let path = elf_path.read_cstr()?.0;
+    let path = read_user_str(&mut proc_inner.mem_layout, elf_path, USER_STR_MAX)?;
     debug!("proc {} exec {:?}", proc.pid, path);
-    let path: Path = if path.starts_with('/') {
-        path.into()
-    } else {
-        proc_inner.cwd.concat(&path.into())
-    };
+    let path = user_path(&proc_inner, path)?;
     verbose!("Init exec path: {:?}", path);
     let mut args: Vec<Vec<u8>> = Vec::new();
-
-    let mut exec_path = path.clone();
-    // check if it's shabang
-    let file = open(&path, OpenMode::READ | OpenMode::EXEC)?;
-    let shebang = file.read(2)?;
-    if shebang[0] == b'#' && shebang[1] == b'!' {
-        info!("shabang discoverd.");
-        
-        let mut shebang_exec: Vec<u8> = Vec::new();
-        loop {
-            let byte = file.read(1)?[0];
-            if byte == b' ' {
-                continue;
-            } else if byte != b'\r' && byte != b'\n' {
-                shebang_exec.push(byte);
-            } else {
-                break;
-            }
-        }
-        let shebang_exec_str = String::from_utf8(shebang_exec.clone()).map_err(|_| ErrorNum::ENOENT)?;
-        exec_path = shebang_exec_str.into();
-        
-        shebang_exec.push(0);
-        args.push(shebang_exec);
-    }
-
     let mut name_bytes = format!("{:?}", path).into_bytes();
     name_bytes.push(b'\0');
     args.push(name_bytes);
-    let mut p = argv;
-    if p.0 != 0 {
-        let _intr_guard = get_processor();
-        push_sum_on();
-        loop {
-            let argv_str: VirtAddr = unsafe{ p.read_volatile() };
-            if argv_str.0 == 0 {break;}
-            let mut bytes = argv_str.read_cstr_raw(1023);
-            bytes.push(0);
-            args.push(bytes);
-            p += size_of::<VirtAddr>();
-        }
-        pop_sum_on();
-    }
+    let mut budget = ARG_MAX;
+    read_cstr_array(&mut proc_inner.mem_layout, argv, &mut args, &mut budget)?;
+    let mut envs: Vec<Vec<u8>> = Vec::new();
+    read_cstr_array(&mut proc_inner.mem_layout, envp, &mut envs, &mut budget)?;
 
     for (idx, s) in args.iter().enumerate() {
         debug!("argv {} : {:?}", idx, String::from_utf8(s.clone()));
     }
+    for (idx, s) in envs.iter().enumerate() {
+        debug!("envp {} : {:?}", idx, String::from_utf8(s.clone()));
+    }
 
-    let elf_file = open(&exec_path, OpenMode::SYS)?.as_regular()?;
+    let trace_path = format!("{:?}", path);
+    let (elf_file, args) = resolve_exec(&proc_inner.cwd, path, args)?;
     let arg_count = args.len();
-    proc_inner.exec(elf_file, args)?;
+    proc_inner.exec(elf_file, args, envs)?;
+    let dirty = proc_inner.mem_layout.take_unsynced();
+    drop(proc_inner);
+    if let Err(e) = sync_dirty(dirty) {
+        warning!("Shared mapping writeback failed on exec: {:?}", e);
+    }
+    trace(proc.pid.0, TraceEvent::Exec { path: trace_path });
     Ok(arg_count)
 }
 
+/// fork then exec in one go, without copying our address space only to throw it away. `actions` points to
+/// `action_count` SyscallSpawnAction for the child's files. Returns child pid.
+pub fn sys_spawn(elf_path: VirtAddr, argv: VirtAddr, envp: VirtAddr, actions: VirtAddr, action_count: usize) -> Result<usize, ErrorNum> {
+    if action_count > MAX_SPAWN_ACTIONS {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let path = read_user_str(&mut proc_inner.mem_layout, elf_path, USER_STR_MAX)?;
+    let path = user_path(&proc_inner, path)?;
+    let mut args: Vec<Vec<u8>> = Vec::new();
+    let mut name_bytes = format!("{:?}", path).into_bytes();
+    name_bytes.push(b'\0');
+    args.push(name_bytes);
+    let mut budget = ARG_MAX;
+    read_cstr_array(&mut proc_inner.mem_layout, argv, &mut args, &mut budget)?;
+    let mut envs: Vec<Vec<u8>> = Vec::new();
+    read_cstr_array(&mut proc_inner.mem_layout, envp, &mut envs, &mut budget)?;
+    // read everything from user now, the child has a different address space
+    let mut file_actions = Vec::new();
+    for i in 0..action_count {
+        let action: SyscallSpawnAction = read_user(&mut proc_inner.mem_layout, actions + i * size_of::<SyscallSpawnAction>())?;
+        let open_path = if action.op == SPAWN_OPEN {
+            let open_path = read_user_str(&mut proc_inner.mem_layout, action.path.into(), USER_STR_MAX)?;
+            Some((open_path.ends_with('/'), user_path(&proc_inner, open_path)?))
+        } else {
+            None
+        };
+        file_actions.push((action, open_path));
+    }
+    let cwd = proc_inner.cwd.clone();
+    // open procfs need self inner, so unlock first
+    drop(proc_inner);
+
+    let trace_path = format!("{:?}", path);
+    let (elf_file, args) = resolve_exec(&cwd, path, args)?;
+    let child = proc.spawn();
+    // replaced files are closed after the child's lock, declared first to be dropped last on error too
+    let mut displaced = Vec::new();
+    let mut child_inner = child.get_inner();
+    for (action, open_path) in file_actions {
+        let fd = FileDescriptor::from(action.fd);
+        match action.op {
+            SPAWN_CLOSE => {
+                child_inner.files.remove(&fd).ok_or(ErrorNum::EBADFD)?;
+            },
+            SPAWN_DUP2 => {
+                let file = child_inner.get_file(FileDescriptor::from(action.src_fd))?;
+                displaced.push(child_inner.install_file(fd, file)?);
+            },
+            SPAWN_OPEN => {
+                let (dir_only, open_path) = open_path.unwrap();
+                let file = open_masked(None, &open_path, OpenMode::from_bits_truncate(action.mode), child_inner.umask, dir_only)?;
+                displaced.push(child_inner.install_file(fd, file)?);
+            },
+            _ => return Err(ErrorNum::EINVAL),
+        }
+    }
+    child_inner.exec(elf_file, args, envs)?;
+    let dirty = child_inner.mem_layout.take_unsynced();
+    drop(child_inner);
+    drop(displaced);
+    if let Err(e) = sync_dirty(dirty) {
+        warning!("Shared mapping writeback failed on exec: {:?}", e);
+    }
+
+    let pid = child.pid.0;
+    proc.get_inner().children.push_back(child.clone());
+    trace(proc.pid.0, TraceEvent::Fork { child: pid });
+    trace(pid, TraceEvent::Exec { path: trace_path });
+    enqueue(child);
+    Ok(pid)
+}
+
 pub fn sys_exit(exit_code: isize) -> Result<usize, ErrorNum> {
+    // before the processor guard exit_switch runs under, closing files may sleep
+    let proc = get_processor().current().unwrap();
+    let (files, segments, dirty) = proc.get_inner().teardown();
+    drop(files);
+    drop(segments);
+    if let Err(e) = sync_dirty(dirty) {
+        warning!("Shared mapping writeback failed on exit: {:?}", e);
+    }
+    drop(proc);
     let processor = get_processor();
     info!("Application {} exited with code {:}", processor.current().unwrap().pid, exit_code);
     processor.exit_switch(exit_code);
     // unreachable!("This part should be unreachable. Go check __switch.")
 }
 
+/// Exit every thread of our group: the others get SIGKILL, then we exit as sys_exit.
+pub fn sys_exit_group(exit_code: isize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    for member in thread_group(proc.tgid).into_iter().filter(|member| member.pid != proc.pid) {
+        let _ = member.get_inner().recv_signal(SignalNum::SIGKILL);
+        wake_up(member.pid);
+    }
+    // exit_switch never returns, don't keep a reference on the stack
+    drop(proc);
+    sys_exit(exit_code)
+}
+
+/// New session and group led by the caller, without a controlling terminal. EPERM for a group leader, its group
+/// would be split between two sessions.
+pub fn sys_setsid() -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if proc_inner.pgid == proc.pid {
+        return Err(ErrorNum::EPERM);
+    }
+    proc_inner.sid = proc.pid;
+    proc_inner.pgid = proc.pid;
+    Ok(proc.pid.0)
+}
+
+/// Move ourself or a child into group `pgid` of our session, or a new one if it's its pid. 0 means the caller
+/// for `pid` and `pid` for `pgid`.
+pub fn sys_setpgid(pid: ProcessID, pgid: ProcessID) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let target = if pid.0 == 0 || pid == proc.pid {
+        proc.clone()
+    } else {
+        proc.get_inner().children.iter().find(|child| child.pid == pid).cloned().ok_or(ErrorNum::ESRCH)?
+    };
+    let pgid = if pgid.0 == 0 { target.pid } else { pgid };
+    let sid = proc.get_inner().sid;
+    if pgid != target.pid && !pgrp_in_session(pgid, sid) {
+        return Err(ErrorNum::EPERM);
+    }
+    let mut target_inner = target.get_inner();
+    // session leaders stay in their own group
+    if target_inner.sid != sid || target_inner.sid == target.pid {
+        return Err(ErrorNum::EPERM);
+    }
+    target_inner.pgid = pgid;
+    Ok(0)
+}
+
+/// 0 for the caller.
+pub fn sys_getpgid(pid: ProcessID) -> Result<usize, ErrorNum> {
+    let target = if pid.0 == 0 {
+        get_processor().current().unwrap()
+    } else {
+        get_process(pid)?
+    };
+    let pgid = target.get_inner().pgid;
+    Ok(pgid.0)
+}
+
 pub fn sys_mmap(tgt_addr: VirtAddr, length: usize, prot: MMAPProt, flag: MMAPFlag, fd: FileDescriptor, offset: usize) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
@@ -206,6 +458,8 @@ pub fn sys_mmap(tgt_addr: VirtAddr, length: usize, prot: MMAPProt, flag: MMAPFla
     } else {
         proc_inner.mem_layout.get_space(length)?.into()
     };
+    // what's reserved is the whole pages, not `length`
+    proc_inner.check_as_growth((tgt_pos + length).to_vpn_ceil().0.saturating_sub(VirtPageNum::from(tgt_pos).0) * PAGE_SIZE)?;
 
     if flag.contains(MMAPFlag::ANONYMOUS) {
         if fd != FileDescriptor::from(usize::MAX) {
@@ -218,6 +472,10 @@ pub fn sys_mmap(tgt_addr: VirtAddr, length: usize, prot: MMAPProt, flag: MMAPFla
             length
         ));
         proc_inner.mem_layout.do_map();
+        if flag.contains(MMAPFlag::POPULATE) {
+            // like linux, the mapping stands even if populating runs out of memory
+            proc_inner.mem_layout.populate(tgt_pos, length).ok();
+        }
         Ok(VirtAddr::from(tgt_pos).0)
 
     } else {
@@ -246,57 +504,75 @@ pub fn sys_mmap(tgt_addr: VirtAddr, length: usize, prot: MMAPProt, flag: MMAPFla
             }
         )?);
         proc_inner.mem_layout.do_map();
+        if flag.contains(MMAPFlag::POPULATE) {
+            proc_inner.mem_layout.populate(tgt_pos, length).ok();
+        }
         Ok(VirtAddr::from(tgt_pos).0)
     }
 }
 
 pub fn sys_waitpid(pid: isize, exit_code: VirtAddr) -> Result<usize, ErrorNum> {
+    let (pid, corpse_code) = wait_child(pid, false)?.unwrap();
+    if exit_code.0 != 0 {
+        write_user(&mut get_processor().current().unwrap().get_inner().mem_layout, exit_code, &corpse_code)?;
+    }
+    Ok(pid)
+}
+
+/// Reap a child that's done, returns its pid and exit code. Sleeps until there's one, or with `nohang` returns None.
+pub(super) fn wait_child(pid: isize, nohang: bool) -> Result<Option<(usize, isize)>, ErrorNum> {
     info!("Waitpid called for {} from {}", pid, get_processor().current().unwrap().pid);
     loop {
         let proc = get_processor().current().unwrap();
         let mut pcb_inner = proc.get_inner();
 
-        if !pcb_inner.pending_signal.is_empty() {
-            warning!("Recv Signal, Waitpid failed.");
-            return Err(ErrorNum::EINTR);
-        }
-
+        // a thread group is done once the leader and all other threads exited
         let mut zombies = pcb_inner.children.drain_filter(
             |child| -> bool {
-                child.get_inner().status == ProcessStatus::Zombie
+                let exited = child.get_inner().status == ProcessStatus::Zombie;
+                exited && thread_group(child.tgid).is_empty()
             }
         ).collect::<LinkedList<_>>();
 
         if let Some(corpse) = zombies.pop_front() {
             pcb_inner.children.append(&mut zombies);
-            let corpse_inner = corpse.get_inner();
-            // make sure its data got released
-            // 1 here, 2 in scheduler context (maybe)
-            // NOTE: in multicore, it can be referenced by other cores.
-            // assert!(Arc::strong_count(&corpse) <= 2, "Zombie {:?} was referenced by something else, strong_count = {}", corpse.pid, Arc::strong_count(&corpse));
+            let corpse_code = corpse.get_inner().exit_code.unwrap();
             info!("Zombie {:?} was killed.", corpse.pid);
-            if exit_code.0 != 0 {
-                if exit_code.write_user(&pcb_inner.mem_layout.pagetable, &corpse_inner.exit_code.unwrap()).is_err() {
-                    pcb_inner.recv_signal(SignalNum::SIGSEGV).unwrap();
-                    return Err(ErrorNum::EPERM);
-                }
-            }
-            return Ok(corpse.pid.0);
+            let pid = corpse.pid.0;
+            corpse.release();
+            return Ok(Some((pid, corpse_code)));
+        } else if pcb_inner.has_deliverable_signal() {
+            // checked after reaping, SIGCHLD of the very child we wait for must not fail us
+            warning!("Recv Signal, Waitpid failed.");
+            return Err(ErrorNum::EINTR);
+        } else if nohang {
+            return Ok(None);
         } else {
-            drop(pcb_inner);
             // verbose!("Waitpid not found");
-            get_processor().suspend_switch();
+            // pcb_inner is released after we are in queue, exiting child will acquire it before waking us
+            proc.child_exit.sleep_on(pcb_inner);
         }
     }
 }
 
+/// Signal to a thread group goes to the first member that has it enabled, leader first.
 pub fn sys_signal(target_pid: ProcessID, signum: usize) -> Result<usize, ErrorNum> {
-    let to_recv = get_process(target_pid)?;
-    let mut to_recv_inner = to_recv.get_inner();
+    let mut targets = thread_group(target_pid);
+    if targets.is_empty() {
+        targets.push(get_process(target_pid)?);
+    }
     // TODO: check permission
     let signal = SignalNum::try_from(signum)?;
-    to_recv_inner.recv_signal(signal)?;
-    Ok(0)
+    let mut res = Err(ErrorNum::ESIGDISABLED);
+    for to_recv in targets {
+        res = to_recv.get_inner().recv_signal(signal);
+        if res.is_ok() {
+            // interrupt blocking syscalls (e.g. waitpid)
+            wake_up(to_recv.pid);
+            break;
+        }
+    }
+    res.map(|_| 0)
 }
 
 pub fn sys_sigaction(signum: usize, handler: VirtAddr) -> Result<usize, ErrorNum> {
@@ -321,8 +597,73 @@ pub fn sys_sigreturn() -> Result<usize, ErrorNum> {
     }
 }
 
+/// Signals in SignalNum::bit set `set` stay pending instead of being delivered, or not any more, as `how` says.
+/// SIGKILL and SIGSTOP are delivered anyway. Returns the old set.
+pub fn sys_sigprocmask(how: usize, set: u64) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let old = proc_inner.signal_blocked;
+    proc_inner.signal_blocked = match how {
+        SIG_BLOCK => old | set,
+        SIG_UNBLOCK => old & !set,
+        SIG_SETMASK => set,
+        _ => return Err(ErrorNum::EINVAL),
+    };
+    Ok(old as usize)
+}
+
+/// New signalfd for SignalNum::bit set `mask` if `fd` is usize::MAX (-1), else change the mask of that one.
+/// Returns the fd.
+pub fn sys_signalfd(fd: usize, mask: u64, flags: usize) -> Result<usize, ErrorNum> {
+    if flags & !SFD_NONBLOCK != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if fd != usize::MAX {
+        let file = proc_inner.get_file(FileDescriptor::from(fd))?;
+        file.as_any().downcast::<SignalFd>().map_err(|_| ErrorNum::EINVAL)?.set_mask(mask);
+        return Ok(fd);
+    }
+    proc_inner.register_file(SignalFd::new(mask, flags & SFD_NONBLOCK != 0)).map(|fd| fd.0)
+}
+
+/// New inotify instance with no watches, returns its fd.
+pub fn sys_inotify_init(flags: usize) -> Result<usize, ErrorNum> {
+    if flags & !IN_NONBLOCK != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let file = Inotify::new(flags & IN_NONBLOCK != 0);
+    get_processor().current().unwrap().get_inner().register_file(file).map(|fd| fd.0)
+}
+
+/// Watch `path` for the events in `mask`, symlinks followed. Returns the watch descriptor, the same one again if
+/// it's watched already.
+pub fn sys_inotify_add_watch(fd: FileDescriptor, path: VirtAddr, mask: u32) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let inotify = proc_inner.get_file(fd)?.as_any().downcast::<Inotify>().map_err(|_| ErrorNum::EINVAL)?;
+    let path = read_user_str(&mut proc_inner.mem_layout, path, USER_STR_MAX)?;
+    let path = user_path(&proc_inner, path)?;
+    drop(proc_inner);
+    let file = open(&path, OpenMode::SYS)?;
+    if !proc.get_inner().cred.permits(file.owner()?, Permission::OTHER_R, true) {
+        return Err(ErrorNum::EACCES);
+    }
+    inotify.add_watch(&file, mask).map(|wd| wd as usize)
+}
+
+pub fn sys_inotify_rm_watch(fd: FileDescriptor, wd: u32) -> Result<usize, ErrorNum> {
+    let file = get_processor().current().unwrap().get_inner().get_file(fd)?;
+    file.as_any().downcast::<Inotify>().map_err(|_| ErrorNum::EINVAL)?.rm_watch(wd)?;
+    Ok(0)
+}
+
 pub fn sys_getcwd(buf: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
+    if length == 0 {
+        return Err(ErrorNum::EINVAL);
+    }
     let mut proc_inner = proc.get_inner();
     let path = format!("{:?}", proc_inner.cwd);
     let mut path = path.into_bytes();
@@ -331,33 +672,39 @@ pub fn sys_getcwd(buf: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
         path = path[..length-1].to_vec();
     }
     path.push(0);
-    let _int_guard = get_processor();
-    if buf.write_user_data(&proc_inner.mem_layout.pagetable, path).is_err() {
-        proc_inner.recv_signal(SignalNum::SIGSEGV).unwrap();
-    }
+    copy_to_user(&mut proc_inner.mem_layout, buf, &path)?;
     Ok(buf.0)
 }
 
 pub fn sys_chdir(buf: VirtAddr) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
-    let path = buf.read_cstr()?.0;
-    let mut path: Path = if path.starts_with('/') {
-        path.into()
-    } else {
-        proc_inner.cwd.concat(&path.into())
-    };
+    let path = read_user_str(&mut proc_inner.mem_layout, buf, USER_STR_MAX)?;
+    let mut path = user_path(&proc_inner, path)?;
     open(&path, OpenMode::SYS)?.as_dir()?; // check if it's actually a dir
     path.reduce();
     proc_inner.cwd = path;
     Ok(0)
 }
 
+/// Move the program break by `increment`, returns the old one.
 pub fn sys_sbrk(increment: isize) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
-    let data_segment = proc_inner.mem_layout.get_segment((proc_inner.data_end - 1).into())?.as_program()?;
-    data_segment.alter_size(increment, &mut proc_inner.mem_layout.pagetable)
+    let old_brk = proc_inner.brk;
+    let new_brk = old_brk.0.checked_add_signed(increment).ok_or(ErrorNum::ENOMEM)?;
+    proc_inner.set_brk(new_brk.into())?;
+    Ok(old_brk.0)
+}
+
+/// Set the program break to `addr`, returns the new one. 0 or anything below the heap start just asks for it.
+pub fn sys_brk(addr: VirtAddr) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if addr >= proc_inner.heap_start {
+        proc_inner.set_brk(addr)?;
+    }
+    Ok(proc_inner.brk.0)
 }
 
 pub fn sys_getdents(fd: FileDescriptor, buf: VirtAddr, count: usize) -> Result<usize, ErrorNum>{
@@ -370,21 +717,56 @@ pub fn sys_getdents(fd: FileDescriptor, buf: VirtAddr, count: usize) -> Result<u
     let dirents = dir_file.read_dirent()?;
     let mut proc_inner = proc.get_inner();
     
+    if buf.0 % core::mem::align_of::<SyscallDirent>() != 0 {
+        return Err(ErrorNum::EFAULT);
+    }
+    let mut raw = Vec::new();
     let mut written = 0;
-    for (idx, dirent)in dirents.iter().enumerate() {
-        if idx >= count {
-            break;
-        }
+    for dirent in dirents.iter().take(count) {
         let syscall_dirent = SyscallDirent::from(dirent.to_owned());
-        if (buf + idx * size_of::<SyscallDirent>()).write_user(&(proc_inner.mem_layout.pagetable), &syscall_dirent).is_err() {
-            proc_inner.recv_signal(SignalNum::SIGSEGV).unwrap();
-            return Err(ErrorNum::EPERM);
-        }
+        raw.extend_from_slice(bytes_of(&syscall_dirent));
         written += 1;
     }
+    copy_to_user(&mut proc_inner.mem_layout, buf, &raw)?;
     Ok(written)
 }
 
+/// Fill `buf` with as many linux_dirent64 records as fit in `count` bytes, continuing from where last call stopped.
+/// Return bytes written, 0 at end of directory, EINVAL if not even the next entry fits.
+/// The cursor is the cookie of the last entry returned, see DirFile::read_dirent_cookies.
+pub fn sys_getdents64(fd: FileDescriptor, buf: VirtAddr, count: usize) -> Result<usize, ErrorNum> {
+    if buf.0 % 8 != 0 {
+        return Err(ErrorNum::EFAULT);
+    }
+    let proc = get_processor().current().unwrap();
+    let proc_inner = proc.get_inner();
+    let dir_file = proc_inner.get_file(fd)?.as_dir()?;
+
+    // avoid procfs deadlock
+    drop(proc_inner);
+    let mut cursor = dir_file.dir_cursor();
+    let dirents = dir_file.read_dirent_cookies()?;
+
+    let mut raw = Vec::new();
+    let mut pending = dirents.iter().skip_while(|(cookie, _)| *cookie <= cursor).peekable();
+    while let Some((cookie, dirent)) = pending.peek() {
+        let record = encode_dirent64(dirent, *cookie);
+        if raw.len() + record.len() > count {
+            break;
+        }
+        raw.extend_from_slice(&record);
+        cursor = *cookie;
+        pending.next();
+    }
+    if raw.is_empty() && pending.peek().is_some() {
+        return Err(ErrorNum::EINVAL);
+    }
+    let mut proc_inner = proc.get_inner();
+    copy_to_user(&mut proc_inner.mem_layout, buf, &raw)?;
+    dir_file.set_dir_cursor(cursor);
+    Ok(raw.len())
+}
+
 pub fn sys_pipe(ret: VirtAddr) -> Result<usize, ErrorNum> {
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
@@ -394,12 +776,38 @@ pub fn sys_pipe(ret: VirtAddr) -> Result<usize, ErrorNum> {
     let w_fd = proc_inner.register_file(w)?;
 
     let result = [r_fd, w_fd];
-    if ret.write_user(&proc_inner.mem_layout.pagetable, &result).is_err() {
-        proc_inner.recv_signal(SignalNum::SIGSEGV).unwrap();
-        Err(ErrorNum::EPERM)
-    } else {
-        Ok(0)
+    if let Err(e) = write_user(&mut proc_inner.mem_layout, ret, &result) {
+        proc_inner.close_file(r_fd).unwrap();
+        proc_inner.close_file(w_fd).unwrap();
+        return Err(e);
+    }
+    Ok(0)
+}
+
+/// New eventfd counting from `initval`, returns its fd.
+pub fn sys_eventfd(initval: usize, flags: usize) -> Result<usize, ErrorNum> {
+    if flags & !(EFD_SEMAPHORE | EFD_NONBLOCK) != 0 {
+        return Err(ErrorNum::EINVAL);
     }
+    let file = EventFd::new(initval as u64, flags & EFD_SEMAPHORE != 0, flags & EFD_NONBLOCK != 0);
+    get_processor().current().unwrap().get_inner().register_file(file).map(|fd| fd.0)
+}
+
+/// New disarmed timerfd, returns its fd.
+pub fn sys_timerfd_create(flags: usize) -> Result<usize, ErrorNum> {
+    if flags & !TFD_NONBLOCK != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let file = TimerFd::new(flags & TFD_NONBLOCK != 0);
+    get_processor().current().unwrap().get_inner().register_file(file).map(|fd| fd.0)
+}
+
+/// Arm the timerfd to expire in `initial_ms` then every `interval_ms`, 0 `initial_ms` disarms. Returns ms that
+/// were left to the old setting's next expiry.
+pub fn sys_timerfd_settime(fd: FileDescriptor, initial_ms: usize, interval_ms: usize) -> Result<usize, ErrorNum> {
+    let file = get_processor().current().unwrap().get_inner().get_file(fd)?;
+    let timer = file.as_any().downcast::<TimerFd>().map_err(|_| ErrorNum::EINVAL)?;
+    Ok(timer.set_time(initial_ms, interval_ms))
 }
 
 pub fn sys_sysstat(stat_ptr: VirtAddr) -> Result<usize, ErrorNum> {
@@ -416,9 +824,7 @@ pub fn sys_sysstat(stat_ptr: VirtAddr) -> Result<usize, ErrorNum> {
     };
     let proc = get_processor().current().unwrap();
     let mut proc_inner = proc.get_inner();
-    if stat_ptr.write_user(&proc_inner.mem_layout.pagetable, &stat).is_err() {
-        proc_inner.recv_signal(SignalNum::SIGSEGV).unwrap();
-    }
+    write_user(&mut proc_inner.mem_layout, stat_ptr, &stat)?;
     Ok(0)
 }
 
@@ -426,49 +832,554 @@ pub fn sys_munmap(head_ptr: VirtAddr, length: usize) -> Result<usize, ErrorNum>
     let pcb_guard = get_processor().current().unwrap();
     let mut pcb = pcb_guard.get_inner();
     pcb.mem_layout.unmap_vma(head_ptr, length)?;
+    let dirty = pcb.mem_layout.take_unsynced();
+    drop(pcb);
+    sync_dirty(dirty)?;
+    Ok(0)
+}
+
+/// Fault in [head, head + length) and keep it resident. ENOMEM if part of it isn't mapped or over RLIMIT_MEMLOCK.
+pub fn sys_mlock(head_ptr: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
+    let pcb_guard = get_processor().current().unwrap();
+    let mut pcb = pcb_guard.get_inner();
+    pcb.mlock(head_ptr, length)?;
+    Ok(0)
+}
+
+pub fn sys_munlock(head_ptr: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
+    let pcb_guard = get_processor().current().unwrap();
+    let mut pcb = pcb_guard.get_inner();
+    pcb.mem_layout.munlock(head_ptr, length)?;
+    Ok(0)
+}
+
+/// Writeback is synchronous, so flags make no difference.
+pub fn sys_msync(head_ptr: VirtAddr, length: usize, _flags: usize) -> Result<usize, ErrorNum> {
+    let pcb_guard = get_processor().current().unwrap();
+    let mut pcb = pcb_guard.get_inner();
+    pcb.mem_layout.msync(head_ptr, length)?;
+    let dirty = pcb.mem_layout.take_unsynced();
+    drop(pcb);
+    sync_dirty(dirty)?;
     Ok(0)
 }
 
 pub fn sys_ioctl(fd: FileDescriptor, op: usize, buf: VirtAddr, length: usize, target: VirtAddr, tgt_size: usize) -> Result<usize, ErrorNum> {
-    let file = get_processor().current().unwrap().get_inner().get_file(fd)?.clone().as_char()?;
-    let data = unsafe{ buf.read_data(length) };
-    let res = file.ioctl(op, data)?;
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let file = proc_inner.get_file(fd)?.clone();
+    let data = copy_from_user(&mut proc_inner.mem_layout, buf, length)?;
+    drop(proc_inner);
+    let res = if op == IOCTL_GET_ABI_VERSION {
+        ioctl_res(op, &IOCtlVersion{version: IOCTL_ABI_VERSION as u32})
+    } else {
+        file.ioctl(op, data)?
+    };
     let res_len = res.len();
     if res_len > tgt_size {
         return Err(ErrorNum::EOVERFLOW);
     }
-    unsafe{target.write_data(res)};
+    copy_to_user(&mut proc.get_inner().mem_layout, target, &res)?;
     Ok(res_len)
 }
 
 pub fn sys_delete(buf: VirtAddr) -> Result<usize, ErrorNum> {
-    let (path, _) = buf.read_cstr()?;
-    let path = Path::from(path);
+    let path = read_user_str(&mut get_processor().current().unwrap().get_inner().mem_layout, buf, USER_STR_MAX)?;
+    let path = Path::new_s(path)?;
     delete(&path)?;
     Ok(0)
 }
 
 pub fn sys_mkdir(buf: VirtAddr, permission: Permission) -> Result<usize, ErrorNum> {
-    let (path, _) = buf.read_cstr()?;
+    let path = read_user_str(&mut get_processor().current().unwrap().get_inner().mem_layout, buf, USER_STR_MAX)?;
     let prefix = if !path.starts_with('/') {
         get_processor().current().unwrap().get_inner().cwd.clone()
     } else {
         Path::root()
     };
-    let path = prefix.concat(&Path::from(path));
-    make_file(&path, permission, FileType::DIR)?;
+    let path = prefix.concat(&Path::new_s(path)?)?;
+    let umask = get_processor().current().unwrap().get_inner().umask;
+    make_file(&path, permission - umask, FileType::DIR)?;
+    Ok(0)
+}
+
+/// Path from user, relative ones to cwd. EPERM if the caller isn't privileged, for mount and umount.
+fn read_mount_path(buf: VirtAddr) -> Result<Path, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if !proc_inner.cred.privileged() {
+        return Err(ErrorNum::EPERM);
+    }
+    let path = read_user_str(&mut proc_inner.mem_layout, buf, USER_STR_MAX)?;
+    user_path(&proc_inner, path)
+}
+
+/// Only MOUNT_BIND for now: dir `source` shows up at `target` too, in the caller's mount namespace.
+pub fn sys_mount(source: VirtAddr, target: VirtAddr, flags: usize) -> Result<usize, ErrorNum> {
+    if flags != MOUNT_BIND {
+        return Err(ErrorNum::EINVAL);
+    }
+    let source = read_mount_path(source)?;
+    let target = read_mount_path(target)?;
+    bind_mount(&source, &target)?;
     Ok(0)
 }
 
-pub fn sys_seek(fd: FileDescriptor, offset: usize) -> Result<usize, ErrorNum> {
-    let file = get_processor().current().unwrap().get_inner().get_file(fd)?.clone().as_regular()?;
-    file.seek(offset)
+/// Topmost mount on `target`, a bind mount or a fs, what was below it shows up again. ENOENT if there's none.
+pub fn sys_umount(target: VirtAddr) -> Result<usize, ErrorNum> {
+    let target = read_mount_path(target)?;
+    umount(&target)?;
+    Ok(0)
+}
+
+/// Only CLONE_NEWNS: the caller and children it forks later get their own copy of the mount table.
+pub fn sys_unshare(flags: usize) -> Result<usize, ErrorNum> {
+    if flags != CLONE_NEWNS {
+        return Err(ErrorNum::EINVAL);
+    }
+    if !get_processor().current().unwrap().get_inner().cred.privileged() {
+        return Err(ErrorNum::EPERM);
+    }
+    unshare_mount_ns();
+    Ok(0)
+}
+
+/// lseek. On a directory it moves the getdents64 cursor, offset being a d_off from it. Listing goes on after the
+/// entry that d_off came from, even if that entry has since been removed.
+pub fn sys_seek(fd: FileDescriptor, offset: isize, whence: usize) -> Result<usize, ErrorNum> {
+    let whence = SeekWhence::try_from(whence)?;
+    let file = get_processor().current().unwrap().get_inner().get_file(fd)?;
+    if let Ok(dir) = file.clone().as_dir() {
+        let pos = match whence {
+            SeekWhence::Set | SeekWhence::Cur => whence.resolve(offset, dir.dir_cursor(), 0)?,
+            _ => return Err(ErrorNum::EINVAL),
+        };
+        dir.set_dir_cursor(pos);
+        return Ok(pos);
+    }
+    file.as_regular()?.seek(offset, whence)
 }
 
 pub fn sys_time() -> Result<usize, ErrorNum> {
     Ok(crate::utils::time::get_time_ms() as usize)
 }
 
+/// Grow with a hole or shrink, EINVAL unless it's a regular file open for writing.
+pub fn sys_ftruncate(fd: FileDescriptor, length: usize) -> Result<usize, ErrorNum> {
+    let file = get_processor().current().unwrap().get_inner().get_file(fd)?;
+    if !file.stat()?.open_mode.contains(OpenMode::WRITE) {
+        return Err(ErrorNum::EINVAL);
+    }
+    file.as_regular().map_err(|_| ErrorNum::EINVAL)?.truncate(length)?;
+    Ok(0)
+}
+
+pub fn sys_truncate(path: VirtAddr, length: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let path = read_user_str(&mut proc_inner.mem_layout, path, USER_STR_MAX)?;
+    let path = user_path(&proc_inner, path)?;
+    drop(proc_inner);
+    let file = open(&path, OpenMode::WRITE)?;
+    file.as_regular().map_err(|_| ErrorNum::EINVAL)?.truncate(length)?;
+    Ok(0)
+}
+
+/// Only FALLOC_PUNCH_HOLE | FALLOC_KEEP_SIZE: [offset, offset + length) reads as zero and its whole blocks are freed.
+pub fn sys_fallocate(fd: FileDescriptor, mode: usize, offset: usize, length: usize) -> Result<usize, ErrorNum> {
+    if mode != FALLOC_PUNCH_HOLE | FALLOC_KEEP_SIZE {
+        return Err(ErrorNum::EOPNOTSUPP);
+    }
+    if length == 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let file = get_processor().current().unwrap().get_inner().get_file(fd)?;
+    if !file.stat()?.open_mode.contains(OpenMode::WRITE) {
+        return Err(ErrorNum::EBADF);
+    }
+    file.as_regular().map_err(|_| ErrorNum::ENODEV)?.punch_hole(offset, length)?;
+    Ok(0)
+}
+
+/// Can the caller read, write or execute `path` (relative to `dirfd` like openat), as `mode` asks, without opening
+/// it. EACCES if the permission bits say no, on it or on a dir on the way, EROFS or EACCES if the mount does.
+pub fn sys_faccessat(dirfd: FileDescriptor, path: VirtAddr, mode: usize, flags: usize) -> Result<usize, ErrorNum> {
+    if mode & !(R_OK | W_OK | X_OK) != 0 || flags & !(AT_SYMLINK_NOFOLLOW | AT_EACCESS) != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let path = read_user_str(&mut proc_inner.mem_layout, path, USER_STR_MAX)?;
+    let (dir_file, path) = at_dir(&proc_inner, dirfd, path)?;
+    let cred = proc_inner.cred;
+    // procfs needs self inner, and only the bits are looked at, so no mount or device open checks
+    drop(proc_inner);
+    let open_mode = if flags & AT_SYMLINK_NOFOLLOW != 0 {OpenMode::SYS | OpenMode::NO_FOLLOW} else {OpenMode::SYS};
+    let file = open_searching(dir_file, &path, open_mode, cred, flags & AT_EACCESS != 0)?;
+    let mount = mount_flags(&file.vfs());
+    if mode & W_OK != 0 && mount.contains(MountFlags::RDONLY) {
+        return Err(ErrorNum::EROFS);
+    }
+    if mode & X_OK != 0 && mount.contains(MountFlags::NOEXEC) {
+        return Err(ErrorNum::EACCES);
+    }
+    if !cred.permits(file.owner()?, Permission::from_bits_truncate(mode as u16), flags & AT_EACCESS != 0) {
+        return Err(ErrorNum::EACCES);
+    }
+    Ok(0)
+}
+
+/// Pipes only: O_NONBLOCK of either end with F_GETFL and F_SETFL, buffer size with F_GETPIPE_SZ and F_SETPIPE_SZ.
+/// Sizes past PIPE_MAX_SIZE need privilege.
+pub fn sys_fcntl(fd: FileDescriptor, cmd: usize, arg: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let proc_inner = proc.get_inner();
+    let end = as_pipe_end(proc_inner.get_file(fd)?).ok_or(ErrorNum::EINVAL)?;
+    let privileged = proc_inner.cred.privileged();
+    drop(proc_inner);
+    match cmd {
+        F_GETFL => Ok(if end.nonblock() {O_NONBLOCK} else {0}),
+        F_SETFL => {
+            end.set_nonblock(arg & O_NONBLOCK != 0);
+            Ok(0)
+        },
+        F_GETPIPE_SZ => Ok(end.pipe().ok_or(ErrorNum::EPIPE)?.capacity()),
+        F_SETPIPE_SZ => {
+            if arg > PIPE_MAX_SIZE && !privileged {
+                return Err(ErrorNum::EPERM);
+            }
+            end.pipe().ok_or(ErrorNum::EPIPE)?.set_capacity(arg)
+        },
+        _ => Err(ErrorNum::EINVAL),
+    }
+}
+
+/// Usage of the filesystem `path` is on, as SyscallStatFs.
+pub fn sys_statfs(path: VirtAddr, buf: VirtAddr) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let path = read_user_str(&mut proc_inner.mem_layout, path, USER_STR_MAX)?;
+    let path = user_path(&proc_inner, path)?;
+    drop(proc_inner);
+    let stat: SyscallStatFs = open(&path, OpenMode::SYS)?.vfs().statfs()?.into();
+    write_user(&mut proc.get_inner().mem_layout, buf, &stat)?;
+    Ok(0)
+}
+
+/// Move up to `length` bytes from `fd_in` to `fd_out` without going through user memory, one of them a pipe. Files
+/// are read or written at their cursor. The pipe buffer is bytes rather than pages, so this still copies, just once
+/// and in kernel. Returns bytes moved, 0 when the input pipe is closed and drained or the input file is at its end.
+pub fn sys_splice(fd_in: FileDescriptor, fd_out: FileDescriptor, length: usize, flags: usize) -> Result<usize, ErrorNum> {
+    if flags & !SPLICE_F_NONBLOCK != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let nonblock = flags & SPLICE_F_NONBLOCK != 0;
+    let proc = get_processor().current().unwrap();
+    let proc_inner = proc.get_inner();
+    let file_in = proc_inner.get_file(fd_in)?;
+    let file_out = proc_inner.get_file(fd_out)?;
+    // pipes might block, don't hold pcb lock
+    drop(proc_inner);
+    if length == 0 {
+        return Ok(0);
+    }
+    if let Some(end) = as_pipe_end(file_in.clone()) {
+        return end.pipe().ok_or(ErrorNum::EPIPE)?.splice_to(file_out.as_ref(), length, nonblock || end.nonblock());
+    }
+    let end = as_pipe_end(file_out).ok_or(ErrorNum::EINVAL)?;
+    let pipe = end.pipe().ok_or(ErrorNum::EPIPE)?;
+    let nonblock = nonblock || end.nonblock();
+    // read no more than fits, what's read from the file can't go back
+    let room = pipe.room();
+    if room == 0 && nonblock {
+        return Err(ErrorNum::EAGAIN);
+    }
+    let data = file_in.read(min(length, room.max(1)).min(PAGE_SIZE))?;
+    if data.is_empty() {
+        return Ok(0);
+    }
+    let res = pipe.write(&data, false);
+    let written = *res.as_ref().unwrap_or(&0);
+    if written < data.len() {
+        // reader went away midway, a file can at least be read again from where the pipe stopped
+        if let Ok(regular) = file_in.as_regular() {
+            let _ = regular.seek(-((data.len() - written) as isize), SeekWhence::Cur);
+        }
+    }
+    res
+}
+
+/// Copy from cursor of fd_in to cursor of fd_out without going through user space.
+pub fn sys_copy_file_range(fd_in: FileDescriptor, fd_out: FileDescriptor, length: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let proc_inner = proc.get_inner();
+    let file_in = proc_inner.get_file(fd_in)?.clone().as_regular()?;
+    let file_out = proc_inner.get_file(fd_out)?.clone().as_regular()?;
+    drop(proc_inner);
+    check_open_mode(file_in.clone().as_file(), OpenMode::READ)?;
+    check_open_mode(file_out.clone().as_file(), OpenMode::WRITE)?;
+    file_in.copy_range_to(file_out, length)
+}
+
+/// Up to `count` bytes of regular file fd_in to fd_out, which can be anything written to: a pipe, a tty, another
+/// file. From the cursor of fd_in, or if `offset` isn't null from the usize there, which is then advanced while the
+/// cursor stays. Short when fd_in ends.
+pub fn sys_sendfile(fd_out: FileDescriptor, fd_in: FileDescriptor, offset: VirtAddr, count: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let file_in = proc_inner.get_file(fd_in)?.as_regular()?;
+    let file_out = proc_inner.get_file(fd_out)?;
+    let start = if offset.0 != 0 {
+        Some(read_user::<usize>(&mut proc_inner.mem_layout, offset)?)
+    } else {
+        None
+    };
+    // pipes might block, don't hold pcb lock
+    drop(proc_inner);
+    check_open_mode(file_in.clone().as_file(), OpenMode::READ)?;
+    check_open_mode(file_out.clone(), OpenMode::WRITE)?;
+    let start = match start {
+        Some(start) => start,
+        None => file_in.seek(0, SeekWhence::Cur)?,
+    };
+    // positional, so nobody sharing the cursor sees it move for an `offset` send
+    let sent = copy_range_bounce(
+        |done, len| file_in.read_at(start.checked_add(done).ok_or(ErrorNum::EINVAL)?, len),
+        file_out.as_ref(),
+        count
+    )?;
+    if offset.0 != 0 {
+        write_user(&mut proc.get_inner().mem_layout, offset, &(start + sent))?;
+    } else {
+        file_in.seek((start + sent) as isize, SeekWhence::Set)?;
+    }
+    Ok(sent)
+}
+
+/// EBADF unless `file` was opened for `mode`, for the calls moving data between two fds.
+fn check_open_mode(file: Arc<dyn File>, mode: OpenMode) -> Result<(), ErrorNum> {
+    if file.stat()?.open_mode.contains(mode) {
+        Ok(())
+    } else {
+        Err(ErrorNum::EBADF)
+    }
+}
+
+/// Read the iovec array from user, then translate and pin each entry.
+fn pin_iovec(mem_layout: &mut MemLayout, iov: VirtAddr, iovcnt: usize, writable: bool) -> Result<Vec<UserBuffer>, ErrorNum> {
+    if iovcnt > MAX_IOV {
+        return Err(ErrorNum::EINVAL);
+    }
+    let mut res = Vec::with_capacity(iovcnt);
+    for i in 0..iovcnt {
+        let entry: SyscallIOVec = read_user(mem_layout, iov + i * size_of::<SyscallIOVec>())?;
+        res.push(UserBuffer::new(mem_layout, VirtAddr::from(entry.base), entry.len, writable)?);
+    }
+    Ok(res)
+}
+
+/// Copy between our `local_iov` and `remote_iov` of process `pid`, into the remote one if `to_remote`. Both sides
+/// are pinned through their own pagetable first, so lazy pages get allocated and COW ones broken in the process
+/// written to, and neither pcb is locked while copying. All or nothing: EFAULT if any range is bad, a remote one
+/// included, with nothing copied. Read-only mappings can't be written this way.
+fn pvm_copy(pid: ProcessID, local_iov: VirtAddr, liovcnt: usize, remote_iov: VirtAddr, riovcnt: usize, to_remote: bool) -> Result<usize, ErrorNum> {
+    if riovcnt > MAX_IOV {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if !proc_inner.cred.privileged() {
+        return Err(ErrorNum::EPERM);
+    }
+    let local = UserBuffer::join(&pin_iovec(&mut proc_inner.mem_layout, local_iov, liovcnt, !to_remote)?);
+    let mut remote_ranges = Vec::with_capacity(riovcnt);
+    for i in 0..riovcnt {
+        let entry: SyscallIOVec = read_user(&mut proc_inner.mem_layout, remote_iov + i * size_of::<SyscallIOVec>())?;
+        remote_ranges.push(entry);
+    }
+    drop(proc_inner);
+
+    let target = get_process(pid)?;
+    let mut target_inner = target.get_inner();
+    if target_inner.status == ProcessStatus::Zombie {
+        return Err(ErrorNum::ESRCH);
+    }
+    let mut remote = Vec::with_capacity(riovcnt);
+    for entry in remote_ranges {
+        remote.push(UserBuffer::new(&mut target_inner.mem_layout, VirtAddr::from(entry.base), entry.len, to_remote)?);
+    }
+    drop(target_inner);
+    let remote = UserBuffer::join(&remote);
+
+    let (src, mut dst) = if to_remote {(local, remote)} else {(remote, local)};
+    let mut copied = 0;
+    for chunk in src.chunks() {
+        let len = dst.write_slice(copied, chunk);
+        copied += len;
+        if len < chunk.len() {
+            break;
+        }
+    }
+    Ok(copied)
+}
+
+/// Read `remote_iov` of process `pid` into our `local_iov`, returns bytes copied. Privileged only, for debuggers.
+pub fn sys_pvm_read(pid: ProcessID, local_iov: VirtAddr, liovcnt: usize, remote_iov: VirtAddr, riovcnt: usize) -> Result<usize, ErrorNum> {
+    pvm_copy(pid, local_iov, liovcnt, remote_iov, riovcnt, false)
+}
+
+/// Write our `local_iov` into `remote_iov` of process `pid`, returns bytes copied. Privileged only, for debuggers.
+pub fn sys_pvm_write(pid: ProcessID, local_iov: VirtAddr, liovcnt: usize, remote_iov: VirtAddr, riovcnt: usize) -> Result<usize, ErrorNum> {
+    pvm_copy(pid, local_iov, liovcnt, remote_iov, riovcnt, true)
+}
+
+pub fn sys_readv(fd: FileDescriptor, iov: VirtAddr, iovcnt: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let file = proc_inner.get_file(fd)?.clone();
+    let mut bufs = pin_iovec(&mut proc_inner.mem_layout, iov, iovcnt, true)?;
+    drop(proc_inner);
+    file.readv(&mut bufs)
+}
+
+pub fn sys_writev(fd: FileDescriptor, iov: VirtAddr, iovcnt: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let file = proc_inner.get_file(fd)?.clone();
+    let bufs = pin_iovec(&mut proc_inner.mem_layout, iov, iovcnt, false)?;
+    drop(proc_inner);
+    file.writev(&bufs)
+}
+
+pub fn sys_getrlimit(resource: usize, rlim: VirtAddr) -> Result<usize, ErrorNum> {
+    if resource >= RLIMIT_COUNT {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let limit = proc_inner.rlimits[resource];
+    write_user(&mut proc_inner.mem_layout, rlim, &limit)?;
+    Ok(0)
+}
+
+/// Set the bits taken off the permission of files created from now on, return the old ones.
+pub fn sys_umask(mask: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let old = proc_inner.umask;
+    proc_inner.umask = Permission::from_bits_truncate((mask & 0o777) as u16);
+    Ok(old.bits() as usize)
+}
+
+/// Real, effective and saved uid and gid, as Credentials.
+pub fn sys_getcred(cred: VirtAddr) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let res = proc_inner.cred;
+    write_user(&mut proc_inner.mem_layout, cred, &res)?;
+    Ok(0)
+}
+
+/// ID_UNCHANGED keeps one as it is. Without privilege each can only be set to one of the three we have.
+pub fn sys_setresuid(uid: u32, euid: u32, suid: u32) -> Result<usize, ErrorNum> {
+    get_processor().current().unwrap().get_inner().cred.set_resuid(uid, euid, suid)?;
+    Ok(0)
+}
+
+pub fn sys_setresgid(gid: u32, egid: u32, sgid: u32) -> Result<usize, ErrorNum> {
+    get_processor().current().unwrap().get_inner().cred.set_resgid(gid, egid, sgid)?;
+    Ok(0)
+}
+
+pub fn sys_setrlimit(resource: usize, rlim: VirtAddr) -> Result<usize, ErrorNum> {
+    if resource >= RLIMIT_COUNT {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let limit: RLimit = read_user(&mut proc_inner.mem_layout, rlim)?;
+    limit.validate(&proc_inner.rlimits[resource], rlimit_ceiling(resource))?;
+    proc_inner.rlimits[resource] = limit;
+    Ok(0)
+}
+
+/// Get or set the ParchFS quota of `uid`, see SyscallQuota. Setting is privileged.
+pub fn sys_quotactl(cmd: usize, uid: u32, quota: VirtAddr) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    match cmd {
+        QUOTACTL_GET => {
+            let res = SyscallQuota::from(parch_fs_get_quota(uid)?);
+            write_user(&mut proc.get_inner().mem_layout, quota, &res)?;
+        },
+        QUOTACTL_SET => {
+            let mut proc_inner = proc.get_inner();
+            if !proc_inner.cred.privileged() {
+                return Err(ErrorNum::EPERM);
+            }
+            let limits: SyscallQuota = read_user(&mut proc_inner.mem_layout, quota)?;
+            drop(proc_inner);
+            parch_fs_set_quota(uid, limits.into())?;
+        },
+        _ => return Err(ErrorNum::EINVAL),
+    }
+    Ok(0)
+}
+
+/// Only RUSAGE_SELF (0) for now.
+pub fn sys_getrusage(who: usize, usage_ptr: VirtAddr) -> Result<usize, ErrorNum> {
+    if who != 0 {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    let mem_usage = proc_inner.mem_layout.mem_usage();
+    let faults = proc_inner.mem_layout.faults;
+    let usage = SyscallRUsage {
+        cpu_ticks: proc_inner.cpu_ticks,
+        vm_size: proc_inner.mem_layout.user_size(),
+        rss: mem_usage.resident * PAGE_SIZE,
+        shared: mem_usage.shared * PAGE_SIZE,
+        pss: mem_usage.pss,
+        minor_faults: faults.minor,
+        cow_faults: faults.cow,
+        file_faults: faults.file,
+    };
+    write_user(&mut proc_inner.mem_layout, usage_ptr, &usage)?;
+    Ok(0)
+}
+
+/// Install the syscall filter of this process from `len` bytes of bitmap. Only once, there's no way to loosen or
+/// remove it later.
+pub fn sys_set_filter(mode: usize, bitmap: VirtAddr, len: usize) -> Result<usize, ErrorNum> {
+    if len > FILTER_BITMAP_MAX {
+        return Err(ErrorNum::EINVAL);
+    }
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    if proc_inner.syscall_filter.is_some() {
+        return Err(ErrorNum::EPERM);
+    }
+    let raw = copy_from_user(&mut proc_inner.mem_layout, bitmap, len)?;
+    proc_inner.syscall_filter = Some(SyscallFilter::from_bitmap(mode, &raw)?);
+    info!("Syscall filter installed for {:?}", proc.pid);
+    Ok(0)
+}
+
+/// PR_SET_SYSCALL_ABI picks the ABI of unbranded images exec'ed from now on, PR_GET_SYSCALL_ABI returns the one
+/// this process runs with.
+pub fn sys_prctl(option: usize, arg: usize) -> Result<usize, ErrorNum> {
+    let proc = get_processor().current().unwrap();
+    let mut proc_inner = proc.get_inner();
+    match option {
+        PR_SET_SYSCALL_ABI => {
+            proc_inner.exec_abi = SyscallAbi::try_from(arg).map_err(|_| ErrorNum::EINVAL)?;
+            Ok(0)
+        },
+        PR_GET_SYSCALL_ABI => Ok(proc_inner.syscall_abi as usize),
+        _ => Err(ErrorNum::EINVAL),
+    }
+}
+
 pub fn sys_unknown(syscall_id:usize) -> Result<usize, ErrorNum> {
     error!("Unknown syscall id {}", syscall_id);
     Err(ErrorNum::ENOSYS)
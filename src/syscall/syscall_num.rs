@@ -24,3 +24,123 @@ pub const SYSCALL_IOCTL     : usize =  22;
 pub const SYSCALL_DELETE    : usize =  23;
 pub const SYSCALL_SEEK      : usize =  24;
 pub const SYSCALL_TIME      : usize =  25;
+pub const SYSCALL_READV     : usize =  26;
+pub const SYSCALL_WRITEV    : usize =  27;
+pub const SYSCALL_COPY_FILE_RANGE: usize =  28;
+pub const SYSCALL_GETRLIMIT : usize =  29;
+pub const SYSCALL_SETRLIMIT : usize =  30;
+pub const SYSCALL_GETRUSAGE : usize =  31;
+pub const SYSCALL_SET_FILTER: usize =  32;
+pub const SYSCALL_GETDENTS64: usize =  33;
+pub const SYSCALL_QUOTACTL  : usize =  34;
+pub const SYSCALL_MSYNC     : usize =  35;
+pub const SYSCALL_BRK       : usize =  36;
+pub const SYSCALL_SPAWN     : usize =  37;
+pub const SYSCALL_EXIT_GROUP: usize =  38;
+pub const SYSCALL_SETSID    : usize =  39;
+pub const SYSCALL_SETPGID   : usize =  40;
+pub const SYSCALL_GETPGID   : usize =  41;
+pub const SYSCALL_GETCRED   : usize =  42;
+pub const SYSCALL_SETRESUID : usize =  43;
+pub const SYSCALL_SETRESGID : usize =  44;
+pub const SYSCALL_MOUNT     : usize =  45;
+pub const SYSCALL_UMOUNT    : usize =  46;
+pub const SYSCALL_UNSHARE   : usize =  47;
+pub const SYSCALL_FSYNC     : usize =  48;
+pub const SYSCALL_FTRUNCATE : usize =  49;
+pub const SYSCALL_TRUNCATE  : usize =  50;
+pub const SYSCALL_FALLOCATE : usize =  51;
+pub const SYSCALL_FACCESSAT : usize =  52;
+pub const SYSCALL_UMASK     : usize =  53;
+pub const SYSCALL_STATFS    : usize =  54;
+pub const SYSCALL_FCNTL     : usize =  55;
+pub const SYSCALL_SPLICE    : usize =  56;
+pub const SYSCALL_EVENTFD   : usize =  57;
+pub const SYSCALL_TIMERFD_CREATE: usize =  58;
+pub const SYSCALL_TIMERFD_SETTIME: usize =  59;
+pub const SYSCALL_SIGPROCMASK: usize =  60;
+pub const SYSCALL_SIGNALFD  : usize =  61;
+pub const SYSCALL_INOTIFY_INIT: usize =  62;
+pub const SYSCALL_INOTIFY_ADD_WATCH: usize =  63;
+pub const SYSCALL_INOTIFY_RM_WATCH: usize =  64;
+pub const SYSCALL_PVM_READ  : usize =  65;
+pub const SYSCALL_PVM_WRITE : usize =  66;
+pub const SYSCALL_PRCTL     : usize =  67;
+pub const SYSCALL_MLOCK     : usize =  68;
+pub const SYSCALL_MUNLOCK   : usize =  69;
+pub const SYSCALL_SENDFILE  : usize =  70;
+
+/// id and name of each, for reporting
+pub const SYSCALL_NAMES: &[(usize, &str)] = &[
+    (SYSCALL_WRITE     , "write"),
+    (SYSCALL_READ      , "read"),
+    (SYSCALL_OPEN      , "open"),
+    (SYSCALL_OPENAT    , "openat"),
+    (SYSCALL_CLOSE     , "close"),
+    (SYSCALL_DUP       , "dup"),
+    (SYSCALL_FORK      , "fork"),
+    (SYSCALL_EXEC      , "exec"),
+    (SYSCALL_EXIT      , "exit"),
+    (SYSCALL_MMAP      , "mmap"),
+    (SYSCALL_SIGNAL    , "signal"),
+    (SYSCALL_WAITPID   , "waitpid"),
+    (SYSCALL_SIGACTION , "sigaction"),
+    (SYSCALL_SIGRETURN , "sigreturn"),
+    (SYSCALL_GETCWD    , "getcwd"),
+    (SYSCALL_CHDIR     , "chdir"),
+    (SYSCALL_SBRK      , "sbrk"),
+    (SYSCALL_GETDENTS  , "getdents"),
+    (SYSCALL_PIPE      , "pipe"),
+    (SYSCALL_SYSSTAT   , "sysstat"),
+    (SYSCALL_MUNMAP    , "munmap"),
+    (SYSCALL_MKDIR     , "mkdir"),
+    (SYSCALL_IOCTL     , "ioctl"),
+    (SYSCALL_DELETE    , "delete"),
+    (SYSCALL_SEEK      , "seek"),
+    (SYSCALL_TIME      , "time"),
+    (SYSCALL_READV     , "readv"),
+    (SYSCALL_WRITEV    , "writev"),
+    (SYSCALL_COPY_FILE_RANGE, "copy_file_range"),
+    (SYSCALL_GETRLIMIT , "getrlimit"),
+    (SYSCALL_SETRLIMIT , "setrlimit"),
+    (SYSCALL_GETRUSAGE , "getrusage"),
+    (SYSCALL_SET_FILTER, "set_filter"),
+    (SYSCALL_GETDENTS64, "getdents64"),
+    (SYSCALL_QUOTACTL  , "quotactl"),
+    (SYSCALL_MSYNC     , "msync"),
+    (SYSCALL_BRK       , "brk"),
+    (SYSCALL_SPAWN     , "spawn"),
+    (SYSCALL_EXIT_GROUP, "exit_group"),
+    (SYSCALL_SETSID    , "setsid"),
+    (SYSCALL_SETPGID   , "setpgid"),
+    (SYSCALL_GETPGID   , "getpgid"),
+    (SYSCALL_GETCRED   , "getcred"),
+    (SYSCALL_SETRESUID , "setresuid"),
+    (SYSCALL_SETRESGID , "setresgid"),
+    (SYSCALL_MOUNT     , "mount"),
+    (SYSCALL_UMOUNT    , "umount"),
+    (SYSCALL_UNSHARE   , "unshare"),
+    (SYSCALL_FSYNC     , "fsync"),
+    (SYSCALL_FTRUNCATE , "ftruncate"),
+    (SYSCALL_TRUNCATE  , "truncate"),
+    (SYSCALL_FALLOCATE , "fallocate"),
+    (SYSCALL_FACCESSAT , "faccessat"),
+    (SYSCALL_UMASK     , "umask"),
+    (SYSCALL_STATFS    , "statfs"),
+    (SYSCALL_FCNTL     , "fcntl"),
+    (SYSCALL_SPLICE    , "splice"),
+    (SYSCALL_EVENTFD   , "eventfd"),
+    (SYSCALL_TIMERFD_CREATE, "timerfd_create"),
+    (SYSCALL_TIMERFD_SETTIME, "timerfd_settime"),
+    (SYSCALL_SIGPROCMASK, "sigprocmask"),
+    (SYSCALL_SIGNALFD  , "signalfd"),
+    (SYSCALL_INOTIFY_INIT, "inotify_init"),
+    (SYSCALL_INOTIFY_ADD_WATCH, "inotify_add_watch"),
+    (SYSCALL_INOTIFY_RM_WATCH, "inotify_rm_watch"),
+    (SYSCALL_PVM_READ  , "process_vm_readv"),
+    (SYSCALL_PVM_WRITE , "process_vm_writev"),
+    (SYSCALL_PRCTL     , "prctl"),
+    (SYSCALL_MLOCK     , "mlock"),
+    (SYSCALL_MUNLOCK   , "munlock"),
+    (SYSCALL_SENDFILE  , "sendfile"),
+];
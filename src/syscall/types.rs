@@ -1,6 +1,8 @@
 use bitflags::*;
 
-use crate::{mem::SegmentFlags, fs::Dirent};
+use alloc::vec::Vec;
+
+use crate::{mem::SegmentFlags, fs::{Dirent, FileType, FsStat, QuotaEntry, QuotaLimits}};
 
 bitflags! {
     /// struct for MMAP prot
@@ -24,6 +26,8 @@ bitflags! {
         const ANONYMOUS   = 0x20;
         const PRIVATE     = 0x40;
         const SHARED      = 0x80;
+        /// fault the whole mapping in now
+        const POPULATE    = 0x100;
     }
 }
 
@@ -49,6 +53,37 @@ impl From<Dirent> for SyscallDirent {
     }
 }
 
+/// linux_dirent64 header: d_ino u64, d_off i64, d_reclen u16, d_type u8, then NUL terminated d_name.
+const DIRENT64_NAME_OFFSET: usize = 19;
+
+/// d_type, same value as linux DT_*
+fn dirent64_type(f_type: FileType) -> u8 {
+    match f_type {
+        FileType::FIFO      => 1,
+        FileType::CHAR      => 2,
+        FileType::DIR       => 4,
+        FileType::BLOCK     => 6,
+        FileType::REGULAR   => 8,
+        FileType::LINK      => 10,
+        FileType::SOCKET    => 12,
+        _                   => 0,
+    }
+}
+
+/// One getdents64 record, padded to 8 bytes. `d_off` is the entry's cookie, seeking there resumes after it.
+pub fn encode_dirent64(dirent: &Dirent, d_off: usize) -> Vec<u8> {
+    let name = dirent.f_name.as_bytes();
+    let reclen = (DIRENT64_NAME_OFFSET + name.len() + 1 + 7) & !7;
+    let mut res = Vec::with_capacity(reclen);
+    res.extend_from_slice(&(dirent.inode as u64).to_le_bytes());
+    res.extend_from_slice(&(d_off as i64).to_le_bytes());
+    res.extend_from_slice(&(reclen as u16).to_le_bytes());
+    res.push(dirent64_type(dirent.f_type));
+    res.extend_from_slice(name);
+    res.resize(reclen, 0);
+    res
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct SyscallStat {
@@ -56,4 +91,165 @@ pub struct SyscallStat {
     pub runtime_usage: usize,
     pub kernel_usage: usize,
     pub total_available: usize,
-}
\ No newline at end of file
+}
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SyscallIOVec {
+    pub base: usize,
+    pub len: usize,
+}
+
+/// What SYSCALL_STATFS writes to user, see FsStat.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SyscallStatFs {
+    pub block_size: u64,
+    pub blocks: u64,
+    pub free_blocks: u64,
+    pub inodes: u64,
+    pub free_inodes: u64,
+    pub name_max: u64,
+}
+
+impl From<FsStat> for SyscallStatFs {
+    fn from(src: FsStat) -> Self {
+        Self {
+            block_size: src.block_size as u64,
+            blocks: src.blocks as u64,
+            free_blocks: src.free_blocks as u64,
+            inodes: src.inodes as u64,
+            free_inodes: src.free_inodes as u64,
+            name_max: src.name_max as u64,
+        }
+    }
+}
+
+pub const QUOTACTL_GET: usize = 0;
+pub const QUOTACTL_SET: usize = 1;
+
+/// Quota of one uid, usage is ignored on QUOTACTL_SET. Limits of 0 mean none.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SyscallQuota {
+    pub blocks: u64,
+    pub inodes: u64,
+    pub soft_blocks: u64,
+    pub hard_blocks: u64,
+    pub soft_inodes: u64,
+    pub hard_inodes: u64,
+}
+
+impl From<QuotaEntry> for SyscallQuota {
+    fn from(src: QuotaEntry) -> Self {
+        Self {
+            blocks: src.blocks,
+            inodes: src.inodes,
+            soft_blocks: src.limits.soft_blocks,
+            hard_blocks: src.limits.hard_blocks,
+            soft_inodes: src.limits.soft_inodes,
+            hard_inodes: src.limits.hard_inodes,
+        }
+    }
+}
+
+impl Into<QuotaLimits> for SyscallQuota {
+    fn into(self) -> QuotaLimits {
+        QuotaLimits {
+            soft_blocks: self.soft_blocks,
+            hard_blocks: self.hard_blocks,
+            soft_inodes: self.soft_inodes,
+            hard_inodes: self.hard_inodes,
+        }
+    }
+}
+
+/// flag of SYSCALL_MOUNT, the only kind of mount user can do for now
+pub const MOUNT_BIND : usize = 0x1000;
+
+/// modes of SYSCALL_FALLOCATE, only punching a hole for now, and that takes both
+pub const FALLOC_KEEP_SIZE : usize = 0x01;
+pub const FALLOC_PUNCH_HOLE: usize = 0x02;
+
+/// modes of SYSCALL_FACCESSAT, or'ed, F_OK only asks if it's there
+pub const F_OK: usize = 0;
+pub const X_OK: usize = 1;
+pub const W_OK: usize = 2;
+pub const R_OK: usize = 4;
+/// dirfd of SYSCALL_OPENAT and SYSCALL_FACCESSAT for the working directory, -100 like linux
+pub const AT_FDCWD: usize = -100isize as usize;
+/// flags of SYSCALL_FACCESSAT, check a link itself, and go by effective ids rather than real ones
+pub const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+pub const AT_EACCESS         : usize = 0x200;
+
+/// commands of SYSCALL_FCNTL, for pipes only for now
+pub const F_GETFL      : usize = 3;
+pub const F_SETFL      : usize = 4;
+pub const F_SETPIPE_SZ : usize = 1031;
+pub const F_GETPIPE_SZ : usize = 1032;
+/// the one flag F_GETFL and F_SETFL know
+pub const O_NONBLOCK   : usize = 0o4000;
+
+/// flag of SYSCALL_SPLICE, don't block on the pipe
+pub const SPLICE_F_NONBLOCK: usize = 0x02;
+
+/// flags of SYSCALL_EVENTFD, read takes one at a time, and read and write don't block
+pub const EFD_SEMAPHORE: usize = 0o1;
+pub const EFD_NONBLOCK : usize = 0o4000;
+
+/// flag of SYSCALL_TIMERFD_CREATE, read doesn't block
+pub const TFD_NONBLOCK : usize = 0o4000;
+
+/// how of SYSCALL_SIGPROCMASK
+pub const SIG_BLOCK    : usize = 0;
+pub const SIG_UNBLOCK  : usize = 1;
+pub const SIG_SETMASK  : usize = 2;
+
+/// flag of SYSCALL_SIGNALFD, read doesn't block
+pub const SFD_NONBLOCK : usize = 0o4000;
+
+/// flag of SYSCALL_INOTIFY_INIT, read doesn't block
+pub const IN_NONBLOCK : usize = 0o4000;
+
+/// options of SYSCALL_PRCTL, arg is a SyscallAbi
+pub const PR_SET_SYSCALL_ABI: usize = 0x5041_0001;
+pub const PR_GET_SYSCALL_ABI: usize = 0x5041_0002;
+
+/// flag of SYSCALL_UNSHARE, own copy of the mount table
+pub const CLONE_NEWNS: usize = 0x20000;
+
+pub const SPAWN_CLOSE: usize = 0;
+pub const SPAWN_DUP2 : usize = 1;
+pub const SPAWN_OPEN : usize = 2;
+
+/// File action of spawn, applied in order to the child's files before the new image is loaded.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SyscallSpawnAction {
+    /// SPAWN_*
+    pub op: usize,
+    /// closed, or target of dup2 and open
+    pub fd: usize,
+    /// dup2 source
+    pub src_fd: usize,
+    /// open path, C string
+    pub path: usize,
+    /// open mode
+    pub mode: usize,
+}
+
+/// Resource usage of a process, memory in bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SyscallRUsage {
+    pub cpu_ticks: usize,
+    pub vm_size: usize,
+    pub rss: usize,
+    pub shared: usize,
+    pub pss: usize,
+    /// page faults served with a fresh frame
+    pub minor_faults: usize,
+    /// write faults on pages shared after fork
+    pub cow_faults: usize,
+    /// page faults on mapped files
+    pub file_faults: usize,
+}
@@ -0,0 +1,63 @@
+//! Kernel command line, from `bootargs` of the /chosen DTB node.
+//!
+//! Space separated `key=value` or bare `flag` words. Recognized:
+//! - `loglevel=<verbose|debug|info|warning|error|milestone|fatal>` drop messages below it at runtime
+//! - `init=<path>` first user process, instead of INIT_PROCESS_PATH
+//! - `root=<fs>` root filesystem, see fs::fs_impl::root_fs_by_name
+//! - `selftest` run ktest before starting init
+//! - `fsck=repair` fix what the mount time ParchFS check finds, instead of only reporting
+//! - `pfs_casefold` make the mounted ParchFS image look up names case-insensitively, kept in its superblock
+//! - `console=ttyS<N>` UART for kernel print, ttyS0 by default
+//! - `vt=<N>` N virtual consoles on the console UART, see device::vconsole
+//! - `driver.<name>=off` don't probe that built-in driver, see device::registry
+
+use alloc::{collections::BTreeMap, string::{String, ToString}};
+
+use crate::{device::DEVICE_MANAGER, config::INIT_PROCESS_PATH};
+
+use super::{RWLock, SpinRWLock, LogLevel, fmt_io::set_log_level};
+
+use lazy_static::*;
+
+lazy_static!{
+    static ref BOOTARGS: SpinRWLock<BTreeMap<String, String>> = SpinRWLock::new(BTreeMap::new());
+}
+
+pub fn parse(cmdline: &str) -> BTreeMap<String, String> {
+    cmdline.split_whitespace().map(|word| match word.split_once('=') {
+        Some((key, value)) => (key.to_string(), value.to_string()),
+        None => (word.to_string(), String::new()),
+    }).collect()
+}
+
+/// Read bootargs from device tree and apply the ones that take effect right away. Hart 0 only, after device init.
+pub fn init() {
+    let cmdline = DEVICE_MANAGER.acquire_r().get_dev_tree().bootargs().unwrap_or_default();
+    milestone!("Kernel command line: {}", cmdline);
+    *BOOTARGS.acquire_w() = parse(&cmdline);
+
+    if let Some(level) = get("loglevel") {
+        match LogLevel::from_name(&level) {
+            Some(level) => set_log_level(level),
+            None => warning!("Unknown loglevel {}", level),
+        }
+    }
+
+    if let Some(console) = get("console") {
+        if let Err(e) = crate::device::drivers::uart::set_console(&console) {
+            warning!("Bad console {}: {:?}", console, e);
+        }
+    }
+}
+
+pub fn get(key: &str) -> Option<String> {
+    BOOTARGS.acquire_r().get(key).cloned()
+}
+
+pub fn has(key: &str) -> bool {
+    BOOTARGS.acquire_r().contains_key(key)
+}
+
+pub fn init_path() -> String {
+    get("init").unwrap_or_else(|| INIT_PROCESS_PATH.to_string())
+}
@@ -0,0 +1,79 @@
+//! pstore-like crash dump. By the time panic calls `write_crash_dump`, the panic message, trap frame and backtrace
+//! have been logged, so the tail of the log ring has them all and that's what goes out, to the `ramoops` region of
+//! /reserved-memory in the DTB. Memory there survives a warm reboot, next boot takes the record out for
+//! /proc/lastcrash and clears it. Without such a region nothing is kept.
+
+use alloc::string::String;
+use core::sync::atomic::{AtomicUsize, Ordering, fence};
+use lazy_static::*;
+
+use crate::{config::{PHYS_END_ADDR, PHYS_START_ADDR}, mem::PhysAddr};
+
+use super::{Mutex, SpinMutex, log_ring::log_tail};
+
+const CRASH_MAGIC: u64 = u64::from_le_bytes(*b"PARCHCRS");
+
+#[repr(C)]
+struct CrashHeader {
+    magic: u64,
+    /// bytes of log text right after the header
+    len: u64,
+}
+
+/// region the page allocator set aside, 0 size if none
+static CRASH_BASE: AtomicUsize = AtomicUsize::new(0);
+static CRASH_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static!{
+    static ref LAST_CRASH: SpinMutex<Option<String>> = SpinMutex::new("last crash", None);
+}
+
+/// `[start, end)` is inside RAM, which kernel maps as is, and can hold a header.
+pub fn region_usable(start: PhysAddr, end: PhysAddr) -> bool {
+    start >= PHYS_START_ADDR && end <= PHYS_END_ADDR && end.0 > start.0 + core::mem::size_of::<CrashHeader>()
+}
+
+/// By the page allocator, once it has kept the pages for itself.
+pub fn set_region(start: PhysAddr, end: PhysAddr) {
+    CRASH_BASE.store(start.0, Ordering::Relaxed);
+    CRASH_SIZE.store(end.0 - start.0, Ordering::Relaxed);
+}
+
+/// Take the record the last boot left, if any, and clear it.
+pub fn init() {
+    let size = CRASH_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        info!("No ramoops region, crash dump disabled.");
+        return;
+    }
+    let header = CRASH_BASE.load(Ordering::Relaxed) as *mut CrashHeader;
+    let (magic, len) = unsafe {((*header).magic, (*header).len as usize)};
+    if magic != CRASH_MAGIC || len > size - core::mem::size_of::<CrashHeader>() {
+        return;
+    }
+    let text = unsafe {core::slice::from_raw_parts(header.add(1) as *const u8, len)};
+    *LAST_CRASH.acquire() = Some(String::from_utf8_lossy(text).into_owned());
+    unsafe {(*header).magic = 0};
+    warning!("Kernel crashed last boot, see /proc/lastcrash.");
+}
+
+pub fn last_crash() -> Option<String> {
+    LAST_CRASH.acquire().clone()
+}
+
+/// From the panic handler, last thing before halting. No locks, no allocation.
+pub fn write_crash_dump() {
+    let size = CRASH_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        return;
+    }
+    let header = CRASH_BASE.load(Ordering::Relaxed) as *mut CrashHeader;
+    unsafe {
+        (*header).magic = 0;
+        let text = core::slice::from_raw_parts_mut(header.add(1) as *mut u8, size - core::mem::size_of::<CrashHeader>());
+        (*header).len = log_tail(text) as u64;
+        // magic last, a dump cut short by reset is never taken for a whole one
+        fence(Ordering::SeqCst);
+        (*header).magic = CRASH_MAGIC;
+    }
+}
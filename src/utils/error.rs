@@ -1,4 +1,6 @@
-use core::ops::Neg;
+use core::{fmt::{Debug, Formatter}, ops::Neg};
+#[cfg(debug_assertions)]
+use core::panic::Location;
 
 crate::enum_with_tryfrom_usize!{
     #[repr(usize)]
@@ -296,4 +298,118 @@ impl ErrorNum {
     pub fn to_ret(&self) -> usize {
         (*self as isize).neg() as usize
     }
+
+    /// This error, saying what went wrong and (in debug builds) where.
+    #[track_caller]
+    pub fn ctx(self, msg: &'static str) -> KernelError {
+        KernelError::new(self, msg)
+    }
+}
+
+/// ErrorNum with where it came from. Debug builds keep a message and the location it was made at, release builds
+/// only the errno, so it's as cheap as ErrorNum there. `?` on an ErrorNum result makes one at the `?`.
+/// Compares by errno only.
+#[derive(Clone, Copy)]
+pub struct KernelError {
+    errno: ErrorNum,
+    #[cfg(debug_assertions)]
+    msg: &'static str,
+    #[cfg(debug_assertions)]
+    location: &'static Location<'static>,
+}
+
+impl KernelError {
+    #[track_caller]
+    #[allow(unused_variables)]
+    pub fn new(errno: ErrorNum, msg: &'static str) -> Self {
+        Self {
+            errno,
+            #[cfg(debug_assertions)]
+            msg,
+            #[cfg(debug_assertions)]
+            location: Location::caller(),
+        }
+    }
+
+    pub fn errno(&self) -> ErrorNum {
+        self.errno
+    }
+
+    pub fn to_ret(&self) -> usize {
+        self.errno.to_ret()
+    }
+}
+
+impl From<ErrorNum> for KernelError {
+    #[track_caller]
+    fn from(errno: ErrorNum) -> Self {
+        Self::new(errno, "")
+    }
+}
+
+impl From<KernelError> for ErrorNum {
+    fn from(err: KernelError) -> Self {
+        err.errno
+    }
+}
+
+impl PartialEq for KernelError {
+    fn eq(&self, other: &Self) -> bool {
+        self.errno == other.errno
+    }
+}
+
+impl Eq for KernelError {}
+
+impl PartialEq<ErrorNum> for KernelError {
+    fn eq(&self, other: &ErrorNum) -> bool {
+        self.errno == *other
+    }
+}
+
+impl Debug for KernelError {
+    #[cfg(debug_assertions)]
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.errno)?;
+        if !self.msg.is_empty() {
+            write!(f, " ({})", self.msg)?;
+        }
+        write!(f, " at {}:{}", self.location.file(), self.location.line())
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.errno)
+    }
+}
+
+/// `context` on a result: its error as a KernelError with `msg`. An error that already is a KernelError keeps the
+/// message and location it has, the innermost one says the most.
+pub trait ErrorContext<T> {
+    fn context(self, msg: &'static str) -> Result<T, KernelError>;
+}
+
+impl<T> ErrorContext<T> for Result<T, ErrorNum> {
+    #[track_caller]
+    fn context(self, msg: &'static str) -> Result<T, KernelError> {
+        match self {
+            Ok(v) => Ok(v),
+            Err(e) => Err(KernelError::new(e, msg)),
+        }
+    }
+}
+
+impl<T> ErrorContext<T> for Result<T, KernelError> {
+    #[track_caller]
+    #[allow(unused_mut)]
+    fn context(self, msg: &'static str) -> Result<T, KernelError> {
+        self.map_err(|mut e| {
+            #[cfg(debug_assertions)]
+            if e.msg.is_empty() {
+                e.msg = msg;
+            }
+            let _ = msg;
+            e
+        })
+    }
 }
\ No newline at end of file
@@ -6,9 +6,10 @@ use alloc::{string::String, sync::Arc};
 use crate::{process::{push_intr_off, pop_intr_off, get_hart_id, get_processor}, utils::time::{get_cycle, get_time_ms, get_time_second}, println, print, print_no_lock};
 
 use super::{SpinMutex, Mutex};
-use core::fmt::{self, Write};
+use core::{fmt::{self, Write}, sync::atomic::{AtomicUsize, Ordering}};
 
-use super::K_PRINT_HANDLER;
+use super::{K_PRINT_HANDLER, log_ring::LogRingWriter};
+use crate::device::drivers::pstore::PStoreWriter;
 
 // ======================== color constants ========================
 const FG_BLACK      :u8 = 30;
@@ -96,6 +97,20 @@ impl LogLevel {
     pub fn to_num(&self) -> usize {
         *self as usize
     }
+
+    /// Case insensitive, same names as printed in log title.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Verbose, Self::Debug, Self::Info, Self::Warning, Self::Error, Self::Milestone, Self::Fatal]
+            .into_iter()
+            .find(|level| LOG_TITLE[level.to_num()].eq_ignore_ascii_case(name))
+    }
+}
+
+/// Runtime floor on top of the log_* features, set by `loglevel=` bootarg.
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level.to_num(), Ordering::Relaxed);
 }
 
 static LOG_FG_COLOURS: &'static [u8] = &[
@@ -130,6 +145,11 @@ static LOG_TITLE: &'static [&str] = &[
 
 pub fn do_log(log_level: LogLevel, args: fmt::Arguments) {
     let guard = PRINT_LOCK.acquire();
+    let pid = get_processor().current().map_or(0, |proc| proc.pid.0);
+    // persistent log first, it has to be there even if console never prints
+    if log_level >= LogLevel::Milestone {
+        let _ = write!(PStoreWriter, "[ {:>8.5} ] h {} p {:3} {:<10}: {}\n", get_time_second(), get_hart_id(), pid, LOG_TITLE[log_level.to_num()], args);
+    }
     // print_no_lock!("\x1b[{};{}m{}", LOG_FG_COLOURS[log_level.to_num()], LOG_BG_COLOURS[log_level.to_num()], LOG_TITLE[log_level.to_num()]);
     // print_no_lock!("[{:>10.5}] on hart {}: ", get_time_second(), get_hart_id());
     // print_no_lock(args);
@@ -140,19 +160,20 @@ pub fn do_log(log_level: LogLevel, args: fmt::Arguments) {
         LOG_BG_COLOURS[log_level.to_num()], 
         get_time_second(),
         get_hart_id(),
-        if let Some(proc) = get_processor().current() {
-            proc.pid.0
-        } else {
-            0
-        },
+        pid,
         LOG_TITLE[log_level.to_num()],
     );
     print_no_lock(args);
-    print_no_lock!("\x1b[{};{}m\r\n", FG_DEFAULT, BG_DEFAULT)
+    print_no_lock!("\x1b[{};{}m\r\n", FG_DEFAULT, BG_DEFAULT);
+    // same without colours, for the crash dump
+    let _ = write!(LogRingWriter, "[ {:>8.5} ] h {} p {:3} {:<10}: {}\n", get_time_second(), get_hart_id(), pid, LOG_TITLE[log_level.to_num()], args);
 }
 
 
 pub fn log(log_level: LogLevel, args: fmt::Arguments) {
+    if log_level.to_num() < LOG_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
     match log_level {
         LogLevel::Verbose => {
             if cfg!(feature = "log_verbose") {
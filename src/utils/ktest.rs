@@ -0,0 +1,49 @@
+//! In-kernel self test.
+//!
+//! Cases are registered with `ktest!` into the `.ktest` section and run in link order by `run_all`, from
+//! /proc/ktest, or at boot with `selftest` bootarg. Keep them from blocking, boot time runs have no process to sleep.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+pub type KTestResult = Result<(), String>;
+
+pub struct KTestCase {
+    pub name: &'static str,
+    pub func: fn() -> KTestResult,
+}
+
+fn cases() -> &'static [KTestCase] {
+    extern "C" {
+        fn sktest();
+        fn ektest();
+    }
+    let len = (ektest as usize - sktest as usize) / core::mem::size_of::<KTestCase>();
+    unsafe {
+        core::slice::from_raw_parts(sktest as usize as *const KTestCase, len)
+    }
+}
+
+/// Run every case, print progress to console and return the report.
+pub fn run_all() -> String {
+    let mut report = String::new();
+    let mut failed = 0;
+    let cases = cases();
+    milestone!("ktest: running {} cases", cases.len());
+    for case in cases {
+        match (case.func)() {
+            Ok(()) => {
+                info!("ktest: {} ... ok", case.name);
+                writeln!(report, "{} ... ok", case.name).unwrap();
+            },
+            Err(msg) => {
+                failed += 1;
+                error!("ktest: {} ... FAILED: {}", case.name, msg);
+                writeln!(report, "{} ... FAILED: {}", case.name, msg).unwrap();
+            }
+        }
+    }
+    milestone!("ktest: {} passed, {} failed", cases.len() - failed, failed);
+    writeln!(report, "{} passed, {} failed", cases.len() - failed, failed).unwrap();
+    report
+}
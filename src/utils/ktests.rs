@@ -0,0 +1,55 @@
+//! Self tests for the event ring and KernelError, see utils::ktest.
+
+use alloc::string::String;
+
+use crate::config::TRACE_RING_SIZE;
+
+use super::{ErrorNum, ErrorContext, KernelError, ktest::KTestResult, trace::{TraceEvent, drain, set_trace_enabled, trace, trace_enabled}};
+
+fn trace_ring() -> KTestResult {
+    let was_enabled = trace_enabled();
+    set_trace_enabled(false);
+    drain();
+    trace(1, TraceEvent::Exit { code: 0 });
+    let off = drain();
+
+    set_trace_enabled(true);
+    trace(1, TraceEvent::Exec { path: String::from("/ktest") });
+    let once = drain();
+    let again = drain();
+    for _ in 0..TRACE_RING_SIZE + 1 {
+        trace(1, TraceEvent::Switch);
+    }
+    let overflow = drain();
+    set_trace_enabled(was_enabled);
+
+    kassert!(off.is_empty());
+    kassert!(once.contains("exec path=/ktest"));
+    kassert!(!again.contains("exec path=/ktest"));
+    kassert!(overflow.starts_with("# "));
+    Ok(())
+}
+ktest!(trace_ring, trace_ring);
+
+fn kernel_error_context() -> KTestResult {
+    fn inner() -> Result<(), KernelError> {
+        Err(ErrorNum::EPERM.ctx("inner"))
+    }
+    fn outer() -> Result<(), ErrorNum> {
+        // back to the errno at the boundary
+        inner()?;
+        Ok(())
+    }
+    let err = inner().context("outer").unwrap_err();
+    kassert!(err == ErrorNum::EPERM);
+    kassert!(outer() == Err(ErrorNum::EPERM));
+    let wrapped: Result<(), KernelError> = Err::<(), _>(ErrorNum::ENOENT).context("lookup");
+    kassert!(wrapped.map_err(|e| e.to_ret()) == Err(ErrorNum::ENOENT.to_ret()));
+    if cfg!(debug_assertions) {
+        let shown = format!("{:?}", err);
+        // the innermost message and where it was made
+        kassert!(shown.starts_with("EPERM (inner) at ") && shown.contains("ktests.rs"));
+    }
+    Ok(())
+}
+ktest!(kernel_error_context, kernel_error_context);
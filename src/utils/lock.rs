@@ -1,10 +1,12 @@
-use core::cell::{RefCell, UnsafeCell};
+use core::cell::UnsafeCell;
 
 use core::ops::{Deref, DerefMut};
-use core::sync::atomic::{Ordering, AtomicBool};
+use core::sync::atomic::{Ordering, AtomicBool, AtomicUsize};
 use core::option::Option;
-use alloc::string::String;
-use crate::process::{get_hart_id, pop_intr_off, push_intr_off, get_processor};
+use alloc::{string::String, sync::Arc};
+use crate::config::LOCK_SPIN_WARN;
+use crate::process::{pop_intr_off, push_intr_off, WaitQueue, Waker, get_hart_id};
+use super::symbols::SymbolizedPC;
 
 pub trait Mutex<T> {
     fn acquire(&self) -> MutexGuard<'_, T>;
@@ -59,7 +61,9 @@ impl<T> MutexGuard<'_, T> {
 pub struct SpinMutex<T> {
     is_acquired  : AtomicBool,
     name        : String,
-    data        : UnsafeCell<T>
+    data        : UnsafeCell<T>,
+    /// return address of the acquire() call of current holder, for deadlock report
+    holder_pc   : AtomicUsize,
 }
 
 impl<T> SpinMutex<T> {
@@ -67,17 +71,40 @@ impl<T> SpinMutex<T> {
         Self {
             is_acquired: AtomicBool::new(false),
             name: String::from(name),
-            data: UnsafeCell::new(data)
+            data: UnsafeCell::new(data),
+            holder_pc: AtomicUsize::new(0),
         }
     }
 }
 
+/// Return address saved in the current frame (needs frame pointers). Called from `acquire`, which is never inlined,
+/// so that's where the lock was taken.
+#[inline(always)]
+fn caller_pc() -> usize {
+    let ra: usize;
+    unsafe {
+        core::arch::asm!("ld {0}, -8(s0)", out(reg) ra);
+    }
+    ra
+}
+
 impl<T> Mutex<T> for SpinMutex<T> {
+    #[inline(never)]
     fn acquire(&self) -> MutexGuard<'_, T> {
+        let pc = caller_pc();
         push_intr_off();
+        let mut spins = 0;
         while self.is_acquired.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
-            // spin wait
+            spins += 1;
+            if spins == LOCK_SPIN_WARN {
+                // report once per acquire, not on every spin
+                warning!(
+                    "Hart {} stuck on SpinMutex {} from {}, held from {}",
+                    get_hart_id(), self.name, SymbolizedPC(pc), SymbolizedPC(self.holder_pc.load(Ordering::Relaxed))
+                );
+            }
         }
+        self.holder_pc.store(pc, Ordering::Relaxed);
         MutexGuard{mutex: self}
     }
 
@@ -121,39 +148,43 @@ impl<T> Mutex<T> for SpinMutex<T> {
     }
 }
 
+/// Blocking mutex for PCB context code. Waiters sleep instead of spinning.
+/// Scheduler context (no current process) falls back to spinning.
 pub struct SleepMutex<T> {
-    is_acquired : AtomicBool,
+    is_acquired : SpinMutex<bool>,
     name        : String,
     data        : UnsafeCell<T>,
-    acquired_by : RefCell<Option<usize>>
+    waiters     : WaitQueue
 }
 
 
 impl<T> SleepMutex<T> {
-    pub fn new(name: String, data: T) -> Self {
+    pub fn new(name: &str, data: T) -> Self {
         Self {
-            is_acquired: AtomicBool::new(false),
-            acquired_by: RefCell::new(None),
-            name,
-            data: UnsafeCell::new(data)
+            is_acquired: SpinMutex::new("sleep mutex state", false),
+            name: String::from(name),
+            data: UnsafeCell::new(data),
+            waiters: WaitQueue::new("sleep mutex waiters")
         }
     }
 }
 
 impl<T> Mutex<T> for SleepMutex<T> {
     fn acquire(&self) -> MutexGuard<'_, T> {
-        // TODO: Check if is Scheduler Kernel thread for Proc is acquiring SleepMutex. Scheduler kernel thread is not allowed to use this.
-        while !self.is_acquired.swap(true, Ordering::SeqCst) {
-            get_processor().suspend_switch();
+        loop {
+            let mut is_acquired = self.is_acquired.acquire();
+            if !*is_acquired {
+                *is_acquired = true;
+                break;
+            }
+            self.waiters.sleep_on(is_acquired);
         }
-        // change after lock has been successfully acquired, thus refcell is safe to change
-        *self.acquired_by.borrow_mut() = Some(get_hart_id());
         MutexGuard{mutex: self}
     }
 
     fn release(&self) {
-        self.is_acquired.store(false, Ordering::Release)
-        // TODO: notify yielded Process
+        unsafe {self.force_unlock();}
+        self.waiters.wake_one();
     }
 
     fn get_data(&self) -> &mut T {
@@ -165,19 +196,23 @@ impl<T> Mutex<T> for SleepMutex<T> {
     }
 
     fn locked(&self) -> bool {
-        self.is_acquired.load(Ordering::Relaxed)
+        *self.is_acquired.acquire()
     }
 
     unsafe fn force_relock(&self) {
-        if self.is_acquired.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        let mut is_acquired = self.is_acquired.acquire();
+        if *is_acquired {
             panic!("Mutex must be unlocked to be force relock")
         }
+        *is_acquired = true;
     }
 
     unsafe fn force_unlock(&self) {
-        if self.is_acquired.compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        let mut is_acquired = self.is_acquired.acquire();
+        if !*is_acquired {
             panic!("Mutex must be locked to be force unlock")
         }
+        *is_acquired = false;
     }
 
     unsafe fn from_locked(&self) -> MutexGuard<'_, T> {
@@ -191,8 +226,45 @@ impl<T> Mutex<T> for SleepMutex<T> {
     }
 }
 
+/// Condition variable for PCB context code, works with any Mutex.
+/// Notifier should hold the same mutex when changing the condition.
+pub struct Condvar {
+    waiters: WaitQueue
+}
+
+impl Condvar {
+    pub fn new(name: &str) -> Self {
+        Self {
+            waiters: WaitQueue::new(name)
+        }
+    }
+
+    /// Release the mutex and sleep until notified, then reacquire the mutex.
+    /// Spurious wakeup is possible, always recheck condition.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        self.waiters.sleep_on(guard);
+        mutex.acquire()
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+
+    /// `waker` is woken on the next notify.
+    pub fn register_waker(&self, waker: &Arc<Waker>) {
+        self.waiters.register_waker(waker);
+    }
+}
+
 unsafe impl<T> Send for SpinMutex<T> where T: Send {}
 unsafe impl<T> Sync for SpinMutex<T> where T: Send {}
+unsafe impl<T> Send for SleepMutex<T> where T: Send {}
+unsafe impl<T> Sync for SleepMutex<T> where T: Send {}
 unsafe impl<T> Send for MutexGuard<'_, T> where T: Send {}
 unsafe impl<T> Sync for MutexGuard<'_, T> where T: Send + Sync {}
 
@@ -0,0 +1,35 @@
+//! Last LOG_RING_SIZE bytes of kernel log as plain text, for the crash dump. Static and lock free so the panic
+//! path can read it, written by do_log under the print lock.
+
+use core::{fmt, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::config::LOG_RING_SIZE;
+
+static mut LOG_RING: [u8; LOG_RING_SIZE] = [0; LOG_RING_SIZE];
+/// bytes ever written, head is at LOG_WRITTEN % LOG_RING_SIZE
+static LOG_WRITTEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Appends to the ring. Only under the print lock, there's no other exclusion.
+pub struct LogRingWriter;
+
+impl fmt::Write for LogRingWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut written = LOG_WRITTEN.load(Ordering::Relaxed);
+        for &b in s.as_bytes() {
+            unsafe {LOG_RING[written % LOG_RING_SIZE] = b};
+            written += 1;
+        }
+        LOG_WRITTEN.store(written, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// Copy the newest bytes of the log, oldest first, into `dst`. Returns how many.
+pub fn log_tail(dst: &mut [u8]) -> usize {
+    let written = LOG_WRITTEN.load(Ordering::Acquire);
+    let len = dst.len().min(written).min(LOG_RING_SIZE);
+    for (i, b) in dst[..len].iter_mut().enumerate() {
+        *b = unsafe {LOG_RING[(written - len + i) % LOG_RING_SIZE]};
+    }
+    len
+}
@@ -216,4 +216,46 @@ macro_rules! AddCounter {
             }
         };
     }
-}
\ No newline at end of file
+}
+
+/// Register a kernel self test case, `fn() -> KTestResult`.
+#[macro_export]
+macro_rules! ktest {
+    ($name: ident, $func: expr) => {
+        // own scope, so the case can be named after its fn
+        const _: () = {
+            #[used]
+            #[link_section = ".ktest"]
+            static KTEST_CASE: $crate::utils::ktest::KTestCase = $crate::utils::ktest::KTestCase {
+                name: stringify!($name),
+                func: $func
+            };
+        };
+    }
+}
+
+/// Register a built-in driver under `name` for nodes compatible with any of `compatible`, see device::registry.
+#[macro_export]
+macro_rules! register_driver {
+    ($name: ident, $driver: ty, [$($compatible: expr),* $(,)?]) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".drivers"]
+            static DRIVER_ENTRY: $crate::device::registry::DriverEntry = $crate::device::registry::DriverEntry {
+                name: stringify!($name),
+                compatible: &[$($compatible),*],
+                probe: <$driver as $crate::device::Driver>::new,
+            };
+        };
+    }
+}
+
+/// Fail current ktest case with location if `cond` does not hold.
+#[macro_export]
+macro_rules! kassert {
+    ($cond: expr) => {
+        if !$cond {
+            return Err(alloc::format!("{}:{}: {}", file!(), line!(), stringify!($cond)));
+        }
+    }
+}
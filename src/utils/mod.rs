@@ -5,6 +5,7 @@ mod fmt_io;
 pub mod marcos;
 
 mod panic_handler;
+pub use panic_handler::park_if_panicking;
 // mod uart;
 mod lock;
 pub mod time;
@@ -14,6 +15,15 @@ pub mod elf_rs_wrapper;
 pub mod range;
 mod random;
 mod kprint;
+pub mod stack_guard;
+pub mod symbols;
+pub mod ktest;
+pub mod bootargs;
+pub mod vdso;
+pub mod trace;
+pub mod log_ring;
+pub mod crash_dump;
+mod ktests;
 
 pub use random::{
     rand_usize,
@@ -22,6 +32,8 @@ pub use random::{
 
 pub use lock::{
     SpinMutex,
+    SleepMutex,
+    Condvar,
     MutexGuard,
     Mutex,
     SpinRWLock,
@@ -43,7 +55,9 @@ pub use fmt_io::{
 };
 
 pub use error::{
-    ErrorNum
+    ErrorNum,
+    KernelError,
+    ErrorContext,
 };
 
 pub use kprint::K_PRINT_HANDLER;
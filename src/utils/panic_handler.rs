@@ -1,12 +1,130 @@
-use core::panic::PanicInfo;
+use core::{panic::PanicInfo, arch::asm, sync::atomic::{AtomicUsize, Ordering}};
 
+use riscv::register::{sepc, scause, stval};
+
+use crate::{process::{get_hart_id, intr_off, present_harts}, interrupt::{CLINT, sbi}};
+
+use super::{stack_guard::{current_sp, kernel_stack_of}, symbols::SymbolizedPC, crash_dump::write_crash_dump};
+
+/// Frames to walk at most, in case the chain loops.
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// Hart that panicked first, usize::MAX if none.
+static PANIC_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    intr_off();
+    let hart_id = get_hart_id();
+    if let Err(first) = PANIC_HART.compare_exchange(usize::MAX, hart_id, Ordering::SeqCst, Ordering::SeqCst) {
+        if first == hart_id {
+            // panicked again while dumping, don't try anything fancy this time
+            fatal!("Nested panic on hart {}", hart_id);
+        }
+        // another hart is panicking and will halt us anyway
+        halt();
+    }
+
+    halt_other_harts(hart_id);
+
     if let Some(location) = info.location() {
-        fatal!("Panic @ {}:{} : {}", location.file(), location.line(), info.message().unwrap());
+        fatal!("Panic on hart {} @ {}:{} : {}", hart_id, location.file(), location.line(), info.message().unwrap());
     } else {
-        fatal!("Panic @ ?:? : {}", info.message().unwrap());
+        fatal!("Panic on hart {} @ ?:? : {}", hart_id, info.message().unwrap());
+    }
+    dump_trap_frame();
+    backtrace();
+    write_crash_dump();
+    halt();
+}
+
+/// Send machine software interrupt to every other hart, with the park request timervec parks them in M mode.
+/// Under SBI it's a supervisor IPI instead, and they park in park_if_panicking.
+fn halt_other_harts(hart_id: usize) {
+    for hart in present_harts() {
+        if hart != hart_id && unsafe{crate::HART_REGISTER[hart]} {
+            if sbi::sbi_boot() {
+                let _ = sbi::send_ipi(1 << hart, 0);
+            } else {
+                unsafe {
+                    (&mut crate::MSCRATCH_ARR[hart][8] as *mut usize).write_volatile(1);
+                }
+                CLINT.send_soft(hart);
+            }
+        }
+    }
+}
+
+/// Called on IPI, halt if another hart is panicking.
+pub fn park_if_panicking() {
+    let panic_hart = PANIC_HART.load(Ordering::SeqCst);
+    if panic_hart != usize::MAX && panic_hart != get_hart_id() {
+        intr_off();
+        halt();
+    }
+}
+
+/// CSRs of the last trap taken on this hart, and where we are now.
+fn dump_trap_frame() {
+    let ra: usize;
+    let fp: usize;
+    let sstatus: usize;
+    let satp: usize;
+    unsafe {
+        asm!("mv {0}, ra", out(reg) ra);
+        asm!("mv {0}, s0", out(reg) fp);
+        asm!("csrr {0}, sstatus", out(reg) sstatus);
+        asm!("csrr {0}, satp", out(reg) satp);
+    }
+    fatal!("Last trap:");
+    fatal!("    scause  = {:?}", scause::read().cause());
+    fatal!("    sepc    = {}", SymbolizedPC(sepc::read()));
+    fatal!("    stval   = {:#018x}", stval::read());
+    fatal!("    sstatus = {:#018x}", sstatus);
+    fatal!("    satp    = {:#018x}", satp);
+    fatal!("Current:");
+    fatal!("    ra      = {}", SymbolizedPC(ra));
+    fatal!("    sp      = {:#018x}", current_sp());
+    fatal!("    fp      = {:#018x}", fp);
+}
+
+/// Frame pointer walk, needs -Cforce-frame-pointers (set in .cargo/config).
+/// With frame pointer on riscv, fp points right above the saved ra (fp - 8) and saved fp (fp - 16).
+fn backtrace() {
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {0}, s0", out(reg) fp);
+    }
+    let (bottom, top, name) = match kernel_stack_of(current_sp()) {
+        Some(res) => res,
+        None => {
+            fatal!("Backtrace unavailable: not on a kernel stack");
+            return;
+        }
+    };
+    fatal!("Backtrace ({} {:#x} - {:#x}):", name, bottom, top);
+    for depth in 0..MAX_BACKTRACE_DEPTH {
+        if fp < bottom + 16 || fp > top || fp % core::mem::size_of::<usize>() != 0 {
+            break;
+        }
+        let (ra, prev_fp) = unsafe {
+            (((fp - 8) as *const usize).read_volatile(), ((fp - 16) as *const usize).read_volatile())
+        };
+        if ra == 0 {
+            break;
+        }
+        fatal!("    #{:<2} {}", depth, SymbolizedPC(ra));
+        if prev_fp <= fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
+
+fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
     }
-    loop {}
-}
\ No newline at end of file
+}
@@ -0,0 +1,67 @@
+use core::arch::asm;
+
+use crate::{config::{PROC_K_STACK_ADDR, PROC_K_STACK_SIZE, BOOT_STACK_SIZE, KSTACK_CANARY, KSTACK_CANARY_WORDS}, process::get_hart_id};
+
+/// Words at the lowest end of every kernel stack. Stack grow down, so an overflow or a wild write
+/// below a frame tramples them before anything else.
+pub unsafe fn set_canary(stack_bottom: usize) {
+    let canary = stack_bottom as *mut usize;
+    for i in 0..KSTACK_CANARY_WORDS {
+        canary.add(i).write_volatile(KSTACK_CANARY);
+    }
+}
+
+/// Put canary on this hart's boot stack, which is also the idle / scheduler stack.
+pub fn init_boot_stack_canary() {
+    unsafe {set_canary(boot_stack_bottom(get_hart_id()))};
+}
+
+pub fn current_sp() -> usize {
+    let sp: usize;
+    unsafe {
+        asm!("mv {0}, sp", out(reg) sp);
+    }
+    sp
+}
+
+fn boot_stack_bottom(hart_id: usize) -> usize {
+    extern "C" {fn boot_stack();}
+    boot_stack as usize + BOOT_STACK_SIZE * hart_id
+}
+
+/// (bottom, top, name) of the kernel stack on this hart that `sp` lies in.
+pub fn kernel_stack_of(sp: usize) -> Option<(usize, usize, &'static str)> {
+    let proc_bottom = PROC_K_STACK_ADDR.0;
+    let boot_bottom = boot_stack_bottom(get_hart_id());
+    if sp >= proc_bottom && sp <= proc_bottom + PROC_K_STACK_SIZE {
+        Some((proc_bottom, proc_bottom + PROC_K_STACK_SIZE, "process kernel stack"))
+    } else if sp >= boot_bottom && sp <= boot_bottom + BOOT_STACK_SIZE {
+        Some((boot_bottom, boot_bottom + BOOT_STACK_SIZE, "boot stack"))
+    } else {
+        None
+    }
+}
+
+/// Check the kernel stack we are running on. Called on trap entry and exit, panic with a dump if it was smashed.
+pub fn check_kernel_stack(location: &str) {
+    let sp = current_sp();
+    let hart_id = get_hart_id();
+    let (bottom, _, name) = match kernel_stack_of(sp) {
+        Some(res) => res,
+        None => panic!("{}: sp {:#x} is not on any kernel stack of hart {}", location, sp, hart_id),
+    };
+
+    let canary_end = bottom + KSTACK_CANARY_WORDS * core::mem::size_of::<usize>();
+    let canary = bottom as *const usize;
+    let intact = (0..KSTACK_CANARY_WORDS).all(|i| unsafe{canary.add(i).read_volatile()} == KSTACK_CANARY);
+    if intact && sp >= canary_end {
+        return;
+    }
+
+    fatal!("Kernel stack smashed at {} on hart {}: {} @ {:#x}, sp = {:#x}", location, hart_id, name, bottom, sp);
+    for i in 0..KSTACK_CANARY_WORDS {
+        let val = unsafe{canary.add(i).read_volatile()};
+        fatal!("    {:#x}: {:#018x}{}", bottom + i * core::mem::size_of::<usize>(), val, if val == KSTACK_CANARY {""} else {"  <- corrupted"});
+    }
+    panic!("Kernel stack canary corrupted");
+}
@@ -0,0 +1,112 @@
+//! Kernel symbol table for backtraces and /proc/kallsyms.
+//!
+//! The kernel is linked with a zeroed `.kallsyms` section, `tools/kallsyms.py` patches the real table into the
+//! ELF after link, run by the linker wrapper `tools/kallsyms_ld.py` cargo links with. Layout (little endian):
+//!
+//! ```text
+//! u64 magic "KALLSYMS"
+//! u64 count
+//! count * { u64 addr, u32 name_off, u32 name_len }   sorted by addr
+//! names, name_off is relative to the end of entry array
+//! ```
+//!
+//! An unpatched kernel has no magic and every lookup returns None.
+
+use core::fmt::{Display, Formatter};
+
+use crate::config::KALLSYMS_SIZE;
+
+const KALLSYMS_MAGIC: u64 = u64::from_le_bytes(*b"KALLSYMS");
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
+
+/// Placeholder to be overwritten, never read through this symbol as the compiler knows it's all zero.
+#[used]
+#[link_section = ".kallsyms"]
+static KALLSYMS_PLACEHOLDER: [u8; KALLSYMS_SIZE] = [0; KALLSYMS_SIZE];
+
+fn table() -> &'static [u8] {
+    extern "C" {
+        fn skallsyms();
+        fn ekallsyms();
+    }
+    unsafe {
+        core::slice::from_raw_parts(skallsyms as usize as *const u8, ekallsyms as usize - skallsyms as usize)
+    }
+}
+
+fn read_u64(table: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&table[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+fn read_u32(table: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&table[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+/// Number of symbols, 0 if table was not patched in.
+pub fn symbol_count() -> usize {
+    let table = table();
+    if table.len() < HEADER_SIZE || read_u64(table, 0) != KALLSYMS_MAGIC {
+        return 0;
+    }
+    let count = read_u64(table, 8) as usize;
+    if HEADER_SIZE + count * ENTRY_SIZE > table.len() {
+        return 0;
+    }
+    count
+}
+
+/// (address, name) of the idx-th symbol, by address order.
+pub fn symbol_at(idx: usize) -> Option<(usize, &'static str)> {
+    let count = symbol_count();
+    if idx >= count {
+        return None;
+    }
+    let table = table();
+    let entry = HEADER_SIZE + idx * ENTRY_SIZE;
+    let addr = read_u64(table, entry) as usize;
+    let name_start = HEADER_SIZE + count * ENTRY_SIZE + read_u32(table, entry + 8) as usize;
+    let name_end = name_start + read_u32(table, entry + 12) as usize;
+    let name = table.get(name_start..name_end).and_then(|name| core::str::from_utf8(name).ok())?;
+    Some((addr, name))
+}
+
+pub fn symbols() -> impl Iterator<Item = (usize, &'static str)> {
+    (0..symbol_count()).filter_map(symbol_at)
+}
+
+/// Symbol containing `pc`, as (name, offset into it).
+pub fn lookup(pc: usize) -> Option<(&'static str, usize)> {
+    let count = symbol_count();
+    // last symbol with addr <= pc
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if symbol_at(mid)?.0 <= pc {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    if lo == 0 {
+        return None;
+    }
+    let (addr, name) = symbol_at(lo - 1)?;
+    Some((name, pc - addr))
+}
+
+/// Format a code address as `0x... <symbol+0xoff>`.
+pub struct SymbolizedPC(pub usize);
+
+impl Display for SymbolizedPC {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match lookup(self.0) {
+            Some((name, offset)) => write!(f, "{:#018x} <{}+{:#x}>", self.0, name, offset),
+            None => write!(f, "{:#018x} <?>", self.0),
+        }
+    }
+}
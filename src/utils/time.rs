@@ -1,12 +1,17 @@
 //! Timer related sbi calls.
-use crate::{config::{CLOCK_FREQ}, interrupt::CLINT};
+use crate::{config::{CLOCK_FREQ}, interrupt::{CLINT, sbi::sbi_boot}};
 
 // trigger per 1ms
 pub const MILLI_PER_SECOND  : usize = 1000;
 
 /// Get times elaped since boot, in cycles.
 pub fn get_cycle() -> usize {
-    CLINT.get_time()
+    if sbi_boot() {
+        // CLINT is firmware's under SBI
+        ::riscv::register::time::read()
+    } else {
+        CLINT.get_time()
+    }
 }
 
 /// get milisecond since boot.
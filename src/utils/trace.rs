@@ -0,0 +1,96 @@
+//! Kernel event ring, to see what a workload does without a debugger.
+//!
+//! Off until enabled through /proc/sys/trace_events. Then fork, exec, exit, signal delivery and context switches
+//! are recorded with the time and hart they happen on, and /proc/trace_pipe hands them out oldest first, each
+//! event once. When TRACE_RING_SIZE events pile up unread the oldest are dropped, and counted.
+
+use alloc::{collections::VecDeque, string::String};
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+
+use crate::{config::{CLOCK_FREQ, TRACE_RING_SIZE}, process::{SignalNum, get_hart_id}};
+
+use super::{Mutex, SpinMutex, time::get_cycle};
+
+static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static!{
+    static ref TRACE_RING: SpinMutex<TraceRing> = SpinMutex::new("trace ring", TraceRing {
+        records: VecDeque::new(),
+        dropped: 0,
+    });
+}
+
+#[derive(Debug)]
+pub enum TraceEvent {
+    Fork { child: usize },
+    Exec { path: String },
+    Exit { code: isize },
+    Signal { signal: SignalNum },
+    /// the process got the hart
+    Switch,
+}
+
+struct TraceRecord {
+    cycle: usize,
+    hart: usize,
+    pid: usize,
+    event: TraceEvent,
+}
+
+struct TraceRing {
+    records: VecDeque<TraceRecord>,
+    /// since last drain
+    dropped: usize,
+}
+
+pub fn trace_enabled() -> bool {
+    TRACE_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_trace_enabled(enabled: bool) {
+    TRACE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Record `event` of process `pid`. Cheap when tracing is off, safe with interrupts off.
+pub fn trace(pid: usize, event: TraceEvent) {
+    if !trace_enabled() {
+        return;
+    }
+    let record = TraceRecord {
+        cycle: get_cycle(),
+        hart: get_hart_id(),
+        pid,
+        event,
+    };
+    let mut ring = TRACE_RING.acquire();
+    if ring.records.len() >= TRACE_RING_SIZE {
+        ring.records.pop_front();
+        ring.dropped += 1;
+    }
+    ring.records.push_back(record);
+}
+
+/// Take every recorded event, one per line like `  12 [1]    3.141592: exec path=/bin/sh`.
+pub fn drain() -> String {
+    let (records, dropped) = {
+        let mut ring = TRACE_RING.acquire();
+        (core::mem::take(&mut ring.records), core::mem::take(&mut ring.dropped))
+    };
+    let mut res = String::new();
+    if dropped != 0 {
+        res += &format!("# {} events lost\n", dropped);
+    }
+    for record in records {
+        let usec = record.cycle / (CLOCK_FREQ / 1_000_000);
+        res += &format!("{:>5} [{}] {:>6}.{:06}: ", record.pid, record.hart, usec / 1_000_000, usec % 1_000_000);
+        res += &match record.event {
+            TraceEvent::Fork { child } => format!("fork child={}\n", child),
+            TraceEvent::Exec { path } => format!("exec path={}\n", path),
+            TraceEvent::Exit { code } => format!("exit code={}\n", code),
+            TraceEvent::Signal { signal } => format!("signal {:?}\n", signal),
+            TraceEvent::Switch => "switch\n".into(),
+        };
+    }
+    res
+}
@@ -156,7 +156,7 @@ impl Uart {
         let mut inner = self.inner.acquire();
         while inner.write_buffer.len() >= 1024 {
             drop(inner);
-            get_processor().suspend_switch();
+            get_processor().suspend_switch(true);
             inner = self.inner.acquire();
         }
         inner.write_bytes(data);
@@ -256,7 +256,7 @@ impl Uart {
         // waiting for irq
         while inner.read_buffer.len() == 0 {
             drop(inner);
-            get_processor().suspend_switch();
+            get_processor().suspend_switch(true);
             inner = self.inner.acquire();
         }
         inner.read_buffer.pop_front().unwrap()
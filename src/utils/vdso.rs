@@ -0,0 +1,163 @@
+//! Kernel maintained time page, mapped read only into every process at VDSO_DATA_ADDR, so user can get time
+//! with `rdtime` instead of trapping. The user side helpers live in u_trampoline, their addresses are passed
+//! in auxv (AT_PARCH_VDSO_*).
+//!
+//! Readers retry while `seq` is odd or changed across the read.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{config::{CLOCK_FREQ, VDSO_DATA_ADDR, U_TRAMPOLINE_ADDR}, mem::{PhysAddr, PhysPageNum}};
+
+/// Bumped on layout change.
+pub const VDSO_VERSION: usize = 1;
+
+/// Layout seen by user, keep in sync with user side helpers.
+#[repr(C)]
+pub struct VdsoData {
+    pub seq: AtomicUsize,
+    pub version: AtomicUsize,
+    /// mtime ticks per second
+    pub clock_freq: AtomicUsize,
+    /// mtime at boot, uptime = (rdtime - mtime_base) / clock_freq
+    pub mtime_base: AtomicUsize,
+    /// unix seconds at mtime_base
+    pub rtc_offset: AtomicUsize,
+    /// 1 if rdtime works in U mode, else helpers fail and caller should syscall
+    pub user_rdtime: AtomicUsize,
+}
+
+/// Own the whole page, nothing else in kernel data gets exposed.
+#[repr(C, align(4096))]
+struct VdsoPage(VdsoData);
+
+static VDSO_PAGE: VdsoPage = VdsoPage(VdsoData {
+    seq: AtomicUsize::new(0),
+    version: AtomicUsize::new(VDSO_VERSION),
+    clock_freq: AtomicUsize::new(CLOCK_FREQ),
+    mtime_base: AtomicUsize::new(0),
+    rtc_offset: AtomicUsize::new(0),
+    user_rdtime: AtomicUsize::new(0),
+});
+
+fn update(f: impl FnOnce(&VdsoData)) {
+    let data = &VDSO_PAGE.0;
+    data.seq.fetch_add(1, Ordering::AcqRel);
+    f(data);
+    data.seq.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Called once on boot, after scounteren is set up.
+pub fn init() {
+    update(|data| {
+        // kernel time counts from mtime 0, see utils::time
+        data.mtime_base.store(0, Ordering::Relaxed);
+        data.rtc_offset.store(crate::version::COMPILE_EPOCH, Ordering::Relaxed);
+        data.user_rdtime.store(1, Ordering::Relaxed);
+    });
+    milestone!("vDSO data page at {:?}", vdso_page_ppn());
+}
+
+pub fn set_rtc_offset(epoch_seconds: usize) {
+    update(|data| data.rtc_offset.store(epoch_seconds, Ordering::Relaxed));
+}
+
+pub fn vdso_page_ppn() -> PhysPageNum {
+    PhysAddr::from(&VDSO_PAGE as *const VdsoPage as usize).into()
+}
+
+/// Where `helper` (a u_trampoline function) is in user space.
+pub fn user_address_of(helper: usize) -> usize {
+    extern "C" {
+        fn sutrampoline();
+    }
+    U_TRAMPOLINE_ADDR.0 + (helper - sutrampoline as usize)
+}
+
+// ---- user side, runs in U mode from u_trampoline, must not touch anything else in kernel ----
+
+/// VDSO_DATA_ADDR, kept in u_trampoline as kernel rodata is not mapped for user.
+#[no_mangle]
+#[link_section = ".text.u_trampoline_rust"]
+static VDSO_DATA: usize = VDSO_DATA_ADDR.0;
+
+/// Volatile word load, inlined so nothing outside u_trampoline gets called.
+#[inline(always)]
+fn user_load(addr: *const AtomicUsize) -> usize {
+    let res: usize;
+    unsafe {
+        core::arch::asm!("ld {0}, 0({1})", out(reg) res, in(reg) addr, options(nostack, readonly, preserves_flags));
+    }
+    res
+}
+
+/// `divu` and `remu` don't trap, and there's no panic path for a zero divisor.
+#[inline(always)]
+fn user_divrem(a: usize, b: usize) -> (usize, usize) {
+    let (quot, rem): (usize, usize);
+    unsafe {
+        core::arch::asm!("divu {0}, {2}, {3}", "remu {1}, {2}, {3}", out(reg) quot, out(reg) rem, in(reg) a, in(reg) b,
+            options(pure, nomem, nostack));
+    }
+    (quot, rem)
+}
+
+/// Consistent (mtime since base, clock_freq, rtc_offset), None if rdtime is not usable.
+/// Plain volatile loads and fences only, atomics methods aren't guaranteed to be inlined.
+#[inline(always)]
+fn user_read_clock() -> Option<(usize, usize, usize)> {
+    let data = user_load(&VDSO_DATA as *const usize as *const AtomicUsize) as *const VdsoData;
+    loop {
+        let seq = user_load(unsafe { core::ptr::addr_of!((*data).seq) });
+        if seq & 1 != 0 {
+            continue;
+        }
+        unsafe { core::arch::asm!("fence r, r", options(nostack, preserves_flags)) };
+        if user_load(unsafe { core::ptr::addr_of!((*data).user_rdtime) }) == 0 {
+            return None;
+        }
+        let now: usize;
+        unsafe {
+            core::arch::asm!("rdtime {0}", out(reg) now);
+        }
+        let res = (
+            now.wrapping_sub(user_load(unsafe { core::ptr::addr_of!((*data).mtime_base) })),
+            user_load(unsafe { core::ptr::addr_of!((*data).clock_freq) }),
+            user_load(unsafe { core::ptr::addr_of!((*data).rtc_offset) }),
+        );
+        unsafe { core::arch::asm!("fence r, r", options(nostack, preserves_flags)) };
+        if user_load(unsafe { core::ptr::addr_of!((*data).seq) }) == seq {
+            // a zero would make divu return garbage, let the syscall deal with it
+            return if res.1 == 0 { None } else { Some(res) };
+        }
+    }
+}
+
+/// Milliseconds since boot, same as sys_time. usize::MAX if caller should syscall instead.
+#[no_mangle]
+#[link_section = ".text.u_trampoline_rust"]
+pub extern "C" fn vdso_time_ms() -> usize {
+    match user_read_clock() {
+        Some((ticks, freq, _)) => {
+            let (secs, frac) = user_divrem(ticks, freq);
+            secs.wrapping_mul(1000).wrapping_add(user_divrem(frac.wrapping_mul(1000), freq).0)
+        },
+        None => usize::MAX,
+    }
+}
+
+/// Wall clock into `ts` as (seconds, nanoseconds). 0 on success, -1 if caller should syscall instead.
+#[no_mangle]
+#[link_section = ".text.u_trampoline_rust"]
+pub extern "C" fn vdso_realtime(ts: *mut [usize; 2]) -> isize {
+    match user_read_clock() {
+        Some((ticks, freq, rtc_offset)) => {
+            let (secs, frac) = user_divrem(ticks, freq);
+            unsafe {
+                (*ts)[0] = rtc_offset.wrapping_add(secs);
+                (*ts)[1] = user_divrem(frac.wrapping_mul(1_000_000_000), freq).0;
+            }
+            0
+        },
+        None => -1,
+    }
+}
@@ -0,0 +1,99 @@
+#!/usr/bin/env python3
+"""Patch the kernel symbol table into the kallsyms area of a linked kernel.
+
+    tools/kallsyms.py target/riscv64gc-unknown-none-elf/debug/parch_kernel
+
+cargo runs this on every link through tools/kallsyms_ld.py, by hand it's only needed for a kernel linked some
+other way. Layout must match src/utils/symbols.rs. Set NM to pick a different nm (default the first of
+riscv64-unknown-elf-nm, llvm-nm and the toolchain's llvm-nm from llvm-tools).
+"""
+
+import os
+import re
+import shutil
+import struct
+import subprocess
+import sys
+
+MAGIC = b"KALLSYMS"
+HASH_SUFFIX = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def rustlib_bin(tool):
+    """Path of `tool` in the host bin directory of the active rust toolchain, where rust-lld and llvm-tools live."""
+    sysroot = subprocess.run(["rustc", "--print", "sysroot"], check=True, capture_output=True, text=True).stdout.strip()
+    verbose = subprocess.run(["rustc", "-vV"], check=True, capture_output=True, text=True).stdout
+    host = next(line.split(": ", 1)[1] for line in verbose.splitlines() if line.startswith("host: "))
+    return os.path.join(sysroot, "lib", "rustlib", host, "bin", tool)
+
+
+def find_nm():
+    if "NM" in os.environ:
+        return os.environ["NM"]
+    for tool in ("riscv64-unknown-elf-nm", "llvm-nm"):
+        if shutil.which(tool):
+            return tool
+    tool = rustlib_bin("llvm-nm")
+    if os.path.exists(tool):
+        return tool
+    sys.exit("kallsyms: no nm found, install riscv64-unknown-elf-nm or llvm-tools, or set NM")
+
+
+def nm(elf, *flags):
+    out = subprocess.run([find_nm(), "--defined-only", *flags, elf], check=True, capture_output=True, text=True).stdout
+    for line in out.splitlines():
+        parts = line.split(" ", 2)
+        if len(parts) == 3:
+            yield int(parts[0], 16), parts[1], parts[2]
+
+
+def build_table(elf):
+    entries = b""
+    names = b""
+    count = 0
+    for addr, kind, name in nm(elf, "-n", "-C"):
+        if kind not in "tTwW":
+            continue
+        raw = HASH_SUFFIX.sub("", name).encode()
+        entries += struct.pack("<QII", addr, len(names), len(raw))
+        names += raw
+        count += 1
+    return MAGIC + struct.pack("<Q", count) + entries + names
+
+
+def file_offset(data, vaddr):
+    """Map a virtual address to file offset through the section headers (ELF64 LE)."""
+    shoff, = struct.unpack_from("<Q", data, 0x28)
+    shentsize, shnum = struct.unpack_from("<HH", data, 0x3A)
+    for i in range(shnum):
+        _, sh_type, _, sh_addr, sh_offset, sh_size, _, _, _, _ = struct.unpack_from("<IIQQQQIIQQ", data, shoff + i * shentsize)
+        # SHT_NOBITS has nothing in file
+        if sh_type != 8 and sh_addr <= vaddr < sh_addr + sh_size:
+            return sh_offset + vaddr - sh_addr
+    return None
+
+
+def patch(elf):
+    with open(elf, "rb") as f:
+        data = bytearray(f.read())
+    syms = {name: addr for addr, _, name in nm(elf)}
+    if "skallsyms" not in syms:
+        sys.exit("no kallsyms area in " + elf)
+    start = file_offset(data, syms["skallsyms"])
+    size = syms["ekallsyms"] - syms["skallsyms"]
+
+    table = build_table(elf)
+    if len(table) > size:
+        sys.exit("symbol table is %d bytes, KALLSYMS_SIZE is %d" % (len(table), size))
+    data[start:start + size] = table + bytes(size - len(table))
+    with open(elf, "wb") as f:
+        f.write(data)
+    print("kallsyms: %d bytes patched into %s" % (len(table), elf))
+
+
+def main():
+    patch(sys.argv[1])
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,31 @@
+#!/usr/bin/env python3
+"""Linker for the kernel, set in .cargo/config: links with rust-lld, then patches the symbol table in.
+
+The table goes into the `.kallsyms` area reserved at a fixed size, so patching moves nothing and one link is
+enough. Set KALLSYMS=0 to link without it, backtraces then show bare addresses.
+"""
+
+import os
+import subprocess
+import sys
+
+sys.path.insert(0, os.path.dirname(os.path.abspath(__file__)))
+import kallsyms
+
+
+def main():
+    args = sys.argv[1:]
+    # rustc passes the lld flavor when it thinks it's calling rust-lld itself
+    if args[:2] == ["-flavor", "gnu"]:
+        args = args[2:]
+    lld = kallsyms.rustlib_bin("rust-lld")
+    status = subprocess.run([lld, "-flavor", "gnu", *args]).returncode
+    if status != 0:
+        sys.exit(status)
+    if os.environ.get("KALLSYMS") == "0":
+        return
+    kallsyms.patch(args[args.index("-o") + 1])
+
+
+if __name__ == "__main__":
+    main()